@@ -316,8 +316,12 @@ impl AuthenticationService {
             }
         };
 
-        // Make sure there's a sliding sync proxy available.
-        if self.custom_sliding_sync_proxy.is_none() && details.sliding_sync_proxy().is_none() {
+        // Make sure there's a sliding sync proxy available, unless the native version
+        // of sliding sync is used.
+        if self.custom_sliding_sync_proxy.is_none()
+            && details.sliding_sync_proxy().is_none()
+            && !client.inner.sliding_sync_version().is_native()
+        {
             return Err(AuthenticationError::SlidingSyncNotAvailable);
         }
 
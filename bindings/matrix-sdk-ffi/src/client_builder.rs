@@ -4,12 +4,13 @@ use futures_util::StreamExt;
 use matrix_sdk::{
     authentication::qrcode::{self, DeviceCodeErrorResponseType, LoginFailureReason},
     crypto::types::qr_login::{LoginQrCodeDecodeError, QrCodeModeData},
-    encryption::{BackupDownloadStrategy, EncryptionSettings},
+    encryption::{BackupDownloadStrategy, EncryptionSettings, TrustRequirement},
     reqwest::Certificate,
     ruma::{
         api::{error::UnknownVersionError, MatrixVersion},
         ServerName, UserId,
     },
+    sliding_sync::VersionBuilder as MatrixSlidingSyncVersionBuilder,
     Client as MatrixClient, ClientBuildError as MatrixClientBuildError,
     ClientBuilder as MatrixClientBuilder, IdParseError,
 };
@@ -194,6 +195,30 @@ impl From<IdParseError> for ClientBuildError {
     }
 }
 
+/// How to select the version of sliding sync when building a client.
+#[derive(Clone, Debug, Default, uniffi::Enum)]
+pub enum SlidingSyncVersionBuilder {
+    /// Use the sliding sync proxy set on the builder, or else the one
+    /// advertised in the `.well-known` of the server, if any.
+    #[default]
+    Proxy,
+    /// Use the native sliding sync API of the homeserver.
+    Native,
+    /// Use the native sliding sync API if the homeserver supports it, and
+    /// behave like [`SlidingSyncVersionBuilder::Proxy`] otherwise.
+    Auto,
+}
+
+impl From<SlidingSyncVersionBuilder> for MatrixSlidingSyncVersionBuilder {
+    fn from(value: SlidingSyncVersionBuilder) -> Self {
+        match value {
+            SlidingSyncVersionBuilder::Proxy => Self::Proxy,
+            SlidingSyncVersionBuilder::Native => Self::Native,
+            SlidingSyncVersionBuilder::Auto => Self::Auto,
+        }
+    }
+}
+
 impl From<std::io::Error> for ClientBuildError {
     fn from(e: std::io::Error) -> ClientBuildError {
         ClientBuildError::Generic { message: format!("{e:#}") }
@@ -221,6 +246,7 @@ pub struct ClientBuilder {
     passphrase: Zeroizing<Option<String>>,
     user_agent: Option<String>,
     sliding_sync_proxy: Option<String>,
+    sliding_sync_version_builder: SlidingSyncVersionBuilder,
     proxy: Option<String>,
     disable_ssl_verification: bool,
     disable_automatic_token_refresh: bool,
@@ -243,6 +269,7 @@ impl ClientBuilder {
             passphrase: Zeroizing::new(None),
             user_agent: None,
             sliding_sync_proxy: None,
+            sliding_sync_version_builder: Default::default(),
             proxy: None,
             disable_ssl_verification: false,
             disable_automatic_token_refresh: false,
@@ -255,6 +282,7 @@ impl ClientBuilder {
                 backup_download_strategy:
                     matrix_sdk::encryption::BackupDownloadStrategy::AfterDecryptionFailure,
                 auto_enable_backups: false,
+                trust_requirement: TrustRequirement::Untrusted,
             },
         })
     }
@@ -335,6 +363,15 @@ impl ClientBuilder {
         Arc::new(builder)
     }
 
+    pub fn sliding_sync_version_builder(
+        self: Arc<Self>,
+        version_builder: SlidingSyncVersionBuilder,
+    ) -> Arc<Self> {
+        let mut builder = unwrap_or_clone_arc(self);
+        builder.sliding_sync_version_builder = version_builder;
+        Arc::new(builder)
+    }
+
     pub fn proxy(self: Arc<Self>, url: String) -> Arc<Self> {
         let mut builder = unwrap_or_clone_arc(self);
         builder.proxy = Some(url);
@@ -392,6 +429,17 @@ impl ClientBuilder {
         Arc::new(builder)
     }
 
+    /// Set the trust level in the sender's device that is required to decrypt
+    /// room events. By default events from all devices are decrypted.
+    pub fn room_decryption_trust_requirement(
+        self: Arc<Self>,
+        trust_requirement: TrustRequirement,
+    ) -> Arc<Self> {
+        let mut builder = unwrap_or_clone_arc(self);
+        builder.encryption_settings.trust_requirement = trust_requirement;
+        Arc::new(builder)
+    }
+
     pub async fn build(self: Arc<Self>) -> Result<Arc<Client>, ClientBuildError> {
         Ok(Arc::new(self.build_inner().await?))
     }
@@ -422,7 +470,9 @@ impl ClientBuilder {
                 HumanQrLoginError::Unknown
             })?;
 
-            if client.sliding_sync_proxy().is_none() {
+            if client.sliding_sync_proxy().is_none()
+                && !client.inner.sliding_sync_version().is_native()
+            {
                 return Err(HumanQrLoginError::SlidingSyncNotAvailable);
             }
 
@@ -557,7 +607,9 @@ impl ClientBuilder {
             );
         }
 
-        inner_builder = inner_builder.with_encryption_settings(builder.encryption_settings);
+        inner_builder = inner_builder
+            .with_encryption_settings(builder.encryption_settings)
+            .sliding_sync_version_builder(builder.sliding_sync_version_builder.into());
 
         let sdk_client = inner_builder.build().await?;
 
@@ -574,8 +626,13 @@ impl ClientBuilder {
         // `Some(_)` value in `builder.sliding_sync_proxy`. That's really important: It
         // might not break an existing app session, but it is likely to break a new
         // session, which not immediate to detect if there is no test.
+        //
+        // The proxy must not override the native version of sliding sync either, if it
+        // has been selected when building the client.
         if let Some(sliding_sync_proxy) = builder.sliding_sync_proxy {
-            sdk_client.set_sliding_sync_proxy(Some(Url::parse(&sliding_sync_proxy)?));
+            if !sdk_client.sliding_sync_version().is_native() {
+                sdk_client.set_sliding_sync_proxy(Some(Url::parse(&sliding_sync_proxy)?));
+            }
         }
 
         Ok(Client::new(sdk_client, builder.cross_process_refresh_lock_id, builder.session_delegate)
@@ -56,6 +56,8 @@ pub enum RoomListError {
     InitializingTimeline { error: String },
     #[error("Event cache ran into an error: {error}")]
     EventCache { error: String },
+    #[error("Presence couldn't be loaded: {error}")]
+    Presence { error: String },
 }
 
 impl From<matrix_sdk_ui::room_list_service::Error> for RoomListError {
@@ -74,6 +76,7 @@ impl From<matrix_sdk_ui::room_list_service::Error> for RoomListError {
                 Self::InitializingTimeline { error: source.to_string() }
             }
             EventCache(error) => Self::EventCache { error: error.to_string() },
+            Presence(error) => Self::Presence { error: error.to_string() },
         }
     }
 }
@@ -303,6 +306,7 @@ impl From<matrix_sdk_ui::room_list_service::SyncIndicator> for RoomListServiceSy
 #[derive(uniffi::Enum)]
 pub enum RoomListLoadingState {
     NotLoaded,
+    Cached { maximum_number_of_rooms: Option<u32> },
     Loaded { maximum_number_of_rooms: Option<u32> },
 }
 
@@ -312,6 +316,7 @@ impl From<matrix_sdk_ui::room_list_service::RoomListLoadingState> for RoomListLo
 
         match value {
             LS::NotLoaded => Self::NotLoaded,
+            LS::Cached { maximum_number_of_rooms } => Self::Cached { maximum_number_of_rooms },
             LS::Loaded { maximum_number_of_rooms } => Self::Loaded { maximum_number_of_rooms },
         }
     }
@@ -125,6 +125,9 @@ pub enum TimelineItemContentKind {
         votes: HashMap<String, Vec<String>>,
         end_time: Option<u64>,
         has_been_edited: bool,
+        /// The error that happened when sending the own user's latest vote,
+        /// if it failed to be sent.
+        own_vote_send_error: Option<String>,
     },
     CallInvite,
     CallNotify,
@@ -432,6 +435,7 @@ impl From<PollResult> for TimelineItemContentKind {
             votes: value.votes,
             end_time: value.end_time,
             has_been_edited: value.has_been_edited,
+            own_vote_send_error: value.own_vote_send_error.map(|error| error.to_string()),
         }
     }
 }
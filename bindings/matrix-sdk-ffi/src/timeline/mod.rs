@@ -12,7 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::{collections::HashMap, fmt::Write as _, fs, sync::Arc};
+use std::{collections::HashMap, fs, sync::Arc};
 
 use anyhow::{Context, Result};
 use as_variant::as_variant;
@@ -23,27 +23,18 @@ use matrix_sdk::attachment::{
     BaseThumbnailInfo, BaseVideoInfo, Thumbnail,
 };
 use matrix_sdk_ui::timeline::{
-    EventItemOrigin, LiveBackPaginationStatus, Profile, TimelineDetails,
+    new_poll_content, EventItemOrigin, LiveBackPaginationStatus, Profile, TimelineDetails,
 };
 use mime::Mime;
 use ruma::{
     events::{
         location::{AssetType as RumaAssetType, LocationContent, ZoomLevel},
-        poll::{
-            unstable_end::UnstablePollEndEventContent,
-            unstable_response::UnstablePollResponseEventContent,
-            unstable_start::{
-                NewUnstablePollStartEventContent, UnstablePollAnswer, UnstablePollAnswers,
-                UnstablePollStartContentBlock,
-            },
-        },
         receipt::ReceiptThread,
         relation::Annotation,
         room::message::{
             ForwardThread, LocationMessageEventContent, MessageType,
             RoomMessageEventContentWithoutRelation,
         },
-        AnyMessageLikeEventContent,
     },
     EventId, OwnedTransactionId,
 };
@@ -52,9 +43,8 @@ use tokio::{
     task::{AbortHandle, JoinHandle},
 };
 use tracing::{error, warn};
-use uuid::Uuid;
 
-use self::content::{Reaction, ReactionSenderData, TimelineItemContent};
+use self::content::{MembershipChange, Reaction, ReactionSenderData, TimelineItemContent};
 use crate::{
     client::ProgressWatcher,
     error::{ClientError, RoomError},
@@ -196,6 +186,11 @@ impl Timeline {
         Ok(self.inner.focused_paginate_forwards(num_events).await?)
     }
 
+    /// Fill the gap with the given unique ID, until it meets known events.
+    pub async fn fill_gap(&self, unique_id: String) -> Result<(), ClientError> {
+        Ok(self.inner.fill_gap(&unique_id).await?)
+    }
+
     pub async fn send_read_receipt(
         &self,
         receipt_type: ReceiptType,
@@ -392,19 +387,10 @@ impl Timeline {
         max_selections: u8,
         poll_kind: PollKind,
     ) -> Result<(), ClientError> {
-        let poll_data = PollData { question, answers, max_selections, poll_kind };
-
-        let poll_start_event_content = NewUnstablePollStartEventContent::plain_text(
-            poll_data.fallback_text(),
-            poll_data.try_into()?,
-        );
-        let event_content =
-            AnyMessageLikeEventContent::UnstablePollStart(poll_start_event_content.into());
-
-        if let Err(err) = self.inner.send(event_content).await {
-            error!("unable to start poll: {err}");
-        }
-
+        self.inner
+            .create_poll(question, answers, max_selections, poll_kind.into())
+            .await
+            .map_err(|err| anyhow::anyhow!(err))?;
         Ok(())
     }
 
@@ -415,34 +401,24 @@ impl Timeline {
     ) -> Result<(), ClientError> {
         let poll_start_event_id =
             EventId::parse(poll_start_id).context("Failed to parse EventId")?;
-        let poll_response_event_content =
-            UnstablePollResponseEventContent::new(answers, poll_start_event_id);
-        let event_content =
-            AnyMessageLikeEventContent::UnstablePollResponse(poll_response_event_content);
-
-        if let Err(err) = self.inner.send(event_content).await {
-            error!("unable to send poll response: {err}");
-        }
-
+        self.inner
+            .send_poll_response(&poll_start_event_id, answers)
+            .await
+            .map_err(|err| anyhow::anyhow!(err))?;
         Ok(())
     }
 
-    pub fn end_poll(
+    pub async fn end_poll(
         self: Arc<Self>,
         poll_start_id: String,
         text: String,
     ) -> Result<(), ClientError> {
         let poll_start_event_id =
             EventId::parse(poll_start_id).context("Failed to parse EventId")?;
-        let poll_end_event_content = UnstablePollEndEventContent::new(text, poll_start_event_id);
-        let event_content = AnyMessageLikeEventContent::UnstablePollEnd(poll_end_event_content);
-
-        RUNTIME.spawn(async move {
-            if let Err(err) = self.inner.send(event_content).await {
-                error!("unable to end poll: {err}");
-            }
-        });
-
+        self.inner
+            .end_poll(&poll_start_event_id, text)
+            .await
+            .map_err(|err| anyhow::anyhow!(err))?;
         Ok(())
     }
 
@@ -478,9 +454,10 @@ impl Timeline {
         poll_kind: PollKind,
         edit_item: Arc<EventTimelineItem>,
     ) -> Result<(), ClientError> {
-        let poll_data = PollData { question, answers, max_selections, poll_kind };
+        let content = new_poll_content(question, answers, max_selections, poll_kind.into())
+            .map_err(|err| anyhow::anyhow!(err))?;
         self.inner
-            .edit_poll(poll_data.fallback_text(), poll_data.try_into()?, &edit_item.0)
+            .edit_poll(content.text.unwrap_or_default(), content.poll_start, &edit_item.0)
             .await
             .map_err(|err| anyhow::anyhow!(err))?;
         Ok(())
@@ -805,6 +782,11 @@ impl TimelineItem {
         match self.0.as_virtual()? {
             VItem::DayDivider(ts) => Some(VirtualTimelineItem::DayDivider { ts: ts.0.into() }),
             VItem::ReadMarker => Some(VirtualTimelineItem::ReadMarker),
+            VItem::StateGroup(group) => Some(VirtualTimelineItem::StateGroup {
+                summary: group.summary().clone().into(),
+                children_unique_ids: group.children().to_vec(),
+            }),
+            VItem::Gap { .. } => Some(VirtualTimelineItem::Gap),
         }
     }
 
@@ -970,44 +952,6 @@ impl From<&TimelineDetails<Profile>> for ProfileDetails {
     }
 }
 
-struct PollData {
-    question: String,
-    answers: Vec<String>,
-    max_selections: u8,
-    poll_kind: PollKind,
-}
-
-impl PollData {
-    fn fallback_text(&self) -> String {
-        self.answers.iter().enumerate().fold(self.question.clone(), |mut acc, (index, answer)| {
-            write!(&mut acc, "\n{}. {answer}", index + 1).unwrap();
-            acc
-        })
-    }
-}
-
-impl TryFrom<PollData> for UnstablePollStartContentBlock {
-    type Error = ClientError;
-
-    fn try_from(value: PollData) -> Result<Self, Self::Error> {
-        let poll_answers_vec: Vec<UnstablePollAnswer> = value
-            .answers
-            .iter()
-            .map(|answer| UnstablePollAnswer::new(Uuid::new_v4().to_string(), answer))
-            .collect();
-
-        let poll_answers = UnstablePollAnswers::try_from(poll_answers_vec)
-            .context("Failed to create poll answers")?;
-
-        let mut poll_content_block =
-            UnstablePollStartContentBlock::new(value.question.clone(), poll_answers);
-        poll_content_block.kind = value.poll_kind.into();
-        poll_content_block.max_selections = value.max_selections.into();
-
-        Ok(poll_content_block)
-    }
-}
-
 #[derive(uniffi::Object)]
 pub struct SendAttachmentJoinHandle {
     join_hdl: Arc<Mutex<JoinHandle<Result<(), RoomError>>>>,
@@ -1046,6 +990,59 @@ pub enum VirtualTimelineItem {
 
     /// The user's own read marker.
     ReadMarker,
+
+    /// A group of consecutive state items.
+    ///
+    /// The grouped items follow this item in the timeline.
+    StateGroup {
+        /// A summary of the changes in the group.
+        summary: StateGroupSummary,
+        /// The unique IDs of the grouped timeline items.
+        children_unique_ids: Vec<String>,
+    },
+
+    /// A hole in the history of the room.
+    ///
+    /// It can be filled with `Timeline::fill_gap`, using the unique ID of this
+    /// item.
+    Gap,
+}
+
+/// A summary of the changes in a [`VirtualTimelineItem::StateGroup`].
+#[derive(uniffi::Record)]
+pub struct StateGroupSummary {
+    pub membership_changes: Vec<MembershipChangeCount>,
+    pub display_name_changes: u64,
+    pub avatar_changes: u64,
+    pub other_state_changes: u64,
+    pub users: Vec<String>,
+}
+
+/// The number of membership changes of a given kind in a
+/// [`StateGroupSummary`].
+#[derive(uniffi::Record)]
+pub struct MembershipChangeCount {
+    pub change: MembershipChange,
+    pub count: u64,
+}
+
+impl From<matrix_sdk_ui::timeline::StateGroupSummary> for StateGroupSummary {
+    fn from(summary: matrix_sdk_ui::timeline::StateGroupSummary) -> Self {
+        Self {
+            membership_changes: summary
+                .membership_changes
+                .into_iter()
+                .map(|(change, count)| MembershipChangeCount {
+                    change: change.into(),
+                    count: count as u64,
+                })
+                .collect(),
+            display_name_changes: summary.display_name_changes as u64,
+            avatar_changes: summary.avatar_changes as u64,
+            other_state_changes: summary.other_state_changes as u64,
+            users: summary.users.into_iter().map(|user_id| user_id.to_string()).collect(),
+        }
+    }
 }
 
 /// A [`TimelineItem`](super::TimelineItem) that doesn't correspond to an event.
@@ -24,8 +24,8 @@ use eyeball::{SharedObservable, Subscriber};
 use matrix_sdk_common::instant::Instant;
 #[cfg(feature = "e2e-encryption")]
 use matrix_sdk_crypto::{
-    store::DynCryptoStore, EncryptionSettings, EncryptionSyncChanges, OlmError, OlmMachine,
-    ToDeviceRequest,
+    store::DynCryptoStore, DecryptionSettings, EncryptionSettings, EncryptionSyncChanges,
+    MegolmError, OlmError, OlmMachine, ToDeviceRequest, TrustRequirement,
 };
 #[cfg(feature = "e2e-encryption")]
 use ruma::events::{
@@ -62,9 +62,11 @@ use tracing::{debug, info, instrument, trace, warn};
 #[cfg(all(feature = "e2e-encryption", feature = "experimental-sliding-sync"))]
 use crate::latest_event::{is_suitable_for_latest_event, LatestEvent, PossibleLatestEvent};
 #[cfg(feature = "e2e-encryption")]
-use crate::RoomMemberships;
+use crate::{deserialized_responses::UnableToDecryptReason, RoomMemberships};
 use crate::{
-    deserialized_responses::{RawAnySyncOrStrippedTimelineEvent, SyncTimelineEvent},
+    deserialized_responses::{
+        ProcessedToDeviceEvent, RawAnySyncOrStrippedTimelineEvent, SyncTimelineEvent,
+    },
     error::{Error, Result},
     rooms::{normal::RoomInfoUpdate, Room, RoomInfo, RoomState},
     store::{
@@ -101,6 +103,12 @@ pub struct BaseClient {
     /// event contains the room and a boolean whether this event should
     /// trigger a room list update.
     pub(crate) roominfo_update_sender: broadcast::Sender<RoomInfoUpdate>,
+
+    /// The trust level in the sender's device that is required to decrypt
+    /// room events received in a sync. Events which fail the requirement are
+    /// left encrypted.
+    #[cfg(feature = "e2e-encryption")]
+    decryption_trust_requirement: TrustRequirement,
 }
 
 #[cfg(not(tarpaulin_include))]
@@ -136,9 +144,25 @@ impl BaseClient {
             olm_machine: Default::default(),
             ignore_user_list_changes: Default::default(),
             roominfo_update_sender,
+            #[cfg(feature = "e2e-encryption")]
+            decryption_trust_requirement: TrustRequirement::Untrusted,
         }
     }
 
+    /// Set the trust level in the sender's device that is required to decrypt
+    /// room events received in a sync.
+    ///
+    /// Events which fail the requirement are left encrypted, and the reason is
+    /// kept in their [`SyncTimelineEvent::unable_to_decrypt_reason`].
+    #[cfg(feature = "e2e-encryption")]
+    pub fn with_decryption_trust_requirement(
+        mut self,
+        decryption_trust_requirement: TrustRequirement,
+    ) -> Self {
+        self.decryption_trust_requirement = decryption_trust_requirement;
+        self
+    }
+
     /// Clones the current base client to use the same crypto store but a
     /// different, in-memory store config, and resets transient state.
     pub fn clone_with_in_memory_state_store(&self) -> Self {
@@ -147,7 +171,11 @@ impl BaseClient {
         #[cfg(feature = "e2e-encryption")]
         let config = config.crypto_store(self.crypto_store.clone());
 
-        Self::with_store_config(config)
+        Self {
+            #[cfg(feature = "e2e-encryption")]
+            decryption_trust_requirement: self.decryption_trust_requirement,
+            ..Self::with_store_config(config)
+        }
     }
 
     /// Get the session meta information.
@@ -284,8 +312,14 @@ impl BaseClient {
         let olm = self.olm_machine().await;
         let Some(olm) = olm.as_ref() else { return Ok(None) };
 
-        let event: SyncTimelineEvent =
-            olm.decrypt_room_event(event.cast_ref(), room_id).await?.into();
+        let decryption_settings = DecryptionSettings {
+            sender_device_trust_requirement: self.decryption_trust_requirement,
+        };
+
+        let event: SyncTimelineEvent = olm
+            .decrypt_room_event_with_settings(event.cast_ref(), room_id, &decryption_settings)
+            .await?
+            .into();
 
         if let Ok(AnySyncTimelineEvent::MessageLike(e)) = event.event.deserialize() {
             match &e {
@@ -384,12 +418,20 @@ impl BaseClient {
                             AnySyncMessageLikeEvent::RoomEncrypted(
                                 SyncMessageLikeEvent::Original(_),
                             ) => {
-                                if let Ok(Some(e)) = Box::pin(
+                                match Box::pin(
                                     self.decrypt_sync_room_event(&event.event, room.room_id()),
                                 )
                                 .await
                                 {
-                                    event = e;
+                                    Ok(Some(e)) => event = e,
+                                    Err(Error::MegolmError(
+                                        MegolmError::SenderIdentityNotTrusted(level),
+                                    )) => {
+                                        event.unable_to_decrypt_reason = Some(
+                                            UnableToDecryptReason::SenderIdentityNotTrusted(level),
+                                        );
+                                    }
+                                    _ => {}
                                 }
                             }
                             AnySyncMessageLikeEvent::RoomMessage(
@@ -660,7 +702,7 @@ impl BaseClient {
         encryption_sync_changes: EncryptionSyncChanges<'_>,
         #[cfg(feature = "experimental-sliding-sync")] changes: &mut StateChanges,
         #[cfg(not(feature = "experimental-sliding-sync"))] _changes: &mut StateChanges,
-    ) -> Result<Vec<Raw<ruma::events::AnyToDeviceEvent>>> {
+    ) -> Result<Vec<ProcessedToDeviceEvent>> {
         if let Some(o) = self.olm_machine().await.as_ref() {
             // Let the crypto machine handle the sync response, this
             // decrypts to-device events, but leaves room events alone.
@@ -683,7 +725,11 @@ impl BaseClient {
             // If we have no OlmMachine, just return the events that were passed in.
             // This should not happen unless we forget to set things up by calling
             // set_session_meta().
-            Ok(encryption_sync_changes.to_device_events)
+            Ok(encryption_sync_changes
+                .to_device_events
+                .into_iter()
+                .map(ProcessedToDeviceEvent::new)
+                .collect())
         }
     }
 
@@ -835,7 +881,8 @@ impl BaseClient {
             .await?;
 
         #[cfg(not(feature = "e2e-encryption"))]
-        let to_device = response.to_device.events;
+        let to_device =
+            response.to_device.events.into_iter().map(ProcessedToDeviceEvent::new).collect();
 
         let mut ambiguity_cache = AmbiguityCache::new(self.store.inner.clone());
 
@@ -53,9 +53,12 @@ pub use matrix_sdk_crypto as crypto;
 pub use once_cell;
 pub use rooms::{
     DisplayName, Room, RoomCreateWithCreatorEventContent, RoomInfo, RoomInfoUpdate, RoomMember,
-    RoomMemberships, RoomState, RoomStateFilter,
+    RoomMemberPage, RoomMemberQuery, RoomMemberships, RoomState, RoomStateFilter,
+};
+pub use store::{
+    ComposerDraft, ComposerDraftType, StateChanges, StateStore, StateStoreDataKey,
+    StateStoreDataValue, StoreError,
 };
-pub use store::{StateChanges, StateStore, StateStoreDataKey, StateStoreDataValue, StoreError};
 pub use utils::{
     MinimalRoomMemberEvent, MinimalStateEvent, OriginalMinimalStateEvent, RedactedMinimalStateEvent,
 };
@@ -1,5 +1,7 @@
 //! Common types for [media content](https://matrix.org/docs/spec/client_server/r0.6.1#id66).
 
+use std::time::Duration;
+
 use ruma::{
     api::client::media::get_content_thumbnail::v3::Method,
     events::{
@@ -12,8 +14,9 @@ use ruma::{
         },
         sticker::StickerEventContent,
     },
-    MxcUri, UInt,
+    MilliSecondsSinceUnixEpoch, MxcUri, OwnedMxcUri, UInt,
 };
+use serde::{Deserialize, Deserializer, Serialize};
 
 const UNIQUE_SEPARATOR: &str = "_";
 
@@ -99,6 +102,296 @@ impl UniqueKey for MediaRequest {
     }
 }
 
+/// The policy that decides which files are kept in the media cache.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MediaRetentionPolicy {
+    /// The maximum total size of the files in the cache, in bytes.
+    ///
+    /// When the cache is bigger, the least recently accessed files are
+    /// evicted first.
+    pub max_cache_size: Option<usize>,
+
+    /// The maximum size of a file in the cache, in bytes.
+    ///
+    /// Bigger files are not cached.
+    pub max_file_size: Option<usize>,
+
+    /// How long a file is kept in the cache after it was last accessed.
+    pub last_access_expiry: Option<Duration>,
+
+    /// Whether the avatars of the joined rooms are never evicted.
+    pub protect_joined_room_avatars: bool,
+
+    /// Whether thumbnails are never evicted.
+    pub protect_thumbnails: bool,
+}
+
+impl MediaRetentionPolicy {
+    /// The default maximum total size of the cache, 400 MiB.
+    pub const DEFAULT_MAX_CACHE_SIZE: usize = 400 * 1024 * 1024;
+
+    /// The default maximum size of a file in the cache, 20 MiB.
+    pub const DEFAULT_MAX_FILE_SIZE: usize = 20 * 1024 * 1024;
+
+    /// The default duration a file is kept after it was last accessed, 60
+    /// days.
+    pub const DEFAULT_LAST_ACCESS_EXPIRY: Duration = Duration::from_secs(60 * 24 * 60 * 60);
+
+    /// Create a policy that keeps all the files in the cache.
+    pub fn empty() -> Self {
+        Self {
+            max_cache_size: None,
+            max_file_size: None,
+            last_access_expiry: None,
+            protect_joined_room_avatars: false,
+            protect_thumbnails: false,
+        }
+    }
+
+    /// Set the maximum total size of the files in the cache, in bytes.
+    pub fn with_max_cache_size(self, size: Option<usize>) -> Self {
+        Self { max_cache_size: size, ..self }
+    }
+
+    /// Set the maximum size of a file in the cache, in bytes.
+    pub fn with_max_file_size(self, size: Option<usize>) -> Self {
+        Self { max_file_size: size, ..self }
+    }
+
+    /// Set how long a file is kept in the cache after it was last accessed.
+    pub fn with_last_access_expiry(self, expiry: Option<Duration>) -> Self {
+        Self { last_access_expiry: expiry, ..self }
+    }
+
+    /// Set whether the avatars of the joined rooms are never evicted.
+    pub fn with_protect_joined_room_avatars(self, protect: bool) -> Self {
+        Self { protect_joined_room_avatars: protect, ..self }
+    }
+
+    /// Set whether thumbnails are never evicted.
+    pub fn with_protect_thumbnails(self, protect: bool) -> Self {
+        Self { protect_thumbnails: protect, ..self }
+    }
+
+    /// Whether the given entry of the media cache must never be evicted.
+    pub fn is_protected<K>(&self, entry: &MediaCacheEntry<K>) -> bool {
+        entry.is_protected || (self.protect_thumbnails && entry.is_thumbnail)
+    }
+
+    /// Whether a file of the given size is too big to be cached.
+    pub fn exceeds_max_file_size(&self, size: usize) -> bool {
+        self.max_file_size.is_some_and(|max_file_size| size > max_file_size)
+    }
+
+    /// Whether a file last accessed at the given time has expired.
+    pub fn has_expired(
+        &self,
+        last_access: MilliSecondsSinceUnixEpoch,
+        now: MilliSecondsSinceUnixEpoch,
+    ) -> bool {
+        self.last_access_expiry.is_some_and(|expiry| {
+            let elapsed = now.get().saturating_sub(last_access.get());
+            Duration::from_millis(elapsed.into()) > expiry
+        })
+    }
+
+    /// Select the entries of the media cache that should be evicted according
+    /// to this policy.
+    ///
+    /// Unprotected files that are too big or that have expired are evicted,
+    /// then the least recently accessed unprotected files are evicted until
+    /// the cache is small enough.
+    ///
+    /// Returns the keys of the evicted entries. This is meant to be used by the
+    /// implementations of [`StateStore::clean_up_media_cache()`].
+    ///
+    /// [`StateStore::clean_up_media_cache()`]: crate::store::StateStore::clean_up_media_cache
+    pub fn select_evicted<K>(
+        &self,
+        entries: Vec<MediaCacheEntry<K>>,
+        now: MilliSecondsSinceUnixEpoch,
+    ) -> Vec<K> {
+        let mut evicted = Vec::new();
+        let mut kept = Vec::with_capacity(entries.len());
+
+        for entry in entries {
+            if !self.is_protected(&entry)
+                && (self.exceeds_max_file_size(entry.size)
+                    || self.has_expired(entry.last_access, now))
+            {
+                evicted.push(entry.key);
+            } else {
+                kept.push(entry);
+            }
+        }
+
+        let Some(max_cache_size) = self.max_cache_size else {
+            return evicted;
+        };
+
+        let mut cache_size = kept.iter().map(|entry| entry.size).sum::<usize>();
+        if cache_size <= max_cache_size {
+            return evicted;
+        }
+
+        kept.sort_by_key(|entry| entry.last_access);
+
+        for entry in kept.into_iter().filter(|entry| !self.is_protected(entry)) {
+            if cache_size <= max_cache_size {
+                break;
+            }
+
+            cache_size -= entry.size;
+            evicted.push(entry.key);
+        }
+
+        evicted
+    }
+}
+
+impl Default for MediaRetentionPolicy {
+    fn default() -> Self {
+        Self {
+            max_cache_size: Some(Self::DEFAULT_MAX_CACHE_SIZE),
+            max_file_size: Some(Self::DEFAULT_MAX_FILE_SIZE),
+            last_access_expiry: Some(Self::DEFAULT_LAST_ACCESS_EXPIRY),
+            protect_joined_room_avatars: true,
+            protect_thumbnails: false,
+        }
+    }
+}
+
+/// A file of the media cache, as seen by
+/// [`MediaRetentionPolicy::select_evicted()`].
+#[derive(Clone, Debug)]
+pub struct MediaCacheEntry<K> {
+    /// The key identifying the file in the store.
+    pub key: K,
+
+    /// The size of the file, in bytes.
+    pub size: usize,
+
+    /// When the file was last accessed.
+    pub last_access: MilliSecondsSinceUnixEpoch,
+
+    /// Whether the file must never be evicted.
+    pub is_protected: bool,
+
+    /// Whether the file is a thumbnail, which is never evicted if
+    /// [`MediaRetentionPolicy::protect_thumbnails`] is set.
+    pub is_thumbnail: bool,
+}
+
+/// The OpenGraph data of a URL, as returned by the homeserver.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct UrlPreview {
+    /// The canonical URL of the page.
+    #[serde(rename = "og:url", default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+
+    /// The title of the page.
+    #[serde(rename = "og:title", default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+
+    /// The description of the page.
+    #[serde(rename = "og:description", default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+
+    /// The name of the site the page belongs to.
+    #[serde(rename = "og:site_name", default, skip_serializing_if = "Option::is_none")]
+    pub site_name: Option<String>,
+
+    /// The URI of the preview image, uploaded to the homeserver.
+    ///
+    /// Use [`UrlPreview::image_request()`] to download it.
+    #[serde(rename = "og:image", default, skip_serializing_if = "Option::is_none")]
+    pub image: Option<OwnedMxcUri>,
+
+    /// The MIME type of the preview image.
+    #[serde(rename = "og:image:type", default, skip_serializing_if = "Option::is_none")]
+    pub image_type: Option<String>,
+
+    /// The width of the preview image, in pixels.
+    #[serde(
+        rename = "og:image:width",
+        default,
+        deserialize_with = "deserialize_lenient_uint",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub image_width: Option<UInt>,
+
+    /// The height of the preview image, in pixels.
+    #[serde(
+        rename = "og:image:height",
+        default,
+        deserialize_with = "deserialize_lenient_uint",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub image_height: Option<UInt>,
+
+    /// The size of the preview image, in bytes.
+    #[serde(
+        rename = "matrix:image:size",
+        default,
+        deserialize_with = "deserialize_lenient_uint",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub image_size: Option<UInt>,
+}
+
+impl UrlPreview {
+    /// The request to download the preview image with
+    /// `Media::get_media_content()`, if there is one.
+    pub fn image_request(&self) -> Option<MediaRequest> {
+        let uri = self.image.clone()?;
+        Some(MediaRequest { source: MediaSource::Plain(uri), format: MediaFormat::File })
+    }
+
+    /// The request under which the preview of the given URL, at the given
+    /// point in time, is cached in the media store.
+    ///
+    /// The URL takes the place of the [`MxcUri`] of a media file, so the
+    /// cached previews are subject to the [`MediaRetentionPolicy`] like any
+    /// other file of the media cache.
+    pub fn cache_request(url: &str, ts: Option<MilliSecondsSinceUnixEpoch>) -> MediaRequest {
+        let uri = match ts {
+            Some(ts) => format!("{}{UNIQUE_SEPARATOR}{url}", ts.get()),
+            None => url.to_owned(),
+        };
+
+        MediaRequest { source: MediaSource::Plain(uri.into()), format: MediaFormat::File }
+    }
+}
+
+/// A [`UrlPreview`], as cached in the media store.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CachedUrlPreview {
+    /// The preview.
+    pub preview: UrlPreview,
+
+    /// When the preview was fetched from the homeserver.
+    pub fetched_at: MilliSecondsSinceUnixEpoch,
+}
+
+/// Deserialize an optional integer that some homeservers send as a string.
+fn deserialize_lenient_uint<'de, D>(deserializer: D) -> std::result::Result<Option<UInt>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum IntOrString {
+        Int(UInt),
+        String(String),
+    }
+
+    Ok(match Option::<IntOrString>::deserialize(deserializer)? {
+        Some(IntOrString::Int(value)) => Some(value),
+        Some(IntOrString::String(value)) => value.parse().ok(),
+        None => None,
+    })
+}
+
 /// Trait for media event content.
 pub trait MediaEventContent {
     /// Get the source of the file for `Self`.
@@ -180,11 +473,84 @@ impl MediaEventContent for LocationMessageEventContent {
 
 #[cfg(test)]
 mod tests {
-    use ruma::mxc_uri;
+    use ruma::{mxc_uri, uint};
     use serde_json::json;
 
     use super::*;
 
+    fn entry(key: &'static str, size: usize, last_access: u32) -> MediaCacheEntry<&'static str> {
+        MediaCacheEntry {
+            key,
+            size,
+            last_access: MilliSecondsSinceUnixEpoch(last_access.into()),
+            is_protected: false,
+            is_thumbnail: false,
+        }
+    }
+
+    #[test]
+    fn test_select_evicted_by_size_and_expiry() {
+        let policy = MediaRetentionPolicy::empty()
+            .with_max_file_size(Some(100))
+            .with_last_access_expiry(Some(Duration::from_secs(10)));
+        let now = MilliSecondsSinceUnixEpoch(uint!(20_000));
+
+        let protected = MediaCacheEntry { is_protected: true, ..entry("protected", 1000, 0) };
+        let evicted = policy.select_evicted(
+            vec![
+                entry("big", 101, 15_000),
+                entry("old", 10, 5_000),
+                entry("ok", 10, 15_000),
+                protected,
+            ],
+            now,
+        );
+
+        assert_eq!(evicted, ["big", "old"]);
+    }
+
+    #[test]
+    fn test_select_evicted_least_recently_used() {
+        let policy = MediaRetentionPolicy::empty().with_max_cache_size(Some(100));
+        let now = MilliSecondsSinceUnixEpoch(uint!(20_000));
+
+        let protected = MediaCacheEntry { is_protected: true, ..entry("protected", 40, 0) };
+        let evicted = policy.select_evicted(
+            vec![
+                entry("recent", 40, 3_000),
+                entry("older", 40, 2_000),
+                entry("oldest", 40, 1_000),
+                protected,
+            ],
+            now,
+        );
+
+        // The protected file is the oldest, but it is kept.
+        assert_eq!(evicted, ["oldest", "older"]);
+
+        // Nothing is evicted when the cache is small enough.
+        assert!(policy.select_evicted(vec![entry("small", 100, 0)], now).is_empty());
+    }
+
+    #[test]
+    fn test_select_evicted_protected_thumbnails() {
+        let thumbnail = || MediaCacheEntry { is_thumbnail: true, ..entry("thumbnail", 40, 0) };
+        let entries = || vec![entry("file", 40, 1_000), thumbnail()];
+        let policy = MediaRetentionPolicy::empty()
+            .with_max_cache_size(Some(40))
+            .with_last_access_expiry(Some(Duration::from_secs(10)));
+        let now = MilliSecondsSinceUnixEpoch(uint!(5_000));
+
+        // Thumbnails are evicted like other files by default.
+        assert_eq!(policy.select_evicted(entries(), now), ["thumbnail"]);
+
+        // Protected thumbnails are kept, even when they expired.
+        let policy = policy.with_protect_thumbnails(true);
+        assert_eq!(policy.select_evicted(entries(), now), ["file"]);
+        let now = MilliSecondsSinceUnixEpoch(uint!(20_000));
+        assert_eq!(policy.select_evicted(vec![thumbnail()], now), Vec::<&str>::new());
+    }
+
     #[test]
     fn test_media_request_url() {
         let mxc_uri = mxc_uri!("mxc://homeserver/media");
@@ -220,4 +586,20 @@ mod tests {
 
         assert_eq!(file.uri(), mxc_uri);
     }
+
+    #[test]
+    fn test_url_preview_lenient_sizes() {
+        let preview: UrlPreview = serde_json::from_value(json!({
+            "og:title": "Matrix",
+            "og:image:width": "640",
+            "og:image:height": 480,
+            "matrix:image:size": "unknown",
+        }))
+        .unwrap();
+
+        assert_eq!(preview.title.as_deref(), Some("Matrix"));
+        assert_eq!(preview.image_width, Some(uint!(640)));
+        assert_eq!(preview.image_height, Some(uint!(480)));
+        assert_eq!(preview.image_size, None);
+    }
 }
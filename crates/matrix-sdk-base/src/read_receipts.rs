@@ -772,6 +772,7 @@ mod tests {
                 }),
                 encryption_info: None,
                 push_actions,
+                unable_to_decrypt_reason: None,
             }
         }
 
@@ -857,6 +858,7 @@ mod tests {
                 }),
                 encryption_info: None,
                 push_actions: Vec::new(),
+                unable_to_decrypt_reason: None,
             }
         }
 
@@ -13,6 +13,7 @@
 // limitations under the License.
 
 use std::{
+    cmp::Reverse,
     collections::{BTreeMap, BTreeSet},
     sync::Arc,
 };
@@ -26,9 +27,10 @@ use ruma::{
         },
         MessageLikeEventType, StateEventType,
     },
-    MxcUri, OwnedUserId, UserId,
+    Int, MxcUri, OwnedUserId, UserId,
 };
 
+use super::RoomMemberships;
 use crate::{
     deserialized_responses::{MemberEvent, SyncOrStrippedState},
     MinimalRoomMemberEvent,
@@ -233,6 +235,170 @@ impl RoomMember {
     pub fn is_ignored(&self) -> bool {
         self.is_ignored
     }
+
+    /// Whether the display name or the user ID of this member contains the
+    /// given string, case-insensitively.
+    pub fn matches_search(&self, search: &str) -> bool {
+        matches_search(&search.to_lowercase(), self.user_id(), self.display_name())
+    }
+}
+
+/// A query for a page of the members of a room, used by
+/// [`StateStore::get_room_member_page()`].
+///
+/// The members are sorted by power level, in descending order, then by name,
+/// case-insensitively. The name of a member is the display name of their
+/// profile, or the localpart of their user ID.
+///
+/// [`StateStore::get_room_member_page()`]: crate::StateStore::get_room_member_page
+#[derive(Clone, Debug)]
+pub struct RoomMemberQuery {
+    /// The memberships of the members to return.
+    ///
+    /// If it is empty, members with any membership are returned.
+    pub memberships: RoomMemberships,
+
+    /// Only return the members whose display name or user ID contains this
+    /// string, case-insensitively.
+    pub search: Option<String>,
+
+    /// The power levels of the users whose power level is not the default
+    /// one.
+    pub users_power_levels: BTreeMap<OwnedUserId, Int>,
+
+    /// The power level of the users that are not in `users_power_levels`.
+    pub users_default_power_level: Int,
+
+    /// The number of matching members to skip.
+    pub offset: usize,
+
+    /// The maximum number of members to return.
+    pub limit: usize,
+}
+
+impl RoomMemberQuery {
+    /// The name a member is sorted and searched by: the lowercase display name
+    /// of their profile, or the localpart of their user ID.
+    ///
+    /// Stores keep it with each member, and update it when the member event
+    /// of the member changes, so they can answer queries without loading the
+    /// profiles of all the members of the room.
+    pub fn sort_name(user_id: &UserId, display_name: Option<&str>) -> String {
+        display_name.unwrap_or(user_id.localpart()).to_lowercase()
+    }
+
+    /// The lowercase string the members must contain, if any.
+    pub fn search_term(&self) -> Option<String> {
+        self.search.as_deref().map(str::to_lowercase)
+    }
+
+    /// Select the page of the given members that matches this query.
+    ///
+    /// This sorts the members in memory, so it is meant for the
+    /// implementations of [`StateStore::get_room_member_page()`] that can't
+    /// sort them in the store, for example because their names are encrypted.
+    ///
+    /// # Arguments
+    ///
+    /// * `members` - The user IDs of the members of the room that have one of
+    ///   the memberships of this query, with their [sort
+    ///   name](Self::sort_name()).
+    ///
+    /// [`StateStore::get_room_member_page()`]: crate::StateStore::get_room_member_page
+    pub fn select_page(
+        &self,
+        members: impl IntoIterator<Item = (OwnedUserId, String)>,
+    ) -> RoomMemberPage {
+        self.select_page_from_sorted(&self.sort_members(members))
+    }
+
+    /// Sort the given members in the order of this query.
+    ///
+    /// The order only depends on the power levels of this query, not on its
+    /// search string, offset or limit, so the result can be kept to select the
+    /// pages of other queries with the same power levels with
+    /// [`Self::select_page_from_sorted()`].
+    ///
+    /// # Arguments
+    ///
+    /// * `members` - The user IDs of the members of the room that have one of
+    ///   the memberships of this query, with their [sort
+    ///   name](Self::sort_name()).
+    pub fn sort_members(
+        &self,
+        members: impl IntoIterator<Item = (OwnedUserId, String)>,
+    ) -> Vec<(OwnedUserId, String)> {
+        let mut members = members
+            .into_iter()
+            .map(|(user_id, name)| {
+                let power_level = self
+                    .users_power_levels
+                    .get(&user_id)
+                    .copied()
+                    .unwrap_or(self.users_default_power_level);
+                (Reverse(power_level), name, user_id)
+            })
+            .collect::<Vec<_>>();
+        members.sort_unstable();
+
+        members.into_iter().map(|(_, name, user_id)| (user_id, name)).collect()
+    }
+
+    /// Select the page of the given members that matches this query.
+    ///
+    /// # Arguments
+    ///
+    /// * `members` - The members of the room, sorted with
+    ///   [`Self::sort_members()`].
+    pub fn select_page_from_sorted(&self, members: &[(OwnedUserId, String)]) -> RoomMemberPage {
+        let Some(search) = self.search_term() else {
+            let user_ids = members
+                .iter()
+                .skip(self.offset)
+                .take(self.limit)
+                .map(|(user_id, _)| user_id.clone())
+                .collect();
+            return RoomMemberPage { user_ids, total: members.len() };
+        };
+
+        let mut total = 0;
+        let mut user_ids = Vec::new();
+
+        for (user_id, name) in members {
+            if !name.contains(search.as_str())
+                && !user_id.as_str().to_lowercase().contains(search.as_str())
+            {
+                continue;
+            }
+
+            if total >= self.offset && user_ids.len() < self.limit {
+                user_ids.push(user_id.clone());
+            }
+            total += 1;
+        }
+
+        RoomMemberPage { user_ids, total }
+    }
+}
+
+/// A page of the members of a room, returned by
+/// [`StateStore::get_room_member_page()`].
+///
+/// [`StateStore::get_room_member_page()`]: crate::StateStore::get_room_member_page
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RoomMemberPage {
+    /// The user IDs of the members in this page, in order.
+    pub user_ids: Vec<OwnedUserId>,
+
+    /// The total number of members matching the query.
+    pub total: usize,
+}
+
+/// Whether the given display name or user ID contains the given lowercase
+/// string, case-insensitively.
+fn matches_search(search: &str, user_id: &UserId, display_name: Option<&str>) -> bool {
+    display_name.is_some_and(|name| name.to_lowercase().contains(search))
+        || user_id.as_str().to_lowercase().contains(search)
 }
 
 // Information about the room a member is in.
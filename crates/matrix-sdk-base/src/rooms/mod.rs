@@ -10,7 +10,7 @@ use std::{
 };
 
 use bitflags::bitflags;
-pub use members::RoomMember;
+pub use members::{RoomMember, RoomMemberPage, RoomMemberQuery};
 pub use normal::{Room, RoomInfo, RoomInfoUpdate, RoomState, RoomStateFilter};
 use ruma::{
     assign,
@@ -39,6 +39,7 @@ use ruma::{
             history_visibility::HistoryVisibility,
             join_rules::JoinRule,
             member::{MembershipState, RoomMemberEventContent},
+            power_levels::RoomPowerLevelsEventContent,
             redaction::SyncRoomRedactionEvent,
             tombstone::RoomTombstoneEventContent,
         },
@@ -46,6 +47,7 @@ use ruma::{
         AnyRoomAccountDataEvent, AnyStrippedStateEvent, AnySyncStateEvent,
         RoomAccountDataEventType,
     },
+    int,
     room::RoomType,
     serde::Raw,
     EventId, MxcUri, OwnedEventId, OwnedMxcUri, OwnedRoomAliasId, OwnedRoomId, OwnedUserId,
@@ -57,7 +59,7 @@ use tracing::{debug, field::debug, info, instrument, warn};
 
 use super::{
     members::MemberRoomInfo, BaseRoomInfo, DisplayName, RoomCreateWithCreatorEventContent,
-    RoomMember, RoomNotableTags,
+    RoomMember, RoomMemberQuery, RoomNotableTags,
 };
 #[cfg(feature = "experimental-sliding-sync")]
 use crate::latest_event::LatestEvent;
@@ -535,27 +537,84 @@ impl Room {
     /// given memberships.
     pub async fn members(&self, memberships: RoomMemberships) -> StoreResult<Vec<RoomMember>> {
         let user_ids = self.store.get_user_ids(self.room_id(), memberships).await?;
+        self.members_with_user_ids(&user_ids).await
+    }
+
+    /// Get a page of the `RoomMember`s of this room that are known to the
+    /// store, with the given memberships.
+    ///
+    /// The members are sorted by power level, in descending order, then by
+    /// name. If `search` is set, only the members whose display name or user
+    /// ID contains it, case-insensitively, are returned.
+    ///
+    /// Returns the members in the page, and the total number of members that
+    /// match the memberships and the search.
+    pub async fn members_page(
+        &self,
+        memberships: RoomMemberships,
+        search: Option<&str>,
+        offset: usize,
+        limit: usize,
+    ) -> StoreResult<(Vec<RoomMember>, usize)> {
+        let power_levels = self
+            .store
+            .get_state_event_static::<RoomPowerLevelsEventContent>(self.room_id())
+            .await?
+            .and_then(|e| e.deserialize().ok());
 
+        let (users_power_levels, users_default_power_level) = match power_levels {
+            Some(event) => {
+                let power_levels = event.power_levels();
+                (power_levels.users, power_levels.users_default)
+            }
+            // Without power levels, the creator of the room has the power level 100.
+            None => {
+                let creator = self.inner.read().creator().map(ToOwned::to_owned);
+                (creator.map(|creator| (creator, int!(100))).into_iter().collect(), int!(0))
+            }
+        };
+
+        let query = RoomMemberQuery {
+            memberships,
+            search: search.map(ToOwned::to_owned),
+            users_power_levels,
+            users_default_power_level,
+            offset,
+            limit,
+        };
+        let page = self.store.get_room_member_page(self.room_id(), &query).await?;
+
+        Ok((self.members_with_user_ids(&page.user_ids).await?, page.total))
+    }
+
+    /// Get the `RoomMember`s of this room with the given user IDs, in the same
+    /// order.
+    async fn members_with_user_ids(
+        &self,
+        user_ids: &[OwnedUserId],
+    ) -> StoreResult<Vec<RoomMember>> {
         if user_ids.is_empty() {
             return Ok(Vec::new());
         }
 
-        let member_events = self
+        let mut member_events = self
             .store
             .get_state_events_for_keys_static::<RoomMemberEventContent, _, _>(
                 self.room_id(),
-                &user_ids,
+                user_ids,
             )
             .await?
             .into_iter()
-            .map(|raw_event| raw_event.deserialize())
-            .collect::<Result<Vec<_>, _>>()?;
+            .map(|raw_event| {
+                raw_event.deserialize().map(|event| (event.user_id().to_owned(), event))
+            })
+            .collect::<Result<BTreeMap<_, _>, _>>()?;
 
-        let mut profiles = self.store.get_profiles(self.room_id(), &user_ids).await?;
+        let mut profiles = self.store.get_profiles(self.room_id(), user_ids).await?;
 
         let mut presences = self
             .store
-            .get_presence_events(&user_ids)
+            .get_presence_events(user_ids)
             .await?
             .into_iter()
             .filter_map(|e| {
@@ -564,14 +623,17 @@ impl Room {
             .collect::<BTreeMap<_, _>>();
 
         let display_names =
-            member_events.iter().map(|e| e.display_name().to_owned()).collect::<Vec<_>>();
+            member_events.values().map(|e| e.display_name().to_owned()).collect::<Vec<_>>();
         let room_info = self.member_room_info(&display_names).await?;
 
         let mut members = Vec::new();
 
-        for event in member_events {
-            let profile = profiles.remove(event.user_id());
-            let presence = presences.remove(event.user_id());
+        for user_id in user_ids {
+            let Some(event) = member_events.remove(user_id) else {
+                continue;
+            };
+            let profile = profiles.remove(&**user_id);
+            let presence = presences.remove(user_id);
             members.push(RoomMember::from_parts(event, profile, presence, &room_info))
         }
 
@@ -16,9 +16,9 @@ use std::collections::BTreeMap;
 #[cfg(feature = "e2e-encryption")]
 use std::ops::Deref;
 
-use matrix_sdk_common::deserialized_responses::SyncTimelineEvent;
 #[cfg(feature = "e2e-encryption")]
-use ruma::events::AnyToDeviceEvent;
+use matrix_sdk_common::deserialized_responses::ProcessedToDeviceEvent;
+use matrix_sdk_common::deserialized_responses::SyncTimelineEvent;
 use ruma::{
     api::client::sync::sync_events::{
         v3::{self, InvitedRoom},
@@ -54,7 +54,7 @@ impl BaseClient {
     pub async fn process_sliding_sync_e2ee(
         &self,
         extensions: &v4::Extensions,
-    ) -> Result<Vec<Raw<AnyToDeviceEvent>>> {
+    ) -> Result<Vec<ProcessedToDeviceEvent>> {
         if extensions.is_empty() {
             return Ok(Default::default());
         }
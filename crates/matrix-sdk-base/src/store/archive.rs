@@ -0,0 +1,617 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Portable archives of the content of the stores.
+//!
+//! An archive is exported from a store using only the methods of the store
+//! traits, so it can be imported into a store that uses another backend, for
+//! example to move a logged-in session from the IndexedDB store to the SQLite
+//! store, or into a fresh store after the previous one was corrupted.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use growable_bloom_filter::GrowableBloom;
+#[cfg(feature = "e2e-encryption")]
+use matrix_sdk_crypto::store::{CryptoStoreArchive, CryptoStoreError, DynCryptoStore};
+#[cfg(feature = "e2e-encryption")]
+use matrix_sdk_store_encryption::{EncryptedValueBase64, StoreCipher};
+use ruma::{
+    events::{
+        secret_storage::default_key::SecretStorageDefaultKeyEvent, AnyGlobalAccountDataEvent,
+        AnyRoomAccountDataEvent, AnyStrippedStateEvent, AnySyncStateEvent,
+        GlobalAccountDataEventType, RoomAccountDataEventType, StateEventType,
+    },
+    serde::Raw,
+    OwnedUserId, UserId,
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+#[cfg(feature = "e2e-encryption")]
+use super::StoreEncryptionError;
+use super::{
+    ComposerDraft, DynStateStore, Result, StateChanges, StateStoreDataKey, StateStoreDataValue,
+    StoreError,
+};
+use crate::{
+    deserialized_responses::RawAnySyncOrStrippedState, MinimalRoomMemberEvent, RoomInfo,
+    RoomMemberships,
+};
+
+/// The version of the format of the encrypted archives.
+#[cfg(feature = "e2e-encryption")]
+const ENCRYPTED_ARCHIVE_VERSION: u8 = 1;
+
+/// The types of the state events that are exported.
+const STATE_EVENT_TYPES: &[StateEventType] = &[
+    StateEventType::PolicyRuleRoom,
+    StateEventType::PolicyRuleServer,
+    StateEventType::PolicyRuleUser,
+    StateEventType::RoomAvatar,
+    StateEventType::RoomCanonicalAlias,
+    StateEventType::RoomCreate,
+    StateEventType::RoomEncryption,
+    StateEventType::RoomGuestAccess,
+    StateEventType::RoomHistoryVisibility,
+    StateEventType::RoomJoinRules,
+    StateEventType::RoomMember,
+    StateEventType::RoomName,
+    StateEventType::RoomPinnedEvents,
+    StateEventType::RoomPowerLevels,
+    StateEventType::RoomServerAcl,
+    StateEventType::RoomThirdPartyInvite,
+    StateEventType::RoomTombstone,
+    StateEventType::RoomTopic,
+    StateEventType::SpaceChild,
+    StateEventType::SpaceParent,
+];
+
+/// The types of the global account data events that are exported, in addition
+/// to the secret storage key events.
+const GLOBAL_ACCOUNT_DATA_TYPES: &[&str] = &[
+    "m.direct",
+    "m.identity_server",
+    "m.ignored_user_list",
+    "m.push_rules",
+    "m.secret_storage.default_key",
+    "m.cross_signing.master",
+    "m.cross_signing.self_signing",
+    "m.cross_signing.user_signing",
+    "m.megolm_backup.v1",
+];
+
+/// The types of the room account data events that are exported.
+const ROOM_ACCOUNT_DATA_TYPES: &[&str] =
+    &["m.fully_read", "m.tag", "m.marked_unread", "com.famedly.marked_unread"];
+
+/// An error that can happen when exporting or importing an archive.
+#[derive(Debug, Error)]
+pub enum ArchiveError {
+    /// An error happened in the state store.
+    #[error(transparent)]
+    StateStore(#[from] StoreError),
+
+    /// An error happened in the crypto store.
+    #[cfg(feature = "e2e-encryption")]
+    #[error(transparent)]
+    CryptoStore(#[from] CryptoStoreError),
+
+    /// The archive could not be encrypted or decrypted, for example because the
+    /// passphrase is wrong.
+    #[cfg(feature = "e2e-encryption")]
+    #[error(transparent)]
+    Encryption(#[from] StoreEncryptionError),
+
+    /// The archive could not be serialized or deserialized.
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+
+    /// The archive uses a version of the format that is not supported.
+    #[error("unsupported archive version: {0}")]
+    UnsupportedVersion(u8),
+}
+
+/// The content of a [`StateStore`], that can be imported into another store.
+///
+/// The archive contains the global account data, and for each room its info,
+/// its state, the profiles and display names of its members, its account data,
+/// its composer draft and whether URL previews are allowed.
+///
+/// Only the well-known types of state events and account data events are
+/// exported. Receipts, presence, filters, custom values and the media cache are
+/// not exported. The sync token is not exported either, so the next sync after
+/// an import is an initial sync, which restores what is missing.
+///
+/// [`StateStore`]: super::StateStore
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StateStoreArchive {
+    account_data: Vec<Raw<AnyGlobalAccountDataEvent>>,
+    rooms: Vec<RoomArchive>,
+    user_avatar_url: Option<String>,
+    recently_visited_rooms: Option<Vec<String>>,
+    utd_hook_manager_data: Option<GrowableBloom>,
+}
+
+/// The content of a room in a [`StateStoreArchive`].
+#[derive(Debug, Serialize, Deserialize)]
+struct RoomArchive {
+    info: RoomInfo,
+    state: Vec<Raw<AnySyncStateEvent>>,
+    stripped_state: Vec<Raw<AnyStrippedStateEvent>>,
+    profiles: BTreeMap<OwnedUserId, MinimalRoomMemberEvent>,
+    display_names: BTreeMap<String, BTreeSet<OwnedUserId>>,
+    account_data: Vec<Raw<AnyRoomAccountDataEvent>>,
+    composer_draft: Option<ComposerDraft>,
+    url_previews_in_encrypted_room: Option<bool>,
+}
+
+impl StateStoreArchive {
+    /// Export the content of the given store.
+    ///
+    /// # Arguments
+    ///
+    /// * `store` - The store to export.
+    ///
+    /// * `user_id` - The ID of the user that owns the store.
+    pub async fn export(store: &DynStateStore, user_id: &UserId) -> Result<Self> {
+        let user_avatar_url = store
+            .get_kv_data(StateStoreDataKey::UserAvatarUrl(user_id))
+            .await?
+            .and_then(StateStoreDataValue::into_user_avatar_url);
+        let recently_visited_rooms = store
+            .get_kv_data(StateStoreDataKey::RecentlyVisitedRooms(user_id))
+            .await?
+            .and_then(StateStoreDataValue::into_recently_visited_rooms);
+        let utd_hook_manager_data = store
+            .get_kv_data(StateStoreDataKey::UtdHookManagerData)
+            .await?
+            .and_then(StateStoreDataValue::into_utd_hook_manager_data);
+
+        let mut account_data_types: Vec<GlobalAccountDataEventType> =
+            GLOBAL_ACCOUNT_DATA_TYPES.iter().map(|&event_type| event_type.into()).collect();
+        let mut account_data = Vec::new();
+
+        while let Some(event_type) = account_data_types.pop() {
+            let Some(event) = store.get_account_data_event(event_type.clone()).await? else {
+                continue;
+            };
+
+            // Also export the key that is used by default for secret storage.
+            if event_type == GlobalAccountDataEventType::SecretStorageDefaultKey {
+                if let Ok(event) = event.deserialize_as::<SecretStorageDefaultKeyEvent>() {
+                    account_data_types
+                        .push(GlobalAccountDataEventType::SecretStorageKey(event.content.key_id));
+                }
+            }
+
+            account_data.push(event);
+        }
+
+        let mut rooms = Vec::new();
+        for info in store.get_room_infos().await? {
+            rooms.push(RoomArchive::export(store, info).await?);
+        }
+
+        Ok(Self {
+            account_data,
+            rooms,
+            user_avatar_url,
+            recently_visited_rooms,
+            utd_hook_manager_data,
+        })
+    }
+
+    /// Import the content of this archive into the given store.
+    ///
+    /// # Arguments
+    ///
+    /// * `store` - The store to import the archive into.
+    ///
+    /// * `user_id` - The ID of the user that owns the store.
+    pub async fn import_into(self, store: &DynStateStore, user_id: &UserId) -> Result<()> {
+        let mut changes = StateChanges::default();
+
+        for event in self.account_data {
+            if let Some(event_type) = event.get_field("type")? {
+                changes.account_data.insert(event_type, event);
+            }
+        }
+
+        let mut kv_data = Vec::new();
+
+        for room in self.rooms {
+            let room_id = room.info.room_id.clone();
+
+            for event in room.state {
+                if let Some((event_type, state_key)) = state_event_key(&event)? {
+                    changes
+                        .state
+                        .entry(room_id.clone())
+                        .or_default()
+                        .entry(event_type)
+                        .or_default()
+                        .insert(state_key, event);
+                }
+            }
+
+            for event in room.stripped_state {
+                if let Some((event_type, state_key)) = state_event_key(&event)? {
+                    changes
+                        .stripped_state
+                        .entry(room_id.clone())
+                        .or_default()
+                        .entry(event_type)
+                        .or_default()
+                        .insert(state_key, event);
+                }
+            }
+
+            for event in room.account_data {
+                if let Some(event_type) = event.get_field::<RoomAccountDataEventType>("type")? {
+                    changes
+                        .room_account_data
+                        .entry(room_id.clone())
+                        .or_default()
+                        .insert(event_type, event);
+                }
+            }
+
+            if !room.profiles.is_empty() {
+                changes.profiles.insert(room_id.clone(), room.profiles);
+            }
+            if !room.display_names.is_empty() {
+                changes.ambiguity_maps.insert(room_id.clone(), room.display_names);
+            }
+
+            if let Some(draft) = room.composer_draft {
+                kv_data.push((room_id.clone(), StateStoreDataValue::ComposerDraft(draft)));
+            }
+            if let Some(allowed) = room.url_previews_in_encrypted_room {
+                kv_data.push((
+                    room_id.clone(),
+                    StateStoreDataValue::UrlPreviewsInEncryptedRoom(allowed),
+                ));
+            }
+
+            changes.add_room(room.info);
+        }
+
+        store.save_changes(&changes).await?;
+
+        for (room_id, value) in kv_data {
+            let key = match &value {
+                StateStoreDataValue::ComposerDraft(_) => StateStoreDataKey::ComposerDraft(&room_id),
+                _ => StateStoreDataKey::UrlPreviewsInEncryptedRoom(&room_id),
+            };
+            store.set_kv_data(key, value).await?;
+        }
+
+        if let Some(url) = self.user_avatar_url {
+            store
+                .set_kv_data(
+                    StateStoreDataKey::UserAvatarUrl(user_id),
+                    StateStoreDataValue::UserAvatarUrl(url),
+                )
+                .await?;
+        }
+        if let Some(rooms) = self.recently_visited_rooms {
+            store
+                .set_kv_data(
+                    StateStoreDataKey::RecentlyVisitedRooms(user_id),
+                    StateStoreDataValue::RecentlyVisitedRooms(rooms),
+                )
+                .await?;
+        }
+        if let Some(data) = self.utd_hook_manager_data {
+            store
+                .set_kv_data(
+                    StateStoreDataKey::UtdHookManagerData,
+                    StateStoreDataValue::UtdHookManagerData(data),
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+}
+
+impl RoomArchive {
+    async fn export(store: &DynStateStore, info: RoomInfo) -> Result<Self> {
+        let room_id = info.room_id.clone();
+
+        let mut state = Vec::new();
+        let mut stripped_state = Vec::new();
+
+        for event_type in STATE_EVENT_TYPES {
+            for event in store.get_state_events(&room_id, event_type.clone()).await? {
+                match event {
+                    RawAnySyncOrStrippedState::Sync(event) => state.push(event),
+                    RawAnySyncOrStrippedState::Stripped(event) => stripped_state.push(event),
+                }
+            }
+        }
+
+        let user_ids = store.get_user_ids(&room_id, RoomMemberships::empty()).await?;
+        let profiles: BTreeMap<_, _> = store
+            .get_profiles(&room_id, &user_ids)
+            .await?
+            .into_iter()
+            .map(|(user_id, profile)| (user_id.to_owned(), profile))
+            .collect();
+
+        // Look up the display names that the members are likely to use, like the
+        // ambiguity map does.
+        let names: BTreeSet<String> = user_ids
+            .iter()
+            .map(|user_id| {
+                profiles
+                    .get(user_id)
+                    .and_then(|profile| profile.as_original()?.content.displayname.clone())
+                    .unwrap_or_else(|| user_id.localpart().to_owned())
+            })
+            .collect();
+        let names: Vec<String> = names.into_iter().collect();
+        let display_names = store
+            .get_users_with_display_names(&room_id, &names)
+            .await?
+            .into_iter()
+            .filter(|(_, users)| !users.is_empty())
+            .map(|(name, users)| (name.to_owned(), users))
+            .collect();
+
+        let mut account_data = Vec::new();
+        for &event_type in ROOM_ACCOUNT_DATA_TYPES {
+            if let Some(event) =
+                store.get_room_account_data_event(&room_id, event_type.into()).await?
+            {
+                account_data.push(event);
+            }
+        }
+
+        let composer_draft = store
+            .get_kv_data(StateStoreDataKey::ComposerDraft(&room_id))
+            .await?
+            .and_then(StateStoreDataValue::into_composer_draft);
+        let url_previews_in_encrypted_room = store
+            .get_kv_data(StateStoreDataKey::UrlPreviewsInEncryptedRoom(&room_id))
+            .await?
+            .and_then(StateStoreDataValue::into_url_previews_in_encrypted_room);
+
+        Ok(Self {
+            info,
+            state,
+            stripped_state,
+            profiles,
+            display_names,
+            account_data,
+            composer_draft,
+            url_previews_in_encrypted_room,
+        })
+    }
+}
+
+/// Get the type and state key of the given state event.
+fn state_event_key<T>(
+    event: &Raw<T>,
+) -> Result<Option<(StateEventType, String)>, serde_json::Error> {
+    let event_type = event.get_field::<StateEventType>("type")?;
+    let state_key = event.get_field::<String>("state_key")?;
+    Ok(event_type.zip(state_key))
+}
+
+/// The content of a crypto store and, optionally, of a state store, that can
+/// be imported into stores with another backend.
+///
+/// The archive can be serialized and encrypted with a passphrase with
+/// [`StoreArchive::encrypt()`].
+#[cfg(feature = "e2e-encryption")]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StoreArchive {
+    crypto: CryptoStoreArchive,
+    state: Option<StateStoreArchive>,
+}
+
+/// The serialized form of an encrypted [`StoreArchive`].
+#[cfg(feature = "e2e-encryption")]
+#[derive(Serialize, Deserialize)]
+struct EncryptedArchive {
+    version: u8,
+    /// The store cipher, encrypted with the passphrase.
+    cipher: Vec<u8>,
+    /// The archive, encrypted with the store cipher.
+    archive: EncryptedValueBase64,
+}
+
+#[cfg(feature = "e2e-encryption")]
+impl StoreArchive {
+    /// Export the content of the given crypto store and, if it is set, of the
+    /// given state store.
+    pub async fn export(
+        crypto_store: &DynCryptoStore,
+        state_store: Option<&DynStateStore>,
+    ) -> Result<Self, ArchiveError> {
+        let crypto = CryptoStoreArchive::export(crypto_store).await?;
+
+        let state = match state_store {
+            Some(store) => Some(StateStoreArchive::export(store, crypto.user_id()).await?),
+            None => None,
+        };
+
+        Ok(Self { crypto, state })
+    }
+
+    /// The archive of the crypto store.
+    pub fn crypto(&self) -> &CryptoStoreArchive {
+        &self.crypto
+    }
+
+    /// The archive of the state store, if it was exported.
+    pub fn state(&self) -> Option<&StateStoreArchive> {
+        self.state.as_ref()
+    }
+
+    /// Import the content of this archive into the given crypto store and, if
+    /// it is set, into the given state store.
+    ///
+    /// The state store is left untouched if the archive doesn't contain the
+    /// content of a state store.
+    pub async fn import_into(
+        self,
+        crypto_store: &DynCryptoStore,
+        state_store: Option<&DynStateStore>,
+    ) -> Result<(), ArchiveError> {
+        let user_id = self.crypto.user_id().to_owned();
+
+        self.crypto.import_into(crypto_store).await?;
+
+        if let (Some(state), Some(store)) = (self.state, state_store) {
+            state.import_into(store, &user_id).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Serialize this archive and encrypt it with the given passphrase.
+    pub fn encrypt(&self, passphrase: &str) -> Result<Vec<u8>, ArchiveError> {
+        let cipher = StoreCipher::new()?;
+        #[cfg(not(test))]
+        let exported_cipher = cipher.export(passphrase)?;
+        #[cfg(test)]
+        let exported_cipher = cipher._insecure_export_fast_for_testing(passphrase)?;
+
+        let encrypted = EncryptedArchive {
+            version: ENCRYPTED_ARCHIVE_VERSION,
+            cipher: exported_cipher,
+            archive: cipher.encrypt_value_base64_typed(self)?,
+        };
+
+        Ok(serde_json::to_vec(&encrypted)?)
+    }
+
+    /// Decrypt an archive that was encrypted with [`StoreArchive::encrypt()`]
+    /// and the given passphrase.
+    pub fn decrypt(bytes: &[u8], passphrase: &str) -> Result<Self, ArchiveError> {
+        let encrypted: EncryptedArchive = serde_json::from_slice(bytes)?;
+
+        if encrypted.version != ENCRYPTED_ARCHIVE_VERSION {
+            return Err(ArchiveError::UnsupportedVersion(encrypted.version));
+        }
+
+        let cipher = StoreCipher::import(passphrase, &encrypted.cipher)?;
+        Ok(cipher.decrypt_value_base64_typed(encrypted.archive)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use matrix_sdk_test::async_test;
+    use ruma::{
+        events::{GlobalAccountDataEventType, StateEventType},
+        room_id, user_id,
+    };
+
+    use super::StateStoreArchive;
+    use crate::{
+        store::{
+            IntoStateStore, MemoryStore, StateStore, StateStoreDataKey, StateStoreIntegrationTests,
+        },
+        RoomMemberships,
+    };
+
+    #[async_test]
+    async fn test_state_store_round_trip() {
+        let user_id = user_id!("@example:localhost");
+        let room_id = room_id!("!test:localhost");
+
+        let store = MemoryStore::new().into_state_store();
+        store.populate().await.unwrap();
+
+        let archive = StateStoreArchive::export(&store, user_id).await.unwrap();
+
+        // The archive survives a serialization round trip.
+        let archive: StateStoreArchive =
+            serde_json::from_slice(&serde_json::to_vec(&archive).unwrap()).unwrap();
+
+        let new_store = MemoryStore::new().into_state_store();
+        archive.import_into(&new_store, user_id).await.unwrap();
+
+        // The sync token isn't restored, since the archive is incomplete.
+        assert!(new_store.get_kv_data(StateStoreDataKey::SyncToken).await.unwrap().is_none());
+
+        assert_eq!(new_store.get_room_infos().await.unwrap().len(), 2);
+        assert!(new_store
+            .get_account_data_event(GlobalAccountDataEventType::PushRules)
+            .await
+            .unwrap()
+            .is_some());
+        assert!(new_store
+            .get_state_event(room_id, StateEventType::RoomName, "")
+            .await
+            .unwrap()
+            .is_some());
+        assert_eq!(
+            new_store.get_user_ids(room_id, RoomMemberships::empty()).await.unwrap(),
+            store.get_user_ids(room_id, RoomMemberships::empty()).await.unwrap()
+        );
+        assert_eq!(
+            new_store.get_users_with_display_name(room_id, "example").await.unwrap(),
+            store.get_users_with_display_name(room_id, "example").await.unwrap()
+        );
+    }
+
+    #[cfg(feature = "e2e-encryption")]
+    #[async_test]
+    async fn test_encrypted_archive() {
+        use assert_matches::assert_matches;
+        use matrix_sdk_crypto::{
+            olm::Account,
+            store::{
+                CryptoStore, IntoCryptoStore, MemoryStore as MemoryCryptoStore, PendingChanges,
+            },
+        };
+        use ruma::device_id;
+
+        use super::{ArchiveError, StoreArchive};
+
+        let account = Account::with_device_id(user_id!("@example:localhost"), device_id!("DEVICE"));
+        let crypto_store = MemoryCryptoStore::new();
+        crypto_store
+            .save_pending_changes(PendingChanges { account: Some(account.deep_clone()) })
+            .await
+            .unwrap();
+        let crypto_store = crypto_store.into_crypto_store();
+
+        let state_store = MemoryStore::new().into_state_store();
+        state_store.populate().await.unwrap();
+
+        let archive = StoreArchive::export(&crypto_store, Some(&state_store)).await.unwrap();
+        let encrypted = archive.encrypt("passphrase").unwrap();
+
+        assert_matches!(
+            StoreArchive::decrypt(&encrypted, "wrong passphrase"),
+            Err(ArchiveError::Encryption(_))
+        );
+
+        let archive = StoreArchive::decrypt(&encrypted, "passphrase").unwrap();
+        assert!(archive.state().is_some());
+
+        let new_crypto_store = MemoryCryptoStore::new().into_crypto_store();
+        let new_state_store = MemoryStore::new().into_state_store();
+        archive.import_into(&new_crypto_store, Some(&new_state_store)).await.unwrap();
+
+        let new_account = new_crypto_store.load_account().await.unwrap().unwrap();
+        assert_eq!(new_account.identity_keys(), account.identity_keys());
+        assert_eq!(new_state_store.get_room_infos().await.unwrap().len(), 2);
+    }
+}
@@ -1,6 +1,9 @@
 //! Trait and macro of integration tests for StateStore implementations.
 
-use std::collections::{BTreeMap, BTreeSet};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    time::Duration,
+};
 
 use assert_matches::assert_matches;
 use assert_matches2::assert_let;
@@ -26,18 +29,19 @@ use ruma::{
         AnyStrippedStateEvent, AnySyncEphemeralRoomEvent, AnySyncStateEvent,
         GlobalAccountDataEventType, RoomAccountDataEventType, StateEventType, SyncStateEvent,
     },
-    mxc_uri, room_id,
+    int, mxc_uri, owned_event_id, room_id,
     serde::Raw,
-    uint, user_id, EventId, OwnedEventId, OwnedUserId, RoomId, UserId,
+    uint, user_id, EventId, MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedUserId, RoomId, UserId,
 };
 use serde_json::{json, value::Value as JsonValue};
 
 use super::DynStateStore;
 use crate::{
     deserialized_responses::MemberEvent,
-    media::{MediaFormat, MediaRequest, MediaThumbnailSize},
-    store::{Result, StateStoreExt},
-    RoomInfo, RoomMemberships, RoomState, StateChanges, StateStoreDataKey, StateStoreDataValue,
+    media::{MediaFormat, MediaRequest, MediaRetentionPolicy, MediaThumbnailSize},
+    store::{ComposerDraft, ComposerDraftType, Result, StateStoreExt},
+    RoomInfo, RoomMemberQuery, RoomMemberships, RoomState, StateChanges, StateStoreDataKey,
+    StateStoreDataValue,
 };
 
 /// `StateStore` integration tests.
@@ -51,6 +55,8 @@ pub trait StateStoreIntegrationTests {
     async fn populate(&self) -> Result<()>;
     /// Test media content storage.
     async fn test_media_content(&self);
+    /// Test media cache clean-up.
+    async fn test_media_cache_clean_up(&self);
     /// Test room topic redaction.
     async fn test_topic_redaction(&self) -> Result<()>;
     /// Test populating the store.
@@ -65,6 +71,10 @@ pub trait StateStoreIntegrationTests {
     async fn test_sync_token_saving(&self);
     /// Test UtdHookManagerData saving.
     async fn test_utd_hook_manager_data_saving(&self);
+    /// Test composer draft saving.
+    async fn test_composer_draft_saving(&self);
+    /// Test URL previews setting saving.
+    async fn test_url_previews_setting_saving(&self);
     /// Test stripped room member saving.
     async fn test_stripped_member_saving(&self);
     /// Test room power levels saving.
@@ -85,6 +95,8 @@ pub trait StateStoreIntegrationTests {
     async fn test_presence_saving(&self);
     /// Test display names saving.
     async fn test_display_names_saving(&self);
+    /// Test the pages of room members.
+    async fn test_room_member_page(&self);
 }
 
 #[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
@@ -309,6 +321,53 @@ impl StateStoreIntegrationTests for DynStateStore {
         );
     }
 
+    async fn test_media_cache_clean_up(&self) {
+        let uri = mxc_uri!("mxc://localhost/media");
+        let request_file =
+            MediaRequest { source: MediaSource::Plain(uri.to_owned()), format: MediaFormat::File };
+        let big_uri = mxc_uri!("mxc://localhost/media-big");
+        let request_big_file = MediaRequest {
+            source: MediaSource::Plain(big_uri.to_owned()),
+            format: MediaFormat::File,
+        };
+        let other_uri = mxc_uri!("mxc://localhost/media-other");
+        let request_other_file = MediaRequest {
+            source: MediaSource::Plain(other_uri.to_owned()),
+            format: MediaFormat::File,
+        };
+
+        self.add_media_content(&request_file, "hello".into()).await.unwrap();
+        self.add_media_content(&request_big_file, "hello world".into()).await.unwrap();
+        self.add_media_content(&request_other_file, "foo".into()).await.unwrap();
+        assert_eq!(self.media_cache_size().await.unwrap(), 19);
+
+        let now = MilliSecondsSinceUnixEpoch::now();
+
+        // Files that are too big are evicted.
+        let policy = MediaRetentionPolicy::empty().with_max_file_size(Some(8));
+        self.clean_up_media_cache(policy, &[], now).await.unwrap();
+        assert!(self.get_media_content(&request_big_file).await.unwrap().is_none());
+        assert_eq!(self.media_cache_size().await.unwrap(), 8);
+
+        // Protected files are kept even if the cache is too big.
+        let policy = MediaRetentionPolicy::empty().with_max_cache_size(Some(0));
+        self.clean_up_media_cache(policy, &[uri.to_owned()], now).await.unwrap();
+        assert!(self.get_media_content(&request_file).await.unwrap().is_some());
+        assert!(self.get_media_content(&request_other_file).await.unwrap().is_none());
+        assert_eq!(self.media_cache_size().await.unwrap(), 5);
+
+        // Files that weren't accessed for too long are evicted.
+        let policy = MediaRetentionPolicy::empty()
+            .with_last_access_expiry(Some(Duration::from_secs(60 * 60 * 24)));
+        self.clean_up_media_cache(policy, &[], now).await.unwrap();
+        assert_eq!(self.media_cache_size().await.unwrap(), 5);
+
+        let later = MilliSecondsSinceUnixEpoch(now.get() + uint!(172_800_000));
+        self.clean_up_media_cache(policy, &[], later).await.unwrap();
+        assert!(self.get_media_content(&request_file).await.unwrap().is_none());
+        assert_eq!(self.media_cache_size().await.unwrap(), 0);
+    }
+
     async fn test_topic_redaction(&self) -> Result<()> {
         let room_id = room_id();
         self.populate().await?;
@@ -628,6 +687,108 @@ impl StateStoreIntegrationTests for DynStateStore {
         assert_eq!(read_data, data);
     }
 
+    async fn test_composer_draft_saving(&self) {
+        let room_id = room_id!("!test_composer_draft:localhost");
+
+        // Before any data is written, the getter should return None.
+        assert!(
+            self.get_kv_data(StateStoreDataKey::ComposerDraft(room_id))
+                .await
+                .expect("Could not read data")
+                .is_none(),
+            "Store was not empty at start"
+        );
+
+        // Put some data in the store...
+        let draft = ComposerDraft {
+            plain_text: "Hello, *world*!".to_owned(),
+            html_text: Some("Hello, <em>world</em>!".to_owned()),
+            draft_type: ComposerDraftType::Reply { event_id: owned_event_id!("$replied_to") },
+        };
+        self.set_kv_data(
+            StateStoreDataKey::ComposerDraft(room_id),
+            StateStoreDataValue::ComposerDraft(draft),
+        )
+        .await
+        .expect("Could not save data");
+
+        // ... and check it comes back.
+        let read_draft = self
+            .get_kv_data(StateStoreDataKey::ComposerDraft(room_id))
+            .await
+            .expect("Could not read data")
+            .expect("no data found")
+            .into_composer_draft()
+            .expect("not ComposerDraft");
+
+        assert_eq!(read_draft.plain_text, "Hello, *world*!");
+        assert_eq!(read_draft.html_text.as_deref(), Some("Hello, <em>world</em>!"));
+        assert_let!(ComposerDraftType::Reply { event_id } = read_draft.draft_type);
+        assert_eq!(event_id, "$replied_to");
+
+        // Drafts are stored per room.
+        assert!(self
+            .get_kv_data(StateStoreDataKey::ComposerDraft(room_id!("!other:localhost")))
+            .await
+            .expect("Could not read data")
+            .is_none());
+
+        // Removing the draft clears it.
+        self.remove_kv_data(StateStoreDataKey::ComposerDraft(room_id))
+            .await
+            .expect("Could not remove data");
+        assert!(self
+            .get_kv_data(StateStoreDataKey::ComposerDraft(room_id))
+            .await
+            .expect("Could not read data")
+            .is_none());
+    }
+
+    async fn test_url_previews_setting_saving(&self) {
+        let room_id = room_id!("!test_url_previews:localhost");
+
+        // Before any data is written, the getter should return None.
+        assert!(self
+            .get_kv_data(StateStoreDataKey::UrlPreviewsInEncryptedRoom(room_id))
+            .await
+            .expect("Could not read data")
+            .is_none());
+
+        self.set_kv_data(
+            StateStoreDataKey::UrlPreviewsInEncryptedRoom(room_id),
+            StateStoreDataValue::UrlPreviewsInEncryptedRoom(true),
+        )
+        .await
+        .expect("Could not save data");
+
+        let allowed = self
+            .get_kv_data(StateStoreDataKey::UrlPreviewsInEncryptedRoom(room_id))
+            .await
+            .expect("Could not read data")
+            .expect("no data found")
+            .into_url_previews_in_encrypted_room()
+            .expect("not UrlPreviewsInEncryptedRoom");
+        assert!(allowed);
+
+        // The setting is stored per room.
+        assert!(self
+            .get_kv_data(StateStoreDataKey::UrlPreviewsInEncryptedRoom(room_id!(
+                "!other:localhost"
+            )))
+            .await
+            .expect("Could not read data")
+            .is_none());
+
+        self.remove_kv_data(StateStoreDataKey::UrlPreviewsInEncryptedRoom(room_id))
+            .await
+            .expect("Could not remove data");
+        assert!(self
+            .get_kv_data(StateStoreDataKey::UrlPreviewsInEncryptedRoom(room_id))
+            .await
+            .expect("Could not read data")
+            .is_none());
+    }
+
     async fn test_stripped_member_saving(&self) {
         let room_id = room_id!("!test_stripped_member_saving:localhost");
         let user_id = user_id();
@@ -1296,6 +1457,135 @@ impl StateStoreIntegrationTests for DynStateStore {
         let names = self.get_users_with_display_names(room_id, &[]).await;
         assert!(names.unwrap().is_empty());
     }
+
+    async fn test_room_member_page(&self) {
+        let room_id = room_id!("!test_room_member_page:localhost");
+        let alice = user_id!("@alice:localhost");
+        let bob = user_id!("@bob:localhost");
+        let carol = user_id!("@carol:localhost");
+        let dave = user_id!("@dave:localhost");
+
+        let mut query = RoomMemberQuery {
+            memberships: RoomMemberships::JOIN,
+            search: None,
+            users_power_levels: [(alice.to_owned(), int!(100))].into(),
+            users_default_power_level: int!(0),
+            offset: 0,
+            limit: 10,
+        };
+
+        // No member in store.
+        let page = self.get_room_member_page(room_id, &query).await.unwrap();
+        assert!(page.user_ids.is_empty());
+        assert_eq!(page.total, 0);
+
+        let member_changes = |members: &[(&UserId, MembershipState, Option<&str>)]| {
+            let mut changes = StateChanges::default();
+            for (user_id, membership, display_name) in members {
+                let raw_event: Raw<SyncRoomMemberEvent> = Raw::new(&json!({
+                    "type": "m.room.member",
+                    "content": {
+                        "membership": membership,
+                        "displayname": display_name,
+                    },
+                    "event_id": format!("$member_{}_{}", user_id.localpart(), membership.as_str()),
+                    "origin_server_ts": 198,
+                    "sender": user_id,
+                    "state_key": user_id,
+                }))
+                .unwrap()
+                .cast();
+                let profile = raw_event.deserialize().unwrap().into();
+                changes
+                    .state
+                    .entry(room_id.to_owned())
+                    .or_default()
+                    .entry(StateEventType::RoomMember)
+                    .or_default()
+                    .insert(user_id.to_string(), raw_event.cast());
+                changes
+                    .profiles
+                    .entry(room_id.to_owned())
+                    .or_default()
+                    .insert((*user_id).to_owned(), profile);
+            }
+            changes
+        };
+
+        let changes = member_changes(&[
+            (alice, MembershipState::Join, Some("Zed")),
+            (bob, MembershipState::Join, Some("alpha")),
+            (carol, MembershipState::Join, None),
+            (dave, MembershipState::Invite, Some("Dave")),
+        ]);
+        self.save_changes(&changes).await.unwrap();
+
+        // The members are sorted by power level, then by name.
+        let page = self.get_room_member_page(room_id, &query).await.unwrap();
+        assert_eq!(page.user_ids, [alice, bob, carol]);
+        assert_eq!(page.total, 3);
+
+        // Pagination.
+        query.offset = 1;
+        query.limit = 1;
+        let page = self.get_room_member_page(room_id, &query).await.unwrap();
+        assert_eq!(page.user_ids, [bob]);
+        assert_eq!(page.total, 3);
+
+        query.offset = 3;
+        let page = self.get_room_member_page(room_id, &query).await.unwrap();
+        assert!(page.user_ids.is_empty());
+        assert_eq!(page.total, 3);
+
+        // Search by display name, or by user ID.
+        query.offset = 0;
+        query.limit = 10;
+        query.search = Some("ALP".to_owned());
+        let page = self.get_room_member_page(room_id, &query).await.unwrap();
+        assert_eq!(page.user_ids, [bob]);
+        assert_eq!(page.total, 1);
+
+        query.search = Some("@car".to_owned());
+        let page = self.get_room_member_page(room_id, &query).await.unwrap();
+        assert_eq!(page.user_ids, [carol]);
+        assert_eq!(page.total, 1);
+
+        // Other memberships.
+        query.search = None;
+        query.memberships = RoomMemberships::INVITE;
+        let page = self.get_room_member_page(room_id, &query).await.unwrap();
+        assert_eq!(page.user_ids, [dave]);
+        assert_eq!(page.total, 1);
+
+        query.memberships = RoomMemberships::empty();
+        let page = self.get_room_member_page(room_id, &query).await.unwrap();
+        assert_eq!(page.user_ids, [alice, bob, carol, dave]);
+        assert_eq!(page.total, 4);
+
+        // The members are sorted again when they change.
+        let changes = member_changes(&[
+            (bob, MembershipState::Join, Some("Zulu")),
+            (dave, MembershipState::Join, None),
+        ]);
+        self.save_changes(&changes).await.unwrap();
+
+        query.memberships = RoomMemberships::JOIN;
+        let page = self.get_room_member_page(room_id, &query).await.unwrap();
+        assert_eq!(page.user_ids, [alice, carol, dave, bob]);
+        assert_eq!(page.total, 4);
+
+        query.search = Some("zul".to_owned());
+        let page = self.get_room_member_page(room_id, &query).await.unwrap();
+        assert_eq!(page.user_ids, [bob]);
+        assert_eq!(page.total, 1);
+
+        // The members with a power level lower than the default one are last.
+        query.search = None;
+        query.users_power_levels.insert(carol.to_owned(), int!(-10));
+        let page = self.get_room_member_page(room_id, &query).await.unwrap();
+        assert_eq!(page.user_ids, [alice, dave, bob, carol]);
+        assert_eq!(page.total, 4);
+    }
 }
 
 /// Macro building to allow your StateStore implementation to run the entire
@@ -1335,6 +1625,12 @@ macro_rules! statestore_integration_tests {
                 let store = get_store().await.unwrap().into_state_store();
                 store.test_media_content().await;
             }
+
+            #[async_test]
+            async fn test_media_cache_clean_up() {
+                let store = get_store().await.unwrap().into_state_store();
+                store.test_media_cache_clean_up().await;
+            }
         }
     };
     () => {
@@ -1391,6 +1687,18 @@ macro_rules! statestore_integration_tests {
              store.test_utd_hook_manager_data_saving().await;
         }
 
+        #[async_test]
+        async fn test_composer_draft_saving() {
+            let store = get_store().await.expect("creating store failed").into_state_store();
+            store.test_composer_draft_saving().await;
+        }
+
+        #[async_test]
+        async fn test_url_previews_setting_saving() {
+            let store = get_store().await.expect("creating store failed").into_state_store();
+            store.test_url_previews_setting_saving().await;
+        }
+
         #[async_test]
         async fn test_stripped_member_saving() {
             let store = get_store().await.unwrap().into_state_store();
@@ -1450,6 +1758,12 @@ macro_rules! statestore_integration_tests {
             let store = get_store().await.expect("creating store failed").into_state_store();
             store.test_display_names_saving().await;
         }
+
+        #[async_test]
+        async fn test_room_member_page() {
+            let store = get_store().await.expect("creating store failed").into_state_store();
+            store.test_room_member_page().await;
+        }
     };
 }
 
@@ -31,16 +31,17 @@ use ruma::{
         AnySyncStateEvent, GlobalAccountDataEventType, RoomAccountDataEventType, StateEventType,
     },
     serde::Raw,
-    CanonicalJsonObject, EventId, MxcUri, OwnedEventId, OwnedMxcUri, OwnedRoomId, OwnedUserId,
-    RoomId, RoomVersionId, UserId,
+    CanonicalJsonObject, EventId, MilliSecondsSinceUnixEpoch, MxcUri, OwnedEventId, OwnedMxcUri,
+    OwnedRoomId, OwnedUserId, RoomId, RoomVersionId, UserId,
 };
 use tracing::{debug, warn};
 
-use super::{Result, RoomInfo, StateChanges, StateStore, StoreError};
+use super::{ComposerDraft, Result, RoomInfo, StateChanges, StateStore, StoreError};
 use crate::{
     deserialized_responses::RawAnySyncOrStrippedState,
-    media::{MediaRequest, UniqueKey as _},
-    MinimalRoomMemberEvent, RoomMemberships, RoomState, StateStoreDataKey, StateStoreDataValue,
+    media::{MediaCacheEntry, MediaFormat, MediaRequest, MediaRetentionPolicy, UniqueKey as _},
+    MinimalRoomMemberEvent, RoomMemberPage, RoomMemberQuery, RoomMemberships, RoomState,
+    StateStoreDataKey, StateStoreDataValue,
 };
 
 /// In-Memory, non-persistent implementation of the `StateStore`
@@ -54,10 +55,15 @@ pub struct MemoryStore {
     sync_token: StdRwLock<Option<String>>,
     filters: StdRwLock<HashMap<String, String>>,
     utd_hook_manager_data: StdRwLock<Option<GrowableBloom>>,
+    composer_drafts: StdRwLock<HashMap<OwnedRoomId, ComposerDraft>>,
+    url_previews_in_encrypted_rooms: StdRwLock<HashMap<OwnedRoomId, bool>>,
     account_data: StdRwLock<HashMap<GlobalAccountDataEventType, Raw<AnyGlobalAccountDataEvent>>>,
     profiles: StdRwLock<HashMap<OwnedRoomId, HashMap<OwnedUserId, MinimalRoomMemberEvent>>>,
     display_names: StdRwLock<HashMap<OwnedRoomId, HashMap<String, BTreeSet<OwnedUserId>>>>,
     members: StdRwLock<HashMap<OwnedRoomId, HashMap<OwnedUserId, MembershipState>>>,
+    /// The [sort names](RoomMemberQuery::sort_name()) of the members of the
+    /// rooms, for stripped and regular rooms alike.
+    member_names: StdRwLock<HashMap<OwnedRoomId, HashMap<OwnedUserId, String>>>,
     room_info: StdRwLock<HashMap<OwnedRoomId, RoomInfo>>,
     room_state: StdRwLock<
         HashMap<OwnedRoomId, HashMap<StateEventType, HashMap<String, Raw<AnySyncStateEvent>>>>,
@@ -82,10 +88,21 @@ pub struct MemoryStore {
             HashMap<(String, Option<String>), HashMap<OwnedEventId, HashMap<OwnedUserId, Receipt>>>,
         >,
     >,
-    media: StdRwLock<RingBuffer<(OwnedMxcUri, String /* unique key */, Vec<u8>)>>,
+    media: StdRwLock<RingBuffer<MediaContent>>,
     custom: StdRwLock<HashMap<Vec<u8>, Vec<u8>>>,
 }
 
+/// A media file in the [`MemoryStore`].
+#[derive(Debug)]
+struct MediaContent {
+    uri: OwnedMxcUri,
+    /// The unique key of the `MediaRequest`.
+    key: String,
+    data: Vec<u8>,
+    last_access: MilliSecondsSinceUnixEpoch,
+    is_thumbnail: bool,
+}
+
 // SAFETY: `new_unchecked` is safe because 20 is not zero.
 const NUMBER_OF_MEDIAS: NonZeroUsize = unsafe { NonZeroUsize::new_unchecked(20) };
 
@@ -97,10 +114,13 @@ impl Default for MemoryStore {
             sync_token: Default::default(),
             filters: Default::default(),
             utd_hook_manager_data: Default::default(),
+            composer_drafts: Default::default(),
+            url_previews_in_encrypted_rooms: Default::default(),
             account_data: Default::default(),
             profiles: Default::default(),
             display_names: Default::default(),
             members: Default::default(),
+            member_names: Default::default(),
             room_info: Default::default(),
             room_state: Default::default(),
             room_account_data: Default::default(),
@@ -195,6 +215,20 @@ impl StateStore for MemoryStore {
                 .unwrap()
                 .clone()
                 .map(StateStoreDataValue::UtdHookManagerData),
+            StateStoreDataKey::ComposerDraft(room_id) => self
+                .composer_drafts
+                .read()
+                .unwrap()
+                .get(room_id)
+                .cloned()
+                .map(StateStoreDataValue::ComposerDraft),
+            StateStoreDataKey::UrlPreviewsInEncryptedRoom(room_id) => self
+                .url_previews_in_encrypted_rooms
+                .read()
+                .unwrap()
+                .get(room_id)
+                .copied()
+                .map(StateStoreDataValue::UrlPreviewsInEncryptedRoom),
         })
     }
 
@@ -235,6 +269,20 @@ impl StateStore for MemoryStore {
                         .expect("Session data not the hook manager data"),
                 );
             }
+            StateStoreDataKey::ComposerDraft(room_id) => {
+                self.composer_drafts.write().unwrap().insert(
+                    room_id.to_owned(),
+                    value.into_composer_draft().expect("Session data not a composer draft"),
+                );
+            }
+            StateStoreDataKey::UrlPreviewsInEncryptedRoom(room_id) => {
+                self.url_previews_in_encrypted_rooms.write().unwrap().insert(
+                    room_id.to_owned(),
+                    value
+                        .into_url_previews_in_encrypted_room()
+                        .expect("Session data not a URL previews setting"),
+                );
+            }
         }
 
         Ok(())
@@ -255,6 +303,12 @@ impl StateStore for MemoryStore {
             StateStoreDataKey::UtdHookManagerData => {
                 *self.utd_hook_manager_data.write().unwrap() = None
             }
+            StateStoreDataKey::ComposerDraft(room_id) => {
+                self.composer_drafts.write().unwrap().remove(room_id);
+            }
+            StateStoreDataKey::UrlPreviewsInEncryptedRoom(room_id) => {
+                self.url_previews_in_encrypted_rooms.write().unwrap().remove(room_id);
+            }
         }
         Ok(())
     }
@@ -323,6 +377,7 @@ impl StateStore for MemoryStore {
             let mut stripped_room_state = self.stripped_room_state.write().unwrap();
             let mut members = self.members.write().unwrap();
             let mut stripped_members = self.stripped_members.write().unwrap();
+            let mut member_names = self.member_names.write().unwrap();
 
             for (room, event_types) in &changes.state {
                 for (event_type, events) in event_types {
@@ -348,6 +403,14 @@ impl StateStore for MemoryStore {
 
                             stripped_members.remove(room);
 
+                            let display_name = event
+                                .as_original()
+                                .and_then(|event| event.content.displayname.as_deref());
+                            member_names.entry(room.clone()).or_default().insert(
+                                event.state_key().to_owned(),
+                                RoomMemberQuery::sort_name(event.state_key(), display_name),
+                            );
+
                             members
                                 .entry(room.clone())
                                 .or_default()
@@ -375,6 +438,7 @@ impl StateStore for MemoryStore {
         {
             let mut stripped_room_state = self.stripped_room_state.write().unwrap();
             let mut stripped_members = self.stripped_members.write().unwrap();
+            let mut member_names = self.member_names.write().unwrap();
 
             for (room, event_types) in &changes.stripped_state {
                 for (event_type, events) in event_types {
@@ -401,6 +465,14 @@ impl StateStore for MemoryStore {
                                 }
                             };
 
+                            member_names.entry(room.clone()).or_default().insert(
+                                event.state_key.clone(),
+                                RoomMemberQuery::sort_name(
+                                    &event.state_key,
+                                    event.content.displayname.as_deref(),
+                                ),
+                            );
+
                             stripped_members
                                 .entry(room.clone())
                                 .or_default()
@@ -676,6 +748,25 @@ impl StateStore for MemoryStore {
         StateStore::get_user_ids(self, room_id, RoomMemberships::JOIN).await
     }
 
+    async fn get_room_member_page(
+        &self,
+        room_id: &RoomId,
+        query: &RoomMemberQuery,
+    ) -> Result<RoomMemberPage> {
+        let user_ids = StateStore::get_user_ids(self, room_id, query.memberships).await?;
+
+        let member_names = self.member_names.read().unwrap();
+        let room_member_names = member_names.get(room_id);
+
+        Ok(query.select_page(user_ids.into_iter().map(|user_id| {
+            let name = room_member_names
+                .and_then(|names| names.get(&user_id))
+                .cloned()
+                .unwrap_or_else(|| RoomMemberQuery::sort_name(&user_id, None));
+            (user_id, name)
+        })))
+    }
+
     async fn get_room_infos(&self) -> Result<Vec<RoomInfo>> {
         Ok(self.room_info.read().unwrap().values().cloned().collect())
     }
@@ -784,27 +875,39 @@ impl StateStore for MemoryStore {
         // Avoid duplication. Let's try to remove it first.
         self.remove_media_content(request).await?;
         // Now, let's add it.
-        self.media.write().unwrap().push((request.uri().to_owned(), request.unique_key(), data));
+        self.media.write().unwrap().push(MediaContent {
+            uri: request.uri().to_owned(),
+            key: request.unique_key(),
+            data,
+            last_access: MilliSecondsSinceUnixEpoch::now(),
+            is_thumbnail: matches!(request.format, MediaFormat::Thumbnail(_)),
+        });
 
         Ok(())
     }
 
     async fn get_media_content(&self, request: &MediaRequest) -> Result<Option<Vec<u8>>> {
-        let media = self.media.read().unwrap();
+        let mut media = self.media.write().unwrap();
         let expected_key = request.unique_key();
 
-        Ok(media.iter().find_map(|(_media_uri, media_key, media_content)| {
-            (media_key == &expected_key).then(|| media_content.to_owned())
-        }))
+        let Some(index) = media.iter().position(|content| content.key == expected_key) else {
+            return Ok(None);
+        };
+
+        // Move the media to the end of the ring buffer, so the least recently
+        // accessed media are the first ones to be dropped.
+        let mut content = media.remove(index).expect("the index is valid");
+        content.last_access = MilliSecondsSinceUnixEpoch::now();
+        let data = content.data.clone();
+        media.push(content);
+
+        Ok(Some(data))
     }
 
     async fn remove_media_content(&self, request: &MediaRequest) -> Result<()> {
         let mut media = self.media.write().unwrap();
         let expected_key = request.unique_key();
-        let Some(index) = media
-            .iter()
-            .position(|(_media_uri, media_key, _media_content)| media_key == &expected_key)
-        else {
+        let Some(index) = media.iter().position(|content| content.key == expected_key) else {
             return Ok(());
         };
 
@@ -814,19 +917,37 @@ impl StateStore for MemoryStore {
     }
 
     async fn remove_media_content_for_uri(&self, uri: &MxcUri) -> Result<()> {
+        self.media.write().unwrap().retain(|content| content.uri.as_str() != uri.as_str());
+
+        Ok(())
+    }
+
+    async fn media_cache_size(&self) -> Result<usize> {
+        Ok(self.media.read().unwrap().iter().map(|content| content.data.len()).sum())
+    }
+
+    async fn clean_up_media_cache(
+        &self,
+        policy: MediaRetentionPolicy,
+        protected_uris: &[OwnedMxcUri],
+        now: MilliSecondsSinceUnixEpoch,
+    ) -> Result<()> {
         let mut media = self.media.write().unwrap();
-        let expected_key = uri.to_owned();
-        let positions = media
+
+        let entries = media
             .iter()
-            .enumerate()
-            .filter_map(|(position, (media_uri, _media_key, _media_content))| {
-                (media_uri == &expected_key).then_some(position)
+            .map(|content| MediaCacheEntry {
+                key: content.key.clone(),
+                size: content.data.len(),
+                last_access: content.last_access,
+                is_protected: protected_uris.contains(&content.uri),
+                is_thumbnail: content.is_thumbnail,
             })
-            .collect::<Vec<_>>();
+            .collect();
+        let evicted = policy.select_evicted(entries, now);
 
-        // Iterate in reverse-order so that positions stay valid after first removals.
-        for position in positions.into_iter().rev() {
-            media.remove(position);
+        if !evicted.is_empty() {
+            media.retain(|content| !evicted.contains(&content.key));
         }
 
         Ok(())
@@ -836,6 +957,7 @@ impl StateStore for MemoryStore {
         self.profiles.write().unwrap().remove(room_id);
         self.display_names.write().unwrap().remove(room_id);
         self.members.write().unwrap().remove(room_id);
+        self.member_names.write().unwrap().remove(room_id);
         self.room_info.write().unwrap().remove(room_id);
         self.room_state.write().unwrap().remove(room_id);
         self.room_account_data.write().unwrap().remove(room_id);
@@ -32,6 +32,7 @@ use std::{
 
 use once_cell::sync::OnceCell;
 
+mod archive;
 #[cfg(any(test, feature = "testing"))]
 #[macro_use]
 pub mod integration_tests;
@@ -65,13 +66,16 @@ pub(crate) mod ambiguity_map;
 mod memory_store;
 pub mod migration_helpers;
 
+#[cfg(feature = "e2e-encryption")]
+pub use self::archive::StoreArchive;
 #[cfg(any(test, feature = "testing"))]
 pub use self::integration_tests::StateStoreIntegrationTests;
 pub use self::{
+    archive::{ArchiveError, StateStoreArchive},
     memory_store::MemoryStore,
     traits::{
-        DynStateStore, IntoStateStore, StateStore, StateStoreDataKey, StateStoreDataValue,
-        StateStoreExt,
+        ComposerDraft, ComposerDraftType, DynStateStore, IntoStateStore, StateStore,
+        StateStoreDataKey, StateStoreDataValue, StateStoreExt,
     },
 };
 
@@ -33,14 +33,16 @@ use ruma::{
         RoomAccountDataEventType, StateEventType, StaticEventContent, StaticStateEventContent,
     },
     serde::Raw,
-    EventId, MxcUri, OwnedEventId, OwnedUserId, RoomId, UserId,
+    EventId, MilliSecondsSinceUnixEpoch, MxcUri, OwnedEventId, OwnedMxcUri, OwnedUserId, RoomId,
+    UserId,
 };
+use serde::{Deserialize, Serialize};
 
 use super::{StateChanges, StoreError};
 use crate::{
     deserialized_responses::{RawAnySyncOrStrippedState, RawMemberEvent, RawSyncOrStrippedState},
-    media::MediaRequest,
-    MinimalRoomMemberEvent, RoomInfo, RoomMemberships,
+    media::{MediaRequest, MediaRetentionPolicy},
+    MinimalRoomMemberEvent, RoomInfo, RoomMemberPage, RoomMemberQuery, RoomMemberships,
 };
 
 /// An abstract state store trait that can be used to implement different stores
@@ -196,6 +198,31 @@ pub trait StateStore: AsyncTraitDeps {
     #[deprecated = "Use get_user_ids with RoomMemberships::JOIN instead."]
     async fn get_joined_user_ids(&self, room_id: &RoomId) -> Result<Vec<OwnedUserId>, Self::Error>;
 
+    /// Get a page of the user IDs of the members of the given room that match
+    /// the given query.
+    ///
+    /// Like [`StateStore::get_user_ids()`], it works for stripped and regular
+    /// rooms alike. Implementations should keep the
+    /// [sort name](RoomMemberQuery::sort_name()) of each member next to its
+    /// membership, updated when its member event is saved, to avoid loading
+    /// the profiles of all the members of the room for every page.
+    /// [`RoomMemberQuery::select_page()`] can be used to sort and filter the
+    /// members when the store can't do it, or
+    /// [`RoomMemberQuery::sort_members()`] and
+    /// [`RoomMemberQuery::select_page_from_sorted()`] to keep the sorted members
+    /// between pages.
+    ///
+    /// # Arguments
+    ///
+    /// * `room_id` - The ID of the room to get the members of.
+    ///
+    /// * `query` - The query the members must match.
+    async fn get_room_member_page(
+        &self,
+        room_id: &RoomId,
+        query: &RoomMemberQuery,
+    ) -> Result<RoomMemberPage, Self::Error>;
+
     /// Get all the pure `RoomInfo`s the store knows about.
     async fn get_room_infos(&self) -> Result<Vec<RoomInfo>, Self::Error>;
 
@@ -360,6 +387,9 @@ pub trait StateStore: AsyncTraitDeps {
 
     /// Get a media file's content out of the media store.
     ///
+    /// This updates the last access time of the file, used by
+    /// [`StateStore::clean_up_media_cache()`].
+    ///
     /// # Arguments
     ///
     /// * `request` - The `MediaRequest` of the file.
@@ -383,6 +413,28 @@ pub trait StateStore: AsyncTraitDeps {
     /// * `uri` - The `MxcUri` of the media files.
     async fn remove_media_content_for_uri(&self, uri: &MxcUri) -> Result<(), Self::Error>;
 
+    /// Get the total size of the files in the media store, in bytes.
+    async fn media_cache_size(&self) -> Result<usize, Self::Error>;
+
+    /// Evict media files from the media store according to the given policy.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - The policy deciding which files are evicted, see
+    ///   [`MediaRetentionPolicy::select_evicted()`].
+    ///
+    /// * `protected_uris` - The `MxcUri`s of the files that must never be
+    ///   evicted.
+    ///
+    /// * `now` - The current time, used to compute how long ago the files were
+    ///   accessed.
+    async fn clean_up_media_cache(
+        &self,
+        policy: MediaRetentionPolicy,
+        protected_uris: &[OwnedMxcUri],
+        now: MilliSecondsSinceUnixEpoch,
+    ) -> Result<(), Self::Error>;
+
     /// Removes a room and all elements associated from the state store.
     ///
     /// # Arguments
@@ -504,6 +556,14 @@ impl<T: StateStore> StateStore for EraseStateStoreError<T> {
         self.0.get_user_ids(room_id, RoomMemberships::JOIN).await.map_err(Into::into)
     }
 
+    async fn get_room_member_page(
+        &self,
+        room_id: &RoomId,
+        query: &RoomMemberQuery,
+    ) -> Result<RoomMemberPage, Self::Error> {
+        self.0.get_room_member_page(room_id, query).await.map_err(Into::into)
+    }
+
     async fn get_room_infos(&self) -> Result<Vec<RoomInfo>, Self::Error> {
         self.0.get_room_infos().await.map_err(Into::into)
     }
@@ -609,6 +669,19 @@ impl<T: StateStore> StateStore for EraseStateStoreError<T> {
         self.0.remove_media_content_for_uri(uri).await.map_err(Into::into)
     }
 
+    async fn media_cache_size(&self) -> Result<usize, Self::Error> {
+        self.0.media_cache_size().await.map_err(Into::into)
+    }
+
+    async fn clean_up_media_cache(
+        &self,
+        policy: MediaRetentionPolicy,
+        protected_uris: &[OwnedMxcUri],
+        now: MilliSecondsSinceUnixEpoch,
+    ) -> Result<(), Self::Error> {
+        self.0.clean_up_media_cache(policy, protected_uris, now).await.map_err(Into::into)
+    }
+
     async fn remove_room(&self, room_id: &RoomId) -> Result<(), Self::Error> {
         self.0.remove_room(room_id).await.map_err(Into::into)
     }
@@ -813,6 +886,43 @@ pub enum StateStoreDataValue {
     /// Persistent data for
     /// `matrix_sdk_ui::unable_to_decrypt_hook::UtdHookManager`.
     UtdHookManagerData(GrowableBloom),
+
+    /// A composer draft for the room.
+    ///
+    /// To learn more, see [`ComposerDraft`].
+    ComposerDraft(ComposerDraft),
+
+    /// Whether URL previews are allowed in an encrypted room.
+    UrlPreviewsInEncryptedRoom(bool),
+}
+
+/// Current draft of the composer for the room.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ComposerDraft {
+    /// The draft content in plain text.
+    pub plain_text: String,
+    /// If the message is formatted in HTML, the HTML representation of the
+    /// message.
+    pub html_text: Option<String>,
+    /// The type of draft.
+    pub draft_type: ComposerDraftType,
+}
+
+/// The type of draft of the composer.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ComposerDraftType {
+    /// The draft is a new message.
+    NewMessage,
+    /// The draft is a reply to an event.
+    Reply {
+        /// The ID of the event being replied to.
+        event_id: OwnedEventId,
+    },
+    /// The draft is an edit of an event.
+    Edit {
+        /// The ID of the event being edited.
+        event_id: OwnedEventId,
+    },
 }
 
 impl StateStoreDataValue {
@@ -840,6 +950,16 @@ impl StateStoreDataValue {
     pub fn into_utd_hook_manager_data(self) -> Option<GrowableBloom> {
         as_variant!(self, Self::UtdHookManagerData)
     }
+
+    /// Get this value if it is a composer draft.
+    pub fn into_composer_draft(self) -> Option<ComposerDraft> {
+        as_variant!(self, Self::ComposerDraft)
+    }
+
+    /// Get this value if it is the URL previews setting of an encrypted room.
+    pub fn into_url_previews_in_encrypted_room(self) -> Option<bool> {
+        as_variant!(self, Self::UrlPreviewsInEncryptedRoom)
+    }
 }
 
 /// A key for key-value data.
@@ -860,6 +980,14 @@ pub enum StateStoreDataKey<'a> {
     /// Persistent data for
     /// `matrix_sdk_ui::unable_to_decrypt_hook::UtdHookManager`.
     UtdHookManagerData,
+
+    /// A composer draft for the room.
+    ///
+    /// To learn more, see [`ComposerDraft`].
+    ComposerDraft(&'a RoomId),
+
+    /// Whether URL previews are allowed in the encrypted room.
+    UrlPreviewsInEncryptedRoom(&'a RoomId),
 }
 
 impl StateStoreDataKey<'_> {
@@ -878,4 +1006,13 @@ impl StateStoreDataKey<'_> {
     /// Key to use for the [`UtdHookManagerData`][Self::UtdHookManagerData]
     /// variant.
     pub const UTD_HOOK_MANAGER_DATA: &'static str = "utd_hook_manager_data";
+
+    /// Key prefix to use for the [`ComposerDraft`][Self::ComposerDraft]
+    /// variant.
+    pub const COMPOSER_DRAFT: &'static str = "composer_draft";
+
+    /// Key prefix to use for the
+    /// [`UrlPreviewsInEncryptedRoom`][Self::UrlPreviewsInEncryptedRoom]
+    /// variant.
+    pub const URL_PREVIEWS_IN_ENCRYPTED_ROOM: &'static str = "url_previews_in_encrypted_room";
 }
@@ -15,14 +15,14 @@
 use std::{collections::BTreeMap, fmt};
 
 use ruma::{
-    events::{AnySyncTimelineEvent, AnyTimelineEvent},
+    events::{AnySyncTimelineEvent, AnyTimelineEvent, AnyToDeviceEvent},
     push::Action,
     serde::Raw,
     DeviceKeyAlgorithm, OwnedDeviceId, OwnedEventId, OwnedUserId,
 };
 use serde::{Deserialize, Serialize};
 
-use crate::debug::{DebugRawEvent, DebugStructExt};
+use crate::debug::{DebugRawEvent, DebugRawEventNoId, DebugStructExt};
 
 const AUTHENTICITY_NOT_GUARANTEED: &str =
     "The authenticity of this encrypted message can't be guaranteed on this device.";
@@ -200,6 +200,19 @@ pub enum AlgorithmInfo {
         /// decrypt this session. This map will usually contain a single ed25519
         /// key.
         sender_claimed_keys: BTreeMap<DeviceKeyAlgorithm, String>,
+        /// The ID of the megolm session that was used to decrypt the event.
+        ///
+        /// `None` if the event was decrypted by a version of the SDK that
+        /// didn't record it.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        session_id: Option<String>,
+    },
+
+    /// The info if the event was encrypted using m.olm.v1.curve25519-aes-sha2
+    OlmV1Curve25519AesSha2 {
+        /// The curve25519 key of the device that sent us the event, the Olm
+        /// session that decrypted the event was established with this key.
+        curve25519_key: String,
     },
 }
 
@@ -235,6 +248,10 @@ pub struct SyncTimelineEvent {
     /// The push actions associated with this event.
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub push_actions: Vec<Action>,
+    /// The reason why the event couldn't be decrypted, if it is encrypted and
+    /// the reason was known when decrypting it failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unable_to_decrypt_reason: Option<UnableToDecryptReason>,
 }
 
 impl SyncTimelineEvent {
@@ -243,7 +260,7 @@ impl SyncTimelineEvent {
     /// This is a convenience constructor for when you don't need to set
     /// `encryption_info` or `push_action`, for example inside a test.
     pub fn new(event: Raw<AnySyncTimelineEvent>) -> Self {
-        Self { event, encryption_info: None, push_actions: vec![] }
+        Self { event, encryption_info: None, push_actions: vec![], unable_to_decrypt_reason: None }
     }
 
     /// Get the event id of this `SyncTimelineEvent` if the event has any valid
@@ -256,20 +273,27 @@ impl SyncTimelineEvent {
 #[cfg(not(tarpaulin_include))]
 impl fmt::Debug for SyncTimelineEvent {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let SyncTimelineEvent { event, encryption_info, push_actions } = self;
+        let SyncTimelineEvent { event, encryption_info, push_actions, unable_to_decrypt_reason } =
+            self;
         let mut s = f.debug_struct("SyncTimelineEvent");
         s.field("event", &DebugRawEvent(event));
         s.maybe_field("encryption_info", encryption_info);
         if !push_actions.is_empty() {
             s.field("push_actions", push_actions);
         }
+        s.maybe_field("unable_to_decrypt_reason", unable_to_decrypt_reason);
         s.finish()
     }
 }
 
 impl From<Raw<AnySyncTimelineEvent>> for SyncTimelineEvent {
     fn from(inner: Raw<AnySyncTimelineEvent>) -> Self {
-        Self { encryption_info: None, event: inner, push_actions: Vec::default() }
+        Self {
+            encryption_info: None,
+            event: inner,
+            push_actions: Vec::default(),
+            unable_to_decrypt_reason: None,
+        }
     }
 }
 
@@ -283,10 +307,20 @@ impl From<TimelineEvent> for SyncTimelineEvent {
             event: o.event.cast(),
             encryption_info: o.encryption_info,
             push_actions: o.push_actions.unwrap_or_default(),
+            unable_to_decrypt_reason: None,
         }
     }
 }
 
+/// The reason why an encrypted event couldn't be decrypted, when it is known at
+/// the time of the decryption attempt.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub enum UnableToDecryptReason {
+    /// The room key is available, but the sender's device doesn't satisfy the
+    /// trust requirement that was set for decrypting the event.
+    SenderIdentityNotTrusted(VerificationLevel),
+}
+
 #[derive(Clone)]
 pub struct TimelineEvent {
     /// The actual event.
@@ -325,6 +359,42 @@ impl fmt::Debug for TimelineEvent {
     }
 }
 
+/// A to-device event that was received from the sync, and processed by the
+/// crypto layer.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct ProcessedToDeviceEvent {
+    /// The actual event, in its decrypted form if it was encrypted.
+    pub event: Raw<AnyToDeviceEvent>,
+    /// The encryption info about the event. Will be `None` if the event was not
+    /// encrypted, or if it couldn't be decrypted.
+    pub encryption_info: Option<EncryptionInfo>,
+}
+
+impl ProcessedToDeviceEvent {
+    /// Create a new `ProcessedToDeviceEvent` from the given raw event, without
+    /// any encryption info.
+    pub fn new(event: Raw<AnyToDeviceEvent>) -> Self {
+        Self { event, encryption_info: None }
+    }
+}
+
+#[cfg(not(tarpaulin_include))]
+impl fmt::Debug for ProcessedToDeviceEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ProcessedToDeviceEvent { event, encryption_info } = self;
+        let mut s = f.debug_struct("ProcessedToDeviceEvent");
+        s.field("event", &DebugRawEventNoId(event));
+        s.maybe_field("encryption_info", encryption_info);
+        s.finish()
+    }
+}
+
+impl From<Raw<AnyToDeviceEvent>> for ProcessedToDeviceEvent {
+    fn from(event: Raw<AnyToDeviceEvent>) -> Self {
+        Self::new(event)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use ruma::{
@@ -12,6 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use matrix_sdk_common::deserialized_responses::VerificationLevel;
 use ruma::{CanonicalJsonError, IdParseError, OwnedDeviceId, OwnedRoomId, OwnedUserId};
 use serde_json::Error as SerdeError;
 use thiserror::Error;
@@ -115,6 +116,15 @@ pub enum MegolmError {
     /// The storage layer returned an error.
     #[error(transparent)]
     Store(#[from] CryptoStoreError),
+
+    /// The sender's device is not sufficiently trusted to decrypt the event,
+    /// according to the configured [`TrustRequirement`].
+    ///
+    /// [`TrustRequirement`]: crate::TrustRequirement
+    #[error(
+        "decryption failed because the sender's device doesn't satisfy the trust requirement: {0:?}"
+    )]
+    SenderIdentityNotTrusted(VerificationLevel),
 }
 
 /// Error that occurs when decrypting an event that is malformed.
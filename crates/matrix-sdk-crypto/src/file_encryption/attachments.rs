@@ -41,9 +41,7 @@ type Aes256Ctr = ctr::Ctr128BE<Aes256>;
 /// Matrix attachment.
 pub struct AttachmentDecryptor<'a, R: Read> {
     inner: &'a mut R,
-    expected_hash: Vec<u8>,
-    sha: Sha256,
-    aes: Aes256Ctr,
+    decryptor: ChunkedAttachmentDecryptor,
 }
 
 #[cfg(not(tarpaulin_include))]
@@ -51,7 +49,7 @@ impl<'a, R: 'a + Read + std::fmt::Debug> std::fmt::Debug for AttachmentDecryptor
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("AttachmentDecryptor")
             .field("inner", &self.inner)
-            .field("expected_hash", &self.expected_hash)
+            .field("expected_hash", &self.decryptor.expected_hash)
             .finish()
     }
 }
@@ -61,17 +59,10 @@ impl<'a, R: Read> Read for AttachmentDecryptor<'a, R> {
         let read_bytes = self.inner.read(buf)?;
 
         if read_bytes == 0 {
-            let hash = self.sha.finalize_reset();
-
-            if hash.as_slice() == self.expected_hash.as_slice() {
-                Ok(0)
-            } else {
-                Err(IoError::new(ErrorKind::Other, "Hash mismatch while decrypting"))
-            }
+            self.decryptor.verify_hash().map_err(|e| IoError::new(ErrorKind::Other, e))?;
+            Ok(0)
         } else {
-            self.sha.update(&buf[0..read_bytes]);
-            self.aes.apply_keystream(&mut buf[0..read_bytes]);
-
+            self.decryptor.decrypt_chunk(&mut buf[0..read_bytes]);
             Ok(read_bytes)
         }
     }
@@ -94,6 +85,9 @@ pub enum DecryptorError {
     /// attachment encryption spec.
     #[error("Unknown version for the encrypted attachment.")]
     UnknownVersion,
+    /// The hash of the decrypted data doesn't match the expected hash.
+    #[error("Hash mismatch while decrypting")]
+    HashMismatch,
 }
 
 impl<'a, R: Read + 'a> AttachmentDecryptor<'a, R> {
@@ -130,6 +124,32 @@ impl<'a, R: Read + 'a> AttachmentDecryptor<'a, R> {
         input: &'a mut R,
         info: MediaEncryptionInfo,
     ) -> Result<AttachmentDecryptor<'a, R>, DecryptorError> {
+        Ok(AttachmentDecryptor { inner: input, decryptor: ChunkedAttachmentDecryptor::new(info)? })
+    }
+}
+
+/// Decrypts a Matrix attachment chunk by chunk, as it is received.
+///
+/// This is the building block of [`AttachmentDecryptor`], for data that isn't
+/// available through a `Read`er, like a stream of HTTP chunks.
+pub struct ChunkedAttachmentDecryptor {
+    expected_hash: Vec<u8>,
+    sha: Sha256,
+    aes: Aes256Ctr,
+}
+
+#[cfg(not(tarpaulin_include))]
+impl std::fmt::Debug for ChunkedAttachmentDecryptor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ChunkedAttachmentDecryptor")
+            .field("expected_hash", &self.expected_hash)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ChunkedAttachmentDecryptor {
+    /// Create a decryptor for an attachment encrypted with the given info.
+    pub fn new(info: MediaEncryptionInfo) -> Result<Self, DecryptorError> {
         if info.version != VERSION {
             return Err(DecryptorError::UnknownVersion);
         }
@@ -151,7 +171,38 @@ impl<'a, R: Read + 'a> AttachmentDecryptor<'a, R> {
         let aes = Aes256Ctr::new(key_array, &iv);
         key.zeroize();
 
-        Ok(AttachmentDecryptor { inner: input, expected_hash: hash, sha, aes })
+        Ok(Self { expected_hash: hash, sha, aes })
+    }
+
+    /// Decrypt the next chunk of the attachment in place.
+    pub fn decrypt_chunk(&mut self, chunk: &mut [u8]) {
+        self.sha.update(&*chunk);
+        self.aes.apply_keystream(chunk);
+    }
+
+    /// Skip the next chunk of the attachment, that was already decrypted.
+    ///
+    /// This is used to resume an interrupted decryption: the chunk is
+    /// encrypted again to update the hash of the attachment, so it can still
+    /// be verified at the end.
+    pub fn skip_decrypted_chunk(&mut self, chunk: &[u8]) {
+        let mut chunk = chunk.to_vec();
+        self.aes.apply_keystream(&mut chunk);
+        self.sha.update(&chunk);
+    }
+
+    /// Check that the hash of the chunks decrypted so far matches the
+    /// expected hash of the attachment.
+    ///
+    /// This must be called once the whole attachment was decrypted.
+    pub fn verify_hash(&mut self) -> Result<(), DecryptorError> {
+        let hash = self.sha.finalize_reset();
+
+        if hash.as_slice() == self.expected_hash.as_slice() {
+            Ok(())
+        } else {
+            Err(DecryptorError::HashMismatch)
+        }
     }
 }
 
@@ -159,11 +210,7 @@ impl<'a, R: Read + 'a> AttachmentDecryptor<'a, R> {
 pub struct AttachmentEncryptor<'a, R: Read + ?Sized> {
     finished: bool,
     inner: &'a mut R,
-    web_key: JsonWebKey,
-    iv: Base64,
-    hashes: BTreeMap<String, Base64>,
-    aes: Aes256Ctr,
-    sha: Sha256,
+    encryptor: ChunkedAttachmentEncryptor,
 }
 
 #[cfg(not(tarpaulin_include))]
@@ -181,15 +228,10 @@ impl<'a, R: Read + ?Sized + 'a> Read for AttachmentEncryptor<'a, R> {
         let read_bytes = self.inner.read(buf)?;
 
         if read_bytes == 0 {
-            let hash = self.sha.finalize_reset();
-            self.hashes
-                .entry("sha256".to_owned())
-                .or_insert_with(|| Base64::new(hash.as_slice().to_owned()));
+            self.encryptor.finalize_hash();
             Ok(0)
         } else {
-            self.aes.apply_keystream(&mut buf[0..read_bytes]);
-            self.sha.update(&buf[0..read_bytes]);
-
+            self.encryptor.encrypt_chunk(&mut buf[0..read_bytes]);
             Ok(read_bytes)
         }
     }
@@ -225,6 +267,52 @@ impl<'a, R: Read + ?Sized + 'a> AttachmentEncryptor<'a, R> {
     /// let key = encryptor.finish();
     /// ```
     pub fn new(reader: &'a mut R) -> Self {
+        AttachmentEncryptor {
+            finished: false,
+            inner: reader,
+            encryptor: ChunkedAttachmentEncryptor::new(),
+        }
+    }
+
+    /// Consume the encryptor and get the encryption key.
+    pub fn finish(self) -> MediaEncryptionInfo {
+        self.encryptor.finish()
+    }
+}
+
+/// Encrypts a Matrix attachment chunk by chunk.
+///
+/// This is the building block of [`AttachmentEncryptor`], for data that isn't
+/// available through a `Read`er, like an asynchronous stream.
+pub struct ChunkedAttachmentEncryptor {
+    web_key: JsonWebKey,
+    iv: Base64,
+    hashes: BTreeMap<String, Base64>,
+    aes: Aes256Ctr,
+    sha: Sha256,
+}
+
+#[cfg(not(tarpaulin_include))]
+impl std::fmt::Debug for ChunkedAttachmentEncryptor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ChunkedAttachmentEncryptor").finish_non_exhaustive()
+    }
+}
+
+impl Default for ChunkedAttachmentEncryptor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ChunkedAttachmentEncryptor {
+    /// Create an encryptor with a fresh encryption key.
+    ///
+    /// # Panics
+    ///
+    /// Panics if we can't generate enough random data to create a fresh
+    /// encryption key.
+    pub fn new() -> Self {
         let mut key = [0u8; KEY_SIZE];
         let mut iv = [0u8; IV_SIZE];
 
@@ -251,23 +339,29 @@ impl<'a, R: Read + ?Sized + 'a> AttachmentEncryptor<'a, R> {
         let aes = Aes256Ctr::new(key_array, &iv.into());
         key.zeroize();
 
-        AttachmentEncryptor {
-            finished: false,
-            inner: reader,
-            iv: encoded_iv,
-            web_key,
-            hashes: BTreeMap::new(),
-            aes,
-            sha: Sha256::default(),
-        }
+        Self { iv: encoded_iv, web_key, hashes: BTreeMap::new(), aes, sha: Sha256::default() }
     }
 
-    /// Consume the encryptor and get the encryption key.
-    pub fn finish(mut self) -> MediaEncryptionInfo {
-        let hash = self.sha.finalize();
+    /// Encrypt the next chunk of the attachment in place.
+    pub fn encrypt_chunk(&mut self, chunk: &mut [u8]) {
+        self.aes.apply_keystream(chunk);
+        self.sha.update(&*chunk);
+    }
+
+    /// Record the hash of the chunks encrypted so far, if it wasn't recorded
+    /// yet.
+    fn finalize_hash(&mut self) {
+        let hash = self.sha.finalize_reset();
         self.hashes
             .entry("sha256".to_owned())
             .or_insert_with(|| Base64::new(hash.as_slice().to_owned()));
+    }
+
+    /// Consume the encryptor and get the encryption key.
+    ///
+    /// This must be called once the whole attachment was encrypted.
+    pub fn finish(mut self) -> MediaEncryptionInfo {
+        self.finalize_hash();
 
         MediaEncryptionInfo {
             version: VERSION.to_owned(),
@@ -303,9 +397,13 @@ impl From<EncryptedFile> for MediaEncryptionInfo {
 mod tests {
     use std::io::{Cursor, Read};
 
+    use assert_matches::assert_matches;
     use serde_json::json;
 
-    use super::{AttachmentDecryptor, AttachmentEncryptor, MediaEncryptionInfo};
+    use super::{
+        AttachmentDecryptor, AttachmentEncryptor, ChunkedAttachmentDecryptor,
+        ChunkedAttachmentEncryptor, DecryptorError, MediaEncryptionInfo,
+    };
 
     const EXAMPLE_DATA: &[u8] = &[
         179, 154, 118, 127, 186, 127, 110, 33, 203, 33, 33, 134, 67, 100, 173, 46, 235, 27, 215,
@@ -379,4 +477,33 @@ mod tests {
 
         decryptor.read_to_end(&mut decrypted_data).unwrap_err();
     }
+
+    #[test]
+    fn chunked_encrypt_decrypt_cycle() {
+        let data = b"Hello world, in chunks".to_vec();
+
+        let mut encryptor = ChunkedAttachmentEncryptor::new();
+        let mut encrypted = data.clone();
+        for chunk in encrypted.chunks_mut(5) {
+            encryptor.encrypt_chunk(chunk);
+        }
+        let key = encryptor.finish();
+
+        // Resume the decryption after the first bytes were already decrypted.
+        let mut decryptor = ChunkedAttachmentDecryptor::new(key).unwrap();
+        decryptor.skip_decrypted_chunk(&data[..7]);
+        let mut rest = encrypted[7..].to_vec();
+        decryptor.decrypt_chunk(&mut rest);
+        decryptor.verify_hash().unwrap();
+        assert_eq!(rest, &data[7..]);
+    }
+
+    #[test]
+    fn chunked_decrypt_invalid_hash() {
+        let mut decryptor = ChunkedAttachmentDecryptor::new(example_key()).unwrap();
+        let mut chunk = b"fake message".to_vec();
+        decryptor.decrypt_chunk(&mut chunk);
+
+        assert_matches!(decryptor.verify_hash(), Err(DecryptorError::HashMismatch));
+    }
 }
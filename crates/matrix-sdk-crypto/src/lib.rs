@@ -70,12 +70,44 @@ impl RoomKeyImportResult {
     }
 }
 
+/// The trust level in the sender's device that is required to decrypt an
+/// event.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Enum))]
+pub enum TrustRequirement {
+    /// Decrypt events from everyone regardless of trust.
+    #[default]
+    Untrusted,
+
+    /// Only decrypt events from cross-signed devices or legacy sessions.
+    ///
+    /// A legacy session is either a session sent by a device belonging to a
+    /// user who never set up cross-signing, or a session we received from an
+    /// insecure source, like a key export or a legacy key backup, which can't
+    /// be linked back to the sending device.
+    CrossSignedOrLegacy,
+
+    /// Only decrypt events from cross-signed devices, legacy sessions are
+    /// rejected as well.
+    CrossSigned,
+}
+
+/// Settings for decrypting room events.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DecryptionSettings {
+    /// The trust level in the sender's device that is required to decrypt the
+    /// event. If the sender's device is not sufficiently trusted,
+    /// [`MegolmError::SenderIdentityNotTrusted`] will be returned.
+    pub sender_device_trust_requirement: TrustRequirement,
+}
+
 pub use error::{
     EventError, MegolmError, OlmError, SessionCreationError, SetRoomSettingsError, SignatureError,
 };
 pub use file_encryption::{
     decrypt_room_key_export, encrypt_room_key_export, AttachmentDecryptor, AttachmentEncryptor,
-    DecryptorError, KeyExportError, MediaEncryptionInfo,
+    ChunkedAttachmentDecryptor, ChunkedAttachmentEncryptor, DecryptorError, KeyExportError,
+    MediaEncryptionInfo,
 };
 pub use gossiping::{GossipRequest, GossippedSecret};
 pub use identities::{
@@ -20,8 +20,8 @@ use std::{
 
 use itertools::Itertools;
 use matrix_sdk_common::deserialized_responses::{
-    AlgorithmInfo, DeviceLinkProblem, EncryptionInfo, TimelineEvent, VerificationLevel,
-    VerificationState,
+    AlgorithmInfo, DeviceLinkProblem, EncryptionInfo, ProcessedToDeviceEvent, TimelineEvent,
+    VerificationLevel, VerificationState,
 };
 use ruma::{
     api::client::{
@@ -37,13 +37,14 @@ use ruma::{
     assign,
     events::{
         secret::request::SecretName, AnyMessageLikeEvent, AnyMessageLikeEventContent,
-        AnyToDeviceEvent, MessageLikeEventContent,
+        AnyToDeviceEvent, MessageLikeEventContent, ToDeviceEventType,
     },
     serde::Raw,
+    to_device::DeviceIdOrAllDevices,
     DeviceId, DeviceKeyAlgorithm, MilliSecondsSinceUnixEpoch, OwnedDeviceId, OwnedDeviceKeyId,
     OwnedTransactionId, OwnedUserId, RoomId, TransactionId, UInt, UserId,
 };
-use serde_json::value::to_raw_value;
+use serde_json::{value::to_raw_value, Value};
 use tokio::sync::Mutex;
 use tracing::{
     debug, error,
@@ -89,8 +90,8 @@ use crate::{
     },
     utilities::timestamp_to_iso8601,
     verification::{Verification, VerificationMachine, VerificationRequest},
-    CrossSigningKeyExport, CryptoStoreError, KeysQueryRequest, LocalTrust, ReadOnlyDevice,
-    SignatureError, ToDeviceRequest,
+    CrossSigningKeyExport, CryptoStoreError, DecryptionSettings, KeysQueryRequest, LocalTrust,
+    ReadOnlyDevice, SignatureError, ToDeviceRequest, TrustRequirement,
 };
 
 /// State machine implementation of the Olm/Megolm encryption protocol used for
@@ -1004,6 +1005,83 @@ impl OlmMachine {
         self.inner.group_session_manager.share_room_key(room_id, users, encryption_settings).await
     }
 
+    /// Encrypt the given content as an `m.room.encrypted` to-device event for
+    /// each of the given devices.
+    ///
+    /// The Olm sessions with the devices need to be established beforehand
+    /// using the [`OlmMachine::get_missing_sessions()`] method, devices we
+    /// don't have an Olm session with are skipped.
+    ///
+    /// # Arguments
+    ///
+    /// * `devices` - The devices the content should be encrypted for.
+    ///
+    /// * `event_type` - The type of the event that should be encrypted.
+    ///
+    /// * `content` - The content of the event that should be encrypted.
+    ///
+    /// # Returns
+    ///
+    /// A tuple of (to-device requests, devices without an Olm session). Each
+    /// request contains a limited amount of to-device messages, the responses
+    /// need to be passed back to the state machine with
+    /// [`mark_request_as_sent`], using the to-device `txn_id` as `request_id`.
+    ///
+    /// [`mark_request_as_sent`]: #method.mark_request_as_sent
+    pub async fn encrypt_content_for_devices(
+        &self,
+        devices: Vec<Device>,
+        event_type: &str,
+        content: &Value,
+    ) -> OlmResult<(Vec<ToDeviceRequest>, Vec<(OwnedUserId, OwnedDeviceId)>)> {
+        let mut used_sessions = Vec::new();
+        let mut messages = Vec::new();
+        let mut devices_without_session = Vec::new();
+
+        for device in devices {
+            let user_id = device.user_id().to_owned();
+            let device_id = device.device_id().to_owned();
+
+            match device.encrypt(event_type, content).await {
+                Ok((session, encrypted)) => {
+                    used_sessions.push(session);
+                    messages.push((user_id, device_id, encrypted));
+                }
+                Err(OlmError::MissingSession) => devices_without_session.push((user_id, device_id)),
+                Err(e) => return Err(e),
+            }
+        }
+
+        // Encrypting ratchets the Olm sessions forward, persist them.
+        self.store()
+            .save_changes(Changes { sessions: used_sessions, ..Default::default() })
+            .await?;
+
+        let requests = messages
+            .into_iter()
+            .chunks(GroupSessionManager::MAX_TO_DEVICE_MESSAGES)
+            .into_iter()
+            .map(|chunk| {
+                let mut messages = BTreeMap::new();
+
+                for (user_id, device_id, content) in chunk {
+                    messages
+                        .entry(user_id)
+                        .or_insert_with(BTreeMap::new)
+                        .insert(DeviceIdOrAllDevices::DeviceId(device_id), content.cast());
+                }
+
+                ToDeviceRequest {
+                    event_type: ToDeviceEventType::RoomEncrypted,
+                    txn_id: TransactionId::new(),
+                    messages,
+                }
+            })
+            .collect();
+
+        Ok((requests, devices_without_session))
+    }
+
     /// Receive an unencrypted verification event.
     ///
     /// This method can be used to pass verification events that are happening
@@ -1174,7 +1252,7 @@ impl OlmMachine {
         transaction: &mut StoreTransaction,
         changes: &mut Changes,
         mut raw_event: Raw<AnyToDeviceEvent>,
-    ) -> Raw<AnyToDeviceEvent> {
+    ) -> ProcessedToDeviceEvent {
         Self::record_message_id(&raw_event);
 
         let event: ToDeviceEvents = match raw_event.deserialize_as() {
@@ -1183,7 +1261,7 @@ impl OlmMachine {
                 // Skip invalid events.
                 warn!("Received an invalid to-device event: {e}");
 
-                return raw_event;
+                return ProcessedToDeviceEvent::new(raw_event);
             }
         };
 
@@ -1208,7 +1286,7 @@ impl OlmMachine {
                             }
                         }
 
-                        return raw_event;
+                        return ProcessedToDeviceEvent::new(raw_event);
                     }
                 };
 
@@ -1226,6 +1304,24 @@ impl OlmMachine {
                     changes.inbound_group_sessions.push(group_session);
                 }
 
+                // Room keys are accepted from devices we don't know about yet, so
+                // we can't tell who sent those.
+                let encryption_info =
+                    if matches!(*decrypted.result.event, AnyDecryptedOlmEvent::RoomKey(_)) {
+                        None
+                    } else {
+                        match self
+                            .get_olm_encryption_info(&e.sender, decrypted.result.sender_key)
+                            .await
+                        {
+                            Ok(info) => Some(info),
+                            Err(e) => {
+                                warn!("Couldn't get the encryption info of a to-device event: {e}");
+                                None
+                            }
+                        }
+                    };
+
                 match decrypted.result.raw_event.deserialize_as() {
                     Ok(event) => {
                         self.handle_to_device_event(changes, &event).await;
@@ -1240,12 +1336,14 @@ impl OlmMachine {
                         raw_event = decrypted.result.raw_event;
                     }
                 }
+
+                return ProcessedToDeviceEvent { event: raw_event, encryption_info };
             }
 
             e => self.handle_to_device_event(changes, &e).await,
         }
 
-        raw_event
+        ProcessedToDeviceEvent::new(raw_event)
     }
 
     /// Handle a to-device and one-time key counts from a sync response.
@@ -1269,7 +1367,7 @@ impl OlmMachine {
     pub async fn receive_sync_changes(
         &self,
         sync_changes: EncryptionSyncChanges<'_>,
-    ) -> OlmResult<(Vec<Raw<AnyToDeviceEvent>>, Vec<RoomKeyInfo>)> {
+    ) -> OlmResult<(Vec<ProcessedToDeviceEvent>, Vec<RoomKeyInfo>)> {
         let mut store_transaction = self.inner.store.transaction().await;
 
         let (events, changes) =
@@ -1290,9 +1388,15 @@ impl OlmMachine {
         &self,
         transaction: &mut StoreTransaction,
         sync_changes: EncryptionSyncChanges<'_>,
-    ) -> OlmResult<(Vec<Raw<AnyToDeviceEvent>>, Changes)> {
+    ) -> OlmResult<(Vec<ProcessedToDeviceEvent>, Changes)> {
         // Remove verification objects that have expired or are done.
-        let mut events = self.inner.verification_machine.garbage_collect();
+        let mut events: Vec<_> = self
+            .inner
+            .verification_machine
+            .garbage_collect()
+            .into_iter()
+            .map(ProcessedToDeviceEvent::new)
+            .collect();
 
         // The account is automatically saved by the store transaction created by the
         // caller.
@@ -1319,9 +1423,9 @@ impl OlmMachine {
         }
 
         for raw_event in sync_changes.to_device_events {
-            let raw_event =
+            let event =
                 Box::pin(self.receive_to_device_event(transaction, &mut changes, raw_event)).await;
-            events.push(raw_event);
+            events.push(event);
         }
 
         let changed_sessions = self
@@ -1508,6 +1612,27 @@ impl OlmMachine {
                     .iter()
                     .map(|(k, v)| (k.to_owned(), v.to_base64()))
                     .collect(),
+                session_id: Some(session.session_id().to_owned()),
+            },
+            verification_state,
+        })
+    }
+
+    /// Get the encryption info of a to-device event which was decrypted using
+    /// an Olm session established with the given Curve25519 key.
+    async fn get_olm_encryption_info(
+        &self,
+        sender: &UserId,
+        sender_key: Curve25519PublicKey,
+    ) -> StoreResult<EncryptionInfo> {
+        let device = self.store().get_device_from_curve_key(sender, sender_key).await?;
+        let verification_state = device_verification_state(device.as_ref());
+
+        Ok(EncryptionInfo {
+            sender: sender.to_owned(),
+            sender_device: device.map(|d| d.device_id().to_owned()),
+            algorithm_info: AlgorithmInfo::OlmV1Curve25519AesSha2 {
+                curve25519_key: sender_key.to_base64(),
             },
             verification_state,
         })
@@ -1529,6 +1654,7 @@ impl OlmMachine {
         room_id: &RoomId,
         event: &EncryptedEvent,
         content: &SupportedEventEncryptionSchemes<'_>,
+        decryption_settings: &DecryptionSettings,
     ) -> MegolmResult<TimelineEvent> {
         let session =
             self.get_inbound_group_session_or_error(room_id, content.session_id()).await?;
@@ -1544,6 +1670,14 @@ impl OlmMachine {
         match result {
             Ok((decrypted_event, _)) => {
                 let encryption_info = self.get_encryption_info(&session, &event.sender).await?;
+
+                self.check_sender_trust_requirement(
+                    &event.sender,
+                    &encryption_info.verification_state,
+                    decryption_settings.sender_device_trust_requirement,
+                )
+                .await?;
+
                 Ok(TimelineEvent {
                     encryption_info: Some(encryption_info),
                     event: decrypted_event,
@@ -1572,6 +1706,81 @@ impl OlmMachine {
         }
     }
 
+    /// Check that the sender's device satisfies the given [`TrustRequirement`],
+    /// returning a [`MegolmError::SenderIdentityNotTrusted`] error otherwise.
+    async fn check_sender_trust_requirement(
+        &self,
+        sender: &UserId,
+        verification_state: &VerificationState,
+        trust_requirement: TrustRequirement,
+    ) -> MegolmResult<()> {
+        let verification_level = match verification_state {
+            // The device is cross-signed by its owner, whether we verified the owner or not
+            // doesn't matter for the trust requirement.
+            VerificationState::Verified
+            | VerificationState::Unverified(VerificationLevel::UnverifiedIdentity) => return Ok(()),
+            VerificationState::Unverified(level) => level,
+        };
+
+        let is_legacy = match verification_level {
+            // The session can't be linked back to a device, it was received from a key export
+            // or a legacy backup.
+            VerificationLevel::None(DeviceLinkProblem::InsecureSource) => true,
+            // The device isn't cross-signed, this is a legacy device if its owner never set up
+            // cross-signing.
+            VerificationLevel::UnsignedDevice => self.store().get_identity(sender).await?.is_none(),
+            VerificationLevel::None(DeviceLinkProblem::MissingDevice)
+            | VerificationLevel::UnverifiedIdentity => false,
+        };
+
+        match trust_requirement {
+            TrustRequirement::Untrusted => Ok(()),
+            TrustRequirement::CrossSignedOrLegacy if is_legacy => Ok(()),
+            TrustRequirement::CrossSignedOrLegacy | TrustRequirement::CrossSigned => {
+                Err(MegolmError::SenderIdentityNotTrusted(verification_level.clone()))
+            }
+        }
+    }
+
+    /// Check that the sender of an event we already decrypted still satisfies
+    /// the given [`TrustRequirement`], returning a
+    /// [`MegolmError::SenderIdentityNotTrusted`] error otherwise.
+    ///
+    /// The trust in the sender's device is evaluated using the current state
+    /// of the device, which might have changed since the event was decrypted,
+    /// for example if the sender reset their cross-signing identity.
+    ///
+    /// # Arguments
+    ///
+    /// * `encryption_info` - The encryption info of the decrypted event.
+    ///
+    /// * `trust_requirement` - The trust requirement the sender's device needs
+    ///   to satisfy.
+    pub async fn check_sender_trust(
+        &self,
+        encryption_info: &EncryptionInfo,
+        trust_requirement: TrustRequirement,
+    ) -> MegolmResult<()> {
+        let verification_state =
+            match (&encryption_info.verification_state, &encryption_info.sender_device) {
+                // The session couldn't be linked to a device, this doesn't change over time.
+                (state @ VerificationState::Unverified(VerificationLevel::None(_)), _)
+                | (state, None) => state.clone(),
+                (_, Some(device_id)) => {
+                    let device =
+                        self.store().get_device(&encryption_info.sender, device_id).await?;
+                    device_verification_state(device.as_ref())
+                }
+            };
+
+        self.check_sender_trust_requirement(
+            &encryption_info.sender,
+            &verification_state,
+            trust_requirement,
+        )
+        .await
+    }
+
     /// Attempt to retrieve an inbound group session from the store.
     ///
     /// If the session is not found, checks for withheld reports, and returns a
@@ -1597,16 +1806,43 @@ impl OlmMachine {
 
     /// Decrypt an event from a room timeline.
     ///
+    /// This doesn't put any trust requirement on the sender's device, use
+    /// [`OlmMachine::decrypt_room_event_with_settings()`] to only decrypt
+    /// events from sufficiently trusted devices.
+    ///
     /// # Arguments
     ///
     /// * `event` - The event that should be decrypted.
     ///
     /// * `room_id` - The ID of the room where the event was sent to.
-    #[instrument(skip_all, fields(?room_id, event_id, origin_server_ts, sender, algorithm, session_id, sender_key))]
     pub async fn decrypt_room_event(
         &self,
         event: &Raw<EncryptedEvent>,
         room_id: &RoomId,
+    ) -> MegolmResult<TimelineEvent> {
+        self.decrypt_room_event_with_settings(event, room_id, &DecryptionSettings::default()).await
+    }
+
+    /// Decrypt an event from a room timeline, using the given
+    /// [`DecryptionSettings`].
+    ///
+    /// If the sender's device doesn't satisfy the trust requirement of the
+    /// settings, a [`MegolmError::SenderIdentityNotTrusted`] error is
+    /// returned.
+    ///
+    /// # Arguments
+    ///
+    /// * `event` - The event that should be decrypted.
+    ///
+    /// * `room_id` - The ID of the room where the event was sent to.
+    ///
+    /// * `decryption_settings` - The settings to use for the decryption.
+    #[instrument(skip_all, fields(?room_id, event_id, origin_server_ts, sender, algorithm, session_id, sender_key))]
+    pub async fn decrypt_room_event_with_settings(
+        &self,
+        event: &Raw<EncryptedEvent>,
+        room_id: &RoomId,
+        decryption_settings: &DecryptionSettings,
     ) -> MegolmResult<TimelineEvent> {
         let event = event.deserialize()?;
 
@@ -1634,7 +1870,8 @@ impl OlmMachine {
         };
 
         Span::current().record("session_id", content.session_id());
-        let result = self.decrypt_megolm_events(room_id, &event, &content).await;
+        let result =
+            self.decrypt_megolm_events(room_id, &event, &content, decryption_settings).await;
 
         if let Err(e) = &result {
             #[cfg(feature = "automatic-room-key-forwarding")]
@@ -2199,6 +2436,22 @@ pub struct EncryptionSyncChanges<'a> {
     pub next_batch_token: Option<String>,
 }
 
+/// The verification state of a device, only taking cross-signing into account.
+fn device_verification_state(device: Option<&Device>) -> VerificationState {
+    match device {
+        None => {
+            VerificationState::Unverified(VerificationLevel::None(DeviceLinkProblem::MissingDevice))
+        }
+        Some(device) if !device.is_cross_signed_by_owner() => {
+            VerificationState::Unverified(VerificationLevel::UnsignedDevice)
+        }
+        Some(device) if !device.is_device_owner_verified() => {
+            VerificationState::Unverified(VerificationLevel::UnverifiedIdentity)
+        }
+        Some(_) => VerificationState::Verified,
+    }
+}
+
 #[cfg(any(feature = "testing", test))]
 #[allow(dead_code)]
 pub(crate) mod testing {
@@ -2223,7 +2476,8 @@ pub(crate) mod tests {
     use futures_util::{FutureExt, StreamExt};
     use itertools::Itertools;
     use matrix_sdk_common::deserialized_responses::{
-        DeviceLinkProblem, ShieldState, VerificationLevel, VerificationState,
+        AlgorithmInfo, DeviceLinkProblem, ShieldState, TimelineEvent, VerificationLevel,
+        VerificationState,
     };
     use matrix_sdk_test::{async_test, message_like_event_content, test_json};
     use ruma::{
@@ -2244,13 +2498,13 @@ pub(crate) mod tests {
             key::verification::VerificationMethod,
             room::message::{MessageType, RoomMessageEventContent},
             AnyMessageLikeEvent, AnyMessageLikeEventContent, AnyTimelineEvent, AnyToDeviceEvent,
-            MessageLikeEvent, OriginalMessageLikeEvent,
+            MessageLikeEvent, OriginalMessageLikeEvent, ToDeviceEventType,
         },
         room_id,
         serde::Raw,
         to_device::DeviceIdOrAllDevices,
         uint, user_id, DeviceId, DeviceKeyAlgorithm, DeviceKeyId, MilliSecondsSinceUnixEpoch,
-        OwnedDeviceKeyId, SecondsSinceUnixEpoch, TransactionId, UserId,
+        OwnedDeviceKeyId, RoomId, SecondsSinceUnixEpoch, TransactionId, UserId,
     };
     use serde_json::{json, value::to_raw_value};
     use vodozemac::{
@@ -2260,7 +2514,7 @@ pub(crate) mod tests {
 
     use super::{testing::response_from_file, CrossSigningBootstrapRequests};
     use crate::{
-        error::{EventError, SetRoomSettingsError},
+        error::{EventError, MegolmResult, SetRoomSettingsError},
         machine::{EncryptionSyncChanges, OlmMachine},
         olm::{
             BackedUpRoomKey, ExportedRoomKey, InboundGroupSession, OutboundGroupSession, VerifyJson,
@@ -2268,7 +2522,9 @@ pub(crate) mod tests {
         store::{BackupDecryptionKey, Changes, CryptoStore, MemoryStore, RoomSettings},
         types::{
             events::{
-                room::encrypted::{EncryptedToDeviceEvent, ToDeviceEncryptedEventContent},
+                room::encrypted::{
+                    EncryptedEvent, EncryptedToDeviceEvent, ToDeviceEncryptedEventContent,
+                },
                 room_key_withheld::{RoomKeyWithheldContent, WithheldCode},
                 ToDeviceEvent,
             },
@@ -2276,8 +2532,8 @@ pub(crate) mod tests {
         },
         utilities::json_convert,
         verification::tests::{bob_id, outgoing_request_to_event, request_to_event},
-        Account, EncryptionSettings, LocalTrust, MegolmError, OlmError, OutgoingRequests,
-        ReadOnlyDevice, ToDeviceRequest, UserIdentities,
+        Account, DecryptionSettings, EncryptionSettings, LocalTrust, MegolmError, OlmError,
+        OutgoingRequests, ReadOnlyDevice, ToDeviceRequest, TrustRequirement, UserIdentities,
     };
 
     /// These keys need to be periodically uploaded to the server.
@@ -2911,7 +3167,8 @@ pub(crate) mod tests {
             .await
             .unwrap();
 
-        let event = decrypted[0].deserialize().unwrap();
+        let event = decrypted[0].event.deserialize().unwrap();
+        assert!(decrypted[0].encryption_info.is_none(), "Room keys don't carry the sender device");
 
         if let AnyToDeviceEvent::RoomKey(event) = event {
             assert_eq!(&event.sender, alice.user_id());
@@ -3287,6 +3544,112 @@ pub(crate) mod tests {
         assert_shield!(encryption_info, Red, Grey);
     }
 
+    #[async_test]
+    async fn test_decryption_trust_requirement() {
+        async fn decrypt(
+            machine: &OlmMachine,
+            event: &Raw<EncryptedEvent>,
+            room_id: &RoomId,
+            sender_device_trust_requirement: TrustRequirement,
+        ) -> MegolmResult<TimelineEvent> {
+            let settings = DecryptionSettings { sender_device_trust_requirement };
+            machine.decrypt_room_event_with_settings(event, room_id, &settings).await
+        }
+
+        let (alice, bob) =
+            get_machine_pair_with_setup_sessions_test_helper(alice_id(), user_id(), false).await;
+        let room_id = room_id!("!test:example.org");
+
+        let to_device_requests = alice
+            .share_room_key(room_id, iter::once(bob.user_id()), EncryptionSettings::default())
+            .await
+            .unwrap();
+
+        let event = ToDeviceEvent::new(
+            alice.user_id().to_owned(),
+            to_device_requests_to_content(to_device_requests),
+        );
+
+        let group_session = bob
+            .store()
+            .with_transaction(|mut tr| async {
+                let res =
+                    bob.decrypt_to_device_event(&mut tr, &event, &mut Changes::default()).await?;
+                Ok((tr, res))
+            })
+            .await
+            .unwrap()
+            .inbound_group_session
+            .unwrap();
+
+        let export = group_session.clone().export().await;
+        bob.store().save_inbound_group_sessions(&[group_session]).await.unwrap();
+
+        let content = RoomMessageEventContent::text_plain("It is a secret to everybody");
+        let encrypted_content = alice
+            .encrypt_room_event(room_id, AnyMessageLikeEventContent::RoomMessage(content))
+            .await
+            .unwrap();
+
+        let event = json_convert(&json!({
+            "event_id": "$xxxxx:example.org",
+            "origin_server_ts": MilliSecondsSinceUnixEpoch::now(),
+            "sender": alice.user_id(),
+            "type": "m.room.encrypted",
+            "content": encrypted_content,
+        }))
+        .unwrap();
+
+        // Alice never set up cross-signing, her device is a legacy device.
+        let legacy_encryption_info = decrypt(&bob, &event, room_id, TrustRequirement::Untrusted)
+            .await
+            .unwrap()
+            .encryption_info
+            .unwrap();
+        decrypt(&bob, &event, room_id, TrustRequirement::CrossSignedOrLegacy).await.unwrap();
+        assert_matches!(
+            decrypt(&bob, &event, room_id, TrustRequirement::CrossSigned).await,
+            Err(MegolmError::SenderIdentityNotTrusted(VerificationLevel::UnsignedDevice))
+        );
+
+        // Alice now has a cross-signing identity, but her device isn't signed.
+        setup_cross_signing_for_machine_test_helper(&alice, &bob).await;
+        decrypt(&bob, &event, room_id, TrustRequirement::Untrusted).await.unwrap();
+        assert_matches!(
+            decrypt(&bob, &event, room_id, TrustRequirement::CrossSignedOrLegacy).await,
+            Err(MegolmError::SenderIdentityNotTrusted(VerificationLevel::UnsignedDevice))
+        );
+
+        // The trust in the sender of the event we decrypted earlier dropped as well.
+        assert_matches!(
+            bob.check_sender_trust(&legacy_encryption_info, TrustRequirement::CrossSignedOrLegacy)
+                .await,
+            Err(MegolmError::SenderIdentityNotTrusted(VerificationLevel::UnsignedDevice))
+        );
+
+        // Once the device is cross-signed, all requirements are satisfied, even if we
+        // didn't verify Alice.
+        sign_alice_device_for_machine_test_helper(&alice, &bob).await;
+        decrypt(&bob, &event, room_id, TrustRequirement::CrossSignedOrLegacy).await.unwrap();
+        decrypt(&bob, &event, room_id, TrustRequirement::CrossSigned).await.unwrap();
+        bob.check_sender_trust(&legacy_encryption_info, TrustRequirement::CrossSigned)
+            .await
+            .unwrap();
+
+        // An imported session can't be linked back to the device, it's a legacy
+        // session.
+        let imported = InboundGroupSession::from_export(&export).unwrap();
+        bob.store().save_inbound_group_sessions(&[imported]).await.unwrap();
+
+        decrypt(&bob, &event, room_id, TrustRequirement::CrossSignedOrLegacy).await.unwrap();
+        assert_matches!(
+            decrypt(&bob, &event, room_id, TrustRequirement::CrossSigned).await,
+            Err(MegolmError::SenderIdentityNotTrusted(VerificationLevel::None(
+                DeviceLinkProblem::InsecureSource
+            )))
+        );
+    }
+
     /// Test what happens when we feed an unencrypted event into the decryption
     /// functions
     #[async_test]
@@ -4320,11 +4683,20 @@ pub(crate) mod tests {
 
         assert_eq!(1, decrypted.len());
 
-        let decrypted_event = decrypted[0].deserialize().unwrap();
+        let decrypted_event = decrypted[0].event.deserialize().unwrap();
 
         assert_eq!(decrypted_event.event_type().to_string(), custom_event_type.to_owned());
 
-        let decrypted_value = to_raw_value(&decrypted[0]).unwrap();
+        let encryption_info = decrypted[0].encryption_info.as_ref().unwrap();
+        assert_eq!(encryption_info.sender, alice.user_id());
+        assert_eq!(encryption_info.sender_device.as_deref(), Some(alice.device_id()));
+        assert_matches!(
+            &encryption_info.algorithm_info,
+            AlgorithmInfo::OlmV1Curve25519AesSha2 { curve25519_key }
+                if *curve25519_key == alice.identity_keys().curve25519.to_base64()
+        );
+
+        let decrypted_value = to_raw_value(&decrypted[0].event).unwrap();
         let decrypted_value = serde_json::to_value(decrypted_value).unwrap();
 
         assert_eq!(
@@ -4360,6 +4732,60 @@ pub(crate) mod tests {
         assert_matches!(encryption_result, Err(OlmError::MissingSession));
     }
 
+    #[async_test]
+    async fn test_encrypt_content_for_devices() {
+        let (alice, bob) = get_machine_pair_with_session(alice_id(), user_id(), false).await;
+        let content = json!({ "call_id": "1234" });
+
+        let device = alice.get_device(bob.user_id(), bob.device_id(), None).await.unwrap().unwrap();
+        let (requests, without_session) = alice
+            .encrypt_content_for_devices(vec![device], "org.example.call", &content)
+            .await
+            .unwrap();
+
+        assert!(without_session.is_empty());
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].event_type, ToDeviceEventType::RoomEncrypted);
+
+        let event = ToDeviceEvent::new(
+            alice.user_id().to_owned(),
+            to_device_requests_to_content(vec![requests[0].clone().into()]),
+        );
+
+        let (decrypted, _) = bob
+            .receive_sync_changes(EncryptionSyncChanges {
+                to_device_events: vec![json_convert(&event).unwrap()],
+                changed_devices: &Default::default(),
+                one_time_keys_counts: &Default::default(),
+                unused_fallback_keys: None,
+                next_batch_token: None,
+            })
+            .await
+            .unwrap();
+
+        let event = decrypted[0].event.deserialize().unwrap();
+        assert_eq!(event.event_type().to_string(), "org.example.call");
+
+        let encryption_info = decrypted[0].encryption_info.as_ref().unwrap();
+        assert_eq!(encryption_info.sender_device.as_deref(), Some(alice.device_id()));
+        assert_eq!(
+            encryption_info.verification_state,
+            VerificationState::Unverified(VerificationLevel::UnsignedDevice)
+        );
+
+        // Without an Olm session, the device is skipped.
+        let (alice, bob, _) = get_machine_pair(alice_id(), user_id(), false).await;
+
+        let device = alice.get_device(bob.user_id(), bob_device_id(), None).await.unwrap().unwrap();
+        let (requests, without_session) = alice
+            .encrypt_content_for_devices(vec![device], "org.example.call", &content)
+            .await
+            .unwrap();
+
+        assert!(requests.is_empty());
+        assert_eq!(without_session, vec![(bob.user_id().to_owned(), bob_device_id().to_owned())]);
+    }
+
     #[async_test]
     async fn test_fix_incorrect_usage_of_backup_key_causing_decryption_errors() {
         let store = MemoryStore::new();
@@ -144,7 +144,7 @@ pub(crate) struct GroupSessionManager {
 }
 
 impl GroupSessionManager {
-    const MAX_TO_DEVICE_MESSAGES: usize = 250;
+    pub(crate) const MAX_TO_DEVICE_MESSAGES: usize = 250;
 
     pub fn new(store: Store) -> Self {
         Self { store: store.clone(), sessions: GroupSessionCache::new(store) }
@@ -0,0 +1,349 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A portable archive of the content of a [`CryptoStore`].
+//!
+//! A [`CryptoStoreArchive`] is exported from a store using only the methods of
+//! the [`CryptoStore`] trait, so it can be imported into a store that uses
+//! another backend, for example to move a session from the IndexedDB store to
+//! the SQLite store.
+
+use std::{collections::BTreeMap, fmt};
+
+use ruma::{events::secret::request::SecretName, DeviceId, OwnedRoomId, UserId};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    BackupDecryptionKey, Changes, CryptoStoreError, DeviceChanges, DynCryptoStore, IdentityChanges,
+    PendingChanges, Result, RoomSettings, TrackedUser,
+};
+use crate::{
+    olm::{
+        Account, InboundGroupSession, PickledAccount, PickledCrossSigningIdentity,
+        PickledInboundGroupSession, PickledSession, PrivateCrossSigningIdentity, Session,
+    },
+    GossippedSecret, ReadOnlyDevice, ReadOnlyUserIdentities,
+};
+
+/// The names of the secrets that can be found in the secrets inbox.
+const INBOX_SECRET_NAMES: &[SecretName] = &[
+    SecretName::CrossSigningMasterKey,
+    SecretName::CrossSigningSelfSigningKey,
+    SecretName::CrossSigningUserSigningKey,
+    SecretName::RecoveryKey,
+];
+
+/// The content of a [`CryptoStore`], that can be imported into another store.
+///
+/// The archive contains the account, the private cross-signing identity, the
+/// Olm sessions, the inbound group sessions, the devices and identities of the
+/// tracked users, the backup keys, the secrets inbox, the room settings and the
+/// sync token.
+///
+/// Outbound group sessions, outgoing secret requests and withheld info are not
+/// included, they are recreated as needed. The Olm sessions are only found for
+/// the devices of the tracked users, and the room settings for the rooms with
+/// inbound group sessions.
+///
+/// The archive contains private keys, so its serialized form must be kept
+/// secret, or encrypted.
+///
+/// [`CryptoStore`]: super::CryptoStore
+#[derive(Serialize, Deserialize)]
+pub struct CryptoStoreArchive {
+    account: PickledAccount,
+    private_identity: Option<PickledCrossSigningIdentity>,
+    sessions: Vec<PickledSession>,
+    inbound_group_sessions: Vec<PickledInboundGroupSession>,
+    tracked_users: Vec<TrackedUser>,
+    devices: Vec<ReadOnlyDevice>,
+    identities: Vec<ReadOnlyUserIdentities>,
+    backup_version: Option<String>,
+    backup_decryption_key: Option<BackupDecryptionKey>,
+    secrets: Vec<GossippedSecret>,
+    room_settings: BTreeMap<OwnedRoomId, RoomSettings>,
+    next_batch_token: Option<String>,
+}
+
+#[cfg(not(tarpaulin_include))]
+impl fmt::Debug for CryptoStoreArchive {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CryptoStoreArchive")
+            .field("user_id", &self.account.user_id)
+            .field("device_id", &self.account.device_id)
+            .field("sessions", &self.sessions.len())
+            .field("inbound_group_sessions", &self.inbound_group_sessions.len())
+            .field("tracked_users", &self.tracked_users.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl CryptoStoreArchive {
+    /// The ID of the user that owns the account in this archive.
+    pub fn user_id(&self) -> &UserId {
+        &self.account.user_id
+    }
+
+    /// The ID of the device of the account in this archive.
+    pub fn device_id(&self) -> &DeviceId {
+        &self.account.device_id
+    }
+
+    /// Export the content of the given store.
+    ///
+    /// Returns [`CryptoStoreError::AccountUnset`] if the store doesn't contain
+    /// an account.
+    pub async fn export(store: &DynCryptoStore) -> Result<Self> {
+        let account = store.load_account().await?.ok_or(CryptoStoreError::AccountUnset)?;
+
+        let private_identity = match store.load_identity().await? {
+            Some(identity) => Some(identity.pickle().await),
+            None => None,
+        };
+
+        let tracked_users = store.load_tracked_users().await?;
+
+        let mut devices = Vec::new();
+        let mut identities = Vec::new();
+        let mut sessions = Vec::new();
+
+        for user in &tracked_users {
+            if let Some(identity) = store.get_user_identity(&user.user_id).await? {
+                identities.push(identity);
+            }
+
+            for device in store.get_user_devices(&user.user_id).await?.into_values() {
+                if let Some(sender_key) = device.curve25519_key() {
+                    if let Some(device_sessions) =
+                        store.get_sessions(&sender_key.to_base64()).await?
+                    {
+                        for session in device_sessions.lock().await.iter() {
+                            sessions.push(session.pickle().await);
+                        }
+                    }
+                }
+
+                devices.push(device);
+            }
+        }
+
+        let mut inbound_group_sessions = Vec::new();
+        let mut room_settings = BTreeMap::new();
+
+        for session in store.get_inbound_group_sessions().await? {
+            let room_id = session.room_id();
+
+            if !room_settings.contains_key(room_id) {
+                if let Some(settings) = store.get_room_settings(room_id).await? {
+                    room_settings.insert(room_id.to_owned(), settings);
+                }
+            }
+
+            inbound_group_sessions.push(session.pickle().await);
+        }
+
+        let backup_keys = store.load_backup_keys().await?;
+
+        let mut secrets = Vec::new();
+        for secret_name in INBOX_SECRET_NAMES {
+            secrets.extend(store.get_secrets_from_inbox(secret_name).await?);
+        }
+
+        Ok(Self {
+            account: account.pickle(),
+            private_identity,
+            sessions,
+            inbound_group_sessions,
+            tracked_users,
+            devices,
+            identities,
+            backup_version: backup_keys.backup_version,
+            backup_decryption_key: backup_keys.decryption_key,
+            secrets,
+            room_settings,
+            next_batch_token: store.next_batch_token().await?,
+        })
+    }
+
+    /// Import the content of this archive into the given store.
+    ///
+    /// The store must be empty, or contain the same account. Returns
+    /// [`CryptoStoreError::MismatchedAccount`] if it contains another account.
+    pub async fn import_into(self, store: &DynCryptoStore) -> Result<()> {
+        if let Some(existing) = store.load_account().await? {
+            if existing.user_id() != self.user_id() || existing.device_id() != self.device_id() {
+                return Err(CryptoStoreError::MismatchedAccount {
+                    expected: (self.user_id().to_owned(), self.device_id().to_owned()),
+                    got: (existing.user_id().to_owned(), existing.device_id().to_owned()),
+                });
+            }
+        }
+
+        let account = Account::from_pickle(self.account)?;
+        let static_data = account.static_data().clone();
+
+        store.save_pending_changes(PendingChanges { account: Some(account) }).await?;
+
+        let private_identity = self
+            .private_identity
+            .map(PrivateCrossSigningIdentity::from_pickle)
+            .transpose()
+            .map_err(|_| CryptoStoreError::UnpicklingError)?;
+
+        let sessions = self
+            .sessions
+            .into_iter()
+            .map(|pickle| {
+                Session::from_pickle(
+                    static_data.user_id.clone(),
+                    static_data.device_id.clone(),
+                    static_data.identity_keys.clone(),
+                    pickle,
+                )
+            })
+            .collect();
+
+        let inbound_group_sessions = self
+            .inbound_group_sessions
+            .into_iter()
+            .map(InboundGroupSession::from_pickle)
+            .collect::<Result<_, _>>()?;
+
+        let changes = Changes {
+            private_identity,
+            backup_version: self.backup_version,
+            backup_decryption_key: self.backup_decryption_key,
+            sessions,
+            inbound_group_sessions,
+            identities: IdentityChanges { new: self.identities, ..Default::default() },
+            devices: DeviceChanges { new: self.devices, ..Default::default() },
+            room_settings: self.room_settings.into_iter().collect(),
+            secrets: self.secrets,
+            next_batch_token: self.next_batch_token,
+            ..Default::default()
+        };
+        store.save_changes(changes).await?;
+
+        let tracked_users: Vec<_> =
+            self.tracked_users.iter().map(|user| (user.user_id.as_ref(), user.dirty)).collect();
+        store.save_tracked_users(&tracked_users).await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_matches::assert_matches;
+    use matrix_sdk_test::async_test;
+    use ruma::{device_id, room_id, user_id};
+
+    use super::CryptoStoreArchive;
+    use crate::{
+        olm::Account,
+        store::{
+            BackupDecryptionKey, Changes, CryptoStore, CryptoStoreError, DeviceChanges,
+            IntoCryptoStore, MemoryStore, PendingChanges, RoomSettings,
+        },
+        ReadOnlyDevice,
+    };
+
+    #[async_test]
+    async fn test_round_trip() {
+        let room_id = room_id!("!test:localhost");
+
+        let account = Account::with_device_id(user_id!("@alice:localhost"), device_id!("ALICE"));
+        let bob = Account::with_device_id(user_id!("@bob:localhost"), device_id!("BOB"));
+
+        let (outbound, inbound) = account.create_group_session_pair_with_defaults(room_id).await;
+
+        let store = MemoryStore::new();
+        store
+            .save_pending_changes(PendingChanges { account: Some(account.deep_clone()) })
+            .await
+            .unwrap();
+        store
+            .save_changes(Changes {
+                inbound_group_sessions: vec![inbound],
+                devices: DeviceChanges {
+                    new: vec![ReadOnlyDevice::from_account(&bob)],
+                    ..Default::default()
+                },
+                room_settings: [(room_id.to_owned(), RoomSettings::default())].into(),
+                backup_version: Some("1".to_owned()),
+                backup_decryption_key: Some(BackupDecryptionKey::new().unwrap()),
+                next_batch_token: Some("s1234".to_owned()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        store.save_tracked_users(&[(bob.user_id(), false)]).await.unwrap();
+
+        let archive = CryptoStoreArchive::export(&store.into_crypto_store()).await.unwrap();
+
+        // The archive survives a serialization round trip.
+        let archive: CryptoStoreArchive =
+            serde_json::from_str(&serde_json::to_string(&archive).unwrap()).unwrap();
+
+        let new_store = MemoryStore::new().into_crypto_store();
+        archive.import_into(&new_store).await.unwrap();
+
+        let new_account = new_store.load_account().await.unwrap().unwrap();
+        assert_eq!(new_account.identity_keys(), account.identity_keys());
+
+        assert!(
+            new_store
+                .get_inbound_group_session(room_id, outbound.session_id())
+                .await
+                .unwrap()
+                .is_some(),
+            "The inbound group session should have been imported"
+        );
+
+        assert!(new_store.get_device(bob.user_id(), bob.device_id()).await.unwrap().is_some());
+        assert_eq!(new_store.load_tracked_users().await.unwrap().len(), 1);
+        assert!(new_store.get_room_settings(room_id).await.unwrap().is_some());
+
+        let backup_keys = new_store.load_backup_keys().await.unwrap();
+        assert_eq!(backup_keys.backup_version.as_deref(), Some("1"));
+        assert!(backup_keys.decryption_key.is_some());
+
+        assert_eq!(new_store.next_batch_token().await.unwrap().as_deref(), Some("s1234"));
+    }
+
+    #[async_test]
+    async fn test_import_into_store_with_another_account() {
+        let alice = Account::with_device_id(user_id!("@alice:localhost"), device_id!("ALICE"));
+        let store = MemoryStore::new();
+        store.save_pending_changes(PendingChanges { account: Some(alice) }).await.unwrap();
+        let archive = CryptoStoreArchive::export(&store.into_crypto_store()).await.unwrap();
+
+        let bob = Account::with_device_id(user_id!("@bob:localhost"), device_id!("BOB"));
+        let bob_store = MemoryStore::new();
+        bob_store
+            .save_pending_changes(PendingChanges { account: Some(bob.deep_clone()) })
+            .await
+            .unwrap();
+        let bob_store = bob_store.into_crypto_store();
+
+        assert_matches!(
+            archive.import_into(&bob_store).await,
+            Err(CryptoStoreError::MismatchedAccount { .. })
+        );
+
+        // The account of the store is left untouched.
+        let account = bob_store.load_account().await.unwrap().unwrap();
+        assert_eq!(account.identity_keys(), bob.identity_keys());
+    }
+}
@@ -80,6 +80,7 @@ use crate::{
     CrossSigningStatus, ReadOnlyOwnUserIdentity, RoomKeyImportResult,
 };
 
+mod archive;
 pub mod caches;
 mod crypto_store_wrapper;
 mod error;
@@ -91,6 +92,7 @@ mod traits;
 #[allow(missing_docs)]
 pub mod integration_tests;
 
+pub use archive::CryptoStoreArchive;
 use caches::{SequenceNumber, UsersForKeyQuery};
 pub(crate) use crypto_store_wrapper::CryptoStoreWrapper;
 pub use error::{CryptoStoreError, Result};
@@ -27,6 +27,16 @@ pub enum UtdCause {
     /// This event was sent when we were not a member of the room (or invited),
     /// so it is impossible to decrypt (without MSC3061).
     Membership = 1,
+
+    /// The room key is available, but the sender's device doesn't satisfy the
+    /// configured trust requirement, so the event was not decrypted.
+    ///
+    /// This cause can't be determined from the event alone, it's set when a
+    /// decryption attempt fails with a
+    /// [`MegolmError::SenderIdentityNotTrusted`] error.
+    ///
+    /// [`MegolmError::SenderIdentityNotTrusted`]: crate::MegolmError::SenderIdentityNotTrusted
+    UntrustedSender = 2,
     //
     // TODO: Other causes for UTDs. For example, this message is device-historical, information
     // extracted from the WithheldCode in the MissingRoomKey object, or various types of Olm
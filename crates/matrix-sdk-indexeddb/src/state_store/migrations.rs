@@ -21,7 +21,7 @@ use gloo_utils::format::JsValueSerdeExt;
 use indexed_db_futures::{prelude::*, request::OpenDbRequest, IdbDatabase, IdbVersionChangeEvent};
 use js_sys::Date as JsDate;
 use matrix_sdk_base::{
-    deserialized_responses::SyncOrStrippedState, store::migration_helpers::RoomInfoV1,
+    deserialized_responses::SyncOrStrippedState, store::migration_helpers::RoomInfoV1, RoomInfo,
     StateStoreDataKey,
 };
 use matrix_sdk_store_encryption::StoreCipher;
@@ -34,6 +34,7 @@ use ruma::{
         StateEventType,
     },
     serde::Raw,
+    MilliSecondsSinceUnixEpoch,
 };
 use serde::{Deserialize, Serialize};
 use serde_json::value::{RawValue as RawJsonValue, Value as JsonValue};
@@ -41,12 +42,12 @@ use wasm_bindgen::JsValue;
 use web_sys::IdbTransactionMode;
 
 use super::{
-    deserialize_event, encode_key, encode_to_range, keys, serialize_event, Result, RoomMember,
-    ALL_STORES,
+    deserialize_event, encode_key, encode_to_range, keys, serialize_event, MediaContent, Result,
+    RoomMember, ALL_STORES,
 };
 use crate::IndexeddbStateStoreError;
 
-const CURRENT_DB_VERSION: u32 = 8;
+const CURRENT_DB_VERSION: u32 = 10;
 const CURRENT_META_DB_VERSION: u32 = 2;
 
 /// Sometimes Migrations can't proceed without having to drop existing
@@ -225,6 +226,12 @@ pub async fn upgrade_inner_db(
             if old_version < 8 {
                 db = migrate_to_v8(db, store_cipher).await?;
             }
+            if old_version < 9 {
+                db = migrate_to_v9(db, store_cipher).await?;
+            }
+            if old_version < 10 {
+                db = migrate_to_v10(db, store_cipher).await?;
+            }
         }
 
         db.close();
@@ -731,17 +738,130 @@ async fn migrate_to_v8(db: IdbDatabase, store_cipher: Option<&StoreCipher>) -> R
     Ok(IdbDatabase::open_u32(&name, 8)?.await?)
 }
 
+/// Add the last access time to the media files, set to the time of the
+/// migration.
+async fn migrate_to_v9(db: IdbDatabase, store_cipher: Option<&StoreCipher>) -> Result<IdbDatabase> {
+    let tx = db.transaction_on_one_with_mode(keys::MEDIA, IdbTransactionMode::Readwrite)?;
+    let store = tx.object_store(keys::MEDIA)?;
+    let last_access = MilliSecondsSinceUnixEpoch::now();
+
+    if let Some(cursor) = store.open_cursor()?.await? {
+        loop {
+            let data: Vec<u8> = deserialize_event(store_cipher, &cursor.value())?;
+            let media = MediaContent { data, last_access };
+            cursor.update(&serialize_event(store_cipher, &media)?)?.await?;
+
+            if !cursor.continue_cursor()?.await? {
+                break;
+            }
+        }
+    }
+
+    tx.await.into_result()?;
+
+    let name = db.name();
+    db.close();
+
+    // Update the version of the database.
+    Ok(IdbDatabase::open_u32(&name, 9)?.await?)
+}
+
+/// Add the sort name to the members, computed from their member events.
+async fn migrate_to_v10(
+    db: IdbDatabase,
+    store_cipher: Option<&StoreCipher>,
+) -> Result<IdbDatabase> {
+    let tx = db.transaction_on_multi_with_mode(
+        &[
+            keys::ROOM_INFOS,
+            keys::ROOM_STATE,
+            keys::STRIPPED_ROOM_STATE,
+            keys::USER_IDS,
+            keys::STRIPPED_USER_IDS,
+        ],
+        IdbTransactionMode::Readwrite,
+    )?;
+
+    let room_infos = tx
+        .object_store(keys::ROOM_INFOS)?
+        .get_all()?
+        .await?
+        .iter()
+        .filter_map(|f| deserialize_event::<RoomInfo>(store_cipher, &f).ok())
+        .collect::<Vec<_>>();
+    let state_store = tx.object_store(keys::ROOM_STATE)?;
+    let stripped_state_store = tx.object_store(keys::STRIPPED_ROOM_STATE)?;
+    let user_ids_store = tx.object_store(keys::USER_IDS)?;
+    let stripped_user_ids_store = tx.object_store(keys::STRIPPED_USER_IDS)?;
+
+    for room_info in room_infos {
+        let room_id = room_info.room_id();
+
+        let range =
+            encode_to_range(store_cipher, keys::ROOM_STATE, (room_id, StateEventType::RoomMember))?;
+        for value in state_store.get_all_with_key(&range)?.await?.iter() {
+            let Ok(member_event) =
+                deserialize_event::<Raw<SyncRoomMemberEvent>>(store_cipher, &value)?.deserialize()
+            else {
+                continue;
+            };
+            let key = encode_key(store_cipher, keys::USER_IDS, (room_id, member_event.state_key()));
+
+            if user_ids_store.get(&key)?.await?.is_some() {
+                let value = serialize_event(store_cipher, &RoomMember::from(&member_event))?;
+                user_ids_store.put_key_val_owned(&key, &value)?;
+            }
+        }
+
+        let range = encode_to_range(
+            store_cipher,
+            keys::STRIPPED_ROOM_STATE,
+            (room_id, StateEventType::RoomMember),
+        )?;
+        for value in stripped_state_store.get_all_with_key(&range)?.await?.iter() {
+            let Ok(member_event) =
+                deserialize_event::<Raw<StrippedRoomMemberEvent>>(store_cipher, &value)?
+                    .deserialize()
+            else {
+                continue;
+            };
+            let key = encode_key(
+                store_cipher,
+                keys::STRIPPED_USER_IDS,
+                (room_id, &member_event.state_key),
+            );
+
+            if stripped_user_ids_store.get(&key)?.await?.is_some() {
+                let value = serialize_event(store_cipher, &RoomMember::from(&member_event))?;
+                stripped_user_ids_store.put_key_val_owned(&key, &value)?;
+            }
+        }
+    }
+
+    tx.await.into_result()?;
+
+    let name = db.name();
+    db.close();
+
+    // Update the version of the database.
+    Ok(IdbDatabase::open_u32(&name, 10)?.await?)
+}
+
 #[cfg(all(test, target_arch = "wasm32"))]
 mod tests {
     wasm_bindgen_test::wasm_bindgen_test_configure!(run_in_browser);
 
+    use std::time::Duration;
+
     use assert_matches::assert_matches;
     use assert_matches2::assert_let;
     use indexed_db_futures::prelude::*;
     use matrix_sdk_base::{
-        deserialized_responses::RawMemberEvent, store::StateStoreExt,
-        sync::UnreadNotificationsCount, RoomMemberships, RoomState, StateStore, StateStoreDataKey,
-        StoreError,
+        deserialized_responses::RawMemberEvent,
+        media::{MediaFormat, MediaRequest, MediaRetentionPolicy, UniqueKey},
+        store::StateStoreExt,
+        sync::UnreadNotificationsCount,
+        RoomMemberships, RoomState, StateStore, StateStoreDataKey, StoreError,
     };
     use matrix_sdk_test::{async_test, test_json};
     use ruma::{
@@ -749,10 +869,11 @@ mod tests {
             room::{
                 create::RoomCreateEventContent,
                 member::{StrippedRoomMemberEvent, SyncRoomMemberEvent},
+                MediaSource,
             },
             AnySyncStateEvent, StateEventType,
         },
-        room_id,
+        mxc_uri, room_id,
         serde::Raw,
         server_name, user_id, EventId, MilliSecondsSinceUnixEpoch, RoomId, UserId,
     };
@@ -1539,4 +1660,43 @@ mod tests {
 
         Ok(())
     }
+
+    #[async_test]
+    pub async fn test_migrating_to_v9() -> Result<()> {
+        let name = format!("migrating-v9-{}", Uuid::new_v4().as_hyphenated().to_string());
+
+        let request = MediaRequest {
+            source: MediaSource::Plain(mxc_uri!("mxc://localhost/media").to_owned()),
+            format: MediaFormat::File,
+        };
+
+        // Populate DB with a media file without a last access time.
+        {
+            let db = create_fake_db(&name, 8).await?;
+            let tx = db.transaction_on_one_with_mode(keys::MEDIA, IdbTransactionMode::Readwrite)?;
+
+            tx.object_store(keys::MEDIA)?.put_key_val(
+                &encode_key(
+                    None,
+                    keys::MEDIA,
+                    (request.source.unique_key(), request.format.unique_key()),
+                ),
+                &serialize_event(None, &b"media".to_vec())?,
+            )?;
+
+            tx.await.into_result()?;
+            db.close();
+        }
+
+        // This transparently migrates to the latest version.
+        let store = IndexeddbStateStore::builder().name(name).build().await?;
+
+        // The media file was accessed during the migration, so it hasn't expired.
+        let policy =
+            MediaRetentionPolicy::empty().with_last_access_expiry(Some(Duration::from_secs(60)));
+        store.clean_up_media_cache(policy, &[], MilliSecondsSinceUnixEpoch::now()).await?;
+        assert_eq!(store.get_media_content(&request).await?.as_deref(), Some(&b"media"[..]));
+
+        Ok(())
+    }
 }
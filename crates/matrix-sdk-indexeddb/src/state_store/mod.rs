@@ -24,10 +24,10 @@ use growable_bloom_filter::GrowableBloom;
 use indexed_db_futures::prelude::*;
 use matrix_sdk_base::{
     deserialized_responses::RawAnySyncOrStrippedState,
-    media::{MediaRequest, UniqueKey},
-    store::{StateChanges, StateStore, StoreError},
-    MinimalRoomMemberEvent, RoomInfo, RoomMemberships, RoomState, StateStoreDataKey,
-    StateStoreDataValue,
+    media::{MediaCacheEntry, MediaFormat, MediaRequest, MediaRetentionPolicy, UniqueKey},
+    store::{ComposerDraft, StateChanges, StateStore, StoreError},
+    MinimalRoomMemberEvent, RoomInfo, RoomMemberPage, RoomMemberQuery, RoomMemberships, RoomState,
+    StateStoreDataKey, StateStoreDataValue,
 };
 use matrix_sdk_store_encryption::{Error as EncryptionError, StoreCipher};
 use ruma::{
@@ -42,7 +42,8 @@ use ruma::{
         GlobalAccountDataEventType, RoomAccountDataEventType, StateEventType, SyncStateEvent,
     },
     serde::Raw,
-    CanonicalJsonObject, EventId, MxcUri, OwnedEventId, OwnedUserId, RoomId, RoomVersionId, UserId,
+    CanonicalJsonObject, EventId, MilliSecondsSinceUnixEpoch, MxcUri, OwnedEventId, OwnedMxcUri,
+    OwnedUserId, RoomId, RoomVersionId, UserId,
 };
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use tracing::{debug, warn};
@@ -53,7 +54,7 @@ mod migrations;
 
 pub use self::migrations::MigrationConflictStrategy;
 use self::migrations::{upgrade_inner_db, upgrade_meta_db};
-use crate::safe_encode::SafeEncode;
+use crate::safe_encode::{SafeEncode, KEY_SEPARATOR};
 
 #[derive(Debug, thiserror::Error)]
 pub enum IndexeddbStateStoreError {
@@ -322,6 +323,22 @@ impl IndexeddbStateStore {
         memberships: RoomMemberships,
         stripped: bool,
     ) -> Result<Vec<OwnedUserId>> {
+        Ok(self
+            .get_members_inner(room_id, memberships, stripped)
+            .await?
+            .into_iter()
+            .map(|member| member.user_id)
+            .collect())
+    }
+
+    /// Get the members of the given room with the given memberships and
+    /// stripped state.
+    async fn get_members_inner(
+        &self,
+        room_id: &RoomId,
+        memberships: RoomMemberships,
+        stripped: bool,
+    ) -> Result<Vec<RoomMember>> {
         let store_name = if stripped { keys::STRIPPED_USER_IDS } else { keys::USER_IDS };
 
         let tx =
@@ -329,16 +346,16 @@ impl IndexeddbStateStore {
         let store = tx.object_store(store_name)?;
         let range = self.encode_to_range(store_name, room_id)?;
 
-        let user_ids = if memberships.is_empty() {
-            // It should be faster to just get all user IDs in this case.
+        let members = if memberships.is_empty() {
+            // It should be faster to just get all members in this case.
             store
                 .get_all_with_key(&range)?
                 .await?
                 .iter()
-                .filter_map(|f| self.deserialize_event::<RoomMember>(&f).ok().map(|m| m.user_id))
+                .filter_map(|f| self.deserialize_event::<RoomMember>(&f).ok())
                 .collect::<Vec<_>>()
         } else {
-            let mut user_ids = Vec::new();
+            let mut members = Vec::new();
             let cursor = store.open_cursor_with_range(&range)?.await?;
 
             if let Some(cursor) = cursor {
@@ -347,7 +364,7 @@ impl IndexeddbStateStore {
                     let member = self.deserialize_event::<RoomMember>(&value)?;
 
                     if memberships.matches(&member.membership) {
-                        user_ids.push(member.user_id);
+                        members.push(member);
                     }
 
                     if !cursor.continue_cursor()?.await? {
@@ -356,10 +373,10 @@ impl IndexeddbStateStore {
                 }
             }
 
-            user_ids
+            members
         };
 
-        Ok(user_ids)
+        Ok(members)
     }
 
     async fn get_custom_value_for_js(&self, jskey: &JsValue) -> Result<Option<Vec<u8>>> {
@@ -392,6 +409,11 @@ impl IndexeddbStateStore {
             StateStoreDataKey::UtdHookManagerData => {
                 self.encode_key(keys::KV, StateStoreDataKey::UTD_HOOK_MANAGER_DATA)
             }
+            StateStoreDataKey::ComposerDraft(room_id) => {
+                self.encode_key(keys::KV, (StateStoreDataKey::COMPOSER_DRAFT, room_id))
+            }
+            StateStoreDataKey::UrlPreviewsInEncryptedRoom(room_id) => self
+                .encode_key(keys::KV, (StateStoreDataKey::URL_PREVIEWS_IN_ENCRYPTED_ROOM, room_id)),
         }
     }
 }
@@ -457,6 +479,14 @@ impl_state_store!({
                 .map(|f| self.deserialize_event::<GrowableBloom>(&f))
                 .transpose()?
                 .map(StateStoreDataValue::UtdHookManagerData),
+            StateStoreDataKey::ComposerDraft(_) => value
+                .map(|f| self.deserialize_event::<ComposerDraft>(&f))
+                .transpose()?
+                .map(StateStoreDataValue::ComposerDraft),
+            StateStoreDataKey::UrlPreviewsInEncryptedRoom(_) => value
+                .map(|f| self.deserialize_event::<bool>(&f))
+                .transpose()?
+                .map(StateStoreDataValue::UrlPreviewsInEncryptedRoom),
         };
 
         Ok(value)
@@ -486,6 +516,14 @@ impl_state_store!({
             StateStoreDataKey::UtdHookManagerData => self.serialize_event(
                 &value.into_utd_hook_manager_data().expect("Session data not UtdHookManagerData"),
             ),
+            StateStoreDataKey::ComposerDraft(_) => self.serialize_event(
+                &value.into_composer_draft().expect("Session data not a composer draft"),
+            ),
+            StateStoreDataKey::UrlPreviewsInEncryptedRoom(_) => self.serialize_event(
+                &value
+                    .into_url_previews_in_encrypted_room()
+                    .expect("Session data not a URL previews setting"),
+            ),
         };
 
         let tx =
@@ -1185,7 +1223,8 @@ impl_state_store!({
         let tx =
             self.inner.transaction_on_one_with_mode(keys::MEDIA, IdbTransactionMode::Readwrite)?;
 
-        tx.object_store(keys::MEDIA)?.put_key_val(&key, &self.serialize_event(&data)?)?;
+        let media = MediaContent { data, last_access: MilliSecondsSinceUnixEpoch::now() };
+        tx.object_store(keys::MEDIA)?.put_key_val(&key, &self.serialize_event(&media)?)?;
 
         tx.await.into_result().map_err(|e| e.into())
     }
@@ -1193,13 +1232,20 @@ impl_state_store!({
     async fn get_media_content(&self, request: &MediaRequest) -> Result<Option<Vec<u8>>> {
         let key = self
             .encode_key(keys::MEDIA, (request.source.unique_key(), request.format.unique_key()));
-        self.inner
-            .transaction_on_one_with_mode(keys::MEDIA, IdbTransactionMode::Readonly)?
-            .object_store(keys::MEDIA)?
-            .get(&key)?
-            .await?
-            .map(|f| self.deserialize_event(&f))
-            .transpose()
+        let tx =
+            self.inner.transaction_on_one_with_mode(keys::MEDIA, IdbTransactionMode::Readwrite)?;
+        let store = tx.object_store(keys::MEDIA)?;
+
+        let Some(value) = store.get(&key)?.await? else {
+            return Ok(None);
+        };
+
+        let mut media = self.deserialize_event::<MediaContent>(&value)?;
+        media.last_access = MilliSecondsSinceUnixEpoch::now();
+        store.put_key_val(&key, &self.serialize_event(&media)?)?;
+
+        tx.await.into_result()?;
+        Ok(Some(media.data))
     }
 
     async fn get_custom_value(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
@@ -1259,6 +1305,69 @@ impl_state_store!({
         tx.await.into_result().map_err(|e| e.into())
     }
 
+    async fn media_cache_size(&self) -> Result<usize> {
+        let tx =
+            self.inner.transaction_on_one_with_mode(keys::MEDIA, IdbTransactionMode::Readonly)?;
+        let store = tx.object_store(keys::MEDIA)?;
+
+        let mut size = 0;
+        for value in store.get_all()?.await?.iter() {
+            size += self.deserialize_event::<MediaContent>(&value)?.data.len();
+        }
+
+        Ok(size)
+    }
+
+    async fn clean_up_media_cache(
+        &self,
+        policy: MediaRetentionPolicy,
+        protected_uris: &[OwnedMxcUri],
+        now: MilliSecondsSinceUnixEpoch,
+    ) -> Result<()> {
+        let tx =
+            self.inner.transaction_on_one_with_mode(keys::MEDIA, IdbTransactionMode::Readwrite)?;
+        let store = tx.object_store(keys::MEDIA)?;
+
+        let mut protected_keys = HashSet::new();
+        for uri in protected_uris {
+            let range = self.encode_to_range(keys::MEDIA, uri.as_str())?;
+            for key in store.get_all_keys_with_key(&range)?.await?.iter() {
+                protected_keys.extend(key.as_string());
+            }
+        }
+
+        // The keys end with the encoded format of the media.
+        let file_format = self.encode_key(keys::MEDIA, MediaFormat::File.unique_key()).as_string();
+
+        let mut entries = Vec::new();
+        if let Some(cursor) = store.open_cursor()?.await? {
+            loop {
+                let key = cursor.key().and_then(|key| key.as_string());
+                let media = self.deserialize_event::<MediaContent>(&cursor.value())?;
+
+                if let Some(key) = key {
+                    entries.push(MediaCacheEntry {
+                        is_protected: protected_keys.contains(&key),
+                        is_thumbnail: key.rsplit(KEY_SEPARATOR).next() != file_format.as_deref(),
+                        key,
+                        size: media.data.len(),
+                        last_access: media.last_access,
+                    });
+                }
+
+                if !cursor.continue_cursor()?.await? {
+                    break;
+                }
+            }
+        }
+
+        for key in policy.select_evicted(entries, now) {
+            store.delete(&JsValue::from_str(&key))?;
+        }
+
+        tx.await.into_result().map_err(|e| e.into())
+    }
+
     async fn remove_room(&self, room_id: &RoomId) -> Result<()> {
         let direct_stores = [keys::ROOM_INFOS];
 
@@ -1296,6 +1405,7 @@ impl_state_store!({
                 store.delete(&key)?;
             }
         }
+
         tx.await.into_result().map_err(|e| e.into())
     }
 
@@ -1318,24 +1428,71 @@ impl_state_store!({
     async fn get_joined_user_ids(&self, room_id: &RoomId) -> Result<Vec<OwnedUserId>> {
         self.get_user_ids(room_id, RoomMemberships::JOIN).await
     }
+
+    async fn get_room_member_page(
+        &self,
+        room_id: &RoomId,
+        query: &RoomMemberQuery,
+    ) -> Result<RoomMemberPage> {
+        // Stripped members take precedence, like in `get_user_ids()`.
+        let mut members = self.get_members_inner(room_id, query.memberships, true).await?;
+        if members.is_empty() {
+            members = self.get_members_inner(room_id, query.memberships, false).await?;
+        }
+
+        // IndexedDB indexes can't search substrings, and the names are encrypted
+        // when the store has a passphrase, so the members are sorted and searched
+        // in memory.
+        Ok(query.select_page(members.into_iter().map(|member| {
+            let name =
+                member.name.unwrap_or_else(|| RoomMemberQuery::sort_name(&member.user_id, None));
+            (member.user_id, name)
+        })))
+    }
 });
 
+/// A media file in the media store.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct MediaContent {
+    data: Vec<u8>,
+    last_access: MilliSecondsSinceUnixEpoch,
+}
+
 /// A room member.
 #[derive(Debug, Serialize, Deserialize)]
 struct RoomMember {
     user_id: OwnedUserId,
     membership: MembershipState,
+    /// The [sort name](RoomMemberQuery::sort_name) of the member.
+    ///
+    /// It is missing for members stored before version 10 of the database.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
 }
 
 impl From<&SyncStateEvent<RoomMemberEventContent>> for RoomMember {
     fn from(event: &SyncStateEvent<RoomMemberEventContent>) -> Self {
-        Self { user_id: event.state_key().clone(), membership: event.membership().clone() }
+        let display_name =
+            event.as_original().and_then(|event| event.content.displayname.as_deref());
+
+        Self {
+            user_id: event.state_key().clone(),
+            membership: event.membership().clone(),
+            name: Some(RoomMemberQuery::sort_name(event.state_key(), display_name)),
+        }
     }
 }
 
 impl From<&StrippedRoomMemberEvent> for RoomMember {
     fn from(event: &StrippedRoomMemberEvent) -> Self {
-        Self { user_id: event.state_key.clone(), membership: event.content.membership.clone() }
+        Self {
+            user_id: event.state_key.clone(),
+            membership: event.content.membership.clone(),
+            name: Some(RoomMemberQuery::sort_name(
+                &event.state_key,
+                event.content.displayname.as_deref(),
+            )),
+        }
     }
 }
 
@@ -0,0 +1,1094 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+
+use async_trait::async_trait;
+use matrix_sdk_crypto::{
+    olm::{
+        InboundGroupSession, OutboundGroupSession, PickledInboundGroupSession,
+        PrivateCrossSigningIdentity, Session, StaticAccountData,
+    },
+    store::{
+        caches::SessionStore, BackupKeys, Changes, CryptoStore, PendingChanges, RoomKeyCounts,
+        RoomSettings,
+    },
+    types::events::room_key_withheld::RoomKeyWithheldEvent,
+    Account, GossipRequest, GossippedSecret, ReadOnlyDevice, ReadOnlyUserIdentities, SecretInfo,
+    TrackedUser,
+};
+use matrix_sdk_store_encryption::StoreCipher;
+use ruma::{
+    events::secret::request::SecretName, DeviceId, MilliSecondsSinceUnixEpoch, OwnedDeviceId,
+    RoomId, TransactionId, UserId,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::sync::Mutex;
+use tracing::{debug, instrument, warn};
+
+use crate::{
+    error::{Error, Result},
+    get_or_create_store_cipher,
+    utils::{
+        compose, decode_bool, decompose_n, encode_bool, get, get_prefixed, load_db_version,
+        remove_prefixed, Key, RedbDatabase, KV,
+    },
+    OpenStoreError,
+};
+
+/// The definitions of the tables, and the layout of their entries.
+///
+/// Keys and values made of several parts are built with [`compose`].
+mod tables {
+    use redb::TableDefinition;
+
+    use crate::utils::Table;
+
+    /// `(sender_key, session_id)` => `data`
+    pub const SESSION: Table = TableDefinition::new("session");
+    /// `session_id` => `(room_id, backed_up, data)`
+    pub const INBOUND_GROUP_SESSION: Table = TableDefinition::new("inbound_group_session");
+    /// `room_id` => `data`
+    pub const OUTBOUND_GROUP_SESSION: Table = TableDefinition::new("outbound_group_session");
+    /// `(user_id, device_id)` => `data`
+    pub const DEVICE: Table = TableDefinition::new("device");
+    /// `user_id` => `data`
+    pub const IDENTITY: Table = TableDefinition::new("identity");
+    /// `hash` => empty
+    pub const OLM_HASH: Table = TableDefinition::new("olm_hash");
+    /// `user_id` => `data`
+    pub const TRACKED_USER: Table = TableDefinition::new("tracked_user");
+    /// `request_id` => `(sent_out, data)`
+    pub const KEY_REQUESTS: Table = TableDefinition::new("key_requests");
+    /// `(room_id, session_id)` => `data`
+    pub const DIRECT_WITHHELD_INFO: Table = TableDefinition::new("direct_withheld_info");
+    /// `room_id` => `data`
+    pub const ROOM_SETTINGS: Table = TableDefinition::new("room_settings");
+    /// `(secret_name, index)` => `data`, the index being a big-endian `u64`
+    pub const SECRETS: Table = TableDefinition::new("secrets");
+    /// `key` => `(holder, expiration_ts)`, the timestamp being a big-endian
+    /// `u64`
+    pub const LEASE_LOCKS: Table = TableDefinition::new("lease_locks");
+
+    pub const ALL: &[Table] = &[
+        SESSION,
+        INBOUND_GROUP_SESSION,
+        OUTBOUND_GROUP_SESSION,
+        DEVICE,
+        IDENTITY,
+        OLM_HASH,
+        TRACKED_USER,
+        KEY_REQUESTS,
+        DIRECT_WITHHELD_INFO,
+        ROOM_SETTINGS,
+        SECRETS,
+        LEASE_LOCKS,
+    ];
+}
+
+const DATABASE_VERSION: u8 = 1;
+
+/// A redb based cryptostore.
+#[derive(Clone)]
+pub struct RedbCryptoStore {
+    store_cipher: Option<Arc<StoreCipher>>,
+    path: PathBuf,
+    db: RedbDatabase,
+
+    // DB values cached in memory
+    static_account: Arc<RwLock<Option<StaticAccountData>>>,
+    session_cache: SessionStore,
+    save_changes_lock: Arc<Mutex<()>>,
+}
+
+#[cfg(not(tarpaulin_include))]
+impl fmt::Debug for RedbCryptoStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RedbCryptoStore").field("path", &self.path).finish()
+    }
+}
+
+impl RedbCryptoStore {
+    /// Open the redb-based crypto store at the given path using the given
+    /// passphrase to encrypt private data.
+    pub async fn open(
+        path: impl AsRef<Path>,
+        passphrase: Option<&str>,
+    ) -> Result<Self, OpenStoreError> {
+        let path = path.as_ref();
+        let db = RedbDatabase::open(path, "matrix-sdk-crypto.redb", tables::ALL).await?;
+
+        let version = load_db_version(&db).await?;
+        if version > DATABASE_VERSION {
+            return Err(OpenStoreError::UnsupportedVersion(version));
+        }
+        if version < DATABASE_VERSION {
+            debug!(version, new_version = DATABASE_VERSION, "Upgrading database");
+            db.set_kv("version", vec![DATABASE_VERSION]).await?;
+        }
+
+        let store_cipher = match passphrase {
+            Some(p) => Some(Arc::new(get_or_create_store_cipher(p, &db).await?)),
+            None => None,
+        };
+
+        Ok(RedbCryptoStore {
+            store_cipher,
+            path: path.to_owned(),
+            db,
+            static_account: Arc::new(RwLock::new(None)),
+            session_cache: SessionStore::new(),
+            save_changes_lock: Default::default(),
+        })
+    }
+
+    fn encode_value(&self, value: Vec<u8>) -> Result<Vec<u8>> {
+        if let Some(key) = &self.store_cipher {
+            let encrypted = key.encrypt_value_data(value)?;
+            Ok(rmp_serde::to_vec_named(&encrypted)?)
+        } else {
+            Ok(value)
+        }
+    }
+
+    fn decode_value<'a>(&self, value: &'a [u8]) -> Result<Cow<'a, [u8]>> {
+        if let Some(key) = &self.store_cipher {
+            let encrypted = rmp_serde::from_slice(value)?;
+            let decrypted = key.decrypt_value_data(encrypted)?;
+            Ok(Cow::Owned(decrypted))
+        } else {
+            Ok(Cow::Borrowed(value))
+        }
+    }
+
+    fn serialize_json(&self, value: &impl Serialize) -> Result<Vec<u8>> {
+        let serialized = serde_json::to_vec(value)?;
+        self.encode_value(serialized)
+    }
+
+    fn deserialize_json<T: DeserializeOwned>(&self, data: &[u8]) -> Result<T> {
+        let decoded = self.decode_value(data)?;
+        Ok(serde_json::from_slice(&decoded)?)
+    }
+
+    fn serialize_value(&self, value: &impl Serialize) -> Result<Vec<u8>> {
+        let serialized = rmp_serde::to_vec_named(value)?;
+        self.encode_value(serialized)
+    }
+
+    fn deserialize_value<T: DeserializeOwned>(&self, value: &[u8]) -> Result<T> {
+        let decoded = self.decode_value(value)?;
+        Ok(rmp_serde::from_slice(&decoded)?)
+    }
+
+    fn deserialize_pickled_inbound_group_session(
+        &self,
+        value: &[u8],
+    ) -> Result<PickledInboundGroupSession> {
+        let [_, backed_up, data] = decompose_n(value)?;
+        let mut pickle: PickledInboundGroupSession = self.deserialize_value(data)?;
+        // The backed_up part of the entry is the source of truth, the backed_up field
+        // in the pickle is needed for other stores though.
+        pickle.backed_up = decode_bool(backed_up)?;
+        Ok(pickle)
+    }
+
+    fn deserialize_key_request(&self, value: &[u8]) -> Result<GossipRequest> {
+        let [sent_out, data] = decompose_n(value)?;
+        let mut request: GossipRequest = self.deserialize_value(data)?;
+        // The sent_out part of the entry is the source of truth, the sent_out field in
+        // the serialized value is needed for other stores though.
+        request.sent_out = decode_bool(sent_out)?;
+        Ok(request)
+    }
+
+    fn encode_key(&self, table_name: &str, key: impl AsRef<[u8]>) -> Key {
+        let bytes = key.as_ref();
+        if let Some(store_cipher) = &self.store_cipher {
+            Key::Hashed(store_cipher.hash_key(table_name, bytes))
+        } else {
+            Key::Plain(bytes.to_owned())
+        }
+    }
+
+    fn get_static_account(&self) -> Option<StaticAccountData> {
+        self.static_account.read().unwrap().clone()
+    }
+
+    /// Rewrite the backed up flag of the inbound group sessions with the given
+    /// IDs, or of all the sessions if `session_ids` is `None`.
+    async fn set_inbound_group_sessions_backed_up(
+        &self,
+        session_ids: Option<Vec<Key>>,
+        backed_up: bool,
+    ) -> Result<()> {
+        self.db
+            .write(move |txn| {
+                let mut table = txn.open_table(tables::INBOUND_GROUP_SESSION)?;
+
+                let entries = match session_ids {
+                    Some(session_ids) => {
+                        let mut entries = Vec::new();
+                        for session_id in session_ids {
+                            if let Some(value) = get(&table, &session_id)? {
+                                entries.push((session_id.to_vec(), value));
+                            }
+                        }
+                        entries
+                    }
+                    None => get_prefixed(&table, &[])?,
+                };
+
+                for (session_id, value) in entries {
+                    let [room_id, _, data] = decompose_n(&value)?;
+                    table.insert(
+                        session_id.as_slice(),
+                        compose(&[room_id, encode_bool(backed_up), data]).as_slice(),
+                    )?;
+                }
+
+                Ok(())
+            })
+            .await
+    }
+}
+
+#[async_trait]
+impl CryptoStore for RedbCryptoStore {
+    type Error = Error;
+
+    async fn clear_caches(&self) {
+        self.session_cache.clear()
+        // We don't need to clear `static_account` as it only contains immutable
+        // data therefore cannot get out of sync with the underlying
+        // store.
+    }
+
+    async fn load_account(&self) -> Result<Option<Account>> {
+        if let Some(pickle) = self.db.get_kv("account").await? {
+            let pickle = self.deserialize_value(&pickle)?;
+
+            let account = Account::from_pickle(pickle).map_err(|_| Error::Unpickle)?;
+
+            *self.static_account.write().unwrap() = Some(account.static_data().clone());
+
+            Ok(Some(account))
+        } else {
+            Ok(None)
+        }
+    }
+
+    async fn load_identity(&self) -> Result<Option<PrivateCrossSigningIdentity>> {
+        if let Some(i) = self.db.get_kv("identity").await? {
+            let pickle = self.deserialize_value(&i)?;
+            Ok(Some(PrivateCrossSigningIdentity::from_pickle(pickle).map_err(|_| Error::Unpickle)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    async fn save_pending_changes(&self, changes: PendingChanges) -> Result<()> {
+        // Serialize calls to `save_pending_changes`; there are multiple await points
+        // below, and we're pickling data as we go, so we don't want to
+        // invalidate data we've previously read and overwrite it in the store.
+        // TODO: #2000 should make this lock go away, or change its shape.
+        let _guard = self.save_changes_lock.lock().await;
+
+        if let Some(account) = changes.account {
+            *self.static_account.write().unwrap() = Some(account.static_data().clone());
+            let serialized_account = self.serialize_value(&account.pickle())?;
+            self.db.set_kv("account", serialized_account).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn save_changes(&self, changes: Changes) -> Result<()> {
+        // Serialize calls to `save_changes`; there are multiple await points below, and
+        // we're pickling data as we go, so we don't want to invalidate data
+        // we've previously read and overwrite it in the store.
+        // TODO: #2000 should make this lock go away, or change its shape.
+        let _guard = self.save_changes_lock.lock().await;
+
+        let pickled_private_identity =
+            if let Some(i) = changes.private_identity { Some(i.pickle().await) } else { None };
+
+        let mut session_changes = Vec::new();
+        for session in changes.sessions {
+            let session_id = self.encode_key("session", session.session_id());
+            let sender_key = self.encode_key("session", session.sender_key().to_base64());
+            let pickle = session.pickle().await;
+            session_changes.push((session_id, sender_key, pickle));
+
+            self.session_cache.add(session).await;
+        }
+
+        let mut inbound_session_changes = Vec::new();
+        for session in changes.inbound_group_sessions {
+            let room_id = self.encode_key("inbound_group_session", session.room_id().as_bytes());
+            let session_id = self.encode_key("inbound_group_session", session.session_id());
+            let pickle = session.pickle().await;
+            inbound_session_changes.push((room_id, session_id, pickle));
+        }
+
+        let mut outbound_session_changes = Vec::new();
+        for session in changes.outbound_group_sessions {
+            let room_id = self.encode_key("outbound_group_session", session.room_id().as_bytes());
+            let pickle = session.pickle().await;
+            outbound_session_changes.push((room_id, pickle));
+        }
+
+        let this = self.clone();
+        self.db
+            .write(move |txn| {
+                {
+                    let mut kv = txn.open_table(KV)?;
+
+                    if let Some(pickled_private_identity) = &pickled_private_identity {
+                        let serialized_private_identity =
+                            this.serialize_value(pickled_private_identity)?;
+                        kv.insert(b"identity".as_slice(), serialized_private_identity.as_slice())?;
+                    }
+
+                    if let Some(token) = &changes.next_batch_token {
+                        let serialized_token = this.serialize_value(token)?;
+                        kv.insert(b"next_batch_token".as_slice(), serialized_token.as_slice())?;
+                    }
+
+                    if let Some(decryption_key) = &changes.backup_decryption_key {
+                        let serialized_decryption_key = this.serialize_value(decryption_key)?;
+                        kv.insert(
+                            b"recovery_key_v1".as_slice(),
+                            serialized_decryption_key.as_slice(),
+                        )?;
+                    }
+
+                    if let Some(backup_version) = &changes.backup_version {
+                        let serialized_backup_version = this.serialize_value(backup_version)?;
+                        kv.insert(
+                            b"backup_version_v1".as_slice(),
+                            serialized_backup_version.as_slice(),
+                        )?;
+                    }
+                }
+
+                {
+                    let mut table = txn.open_table(tables::DEVICE)?;
+
+                    for device in changes.devices.new.iter().chain(&changes.devices.changed) {
+                        let user_id = this.encode_key("device", device.user_id().as_bytes());
+                        let device_id = this.encode_key("device", device.device_id().as_bytes());
+                        let data = this.serialize_value(&device)?;
+                        table
+                            .insert(compose(&[&user_id, &device_id]).as_slice(), data.as_slice())?;
+                    }
+
+                    for device in &changes.devices.deleted {
+                        let user_id = this.encode_key("device", device.user_id().as_bytes());
+                        let device_id = this.encode_key("device", device.device_id().as_bytes());
+                        table.remove(compose(&[&user_id, &device_id]).as_slice())?;
+                    }
+                }
+
+                {
+                    let mut table = txn.open_table(tables::IDENTITY)?;
+
+                    for identity in changes.identities.changed.iter().chain(&changes.identities.new)
+                    {
+                        let user_id = this.encode_key("identity", identity.user_id().as_bytes());
+                        let data = this.serialize_value(&identity)?;
+                        table.insert(&*user_id, data.as_slice())?;
+                    }
+                }
+
+                {
+                    let mut table = txn.open_table(tables::SESSION)?;
+
+                    for (session_id, sender_key, pickle) in &session_changes {
+                        let serialized_session = this.serialize_value(&pickle)?;
+                        table.insert(
+                            compose(&[sender_key, session_id]).as_slice(),
+                            serialized_session.as_slice(),
+                        )?;
+                    }
+                }
+
+                {
+                    let mut table = txn.open_table(tables::INBOUND_GROUP_SESSION)?;
+
+                    for (room_id, session_id, pickle) in &inbound_session_changes {
+                        let serialized_session = this.serialize_value(&pickle)?;
+                        table.insert(
+                            &**session_id,
+                            compose(&[room_id, encode_bool(pickle.backed_up), &serialized_session])
+                                .as_slice(),
+                        )?;
+                    }
+                }
+
+                {
+                    let mut table = txn.open_table(tables::OUTBOUND_GROUP_SESSION)?;
+
+                    for (room_id, pickle) in &outbound_session_changes {
+                        let serialized_session = this.serialize_json(&pickle)?;
+                        table.insert(&**room_id, serialized_session.as_slice())?;
+                    }
+                }
+
+                {
+                    let mut table = txn.open_table(tables::OLM_HASH)?;
+
+                    for hash in &changes.message_hashes {
+                        let hash = rmp_serde::to_vec(hash)?;
+                        table.insert(hash.as_slice(), [].as_slice())?;
+                    }
+                }
+
+                {
+                    let mut table = txn.open_table(tables::KEY_REQUESTS)?;
+
+                    for request in changes.key_requests {
+                        let request_id =
+                            this.encode_key("key_requests", request.request_id.as_bytes());
+                        let serialized_request = this.serialize_value(&request)?;
+                        table.insert(
+                            &*request_id,
+                            compose(&[encode_bool(request.sent_out), &serialized_request])
+                                .as_slice(),
+                        )?;
+                    }
+                }
+
+                {
+                    let mut table = txn.open_table(tables::DIRECT_WITHHELD_INFO)?;
+
+                    for (room_id, data) in changes.withheld_session_info {
+                        for (session_id, event) in data {
+                            let session_id = this.encode_key("direct_withheld_info", session_id);
+                            let room_id = this.encode_key("direct_withheld_info", &room_id);
+                            let serialized_info = this.serialize_json(&event)?;
+                            table.insert(
+                                compose(&[&room_id, &session_id]).as_slice(),
+                                serialized_info.as_slice(),
+                            )?;
+                        }
+                    }
+                }
+
+                {
+                    let mut table = txn.open_table(tables::ROOM_SETTINGS)?;
+
+                    for (room_id, settings) in changes.room_settings {
+                        let room_id = this.encode_key("room_settings", room_id.as_bytes());
+                        let value = this.serialize_value(&settings)?;
+                        table.insert(&*room_id, value.as_slice())?;
+                    }
+                }
+
+                {
+                    let mut table = txn.open_table(tables::SECRETS)?;
+
+                    for secret in changes.secrets {
+                        let secret_name =
+                            this.encode_key("secrets", secret.secret_name.to_string());
+                        let value = this.serialize_json(&secret)?;
+
+                        // Several secrets can be received with the same name, use the number
+                        // of secrets already stored as a unique index.
+                        let prefix = compose(&[&secret_name]);
+                        let index = get_prefixed(&table, &prefix)?.len() as u64;
+                        table.insert(
+                            compose(&[&secret_name, &index.to_be_bytes()]).as_slice(),
+                            value.as_slice(),
+                        )?;
+                    }
+                }
+
+                Ok(())
+            })
+            .await
+    }
+
+    async fn save_inbound_group_sessions(
+        &self,
+        sessions: Vec<InboundGroupSession>,
+        backed_up_to_version: Option<&str>,
+    ) -> matrix_sdk_crypto::store::Result<(), Self::Error> {
+        // Sanity-check that the data in the sessions corresponds to backed_up_version
+        sessions.iter().for_each(|s| {
+            let backed_up = s.backed_up();
+            if backed_up != backed_up_to_version.is_some() {
+                warn!(
+                    backed_up,
+                    backed_up_to_version,
+                    "Session backed-up flag does not correspond to backup version setting",
+                );
+            }
+        });
+
+        // Currently, this store doesn't save the backup version separately, so this
+        // just delegates to save_changes.
+        self.save_changes(Changes { inbound_group_sessions: sessions, ..Changes::default() }).await
+    }
+
+    async fn get_sessions(&self, sender_key: &str) -> Result<Option<Arc<Mutex<Vec<Session>>>>> {
+        let account_info = self.get_static_account().ok_or(Error::AccountUnset)?;
+
+        if self.session_cache.get(sender_key).is_none() {
+            let prefix = compose(&[&self.encode_key("session", sender_key.as_bytes())]);
+            let sessions = self
+                .db
+                .get_prefixed(tables::SESSION, prefix)
+                .await?
+                .into_iter()
+                .map(|(_, bytes)| {
+                    let pickle = self.deserialize_value(&bytes)?;
+                    Ok(Session::from_pickle(
+                        account_info.user_id.clone(),
+                        account_info.device_id.clone(),
+                        account_info.identity_keys.clone(),
+                        pickle,
+                    ))
+                })
+                .collect::<Result<_>>()?;
+
+            self.session_cache.set_for_sender(sender_key, sessions);
+        }
+
+        Ok(self.session_cache.get(sender_key))
+    }
+
+    #[instrument(skip(self))]
+    async fn get_inbound_group_session(
+        &self,
+        room_id: &RoomId,
+        session_id: &str,
+    ) -> Result<Option<InboundGroupSession>> {
+        let session_id = self.encode_key("inbound_group_session", session_id);
+        let Some(value) = self.db.get(tables::INBOUND_GROUP_SESSION, session_id.to_vec()).await?
+        else {
+            return Ok(None);
+        };
+
+        let [room_id_from_db, _, _] = decompose_n(&value)?;
+        let room_id = self.encode_key("inbound_group_session", room_id.as_bytes());
+        if *room_id != *room_id_from_db {
+            warn!("expected room_id for session_id doesn't match what's in the DB");
+            return Ok(None);
+        }
+
+        let pickle = self.deserialize_pickled_inbound_group_session(&value)?;
+
+        Ok(Some(InboundGroupSession::from_pickle(pickle)?))
+    }
+
+    async fn get_inbound_group_sessions(&self) -> Result<Vec<InboundGroupSession>> {
+        self.db
+            .get_prefixed(tables::INBOUND_GROUP_SESSION, Vec::new())
+            .await?
+            .into_iter()
+            .map(|(_, value)| {
+                let pickle = self.deserialize_pickled_inbound_group_session(&value)?;
+                Ok(InboundGroupSession::from_pickle(pickle)?)
+            })
+            .collect()
+    }
+
+    async fn inbound_group_session_counts(
+        &self,
+        _backup_version: Option<&str>,
+    ) -> Result<RoomKeyCounts> {
+        self.db
+            .read(|txn| {
+                let mut counts = RoomKeyCounts { total: 0, backed_up: 0 };
+
+                for entry in txn.open_table(tables::INBOUND_GROUP_SESSION)?.iter()? {
+                    let (_, value) = entry?;
+                    let [_, backed_up, _] = decompose_n(value.value())?;
+
+                    counts.total += 1;
+                    if decode_bool(backed_up)? {
+                        counts.backed_up += 1;
+                    }
+                }
+
+                Ok(counts)
+            })
+            .await
+    }
+
+    async fn inbound_group_sessions_for_backup(
+        &self,
+        _backup_version: &str,
+        limit: usize,
+    ) -> Result<Vec<InboundGroupSession>> {
+        let values = self
+            .db
+            .read(move |txn| {
+                let mut values = Vec::new();
+
+                for entry in txn.open_table(tables::INBOUND_GROUP_SESSION)?.iter()? {
+                    if values.len() >= limit {
+                        break;
+                    }
+
+                    let (_, value) = entry?;
+                    let [_, backed_up, _] = decompose_n(value.value())?;
+
+                    if !decode_bool(backed_up)? {
+                        values.push(value.value().to_owned());
+                    }
+                }
+
+                Ok(values)
+            })
+            .await?;
+
+        values
+            .into_iter()
+            .map(|value| {
+                let pickle = self.deserialize_pickled_inbound_group_session(&value)?;
+                Ok(InboundGroupSession::from_pickle(pickle)?)
+            })
+            .collect()
+    }
+
+    async fn mark_inbound_group_sessions_as_backed_up(
+        &self,
+        _backup_version: &str,
+        session_ids: &[(&RoomId, &str)],
+    ) -> Result<()> {
+        if session_ids.is_empty() {
+            // We are not expecting to be called with an empty list of sessions
+            warn!("No sessions to mark as backed up!");
+            return Ok(());
+        }
+
+        let session_ids =
+            session_ids.iter().map(|(_, s)| self.encode_key("inbound_group_session", s)).collect();
+
+        self.set_inbound_group_sessions_backed_up(Some(session_ids), true).await
+    }
+
+    async fn reset_backup_state(&self) -> Result<()> {
+        self.set_inbound_group_sessions_backed_up(None, false).await
+    }
+
+    async fn load_backup_keys(&self) -> Result<BackupKeys> {
+        let backup_version = self
+            .db
+            .get_kv("backup_version_v1")
+            .await?
+            .map(|value| self.deserialize_value(&value))
+            .transpose()?;
+
+        let decryption_key = self
+            .db
+            .get_kv("recovery_key_v1")
+            .await?
+            .map(|value| self.deserialize_value(&value))
+            .transpose()?;
+
+        Ok(BackupKeys { backup_version, decryption_key })
+    }
+
+    async fn get_outbound_group_session(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Option<OutboundGroupSession>> {
+        let room_id = self.encode_key("outbound_group_session", room_id.as_bytes());
+        let Some(value) = self.db.get(tables::OUTBOUND_GROUP_SESSION, room_id.to_vec()).await?
+        else {
+            return Ok(None);
+        };
+
+        let account_info = self.get_static_account().ok_or(Error::AccountUnset)?;
+
+        let pickle = self.deserialize_json(&value)?;
+        let session = OutboundGroupSession::from_pickle(
+            account_info.device_id,
+            account_info.identity_keys,
+            pickle,
+        )
+        .map_err(|_| Error::Unpickle)?;
+
+        return Ok(Some(session));
+    }
+
+    async fn load_tracked_users(&self) -> Result<Vec<TrackedUser>> {
+        self.db
+            .get_prefixed(tables::TRACKED_USER, Vec::new())
+            .await?
+            .iter()
+            .map(|(_, value)| self.deserialize_value(value))
+            .collect()
+    }
+
+    async fn save_tracked_users(&self, tracked_users: &[(&UserId, bool)]) -> Result<()> {
+        let users: Vec<(Key, Vec<u8>)> = tracked_users
+            .iter()
+            .map(|(u, d)| {
+                let user_id = self.encode_key("tracked_users", u.as_bytes());
+                let data =
+                    self.serialize_value(&TrackedUser { user_id: (*u).into(), dirty: *d })?;
+                Ok((user_id, data))
+            })
+            .collect::<Result<_>>()?;
+
+        self.db
+            .write(move |txn| {
+                let mut table = txn.open_table(tables::TRACKED_USER)?;
+
+                for (user_id, data) in users {
+                    table.insert(&*user_id, data.as_slice())?;
+                }
+
+                Ok(())
+            })
+            .await
+    }
+
+    async fn get_device(
+        &self,
+        user_id: &UserId,
+        device_id: &DeviceId,
+    ) -> Result<Option<ReadOnlyDevice>> {
+        let user_id = self.encode_key("device", user_id.as_bytes());
+        let device_id = self.encode_key("device", device_id.as_bytes());
+        self.db
+            .get(tables::DEVICE, compose(&[&user_id, &device_id]))
+            .await?
+            .map(|value| self.deserialize_value(&value))
+            .transpose()
+    }
+
+    async fn get_user_devices(
+        &self,
+        user_id: &UserId,
+    ) -> Result<HashMap<OwnedDeviceId, ReadOnlyDevice>> {
+        let user_id = self.encode_key("device", user_id.as_bytes());
+        self.db
+            .get_prefixed(tables::DEVICE, compose(&[&user_id]))
+            .await?
+            .into_iter()
+            .map(|(_, value)| {
+                let device: ReadOnlyDevice = self.deserialize_value(&value)?;
+                Ok((device.device_id().to_owned(), device))
+            })
+            .collect()
+    }
+
+    async fn get_user_identity(&self, user_id: &UserId) -> Result<Option<ReadOnlyUserIdentities>> {
+        let user_id = self.encode_key("identity", user_id.as_bytes());
+        self.db
+            .get(tables::IDENTITY, user_id.to_vec())
+            .await?
+            .map(|value| self.deserialize_value(&value))
+            .transpose()
+    }
+
+    async fn is_message_known(
+        &self,
+        message_hash: &matrix_sdk_crypto::olm::OlmMessageHash,
+    ) -> Result<bool> {
+        let value = rmp_serde::to_vec(message_hash)?;
+        Ok(self.db.get(tables::OLM_HASH, value).await?.is_some())
+    }
+
+    async fn get_outgoing_secret_requests(
+        &self,
+        request_id: &TransactionId,
+    ) -> Result<Option<GossipRequest>> {
+        let request_id = self.encode_key("key_requests", request_id.as_bytes());
+        self.db
+            .get(tables::KEY_REQUESTS, request_id.to_vec())
+            .await?
+            .map(|value| self.deserialize_key_request(&value))
+            .transpose()
+    }
+
+    async fn get_secret_request_by_info(
+        &self,
+        key_info: &SecretInfo,
+    ) -> Result<Option<GossipRequest>> {
+        let requests = self.db.get_prefixed(tables::KEY_REQUESTS, Vec::new()).await?;
+        for (_, request) in requests {
+            let request = self.deserialize_key_request(&request)?;
+            if request.info == *key_info {
+                return Ok(Some(request));
+            }
+        }
+        Ok(None)
+    }
+
+    async fn get_unsent_secret_requests(&self) -> Result<Vec<GossipRequest>> {
+        let requests = self.db.get_prefixed(tables::KEY_REQUESTS, Vec::new()).await?;
+
+        let mut unsent_requests = Vec::new();
+        for (_, request) in requests {
+            let request = self.deserialize_key_request(&request)?;
+            if !request.sent_out {
+                unsent_requests.push(request);
+            }
+        }
+
+        Ok(unsent_requests)
+    }
+
+    async fn delete_outgoing_secret_requests(&self, request_id: &TransactionId) -> Result<()> {
+        let request_id = self.encode_key("key_requests", request_id.as_bytes());
+        self.db
+            .write(move |txn| {
+                txn.open_table(tables::KEY_REQUESTS)?.remove(&*request_id)?;
+                Ok(())
+            })
+            .await
+    }
+
+    async fn get_secrets_from_inbox(
+        &self,
+        secret_name: &SecretName,
+    ) -> Result<Vec<GossippedSecret>> {
+        let secret_name = self.encode_key("secrets", secret_name.to_string());
+
+        self.db
+            .get_prefixed(tables::SECRETS, compose(&[&secret_name]))
+            .await?
+            .into_iter()
+            .map(|(_, value)| self.deserialize_json(&value))
+            .collect()
+    }
+
+    async fn delete_secrets_from_inbox(&self, secret_name: &SecretName) -> Result<()> {
+        let prefix = compose(&[&self.encode_key("secrets", secret_name.to_string())]);
+        self.db
+            .write(move |txn| remove_prefixed(&mut txn.open_table(tables::SECRETS)?, &prefix))
+            .await
+    }
+
+    async fn get_withheld_info(
+        &self,
+        room_id: &RoomId,
+        session_id: &str,
+    ) -> Result<Option<RoomKeyWithheldEvent>> {
+        let room_id = self.encode_key("direct_withheld_info", room_id);
+        let session_id = self.encode_key("direct_withheld_info", session_id);
+
+        self.db
+            .get(tables::DIRECT_WITHHELD_INFO, compose(&[&room_id, &session_id]))
+            .await?
+            .map(|value| {
+                let info = self.deserialize_json::<RoomKeyWithheldEvent>(&value)?;
+                Ok(info)
+            })
+            .transpose()
+    }
+
+    async fn get_room_settings(&self, room_id: &RoomId) -> Result<Option<RoomSettings>> {
+        let room_id = self.encode_key("room_settings", room_id.as_bytes());
+        let Some(value) = self.db.get(tables::ROOM_SETTINGS, room_id.to_vec()).await? else {
+            return Ok(None);
+        };
+
+        let settings = self.deserialize_value(&value)?;
+
+        return Ok(Some(settings));
+    }
+
+    async fn get_custom_value(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let Some(serialized) = self.db.get_kv(key).await? else {
+            return Ok(None);
+        };
+        let value = if let Some(cipher) = &self.store_cipher {
+            let encrypted = rmp_serde::from_slice(&serialized)?;
+            cipher.decrypt_value_data(encrypted)?
+        } else {
+            serialized
+        };
+
+        Ok(Some(value))
+    }
+
+    async fn set_custom_value(&self, key: &str, value: Vec<u8>) -> Result<()> {
+        let serialized = if let Some(cipher) = &self.store_cipher {
+            let encrypted = cipher.encrypt_value_data(value)?;
+            rmp_serde::to_vec_named(&encrypted)?
+        } else {
+            value
+        };
+
+        self.db.set_kv(key, serialized).await?;
+        Ok(())
+    }
+
+    async fn remove_custom_value(&self, key: &str) -> Result<()> {
+        let key = key.to_owned();
+        self.db
+            .write(move |txn| {
+                txn.open_table(KV)?.remove(key.as_bytes())?;
+                Ok(())
+            })
+            .await
+    }
+
+    async fn try_take_leased_lock(
+        &self,
+        lease_duration_ms: u32,
+        key: &str,
+        holder: &str,
+    ) -> Result<bool> {
+        let key = key.to_owned();
+        let holder = holder.to_owned();
+
+        let now_ts: u64 = MilliSecondsSinceUnixEpoch::now().get().into();
+        let expiration_ts = now_ts + lease_duration_ms as u64;
+
+        self.db
+            .write(move |txn| {
+                let mut table = txn.open_table(tables::LEASE_LOCKS)?;
+
+                // The lock can be taken if it doesn't exist, if we already hold it, or if it
+                // has expired.
+                let can_take = match get(&table, key.as_bytes())? {
+                    Some(value) => {
+                        let [current_holder, current_expiration_ts] = decompose_n(&value)?;
+                        let current_expiration_ts = current_expiration_ts
+                            .try_into()
+                            .map(u64::from_be_bytes)
+                            .map_err(|_| Error::MalformedEntry)?;
+
+                        current_holder == holder.as_bytes() || current_expiration_ts < now_ts
+                    }
+                    None => true,
+                };
+
+                if can_take {
+                    table.insert(
+                        key.as_bytes(),
+                        compose(&[holder.as_bytes(), &expiration_ts.to_be_bytes()]).as_slice(),
+                    )?;
+                }
+
+                Ok(can_take)
+            })
+            .await
+    }
+
+    async fn next_batch_token(&self) -> Result<Option<String>, Self::Error> {
+        if let Some(token) = self.db.get_kv("next_batch_token").await? {
+            let maybe_token: Option<String> = self.deserialize_value(&token)?;
+            Ok(maybe_token)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use matrix_sdk_crypto::{cryptostore_integration_tests, cryptostore_integration_tests_time};
+    use matrix_sdk_test::async_test;
+    use once_cell::sync::Lazy;
+    use tempfile::{tempdir, TempDir};
+
+    use super::{RedbCryptoStore, DATABASE_VERSION};
+    use crate::OpenStoreError;
+
+    static TMP_DIR: Lazy<TempDir> = Lazy::new(|| tempdir().unwrap());
+
+    async fn get_store(name: &str, passphrase: Option<&str>) -> RedbCryptoStore {
+        let tmpdir_path = TMP_DIR.path().join(name);
+
+        RedbCryptoStore::open(tmpdir_path.to_str().unwrap(), passphrase)
+            .await
+            .expect("Can't create a passphrase protected store")
+    }
+
+    cryptostore_integration_tests!();
+    cryptostore_integration_tests_time!();
+
+    #[async_test]
+    async fn test_open_newer_database() {
+        let path = TMP_DIR.path().join("newer_database");
+
+        let store = RedbCryptoStore::open(&path, None).await.unwrap();
+        store.db.set_kv("version", vec![DATABASE_VERSION + 1]).await.unwrap();
+        drop(store);
+
+        let error = RedbCryptoStore::open(&path, None).await.unwrap_err();
+        assert!(
+            matches!(error, OpenStoreError::UnsupportedVersion(v) if v == DATABASE_VERSION + 1)
+        );
+    }
+}
+
+#[cfg(test)]
+mod encrypted_tests {
+    use matrix_sdk_crypto::{
+        cryptostore_integration_tests, cryptostore_integration_tests_time,
+        store::{Changes, CryptoStore as _, PendingChanges},
+    };
+    use matrix_sdk_test::async_test;
+    use once_cell::sync::Lazy;
+    use tempfile::{tempdir, TempDir};
+
+    use super::RedbCryptoStore;
+
+    static TMP_DIR: Lazy<TempDir> = Lazy::new(|| tempdir().unwrap());
+
+    async fn get_store(name: &str, passphrase: Option<&str>) -> RedbCryptoStore {
+        let tmpdir_path = TMP_DIR.path().join(name);
+        let pass = passphrase.unwrap_or("default_test_password");
+
+        RedbCryptoStore::open(tmpdir_path.to_str().unwrap(), Some(pass))
+            .await
+            .expect("Can't create a passphrase protected store")
+    }
+
+    #[async_test]
+    async fn cache_cleared() {
+        let store = get_store("cache_cleared", None).await;
+        // Given we created a session and saved it in the store
+        let (account, session) = cryptostore_integration_tests::get_account_and_session().await;
+        let sender_key = session.sender_key.to_base64();
+
+        store
+            .save_pending_changes(PendingChanges { account: Some(account.deep_clone()) })
+            .await
+            .expect("Can't save account");
+
+        let changes = Changes { sessions: vec![session.clone()], ..Default::default() };
+        store.save_changes(changes).await.unwrap();
+
+        store.session_cache.get(&sender_key).expect("We should have a session");
+
+        // When we clear the caches
+        store.clear_caches().await;
+
+        // Then the session is no longer in the cache
+        assert!(
+            store.session_cache.get(&sender_key).is_none(),
+            "Session should not be in the cache!"
+        );
+    }
+
+    cryptostore_integration_tests!();
+    cryptostore_integration_tests_time!();
+}
@@ -0,0 +1,1506 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use matrix_sdk_base::{
+    deserialized_responses::RawAnySyncOrStrippedState,
+    media::{MediaCacheEntry, MediaFormat, MediaRequest, MediaRetentionPolicy, UniqueKey},
+    MinimalRoomMemberEvent, RoomInfo, RoomMemberPage, RoomMemberQuery, RoomMemberships, RoomState,
+    StateChanges, StateStore, StateStoreDataKey, StateStoreDataValue,
+};
+use matrix_sdk_store_encryption::StoreCipher;
+use redb::{ReadableTable, WriteTransaction};
+use ruma::{
+    canonical_json::{redact, RedactedBecause},
+    events::{
+        presence::PresenceEvent,
+        receipt::{Receipt, ReceiptThread, ReceiptType},
+        room::member::{StrippedRoomMemberEvent, SyncRoomMemberEvent},
+        AnyGlobalAccountDataEvent, AnyRoomAccountDataEvent, AnySyncStateEvent,
+        GlobalAccountDataEventType, RoomAccountDataEventType, StateEventType,
+    },
+    serde::Raw,
+    CanonicalJsonObject, EventId, MilliSecondsSinceUnixEpoch, MxcUri, OwnedEventId, OwnedMxcUri,
+    OwnedUserId, RoomId, RoomVersionId, UserId,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::{
+    error::{Error, Result},
+    get_or_create_store_cipher,
+    utils::{
+        compose, decode_bool, decompose_n, encode_bool, get, get_prefixed, load_db_version,
+        remove_prefixed, Key, RedbDatabase, WriteTable,
+    },
+    OpenStoreError,
+};
+
+mod keys {
+    // Tables
+    pub const KV_BLOB: &str = "kv_blob";
+    pub const ROOM_INFO: &str = "room_info";
+    pub const STATE_EVENT: &str = "state_event";
+    pub const STATE_EVENT_ID: &str = "state_event_id";
+    pub const GLOBAL_ACCOUNT_DATA: &str = "global_account_data";
+    pub const ROOM_ACCOUNT_DATA: &str = "room_account_data";
+    pub const MEMBER: &str = "member";
+    pub const PROFILE: &str = "profile";
+    pub const RECEIPT: &str = "receipt";
+    pub const DISPLAY_NAME: &str = "display_name";
+    pub const MEDIA: &str = "media";
+    pub const MEDIA_LAST_ACCESS: &str = "media_last_access";
+}
+
+/// The definitions of the tables, and the layout of their entries.
+///
+/// Keys and values made of several parts are built with [`compose`].
+mod tables {
+    use redb::TableDefinition;
+
+    use super::keys;
+    use crate::utils::Table;
+
+    /// `key` => `value`
+    pub const KV_BLOB: Table = TableDefinition::new(keys::KV_BLOB);
+    /// `room_id` => `(state, data)`
+    pub const ROOM_INFO: Table = TableDefinition::new(keys::ROOM_INFO);
+    /// `(room_id, event_type, state_key)` => `(stripped, event_id, data)`
+    ///
+    /// The event ID is empty for stripped events.
+    pub const STATE_EVENT: Table = TableDefinition::new(keys::STATE_EVENT);
+    /// `(room_id, event_id)` => `(event_type, state_key)`
+    pub const STATE_EVENT_ID: Table = TableDefinition::new(keys::STATE_EVENT_ID);
+    /// `event_type` => `data`
+    pub const GLOBAL_ACCOUNT_DATA: Table = TableDefinition::new(keys::GLOBAL_ACCOUNT_DATA);
+    /// `(room_id, event_type)` => `data`
+    pub const ROOM_ACCOUNT_DATA: Table = TableDefinition::new(keys::ROOM_ACCOUNT_DATA);
+    /// `(room_id, user_id)` => `(membership, stripped, name, data)`
+    ///
+    /// The name is the [sort name](matrix_sdk_base::RoomMemberQuery::sort_name)
+    /// of the member.
+    pub const MEMBER: Table = TableDefinition::new(keys::MEMBER);
+    /// `(room_id, user_id)` => `data`
+    pub const PROFILE: Table = TableDefinition::new(keys::PROFILE);
+    /// `(room_id, receipt_type, thread, user_id)` => `(event_id, data)`
+    pub const RECEIPT: Table = TableDefinition::new(keys::RECEIPT);
+    /// `(room_id, name)` => `data`
+    pub const DISPLAY_NAME: Table = TableDefinition::new(keys::DISPLAY_NAME);
+    /// `(uri, format)` => `data`
+    pub const MEDIA: Table = TableDefinition::new(keys::MEDIA);
+    /// `(uri, format)` => last access timestamp, as big-endian `u64`
+    pub const MEDIA_LAST_ACCESS: Table = TableDefinition::new(keys::MEDIA_LAST_ACCESS);
+
+    pub const ALL: &[Table] = &[
+        KV_BLOB,
+        ROOM_INFO,
+        STATE_EVENT,
+        STATE_EVENT_ID,
+        GLOBAL_ACCOUNT_DATA,
+        ROOM_ACCOUNT_DATA,
+        MEMBER,
+        PROFILE,
+        RECEIPT,
+        DISPLAY_NAME,
+        MEDIA,
+        MEDIA_LAST_ACCESS,
+    ];
+}
+
+const DATABASE_VERSION: u8 = 1;
+
+/// A redb based state store.
+#[derive(Clone)]
+pub struct RedbStateStore {
+    store_cipher: Option<Arc<StoreCipher>>,
+    path: PathBuf,
+    db: RedbDatabase,
+}
+
+#[cfg(not(tarpaulin_include))]
+impl fmt::Debug for RedbStateStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RedbStateStore").field("path", &self.path).finish()
+    }
+}
+
+impl RedbStateStore {
+    /// Open the redb-based state store at the given path using the given
+    /// passphrase to encrypt private data.
+    pub async fn open(
+        path: impl AsRef<Path>,
+        passphrase: Option<&str>,
+    ) -> Result<Self, OpenStoreError> {
+        let path = path.as_ref();
+        let db = RedbDatabase::open(path, "matrix-sdk-state.redb", tables::ALL).await?;
+
+        let version = load_db_version(&db).await?;
+        if version > DATABASE_VERSION {
+            return Err(OpenStoreError::UnsupportedVersion(version));
+        }
+        if version < DATABASE_VERSION {
+            debug!(version, new_version = DATABASE_VERSION, "Upgrading database");
+            db.set_kv("version", vec![DATABASE_VERSION]).await?;
+        }
+
+        let store_cipher = match passphrase {
+            Some(p) => Some(Arc::new(get_or_create_store_cipher(p, &db).await?)),
+            None => None,
+        };
+
+        Ok(Self { store_cipher, path: path.to_owned(), db })
+    }
+
+    fn encode_value(&self, value: Vec<u8>) -> Result<Vec<u8>> {
+        if let Some(key) = &self.store_cipher {
+            let encrypted = key.encrypt_value_data(value)?;
+            Ok(rmp_serde::to_vec_named(&encrypted)?)
+        } else {
+            Ok(value)
+        }
+    }
+
+    fn serialize_value(&self, value: &impl Serialize) -> Result<Vec<u8>> {
+        let serialized = rmp_serde::to_vec_named(value)?;
+        self.encode_value(serialized)
+    }
+
+    fn serialize_json(&self, value: &impl Serialize) -> Result<Vec<u8>> {
+        let serialized = serde_json::to_vec(value)?;
+        self.encode_value(serialized)
+    }
+
+    fn decode_value<'a>(&self, value: &'a [u8]) -> Result<Cow<'a, [u8]>> {
+        if let Some(key) = &self.store_cipher {
+            let encrypted = rmp_serde::from_slice(value)?;
+            let decrypted = key.decrypt_value_data(encrypted)?;
+            Ok(Cow::Owned(decrypted))
+        } else {
+            Ok(Cow::Borrowed(value))
+        }
+    }
+
+    fn deserialize_json<T: DeserializeOwned>(&self, data: &[u8]) -> Result<T> {
+        let decoded = self.decode_value(data)?;
+        Ok(serde_json::from_slice(&decoded)?)
+    }
+
+    fn deserialize_value<T: DeserializeOwned>(&self, value: &[u8]) -> Result<T> {
+        let decoded = self.decode_value(value)?;
+        Ok(rmp_serde::from_slice(&decoded)?)
+    }
+
+    fn encode_key(&self, table_name: &str, key: impl AsRef<[u8]>) -> Key {
+        let bytes = key.as_ref();
+        if let Some(store_cipher) = &self.store_cipher {
+            Key::Hashed(store_cipher.hash_key(table_name, bytes))
+        } else {
+            Key::Plain(bytes.to_owned())
+        }
+    }
+
+    fn encode_state_store_data_key(&self, key: StateStoreDataKey<'_>) -> Key {
+        let key_s = match key {
+            StateStoreDataKey::SyncToken => Cow::Borrowed(StateStoreDataKey::SYNC_TOKEN),
+            StateStoreDataKey::Filter(f) => {
+                Cow::Owned(format!("{}:{f}", StateStoreDataKey::FILTER))
+            }
+            StateStoreDataKey::UserAvatarUrl(u) => {
+                Cow::Owned(format!("{}:{u}", StateStoreDataKey::USER_AVATAR_URL))
+            }
+            StateStoreDataKey::RecentlyVisitedRooms(b) => {
+                Cow::Owned(format!("{}:{b}", StateStoreDataKey::RECENTLY_VISITED_ROOMS))
+            }
+            StateStoreDataKey::UtdHookManagerData => {
+                Cow::Borrowed(StateStoreDataKey::UTD_HOOK_MANAGER_DATA)
+            }
+            StateStoreDataKey::ComposerDraft(room_id) => {
+                Cow::Owned(format!("{}:{room_id}", StateStoreDataKey::COMPOSER_DRAFT))
+            }
+            StateStoreDataKey::UrlPreviewsInEncryptedRoom(room_id) => Cow::Owned(format!(
+                "{}:{room_id}",
+                StateStoreDataKey::URL_PREVIEWS_IN_ENCRYPTED_ROOM
+            )),
+        };
+
+        self.encode_key(keys::KV_BLOB, &*key_s)
+    }
+
+    fn encode_presence_key(&self, user_id: &UserId) -> Key {
+        self.encode_key(keys::KV_BLOB, format!("presence:{user_id}"))
+    }
+
+    fn encode_custom_key(&self, key: &[u8]) -> Key {
+        let mut full_key = b"custom:".to_vec();
+        full_key.extend(key);
+        self.encode_key(keys::KV_BLOB, full_key)
+    }
+
+    fn encode_media_key(&self, request: &MediaRequest) -> Vec<u8> {
+        let uri = self.encode_key(keys::MEDIA, request.source.unique_key());
+        let format = self.encode_key(keys::MEDIA, request.format.unique_key());
+        compose(&[&uri, &format])
+    }
+
+    fn deserialize_maybe_stripped_state_event(
+        &self,
+        value: &[u8],
+    ) -> Result<RawAnySyncOrStrippedState> {
+        let [stripped, _, data] = decompose_n(value)?;
+
+        Ok(if decode_bool(stripped)? {
+            RawAnySyncOrStrippedState::Stripped(self.deserialize_json(data)?)
+        } else {
+            RawAnySyncOrStrippedState::Sync(self.deserialize_json(data)?)
+        })
+    }
+
+    /// Get the version of the given room from its room info, or assume it is
+    /// version 9 if it is unknown.
+    fn room_version(&self, tables: &StateTables<'_>, room_id: &RoomId) -> RoomVersionId {
+        let encoded_room_id = self.encode_key(keys::ROOM_INFO, room_id);
+        tables
+            .get_room_info(&encoded_room_id)
+            .ok()
+            .flatten()
+            .and_then(|v| self.deserialize_json::<RoomInfo>(&v).ok())
+            .and_then(|info| info.room_version().cloned())
+            .unwrap_or_else(|| {
+                warn!(?room_id, "Unable to find the room version, assume version 9");
+                RoomVersionId::V9
+            })
+    }
+
+    fn remove_maybe_stripped_room_data(
+        &self,
+        tables: &mut StateTables<'_>,
+        room_id: &RoomId,
+        stripped: bool,
+    ) -> Result<()> {
+        let state_event_room_id = self.encode_key(keys::STATE_EVENT, room_id);
+        tables.remove_room_state_events(&state_event_room_id, Some(stripped))?;
+
+        let member_room_id = self.encode_key(keys::MEMBER, room_id);
+        tables.remove_room_members(&member_room_id, Some(stripped))?;
+
+        Ok(())
+    }
+
+    async fn get_kv_blob(&self, key: Key) -> Result<Option<Vec<u8>>> {
+        self.db.get(tables::KV_BLOB, key.to_vec()).await
+    }
+
+    async fn set_kv_blob(&self, key: Key, value: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        self.db
+            .write(move |txn| {
+                let mut table = txn.open_table(tables::KV_BLOB)?;
+                let previous = table.insert(&*key, value.as_slice())?;
+                Ok(previous.map(|value| value.value().to_owned()))
+            })
+            .await
+    }
+
+    async fn delete_kv_blob(&self, key: Key) -> Result<Option<Vec<u8>>> {
+        self.db
+            .write(move |txn| {
+                let mut table = txn.open_table(tables::KV_BLOB)?;
+                let previous = table.remove(&*key)?;
+                Ok(previous.map(|value| value.value().to_owned()))
+            })
+            .await
+    }
+}
+
+/// The tables of the state store, opened in a write transaction.
+struct StateTables<'txn> {
+    kv_blob: WriteTable<'txn>,
+    room_info: WriteTable<'txn>,
+    state_event: WriteTable<'txn>,
+    state_event_id: WriteTable<'txn>,
+    global_account_data: WriteTable<'txn>,
+    room_account_data: WriteTable<'txn>,
+    member: WriteTable<'txn>,
+    profile: WriteTable<'txn>,
+    receipt: WriteTable<'txn>,
+    display_name: WriteTable<'txn>,
+}
+
+impl<'txn> StateTables<'txn> {
+    fn open(txn: &'txn WriteTransaction) -> Result<Self> {
+        Ok(Self {
+            kv_blob: txn.open_table(tables::KV_BLOB)?,
+            room_info: txn.open_table(tables::ROOM_INFO)?,
+            state_event: txn.open_table(tables::STATE_EVENT)?,
+            state_event_id: txn.open_table(tables::STATE_EVENT_ID)?,
+            global_account_data: txn.open_table(tables::GLOBAL_ACCOUNT_DATA)?,
+            room_account_data: txn.open_table(tables::ROOM_ACCOUNT_DATA)?,
+            member: txn.open_table(tables::MEMBER)?,
+            profile: txn.open_table(tables::PROFILE)?,
+            receipt: txn.open_table(tables::RECEIPT)?,
+            display_name: txn.open_table(tables::DISPLAY_NAME)?,
+        })
+    }
+
+    fn set_kv_blob(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.kv_blob.insert(key, value)?;
+        Ok(())
+    }
+
+    fn set_global_account_data(&mut self, event_type: &[u8], data: &[u8]) -> Result<()> {
+        self.global_account_data.insert(event_type, data)?;
+        Ok(())
+    }
+
+    fn set_room_account_data(
+        &mut self,
+        room_id: &[u8],
+        event_type: &[u8],
+        data: &[u8],
+    ) -> Result<()> {
+        self.room_account_data.insert(compose(&[room_id, event_type]).as_slice(), data)?;
+        Ok(())
+    }
+
+    fn remove_room_account_data(&mut self, room_id: &[u8]) -> Result<()> {
+        remove_prefixed(&mut self.room_account_data, &compose(&[room_id]))
+    }
+
+    fn set_room_info(&mut self, room_id: &[u8], state: &[u8], data: &[u8]) -> Result<()> {
+        self.room_info.insert(room_id, compose(&[state, data]).as_slice())?;
+        Ok(())
+    }
+
+    fn get_room_info(&self, room_id: &[u8]) -> Result<Option<Vec<u8>>> {
+        get(&self.room_info, room_id)?
+            .map(|value| {
+                let [_, data] = decompose_n(&value)?;
+                Ok(data.to_owned())
+            })
+            .transpose()
+    }
+
+    fn remove_room_info(&mut self, room_id: &[u8]) -> Result<()> {
+        self.room_info.remove(room_id)?;
+        Ok(())
+    }
+
+    fn set_state_event(
+        &mut self,
+        room_id: &[u8],
+        event_type: &[u8],
+        state_key: &[u8],
+        stripped: bool,
+        event_id: Option<&[u8]>,
+        data: &[u8],
+    ) -> Result<()> {
+        let key = compose(&[room_id, event_type, state_key]);
+        let value = compose(&[encode_bool(stripped), event_id.unwrap_or_default(), data]);
+
+        // Keep the event ID index in sync with the replaced event.
+        if let Some(previous) = self.state_event.insert(key.as_slice(), value.as_slice())? {
+            let [_, previous_event_id, _] = decompose_n(previous.value())?;
+            if !previous_event_id.is_empty() && Some(previous_event_id) != event_id {
+                self.state_event_id.remove(compose(&[room_id, previous_event_id]).as_slice())?;
+            }
+        }
+
+        if let Some(event_id) = event_id {
+            self.state_event_id.insert(
+                compose(&[room_id, event_id]).as_slice(),
+                compose(&[event_type, state_key]).as_slice(),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn get_state_event_by_id(&self, room_id: &[u8], event_id: &[u8]) -> Result<Option<Vec<u8>>> {
+        let Some(location) = get(&self.state_event_id, &compose(&[room_id, event_id]))? else {
+            return Ok(None);
+        };
+        let [event_type, state_key] = decompose_n(&location)?;
+
+        let Some(value) = get(&self.state_event, &compose(&[room_id, event_type, state_key]))?
+        else {
+            return Ok(None);
+        };
+        let [_, stored_event_id, data] = decompose_n(&value)?;
+
+        Ok((stored_event_id == event_id).then(|| data.to_owned()))
+    }
+
+    /// Remove state events for the given room.
+    ///
+    /// If `stripped` is `Some()`, only removes state events for the given
+    /// stripped state. Otherwise, state events are removed regardless of the
+    /// stripped state.
+    fn remove_room_state_events(&mut self, room_id: &[u8], stripped: Option<bool>) -> Result<()> {
+        for (key, value) in get_prefixed(&self.state_event, &compose(&[room_id]))? {
+            let [is_stripped, event_id, _] = decompose_n(&value)?;
+
+            if stripped.is_some() && stripped != Some(decode_bool(is_stripped)?) {
+                continue;
+            }
+
+            if !event_id.is_empty() {
+                self.state_event_id.remove(compose(&[room_id, event_id]).as_slice())?;
+            }
+
+            self.state_event.remove(key.as_slice())?;
+        }
+
+        Ok(())
+    }
+
+    fn set_member(
+        &mut self,
+        room_id: &[u8],
+        user_id: &[u8],
+        membership: &[u8],
+        stripped: bool,
+        name: &[u8],
+        data: &[u8],
+    ) -> Result<()> {
+        self.member.insert(
+            compose(&[room_id, user_id]).as_slice(),
+            compose(&[membership, encode_bool(stripped), name, data]).as_slice(),
+        )?;
+        Ok(())
+    }
+
+    /// Remove members for the given room.
+    ///
+    /// If `stripped` is `Some()`, only removes members for the given stripped
+    /// state. Otherwise, members are removed regardless of the stripped state.
+    fn remove_room_members(&mut self, room_id: &[u8], stripped: Option<bool>) -> Result<()> {
+        for (key, value) in get_prefixed(&self.member, &compose(&[room_id]))? {
+            let [_, is_stripped, _, _] = decompose_n(&value)?;
+
+            if stripped.is_some() && stripped != Some(decode_bool(is_stripped)?) {
+                continue;
+            }
+
+            self.member.remove(key.as_slice())?;
+        }
+
+        Ok(())
+    }
+
+    fn set_profile(&mut self, room_id: &[u8], user_id: &[u8], data: &[u8]) -> Result<()> {
+        self.profile.insert(compose(&[room_id, user_id]).as_slice(), data)?;
+        Ok(())
+    }
+
+    fn remove_room_profiles(&mut self, room_id: &[u8]) -> Result<()> {
+        remove_prefixed(&mut self.profile, &compose(&[room_id]))
+    }
+
+    fn remove_room_profile(&mut self, room_id: &[u8], user_id: &[u8]) -> Result<()> {
+        self.profile.remove(compose(&[room_id, user_id]).as_slice())?;
+        Ok(())
+    }
+
+    fn set_receipt(
+        &mut self,
+        room_id: &[u8],
+        user_id: &[u8],
+        receipt_type: &[u8],
+        thread: &[u8],
+        event_id: &[u8],
+        data: &[u8],
+    ) -> Result<()> {
+        self.receipt.insert(
+            compose(&[room_id, receipt_type, thread, user_id]).as_slice(),
+            compose(&[event_id, data]).as_slice(),
+        )?;
+        Ok(())
+    }
+
+    fn remove_room_receipts(&mut self, room_id: &[u8]) -> Result<()> {
+        remove_prefixed(&mut self.receipt, &compose(&[room_id]))
+    }
+
+    fn set_display_name(&mut self, room_id: &[u8], name: &[u8], data: &[u8]) -> Result<()> {
+        self.display_name.insert(compose(&[room_id, name]).as_slice(), data)?;
+        Ok(())
+    }
+
+    fn remove_display_name(&mut self, room_id: &[u8], name: &[u8]) -> Result<()> {
+        self.display_name.remove(compose(&[room_id, name]).as_slice())?;
+        Ok(())
+    }
+
+    fn remove_room_display_names(&mut self, room_id: &[u8]) -> Result<()> {
+        remove_prefixed(&mut self.display_name, &compose(&[room_id]))
+    }
+}
+
+#[async_trait]
+impl StateStore for RedbStateStore {
+    type Error = Error;
+
+    async fn get_kv_data(&self, key: StateStoreDataKey<'_>) -> Result<Option<StateStoreDataValue>> {
+        self.get_kv_blob(self.encode_state_store_data_key(key))
+            .await?
+            .map(|data| {
+                Ok(match key {
+                    StateStoreDataKey::SyncToken => {
+                        StateStoreDataValue::SyncToken(self.deserialize_value(&data)?)
+                    }
+                    StateStoreDataKey::Filter(_) => {
+                        StateStoreDataValue::Filter(self.deserialize_value(&data)?)
+                    }
+                    StateStoreDataKey::UserAvatarUrl(_) => {
+                        StateStoreDataValue::UserAvatarUrl(self.deserialize_value(&data)?)
+                    }
+                    StateStoreDataKey::RecentlyVisitedRooms(_) => {
+                        StateStoreDataValue::RecentlyVisitedRooms(self.deserialize_value(&data)?)
+                    }
+                    StateStoreDataKey::UtdHookManagerData => {
+                        StateStoreDataValue::UtdHookManagerData(self.deserialize_value(&data)?)
+                    }
+                    StateStoreDataKey::ComposerDraft(_) => {
+                        StateStoreDataValue::ComposerDraft(self.deserialize_value(&data)?)
+                    }
+                    StateStoreDataKey::UrlPreviewsInEncryptedRoom(_) => {
+                        StateStoreDataValue::UrlPreviewsInEncryptedRoom(
+                            self.deserialize_value(&data)?,
+                        )
+                    }
+                })
+            })
+            .transpose()
+    }
+
+    async fn set_kv_data(
+        &self,
+        key: StateStoreDataKey<'_>,
+        value: StateStoreDataValue,
+    ) -> Result<()> {
+        let serialized_value = match key {
+            StateStoreDataKey::SyncToken => self.serialize_value(
+                &value.into_sync_token().expect("Session data not a sync token"),
+            )?,
+            StateStoreDataKey::Filter(_) => {
+                self.serialize_value(&value.into_filter().expect("Session data not a filter"))?
+            }
+            StateStoreDataKey::UserAvatarUrl(_) => self.serialize_value(
+                &value.into_user_avatar_url().expect("Session data not an user avatar url"),
+            )?,
+            StateStoreDataKey::RecentlyVisitedRooms(_) => self.serialize_value(
+                &value.into_recently_visited_rooms().expect("Session data not breadcrumbs"),
+            )?,
+            StateStoreDataKey::UtdHookManagerData => self.serialize_value(
+                &value.into_utd_hook_manager_data().expect("Session data not UtdHookManagerData"),
+            )?,
+            StateStoreDataKey::ComposerDraft(_) => self.serialize_value(
+                &value.into_composer_draft().expect("Session data not a composer draft"),
+            )?,
+            StateStoreDataKey::UrlPreviewsInEncryptedRoom(_) => self.serialize_value(
+                &value
+                    .into_url_previews_in_encrypted_room()
+                    .expect("Session data not a URL previews setting"),
+            )?,
+        };
+
+        self.set_kv_blob(self.encode_state_store_data_key(key), serialized_value).await?;
+        Ok(())
+    }
+
+    async fn remove_kv_data(&self, key: StateStoreDataKey<'_>) -> Result<()> {
+        self.delete_kv_blob(self.encode_state_store_data_key(key)).await?;
+        Ok(())
+    }
+
+    async fn save_changes(&self, changes: &StateChanges) -> Result<()> {
+        let changes = changes.to_owned();
+        let this = self.clone();
+        self.db
+            .write(move |txn| {
+                let mut tables = StateTables::open(txn)?;
+
+                let StateChanges {
+                    sync_token,
+                    account_data,
+                    presence,
+                    profiles,
+                    profiles_to_delete,
+                    state,
+                    room_account_data,
+                    room_infos,
+                    receipts,
+                    redactions,
+                    stripped_state,
+                    ambiguity_maps,
+                } = changes;
+
+                if let Some(sync_token) = sync_token {
+                    let key = this.encode_state_store_data_key(StateStoreDataKey::SyncToken);
+                    let value = this.serialize_value(&sync_token)?;
+                    tables.set_kv_blob(&key, &value)?;
+                }
+
+                for (event_type, event) in account_data {
+                    let event_type =
+                        this.encode_key(keys::GLOBAL_ACCOUNT_DATA, event_type.to_string());
+                    let data = this.serialize_json(&event)?;
+                    tables.set_global_account_data(&event_type, &data)?;
+                }
+
+                for (room_id, events) in room_account_data {
+                    let room_id = this.encode_key(keys::ROOM_ACCOUNT_DATA, room_id);
+                    for (event_type, event) in events {
+                        let event_type =
+                            this.encode_key(keys::ROOM_ACCOUNT_DATA, event_type.to_string());
+                        let data = this.serialize_json(&event)?;
+                        tables.set_room_account_data(&room_id, &event_type, &data)?;
+                    }
+                }
+
+                for (user_id, event) in presence {
+                    let key = this.encode_presence_key(&user_id);
+                    let value = this.serialize_json(&event)?;
+                    tables.set_kv_blob(&key, &value)?;
+                }
+
+                for (room_id, room_info) in room_infos {
+                    let stripped = room_info.state() == RoomState::Invited;
+                    // Remove non-stripped data for stripped rooms and vice-versa.
+                    this.remove_maybe_stripped_room_data(&mut tables, &room_id, !stripped)?;
+
+                    let room_id = this.encode_key(keys::ROOM_INFO, room_id);
+                    let state = this
+                        .encode_key(keys::ROOM_INFO, serde_json::to_string(&room_info.state())?);
+                    let data = this.serialize_json(&room_info)?;
+                    tables.set_room_info(&room_id, &state, &data)?;
+                }
+
+                for (room_id, user_ids) in profiles_to_delete {
+                    let room_id = this.encode_key(keys::PROFILE, room_id);
+                    for user_id in user_ids {
+                        let user_id = this.encode_key(keys::PROFILE, user_id);
+                        tables.remove_room_profile(&room_id, &user_id)?;
+                    }
+                }
+
+                for (room_id, state_event_types) in state {
+                    let profiles = profiles.get(&room_id);
+                    let encoded_room_id = this.encode_key(keys::STATE_EVENT, &room_id);
+
+                    for (event_type, state_events) in state_event_types {
+                        let encoded_event_type =
+                            this.encode_key(keys::STATE_EVENT, event_type.to_string());
+
+                        for (state_key, raw_state_event) in state_events {
+                            let encoded_state_key = this.encode_key(keys::STATE_EVENT, &state_key);
+                            let data = this.serialize_json(&raw_state_event)?;
+
+                            let event_id: Option<String> =
+                                raw_state_event.get_field("event_id").ok().flatten();
+                            let encoded_event_id =
+                                event_id.as_ref().map(|e| this.encode_key(keys::STATE_EVENT, e));
+
+                            tables.set_state_event(
+                                &encoded_room_id,
+                                &encoded_event_type,
+                                &encoded_state_key,
+                                false,
+                                encoded_event_id.as_deref(),
+                                &data,
+                            )?;
+
+                            if event_type == StateEventType::RoomMember {
+                                let member_event = match raw_state_event
+                                    .deserialize_as::<SyncRoomMemberEvent>()
+                                {
+                                    Ok(ev) => ev,
+                                    Err(e) => {
+                                        debug!(event_id, "Failed to deserialize member event: {e}");
+                                        continue;
+                                    }
+                                };
+
+                                let encoded_room_id = this.encode_key(keys::MEMBER, &room_id);
+                                let user_id = this.encode_key(keys::MEMBER, &state_key);
+                                let membership = this
+                                    .encode_key(keys::MEMBER, member_event.membership().as_str());
+                                let name = this.serialize_value(&RoomMemberQuery::sort_name(
+                                    member_event.state_key(),
+                                    member_event
+                                        .as_original()
+                                        .and_then(|ev| ev.content.displayname.as_deref()),
+                                ))?;
+                                let data = this.serialize_value(&state_key)?;
+
+                                tables.set_member(
+                                    &encoded_room_id,
+                                    &user_id,
+                                    &membership,
+                                    false,
+                                    &name,
+                                    &data,
+                                )?;
+
+                                if let Some(profile) =
+                                    profiles.and_then(|p| p.get(member_event.state_key()))
+                                {
+                                    let room_id = this.encode_key(keys::PROFILE, &room_id);
+                                    let user_id = this.encode_key(keys::PROFILE, &state_key);
+                                    let data = this.serialize_json(&profile)?;
+                                    tables.set_profile(&room_id, &user_id, &data)?;
+                                }
+                            }
+                        }
+                    }
+                }
+
+                for (room_id, stripped_state_event_types) in stripped_state {
+                    let encoded_room_id = this.encode_key(keys::STATE_EVENT, &room_id);
+
+                    for (event_type, stripped_state_events) in stripped_state_event_types {
+                        let encoded_event_type =
+                            this.encode_key(keys::STATE_EVENT, event_type.to_string());
+
+                        for (state_key, raw_stripped_state_event) in stripped_state_events {
+                            let encoded_state_key = this.encode_key(keys::STATE_EVENT, &state_key);
+                            let data = this.serialize_json(&raw_stripped_state_event)?;
+                            tables.set_state_event(
+                                &encoded_room_id,
+                                &encoded_event_type,
+                                &encoded_state_key,
+                                true,
+                                None,
+                                &data,
+                            )?;
+
+                            if event_type == StateEventType::RoomMember {
+                                let member_event = match raw_stripped_state_event
+                                    .deserialize_as::<StrippedRoomMemberEvent>(
+                                ) {
+                                    Ok(ev) => ev,
+                                    Err(e) => {
+                                        debug!("Failed to deserialize stripped member event: {e}");
+                                        continue;
+                                    }
+                                };
+
+                                let room_id = this.encode_key(keys::MEMBER, &room_id);
+                                let user_id = this.encode_key(keys::MEMBER, &state_key);
+                                let membership = this.encode_key(
+                                    keys::MEMBER,
+                                    member_event.content.membership.as_str(),
+                                );
+                                let name = this.serialize_value(&RoomMemberQuery::sort_name(
+                                    &member_event.state_key,
+                                    member_event.content.displayname.as_deref(),
+                                ))?;
+                                let data = this.serialize_value(&state_key)?;
+
+                                tables.set_member(
+                                    &room_id,
+                                    &user_id,
+                                    &membership,
+                                    true,
+                                    &name,
+                                    &data,
+                                )?;
+                            }
+                        }
+                    }
+                }
+
+                for (room_id, receipt_event) in receipts {
+                    let room_id = this.encode_key(keys::RECEIPT, room_id);
+
+                    for (event_id, receipt_types) in receipt_event {
+                        let encoded_event_id = this.encode_key(keys::RECEIPT, &event_id);
+
+                        for (receipt_type, receipt_users) in receipt_types {
+                            let receipt_type =
+                                this.encode_key(keys::RECEIPT, receipt_type.as_str());
+
+                            for (user_id, receipt) in receipt_users {
+                                let encoded_user_id = this.encode_key(keys::RECEIPT, &user_id);
+                                // The thread is part of the key, so we rely on serialization to
+                                // represent the main and unthreaded "threads".
+                                let thread = this.encode_key(
+                                    keys::RECEIPT,
+                                    rmp_serde::to_vec_named(&receipt.thread)?,
+                                );
+                                let data = this.serialize_json(&ReceiptData {
+                                    receipt,
+                                    event_id: event_id.clone(),
+                                    user_id,
+                                })?;
+
+                                tables.set_receipt(
+                                    &room_id,
+                                    &encoded_user_id,
+                                    &receipt_type,
+                                    &thread,
+                                    &encoded_event_id,
+                                    &data,
+                                )?;
+                            }
+                        }
+                    }
+                }
+
+                for (room_id, redactions) in redactions {
+                    let encoded_room_id = this.encode_key(keys::STATE_EVENT, &room_id);
+                    let mut room_version = None;
+
+                    for (event_id, redaction) in redactions {
+                        let event_id = this.encode_key(keys::STATE_EVENT, event_id);
+
+                        if let Some(Ok(raw_event)) = tables
+                            .get_state_event_by_id(&encoded_room_id, &event_id)?
+                            .map(|value| this.deserialize_json::<Raw<AnySyncStateEvent>>(&value))
+                        {
+                            let event = raw_event.deserialize()?;
+                            let redacted = redact(
+                                raw_event.deserialize_as::<CanonicalJsonObject>()?,
+                                room_version
+                                    .get_or_insert_with(|| this.room_version(&tables, &room_id)),
+                                Some(RedactedBecause::from_raw_event(&redaction)?),
+                            )
+                            .map_err(Error::Redaction)?;
+                            let data = this.serialize_json(&redacted)?;
+
+                            let event_type =
+                                this.encode_key(keys::STATE_EVENT, event.event_type().to_string());
+                            let state_key = this.encode_key(keys::STATE_EVENT, event.state_key());
+
+                            tables.set_state_event(
+                                &encoded_room_id,
+                                &event_type,
+                                &state_key,
+                                false,
+                                Some(&event_id),
+                                &data,
+                            )?;
+                        }
+                    }
+                }
+
+                for (room_id, display_names) in ambiguity_maps {
+                    let room_id = this.encode_key(keys::DISPLAY_NAME, room_id);
+
+                    for (name, user_ids) in display_names {
+                        let name = this.encode_key(keys::DISPLAY_NAME, name);
+                        let data = this.serialize_json(&user_ids)?;
+
+                        if user_ids.is_empty() {
+                            tables.remove_display_name(&room_id, &name)?;
+                        } else {
+                            tables.set_display_name(&room_id, &name, &data)?;
+                        }
+                    }
+                }
+
+                Ok(())
+            })
+            .await
+    }
+
+    async fn get_presence_event(&self, user_id: &UserId) -> Result<Option<Raw<PresenceEvent>>> {
+        self.get_kv_blob(self.encode_presence_key(user_id))
+            .await?
+            .map(|data| self.deserialize_json(&data))
+            .transpose()
+    }
+
+    async fn get_presence_events(
+        &self,
+        user_ids: &[OwnedUserId],
+    ) -> Result<Vec<Raw<PresenceEvent>>> {
+        if user_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let keys = user_ids.iter().map(|u| self.encode_presence_key(u).to_vec()).collect();
+        self.db
+            .get_many(tables::KV_BLOB, keys)
+            .await?
+            .into_iter()
+            .flatten()
+            .map(|data| self.deserialize_json(&data))
+            .collect()
+    }
+
+    async fn get_state_event(
+        &self,
+        room_id: &RoomId,
+        event_type: StateEventType,
+        state_key: &str,
+    ) -> Result<Option<RawAnySyncOrStrippedState>> {
+        Ok(self
+            .get_state_events_for_keys(room_id, event_type, &[state_key])
+            .await?
+            .into_iter()
+            .next())
+    }
+
+    async fn get_state_events(
+        &self,
+        room_id: &RoomId,
+        event_type: StateEventType,
+    ) -> Result<Vec<RawAnySyncOrStrippedState>> {
+        let room_id = self.encode_key(keys::STATE_EVENT, room_id);
+        let event_type = self.encode_key(keys::STATE_EVENT, event_type.to_string());
+        self.db
+            .get_prefixed(tables::STATE_EVENT, compose(&[&room_id, &event_type]))
+            .await?
+            .into_iter()
+            .map(|(_, value)| self.deserialize_maybe_stripped_state_event(&value))
+            .collect()
+    }
+
+    async fn get_state_events_for_keys(
+        &self,
+        room_id: &RoomId,
+        event_type: StateEventType,
+        state_keys: &[&str],
+    ) -> Result<Vec<RawAnySyncOrStrippedState>, Self::Error> {
+        if state_keys.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let room_id = self.encode_key(keys::STATE_EVENT, room_id);
+        let event_type = self.encode_key(keys::STATE_EVENT, event_type.to_string());
+        let keys = state_keys
+            .iter()
+            .map(|k| compose(&[&room_id, &event_type, &self.encode_key(keys::STATE_EVENT, k)]))
+            .collect();
+        self.db
+            .get_many(tables::STATE_EVENT, keys)
+            .await?
+            .into_iter()
+            .flatten()
+            .map(|value| self.deserialize_maybe_stripped_state_event(&value))
+            .collect()
+    }
+
+    async fn get_profile(
+        &self,
+        room_id: &RoomId,
+        user_id: &UserId,
+    ) -> Result<Option<MinimalRoomMemberEvent>> {
+        let room_id = self.encode_key(keys::PROFILE, room_id);
+        let user_id = self.encode_key(keys::PROFILE, user_id);
+
+        self.db
+            .get(tables::PROFILE, compose(&[&room_id, &user_id]))
+            .await?
+            .map(|data| self.deserialize_json(&data))
+            .transpose()
+    }
+
+    async fn get_profiles<'a>(
+        &self,
+        room_id: &RoomId,
+        user_ids: &'a [OwnedUserId],
+    ) -> Result<BTreeMap<&'a UserId, MinimalRoomMemberEvent>> {
+        if user_ids.is_empty() {
+            return Ok(BTreeMap::new());
+        }
+
+        let room_id = self.encode_key(keys::PROFILE, room_id);
+        let keys = user_ids
+            .iter()
+            .map(|u| compose(&[&room_id, &self.encode_key(keys::PROFILE, u)]))
+            .collect();
+
+        user_ids
+            .iter()
+            .zip(self.db.get_many(tables::PROFILE, keys).await?)
+            .filter_map(|(user_id, data)| Some((user_id, data?)))
+            .map(|(user_id, data)| Ok((user_id.as_ref(), self.deserialize_json(&data)?)))
+            .collect()
+    }
+
+    async fn get_user_ids(
+        &self,
+        room_id: &RoomId,
+        membership: RoomMemberships,
+    ) -> Result<Vec<OwnedUserId>> {
+        let room_id = self.encode_key(keys::MEMBER, room_id);
+        let memberships = membership
+            .as_vec()
+            .into_iter()
+            .map(|m| self.encode_key(keys::MEMBER, m.as_str()))
+            .collect::<BTreeSet<_>>();
+
+        let mut user_ids = Vec::new();
+        for (_, value) in self.db.get_prefixed(tables::MEMBER, compose(&[&room_id])).await? {
+            let [membership, _, _, data] = decompose_n(&value)?;
+
+            if memberships.is_empty() || memberships.contains(membership) {
+                user_ids.push(self.deserialize_value(data)?);
+            }
+        }
+
+        Ok(user_ids)
+    }
+
+    async fn get_invited_user_ids(&self, room_id: &RoomId) -> Result<Vec<OwnedUserId>> {
+        self.get_user_ids(room_id, RoomMemberships::INVITE).await
+    }
+
+    async fn get_joined_user_ids(&self, room_id: &RoomId) -> Result<Vec<OwnedUserId>> {
+        self.get_user_ids(room_id, RoomMemberships::JOIN).await
+    }
+
+    async fn get_room_member_page(
+        &self,
+        room_id: &RoomId,
+        query: &RoomMemberQuery,
+    ) -> Result<RoomMemberPage> {
+        let room_id = self.encode_key(keys::MEMBER, room_id);
+        let memberships = query
+            .memberships
+            .as_vec()
+            .into_iter()
+            .map(|m| self.encode_key(keys::MEMBER, m.as_str()))
+            .collect::<BTreeSet<_>>();
+
+        // The tables have no secondary index, so the members of the room are
+        // sorted by name in memory.
+        let mut members: Vec<(OwnedUserId, String)> = Vec::new();
+        for (_, value) in self.db.get_prefixed(tables::MEMBER, compose(&[&room_id])).await? {
+            let [membership, _, name, data] = decompose_n(&value)?;
+
+            if memberships.is_empty() || memberships.contains(membership) {
+                members.push((self.deserialize_value(data)?, self.deserialize_value(name)?));
+            }
+        }
+
+        Ok(query.select_page(members))
+    }
+
+    async fn get_room_infos(&self) -> Result<Vec<RoomInfo>> {
+        self.db
+            .get_prefixed(tables::ROOM_INFO, Vec::new())
+            .await?
+            .into_iter()
+            .map(|(_, value)| {
+                let [_, data] = decompose_n(&value)?;
+                self.deserialize_json(data)
+            })
+            .collect()
+    }
+
+    async fn get_stripped_room_infos(&self) -> Result<Vec<RoomInfo>> {
+        let invited = self.encode_key(keys::ROOM_INFO, serde_json::to_string(&RoomState::Invited)?);
+
+        let mut room_infos = Vec::new();
+        for (_, value) in self.db.get_prefixed(tables::ROOM_INFO, Vec::new()).await? {
+            let [state, data] = decompose_n(&value)?;
+
+            if state == &*invited {
+                room_infos.push(self.deserialize_json(data)?);
+            }
+        }
+
+        Ok(room_infos)
+    }
+
+    async fn get_users_with_display_name(
+        &self,
+        room_id: &RoomId,
+        display_name: &str,
+    ) -> Result<BTreeSet<OwnedUserId>> {
+        let room_id = self.encode_key(keys::DISPLAY_NAME, room_id);
+        let name = self.encode_key(keys::DISPLAY_NAME, display_name);
+
+        Ok(self
+            .db
+            .get(tables::DISPLAY_NAME, compose(&[&room_id, &name]))
+            .await?
+            .map(|data| self.deserialize_json(&data))
+            .transpose()?
+            .unwrap_or_default())
+    }
+
+    async fn get_users_with_display_names<'a>(
+        &self,
+        room_id: &RoomId,
+        display_names: &'a [String],
+    ) -> Result<BTreeMap<&'a str, BTreeSet<OwnedUserId>>> {
+        if display_names.is_empty() {
+            return Ok(BTreeMap::new());
+        }
+
+        let room_id = self.encode_key(keys::DISPLAY_NAME, room_id);
+        let keys = display_names
+            .iter()
+            .map(|n| compose(&[&room_id, &self.encode_key(keys::DISPLAY_NAME, n)]))
+            .collect();
+
+        display_names
+            .iter()
+            .zip(self.db.get_many(tables::DISPLAY_NAME, keys).await?)
+            .filter_map(|(name, data)| Some((name, data?)))
+            .map(|(name, data)| Ok((name.as_str(), self.deserialize_json(&data)?)))
+            .collect()
+    }
+
+    async fn get_account_data_event(
+        &self,
+        event_type: GlobalAccountDataEventType,
+    ) -> Result<Option<Raw<AnyGlobalAccountDataEvent>>> {
+        let event_type = self.encode_key(keys::GLOBAL_ACCOUNT_DATA, event_type.to_string());
+        self.db
+            .get(tables::GLOBAL_ACCOUNT_DATA, event_type.to_vec())
+            .await?
+            .map(|value| self.deserialize_json(&value))
+            .transpose()
+    }
+
+    async fn get_room_account_data_event(
+        &self,
+        room_id: &RoomId,
+        event_type: RoomAccountDataEventType,
+    ) -> Result<Option<Raw<AnyRoomAccountDataEvent>>> {
+        let room_id = self.encode_key(keys::ROOM_ACCOUNT_DATA, room_id);
+        let event_type = self.encode_key(keys::ROOM_ACCOUNT_DATA, event_type.to_string());
+        self.db
+            .get(tables::ROOM_ACCOUNT_DATA, compose(&[&room_id, &event_type]))
+            .await?
+            .map(|value| self.deserialize_json(&value))
+            .transpose()
+    }
+
+    async fn get_user_room_receipt_event(
+        &self,
+        room_id: &RoomId,
+        receipt_type: ReceiptType,
+        thread: ReceiptThread,
+        user_id: &UserId,
+    ) -> Result<Option<(OwnedEventId, Receipt)>> {
+        let room_id = self.encode_key(keys::RECEIPT, room_id);
+        let receipt_type = self.encode_key(keys::RECEIPT, receipt_type.to_string());
+        // The thread is part of the key, so we rely on serialization to represent
+        // the main and unthreaded "threads".
+        let thread = self.encode_key(keys::RECEIPT, rmp_serde::to_vec_named(&thread)?);
+        let user_id = self.encode_key(keys::RECEIPT, user_id);
+
+        self.db
+            .get(tables::RECEIPT, compose(&[&room_id, &receipt_type, &thread, &user_id]))
+            .await?
+            .map(|value| {
+                let [_, data] = decompose_n(&value)?;
+                self.deserialize_json::<ReceiptData>(data).map(|d| (d.event_id, d.receipt))
+            })
+            .transpose()
+    }
+
+    async fn get_event_room_receipt_events(
+        &self,
+        room_id: &RoomId,
+        receipt_type: ReceiptType,
+        thread: ReceiptThread,
+        event_id: &EventId,
+    ) -> Result<Vec<(OwnedUserId, Receipt)>> {
+        let room_id = self.encode_key(keys::RECEIPT, room_id);
+        let receipt_type = self.encode_key(keys::RECEIPT, receipt_type.to_string());
+        // The thread is part of the key, so we rely on serialization to represent
+        // the main and unthreaded "threads".
+        let thread = self.encode_key(keys::RECEIPT, rmp_serde::to_vec_named(&thread)?);
+        let event_id = self.encode_key(keys::RECEIPT, event_id);
+
+        let mut receipts = Vec::new();
+        let prefix = compose(&[&room_id, &receipt_type, &thread]);
+        for (_, value) in self.db.get_prefixed(tables::RECEIPT, prefix).await? {
+            let [receipt_event_id, data] = decompose_n(&value)?;
+
+            if receipt_event_id == &*event_id {
+                let data = self.deserialize_json::<ReceiptData>(data)?;
+                receipts.push((data.user_id, data.receipt));
+            }
+        }
+
+        Ok(receipts)
+    }
+
+    async fn get_custom_value(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.get_kv_blob(self.encode_custom_key(key)).await
+    }
+
+    async fn set_custom_value_no_read(&self, key: &[u8], value: Vec<u8>) -> Result<()> {
+        self.set_kv_blob(self.encode_custom_key(key), value).await?;
+        Ok(())
+    }
+
+    async fn set_custom_value(&self, key: &[u8], value: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        self.set_kv_blob(self.encode_custom_key(key), value).await
+    }
+
+    async fn remove_custom_value(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.delete_kv_blob(self.encode_custom_key(key)).await
+    }
+
+    async fn add_media_content(&self, request: &MediaRequest, content: Vec<u8>) -> Result<()> {
+        let key = self.encode_media_key(request);
+        let data = self.encode_value(content)?;
+        let last_access = u64::from(MilliSecondsSinceUnixEpoch::now().get());
+
+        self.db
+            .write(move |txn| {
+                txn.open_table(tables::MEDIA)?.insert(key.as_slice(), data.as_slice())?;
+                txn.open_table(tables::MEDIA_LAST_ACCESS)?
+                    .insert(key.as_slice(), last_access.to_be_bytes().as_slice())?;
+                Ok(())
+            })
+            .await
+    }
+
+    async fn get_media_content(&self, request: &MediaRequest) -> Result<Option<Vec<u8>>> {
+        let key = self.encode_media_key(request);
+        let last_access = u64::from(MilliSecondsSinceUnixEpoch::now().get());
+
+        let data = self
+            .db
+            .write(move |txn| {
+                let data = get(&txn.open_table(tables::MEDIA)?, &key)?;
+
+                if data.is_some() {
+                    txn.open_table(tables::MEDIA_LAST_ACCESS)?
+                        .insert(key.as_slice(), last_access.to_be_bytes().as_slice())?;
+                }
+
+                Ok(data)
+            })
+            .await?;
+
+        data.map(|v| self.decode_value(&v).map(Into::into)).transpose()
+    }
+
+    async fn remove_media_content(&self, request: &MediaRequest) -> Result<()> {
+        let key = self.encode_media_key(request);
+
+        self.db
+            .write(move |txn| {
+                txn.open_table(tables::MEDIA)?.remove(key.as_slice())?;
+                txn.open_table(tables::MEDIA_LAST_ACCESS)?.remove(key.as_slice())?;
+                Ok(())
+            })
+            .await
+    }
+
+    async fn remove_media_content_for_uri(&self, uri: &MxcUri) -> Result<()> {
+        let prefix = compose(&[&self.encode_key(keys::MEDIA, uri)]);
+
+        self.db
+            .write(move |txn| {
+                remove_prefixed(&mut txn.open_table(tables::MEDIA)?, &prefix)?;
+                remove_prefixed(&mut txn.open_table(tables::MEDIA_LAST_ACCESS)?, &prefix)
+            })
+            .await
+    }
+
+    async fn media_cache_size(&self) -> Result<usize> {
+        self.db
+            .read(|txn| {
+                let mut size = 0;
+                for entry in txn.open_table(tables::MEDIA)?.iter()? {
+                    size += entry?.1.value().len();
+                }
+                Ok(size)
+            })
+            .await
+    }
+
+    async fn clean_up_media_cache(
+        &self,
+        policy: MediaRetentionPolicy,
+        protected_uris: &[OwnedMxcUri],
+        now: MilliSecondsSinceUnixEpoch,
+    ) -> Result<()> {
+        let protected_uris = protected_uris
+            .iter()
+            .map(|uri| self.encode_key(keys::MEDIA, uri.as_str()))
+            .collect::<BTreeSet<_>>();
+        let file_format = self.encode_key(keys::MEDIA, MediaFormat::File.unique_key());
+
+        let entries = self
+            .db
+            .read(|txn| {
+                let media = txn.open_table(tables::MEDIA)?;
+                let last_access_table = txn.open_table(tables::MEDIA_LAST_ACCESS)?;
+
+                let mut entries = Vec::new();
+                for entry in media.iter()? {
+                    let (key, value) = entry?;
+                    let key = key.value().to_owned();
+                    let last_access = get(&last_access_table, &key)?
+                        .and_then(|bytes| bytes.try_into().ok())
+                        .map(u64::from_be_bytes)
+                        .unwrap_or_default();
+
+                    entries.push((key, value.value().len(), last_access));
+                }
+
+                Ok(entries)
+            })
+            .await?
+            .into_iter()
+            .map(|(key, size, last_access)| {
+                let [uri, format] = decompose_n(&key)?;
+
+                Ok(MediaCacheEntry {
+                    is_protected: protected_uris.contains(uri),
+                    is_thumbnail: format != &*file_format,
+                    size,
+                    last_access: MilliSecondsSinceUnixEpoch(
+                        last_access.try_into().unwrap_or_default(),
+                    ),
+                    key,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let evicted = policy.select_evicted(entries, now);
+        if evicted.is_empty() {
+            return Ok(());
+        }
+
+        self.db
+            .write(move |txn| {
+                let mut media = txn.open_table(tables::MEDIA)?;
+                let mut last_access = txn.open_table(tables::MEDIA_LAST_ACCESS)?;
+
+                for key in evicted {
+                    media.remove(key.as_slice())?;
+                    last_access.remove(key.as_slice())?;
+                }
+
+                Ok(())
+            })
+            .await
+    }
+
+    async fn remove_room(&self, room_id: &RoomId) -> Result<()> {
+        let this = self.clone();
+        let room_id = room_id.to_owned();
+
+        self.db
+            .write(move |txn| {
+                let mut tables = StateTables::open(txn)?;
+
+                let room_info_room_id = this.encode_key(keys::ROOM_INFO, &room_id);
+                tables.remove_room_info(&room_info_room_id)?;
+
+                let state_event_room_id = this.encode_key(keys::STATE_EVENT, &room_id);
+                tables.remove_room_state_events(&state_event_room_id, None)?;
+
+                let member_room_id = this.encode_key(keys::MEMBER, &room_id);
+                tables.remove_room_members(&member_room_id, None)?;
+
+                let profile_room_id = this.encode_key(keys::PROFILE, &room_id);
+                tables.remove_room_profiles(&profile_room_id)?;
+
+                let room_account_data_room_id = this.encode_key(keys::ROOM_ACCOUNT_DATA, &room_id);
+                tables.remove_room_account_data(&room_account_data_room_id)?;
+
+                let receipt_room_id = this.encode_key(keys::RECEIPT, &room_id);
+                tables.remove_room_receipts(&receipt_room_id)?;
+
+                let display_name_room_id = this.encode_key(keys::DISPLAY_NAME, &room_id);
+                tables.remove_room_display_names(&display_name_room_id)?;
+
+                Ok(())
+            })
+            .await
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ReceiptData {
+    receipt: Receipt,
+    event_id: OwnedEventId,
+    user_id: OwnedUserId,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering::SeqCst};
+
+    use matrix_sdk_base::{statestore_integration_tests, StateStore, StoreError};
+    use matrix_sdk_test::async_test;
+    use once_cell::sync::Lazy;
+    use tempfile::{tempdir, TempDir};
+
+    use super::{RedbStateStore, DATABASE_VERSION};
+    use crate::OpenStoreError;
+
+    static TMP_DIR: Lazy<TempDir> = Lazy::new(|| tempdir().unwrap());
+    static NUM: AtomicU32 = AtomicU32::new(0);
+
+    async fn get_store() -> Result<impl StateStore, StoreError> {
+        let name = NUM.fetch_add(1, SeqCst).to_string();
+        let tmpdir_path = TMP_DIR.path().join(name);
+
+        Ok(RedbStateStore::open(tmpdir_path.to_str().unwrap(), None).await.unwrap())
+    }
+
+    statestore_integration_tests!(with_media_tests);
+
+    #[async_test]
+    async fn test_open_newer_database() {
+        let path = TMP_DIR.path().join("newer_database");
+
+        let store = RedbStateStore::open(&path, None).await.unwrap();
+        store.db.set_kv("version", vec![DATABASE_VERSION + 1]).await.unwrap();
+        drop(store);
+
+        let error = RedbStateStore::open(&path, None).await.unwrap_err();
+        assert!(
+            matches!(error, OpenStoreError::UnsupportedVersion(v) if v == DATABASE_VERSION + 1)
+        );
+    }
+}
+
+#[cfg(test)]
+mod encrypted_tests {
+    use std::sync::atomic::{AtomicU32, Ordering::SeqCst};
+
+    use matrix_sdk_base::{statestore_integration_tests, StateStore, StoreError};
+    use once_cell::sync::Lazy;
+    use tempfile::{tempdir, TempDir};
+
+    use super::RedbStateStore;
+
+    static TMP_DIR: Lazy<TempDir> = Lazy::new(|| tempdir().unwrap());
+    static NUM: AtomicU32 = AtomicU32::new(0);
+
+    async fn get_store() -> Result<impl StateStore, StoreError> {
+        let name = NUM.fetch_add(1, SeqCst).to_string();
+        let tmpdir_path = TMP_DIR.path().join(name);
+
+        Ok(RedbStateStore::open(tmpdir_path.to_str().unwrap(), Some("default_test_password"))
+            .await
+            .unwrap())
+    }
+
+    statestore_integration_tests!(with_media_tests);
+}
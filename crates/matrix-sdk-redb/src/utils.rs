@@ -0,0 +1,350 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    borrow::Borrow,
+    collections::BTreeMap,
+    fmt,
+    ops::Deref,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, Weak},
+};
+
+use redb::{Database, ReadTransaction, ReadableTable, TableDefinition, WriteTransaction};
+use tokio::{fs, task::spawn_blocking};
+
+use crate::{
+    error::{Error, Result},
+    OpenStoreError,
+};
+
+/// The definition of a table of the database.
+///
+/// All the tables map raw bytes to raw bytes, the keys being built with
+/// [`compose`] when they are made of several parts.
+pub(crate) type Table = TableDefinition<'static, &'static [u8], &'static [u8]>;
+
+/// A table opened in a write transaction.
+pub(crate) type WriteTable<'txn> = redb::Table<'txn, &'static [u8], &'static [u8]>;
+
+/// A table holding miscellaneous values, like the version of the database and
+/// the store cipher.
+pub(crate) const KV: Table = TableDefinition::new("kv");
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Key {
+    Plain(Vec<u8>),
+    Hashed([u8; 32]),
+}
+
+impl Deref for Key {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            Key::Plain(slice) => slice,
+            Key::Hashed(bytes) => bytes,
+        }
+    }
+}
+
+impl Borrow<[u8]> for Key {
+    fn borrow(&self) -> &[u8] {
+        self.deref()
+    }
+}
+
+/// Concatenate the given parts, each one prefixed by its length.
+///
+/// The result of composing the first parts of a key is a prefix of the full
+/// key, which allows to iterate over a range of keys sharing the same first
+/// parts.
+pub(crate) fn compose(parts: &[&[u8]]) -> Vec<u8> {
+    let capacity = parts.iter().map(|part| part.len() + 4).sum();
+    let mut bytes = Vec::with_capacity(capacity);
+
+    for part in parts {
+        let len = u32::try_from(part.len()).expect("a key part should not be larger than 4 GiB");
+        bytes.extend_from_slice(&len.to_be_bytes());
+        bytes.extend_from_slice(part);
+    }
+
+    bytes
+}
+
+/// Split bytes created with [`compose`] into their parts.
+pub(crate) fn decompose(mut bytes: &[u8]) -> Result<Vec<&[u8]>> {
+    let mut parts = Vec::new();
+
+    while !bytes.is_empty() {
+        if bytes.len() < 4 {
+            return Err(Error::MalformedEntry);
+        }
+
+        let (len, rest) = bytes.split_at(4);
+        let len = u32::from_be_bytes(len.try_into().unwrap()) as usize;
+
+        if rest.len() < len {
+            return Err(Error::MalformedEntry);
+        }
+
+        let (part, rest) = rest.split_at(len);
+        parts.push(part);
+        bytes = rest;
+    }
+
+    Ok(parts)
+}
+
+/// Split bytes created with [`compose`] into the given number of parts.
+pub(crate) fn decompose_n<const N: usize>(bytes: &[u8]) -> Result<[&[u8]; N]> {
+    decompose(bytes)?.try_into().map_err(|_| Error::MalformedEntry)
+}
+
+/// Encode a boolean as a key or value part.
+pub(crate) fn encode_bool(value: bool) -> &'static [u8] {
+    if value {
+        &[1]
+    } else {
+        &[0]
+    }
+}
+
+/// Decode a boolean encoded with [`encode_bool`].
+pub(crate) fn decode_bool(bytes: &[u8]) -> Result<bool> {
+    match bytes {
+        [0] => Ok(false),
+        [1] => Ok(true),
+        _ => Err(Error::MalformedEntry),
+    }
+}
+
+/// Get the value of the given key in the given table.
+pub(crate) fn get(
+    table: &impl ReadableTable<&'static [u8], &'static [u8]>,
+    key: &[u8],
+) -> Result<Option<Vec<u8>>> {
+    Ok(table.get(key)?.map(|value| value.value().to_owned()))
+}
+
+/// Get all the entries of the given table whose key starts with the given
+/// prefix.
+pub(crate) fn get_prefixed(
+    table: &impl ReadableTable<&'static [u8], &'static [u8]>,
+    prefix: &[u8],
+) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    let mut entries = Vec::new();
+
+    for entry in table.range(prefix..)? {
+        let (key, value) = entry?;
+        let key = key.value();
+
+        if !key.starts_with(prefix) {
+            break;
+        }
+
+        entries.push((key.to_owned(), value.value().to_owned()));
+    }
+
+    Ok(entries)
+}
+
+/// Remove all the entries of the given table whose key starts with the given
+/// prefix.
+pub(crate) fn remove_prefixed(table: &mut WriteTable<'_>, prefix: &[u8]) -> Result<()> {
+    for (key, _) in get_prefixed(&*table, prefix)? {
+        table.remove(key.as_slice())?;
+    }
+
+    Ok(())
+}
+
+/// The databases that are currently open in this process.
+///
+/// A redb database can only be opened once, so stores that are opened several
+/// times with the same path share the same database.
+static OPEN_DATABASES: Mutex<BTreeMap<PathBuf, Weak<Database>>> = Mutex::new(BTreeMap::new());
+
+/// A redb database, which runs its transactions on the blocking thread pool.
+#[derive(Clone)]
+pub(crate) struct RedbDatabase {
+    inner: Arc<Database>,
+}
+
+#[cfg(not(tarpaulin_include))]
+impl fmt::Debug for RedbDatabase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RedbDatabase").finish_non_exhaustive()
+    }
+}
+
+impl RedbDatabase {
+    /// Open the database in the file with the given name inside the given
+    /// directory, and create the given tables if they don't exist yet.
+    pub(crate) async fn open(
+        dir: &Path,
+        file_name: &str,
+        tables: &'static [Table],
+    ) -> Result<Self, OpenStoreError> {
+        fs::create_dir_all(dir).await.map_err(OpenStoreError::CreateDir)?;
+        let path = fs::canonicalize(dir).await.map_err(OpenStoreError::CreateDir)?.join(file_name);
+
+        let inner = spawn_blocking(move || {
+            let mut open_databases = OPEN_DATABASES.lock().unwrap();
+            open_databases.retain(|_, database| database.strong_count() > 0);
+
+            if let Some(database) = open_databases.get(&path).and_then(Weak::upgrade) {
+                return Ok(database);
+            }
+
+            let database = Arc::new(Database::create(&path)?);
+            open_databases.insert(path, Arc::downgrade(&database));
+
+            Ok::<_, OpenStoreError>(database)
+        })
+        .await
+        .unwrap()?;
+
+        let this = Self { inner };
+        this.write(|txn| {
+            txn.open_table(KV)?;
+            for table in tables {
+                txn.open_table(*table)?;
+            }
+            Ok(())
+        })
+        .await?;
+
+        Ok(this)
+    }
+
+    /// Run the given function in a read transaction.
+    pub(crate) async fn read<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&ReadTransaction) -> Result<T> + Send + 'static,
+    {
+        let database = self.inner.clone();
+        spawn_blocking(move || {
+            let txn = database.begin_read()?;
+            f(&txn)
+        })
+        .await
+        .unwrap()
+    }
+
+    /// Run the given function in a write transaction, and commit it if the
+    /// function succeeds.
+    pub(crate) async fn write<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&WriteTransaction) -> Result<T> + Send + 'static,
+    {
+        let database = self.inner.clone();
+        spawn_blocking(move || {
+            let txn = database.begin_write()?;
+            let result = f(&txn)?;
+            txn.commit()?;
+            Ok(result)
+        })
+        .await
+        .unwrap()
+    }
+
+    /// Get the value of the given key in the given table.
+    pub(crate) async fn get(&self, table: Table, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        self.read(move |txn| get(&txn.open_table(table)?, &key)).await
+    }
+
+    /// Get the values of the given keys in the given table, in the same order.
+    pub(crate) async fn get_many(
+        &self,
+        table: Table,
+        keys: Vec<Vec<u8>>,
+    ) -> Result<Vec<Option<Vec<u8>>>> {
+        self.read(move |txn| {
+            let table = txn.open_table(table)?;
+            keys.iter().map(|key| get(&table, key)).collect()
+        })
+        .await
+    }
+
+    /// Get all the entries of the given table whose key starts with the given
+    /// prefix.
+    pub(crate) async fn get_prefixed(
+        &self,
+        table: Table,
+        prefix: Vec<u8>,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.read(move |txn| get_prefixed(&txn.open_table(table)?, &prefix)).await
+    }
+
+    /// Get the value of the given key in the kv table.
+    pub(crate) async fn get_kv(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let key = key.to_owned();
+        self.read(move |txn| get(&txn.open_table(KV)?, key.as_bytes())).await
+    }
+
+    /// Set the value of the given key in the kv table.
+    pub(crate) async fn set_kv(&self, key: &str, value: Vec<u8>) -> Result<()> {
+        let key = key.to_owned();
+        self.write(move |txn| {
+            txn.open_table(KV)?.insert(key.as_bytes(), value.as_slice())?;
+            Ok(())
+        })
+        .await
+    }
+}
+
+/// Load the version of the database, or 0 if it was just created.
+pub(crate) async fn load_db_version(db: &RedbDatabase) -> Result<u8, OpenStoreError> {
+    let Some(version) = db.get_kv("version").await.map_err(OpenStoreError::LoadVersion)? else {
+        return Ok(0);
+    };
+
+    match version.as_slice() {
+        [version] => Ok(*version),
+        _ => Err(OpenStoreError::InvalidVersion),
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::{compose, decompose, decompose_n};
+
+    #[test]
+    fn test_compose_roundtrip() {
+        let key = compose(&[b"!room:localhost", b"", b"m.room.member"]);
+        let parts = decompose(&key).unwrap();
+
+        assert_eq!(parts, [&b"!room:localhost"[..], b"", b"m.room.member"]);
+        assert!(decompose_n::<2>(&key).is_err());
+    }
+
+    #[test]
+    fn test_compose_prefix() {
+        let prefix = compose(&[b"!room:localhost"]);
+        let key = compose(&[b"!room:localhost", b"@alice:localhost"]);
+        let other_key = compose(&[b"!room:localhost2", b"@alice:localhost"]);
+
+        assert!(key.starts_with(&prefix));
+        assert!(!other_key.starts_with(&prefix));
+    }
+
+    #[test]
+    fn test_decompose_malformed() {
+        assert!(decompose(&[0, 0, 0, 4, 1]).is_err());
+        assert!(decompose(&[0, 0]).is_err());
+    }
+}
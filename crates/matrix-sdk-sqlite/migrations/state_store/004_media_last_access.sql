@@ -0,0 +1,7 @@
+-- The last time a media file was accessed, in milliseconds since the Unix
+-- epoch, used to evict the least recently used media files.
+ALTER TABLE "media" ADD COLUMN "last_access" INTEGER NOT NULL DEFAULT 0;
+
+-- Consider that the existing media files were accessed during the migration,
+-- so they are not all evicted at once by the next clean-up.
+UPDATE "media" SET "last_access" = CAST(strftime('%s', 'now') AS INTEGER) * 1000;
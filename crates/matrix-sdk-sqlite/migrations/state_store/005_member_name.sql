@@ -0,0 +1,6 @@
+-- The name the member is sorted and searched by, used to get pages of the
+-- members of a room. It is the plain lowercase name when the store is not
+-- encrypted, and the encrypted name otherwise.
+ALTER TABLE "member" ADD COLUMN "name" BLOB;
+
+CREATE INDEX "member_room_id_name" ON "member" ("room_id", "name");
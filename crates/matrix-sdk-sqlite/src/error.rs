@@ -44,6 +44,19 @@ pub enum OpenStoreError {
     #[error("Invalid database version")]
     InvalidVersion,
 
+    /// The version of the database is newer than the versions supported by
+    /// this store.
+    #[error("Unsupported database version: {0}")]
+    UnsupportedVersion(u8),
+
+    /// The integrity check of the database failed.
+    #[error("The database is corrupted: {}", .0.join(", "))]
+    Corrupted(Vec<String>),
+
+    /// Failed to run the integrity check of the database.
+    #[error("Failed to check the integrity of the database")]
+    IntegrityCheck(#[source] rusqlite::Error),
+
     /// Failed to apply migrations.
     #[error("Failed to run migrations")]
     Migration(#[from] Error),
@@ -65,6 +78,45 @@ pub enum OpenStoreError {
     SaveCipher(#[source] rusqlite::Error),
 }
 
+impl OpenStoreError {
+    /// Whether this error means that the database file is corrupted.
+    ///
+    /// This is the case if SQLite reports that the file is corrupted or is not
+    /// a database, or if the integrity check of the database fails. The data
+    /// of such a database can't be recovered, so it should be deleted and
+    /// recreated.
+    ///
+    /// Other errors, like a database created by a newer version of the store
+    /// or a failed migration, don't mean that the file is corrupted. See
+    /// [`OpenStoreError::is_failed_migration()`] for the latter.
+    pub fn is_corruption(&self) -> bool {
+        match self {
+            Self::Corrupted(_) => true,
+            Self::LoadVersion(error) | Self::IntegrityCheck(error) => is_corruption_error(error),
+            Self::Migration(Error::Sqlite(error)) => is_corruption_error(error),
+            _ => false,
+        }
+    }
+
+    /// Whether this error means that the database couldn't be initialized or
+    /// migrated to the current version of the store.
+    ///
+    /// The file might not be corrupted, but the store can't be opened until the
+    /// migration succeeds, which is unlikely to happen by retrying it, so the
+    /// database should be deleted and recreated too.
+    pub fn is_failed_migration(&self) -> bool {
+        matches!(self, Self::Migration(_))
+    }
+}
+
+/// Whether the given SQLite error means that the database file is corrupted.
+fn is_corruption_error(error: &rusqlite::Error) -> bool {
+    matches!(
+        error.sqlite_error_code(),
+        Some(rusqlite::ErrorCode::DatabaseCorrupt | rusqlite::ErrorCode::NotADatabase)
+    )
+}
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error(transparent)]
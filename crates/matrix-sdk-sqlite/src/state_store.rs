@@ -1,19 +1,19 @@
 use std::{
     borrow::Cow,
-    collections::{BTreeMap, BTreeSet},
+    collections::{BTreeMap, BTreeSet, HashMap},
     fmt, iter,
     path::{Path, PathBuf},
-    sync::Arc,
+    sync::{Arc, Mutex as StdMutex},
 };
 
 use async_trait::async_trait;
 use deadpool_sqlite::{Object as SqliteConn, Pool as SqlitePool, Runtime};
 use matrix_sdk_base::{
     deserialized_responses::{RawAnySyncOrStrippedState, SyncOrStrippedState},
-    media::{MediaRequest, UniqueKey},
+    media::{MediaCacheEntry, MediaFormat, MediaRequest, MediaRetentionPolicy, UniqueKey},
     store::migration_helpers::RoomInfoV1,
-    MinimalRoomMemberEvent, RoomInfo, RoomMemberships, RoomState, StateChanges, StateStore,
-    StateStoreDataKey, StateStoreDataValue,
+    MinimalRoomMemberEvent, RoomInfo, RoomMemberPage, RoomMemberQuery, RoomMemberships, RoomState,
+    StateChanges, StateStore, StateStoreDataKey, StateStoreDataValue,
 };
 use matrix_sdk_store_encryption::StoreCipher;
 use ruma::{
@@ -29,17 +29,18 @@ use ruma::{
         GlobalAccountDataEventType, RoomAccountDataEventType, StateEventType,
     },
     serde::Raw,
-    CanonicalJsonObject, EventId, OwnedEventId, OwnedUserId, RoomId, RoomVersionId, UserId,
+    CanonicalJsonObject, EventId, Int, MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedMxcUri,
+    OwnedRoomId, OwnedUserId, RoomId, RoomVersionId, UserId,
 };
-use rusqlite::{OptionalExtension, Transaction};
+use rusqlite::{types::Value, OptionalExtension, Transaction};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
-use tokio::fs;
+use tokio::{fs, io};
 use tracing::{debug, warn};
 
 use crate::{
     error::{Error, Result},
     get_or_create_store_cipher,
-    utils::{load_db_version, repeat_vars, Key, SqliteObjectExt},
+    utils::{check_integrity, load_db_version, repeat_vars, Key, SqliteObjectExt},
     OpenStoreError, SqliteObjectStoreExt,
 };
 
@@ -57,7 +58,11 @@ mod keys {
     pub const MEDIA: &str = "media";
 }
 
-const DATABASE_VERSION: u8 = 3;
+const DATABASE_VERSION: u8 = 5;
+
+const DATABASE_NAME: &str = "matrix-sdk-state.sqlite3";
+const DATABASE_WAL_NAME: &str = "matrix-sdk-state.sqlite3-wal";
+const DATABASE_SHM_NAME: &str = "matrix-sdk-state.sqlite3-shm";
 
 /// A sqlite based cryptostore.
 #[derive(Clone)]
@@ -65,6 +70,40 @@ pub struct SqliteStateStore {
     store_cipher: Option<Arc<StoreCipher>>,
     path: Option<PathBuf>,
     pool: SqlitePool,
+    /// The sorted members of the rooms, when the store is encrypted.
+    sorted_members: Arc<StdMutex<SortedMembersCache>>,
+}
+
+/// The members of the rooms, sorted for the last [`RoomMemberQuery`] of each
+/// room.
+///
+/// The names of the members are encrypted when the store has a passphrase, so
+/// SQLite can't sort them. Instead of decrypting and sorting the members of a
+/// room for every page, they are kept until the members or their profiles
+/// change.
+#[derive(Default)]
+struct SortedMembersCache {
+    /// Incremented whenever rooms are removed from the cache, to avoid caching
+    /// members that were loaded before a change.
+    generation: u64,
+    rooms: HashMap<OwnedRoomId, SortedMembers>,
+}
+
+/// The members of a room, sorted with [`RoomMemberQuery::sort_members()`].
+struct SortedMembers {
+    memberships: RoomMemberships,
+    users_power_levels: BTreeMap<OwnedUserId, Int>,
+    users_default_power_level: Int,
+    members: Arc<Vec<(OwnedUserId, String)>>,
+}
+
+impl SortedMembers {
+    /// Whether these members are sorted for the given query.
+    fn matches(&self, query: &RoomMemberQuery) -> bool {
+        self.memberships == query.memberships
+            && self.users_power_levels == query.users_power_levels
+            && self.users_default_power_level == query.users_default_power_level
+    }
 }
 
 #[cfg(not(tarpaulin_include))]
@@ -99,21 +138,45 @@ impl SqliteStateStore {
         let conn = pool.get().await?;
         let mut version = load_db_version(&conn).await?;
 
+        if version > DATABASE_VERSION {
+            return Err(OpenStoreError::UnsupportedVersion(version));
+        }
+
         if version == 0 {
             init(&conn).await?;
             version = 1;
+        } else {
+            check_integrity(&conn).await?;
         }
 
         let store_cipher = match passphrase {
             Some(p) => Some(Arc::new(get_or_create_store_cipher(p, &conn).await?)),
             None => None,
         };
-        let this = Self { store_cipher, path: None, pool };
+        let this = Self { store_cipher, path: None, pool, sorted_members: Default::default() };
         this.run_migrations(&conn, version, None).await?;
 
         Ok(this)
     }
 
+    /// Delete the files of the sqlite-based state store at the given path.
+    ///
+    /// This can be used to recreate a store that can't be opened because it
+    /// is corrupted, see [`OpenStoreError::is_corruption()`]. The store must
+    /// not be open.
+    pub async fn delete_files(path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+
+        for file_name in [DATABASE_NAME, DATABASE_WAL_NAME, DATABASE_SHM_NAME] {
+            match fs::remove_file(path.join(file_name)).await {
+                Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(error),
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
     /// Run database migrations from the given `from` version to the given `to`
     /// version
     ///
@@ -213,6 +276,75 @@ impl SqliteStateStore {
             .await?;
         }
 
+        // Migration to v4: track when media files are accessed.
+        if from < 4 && to >= 4 {
+            conn.with_transaction(|txn| {
+                txn.execute_batch(include_str!(
+                    "../migrations/state_store/004_media_last_access.sql"
+                ))
+            })
+            .await?;
+        }
+
+        // Migration to v5: store the name of the members.
+        if from < 5 && to >= 5 {
+            let this = self.clone();
+            conn.with_transaction(move |txn| {
+                txn.execute_batch(include_str!("../migrations/state_store/005_member_name.sql"))?;
+
+                let room_infos = txn
+                    .prepare("SELECT data FROM room_info")?
+                    .query_map((), |row| row.get::<_, Vec<u8>>(0))?
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                for data in room_infos {
+                    let room_info: RoomInfo = this.deserialize_json(&data)?;
+                    let room_id = room_info.room_id();
+                    let member_room_id = this.encode_key(keys::MEMBER, room_id);
+                    let profile_room_id = this.encode_key(keys::PROFILE, room_id);
+
+                    let members = txn
+                        .prepare("SELECT user_id, data FROM member WHERE room_id = ?")?
+                        .query_map((&member_room_id,), |row| {
+                            Ok((row.get::<_, Vec<u8>>(0)?, row.get::<_, Vec<u8>>(1)?))
+                        })?
+                        .collect::<Result<Vec<_>, _>>()?;
+
+                    for (encoded_user_id, data) in members {
+                        let user_id: OwnedUserId = this.deserialize_value(&data)?;
+                        let profile = txn
+                            .prepare_cached(
+                                "SELECT data FROM profile WHERE room_id = ? AND user_id = ?",
+                            )?
+                            .query_row(
+                                (&profile_room_id, this.encode_key(keys::PROFILE, &user_id)),
+                                |row| row.get::<_, Vec<u8>>(0),
+                            )
+                            .optional()?
+                            .map(|data| this.deserialize_json::<MinimalRoomMemberEvent>(&data))
+                            .transpose()?;
+                        let display_name = profile
+                            .as_ref()
+                            .and_then(|profile| profile.as_original())
+                            .and_then(|profile| profile.content.displayname.as_deref());
+                        let name = this.encode_member_name(&user_id, display_name)?;
+
+                        txn.prepare_cached(
+                            "UPDATE member SET name = ? WHERE room_id = ? AND user_id = ?",
+                        )?
+                        .execute((
+                            name,
+                            &member_room_id,
+                            encoded_user_id,
+                        ))?;
+                    }
+                }
+
+                Result::<_, Error>::Ok(())
+            })
+            .await?;
+        }
+
         conn.set_kv("version", vec![to]).await?;
 
         Ok(())
@@ -257,6 +389,29 @@ impl SqliteStateStore {
         Ok(rmp_serde::from_slice(&decoded)?)
     }
 
+    /// Encode the [sort name](RoomMemberQuery::sort_name) of a member.
+    ///
+    /// The name is kept as plain text when the store is not encrypted, so the
+    /// members can be sorted and searched in SQL.
+    fn encode_member_name(&self, user_id: &UserId, display_name: Option<&str>) -> Result<Vec<u8>> {
+        let name = RoomMemberQuery::sort_name(user_id, display_name);
+        if self.store_cipher.is_some() {
+            self.serialize_value(&name)
+        } else {
+            Ok(name.into_bytes())
+        }
+    }
+
+    /// Forget the sorted members of the given rooms, because their members or
+    /// their profiles changed.
+    fn invalidate_sorted_members<'a>(&self, room_ids: impl IntoIterator<Item = &'a RoomId>) {
+        let mut cache = self.sorted_members.lock().unwrap();
+        cache.generation += 1;
+        for room_id in room_ids {
+            cache.rooms.remove(room_id);
+        }
+    }
+
     fn encode_key(&self, table_name: &str, key: impl AsRef<[u8]>) -> Key {
         let bytes = key.as_ref();
         if let Some(store_cipher) = &self.store_cipher {
@@ -281,6 +436,13 @@ impl SqliteStateStore {
             StateStoreDataKey::UtdHookManagerData => {
                 Cow::Borrowed(StateStoreDataKey::UTD_HOOK_MANAGER_DATA)
             }
+            StateStoreDataKey::ComposerDraft(room_id) => {
+                Cow::Owned(format!("{}:{room_id}", StateStoreDataKey::COMPOSER_DRAFT))
+            }
+            StateStoreDataKey::UrlPreviewsInEncryptedRoom(room_id) => Cow::Owned(format!(
+                "{}:{room_id}",
+                StateStoreDataKey::URL_PREVIEWS_IN_ENCRYPTED_ROOM
+            )),
         };
 
         self.encode_key(keys::KV_BLOB, &*key_s)
@@ -310,13 +472,15 @@ impl SqliteStateStore {
         txn.remove_room_state_events(&state_event_room_id, Some(stripped))?;
 
         let member_room_id = self.encode_key(keys::MEMBER, room_id);
-        txn.remove_room_members(&member_room_id, Some(stripped))
+        txn.remove_room_members(&member_room_id, Some(stripped))?;
+
+        Ok(())
     }
 }
 
 async fn create_pool(path: &Path) -> Result<SqlitePool, OpenStoreError> {
     fs::create_dir_all(path).await.map_err(OpenStoreError::CreateDir)?;
-    let cfg = deadpool_sqlite::Config::new(path.join("matrix-sdk-state.sqlite3"));
+    let cfg = deadpool_sqlite::Config::new(path.join(DATABASE_NAME));
     Ok(cfg.create_pool(Runtime::Tokio1)?)
 }
 
@@ -378,6 +542,7 @@ trait SqliteConnectionStateStoreExt {
         user_id: &[u8],
         membership: &[u8],
         stripped: bool,
+        name: &[u8],
         data: &[u8],
     ) -> rusqlite::Result<()>;
     fn remove_room_members(&self, room_id: &[u8], stripped: Option<bool>) -> rusqlite::Result<()>;
@@ -517,14 +682,15 @@ impl SqliteConnectionStateStoreExt for rusqlite::Connection {
         user_id: &[u8],
         membership: &[u8],
         stripped: bool,
+        name: &[u8],
         data: &[u8],
     ) -> rusqlite::Result<()> {
         self.prepare_cached(
             "INSERT OR REPLACE
-             INTO member (room_id, user_id, membership, stripped, data)
-             VALUES (?, ?, ?, ?, ?)",
+             INTO member (room_id, user_id, membership, stripped, name, data)
+             VALUES (?, ?, ?, ?, ?, ?)",
         )?
-        .execute((room_id, user_id, membership, stripped, data))?;
+        .execute((room_id, user_id, membership, stripped, name, data))?;
         Ok(())
     }
 
@@ -752,6 +918,87 @@ trait SqliteObjectStateStoreExt: SqliteObjectExt {
         Ok(res)
     }
 
+    /// Get the user IDs and the names of the members of a room with the given
+    /// memberships.
+    async fn get_member_names(
+        &self,
+        room_id: Key,
+        memberships: Vec<Key>,
+    ) -> Result<Vec<(Vec<u8>, Option<Vec<u8>>)>> {
+        let (filter, params) = member_filter(room_id, memberships);
+        let sql = format!("SELECT data, name FROM member WHERE {filter}");
+        let params = rusqlite::params_from_iter(params);
+
+        Ok(self
+            .prepare(sql, move |mut stmt| {
+                stmt.query(params)?.mapped(|row| Ok((row.get(0)?, row.get(1)?))).collect()
+            })
+            .await?)
+    }
+
+    /// Get a page of the members of a room and the total number of members
+    /// matching the filter, sorted by power level then by name.
+    ///
+    /// This only works when the names of the members are not encrypted.
+    #[allow(clippy::too_many_arguments)]
+    async fn get_member_page(
+        &self,
+        room_id: Key,
+        memberships: Vec<Key>,
+        search: Option<String>,
+        users_power_levels: Vec<(Key, i64)>,
+        users_default_power_level: i64,
+        offset: i64,
+        limit: i64,
+    ) -> Result<(Vec<Vec<u8>>, usize)> {
+        let (mut filter, mut filter_params) = member_filter(room_id, memberships);
+        if let Some(search) = search {
+            filter.push_str(
+                " AND (instr(CAST(name AS TEXT), ?) > 0 \
+                 OR instr(lower(CAST(user_id AS TEXT)), ?) > 0)",
+            );
+            filter_params.extend([Value::Text(search.clone()), Value::Text(search)]);
+        }
+
+        // The few users with a power level that is not the default one are sorted
+        // first or last.
+        let mut order = String::new();
+        let mut order_params = Vec::new();
+        if !users_power_levels.is_empty() {
+            order.push_str("CASE user_id ");
+            for (user_id, power_level) in users_power_levels {
+                order.push_str("WHEN ? THEN ? ");
+                order_params.push(Value::Blob(user_id.to_vec()));
+                order_params.push(Value::Integer(power_level));
+            }
+            order.push_str("ELSE ? END DESC, ");
+            order_params.push(Value::Integer(users_default_power_level));
+        }
+
+        let total = self
+            .query_row(
+                format!("SELECT COUNT(*) FROM member WHERE {filter}"),
+                rusqlite::params_from_iter(filter_params.clone()),
+                |row| row.get(0),
+            )
+            .await?;
+
+        let sql = format!(
+            "SELECT data FROM member WHERE {filter} ORDER BY {order}name, user_id LIMIT ? OFFSET ?"
+        );
+        let params = rusqlite::params_from_iter(
+            filter_params
+                .into_iter()
+                .chain(order_params)
+                .chain([Value::Integer(limit), Value::Integer(offset)]),
+        );
+        let user_ids = self
+            .prepare(sql, move |mut stmt| stmt.query(params)?.mapped(|row| row.get(0)).collect())
+            .await?;
+
+        Ok((user_ids, total))
+    }
+
     async fn get_global_account_data(&self, event_type: Key) -> Result<Option<Vec<u8>>> {
         Ok(self
             .query_row(
@@ -838,24 +1085,65 @@ trait SqliteObjectStateStoreExt: SqliteObjectExt {
             .await?)
     }
 
-    async fn set_media(&self, uri: Key, format: Key, data: Vec<u8>) -> Result<()> {
+    async fn set_media(
+        &self,
+        uri: Key,
+        format: Key,
+        data: Vec<u8>,
+        last_access: i64,
+    ) -> Result<()> {
         self.execute(
-            "INSERT OR REPLACE INTO media (uri, format, data) VALUES (?, ?, ?)",
-            (uri, format, data),
+            "INSERT OR REPLACE INTO media (uri, format, data, last_access) VALUES (?, ?, ?, ?)",
+            (uri, format, data, last_access),
         )
         .await?;
         Ok(())
     }
 
-    async fn get_media(&self, uri: Key, format: Key) -> Result<Option<Vec<u8>>> {
+    async fn get_media(&self, uri: Key, format: Key, last_access: i64) -> Result<Option<Vec<u8>>> {
         Ok(self
-            .query_row(
-                "SELECT data FROM media WHERE uri = ? AND format = ?",
-                (uri, format),
-                |row| row.get(0),
-            )
-            .await
-            .optional()?)
+            .with_transaction(move |txn| {
+                txn.execute(
+                    "UPDATE media SET last_access = ? WHERE uri = ? AND format = ?",
+                    (last_access, &uri, &format),
+                )?;
+                txn.query_row(
+                    "SELECT data FROM media WHERE uri = ? AND format = ?",
+                    (uri, format),
+                    |row| row.get(0),
+                )
+                .optional()
+            })
+            .await?)
+    }
+
+    async fn get_media_size(&self) -> Result<usize> {
+        let size: i64 = self
+            .query_row("SELECT COALESCE(SUM(LENGTH(data)), 0) FROM media", (), |row| row.get(0))
+            .await?;
+        Ok(size.try_into().unwrap_or_default())
+    }
+
+    async fn get_media_entries(&self) -> Result<Vec<(Vec<u8>, Vec<u8>, i64, i64)>> {
+        Ok(self
+            .prepare("SELECT uri, format, LENGTH(data), last_access FROM media", |mut stmt| {
+                stmt.query(())?
+                    .mapped(|row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)))
+                    .collect()
+            })
+            .await?)
+    }
+
+    async fn remove_medias(&self, keys: Vec<(Vec<u8>, Vec<u8>)>) -> Result<()> {
+        self.with_transaction(move |txn| {
+            let mut stmt = txn.prepare("DELETE FROM media WHERE uri = ? AND format = ?")?;
+            for (uri, format) in keys {
+                stmt.execute((uri, format))?;
+            }
+            Result::<_, rusqlite::Error>::Ok(())
+        })
+        .await?;
+        Ok(())
     }
 
     async fn remove_media(&self, uri: Key, format: Key) -> Result<()> {
@@ -869,6 +1157,18 @@ trait SqliteObjectStateStoreExt: SqliteObjectExt {
     }
 }
 
+/// The SQL filter and its parameters to select the members of a room with the
+/// given memberships.
+fn member_filter(room_id: Key, memberships: Vec<Key>) -> (String, Vec<Value>) {
+    let mut filter = "room_id = ?".to_owned();
+    let mut params = vec![Value::Blob(room_id.to_vec())];
+    if !memberships.is_empty() {
+        filter.push_str(&format!(" AND membership IN ({})", repeat_vars(memberships.len())));
+        params.extend(memberships.iter().map(|membership| Value::Blob(membership.to_vec())));
+    }
+    (filter, params)
+}
+
 #[async_trait]
 impl SqliteObjectStateStoreExt for deadpool_sqlite::Object {
     async fn set_kv_blob(&self, key: Key, value: Vec<u8>) -> Result<()> {
@@ -902,6 +1202,14 @@ impl StateStore for SqliteStateStore {
                     StateStoreDataKey::UtdHookManagerData => {
                         StateStoreDataValue::UtdHookManagerData(self.deserialize_value(&data)?)
                     }
+                    StateStoreDataKey::ComposerDraft(_) => {
+                        StateStoreDataValue::ComposerDraft(self.deserialize_value(&data)?)
+                    }
+                    StateStoreDataKey::UrlPreviewsInEncryptedRoom(_) => {
+                        StateStoreDataValue::UrlPreviewsInEncryptedRoom(
+                            self.deserialize_value(&data)?,
+                        )
+                    }
                 })
             })
             .transpose()
@@ -928,6 +1236,14 @@ impl StateStore for SqliteStateStore {
             StateStoreDataKey::UtdHookManagerData => self.serialize_value(
                 &value.into_utd_hook_manager_data().expect("Session data not UtdHookManagerData"),
             )?,
+            StateStoreDataKey::ComposerDraft(_) => self.serialize_value(
+                &value.into_composer_draft().expect("Session data not a composer draft"),
+            )?,
+            StateStoreDataKey::UrlPreviewsInEncryptedRoom(_) => self.serialize_value(
+                &value
+                    .into_url_previews_in_encrypted_room()
+                    .expect("Session data not a URL previews setting"),
+            )?,
         };
 
         self.acquire()
@@ -941,6 +1257,24 @@ impl StateStore for SqliteStateStore {
     }
 
     async fn save_changes(&self, changes: &StateChanges) -> Result<()> {
+        let member_room_ids: BTreeSet<OwnedRoomId> = changes
+            .state
+            .iter()
+            .filter(|(_, events)| events.contains_key(&StateEventType::RoomMember))
+            .map(|(room_id, _)| room_id)
+            .chain(
+                changes
+                    .stripped_state
+                    .iter()
+                    .filter(|(_, events)| events.contains_key(&StateEventType::RoomMember))
+                    .map(|(room_id, _)| room_id),
+            )
+            .chain(changes.profiles.keys())
+            .chain(changes.profiles_to_delete.keys())
+            .chain(changes.redactions.keys())
+            .cloned()
+            .collect();
+
         let changes = changes.to_owned();
         let this = self.clone();
         self.acquire()
@@ -1051,6 +1385,12 @@ impl StateStore for SqliteStateStore {
                                 let user_id = this.encode_key(keys::MEMBER, &state_key);
                                 let membership = this
                                     .encode_key(keys::MEMBER, member_event.membership().as_str());
+                                let name = this.encode_member_name(
+                                    member_event.state_key(),
+                                    member_event
+                                        .as_original()
+                                        .and_then(|ev| ev.content.displayname.as_deref()),
+                                )?;
                                 let data = this.serialize_value(&state_key)?;
 
                                 txn.set_member(
@@ -1058,6 +1398,7 @@ impl StateStore for SqliteStateStore {
                                     &user_id,
                                     &membership,
                                     false,
+                                    &name,
                                     &data,
                                 )?;
 
@@ -1110,9 +1451,20 @@ impl StateStore for SqliteStateStore {
                                     keys::MEMBER,
                                     member_event.content.membership.as_str(),
                                 );
+                                let name = this.encode_member_name(
+                                    &member_event.state_key,
+                                    member_event.content.displayname.as_deref(),
+                                )?;
                                 let data = this.serialize_value(&state_key)?;
 
-                                txn.set_member(&room_id, &user_id, &membership, true, &data)?;
+                                txn.set_member(
+                                    &room_id,
+                                    &user_id,
+                                    &membership,
+                                    true,
+                                    &name,
+                                    &data,
+                                )?;
                             }
                         }
                     }
@@ -1226,6 +1578,8 @@ impl StateStore for SqliteStateStore {
             })
             .await?;
 
+        self.invalidate_sorted_members(member_room_ids.iter().map(AsRef::as_ref));
+
         Ok(())
     }
 
@@ -1401,6 +1755,91 @@ impl StateStore for SqliteStateStore {
         self.get_user_ids(room_id, RoomMemberships::JOIN).await
     }
 
+    async fn get_room_member_page(
+        &self,
+        room_id: &RoomId,
+        query: &RoomMemberQuery,
+    ) -> Result<RoomMemberPage> {
+        let encoded_room_id = self.encode_key(keys::MEMBER, room_id);
+        let memberships = query
+            .memberships
+            .as_vec()
+            .into_iter()
+            .map(|m| self.encode_key(keys::MEMBER, m.as_str()))
+            .collect();
+
+        // The names of the members are encrypted, so they are sorted in memory, and
+        // kept sorted until the members of the room change.
+        if self.store_cipher.is_some() {
+            let generation = {
+                let cache = self.sorted_members.lock().unwrap();
+                if let Some(sorted) = cache.rooms.get(room_id).filter(|s| s.matches(query)) {
+                    return Ok(query.select_page_from_sorted(&sorted.members));
+                }
+                cache.generation
+            };
+
+            let members = self
+                .acquire()
+                .await?
+                .get_member_names(encoded_room_id, memberships)
+                .await?
+                .into_iter()
+                .map(|(data, name)| {
+                    let user_id: OwnedUserId = self.deserialize_value(&data)?;
+                    let name = match name {
+                        Some(name) => self.deserialize_value(&name)?,
+                        None => RoomMemberQuery::sort_name(&user_id, None),
+                    };
+                    Ok((user_id, name))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let members = Arc::new(query.sort_members(members));
+            let page = query.select_page_from_sorted(&members);
+
+            let mut cache = self.sorted_members.lock().unwrap();
+            if cache.generation == generation {
+                cache.rooms.insert(
+                    room_id.to_owned(),
+                    SortedMembers {
+                        memberships: query.memberships,
+                        users_power_levels: query.users_power_levels.clone(),
+                        users_default_power_level: query.users_default_power_level,
+                        members,
+                    },
+                );
+            }
+
+            return Ok(page);
+        }
+
+        let users_power_levels = query
+            .users_power_levels
+            .iter()
+            .map(|(user_id, power_level)| {
+                (self.encode_key(keys::MEMBER, user_id), (*power_level).into())
+            })
+            .collect();
+
+        let (user_ids, total) = self
+            .acquire()
+            .await?
+            .get_member_page(
+                encoded_room_id,
+                memberships,
+                query.search_term(),
+                users_power_levels,
+                query.users_default_power_level.into(),
+                query.offset.try_into().unwrap_or(i64::MAX),
+                query.limit.try_into().unwrap_or(i64::MAX),
+            )
+            .await?;
+        let user_ids =
+            user_ids.iter().map(|data| self.deserialize_value(data)).collect::<Result<_>>()?;
+
+        Ok(RoomMemberPage { user_ids, total })
+    }
+
     async fn get_room_infos(&self) -> Result<Vec<RoomInfo>> {
         self.acquire()
             .await?
@@ -1585,13 +2024,15 @@ impl StateStore for SqliteStateStore {
         let uri = self.encode_key(keys::MEDIA, request.source.unique_key());
         let format = self.encode_key(keys::MEDIA, request.format.unique_key());
         let data = self.encode_value(content)?;
-        self.acquire().await?.set_media(uri, format, data).await
+        let last_access = MilliSecondsSinceUnixEpoch::now().get().into();
+        self.acquire().await?.set_media(uri, format, data, last_access).await
     }
 
     async fn get_media_content(&self, request: &MediaRequest) -> Result<Option<Vec<u8>>> {
         let uri = self.encode_key(keys::MEDIA, request.source.unique_key());
         let format = self.encode_key(keys::MEDIA, request.format.unique_key());
-        let data = self.acquire().await?.get_media(uri, format).await?;
+        let last_access = MilliSecondsSinceUnixEpoch::now().get().into();
+        let data = self.acquire().await?.get_media(uri, format, last_access).await?;
         data.map(|v| self.decode_value(&v).map(Into::into)).transpose()
     }
 
@@ -1606,13 +2047,53 @@ impl StateStore for SqliteStateStore {
         self.acquire().await?.remove_uri_medias(uri).await
     }
 
+    async fn media_cache_size(&self) -> Result<usize> {
+        self.acquire().await?.get_media_size().await
+    }
+
+    async fn clean_up_media_cache(
+        &self,
+        policy: MediaRetentionPolicy,
+        protected_uris: &[OwnedMxcUri],
+        now: MilliSecondsSinceUnixEpoch,
+    ) -> Result<()> {
+        let protected_uris = protected_uris
+            .iter()
+            .map(|uri| self.encode_key(keys::MEDIA, uri.as_str()).to_vec())
+            .collect::<BTreeSet<_>>();
+        let file_format = self.encode_key(keys::MEDIA, MediaFormat::File.unique_key()).to_vec();
+
+        let conn = self.acquire().await?;
+        let entries = conn
+            .get_media_entries()
+            .await?
+            .into_iter()
+            .map(|(uri, format, size, last_access)| MediaCacheEntry {
+                is_protected: protected_uris.contains(&uri),
+                is_thumbnail: format != file_format,
+                key: (uri, format),
+                size: size.try_into().unwrap_or_default(),
+                last_access: MilliSecondsSinceUnixEpoch(last_access.try_into().unwrap_or_default()),
+            })
+            .collect();
+
+        let evicted = policy.select_evicted(entries, now);
+        if evicted.is_empty() {
+            return Ok(());
+        }
+
+        conn.remove_medias(evicted).await
+    }
+
     async fn remove_room(&self, room_id: &RoomId) -> Result<()> {
         let this = self.clone();
-        let room_id = room_id.to_owned();
+        let owned_room_id = room_id.to_owned();
 
         self.acquire()
             .await?
             .with_transaction(move |txn| {
+                let room_id = owned_room_id;
+
                 let room_info_room_id = this.encode_key(keys::ROOM_INFO, &room_id);
                 txn.remove_room_info(&room_info_room_id)?;
 
@@ -1634,9 +2115,13 @@ impl StateStore for SqliteStateStore {
                 let display_name_room_id = this.encode_key(keys::DISPLAY_NAME, &room_id);
                 txn.remove_room_display_names(&display_name_room_id)?;
 
-                Ok(())
+                Ok::<_, Error>(())
             })
-            .await
+            .await?;
+
+        self.invalidate_sorted_members([room_id]);
+
+        Ok(())
     }
 }
 
@@ -1703,24 +2188,36 @@ mod migration_tests {
             atomic::{AtomicU32, Ordering::SeqCst},
             Arc,
         },
+        time::Duration,
     };
 
-    use matrix_sdk_base::{sync::UnreadNotificationsCount, RoomState, StateStore};
+    use assert_matches::assert_matches;
+    use matrix_sdk_base::{
+        media::{MediaFormat, MediaRequest, MediaRetentionPolicy, UniqueKey},
+        store::{StateStoreDataKey, StateStoreDataValue},
+        sync::UnreadNotificationsCount,
+        RoomInfo, RoomMemberQuery, RoomMemberships, RoomState, StateStore,
+    };
     use matrix_sdk_test::async_test;
     use once_cell::sync::Lazy;
     use ruma::{
-        events::{room::create::RoomCreateEventContent, StateEventType},
-        room_id, server_name, user_id, EventId, MilliSecondsSinceUnixEpoch, RoomId, UserId,
+        events::{
+            room::{create::RoomCreateEventContent, MediaSource},
+            StateEventType,
+        },
+        int, mxc_uri, room_id, server_name, user_id, EventId, MilliSecondsSinceUnixEpoch, RoomId,
+        UserId,
     };
     use rusqlite::Transaction;
     use serde_json::json;
     use tempfile::{tempdir, TempDir};
 
-    use super::{create_pool, init, keys, SqliteStateStore};
+    use super::{create_pool, init, keys, SqliteStateStore, DATABASE_NAME, DATABASE_VERSION};
     use crate::{
         error::{Error, Result},
         get_or_create_store_cipher,
-        utils::SqliteObjectExt,
+        utils::{SqliteObjectExt, SqliteObjectStoreExt},
+        OpenStoreError,
     };
 
     static TMP_DIR: Lazy<TempDir> = Lazy::new(|| tempdir().unwrap());
@@ -1739,7 +2236,8 @@ mod migration_tests {
         init(&conn).await?;
 
         let store_cipher = Some(Arc::new(get_or_create_store_cipher(SECRET, &conn).await.unwrap()));
-        let this = SqliteStateStore { store_cipher, path: None, pool };
+        let this =
+            SqliteStateStore { store_cipher, path: None, pool, sorted_members: Default::default() };
         this.run_migrations(&conn, 1, Some(version)).await?;
 
         Ok(this)
@@ -1961,4 +2459,167 @@ mod migration_tests {
         assert_eq!(room_c.name(), None);
         assert_eq!(room_c.creator(), Some(room_c_create_sender));
     }
+
+    #[async_test]
+    pub async fn test_migrating_v3_to_v4() {
+        let path = new_path();
+
+        let request = MediaRequest {
+            source: MediaSource::Plain(mxc_uri!("mxc://localhost/media").to_owned()),
+            format: MediaFormat::File,
+        };
+
+        // Create and populate db with a media file without a last access time.
+        {
+            let db = create_fake_db(&path, 3).await.unwrap();
+            let conn = db.pool.get().await.unwrap();
+
+            let uri = db.encode_key(keys::MEDIA, request.source.unique_key());
+            let format = db.encode_key(keys::MEDIA, request.format.unique_key());
+            let data = db.encode_value(b"media".to_vec()).unwrap();
+            conn.execute(
+                "INSERT INTO media (uri, format, data) VALUES (?, ?, ?)",
+                (uri, format, data),
+            )
+            .await
+            .unwrap();
+        }
+
+        // This transparently migrates to the latest version.
+        let store = SqliteStateStore::open(path, Some(SECRET)).await.unwrap();
+
+        // The media file was accessed during the migration, so it hasn't expired.
+        let policy =
+            MediaRetentionPolicy::empty().with_last_access_expiry(Some(Duration::from_secs(60)));
+        store.clean_up_media_cache(policy, &[], MilliSecondsSinceUnixEpoch::now()).await.unwrap();
+        assert_eq!(
+            store.get_media_content(&request).await.unwrap().as_deref(),
+            Some(&b"media"[..])
+        );
+    }
+
+    #[async_test]
+    pub async fn test_migrating_v4_to_v5() {
+        let path = new_path();
+        let room_id = room_id!("!room:dummy.local");
+        let alice = user_id!("@alice:dummy.local");
+        let bob = user_id!("@bob:dummy.local");
+
+        // Create and populate db with members without a name.
+        {
+            let db = create_fake_db(&path, 4).await.unwrap();
+            let conn = db.pool.get().await.unwrap();
+
+            let room_info = RoomInfo::new(room_id, RoomState::Joined);
+            let encoded_room_id = db.encode_key(keys::ROOM_INFO, room_id);
+            let encoded_state =
+                db.encode_key(keys::ROOM_INFO, serde_json::to_string(&RoomState::Joined).unwrap());
+            let data = db.serialize_json(&room_info).unwrap();
+            conn.execute(
+                "INSERT INTO room_info (room_id, state, data)
+                 VALUES (?, ?, ?)",
+                (encoded_room_id, encoded_state, data),
+            )
+            .await
+            .unwrap();
+
+            for user_id in [bob, alice] {
+                let encoded_room_id = db.encode_key(keys::MEMBER, room_id);
+                let encoded_user_id = db.encode_key(keys::MEMBER, user_id);
+                let membership = db.encode_key(keys::MEMBER, "join");
+                let data = db.serialize_value(&user_id).unwrap();
+                conn.execute(
+                    "INSERT INTO member (room_id, user_id, membership, stripped, data)
+                     VALUES (?, ?, ?, ?, ?)",
+                    (encoded_room_id, encoded_user_id, membership, false, data),
+                )
+                .await
+                .unwrap();
+            }
+
+            // Bob has a display name that is sorted before the localpart of Alice.
+            let profile = json!({
+                "Original": {
+                    "content": {
+                        "membership": "join",
+                        "displayname": "Aaron",
+                    },
+                },
+            });
+            let encoded_room_id = db.encode_key(keys::PROFILE, room_id);
+            let encoded_user_id = db.encode_key(keys::PROFILE, bob);
+            let data = db.serialize_json(&profile).unwrap();
+            conn.execute(
+                "INSERT INTO profile (room_id, user_id, data)
+                 VALUES (?, ?, ?)",
+                (encoded_room_id, encoded_user_id, data),
+            )
+            .await
+            .unwrap();
+        }
+
+        // This transparently migrates to the latest version.
+        let store = SqliteStateStore::open(path, Some(SECRET)).await.unwrap();
+
+        // The names of the members were computed from their profiles.
+        let mut query = RoomMemberQuery {
+            memberships: RoomMemberships::JOIN,
+            search: None,
+            users_power_levels: Default::default(),
+            users_default_power_level: int!(0),
+            offset: 0,
+            limit: 10,
+        };
+        let page = store.get_room_member_page(room_id, &query).await.unwrap();
+        assert_eq!(page.user_ids, [bob, alice]);
+        assert_eq!(page.total, 2);
+
+        query.search = Some("aar".to_owned());
+        let page = store.get_room_member_page(room_id, &query).await.unwrap();
+        assert_eq!(page.user_ids, [bob]);
+        assert_eq!(page.total, 1);
+    }
+
+    #[async_test]
+    pub async fn test_unsupported_version() {
+        let path = new_path();
+        {
+            let db = create_fake_db(&path, DATABASE_VERSION).await.unwrap();
+            let conn = db.pool.get().await.unwrap();
+            conn.set_kv("version", vec![DATABASE_VERSION + 1]).await.unwrap();
+        }
+
+        let error = SqliteStateStore::open(&path, Some(SECRET)).await.unwrap_err();
+        assert_matches!(error, OpenStoreError::UnsupportedVersion(v) if v == DATABASE_VERSION + 1);
+        // The database may have been created by a newer version of the store, so it
+        // must not be deleted.
+        assert!(!error.is_corruption());
+    }
+
+    #[async_test]
+    pub async fn test_corrupted_database_is_recreated() {
+        let path = new_path();
+        {
+            let store = SqliteStateStore::open(&path, None).await.unwrap();
+            store
+                .set_kv_data(
+                    StateStoreDataKey::SyncToken,
+                    StateStoreDataValue::SyncToken("t1".to_owned()),
+                )
+                .await
+                .unwrap();
+        }
+
+        // Overwrite the database with garbage.
+        std::fs::write(path.join(DATABASE_NAME), vec![0xAB; 4096]).unwrap();
+
+        let error = SqliteStateStore::open(&path, None).await.unwrap_err();
+        assert!(error.is_corruption(), "unexpected error: {error:?}");
+
+        SqliteStateStore::delete_files(&path).await.unwrap();
+
+        // The recreated store is empty.
+        let store = SqliteStateStore::open(&path, None).await.unwrap();
+        assert!(store.get_kv_data(StateStoreDataKey::SyncToken).await.unwrap().is_none());
+    }
 }
@@ -277,6 +277,25 @@ pub(crate) async fn load_db_version(conn: &deadpool_sqlite::Object) -> Result<u8
     }
 }
 
+/// Run the integrity check of the database.
+///
+/// Returns [`OpenStoreError::Corrupted`] with the problems that were found if
+/// the check fails.
+pub(crate) async fn check_integrity(conn: &deadpool_sqlite::Object) -> Result<(), OpenStoreError> {
+    let problems = conn
+        .prepare("PRAGMA integrity_check", |mut stmt| {
+            stmt.query_map((), |row| row.get::<_, String>(0))?.collect::<rusqlite::Result<Vec<_>>>()
+        })
+        .await
+        .map_err(OpenStoreError::IntegrityCheck)?;
+
+    if problems.iter().all(|problem| problem == "ok") {
+        Ok(())
+    } else {
+        Err(OpenStoreError::Corrupted(problems))
+    }
+}
+
 /// Repeat `?` n times, where n is defined by `count`. `?` are comma-separated.
 pub(crate) fn repeat_vars(count: usize) -> impl fmt::Display {
     assert_ne!(count, 0, "Can't generate zero repeated vars");
@@ -0,0 +1,162 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Round trips of store archives between the memory stores and the SQLite
+//! stores.
+
+#![cfg(all(feature = "crypto-store", feature = "state-store"))]
+
+use std::sync::Arc;
+
+use matrix_sdk_base::{
+    store::{
+        DynStateStore, IntoStateStore, MemoryStore, StateStore, StateStoreIntegrationTests,
+        StoreArchive,
+    },
+    RoomMemberships,
+};
+use matrix_sdk_crypto::{
+    olm::Account,
+    store::{
+        Changes, CryptoStore, DeviceChanges, DynCryptoStore, IntoCryptoStore,
+        MemoryStore as MemoryCryptoStore, PendingChanges,
+    },
+    ReadOnlyDevice,
+};
+use matrix_sdk_sqlite::{SqliteCryptoStore, SqliteStateStore};
+use matrix_sdk_test::async_test;
+use ruma::{device_id, room_id, user_id, RoomId};
+use tempfile::tempdir;
+
+fn room_id() -> &'static RoomId {
+    room_id!("!test:localhost")
+}
+
+/// Populate the given stores, and return the account saved in the crypto
+/// store and the ID of the inbound group session it contains.
+async fn populate(crypto_store: &DynCryptoStore, state_store: &DynStateStore) -> (Account, String) {
+    let account = Account::with_device_id(user_id!("@example:localhost"), device_id!("DEVICE"));
+    let bob = Account::with_device_id(user_id!("@bob:localhost"), device_id!("BOBDEVICE"));
+
+    let (_, inbound) = account.create_group_session_pair_with_defaults(room_id()).await;
+    let session_id = inbound.session_id().to_owned();
+
+    crypto_store
+        .save_pending_changes(PendingChanges { account: Some(account.deep_clone()) })
+        .await
+        .unwrap();
+    crypto_store
+        .save_changes(Changes {
+            inbound_group_sessions: vec![inbound],
+            devices: DeviceChanges {
+                new: vec![ReadOnlyDevice::from_account(&bob)],
+                ..Default::default()
+            },
+            next_batch_token: Some("s1234".to_owned()),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    crypto_store.save_tracked_users(&[(bob.user_id(), false)]).await.unwrap();
+
+    state_store.populate().await.unwrap();
+
+    (account, session_id)
+}
+
+/// Check that the given stores contain the data saved by [`populate`].
+async fn assert_populated(
+    crypto_store: &DynCryptoStore,
+    state_store: &DynStateStore,
+    account: &Account,
+    session_id: &str,
+) {
+    let imported_account = crypto_store.load_account().await.unwrap().unwrap();
+    assert_eq!(imported_account.identity_keys(), account.identity_keys());
+
+    assert!(crypto_store.get_inbound_group_session(room_id(), session_id).await.unwrap().is_some());
+    assert!(crypto_store
+        .get_device(user_id!("@bob:localhost"), device_id!("BOBDEVICE"))
+        .await
+        .unwrap()
+        .is_some());
+    assert_eq!(crypto_store.load_tracked_users().await.unwrap().len(), 1);
+    assert_eq!(crypto_store.next_batch_token().await.unwrap().as_deref(), Some("s1234"));
+
+    assert_eq!(state_store.get_room_infos().await.unwrap().len(), 2);
+    assert_eq!(
+        state_store.get_user_ids(room_id(), RoomMemberships::empty()).await.unwrap().len(),
+        2
+    );
+}
+
+#[async_test]
+async fn test_memory_to_sqlite() {
+    let crypto_store = MemoryCryptoStore::new().into_crypto_store();
+    let state_store = MemoryStore::new().into_state_store();
+    let (account, session_id) = populate(&crypto_store, &state_store).await;
+
+    let archive = StoreArchive::export(&crypto_store, Some(&state_store)).await.unwrap();
+
+    let dir = tempdir().unwrap();
+    let sqlite_crypto_store: Arc<DynCryptoStore> =
+        SqliteCryptoStore::open(dir.path(), Some("passphrase")).await.unwrap().into_crypto_store();
+    let sqlite_state_store: Arc<DynStateStore> =
+        SqliteStateStore::open(dir.path(), Some("passphrase")).await.unwrap().into_state_store();
+
+    archive.import_into(&sqlite_crypto_store, Some(&sqlite_state_store)).await.unwrap();
+
+    assert_populated(&sqlite_crypto_store, &sqlite_state_store, &account, &session_id).await;
+}
+
+#[async_test]
+async fn test_sqlite_to_memory() {
+    let dir = tempdir().unwrap();
+    let crypto_store = SqliteCryptoStore::open(dir.path(), None).await.unwrap().into_crypto_store();
+    let state_store = SqliteStateStore::open(dir.path(), None).await.unwrap().into_state_store();
+    let (account, session_id) = populate(&crypto_store, &state_store).await;
+
+    let archive = StoreArchive::export(&crypto_store, Some(&state_store)).await.unwrap();
+
+    let memory_crypto_store = MemoryCryptoStore::new().into_crypto_store();
+    let memory_state_store = MemoryStore::new().into_state_store();
+
+    archive.import_into(&memory_crypto_store, Some(&memory_state_store)).await.unwrap();
+
+    assert_populated(&memory_crypto_store, &memory_state_store, &account, &session_id).await;
+}
+
+#[async_test]
+async fn test_crypto_only_archive() {
+    let crypto_store = MemoryCryptoStore::new().into_crypto_store();
+    let state_store = MemoryStore::new().into_state_store();
+    let (account, session_id) = populate(&crypto_store, &state_store).await;
+
+    let archive = StoreArchive::export(&crypto_store, None).await.unwrap();
+    assert!(archive.state().is_none());
+
+    let dir = tempdir().unwrap();
+    let sqlite_crypto_store = SqliteCryptoStore::open(dir.path(), None).await.unwrap();
+    let sqlite_crypto_store = sqlite_crypto_store.into_crypto_store();
+
+    archive.import_into(&sqlite_crypto_store, None).await.unwrap();
+
+    let imported_account = sqlite_crypto_store.load_account().await.unwrap().unwrap();
+    assert_eq!(imported_account.identity_keys(), account.identity_keys());
+    assert!(sqlite_crypto_store
+        .get_inbound_group_session(room_id(), &session_id)
+        .await
+        .unwrap()
+        .is_some());
+}
@@ -17,6 +17,7 @@ use ruma::html::HtmlSanitizerMode;
 mod events;
 
 pub mod encryption_sync_service;
+pub mod media_gallery;
 pub mod notification_client;
 pub mod room_list_service;
 pub mod sync_service;
@@ -0,0 +1,346 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A gallery of the media shared in a room.
+//!
+//! The [`MediaGallery`] paginates the history of a room, keeping only the
+//! image, video, audio and file messages, grouped by month. It's meant to back
+//! a "Files & media" view of a room, without having to paginate and filter a
+//! whole [`Timeline`](crate::Timeline).
+
+use chrono::{Datelike, Local, TimeZone};
+use eyeball_im::{ObservableVector, VectorDiff};
+use futures_core::Stream;
+use imbl::Vector;
+use matrix_sdk::{
+    deserialized_responses::TimelineEvent,
+    media::MediaEventContent,
+    room::{MessagesOptions, Room},
+    Result,
+};
+use ruma::{
+    api::client::filter::RoomEventFilter,
+    assign,
+    events::{
+        room::{
+            message::{
+                AudioMessageEventContent, FileMessageEventContent, ImageMessageEventContent,
+                MessageType, Relation, VideoMessageEventContent,
+            },
+            MediaSource,
+        },
+        AnyMessageLikeEvent, AnyTimelineEvent, MessageLikeEvent, MessageLikeEventType,
+    },
+    MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedUserId,
+};
+use tokio::sync::{Mutex, RwLock};
+use tracing::{debug, instrument, warn};
+
+/// A paginated, month-grouped list of the media shared in a room.
+///
+/// The gallery starts empty, and is filled, from the most recent media to the
+/// oldest one, by calling [`MediaGallery::paginate_backwards`].
+#[derive(Debug)]
+pub struct MediaGallery {
+    room: Room,
+    pagination: Mutex<PaginationState>,
+    items: RwLock<ObservableVector<MediaGalleryItem>>,
+}
+
+#[derive(Debug, Default)]
+struct PaginationState {
+    /// The token to continue paginating from, if we already started.
+    token: Option<String>,
+    /// Whether we reached the start of the room.
+    hit_start: bool,
+}
+
+impl MediaGallery {
+    /// Create a new, empty, media gallery for the given room.
+    pub fn new(room: Room) -> Self {
+        Self { room, pagination: Default::default(), items: RwLock::new(ObservableVector::new()) }
+    }
+
+    /// The room of this gallery.
+    pub fn room(&self) -> &Room {
+        &self.room
+    }
+
+    /// Get the current items of the gallery, and a stream of changes.
+    ///
+    /// Items are ordered from the oldest to the most recent one, and every
+    /// media item is preceded by the [`MediaGalleryItem::Month`] it belongs to.
+    pub async fn subscribe(
+        &self,
+    ) -> (Vector<MediaGalleryItem>, impl Stream<Item = VectorDiff<MediaGalleryItem>>) {
+        let items = self.items.read().await;
+        (items.clone(), items.subscribe().into_stream())
+    }
+
+    /// Load older media into the gallery.
+    ///
+    /// Paginates the room history until at least `num_items` new media items
+    /// have been found, or the start of the room has been reached.
+    ///
+    /// Returns whether the start of the room has been reached.
+    #[instrument(skip(self), fields(room_id = ?self.room.room_id()))]
+    pub async fn paginate_backwards(&self, num_items: u16) -> Result<bool> {
+        let mut pagination = self.pagination.lock().await;
+
+        if pagination.hit_start {
+            return Ok(true);
+        }
+
+        // Encrypted events can't be filtered on their content by the server, they
+        // need to be decrypted first.
+        let is_encrypted = self.room.is_encrypted().await?;
+        let filter = if is_encrypted {
+            assign!(RoomEventFilter::default(), {
+                types: Some(vec![
+                    MessageLikeEventType::RoomMessage.to_string(),
+                    MessageLikeEventType::RoomEncrypted.to_string(),
+                ]),
+            })
+        } else {
+            assign!(RoomEventFilter::default(), {
+                types: Some(vec![MessageLikeEventType::RoomMessage.to_string()]),
+                contains_url: Some(true),
+            })
+        };
+
+        let mut num_found = 0;
+
+        while num_found < num_items {
+            let options = assign!(MessagesOptions::backward(), {
+                from: pagination.token.clone(),
+                limit: num_items.into(),
+                filter: filter.clone(),
+            });
+
+            let messages = self.room.messages(options).await?;
+
+            let new_items: Vec<_> =
+                messages.chunk.iter().filter_map(MediaItem::from_event).collect();
+            num_found = num_found.saturating_add(new_items.len().try_into().unwrap_or(u16::MAX));
+            debug!(num_events = messages.chunk.len(), num_media = new_items.len(), "Paginated");
+
+            self.prepend_items(new_items).await;
+
+            if messages.end.is_none() {
+                pagination.token = None;
+                pagination.hit_start = true;
+                break;
+            }
+
+            // Don't ask for the same page again and again, if the server doesn't
+            // make progress.
+            if messages.end == pagination.token {
+                warn!("The server returned the same pagination token, stopping");
+                break;
+            }
+
+            pagination.token = messages.end;
+        }
+
+        Ok(pagination.hit_start)
+    }
+
+    /// Insert media items, ordered from the most recent to the oldest one,
+    /// before the current items.
+    async fn prepend_items(&self, new_items: Vec<MediaItem>) {
+        let mut items = self.items.write().await;
+
+        for item in new_items {
+            let month = item.month();
+
+            match items.front() {
+                Some(MediaGalleryItem::Month(front_month)) if *front_month == month => {
+                    items.insert(1, MediaGalleryItem::Media(item));
+                }
+                _ => {
+                    items.push_front(MediaGalleryItem::Media(item));
+                    items.push_front(MediaGalleryItem::Month(month));
+                }
+            }
+        }
+    }
+}
+
+/// An item of the [`MediaGallery`].
+#[derive(Clone, Debug)]
+pub enum MediaGalleryItem {
+    /// The start of a month, followed by the media sent during that month.
+    Month(MediaGalleryMonth),
+
+    /// A media message.
+    Media(MediaItem),
+}
+
+impl MediaGalleryItem {
+    /// Get the inner media item, if this is a media.
+    pub fn as_media(&self) -> Option<&MediaItem> {
+        match self {
+            Self::Media(media) => Some(media),
+            Self::Month(_) => None,
+        }
+    }
+}
+
+/// A month, in the local time zone.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MediaGalleryMonth {
+    /// The year.
+    pub year: i32,
+
+    /// The month, starting from 1.
+    pub month: u32,
+}
+
+impl MediaGalleryMonth {
+    fn from_timestamp(ts: MilliSecondsSinceUnixEpoch) -> Self {
+        let datetime = Local
+            .timestamp_millis_opt(ts.0.into())
+            // Only returns `None` if date is after Dec 31, 262143 BCE.
+            .single()
+            // Fallback to the current date to avoid issues with malicious
+            // homeservers.
+            .unwrap_or_else(Local::now);
+
+        Self { year: datetime.year(), month: datetime.month() }
+    }
+}
+
+/// A media message of the [`MediaGallery`].
+#[derive(Clone, Debug)]
+pub struct MediaItem {
+    event_id: OwnedEventId,
+    sender: OwnedUserId,
+    timestamp: MilliSecondsSinceUnixEpoch,
+    content: MediaItemContent,
+}
+
+impl MediaItem {
+    fn from_event(event: &TimelineEvent) -> Option<Self> {
+        let event = match event.event.deserialize() {
+            Ok(event) => event,
+            Err(error) => {
+                warn!("Failed to deserialize paginated event: {error}");
+                return None;
+            }
+        };
+
+        // Redacted messages and events that couldn't be decrypted are skipped.
+        let AnyTimelineEvent::MessageLike(AnyMessageLikeEvent::RoomMessage(
+            MessageLikeEvent::Original(event),
+        )) = event
+        else {
+            return None;
+        };
+
+        // Edits only replace the content of a media that is already in the
+        // gallery.
+        if matches!(event.content.relates_to, Some(Relation::Replacement(_))) {
+            return None;
+        }
+
+        let content = match event.content.msgtype {
+            MessageType::Image(c) => MediaItemContent::Image(c),
+            MessageType::Video(c) => MediaItemContent::Video(c),
+            MessageType::Audio(c) => MediaItemContent::Audio(c),
+            MessageType::File(c) => MediaItemContent::File(c),
+            _ => return None,
+        };
+
+        Some(Self {
+            event_id: event.event_id,
+            sender: event.sender,
+            timestamp: event.origin_server_ts,
+            content,
+        })
+    }
+
+    /// The ID of the event of this media.
+    pub fn event_id(&self) -> &OwnedEventId {
+        &self.event_id
+    }
+
+    /// The sender of this media.
+    pub fn sender(&self) -> &OwnedUserId {
+        &self.sender
+    }
+
+    /// The timestamp at which this media was sent.
+    pub fn timestamp(&self) -> MilliSecondsSinceUnixEpoch {
+        self.timestamp
+    }
+
+    /// The month this media was sent in.
+    pub fn month(&self) -> MediaGalleryMonth {
+        MediaGalleryMonth::from_timestamp(self.timestamp)
+    }
+
+    /// The content of this media.
+    pub fn content(&self) -> &MediaItemContent {
+        &self.content
+    }
+}
+
+/// The media item can be passed directly to [`Media::get_thumbnail`] or
+/// [`Media::get_file`].
+///
+/// Unencrypted images without a thumbnail use the image itself as a thumbnail
+/// source, so that the homeserver can scale it down. Encrypted media can't be
+/// scaled down by the homeserver, so they don't have a thumbnail source unless
+/// the sender provided one.
+///
+/// [`Media::get_thumbnail`]: matrix_sdk::Media::get_thumbnail
+/// [`Media::get_file`]: matrix_sdk::Media::get_file
+impl MediaEventContent for MediaItem {
+    fn source(&self) -> Option<MediaSource> {
+        match &self.content {
+            MediaItemContent::Image(c) => c.source(),
+            MediaItemContent::Video(c) => c.source(),
+            MediaItemContent::Audio(c) => c.source(),
+            MediaItemContent::File(c) => c.source(),
+        }
+    }
+
+    fn thumbnail_source(&self) -> Option<MediaSource> {
+        match &self.content {
+            MediaItemContent::Image(c) => c.thumbnail_source().or_else(|| match &c.source {
+                source @ MediaSource::Plain(_) => Some(source.clone()),
+                MediaSource::Encrypted(_) => None,
+            }),
+            MediaItemContent::Video(c) => c.thumbnail_source(),
+            MediaItemContent::Audio(c) => c.thumbnail_source(),
+            MediaItemContent::File(c) => c.thumbnail_source(),
+        }
+    }
+}
+
+/// The content of a [`MediaItem`].
+#[derive(Clone, Debug)]
+pub enum MediaItemContent {
+    /// An image.
+    Image(ImageMessageEventContent),
+
+    /// A video.
+    Video(VideoMessageEventContent),
+
+    /// An audio file.
+    Audio(AudioMessageEventContent),
+
+    /// A generic file.
+    File(FileMessageEventContent),
+}
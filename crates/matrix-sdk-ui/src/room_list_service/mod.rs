@@ -76,7 +76,7 @@ use matrix_sdk::{
     event_cache::EventCacheError, sliding_sync::Ranges, Client, Error as SlidingSyncError,
     SlidingSync, SlidingSyncList, SlidingSyncListBuilder, SlidingSyncMode,
 };
-use matrix_sdk_base::ring_buffer::RingBuffer;
+use matrix_sdk_base::{ring_buffer::RingBuffer, RoomStateFilter};
 pub use room::*;
 pub use room_list::*;
 use ruma::{
@@ -86,7 +86,7 @@ use ruma::{
     },
     assign,
     events::{StateEventType, TimelineEventType},
-    OwnedRoomId, RoomId,
+    MilliSecondsSinceUnixEpoch, OwnedRoomId, RoomId,
 };
 pub use state::*;
 use thiserror::Error;
@@ -386,6 +386,57 @@ impl RoomListService {
         RoomList::new(&self.sliding_sync, sliding_sync_list_name, self.state()).await
     }
 
+    /// Fill the `all_rooms` list with the rooms loaded from the state store,
+    /// without waiting for the first sync.
+    ///
+    /// It allows a “cold start from cache”: the joined and invited rooms known
+    /// by the [`Client`], which aren't spaces nor tombstoned, are published
+    /// immediately, sorted by the timestamp of their cached latest event. The
+    /// entries are then updated in place by the sync. The
+    /// [`RoomListLoadingState`] of the [`RoomList`] is
+    /// [`RoomListLoadingState::Cached`] until the first sync is done.
+    ///
+    /// The timelines of these rooms start with the latest event of the room
+    /// until they are synced, see [`Room::default_room_timeline_builder()`].
+    ///
+    /// It must be called before the first sync. Nothing happens if the list
+    /// has already been restored from the sliding sync cache, or if there is
+    /// no room in the state store.
+    ///
+    /// Returns `true` if the list has been filled.
+    pub async fn preload_rooms_from_store(&self) -> bool {
+        let mut rooms = self
+            .client
+            .rooms_filtered(RoomStateFilter::JOINED | RoomStateFilter::INVITED)
+            .into_iter()
+            .filter(|room| !room.is_space() && !room.is_tombstoned())
+            .map(|room| {
+                let latest_event_timestamp = room.latest_event().and_then(|latest_event| {
+                    latest_event
+                        .event()
+                        .event
+                        .get_field::<MilliSecondsSinceUnixEpoch>("origin_server_ts")
+                        .ok()
+                        .flatten()
+                });
+
+                (latest_event_timestamp, room.room_id().to_owned())
+            })
+            .collect::<Vec<_>>();
+
+        // Most recent rooms first, rooms without a latest event last.
+        rooms.sort_by(|(timestamp_a, room_id_a), (timestamp_b, room_id_b)| {
+            timestamp_b.cmp(timestamp_a).then_with(|| room_id_a.cmp(room_id_b))
+        });
+
+        self.sliding_sync
+            .preload_list(
+                ALL_ROOMS_LIST_NAME,
+                rooms.into_iter().map(|(_, room_id)| room_id).collect(),
+            )
+            .await
+    }
+
     /// Get a [`RoomList`] for all rooms.
     pub async fn all_rooms(&self) -> Result<RoomList, Error> {
         self.list_for(ALL_ROOMS_LIST_NAME).await
@@ -502,6 +553,10 @@ pub enum Error {
 
     #[error("The attached event cache ran into an error")]
     EventCache(#[from] EventCacheError),
+
+    /// The presence of a user couldn't be loaded.
+    #[error("Failed to load the presence of the user")]
+    Presence(#[source] matrix_sdk::Error),
 }
 
 /// An input for the [`RoomList`]' state machine.
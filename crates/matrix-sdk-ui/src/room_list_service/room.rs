@@ -17,8 +17,12 @@
 use std::{ops::Deref, sync::Arc};
 
 use async_once_cell::OnceCell as AsyncOnceCell;
+use eyeball::Subscriber;
 use matrix_sdk::{event_cache, SlidingSync, SlidingSyncRoom};
-use ruma::{api::client::sync::sync_events::v4::RoomSubscription, events::StateEventType, RoomId};
+use ruma::{
+    api::client::sync::sync_events::v4::RoomSubscription, events::StateEventType,
+    presence::PresenceState, RoomId,
+};
 
 use super::Error;
 use crate::{
@@ -88,6 +92,30 @@ impl Room {
         Some(self.inner.room.computed_display_name().await.ok()?.to_string())
     }
 
+    /// Subscribe to the presence state of the other user of this room, if it
+    /// is a direct message room with a single other user.
+    ///
+    /// Returns `Ok(None)` if it's not the case. See
+    /// [`matrix_sdk::Client::subscribe_to_presence()`].
+    pub async fn subscribe_to_direct_user_presence(
+        &self,
+    ) -> Result<Option<Subscriber<PresenceState>>, Error> {
+        let mut direct_targets = self.inner.room.direct_targets().into_iter();
+        let (Some(user_id), None) = (direct_targets.next(), direct_targets.next()) else {
+            return Ok(None);
+        };
+
+        let subscriber = self
+            .inner
+            .room
+            .client()
+            .subscribe_to_presence(&user_id)
+            .await
+            .map_err(Error::Presence)?;
+
+        Ok(Some(subscriber))
+    }
+
     /// Get the underlying [`matrix_sdk::Room`].
     pub fn inner_room(&self) -> &matrix_sdk::Room {
         &self.inner.room
@@ -173,18 +201,30 @@ impl Room {
     }
 
     /// Create a new [`TimelineBuilder`] with the default configuration.
+    ///
+    /// If the room hasn't been synced yet, e.g. because it has been preloaded
+    /// from the state store, the timeline starts with the latest event of the
+    /// room persisted in the state store, see [`Room::latest_event()`]. It is
+    /// replaced by the events of the room when it is synced.
     pub async fn default_room_timeline_builder(&self) -> event_cache::Result<TimelineBuilder> {
-        // TODO we can remove this once the event cache handles his own cache.
-        self.inner
-            .room
-            .client()
-            .event_cache()
-            .add_initial_events(
-                self.inner.room.room_id(),
-                self.inner.sliding_sync_room.timeline_queue().iter().cloned().collect(),
-                self.inner.sliding_sync_room.prev_batch(),
-            )
-            .await?;
+        let timeline_queue = self.inner.sliding_sync_room.timeline_queue();
+        let prev_batch = self.inner.sliding_sync_room.prev_batch();
+        let event_cache = self.inner.room.client().event_cache();
+
+        if !timeline_queue.is_empty() || prev_batch.is_some() {
+            // TODO we can remove this once the event cache handles his own cache.
+            event_cache
+                .add_initial_events(
+                    self.inner.room.room_id(),
+                    timeline_queue.iter().cloned().collect(),
+                    prev_batch,
+                )
+                .await?;
+        } else if let Some(latest_event) = self.inner.room.latest_event() {
+            event_cache
+                .seed_events(self.inner.room.room_id(), vec![latest_event.event().clone()])
+                .await?;
+        }
 
         Ok(Timeline::builder(&self.inner.room).track_read_marker_and_receipts())
     }
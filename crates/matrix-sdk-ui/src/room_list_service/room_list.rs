@@ -23,7 +23,7 @@ use eyeball_im_util::vector::VectorObserverExt;
 use futures_util::{pin_mut, stream, Stream, StreamExt as _};
 use matrix_sdk::{
     executor::{spawn, JoinHandle},
-    RoomListEntry, SlidingSync, SlidingSyncList,
+    RoomListEntry, SlidingSync, SlidingSyncList, SlidingSyncListLoadingState,
 };
 use matrix_sdk_base::RoomInfoUpdate;
 use tokio::{select, sync::broadcast};
@@ -56,13 +56,18 @@ impl RoomList {
             .await
             .ok_or_else(|| Error::UnknownList(sliding_sync_list_name.to_owned()))?;
 
-        let loading_state =
-            SharedObservable::new(match sliding_sync_list.maximum_number_of_rooms() {
-                Some(maximum_number_of_rooms) => RoomListLoadingState::Loaded {
+        let loading_state = SharedObservable::new(
+            match (sliding_sync_list.state(), sliding_sync_list.maximum_number_of_rooms()) {
+                // The list has been restored from a cache, and hasn't been synced yet.
+                (SlidingSyncListLoadingState::Preloaded, maximum_number_of_rooms) => {
+                    RoomListLoadingState::Cached { maximum_number_of_rooms }
+                }
+                (_, Some(maximum_number_of_rooms)) => RoomListLoadingState::Loaded {
                     maximum_number_of_rooms: Some(maximum_number_of_rooms),
                 },
-                None => RoomListLoadingState::NotLoaded,
-            });
+                (_, None) => RoomListLoadingState::NotLoaded,
+            },
+        );
 
         Ok(Self {
             sliding_sync_list: sliding_sync_list.clone(),
@@ -82,7 +87,7 @@ impl RoomList {
                     }
                 }
 
-                // Let's jump from `NotLoaded` to `Loaded`.
+                // Let's jump from `NotLoaded` or `Cached` to `Loaded`.
                 let maximum_number_of_rooms = sliding_sync_list.maximum_number_of_rooms();
 
                 loading_state.set(RoomListLoadingState::Loaded { maximum_number_of_rooms });
@@ -225,9 +230,24 @@ pub enum RoomListLoadingState {
     /// It's a good opportunity to show a placeholder to the user.
     ///
     /// From [`Self::NotLoaded`], it's only possible to move to
-    /// [`Self::Loaded`].
+    /// [`Self::Cached`] or [`Self::Loaded`].
     NotLoaded,
 
+    /// The [`RoomList`] has been loaded from a cache, i.e. from the sliding
+    /// sync cache or from the state store, but no sync has been done yet. The
+    /// rooms can be shown to the user, but they may be outdated.
+    ///
+    /// The number of rooms is represented by `maximum_number_of_rooms`, as
+    /// known by the cache.
+    ///
+    /// From [`Self::Cached`], it's only possible to move to [`Self::Loaded`],
+    /// once the data is live.
+    Cached {
+        /// The maximum number of rooms a [`RoomList`] contains, according to
+        /// the cache.
+        maximum_number_of_rooms: Option<u32>,
+    },
+
     /// The [`RoomList`] has been loaded, i.e. a sync has been run, or more
     /// syncs are running, there is probably something to show to the user.
     /// Either the user has 0 room, in this case, it's a good opportunity to
@@ -237,7 +257,7 @@ pub enum RoomListLoadingState {
     /// The number of rooms is represented by `maximum_number_of_rooms`.
     ///
     /// From [`Self::Loaded`], it's not possible to move back to
-    /// [`Self::NotLoaded`].
+    /// [`Self::NotLoaded`] or [`Self::Cached`].
     Loaded {
         /// The maximum number of rooms a [`RoomList`] contains.
         ///
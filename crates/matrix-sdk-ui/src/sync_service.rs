@@ -439,11 +439,19 @@ pub struct SyncServiceBuilder {
     /// Application identifier, used as the cross-process lock value, if
     /// applicable.
     identifier: String,
+
+    /// Are the rooms loaded from the state store before the first sync?
+    with_cold_start_from_cache: bool,
 }
 
 impl SyncServiceBuilder {
     fn new(client: Client) -> Self {
-        Self { client, with_cross_process_lock: false, identifier: "app".to_owned() }
+        Self {
+            client,
+            with_cross_process_lock: false,
+            identifier: "app".to_owned(),
+            with_cold_start_from_cache: false,
+        }
     }
 
     /// Enables the cross-process lock, if the sync service is being built in a
@@ -465,6 +473,16 @@ impl SyncServiceBuilder {
         self
     }
 
+    /// Enables the cold start from cache, i.e. the room list is filled with
+    /// the rooms loaded from the state store as soon as the `SyncService` is
+    /// built, without waiting for the server to be reached.
+    ///
+    /// See [`RoomListService::preload_rooms_from_store`] to learn more.
+    pub fn with_cold_start_from_cache(mut self) -> Self {
+        self.with_cold_start_from_cache = true;
+        self
+    }
+
     /// Finish setting up the `SyncService`.
     ///
     /// This creates the underlying sliding syncs, and will *not* start them in
@@ -475,6 +493,10 @@ impl SyncServiceBuilder {
 
         let room_list = RoomListService::new(self.client.clone()).await?;
 
+        if self.with_cold_start_from_cache {
+            room_list.preload_rooms_from_store().await;
+        }
+
         let encryption_sync = Arc::new(
             EncryptionSyncService::new(
                 self.identifier,
@@ -15,12 +15,18 @@
 use std::{collections::BTreeSet, sync::Arc};
 
 use futures_util::{pin_mut, StreamExt};
+#[cfg(feature = "e2e-encryption")]
+use futures_util::{stream, Stream};
+#[cfg(feature = "e2e-encryption")]
+use matrix_sdk::{encryption::TrustRequirement, Client};
 use matrix_sdk::{
-    event_cache::{EventsOrigin, RoomEventCacheUpdate},
+    event_cache::{EventsOrigin, RoomEventCacheGap, RoomEventCacheUpdate},
     executor::spawn,
     send_queue::{LocalEcho, RoomSendQueueUpdate},
     Room,
 };
+#[cfg(feature = "e2e-encryption")]
+use ruma::OwnedUserId;
 use ruma::{events::AnySyncTimelineEvent, RoomVersionId};
 use tokio::sync::broadcast::error::RecvError;
 use tracing::{info, info_span, trace, warn, Instrument, Span};
@@ -54,6 +60,9 @@ pub struct TimelineBuilder {
 
     /// An optional prefix for internal IDs.
     internal_id_prefix: Option<String>,
+
+    /// Are consecutive state items grouped under a virtual item?
+    group_state_items: bool,
 }
 
 impl TimelineBuilder {
@@ -64,6 +73,7 @@ impl TimelineBuilder {
             unable_to_decrypt_hook: None,
             focus: TimelineFocus::Live,
             internal_id_prefix: None,
+            group_state_items: false,
         }
     }
 
@@ -141,6 +151,21 @@ impl TimelineBuilder {
         self
     }
 
+    /// Whether to group consecutive state items, like membership or profile
+    /// changes, under a [`VirtualTimelineItem::StateGroup`].
+    ///
+    /// The groups summarize the changes they contain, and are kept up to date
+    /// as the timeline changes. The grouped items stay in the timeline, right
+    /// after their group.
+    ///
+    /// Defaults to `false`.
+    ///
+    /// [`VirtualTimelineItem::StateGroup`]: super::VirtualTimelineItem::StateGroup
+    pub fn group_state_items(mut self, group: bool) -> Self {
+        self.group_state_items = group;
+        self
+    }
+
     /// Create a [`Timeline`] with the options set on this builder.
     #[tracing::instrument(
         skip(self),
@@ -150,7 +175,14 @@ impl TimelineBuilder {
         )
     )]
     pub async fn build(self) -> Result<Timeline, Error> {
-        let Self { room, settings, unable_to_decrypt_hook, focus, internal_id_prefix } = self;
+        let Self {
+            room,
+            settings,
+            unable_to_decrypt_hook,
+            focus,
+            internal_id_prefix,
+            group_state_items,
+        } = self;
 
         let client = room.client();
         let event_cache = client.event_cache();
@@ -163,14 +195,26 @@ impl TimelineBuilder {
 
         let is_live = matches!(focus, TimelineFocus::Live);
 
-        let inner = TimelineInner::new(room, focus, internal_id_prefix, unable_to_decrypt_hook)
-            .with_settings(settings);
+        let inner = TimelineInner::new(
+            room,
+            focus,
+            internal_id_prefix,
+            unable_to_decrypt_hook,
+            group_state_items,
+        )
+        .with_settings(settings);
 
         let has_events = inner.init_focus(&room_event_cache).await?;
 
         let room = inner.room();
         let client = room.client();
 
+        // The trust in the senders of the events only matters if a trust requirement
+        // is set.
+        #[cfg(feature = "e2e-encryption")]
+        let check_sender_trust =
+            client.encryption().trust_requirement() != TrustRequirement::Untrusted;
+
         let room_update_join_handle = spawn({
             let room_event_cache = room_event_cache.clone();
             let inner = inner.clone();
@@ -203,6 +247,7 @@ impl TimelineBuilder {
                             match room_event_cache.subscribe().await {
                                 Ok((events, _)) => {
                                     inner.replace_with_initial_remote_events(events, RemoteEventOrigin::Sync).await;
+                                    inner.add_gaps(room_event_cache.gaps().await).await;
                                 }
                                 Err(err) => {
                                     warn!("Error when re-inserting initial events into the timeline: {err}");
@@ -232,16 +277,27 @@ impl TimelineBuilder {
                             inner.clear().await;
                         }
 
+                        RoomEventCacheUpdate::AddGap { prev_token } => {
+                            if !inner.is_live().await {
+                                // Gaps are only relevant for the live timeline, other modes
+                                // paginate on their own.
+                                continue;
+                            }
+
+                            trace!("Adding a gap in the timeline.");
+                            let gap = RoomEventCacheGap { prev_token, next_event_id: None };
+                            inner.add_gaps(vec![gap]).await;
+                        }
+
                         RoomEventCacheUpdate::AddTimelineEvents { events, origin } => {
                             trace!("Received new timeline events.");
 
-                            inner.add_events_at(
-                                events,
-                                TimelineEnd::Back,
-                                match origin {
-                                    EventsOrigin::Sync => RemoteEventOrigin::Sync,
-                                }
-                            ).await;
+                            let origin = match origin {
+                                EventsOrigin::Sync => RemoteEventOrigin::Sync,
+                                EventsOrigin::Cache => RemoteEventOrigin::Cache,
+                            };
+
+                            inner.add_events_at(events, TimelineEnd::Back, origin).await;
                         }
 
                         RoomEventCacheUpdate::AddEphemeralEvents { events } => {
@@ -313,27 +369,46 @@ impl TimelineBuilder {
                                 }
 
                                 RoomSendQueueUpdate::CancelledLocalEvent { transaction_id } => {
-                                    if !timeline.discard_local_echo(&transaction_id).await {
+                                    if !timeline.discard_local_echo(&transaction_id).await
+                                        && !timeline
+                                            .discard_local_poll_response(&transaction_id)
+                                            .await
+                                    {
                                         warn!("couldn't find the local echo to discard");
                                     }
                                 }
 
                                 RoomSendQueueUpdate::SendError { transaction_id, error } => {
-                                    timeline
-                                        .update_event_send_state(
+                                    // A vote that can't be sent is kept in the poll, and marked
+                                    // as failed, until it's sent or discarded.
+                                    if !timeline
+                                        .set_local_poll_response_send_error(
                                             &transaction_id,
-                                            EventSendState::SendingFailed { error },
+                                            Some(error.clone()),
                                         )
-                                        .await;
+                                        .await
+                                    {
+                                        timeline
+                                            .update_event_send_state(
+                                                &transaction_id,
+                                                EventSendState::SendingFailed { error },
+                                            )
+                                            .await;
+                                    }
                                 }
 
                                 RoomSendQueueUpdate::SentEvent { transaction_id, event_id } => {
-                                    timeline
-                                        .update_event_send_state(
-                                            &transaction_id,
-                                            EventSendState::Sent { event_id },
-                                        )
-                                        .await;
+                                    if !timeline
+                                        .set_local_poll_response_send_error(&transaction_id, None)
+                                        .await
+                                    {
+                                        timeline
+                                            .update_event_send_state(
+                                                &transaction_id,
+                                                EventSendState::Sent { event_id },
+                                            )
+                                            .await;
+                                    }
                                 }
                             },
 
@@ -402,6 +477,34 @@ impl TimelineBuilder {
             })
         };
 
+        // Whether the sender of an event satisfies the trust requirement can change
+        // when identities or devices change. Retry the decryption of the UTDs then,
+        // and hide the events whose sender isn't trusted anymore.
+        #[cfg(feature = "e2e-encryption")]
+        let trust_updates_join_handle = if check_sender_trust {
+            match trust_updates_stream(&client).await {
+                Ok(stream) => {
+                    let inner = inner.clone();
+
+                    Some(spawn(async move {
+                        pin_mut!(stream);
+
+                        while let Some(user_ids) = stream.next().await {
+                            let room = inner.room();
+                            inner.retry_event_decryption(room, None).await;
+                            inner.reevaluate_sender_trust(room, user_ids).await;
+                        }
+                    }))
+                }
+                Err(err) => {
+                    warn!("Couldn't listen to trust updates: {err}");
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
         let timeline = Timeline {
             inner,
             event_cache: room_event_cache,
@@ -411,6 +514,8 @@ impl TimelineBuilder {
                 room_update_join_handle,
                 room_key_from_backups_join_handle,
                 local_echo_listener_handle,
+                #[cfg(feature = "e2e-encryption")]
+                trust_updates_join_handle,
                 _event_cache_drop_handle: event_cache_drop,
             }),
         };
@@ -426,3 +531,21 @@ impl TimelineBuilder {
         Ok(timeline)
     }
 }
+
+/// A stream of the users whose identity or devices changed, which means that
+/// the trust in the events they sent might have changed too.
+#[cfg(feature = "e2e-encryption")]
+pub(super) async fn trust_updates_stream(
+    client: &Client,
+) -> matrix_sdk::Result<impl Stream<Item = BTreeSet<OwnedUserId>>> {
+    let encryption = client.encryption();
+
+    let identities = encryption.user_identities_stream().await?.map(|updates| {
+        updates.new.into_keys().chain(updates.changed.into_keys()).collect::<BTreeSet<_>>()
+    });
+    let devices = encryption.devices_stream().await?.map(|updates| {
+        updates.new.into_keys().chain(updates.changed.into_keys()).collect::<BTreeSet<_>>()
+    });
+
+    Ok(stream::select(identities, devices))
+}
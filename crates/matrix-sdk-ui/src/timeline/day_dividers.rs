@@ -105,6 +105,8 @@ impl DayDividerAdjuster {
 
         let mut prev_item: Option<PrevItemDesc<'_>> = None;
         let mut latest_event_ts = None;
+        // The index of the state group right before the current item, if any.
+        let mut state_group_idx = None;
 
         for (i, item) in items.iter().enumerate() {
             match item.kind() {
@@ -123,17 +125,29 @@ impl DayDividerAdjuster {
                 TimelineItemKind::Event(event) => {
                     let ts = event.timestamp();
 
-                    self.handle_event(i, ts, prev_item, latest_event_ts);
+                    // A day divider must not be inserted between a state group and its
+                    // first item, but before the group.
+                    let insert_at = state_group_idx.unwrap_or(i);
+                    self.handle_event(insert_at, ts, prev_item, latest_event_ts);
 
                     prev_item =
                         Some(PrevItemDesc { item_index: i, item, insert_op_at: self.ops.len() });
                     latest_event_ts = Some(ts);
                 }
 
-                TimelineItemKind::Virtual(VirtualTimelineItem::ReadMarker) => {
+                TimelineItemKind::Virtual(VirtualTimelineItem::StateGroup(_)) => {
+                    state_group_idx = Some(i);
+                    continue;
+                }
+
+                TimelineItemKind::Virtual(
+                    VirtualTimelineItem::ReadMarker | VirtualTimelineItem::Gap { .. },
+                ) => {
                     // Nothing to do.
                 }
             }
+
+            state_group_idx = None;
         }
 
         // Also chase trailing day dividers explicitly, by iterating from the end to the
@@ -219,18 +233,25 @@ impl DayDividerAdjuster {
                 return true;
             }
 
-            TimelineItemKind::Virtual(VirtualTimelineItem::ReadMarker) => {
-                // Nothing to do for read markers.
+            TimelineItemKind::Virtual(
+                VirtualTimelineItem::ReadMarker
+                | VirtualTimelineItem::StateGroup(_)
+                | VirtualTimelineItem::Gap { .. },
+            ) => {
+                // Nothing to do for read markers, state groups and gaps.
             }
         }
 
         false
     }
 
+    /// Decides what to do with an event.
+    ///
+    /// A day divider that must precede the event is inserted at `insert_at`.
     #[inline]
     fn handle_event(
         &mut self,
-        i: usize,
+        insert_at: usize,
         ts: MilliSecondsSinceUnixEpoch,
         prev_item_desc: Option<PrevItemDesc<'_>>,
         latest_event_ts: Option<MilliSecondsSinceUnixEpoch>,
@@ -238,8 +259,8 @@ impl DayDividerAdjuster {
         let Some(PrevItemDesc { item_index, insert_op_at, item }) = prev_item_desc else {
             // The event was the first item, so there wasn't any day divider before it:
             // insert one.
-            trace!("inserting the first day divider @ {}", i);
-            self.ops.push(DayDividerOperation::Insert(i, ts));
+            trace!("inserting the first day divider @ {}", insert_at);
+            self.ops.push(DayDividerOperation::Insert(insert_at, ts));
             return;
         };
 
@@ -250,8 +271,11 @@ impl DayDividerAdjuster {
                 let prev_ts = prev_event.timestamp();
 
                 if !is_same_date_as(prev_ts, ts) {
-                    trace!("inserting day divider @ {} between two events with different dates", i);
-                    self.ops.push(DayDividerOperation::Insert(i, ts));
+                    trace!(
+                        "inserting day divider @ {} between two events with different dates",
+                        insert_at
+                    );
+                    self.ops.push(DayDividerOperation::Insert(insert_at, ts));
                 }
             }
 
@@ -278,7 +302,11 @@ impl DayDividerAdjuster {
                 }
             }
 
-            TimelineItemKind::Virtual(VirtualTimelineItem::ReadMarker) => {
+            TimelineItemKind::Virtual(
+                VirtualTimelineItem::ReadMarker
+                | VirtualTimelineItem::StateGroup(_)
+                | VirtualTimelineItem::Gap { .. },
+            ) => {
                 // Nothing to do.
             }
         }
@@ -616,7 +644,7 @@ mod tests {
         event_item::{EventTimelineItemKind, RemoteEventTimelineItem},
         inner::TimelineInnerMetadata,
         util::timestamp_to_date,
-        EventTimelineItem, TimelineItemContent, VirtualTimelineItem,
+        EventTimelineItem, StateGroup, TimelineItemContent, VirtualTimelineItem,
     };
 
     fn event_with_ts(timestamp: MilliSecondsSinceUnixEpoch) -> EventTimelineItem {
@@ -628,7 +656,7 @@ mod tests {
             is_highlighted: false,
             encryption_info: None,
             original_json: None,
-            latest_edit_json: None,
+            edits: Vec::new(),
             origin: crate::timeline::event_item::RemoteEventOrigin::Sync,
         });
         EventTimelineItem::new(
@@ -844,4 +872,36 @@ mod tests {
         assert!(iter.next().unwrap().is_remote_event());
         assert!(iter.next().is_none());
     }
+
+    #[test]
+    fn test_day_divider_before_state_group() {
+        let mut items = ObservableVector::new();
+        let mut txn = items.transaction();
+
+        let mut meta = TimelineInnerMetadata::new(ruma::RoomVersionId::V11, None, None);
+
+        let timestamp = MilliSecondsSinceUnixEpoch(uint!(42));
+        let timestamp_next_day =
+            MilliSecondsSinceUnixEpoch((42 + 3600 * 24 * 1000).try_into().unwrap());
+        assert_ne!(timestamp_to_date(timestamp), timestamp_to_date(timestamp_next_day));
+
+        txn.push_back(meta.new_timeline_item(VirtualTimelineItem::DayDivider(timestamp)));
+        txn.push_back(meta.new_timeline_item(event_with_ts(timestamp)));
+        txn.push_back(meta.new_timeline_item(VirtualTimelineItem::StateGroup(StateGroup::new([]))));
+        txn.push_back(meta.new_timeline_item(event_with_ts(timestamp_next_day)));
+
+        let mut adjuster = DayDividerAdjuster::default();
+        adjuster.run(&mut txn, &mut meta);
+
+        txn.commit();
+
+        let mut iter = items.iter();
+
+        assert!(iter.next().unwrap().is_day_divider());
+        assert!(iter.next().unwrap().is_remote_event());
+        assert!(iter.next().unwrap().is_day_divider());
+        assert!(iter.next().unwrap().is_state_group());
+        assert!(iter.next().unwrap().is_remote_event());
+        assert!(iter.next().is_none());
+    }
 }
@@ -61,6 +61,10 @@ pub enum Error {
     #[error("User ID is not available")]
     UserIdNotAvailable,
 
+    /// The requested gap is not in the timeline.
+    #[error("Gap not found in timeline")]
+    GapNotInTimeline,
+
     /// Something went wrong with the room event cache.
     #[error("Something went wrong with the room event cache.")]
     EventCacheError(#[from] EventCacheError),
@@ -147,6 +151,82 @@ pub enum SendEventError {
     SendError(#[from] RoomSendQueueError),
 }
 
+/// Errors that can happen when fetching the edit history of a timeline item.
+#[derive(Debug, Error)]
+pub enum EditHistoryError {
+    /// The item is a local echo that hasn't been sent yet.
+    #[error("local echoes that haven't been sent yet don't have an edit history")]
+    NotSentYet,
+
+    /// The item has been redacted.
+    #[error("redacted items don't have an edit history")]
+    Redacted,
+
+    /// The item isn't a message.
+    #[error("only messages have an edit history")]
+    NotRoomMessage,
+
+    /// The edits couldn't be fetched from the server.
+    #[error(transparent)]
+    SdkError(#[from] matrix_sdk::Error),
+}
+
+/// Errors that can happen when creating, answering or ending a poll.
+#[derive(Debug, Error)]
+pub enum PollError {
+    /// The poll kind isn't one of the kinds defined by MSC3381.
+    #[error("unsupported poll kind: {0}")]
+    UnsupportedPollKind(String),
+
+    /// A poll must have between 1 and 20 answers.
+    #[error("a poll must have between 1 and 20 answers, got {0}")]
+    InvalidAnswerCount(usize),
+
+    /// The maximum number of selections must be between 1 and the number of
+    /// answers.
+    #[error("invalid maximum number of selections: {0}")]
+    InvalidMaxSelections(u8),
+
+    /// The poll start event isn't in the timeline.
+    #[error("poll not found in the timeline")]
+    PollNotInTimeline,
+
+    /// The poll has already ended.
+    #[error("the poll has already ended")]
+    PollEnded,
+
+    /// A response must select at most the maximum number of selections of the
+    /// poll.
+    #[error("a response must select at most {max_selections} answers, got {count}")]
+    InvalidSelectionCount {
+        /// The number of selected answers.
+        count: usize,
+        /// The maximum number of selections of the poll.
+        max_selections: u64,
+    },
+
+    /// The response selects an answer that isn't part of the poll.
+    #[error("unknown poll answer: {0}")]
+    UnknownAnswer(String),
+
+    /// The response selects the same answer more than once.
+    #[error("poll answer selected more than once: {0}")]
+    DuplicateAnswer(String),
+
+    /// Only the poll creator or users allowed to redact other users' events
+    /// can end a poll.
+    #[error("not allowed to end this poll")]
+    NotAllowedToEnd,
+
+    /// Something went wrong with the SDK.
+    #[error(transparent)]
+    SdkError(#[from] matrix_sdk::Error),
+
+    /// The poll event couldn't be sent.
+    #[error(transparent)]
+    SendError(#[from] RoomSendQueueError),
+}
+
 #[derive(Debug, Error)]
 pub enum RedactEventError {
     #[error("the given local event (with transaction id {0}) doesn't support redaction")]
@@ -18,7 +18,8 @@ use as_variant::as_variant;
 use eyeball_im::{ObservableVectorTransaction, ObservableVectorTransactionEntry};
 use indexmap::{map::Entry, IndexMap};
 use matrix_sdk::{
-    crypto::types::events::UtdCause, deserialized_responses::EncryptionInfo,
+    crypto::types::events::UtdCause,
+    deserialized_responses::{EncryptionInfo, UnableToDecryptReason},
     send_queue::AbortSendHandle,
 };
 use ruma::{
@@ -42,7 +43,6 @@ use ruma::{
         AnySyncTimelineEvent, BundledMessageLikeRelations, EventContent, FullStateEventContent,
         MessageLikeEventType, StateEventType, SyncStateEvent,
     },
-    html::RemoveReplyFallback,
     serde::Raw,
     EventId, MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedTransactionId, OwnedUserId,
     RoomVersionId,
@@ -53,16 +53,16 @@ use super::{
     day_dividers::DayDividerAdjuster,
     event_item::{
         AnyOtherFullStateEventContent, BundledReactions, EventItemIdentifier, EventSendState,
-        EventTimelineItemKind, LocalEventTimelineItem, Profile, RemoteEventOrigin,
+        EventTimelineItemKind, LocalEventTimelineItem, Profile, RemoteEdit, RemoteEventOrigin,
         RemoteEventTimelineItem,
     },
     inner::{TimelineInnerMetadata, TimelineInnerStateTransaction},
     polls::PollState,
     util::{rfind_event_by_id, rfind_event_item},
-    EventTimelineItem, InReplyToDetails, Message, OtherState, ReactionGroup, ReactionSenderData,
-    Sticker, TimelineDetails, TimelineItem, TimelineItemContent,
+    EventTimelineItem, InReplyToDetails, OtherState, ReactionGroup, ReactionSenderData, Sticker,
+    TimelineDetails, TimelineItem, TimelineItemContent,
 };
-use crate::{events::SyncTimelineEventWithoutContent, DEFAULT_SANITIZER_MODE};
+use crate::events::SyncTimelineEventWithoutContent;
 
 /// When adding an event, useful information related to the source of the event.
 #[derive(Clone)]
@@ -86,6 +86,9 @@ pub(super) enum Flow {
         txn_id: Option<OwnedTransactionId>,
         /// The raw serialized JSON event.
         raw_event: Raw<AnySyncTimelineEvent>,
+        /// Why the event couldn't be decrypted, if it's encrypted and the
+        /// reason was known when decrypting it failed.
+        unable_to_decrypt_reason: Option<UnableToDecryptReason>,
         /// Where should this be added in the timeline.
         position: TimelineItemPosition,
         /// Should this event actually be added, based on the event filters.
@@ -225,6 +228,12 @@ pub(super) enum TimelineItemPosition {
     /// recent).
     End { origin: RemoteEventOrigin },
 
+    /// A single item is inserted at the given index, in the middle of the
+    /// timeline.
+    ///
+    /// This only happens when a gap is filled with the missing events.
+    At { idx: usize, origin: RemoteEventOrigin },
+
     /// A single item is updated.
     ///
     /// This only happens when a UTD must be replaced with the decrypted event.
@@ -317,7 +326,8 @@ impl<'a, 'o> TimelineEventHandler<'a, 'o> {
                 // Retrieve the origin of the event.
                 let origin = match position {
                     TimelineItemPosition::End { origin }
-                    | TimelineItemPosition::Start { origin } => *origin,
+                    | TimelineItemPosition::Start { origin }
+                    | TimelineItemPosition::At { origin, .. } => *origin,
 
                     TimelineItemPosition::Update(idx) => self
                         .items
@@ -360,7 +370,14 @@ impl<'a, 'o> TimelineEventHandler<'a, 'o> {
                 }
                 AnyMessageLikeEventContent::RoomEncrypted(c) => {
                     // TODO: Handle replacements if the replaced event is also UTD
-                    let cause = UtdCause::determine(raw_event);
+                    let cause = match &self.ctx.flow {
+                        Flow::Remote {
+                            unable_to_decrypt_reason:
+                                Some(UnableToDecryptReason::SenderIdentityNotTrusted(_)),
+                            ..
+                        } => UtdCause::UntrustedSender,
+                        _ => UtdCause::determine(raw_event),
+                    };
                     self.add_item(TimelineItemContent::unable_to_decrypt(c, cause));
 
                     // Let the hook know that we ran into an unable-to-decrypt that is added to the
@@ -494,25 +511,11 @@ impl<'a, 'o> TimelineEventHandler<'a, 'o> {
                 return None;
             };
 
-            let mut msgtype = replacement.new_content.msgtype;
-            // Edit's content is never supposed to contain the reply fallback.
-            msgtype.sanitize(DEFAULT_SANITIZER_MODE, RemoveReplyFallback::No);
-
-            let new_content = TimelineItemContent::Message(Message {
-                msgtype,
-                in_reply_to: msg.in_reply_to.clone(),
-                thread_root: msg.thread_root.clone(),
-                edited: true,
-                mentions: replacement.new_content.mentions,
-            });
-
-            let edit_json = match &this.ctx.flow {
-                Flow::Local { .. } => None,
-                Flow::Remote { raw_event, .. } => Some(raw_event.clone()),
-            };
+            let new_content =
+                TimelineItemContent::Message(msg.with_edit_content(replacement.new_content));
 
             trace!("Applying edit");
-            Some(event_item.with_content(new_content, edit_json))
+            Some(event_item.with_content(new_content, this.remote_edit()))
         });
 
         if !found {
@@ -632,13 +635,8 @@ impl<'a, 'o> TimelineEventHandler<'a, 'o> {
                 }
             };
 
-            let edit_json = match &this.ctx.flow {
-                Flow::Local { .. } => None,
-                Flow::Remote { raw_event, .. } => Some(raw_event.clone()),
-            };
-
             trace!("Applying edit");
-            Some(event_item.with_content(new_content, edit_json))
+            Some(event_item.with_content(new_content, this.remote_edit()))
         });
 
         if !found {
@@ -660,16 +658,25 @@ impl<'a, 'o> TimelineEventHandler<'a, 'o> {
     }
 
     fn handle_poll_response(&mut self, c: UnstablePollResponseEventContent) {
+        // A local echo is shown optimistically, until its remote echo replaces it.
+        let (local_txn_id, remote_txn_id) = match &self.ctx.flow {
+            Flow::Local { txn_id, .. } => (Some(txn_id.clone()), None),
+            Flow::Remote { txn_id, .. } => (None, txn_id.clone()),
+        };
+
         let found = self.update_timeline_item(&c.relates_to.event_id, |this, event_item| {
             let poll_state = as_variant!(event_item.content(), TimelineItemContent::Poll)?;
-            Some(event_item.with_content(
-                TimelineItemContent::Poll(poll_state.add_response(
-                    &this.ctx.sender,
-                    this.ctx.timestamp,
-                    &c,
-                )),
-                None,
-            ))
+            let mut poll_state = poll_state.add_response(
+                &this.ctx.sender,
+                this.ctx.timestamp,
+                &c,
+                local_txn_id.as_deref(),
+            );
+            if let Some(txn_id) = &remote_txn_id {
+                poll_state.remove_local_response(txn_id);
+            }
+
+            Some(event_item.with_content(TimelineItemContent::Poll(poll_state), None))
         });
 
         if !found {
@@ -683,9 +690,10 @@ impl<'a, 'o> TimelineEventHandler<'a, 'o> {
     }
 
     fn handle_poll_end(&mut self, c: UnstablePollEndEventContent) {
+        let is_local = matches!(self.ctx.flow, Flow::Local { .. });
         let found = self.update_timeline_item(&c.relates_to.event_id, |this, event_item| {
             let poll_state = as_variant!(event_item.content(), TimelineItemContent::Poll)?;
-            match poll_state.end(this.ctx.timestamp) {
+            match poll_state.end(this.ctx.timestamp, is_local) {
                 Ok(poll_state) => {
                     Some(event_item.with_content(TimelineItemContent::Poll(poll_state), None))
                 }
@@ -774,6 +782,10 @@ impl<'a, 'o> TimelineEventHandler<'a, 'o> {
             // implemented) => no early return here.
         }
 
+        // If it's an edit that's being redacted, forget about it and restore the
+        // previous version of the edited item if needs be.
+        self.handle_edit_redaction(&redacts);
+
         // General path: redact another kind of (non-reaction) event.
         let found_redacted_event = self.update_timeline_item(&redacts, |this, event_item| {
             if event_item.as_remote().is_none() {
@@ -819,6 +831,52 @@ impl<'a, 'o> TimelineEventHandler<'a, 'o> {
         });
     }
 
+    /// Remove the edit with the given event ID from the item it applies to, if
+    /// any.
+    ///
+    /// If the redacted edit is the one whose content is currently displayed,
+    /// the content of the previous edit, or of the original event, is restored.
+    fn handle_edit_redaction(&mut self, redacts: &EventId) {
+        let Some((idx, item)) = rfind_event_item(self.items, |it| {
+            it.as_remote().is_some_and(|r| r.edits.iter().any(|e| *e.event_id == *redacts))
+        }) else {
+            return;
+        };
+
+        let Some(remote_event_item) = item.as_remote() else { return };
+        let mut remote_event_item = remote_event_item.clone();
+        let was_latest =
+            remote_event_item.edits.last().is_some_and(|edit| *edit.event_id == *redacts);
+        remote_event_item.edits.retain(|edit| *edit.event_id != *redacts);
+
+        let mut new_item = item.with_kind(remote_event_item.clone());
+
+        if was_latest {
+            if let TimelineItemContent::Message(msg) = item.content() {
+                let previous_edit = remote_event_item.edits.last().map(|edit| &edit.json);
+                let original_json = remote_event_item.original_json.as_ref();
+                match msg.with_edit_reverted(previous_edit, original_json) {
+                    Some(msg) => {
+                        trace!("Restoring previous version of edited message");
+                        new_item.set_content(TimelineItemContent::Message(msg));
+                    }
+                    None => {
+                        warn!("Couldn't restore previous version of edited message");
+                    }
+                }
+            } else {
+                debug!(
+                    "Redacted edit applied to {}, keeping current content",
+                    item.content().debug_string()
+                );
+            }
+        }
+
+        trace!("Removing redacted edit");
+        self.items.set(idx, TimelineItem::new(new_item, item.internal_id.to_owned()));
+        self.result.items_updated += 1;
+    }
+
     // Redacted redactions are no-ops (unfortunately)
     #[instrument(skip_all, fields(redacts_event_id = ?redacts))]
     fn handle_local_redaction(&mut self, redacts: OwnedTransactionId) {
@@ -892,7 +950,8 @@ impl<'a, 'o> TimelineEventHandler<'a, 'o> {
 
                 let origin = match *position {
                     TimelineItemPosition::Start { origin }
-                    | TimelineItemPosition::End { origin } => origin,
+                    | TimelineItemPosition::End { origin }
+                    | TimelineItemPosition::At { origin, .. } => origin,
 
                     // For updates, reuse the origin of the encrypted event.
                     #[cfg(feature = "e2e-encryption")]
@@ -913,7 +972,7 @@ impl<'a, 'o> TimelineEventHandler<'a, 'o> {
                     is_highlighted: self.ctx.is_highlighted,
                     encryption_info: self.ctx.encryption_info.clone(),
                     original_json: Some(raw_event.clone()),
-                    latest_edit_json: None,
+                    edits: Vec::new(),
                     origin,
                 }
                 .into()
@@ -1007,16 +1066,20 @@ impl<'a, 'o> TimelineEventHandler<'a, 'o> {
                 }
 
                 // Local echoes that are pending should stick to the bottom,
-                // find the latest event that isn't that.
-                let latest_event_idx = self
-                    .items
-                    .iter()
-                    .enumerate()
-                    .rev()
-                    .find_map(|(idx, item)| (!item.as_event()?.is_local_echo()).then_some(idx));
+                // find the latest event that isn't that, or the gap that
+                // precedes the newest events.
+                let latest_event_idx =
+                    self.items.iter().enumerate().rev().find_map(|(idx, item)| {
+                        let is_remote = match item.as_event() {
+                            Some(event) => !event.is_local_echo(),
+                            None => item.is_gap(),
+                        };
+                        is_remote.then_some(idx)
+                    });
 
                 // Insert the next item after the latest event item that's not a
-                // pending local echo, or at the start if there is no such item.
+                // pending local echo (or gap), or at the start if there is no
+                // such item.
                 let insert_idx = latest_event_idx.map_or(0, |idx| idx + 1);
 
                 trace!("Adding new remote timeline item after all non-pending events");
@@ -1038,6 +1101,22 @@ impl<'a, 'o> TimelineEventHandler<'a, 'o> {
                 }
             }
 
+            Flow::Remote { position: TimelineItemPosition::At { idx, .. }, event_id, .. } => {
+                if rfind_event_by_id(self.items, event_id).is_some() {
+                    trace!("Skipping gap event that has already been seen");
+                    return;
+                }
+
+                trace!("Adding new remote timeline item at position {idx}");
+
+                let item = self.meta.new_timeline_item(item);
+                if *idx == self.items.len() {
+                    self.items.push_back(item);
+                } else {
+                    self.items.insert(*idx, item);
+                }
+            }
+
             #[cfg(feature = "e2e-encryption")]
             Flow::Remote { position: TimelineItemPosition::Update(idx), .. } => {
                 trace!("Updating timeline item at position {idx}");
@@ -1080,6 +1159,18 @@ impl<'a, 'o> TimelineEventHandler<'a, 'o> {
         }
     }
 
+    /// The [`RemoteEdit`] to record when the current event is an edit.
+    fn remote_edit(&self) -> Option<RemoteEdit> {
+        match &self.ctx.flow {
+            Flow::Local { .. } => None,
+            Flow::Remote { event_id, raw_event, .. } => Some(RemoteEdit {
+                event_id: event_id.clone(),
+                json: raw_event.clone(),
+                encryption_info: self.ctx.encryption_info.clone(),
+            }),
+        }
+    }
+
     /// Updates the given timeline item.
     ///
     /// Returns true iff the item has been found (not necessarily updated),
@@ -23,22 +23,26 @@ use ruma::{
     events::{
         relation::{InReplyTo, Thread},
         room::message::{
-            MessageType, Relation, RoomMessageEventContent, RoomMessageEventContentWithoutRelation,
-            SyncRoomMessageEvent,
+            MessageFormat, MessageType, Relation, RoomMessageEventContent,
+            RoomMessageEventContentWithoutRelation, SyncRoomMessageEvent,
         },
-        AnyMessageLikeEventContent, AnySyncMessageLikeEvent, AnyTimelineEvent,
-        BundledMessageLikeRelations, Mentions,
+        AnyMessageLikeEventContent, AnySyncMessageLikeEvent, AnySyncTimelineEvent,
+        AnyTimelineEvent, BundledMessageLikeRelations, Mentions,
     },
     html::RemoveReplyFallback,
+    serde::Raw,
     OwnedEventId, OwnedUserId, RoomVersionId, UserId,
 };
-use tracing::error;
+use tracing::{error, warn};
 
 use super::TimelineItemContent;
 use crate::{
     timeline::{
         event_item::{EventTimelineItem, Profile, TimelineDetails},
+        mentions::{extract_pills, Pill},
+        rich_text::RichText,
         traits::RoomDataProvider,
+        url_previews::extract_urls,
         Error as TimelineError, TimelineItem,
     },
     DEFAULT_SANITIZER_MODE,
@@ -150,6 +154,62 @@ impl Message {
         self.mentions.as_ref()
     }
 
+    /// Get the pills of this message, i.e. the links to users, rooms and events
+    /// of its formatted body.
+    ///
+    /// Use [`Timeline::resolve_pills()`] to get the profiles of the users they
+    /// point to.
+    ///
+    /// [`Timeline::resolve_pills()`]: crate::timeline::Timeline::resolve_pills
+    pub fn pills(&self) -> Vec<Pill> {
+        let formatted = match &self.msgtype {
+            MessageType::Text(content) => content.formatted.as_ref(),
+            MessageType::Emote(content) => content.formatted.as_ref(),
+            MessageType::Notice(content) => content.formatted.as_ref(),
+            _ => None,
+        };
+
+        formatted
+            .filter(|formatted| formatted.format == MessageFormat::Html)
+            .map(|formatted| extract_pills(&formatted.body))
+            .unwrap_or_default()
+    }
+
+    /// Get the rich text of this message, parsed from its formatted body, or
+    /// from its plain text body if it doesn't have an HTML formatted body.
+    ///
+    /// The formatted body is sanitized and its reply fallback is removed.
+    pub fn rich_text(&self) -> RichText {
+        match &self.msgtype {
+            MessageType::Text(content) => RichText::new(&content.body, content.formatted.as_ref()),
+            MessageType::Emote(content) => RichText::new(&content.body, content.formatted.as_ref()),
+            MessageType::Notice(content) => {
+                RichText::new(&content.body, content.formatted.as_ref())
+            }
+            msgtype => RichText::from_plain_text(msgtype.body()),
+        }
+    }
+
+    /// Get the web URLs of this message, from its body and the links of its
+    /// formatted body.
+    ///
+    /// Use [`Timeline::url_previews()`] to get their previews.
+    ///
+    /// [`Timeline::url_previews()`]: crate::timeline::Timeline::url_previews
+    pub fn urls(&self) -> Vec<String> {
+        let (body, formatted) = match &self.msgtype {
+            MessageType::Text(content) => (&content.body, content.formatted.as_ref()),
+            MessageType::Emote(content) => (&content.body, content.formatted.as_ref()),
+            MessageType::Notice(content) => (&content.body, content.formatted.as_ref()),
+            _ => return Vec::new(),
+        };
+
+        let html = formatted
+            .filter(|formatted| formatted.format == MessageFormat::Html)
+            .map(|formatted| formatted.body.as_str());
+        extract_urls(body, html)
+    }
+
     pub(in crate::timeline) fn to_content(&self) -> RoomMessageEventContent {
         // Like the `impl From<Message> for RoomMessageEventContent` below, but
         // takes &self and only copies what's needed.
@@ -163,6 +223,79 @@ impl Message {
     pub(in crate::timeline) fn with_in_reply_to(&self, in_reply_to: InReplyToDetails) -> Self {
         Self { in_reply_to: Some(in_reply_to), ..self.clone() }
     }
+
+    /// Clone this message, with its content replaced by the new content of an
+    /// edit.
+    pub(in crate::timeline) fn with_edit_content(
+        &self,
+        new_content: RoomMessageEventContentWithoutRelation,
+    ) -> Self {
+        let mut msgtype = new_content.msgtype;
+        // Edit's content is never supposed to contain the reply fallback.
+        msgtype.sanitize(DEFAULT_SANITIZER_MODE, RemoveReplyFallback::No);
+
+        Self { msgtype, edited: true, mentions: new_content.mentions, ..self.clone() }
+    }
+
+    /// Clone this message, with its content replaced by the content of the
+    /// original, unedited event.
+    pub(in crate::timeline) fn with_original_content(
+        &self,
+        content: RoomMessageEventContent,
+    ) -> Self {
+        let remove_reply_fallback = if self.in_reply_to.is_some() {
+            RemoveReplyFallback::Yes
+        } else {
+            RemoveReplyFallback::No
+        };
+
+        let mut msgtype = content.msgtype;
+        msgtype.sanitize(DEFAULT_SANITIZER_MODE, remove_reply_fallback);
+
+        Self { msgtype, edited: false, mentions: content.mentions, ..self.clone() }
+    }
+
+    /// Compute the content this message should have once its currently
+    /// displayed edit is gone, given the JSON of the previous edit, if any, and
+    /// of the original event.
+    ///
+    /// Returns `None` if the content couldn't be computed.
+    pub(in crate::timeline) fn with_edit_reverted(
+        &self,
+        previous_edit_json: Option<&Raw<AnySyncTimelineEvent>>,
+        original_json: Option<&Raw<AnySyncTimelineEvent>>,
+    ) -> Option<Self> {
+        match previous_edit_json {
+            Some(json) => Some(self.with_edit_content(edit_new_content(json)?)),
+            None => Some(self.with_original_content(original_content(original_json?)?)),
+        }
+    }
+}
+
+/// Get the new content of an `m.room.message` edit, given its JSON.
+pub(in crate::timeline) fn edit_new_content(
+    json: &Raw<AnySyncTimelineEvent>,
+) -> Option<RoomMessageEventContentWithoutRelation> {
+    match original_content(json)?.relates_to {
+        Some(Relation::Replacement(replacement)) => Some(replacement.new_content),
+        _ => None,
+    }
+}
+
+/// Get the content of an `m.room.message` event, given its JSON.
+pub(in crate::timeline) fn original_content(
+    json: &Raw<AnySyncTimelineEvent>,
+) -> Option<RoomMessageEventContent> {
+    match json.deserialize() {
+        Ok(AnySyncTimelineEvent::MessageLike(AnySyncMessageLikeEvent::RoomMessage(
+            SyncRoomMessageEvent::Original(ev),
+        ))) => Some(ev.content),
+        Ok(_) => None,
+        Err(error) => {
+            warn!("Failed to deserialize m.room.message event: {error}");
+            None
+        }
+    }
 }
 
 impl From<Message> for RoomMessageEventContent {
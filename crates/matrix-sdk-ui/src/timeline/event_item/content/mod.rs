@@ -16,7 +16,10 @@ use std::sync::Arc;
 
 use as_variant::as_variant;
 use imbl::Vector;
-use matrix_sdk::crypto::types::events::UtdCause;
+use matrix_sdk::{
+    crypto::types::events::UtdCause,
+    deserialized_responses::{AlgorithmInfo, EncryptionInfo},
+};
 use matrix_sdk_base::latest_event::{is_suitable_for_latest_event, PossibleLatestEvent};
 use ruma::{
     events::{
@@ -59,6 +62,7 @@ use crate::timeline::{polls::PollState, TimelineItem};
 
 mod message;
 
+pub(in crate::timeline) use self::message::{edit_new_content, original_content};
 pub use self::message::{InReplyToDetails, Message, RepliedToEvent};
 
 /// The content of an [`EventTimelineItem`][super::EventTimelineItem].
@@ -398,6 +402,49 @@ impl EncryptedMessage {
             _ => Self::Unknown,
         }
     }
+
+    /// Create the `EncryptedMessage` replacing a decrypted event that is hidden
+    /// because its sender doesn't satisfy the decryption trust requirement.
+    ///
+    /// The Megolm session is kept when the encryption info records it, so the
+    /// event can be decrypted again once new keys for it arrive.
+    pub(in crate::timeline) fn hidden(encryption_info: &EncryptionInfo) -> Self {
+        match (&encryption_info.algorithm_info, &encryption_info.sender_device) {
+            #[allow(deprecated)]
+            (
+                AlgorithmInfo::MegolmV1AesSha2 {
+                    curve25519_key, session_id: Some(session_id), ..
+                },
+                Some(device_id),
+            ) => Self::MegolmV1AesSha2 {
+                sender_key: curve25519_key.clone(),
+                device_id: device_id.clone(),
+                session_id: session_id.clone(),
+                cause: UtdCause::UntrustedSender,
+            },
+            _ => Self::Unknown,
+        }
+    }
+
+    /// Clone this `EncryptedMessage`, updating the cause of the UTD.
+    ///
+    /// Returns `None` if the cause wouldn't change, or if this message doesn't
+    /// have a cause.
+    pub(in crate::timeline) fn with_cause(&self, new_cause: UtdCause) -> Option<Self> {
+        match self {
+            Self::MegolmV1AesSha2 { cause, .. } if *cause == new_cause => None,
+            #[allow(deprecated)]
+            Self::MegolmV1AesSha2 { sender_key, device_id, session_id, .. } => {
+                Some(Self::MegolmV1AesSha2 {
+                    sender_key: sender_key.clone(),
+                    device_id: device_id.clone(),
+                    session_id: session_id.clone(),
+                    cause: new_cause,
+                })
+            }
+            Self::OlmV1Curve25519AesSha2 { .. } | Self::Unknown => None,
+        }
+    }
 }
 
 /// An `m.sticker` event.
@@ -453,7 +500,7 @@ impl RoomMembershipChange {
 }
 
 /// An enum over all the possible room membership changes.
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum MembershipChange {
     /// No change.
     None,
@@ -0,0 +1,208 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The edit history of timeline items.
+
+use indexmap::IndexMap;
+use matrix_sdk::{
+    crypto::types::events::UtdCause, deserialized_responses::EncryptionInfo,
+    room::RelationsOptions, Room,
+};
+use ruma::{
+    events::{
+        relation::RelationType,
+        room::{encrypted::SyncRoomEncryptedEvent, message::SyncRoomMessageEvent},
+        AnySyncMessageLikeEvent, AnySyncTimelineEvent,
+    },
+    serde::Raw,
+    MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedUserId,
+};
+use tracing::{info, instrument, warn};
+
+use super::{
+    content::{edit_new_content, original_content},
+    EventTimelineItem, Message, TimelineItemContent,
+};
+use crate::timeline::error::EditHistoryError;
+
+/// One version of an edited timeline item, as returned by
+/// [`EventTimelineItem::edit_history`].
+#[derive(Clone, Debug)]
+pub struct EditVersion {
+    pub(in crate::timeline) event_id: OwnedEventId,
+    pub(in crate::timeline) timestamp: MilliSecondsSinceUnixEpoch,
+    pub(in crate::timeline) content: TimelineItemContent,
+    pub(in crate::timeline) encryption_info: Option<EncryptionInfo>,
+}
+
+impl EditVersion {
+    /// The ID of the event that introduced this version: the original event
+    /// for the first version, an `m.replace` event for the following ones.
+    pub fn event_id(&self) -> &OwnedEventId {
+        &self.event_id
+    }
+
+    /// The time at which this version was sent, according to the sender's
+    /// homeserver.
+    pub fn timestamp(&self) -> MilliSecondsSinceUnixEpoch {
+        self.timestamp
+    }
+
+    /// The content of the item at this version.
+    ///
+    /// This is [`TimelineItemContent::RedactedMessage`] if the edit has been
+    /// redacted, and [`TimelineItemContent::UnableToDecrypt`] if it couldn't
+    /// be decrypted.
+    pub fn content(&self) -> &TimelineItemContent {
+        &self.content
+    }
+
+    /// Information about the encryption of the event that introduced this
+    /// version, if it was encrypted.
+    pub fn encryption_info(&self) -> Option<&EncryptionInfo> {
+        self.encryption_info.as_ref()
+    }
+}
+
+impl EventTimelineItem {
+    /// Get all the versions of this item, from the original event to the
+    /// latest edit, in chronological order.
+    ///
+    /// The edits already seen by the timeline are completed by the ones
+    /// returned by the `/relations` endpoint, which is paginated until all the
+    /// edits have been fetched. Edits sent by another user than the sender of
+    /// the original event are invalid, and thus ignored.
+    ///
+    /// Only messages have an edit history for now.
+    #[instrument(skip_all, fields(event_id = ?self.event_id()))]
+    pub async fn edit_history(&self, room: &Room) -> Result<Vec<EditVersion>, EditHistoryError> {
+        let Some(remote) = self.as_remote() else {
+            return Err(EditHistoryError::NotSentYet);
+        };
+
+        let msg = match self.content() {
+            TimelineItemContent::Message(msg) => msg,
+            TimelineItemContent::RedactedMessage => return Err(EditHistoryError::Redacted),
+            _ => return Err(EditHistoryError::NotRoomMessage),
+        };
+
+        let original_json = remote.original_json.as_ref().ok_or(EditHistoryError::Redacted)?;
+        let original = original_content(original_json).ok_or(EditHistoryError::NotRoomMessage)?;
+
+        // Start with the edits we already know about, then fetch the others.
+        let mut edits: IndexMap<OwnedEventId, (Raw<AnySyncTimelineEvent>, Option<EncryptionInfo>)> =
+            remote
+                .edits
+                .iter()
+                .map(|edit| {
+                    (edit.event_id.clone(), (edit.json.clone(), edit.encryption_info.clone()))
+                })
+                .collect();
+
+        let mut options = RelationsOptions::with_rel_type(RelationType::Replacement);
+        loop {
+            let relations = room.relations(&remote.event_id, options.clone()).await?;
+
+            for event in relations.chunk {
+                let Ok(Some(event_id)) = event.event.get_field::<OwnedEventId>("event_id") else {
+                    warn!("Ignoring related event without an event ID");
+                    continue;
+                };
+
+                edits.entry(event_id).or_insert((event.event.cast(), event.encryption_info));
+            }
+
+            let Some(token) = relations.next_batch_token else { break };
+            options = options.from(token.as_str());
+        }
+
+        let mut versions: Vec<_> = edits
+            .into_iter()
+            .filter_map(|(event_id, (json, encryption_info))| {
+                let Ok(Some(sender)) = json.get_field::<OwnedUserId>("sender") else {
+                    warn!(?event_id, "Ignoring edit without a sender");
+                    return None;
+                };
+                if sender != self.sender {
+                    info!(?event_id, "Ignoring edit from another user");
+                    return None;
+                }
+
+                let Ok(Some(timestamp)) =
+                    json.get_field::<MilliSecondsSinceUnixEpoch>("origin_server_ts")
+                else {
+                    warn!(?event_id, "Ignoring edit without a timestamp");
+                    return None;
+                };
+
+                let content = edit_version_content(msg, &json)?;
+                Some(EditVersion { event_id, timestamp, content, encryption_info })
+            })
+            .collect();
+        versions.sort_by_key(|version| version.timestamp);
+
+        versions.insert(
+            0,
+            EditVersion {
+                event_id: remote.event_id.clone(),
+                timestamp: self.timestamp,
+                content: TimelineItemContent::Message(msg.with_original_content(original)),
+                encryption_info: remote.encryption_info.clone(),
+            },
+        );
+
+        Ok(versions)
+    }
+}
+
+/// Compute the content of `msg` once the edit with the given JSON is applied.
+///
+/// Returns `None` if the edit isn't a valid edit of a message.
+fn edit_version_content(
+    msg: &Message,
+    json: &Raw<AnySyncTimelineEvent>,
+) -> Option<TimelineItemContent> {
+    let event = match json.deserialize() {
+        Ok(AnySyncTimelineEvent::MessageLike(event)) => event,
+        Ok(_) => {
+            info!("Ignoring edit that is not a message-like event");
+            return None;
+        }
+        Err(error) => {
+            warn!("Failed to deserialize edit: {error}");
+            return None;
+        }
+    };
+
+    match event {
+        AnySyncMessageLikeEvent::RoomMessage(SyncRoomMessageEvent::Original(_)) => {
+            let Some(new_content) = edit_new_content(json) else {
+                info!("Ignoring message without a valid m.replace relation");
+                return None;
+            };
+            Some(TimelineItemContent::Message(msg.with_edit_content(new_content)))
+        }
+        AnySyncMessageLikeEvent::RoomEncrypted(SyncRoomEncryptedEvent::Original(ev)) => {
+            Some(TimelineItemContent::unable_to_decrypt(ev.content, UtdCause::Unknown))
+        }
+        AnySyncMessageLikeEvent::RoomMessage(SyncRoomMessageEvent::Redacted(_))
+        | AnySyncMessageLikeEvent::RoomEncrypted(SyncRoomEncryptedEvent::Redacted(_)) => {
+            Some(TimelineItemContent::RedactedMessage)
+        }
+        _ => {
+            info!("Ignoring edit of a message with a different event type");
+            None
+        }
+    }
+}
@@ -28,6 +28,7 @@ use ruma::{
 use tracing::warn;
 
 mod content;
+mod edit_history;
 mod local;
 mod reactions;
 mod remote;
@@ -38,12 +39,13 @@ pub use self::{
         MembershipChange, Message, OtherState, RepliedToEvent, RoomMembershipChange, Sticker,
         TimelineItemContent,
     },
+    edit_history::EditVersion,
     local::EventSendState,
     reactions::{BundledReactions, ReactionGroup},
 };
 pub(super) use self::{
     local::LocalEventTimelineItem,
-    remote::{RemoteEventOrigin, RemoteEventTimelineItem},
+    remote::{RemoteEdit, RemoteEventOrigin, RemoteEventTimelineItem},
 };
 
 /// An item in the timeline that represents at least one event.
@@ -132,7 +134,7 @@ impl EventTimelineItem {
 
         // We may need this, depending on how we are going to display edited messages in
         // previews.
-        let latest_edit_json = None;
+        let edits = Vec::new();
 
         // Probably the origin of the event doesn't matter for the preview.
         let origin = RemoteEventOrigin::Sync;
@@ -145,7 +147,7 @@ impl EventTimelineItem {
             is_highlighted,
             encryption_info,
             original_json: Some(raw_sync_event),
-            latest_edit_json,
+            edits,
             origin,
         }
         .into();
@@ -350,7 +352,9 @@ impl EventTimelineItem {
     pub fn latest_edit_json(&self) -> Option<&Raw<AnySyncTimelineEvent>> {
         match &self.kind {
             EventTimelineItemKind::Local(_) => None,
-            EventTimelineItemKind::Remote(remote_event) => remote_event.latest_edit_json.as_ref(),
+            EventTimelineItemKind::Remote(remote_event) => {
+                remote_event.edits.last().map(|edit| &edit.json)
+            }
         }
     }
 
@@ -385,17 +389,17 @@ impl EventTimelineItem {
 
     /// Clone the current event item, and update its content.
     ///
-    /// Optionally update `latest_edit_json` if the update is an edit received
-    /// from the server.
+    /// Optionally record `edit` if the update is an edit received from the
+    /// server.
     pub(super) fn with_content(
         &self,
         new_content: TimelineItemContent,
-        edit_json: Option<Raw<AnySyncTimelineEvent>>,
+        edit: Option<RemoteEdit>,
     ) -> Self {
         let mut new = self.clone();
         new.content = new_content;
-        if let EventTimelineItemKind::Remote(r) = &mut new.kind {
-            r.latest_edit_json = edit_json;
+        if let (EventTimelineItemKind::Remote(r), Some(edit)) = (&mut new.kind, edit) {
+            r.add_edit(edit);
         }
 
         new
@@ -52,8 +52,8 @@ pub(in crate::timeline) struct RemoteEventTimelineItem {
 
     /// JSON of the original event.
     ///
-    /// If the event is edited, this *won't* change, instead `latest_edit_json`
-    /// will be updated.
+    /// If the event is edited, this *won't* change, instead the edit will be
+    /// appended to `edits`.
     ///
     /// This field always starts out as `Some(_)`, but is set to `None` when the
     /// event is redacted. The redacted form of the event could be computed
@@ -62,8 +62,11 @@ pub(in crate::timeline) struct RemoteEventTimelineItem {
     /// a clear need for that.
     pub original_json: Option<Raw<AnySyncTimelineEvent>>,
 
-    /// JSON of the latest edit to this item.
-    pub latest_edit_json: Option<Raw<AnySyncTimelineEvent>>,
+    /// All the edits to this item that have been seen by the timeline, in the
+    /// order they were applied.
+    ///
+    /// The last one is the edit whose content is currently displayed.
+    pub edits: Vec<RemoteEdit>,
 
     /// Where we got this event from: A sync response or pagination.
     pub origin: RemoteEventOrigin,
@@ -81,10 +84,45 @@ impl RemoteEventTimelineItem {
         Self {
             reactions: BundledReactions::default(),
             original_json: None,
-            latest_edit_json: None,
+            edits: Vec::new(),
             ..self.clone()
         }
     }
+
+    /// Record a new edit of this item.
+    ///
+    /// If the same edit was already seen (e.g. it was received once from sync
+    /// and once from back-pagination), it's moved to the end of the list.
+    pub fn add_edit(&mut self, edit: RemoteEdit) {
+        self.edits.retain(|e| e.event_id != edit.event_id);
+        self.edits.push(edit);
+    }
+}
+
+/// An `m.replace` event that has been applied to a [`RemoteEventTimelineItem`].
+#[derive(Clone)]
+pub(in crate::timeline) struct RemoteEdit {
+    /// The event ID of the edit.
+    pub event_id: OwnedEventId,
+
+    /// JSON of the edit event, decrypted if it was encrypted.
+    pub json: Raw<AnySyncTimelineEvent>,
+
+    /// Encryption information of the edit event.
+    pub encryption_info: Option<EncryptionInfo>,
+}
+
+#[cfg(not(tarpaulin_include))]
+impl fmt::Debug for RemoteEdit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // skip raw JSON, too noisy
+        let Self { event_id, json: _, encryption_info } = self;
+
+        f.debug_struct("RemoteEdit")
+            .field("event_id", event_id)
+            .field("encryption_info", encryption_info)
+            .finish_non_exhaustive()
+    }
 }
 
 /// Where we got an event from.
@@ -112,7 +150,7 @@ impl fmt::Debug for RemoteEventTimelineItem {
             is_own,
             encryption_info,
             original_json: _,
-            latest_edit_json: _,
+            edits,
             is_highlighted,
             origin,
         } = self;
@@ -124,6 +162,7 @@ impl fmt::Debug for RemoteEventTimelineItem {
             .field("is_own", is_own)
             .field("is_highlighted", is_highlighted)
             .field("encryption_info", encryption_info)
+            .field("edits", edits)
             .field("origin", origin)
             .finish_non_exhaustive()
     }
@@ -23,15 +23,17 @@ use futures_core::Stream;
 use imbl::Vector;
 use itertools::Itertools;
 #[cfg(all(test, feature = "e2e-encryption"))]
-use matrix_sdk::crypto::OlmMachine;
+use matrix_sdk::{crypto::OlmMachine, encryption::TrustRequirement};
 use matrix_sdk::{
     deserialized_responses::SyncTimelineEvent,
-    event_cache::{paginator::Paginator, RoomEventCache},
+    event_cache::{paginator::Paginator, RoomEventCache, RoomEventCacheGap},
     send_queue::AbortSendHandle,
     Result, Room,
 };
 #[cfg(test)]
 use ruma::events::receipt::ReceiptEventContent;
+#[cfg(feature = "e2e-encryption")]
+use ruma::OwnedUserId;
 #[cfg(all(test, feature = "e2e-encryption"))]
 use ruma::RoomId;
 use ruma::{
@@ -58,12 +60,13 @@ use super::traits::Decryptor;
 use super::{
     event_handler::TimelineEventKind,
     event_item::RemoteEventOrigin,
+    polls::PollState,
     reactions::ReactionToggleResult,
     traits::RoomDataProvider,
     util::{rfind_event_by_id, rfind_event_item, RelativePosition},
     AnnotationKey, Error, EventSendState, EventTimelineItem, InReplyToDetails, Message,
     PaginationError, Profile, RepliedToEvent, TimelineDetails, TimelineFocus, TimelineItem,
-    TimelineItemContent, TimelineItemKind,
+    TimelineItemContent, TimelineItemKind, VirtualTimelineItem,
 };
 use crate::{
     timeline::{day_dividers::DayDividerAdjuster, TimelineEventFilterFn},
@@ -252,6 +255,7 @@ impl<P: RoomDataProvider> TimelineInner<P> {
         focus: TimelineFocus,
         internal_id_prefix: Option<String>,
         unable_to_decrypt_hook: Option<Arc<UtdHookManager>>,
+        group_state_items: bool,
     ) -> Self {
         let (focus_data, is_live) = match focus {
             TimelineFocus::Live => (TimelineFocusData::Live, true),
@@ -269,6 +273,7 @@ impl<P: RoomDataProvider> TimelineInner<P> {
             is_live,
             internal_id_prefix,
             unable_to_decrypt_hook,
+            group_state_items,
         );
 
         Self {
@@ -300,6 +305,7 @@ impl<P: RoomDataProvider> TimelineInner<P> {
                 let has_events = !events.is_empty();
 
                 self.replace_with_initial_remote_events(events, RemoteEventOrigin::Cache).await;
+                self.add_gaps(room_event_cache.gaps().await).await;
 
                 Ok(has_events)
             }
@@ -569,6 +575,42 @@ impl<P: RoomDataProvider> TimelineInner<P> {
         self.state.write().await.clear();
     }
 
+    /// Add gaps in the history of the room to the timeline, if they're not
+    /// already there.
+    pub(super) async fn add_gaps(&self, gaps: Vec<RoomEventCacheGap>) {
+        self.state.write().await.add_gaps(gaps);
+    }
+
+    /// Get the back-pagination token of the gap item with the given unique
+    /// ID.
+    pub(super) async fn gap_token(&self, gap_id: &str) -> Option<String> {
+        let state = self.state.read().await;
+        state.items.iter().find_map(|item| match item.kind() {
+            TimelineItemKind::Virtual(VirtualTimelineItem::Gap { prev_token })
+                if item.unique_id() == gap_id =>
+            {
+                Some(prev_token.clone())
+            }
+            _ => None,
+        })
+    }
+
+    /// Insert the events of a gap after the gap item with the given unique
+    /// ID.
+    ///
+    /// Events should be ordered in *reverse* topological order, that is,
+    /// `events[0]` is the most recent. `prev_token` is the token of what
+    /// remains of the gap, if it hasn't been closed.
+    pub(super) async fn fill_gap(
+        &self,
+        gap_id: &str,
+        events: Vec<impl Into<SyncTimelineEvent>>,
+        prev_token: Option<String>,
+    ) -> Result<HandleManyEventsResult, Error> {
+        let mut state = self.state.write().await;
+        state.fill_gap(gap_id, events, prev_token, &self.room_data_provider, &self.settings).await
+    }
+
     /// Replaces the content of the current timeline with initial events.
     ///
     /// Also sets up read receipts and the read marker for a live timeline of a
@@ -792,11 +834,13 @@ impl<P: RoomDataProvider> TimelineInner<P> {
 
     pub(super) async fn discard_local_echo(&self, txn_id: &TransactionId) -> bool {
         let mut state = self.state.write().await;
+        let mut txn = state.transaction();
 
         if let Some((idx, _)) =
-            rfind_event_item(&state.items, |it| it.transaction_id() == Some(txn_id))
+            rfind_event_item(&txn.items, |it| it.transaction_id() == Some(txn_id))
         {
-            state.items.remove(idx);
+            txn.items.remove(idx);
+            txn.commit();
             debug!("Discarded local echo");
             true
         } else {
@@ -805,6 +849,70 @@ impl<P: RoomDataProvider> TimelineInner<P> {
         }
     }
 
+    /// Remove the local echo of the poll response with the given transaction
+    /// ID from the poll it answers, because it won't be sent.
+    ///
+    /// Returns whether the response was found.
+    pub(super) async fn discard_local_poll_response(&self, txn_id: &TransactionId) -> bool {
+        let found = self
+            .update_local_poll_response(txn_id, |poll_state| {
+                poll_state.remove_local_response(txn_id);
+            })
+            .await;
+
+        if found {
+            debug!("Discarded local poll response");
+        }
+        found
+    }
+
+    /// Set or clear the error that happened when sending the local echo of the
+    /// poll response with the given transaction ID.
+    ///
+    /// Returns whether the response was found.
+    pub(super) async fn set_local_poll_response_send_error(
+        &self,
+        txn_id: &TransactionId,
+        error: Option<Arc<matrix_sdk::Error>>,
+    ) -> bool {
+        self.update_local_poll_response(txn_id, |poll_state| {
+            poll_state.set_local_response_send_error(txn_id, error);
+        })
+        .await
+    }
+
+    /// Update the poll that has a local echo of the response with the given
+    /// transaction ID.
+    ///
+    /// Returns whether the response was found.
+    async fn update_local_poll_response(
+        &self,
+        txn_id: &TransactionId,
+        update: impl FnOnce(&mut PollState),
+    ) -> bool {
+        let mut state = self.state.write().await;
+
+        let found = rfind_event_item(&state.items, |it| {
+            as_variant!(it.content(), TimelineItemContent::Poll)
+                .is_some_and(|poll| poll.has_local_response(txn_id))
+        });
+        let Some((idx, item)) = found else {
+            return false;
+        };
+
+        let TimelineItemContent::Poll(poll_state) = item.content() else { unreachable!() };
+        let mut poll_state = poll_state.clone();
+        update(&mut poll_state);
+
+        let new_item = TimelineItem::new(
+            item.with_content(TimelineItemContent::Poll(poll_state), None),
+            item.internal_id.to_owned(),
+        );
+        state.items.set(idx, new_item);
+
+        true
+    }
+
     #[cfg(test)]
     pub(super) async fn set_fully_read_event(&self, fully_read_event_id: OwnedEventId) {
         self.state.write().await.set_fully_read_event(fully_read_event_id);
@@ -830,13 +938,113 @@ impl<P: RoomDataProvider> TimelineInner<P> {
         self.retry_event_decryption_inner((olm_machine, room_id.to_owned()), session_ids).await
     }
 
+    #[cfg(all(test, feature = "e2e-encryption"))]
+    pub(super) async fn retry_event_decryption_with_trust_requirement_test(
+        &self,
+        room_id: &RoomId,
+        olm_machine: OlmMachine,
+        trust_requirement: TrustRequirement,
+        session_ids: Option<BTreeSet<String>>,
+    ) {
+        self.retry_event_decryption_inner(
+            (olm_machine, room_id.to_owned(), trust_requirement),
+            session_ids,
+        )
+        .await
+    }
+
+    #[cfg(all(test, feature = "e2e-encryption"))]
+    pub(super) async fn reevaluate_sender_trust_test(
+        &self,
+        room_id: &RoomId,
+        olm_machine: OlmMachine,
+        trust_requirement: TrustRequirement,
+        user_ids: BTreeSet<OwnedUserId>,
+    ) {
+        self.reevaluate_sender_trust_inner(
+            (olm_machine, room_id.to_owned(), trust_requirement),
+            user_ids,
+        )
+        .await
+    }
+
+    /// Hide the decrypted events sent by the given users, if their sender
+    /// doesn't satisfy the decryption trust requirement anymore.
+    ///
+    /// The events are turned into unable-to-decrypt items, which are shown
+    /// again by [`Self::retry_event_decryption()`] once their sender is
+    /// trusted again.
+    #[cfg(feature = "e2e-encryption")]
+    #[instrument(skip(self, room), fields(room_id = ?room.room_id()))]
+    pub(super) async fn reevaluate_sender_trust(
+        &self,
+        room: &Room,
+        user_ids: BTreeSet<OwnedUserId>,
+    ) {
+        self.reevaluate_sender_trust_inner(room.to_owned(), user_ids).await
+    }
+
+    #[cfg(feature = "e2e-encryption")]
+    async fn reevaluate_sender_trust_inner(
+        &self,
+        decryptor: impl Decryptor,
+        user_ids: BTreeSet<OwnedUserId>,
+    ) {
+        use matrix_sdk::crypto::MegolmError;
+
+        use super::EncryptedMessage;
+
+        let mut state = self.state.write().await;
+        let mut txn = state.transaction();
+
+        for idx in 0..txn.items.len() {
+            let item = txn.items[idx].clone();
+            let Some(event_item) = item.as_event() else { continue };
+
+            if !user_ids.contains(event_item.sender())
+                || event_item.content().as_unable_to_decrypt().is_some()
+            {
+                continue;
+            }
+
+            let Some(encryption_info) = event_item.encryption_info() else { continue };
+
+            match decryptor.check_sender_trust_impl(encryption_info).await {
+                Ok(()) => {}
+                Err(matrix_sdk::Error::MegolmError(MegolmError::SenderIdentityNotTrusted(
+                    level,
+                ))) => {
+                    info!(
+                        ?level,
+                        event_id = ?event_item.event_id(),
+                        "Event sender doesn't satisfy the decryption trust requirement anymore"
+                    );
+
+                    let utd = EncryptedMessage::hidden(encryption_info);
+
+                    let new_item =
+                        event_item.with_content(TimelineItemContent::UnableToDecrypt(utd), None);
+                    txn.items.set(idx, item.with_kind(new_item));
+                }
+                Err(e) => {
+                    warn!("Couldn't check the trust in the sender of an event: {e}");
+                }
+            }
+        }
+
+        txn.commit();
+    }
+
     #[cfg(feature = "e2e-encryption")]
     async fn retry_event_decryption_inner(
         &self,
         decryptor: impl Decryptor,
         session_ids: Option<BTreeSet<String>>,
     ) {
-        use matrix_sdk::crypto::types::events::UtdCause;
+        use matrix_sdk::{
+            crypto::{types::events::UtdCause, MegolmError},
+            deserialized_responses::TimelineEvent,
+        };
 
         use super::EncryptedMessage;
 
@@ -854,15 +1062,23 @@ impl<P: RoomDataProvider> TimelineInner<P> {
             .items
             .iter()
             .enumerate()
-            .filter_map(|(idx, item)| match item.as_event()?.content().as_unable_to_decrypt()? {
-                EncryptedMessage::MegolmV1AesSha2 { session_id, .. }
-                    if should_retry(session_id) =>
-                {
-                    Some(idx)
+            .filter_map(|(idx, item)| {
+                let event_item = item.as_event()?;
+
+                match event_item.content().as_unable_to_decrypt()? {
+                    EncryptedMessage::MegolmV1AesSha2 { session_id, .. }
+                        if should_retry(session_id) =>
+                    {
+                        Some(idx)
+                    }
+                    // An event hidden because of its sender, whose session isn't known.
+                    EncryptedMessage::Unknown if event_item.encryption_info().is_some() => {
+                        Some(idx)
+                    }
+                    EncryptedMessage::MegolmV1AesSha2 { .. }
+                    | EncryptedMessage::OlmV1Curve25519AesSha2 { .. }
+                    | EncryptedMessage::Unknown => None,
                 }
-                EncryptedMessage::MegolmV1AesSha2 { .. }
-                | EncryptedMessage::OlmV1Curve25519AesSha2 { .. }
-                | EncryptedMessage::Unknown => None,
             })
             .collect();
 
@@ -885,18 +1101,17 @@ impl<P: RoomDataProvider> TimelineInner<P> {
                 async move {
                     let event_item = item.as_event()?;
 
-                    let session_id = match event_item.content().as_unable_to_decrypt()? {
+                    match event_item.content().as_unable_to_decrypt()? {
                         EncryptedMessage::MegolmV1AesSha2 { session_id, .. }
                             if should_retry(session_id) =>
                         {
-                            session_id
+                            tracing::Span::current().record("session_id", session_id);
                         }
+                        EncryptedMessage::Unknown if event_item.encryption_info().is_some() => {}
                         EncryptedMessage::MegolmV1AesSha2 { .. }
                         | EncryptedMessage::OlmV1Curve25519AesSha2 { .. }
                         | EncryptedMessage::Unknown => return None,
-                    };
-
-                    tracing::Span::current().record("session_id", session_id);
+                    }
 
                     let Some(remote_event) = event_item.as_remote() else {
                         error!("Key for unable-to-decrypt timeline item is not an event ID");
@@ -910,6 +1125,23 @@ impl<P: RoomDataProvider> TimelineInner<P> {
                         return None;
                     };
 
+                    // The event was decrypted before but got hidden because the trust in its
+                    // sender dropped, check whether the sender is trusted again.
+                    if let Some(encryption_info) = event_item.encryption_info() {
+                        return match decryptor.check_sender_trust_impl(encryption_info).await {
+                            Ok(()) => {
+                                trace!("The sender of a hidden event is trusted again");
+
+                                Some(RetryDecryptionOutcome::Decrypted(TimelineEvent {
+                                    event: original_json.clone().cast(),
+                                    encryption_info: Some(encryption_info.clone()),
+                                    push_actions: None,
+                                }))
+                            }
+                            Err(_) => None,
+                        };
+                    }
+
                     match decryptor.decrypt_event_impl(original_json).await {
                         Ok(event) => {
                             trace!(
@@ -923,7 +1155,18 @@ impl<P: RoomDataProvider> TimelineInner<P> {
                                 hook.on_late_decrypt(&remote_event.event_id, cause).await;
                             }
 
-                            Some(event)
+                            Some(RetryDecryptionOutcome::Decrypted(event))
+                        }
+                        Err(matrix_sdk::Error::MegolmError(
+                            MegolmError::SenderIdentityNotTrusted(level),
+                        )) => {
+                            info!(
+                                ?level,
+                                "Event sender doesn't satisfy the decryption trust requirement"
+                            );
+                            Some(RetryDecryptionOutcome::StillUndecryptable(
+                                UtdCause::UntrustedSender,
+                            ))
                         }
                         Err(e) => {
                             info!("Failed to decrypt event after receiving room key: {e}");
@@ -1242,6 +1485,16 @@ pub(super) struct HandleManyEventsResult {
     pub items_updated: u64,
 }
 
+/// The outcome of retrying to decrypt an unable-to-decrypt timeline item.
+#[cfg(feature = "e2e-encryption")]
+pub(super) enum RetryDecryptionOutcome {
+    /// The event could be decrypted.
+    Decrypted(matrix_sdk_base::deserialized_responses::TimelineEvent),
+
+    /// The event still can't be shown, but we learned a more precise cause.
+    StillUndecryptable(matrix_sdk::crypto::types::events::UtdCause),
+}
+
 async fn fetch_replied_to_event(
     mut state: RwLockWriteGuard<'_, TimelineInnerState>,
     index: usize,
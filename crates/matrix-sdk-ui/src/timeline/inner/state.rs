@@ -15,9 +15,12 @@
 use std::{collections::VecDeque, future::Future, sync::Arc};
 
 use eyeball_im::{ObservableVector, ObservableVectorTransaction, ObservableVectorTransactionEntry};
+use imbl::Vector;
 use indexmap::IndexMap;
-use matrix_sdk::{deserialized_responses::SyncTimelineEvent, send_queue::AbortSendHandle};
-use matrix_sdk_base::deserialized_responses::TimelineEvent;
+use matrix_sdk::{
+    deserialized_responses::SyncTimelineEvent, event_cache::RoomEventCacheGap,
+    send_queue::AbortSendHandle,
+};
 #[cfg(test)]
 use ruma::events::receipt::ReceiptEventContent;
 use ruma::{
@@ -29,7 +32,11 @@ use ruma::{
 };
 use tracing::{debug, error, instrument, trace, warn};
 
+#[cfg(feature = "e2e-encryption")]
+use super::RetryDecryptionOutcome;
 use super::{HandleManyEventsResult, ReactionState, TimelineInnerSettings};
+#[cfg(feature = "e2e-encryption")]
+use crate::timeline::TimelineItemContent;
 use crate::{
     events::SyncTimelineEventWithoutContent,
     timeline::{
@@ -42,10 +49,11 @@ use crate::{
         polls::PollPendingEvents,
         reactions::{ReactionToggleResult, Reactions},
         read_receipts::ReadReceipts,
+        state_groups::adjust_state_groups,
         traits::RoomDataProvider,
         util::{rfind_event_by_id, rfind_event_item, RelativePosition},
         AnnotationKey, Error as TimelineError, Profile, ReactionSenderData, TimelineItem,
-        TimelineItemKind,
+        TimelineItemKind, VirtualTimelineItem,
     },
     unable_to_decrypt_hook::UtdHookManager,
 };
@@ -69,6 +77,9 @@ pub(in crate::timeline) struct TimelineInnerState {
 
     /// Is the timeline focused on a live view?
     pub is_live_timeline: bool,
+
+    /// Are consecutive state items grouped under a virtual item?
+    pub group_state_items: bool,
 }
 
 impl TimelineInnerState {
@@ -77,6 +88,7 @@ impl TimelineInnerState {
         is_live_timeline: bool,
         internal_id_prefix: Option<String>,
         unable_to_decrypt_hook: Option<Arc<UtdHookManager>>,
+        group_state_items: bool,
     ) -> Self {
         Self {
             // Upstream default capacity is currently 16, which is making
@@ -89,6 +101,7 @@ impl TimelineInnerState {
                 unable_to_decrypt_hook,
             ),
             is_live_timeline,
+            group_state_items,
         }
     }
 
@@ -118,6 +131,44 @@ impl TimelineInnerState {
         handle_many_res
     }
 
+    /// Add gaps in the history of the room to the timeline.
+    ///
+    /// Each gap is inserted before the event that follows it, or after the
+    /// latest remote event if it has no such event. Gaps that are already in
+    /// the timeline are ignored.
+    pub(super) fn add_gaps(&mut self, gaps: Vec<RoomEventCacheGap>) {
+        if gaps.is_empty() {
+            return;
+        }
+
+        let mut txn = self.transaction();
+        for gap in gaps {
+            txn.add_gap(gap);
+        }
+        txn.commit();
+    }
+
+    /// Insert the events of a gap after the gap item with the given unique
+    /// ID, and update the gap item with what remains of the gap.
+    ///
+    /// Events should be ordered in *reverse* topological order, that is,
+    /// `events[0]` is the most recent.
+    #[instrument(skip(self, events, room_data_provider, settings))]
+    pub(super) async fn fill_gap<P: RoomDataProvider>(
+        &mut self,
+        gap_id: &str,
+        events: Vec<impl Into<SyncTimelineEvent>>,
+        prev_token: Option<String>,
+        room_data_provider: &P,
+        settings: &TimelineInnerSettings,
+    ) -> Result<HandleManyEventsResult, TimelineError> {
+        let mut txn = self.transaction();
+        let res = txn.fill_gap(gap_id, events, prev_token, room_data_provider, settings).await;
+        txn.commit();
+
+        res
+    }
+
     /// Marks the given event as fully read, using the read marker received from
     /// sync.
     pub(super) fn handle_fully_read_marker(&mut self, fully_read_event_id: OwnedEventId) {
@@ -207,7 +258,7 @@ impl TimelineInnerState {
         room_data_provider: &P,
         settings: &TimelineInnerSettings,
     ) where
-        Fut: Future<Output = Option<TimelineEvent>>,
+        Fut: Future<Output = Option<RetryDecryptionOutcome>>,
     {
         let mut txn = self.transaction();
 
@@ -219,8 +270,26 @@ impl TimelineInnerState {
         let mut offset = 0;
         for idx in retry_indices {
             let idx = idx - offset;
-            let Some(mut event) = retry_one(txn.items[idx].clone()).await else {
-                continue;
+            let item = txn.items[idx].clone();
+            let mut event = match retry_one(item.clone()).await {
+                Some(RetryDecryptionOutcome::Decrypted(event)) => event,
+                Some(RetryDecryptionOutcome::StillUndecryptable(cause)) => {
+                    // Only the cause changed, update the item in place.
+                    let Some(event_item) = item.as_event() else { continue };
+                    let Some(utd) = event_item
+                        .content()
+                        .as_unable_to_decrypt()
+                        .and_then(|utd| utd.with_cause(cause))
+                    else {
+                        continue;
+                    };
+
+                    let new_item =
+                        event_item.with_content(TimelineItemContent::UnableToDecrypt(utd), None);
+                    txn.items.set(idx, item.with_kind(new_item));
+                    continue;
+                }
+                None => continue,
             };
 
             event.push_actions = push_rules_context.as_ref().map(|(push_rules, push_context)| {
@@ -378,6 +447,7 @@ impl TimelineInnerState {
             previous_meta: &mut self.meta,
             meta,
             is_live_timeline: self.is_live_timeline,
+            group_state_items: self.group_state_items,
         }
     }
 }
@@ -395,6 +465,9 @@ pub(in crate::timeline) struct TimelineInnerStateTransaction<'a> {
     /// Is the timeline focused on a live view?
     pub is_live_timeline: bool,
 
+    /// Are consecutive state items grouped under a virtual item?
+    group_state_items: bool,
+
     /// Pointer to the previous meta, only used during [`Self::commit`].
     previous_meta: &'a mut TimelineInnerMetadata,
 }
@@ -451,6 +524,127 @@ impl TimelineInnerStateTransaction<'_> {
         total
     }
 
+    /// Insert a gap item, if it's not already in the timeline.
+    fn add_gap(&mut self, gap: RoomEventCacheGap) {
+        let RoomEventCacheGap { prev_token, next_event_id } = gap;
+
+        if self.items.iter().any(|item| {
+            matches!(
+                item.kind(),
+                TimelineItemKind::Virtual(VirtualTimelineItem::Gap { prev_token: token })
+                    if *token == prev_token
+            )
+        }) {
+            trace!("Skipping gap that is already in the timeline");
+            return;
+        }
+
+        // Find the first visible event after the gap, if any.
+        let next_item_idx = next_event_id.and_then(|next_event_id| {
+            self.meta
+                .all_events
+                .iter()
+                .skip_while(|ev| ev.event_id != next_event_id)
+                .filter(|ev| ev.visible)
+                .find_map(|ev| Some(rfind_event_by_id(&self.items, &ev.event_id)?.0))
+        });
+
+        let insert_idx = match next_item_idx {
+            // Keep the day divider of the next event after the gap.
+            Some(idx) if idx > 0 && self.items[idx - 1].is_day_divider() => idx - 1,
+            Some(idx) => idx,
+            None => {
+                // Insert the gap after the latest remote event, or gap.
+                self.items
+                    .iter()
+                    .rposition(|item| {
+                        item.as_event().map_or_else(|| item.is_gap(), |ev| !ev.is_local_echo())
+                    })
+                    .map_or(0, |idx| idx + 1)
+            }
+        };
+
+        trace!(insert_idx, "Adding gap item");
+        let item = self.meta.new_timeline_item(VirtualTimelineItem::Gap { prev_token });
+
+        // Keep push semantics, if we're inserting at the front or the back.
+        if insert_idx == self.items.len() {
+            self.items.push_back(item);
+        } else if insert_idx == 0 {
+            self.items.push_front(item);
+        } else {
+            self.items.insert(insert_idx, item);
+        }
+    }
+
+    /// Insert the given remote events after the gap item with the given unique
+    /// ID, and replace or remove the gap item depending on whether the gap
+    /// has been closed.
+    ///
+    /// Events must be in *reverse* topological order: inserting them one by
+    /// one right after the gap results in the correct order.
+    async fn fill_gap<P: RoomDataProvider>(
+        &mut self,
+        gap_id: &str,
+        events: Vec<impl Into<SyncTimelineEvent>>,
+        prev_token: Option<String>,
+        room_data_provider: &P,
+        settings: &TimelineInnerSettings,
+    ) -> Result<HandleManyEventsResult, TimelineError> {
+        fn find_gap(items: &Vector<Arc<TimelineItem>>, gap_id: &str) -> Option<usize> {
+            items.iter().position(|item| item.is_gap() && item.unique_id() == gap_id)
+        }
+
+        if find_gap(&self.items, gap_id).is_none() {
+            return Err(TimelineError::GapNotInTimeline);
+        }
+
+        let mut total = HandleManyEventsResult::default();
+        let mut day_divider_adjuster = DayDividerAdjuster::default();
+
+        for event in events {
+            // The read marker might move while handling an event, so look for the gap every
+            // time.
+            let Some(gap_idx) = find_gap(&self.items, gap_id) else { break };
+            let position = TimelineItemPosition::At {
+                idx: gap_idx + 1,
+                origin: RemoteEventOrigin::Pagination,
+            };
+
+            let handle_one_res = self
+                .handle_remote_event(
+                    event.into(),
+                    position,
+                    room_data_provider,
+                    settings,
+                    &mut day_divider_adjuster,
+                )
+                .await;
+
+            total.items_added += handle_one_res.item_added as u64;
+            total.items_updated += handle_one_res.items_updated as u64;
+        }
+
+        if let Some(gap_idx) = find_gap(&self.items, gap_id) {
+            match prev_token {
+                Some(prev_token) => {
+                    trace!("Updating gap item");
+                    let item =
+                        self.items[gap_idx].with_kind(VirtualTimelineItem::Gap { prev_token });
+                    self.items.set(gap_idx, item);
+                }
+                None => {
+                    trace!("Gap is closed, removing gap item");
+                    self.items.remove(gap_idx);
+                }
+            }
+        }
+
+        self.adjust_day_dividers(day_divider_adjuster);
+
+        Ok(total)
+    }
+
     /// Handle a remote event.
     ///
     /// Returns the number of timeline updates that were made.
@@ -565,6 +759,7 @@ impl TimelineInnerStateTransaction<'_> {
             flow: Flow::Remote {
                 event_id: event_id.clone(),
                 raw_event: raw.clone(),
+                unable_to_decrypt_reason: event.unable_to_decrypt_reason,
                 txn_id,
                 position,
                 should_add,
@@ -583,9 +778,13 @@ impl TimelineInnerStateTransaction<'_> {
         // `VectorDiff::Clear` should be much more efficient to process for
         // subscribers.
         if self.items.iter().any(|item| item.is_local_echo()) {
-            // Remove all remote events and the read marker
+            // Remove all remote events, the read marker, the state groups and the gaps
             self.items.for_each(|entry| {
-                if entry.is_remote_event() || entry.is_read_marker() {
+                if entry.is_remote_event()
+                    || entry.is_read_marker()
+                    || entry.is_state_group()
+                    || entry.is_gap()
+                {
                     ObservableVectorTransactionEntry::remove(entry);
                 }
             });
@@ -629,7 +828,11 @@ impl TimelineInnerStateTransaction<'_> {
     }
 
     pub(super) fn commit(self) {
-        let Self { items, previous_meta, meta, .. } = self;
+        let Self { mut items, previous_meta, mut meta, group_state_items, .. } = self;
+
+        if group_state_items {
+            adjust_state_groups(&mut items, &mut meta);
+        }
 
         // Replace the pointer to the previous meta with the new one.
         *previous_meta = meta;
@@ -658,6 +861,22 @@ impl TimelineInnerStateTransaction<'_> {
 
                 self.meta.all_events.push_back(event_meta.base_meta());
             }
+            TimelineItemPosition::At { idx, .. } => {
+                // Insert the event before the first known event that follows it.
+                let next_pos = self
+                    .items
+                    .iter()
+                    .skip(idx)
+                    .filter_map(|item| item.as_event()?.event_id())
+                    .find_map(|event_id| {
+                        self.meta.all_events.iter().position(|ev| ev.event_id == event_id)
+                    });
+
+                match next_pos {
+                    Some(pos) => self.meta.all_events.insert(pos, event_meta.base_meta()),
+                    None => self.meta.all_events.push_back(event_meta.base_meta()),
+                }
+            }
             #[cfg(feature = "e2e-encryption")]
             TimelineItemPosition::Update(_) => {
                 if let Some(event) =
@@ -679,7 +898,9 @@ impl TimelineInnerStateTransaction<'_> {
         if settings.track_read_receipts
             && matches!(
                 position,
-                TimelineItemPosition::Start { .. } | TimelineItemPosition::End { .. }
+                TimelineItemPosition::Start { .. }
+                    | TimelineItemPosition::End { .. }
+                    | TimelineItemPosition::At { .. }
             )
         {
             self.load_read_receipts_for_event(event_meta.event_id, room_data_provider).await;
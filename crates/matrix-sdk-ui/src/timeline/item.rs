@@ -103,6 +103,14 @@ impl TimelineItem {
     pub(crate) fn is_read_marker(&self) -> bool {
         matches!(self.kind, TimelineItemKind::Virtual(VirtualTimelineItem::ReadMarker))
     }
+
+    pub(crate) fn is_state_group(&self) -> bool {
+        matches!(self.kind, TimelineItemKind::Virtual(VirtualTimelineItem::StateGroup(_)))
+    }
+
+    pub(crate) fn is_gap(&self) -> bool {
+        matches!(self.kind, TimelineItemKind::Virtual(VirtualTimelineItem::Gap { .. }))
+    }
 }
 
 impl Deref for TimelineItem {
@@ -0,0 +1,454 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Mentions and pills, when composing and when rendering a message.
+//!
+//! On the composer side, the user, room and room alias IDs written in a
+//! message are turned into `matrix.to` links, and fill the `m.mentions` of the
+//! message. On the renderer side, the links to Matrix entities of a formatted
+//! body are extracted as [`Pill`]s.
+
+use std::{fmt::Write as _, ops::Range};
+
+use as_variant::as_variant;
+#[cfg(feature = "markdown")]
+use ruma::events::room::message::FormattedBody;
+use ruma::{
+    events::{room::message::RoomMessageEventContentWithoutRelation, Mentions},
+    html::{Html, NodeRef},
+    matrix_uri::MatrixId,
+    MatrixToUri, MatrixUri, OwnedRoomAliasId, OwnedRoomId, OwnedUserId, RoomAliasId, RoomId,
+    UserId,
+};
+
+use super::Profile;
+
+/// A link to a Matrix entity in the formatted body of a message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Pill {
+    /// The user, room or event the link points to.
+    pub id: MatrixId,
+
+    /// The text of the link, as written by the sender.
+    pub text: String,
+}
+
+/// A [`Pill`] along with the profile of the room member it points to.
+#[derive(Clone, Debug)]
+pub struct ResolvedPill {
+    /// The pill.
+    pub pill: Pill,
+
+    /// The profile of the room member the pill points to.
+    ///
+    /// `None` if the pill doesn't point to a user, or if the user isn't a
+    /// known member of the room.
+    pub profile: Option<Profile>,
+}
+
+/// A reference to a Matrix entity written in the text of a message.
+#[derive(Debug)]
+enum Reference {
+    User(OwnedUserId),
+    Room(OwnedRoomId),
+    RoomAlias(OwnedRoomAliasId),
+    /// `@room`, to mention every member of the room.
+    AtRoom,
+}
+
+impl Reference {
+    fn parse(s: &str) -> Option<Self> {
+        if s == "@room" {
+            return Some(Self::AtRoom);
+        }
+
+        match s.chars().next()? {
+            '@' => UserId::parse(s).ok().map(Self::User),
+            '!' => RoomId::parse(s).ok().map(Self::Room),
+            '#' => RoomAliasId::parse(s).ok().map(Self::RoomAlias),
+            _ => None,
+        }
+    }
+
+    /// The `matrix.to` link to this reference, if it has one.
+    fn matrix_to_uri(&self) -> Option<MatrixToUri> {
+        match self {
+            Self::User(user_id) => Some(user_id.matrix_to_uri()),
+            Self::Room(room_id) => Some(room_id.matrix_to_uri()),
+            Self::RoomAlias(alias) => Some(alias.matrix_to_uri()),
+            Self::AtRoom => None,
+        }
+    }
+}
+
+/// Find the references to Matrix entities in the given text.
+///
+/// A reference must start a word. If `skip_code` is set, references in
+/// Markdown code spans and code blocks are ignored.
+fn find_references(text: &str, skip_code: bool) -> Vec<(Range<usize>, Reference)> {
+    let mut references = Vec::new();
+    // The length of the backtick run that opened the current code span, if any.
+    let mut code_fence = None;
+    let mut prev = None;
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((start, c)) = chars.next() {
+        if skip_code && c == '`' {
+            let mut len = 1;
+            while chars.next_if(|(_, c)| *c == '`').is_some() {
+                len += 1;
+            }
+
+            code_fence = match code_fence {
+                None => Some(len),
+                Some(open) if open == len => None,
+                open => open,
+            };
+            prev = Some(c);
+            continue;
+        }
+
+        let starts_word = prev.map_or(true, |prev: char| prev.is_whitespace() || prev == '(');
+        prev = Some(c);
+
+        if code_fence.is_some() || !starts_word || !matches!(c, '@' | '!' | '#') {
+            continue;
+        }
+
+        let end = text[start..]
+            .find(|c: char| c.is_whitespace() || matches!(c, '<' | '>' | '"' | '(' | ')'))
+            .map_or(text.len(), |len| start + len);
+        let candidate =
+            text[start..end].trim_end_matches(|c| matches!(c, '.' | ',' | ';' | ':' | '!' | '?'));
+
+        if let Some(reference) = Reference::parse(candidate) {
+            let end = start + candidate.len();
+            references.push((start..end, reference));
+
+            while chars.next_if(|(idx, _)| *idx < end).is_some() {}
+            prev = text[..end].chars().next_back();
+        }
+    }
+
+    references
+}
+
+/// Build the mentions of a message from its references.
+///
+/// The own user is never mentioned, since a message can't notify its sender.
+fn mentions(references: &[(Range<usize>, Reference)], own_user_id: &UserId) -> Mentions {
+    let user_ids = references
+        .iter()
+        .filter_map(|(_, reference)| as_variant!(reference, Reference::User(user_id) => user_id))
+        .filter(|user_id| *user_id != own_user_id)
+        .cloned();
+
+    let mut mentions = Mentions::with_user_ids(user_ids);
+    mentions.room = references.iter().any(|(_, reference)| matches!(reference, Reference::AtRoom));
+    mentions
+}
+
+/// Create the content of a plain text message, with its references turned
+/// into links in its formatted body and added to its mentions.
+///
+/// `@room` always sets the room mention; it's up to the caller to check
+/// whether the user is allowed to use it.
+pub(super) fn text_with_mentions(
+    text: &str,
+    own_user_id: &UserId,
+) -> RoomMessageEventContentWithoutRelation {
+    let references = find_references(text, false);
+
+    let mut html = String::new();
+    let mut has_links = false;
+    let mut last = 0;
+
+    for (range, reference) in &references {
+        push_html_escaped(&mut html, &text[last..range.start]);
+
+        if let Some(uri) = reference.matrix_to_uri() {
+            has_links = true;
+            write!(html, "<a href=\"{uri}\">").unwrap();
+            push_html_escaped(&mut html, &text[range.clone()]);
+            html.push_str("</a>");
+        } else {
+            push_html_escaped(&mut html, &text[range.clone()]);
+        }
+
+        last = range.end;
+    }
+
+    push_html_escaped(&mut html, &text[last..]);
+
+    let mut content = if has_links {
+        RoomMessageEventContentWithoutRelation::text_html(text, html)
+    } else {
+        RoomMessageEventContentWithoutRelation::text_plain(text)
+    };
+    content.mentions = Some(mentions(&references, own_user_id));
+
+    content
+}
+
+/// Create the content of a Markdown message, with its references turned into
+/// links in its formatted body and added to its mentions.
+///
+/// The references in code spans and code blocks are left untouched. `@room`
+/// always sets the room mention; it's up to the caller to check whether the
+/// user is allowed to use it.
+#[cfg(feature = "markdown")]
+pub(super) fn markdown_with_mentions(
+    text: &str,
+    own_user_id: &UserId,
+) -> RoomMessageEventContentWithoutRelation {
+    let references = find_references(text, true);
+
+    let mut markdown = String::new();
+    let mut has_links = false;
+    let mut last = 0;
+
+    for (range, reference) in &references {
+        markdown.push_str(&text[last..range.start]);
+
+        if let Some(uri) = reference.matrix_to_uri() {
+            has_links = true;
+            markdown.push('[');
+            for c in text[range.clone()].chars() {
+                if matches!(c, '\\' | '[' | ']' | '*' | '_' | '`') {
+                    markdown.push('\\');
+                }
+                markdown.push(c);
+            }
+            write!(markdown, "]({uri})").unwrap();
+        } else {
+            markdown.push_str(&text[range.clone()]);
+        }
+
+        last = range.end;
+    }
+
+    markdown.push_str(&text[last..]);
+
+    // The plain text body keeps the text as written, only the formatted body
+    // contains the links.
+    let mut content = match has_links.then(|| FormattedBody::markdown(&markdown)).flatten() {
+        Some(formatted) => RoomMessageEventContentWithoutRelation::text_html(text, formatted.body),
+        None => RoomMessageEventContentWithoutRelation::text_markdown(text),
+    };
+    content.mentions = Some(mentions(&references, own_user_id));
+
+    content
+}
+
+fn push_html_escaped(html: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            '\'' => html.push_str("&#39;"),
+            '\n' => html.push_str("<br>"),
+            c => html.push(c),
+        }
+    }
+}
+
+/// Extract the pills of the given HTML formatted body.
+pub(super) fn extract_pills(html: &str) -> Vec<Pill> {
+    let html = Html::parse(html);
+    let mut pills = Vec::new();
+
+    for node in html.children() {
+        collect_pills(&node, &mut pills);
+    }
+
+    pills
+}
+
+fn collect_pills(node: &NodeRef<'_>, pills: &mut Vec<Pill>) {
+    if let Some(element) = node.as_element() {
+        if &*element.name.local == "a" {
+            let id = element
+                .attrs
+                .iter()
+                .find(|attr| &*attr.name.local == "href")
+                .and_then(|href| parse_matrix_id(&href.value));
+
+            if let Some(id) = id {
+                let mut text = String::new();
+                collect_text(node, &mut text);
+                pills.push(Pill { id, text });
+                return;
+            }
+        }
+    }
+
+    for child in node.children() {
+        collect_pills(&child, pills);
+    }
+}
+
+pub(super) fn collect_text(node: &NodeRef<'_>, text: &mut String) {
+    if let Some(node_text) = node.as_text() {
+        text.push_str(node_text);
+    }
+
+    for child in node.children() {
+        collect_text(&child, text);
+    }
+}
+
+/// Parse the ID of a Matrix entity from a `matrix.to` link or a `matrix:`
+/// URI.
+pub(super) fn parse_matrix_id(uri: &str) -> Option<MatrixId> {
+    if let Ok(uri) = MatrixToUri::parse(uri) {
+        return Some(uri.id().clone());
+    }
+
+    MatrixUri::parse(uri).ok().map(|uri| uri.id().clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_matches2::assert_let;
+    use ruma::{
+        event_id,
+        events::room::message::{MessageType, TextMessageEventContent},
+        matrix_uri::MatrixId,
+        owned_user_id, room_id, user_id,
+    };
+
+    use super::{extract_pills, text_with_mentions, Pill};
+
+    #[test]
+    fn test_text_with_mentions() {
+        let own_user_id = user_id!("@me:localhost");
+        let content =
+            text_with_mentions("Hi @alice:localhost, @me:localhost and <b>@bob</b>!", own_user_id);
+
+        assert_let!(
+            MessageType::Text(TextMessageEventContent { body, formatted: Some(formatted), .. }) =
+                content.msgtype
+        );
+        assert_eq!(body, "Hi @alice:localhost, @me:localhost and <b>@bob</b>!");
+        assert_eq!(
+            formatted.body,
+            "Hi <a href=\"https://matrix.to/#/@alice:localhost\">@alice:localhost</a>, \
+             <a href=\"https://matrix.to/#/@me:localhost\">@me:localhost</a> and \
+             &lt;b&gt;@bob&lt;/b&gt;!"
+        );
+
+        let mentions = content.mentions.unwrap();
+        assert_eq!(
+            mentions.user_ids.into_iter().collect::<Vec<_>>(),
+            [owned_user_id!("@alice:localhost")]
+        );
+        assert!(!mentions.room);
+    }
+
+    #[test]
+    fn test_text_with_room_references() {
+        let content = text_with_mentions(
+            "@room see #rust:localhost and !room:localhost. Mail me@localhost",
+            user_id!("@me:localhost"),
+        );
+
+        assert_let!(
+            MessageType::Text(TextMessageEventContent { formatted: Some(formatted), .. }) =
+                content.msgtype
+        );
+        assert_eq!(
+            formatted.body,
+            "@room see <a href=\"https://matrix.to/#/%23rust:localhost\">#rust:localhost</a> and \
+             <a href=\"https://matrix.to/#/!room:localhost\">!room:localhost</a>. \
+             Mail me@localhost"
+        );
+
+        let mentions = content.mentions.unwrap();
+        assert!(mentions.user_ids.is_empty());
+        assert!(mentions.room);
+    }
+
+    #[test]
+    fn test_text_without_references() {
+        let content = text_with_mentions("Hello, world!", user_id!("@me:localhost"));
+
+        assert_let!(
+            MessageType::Text(TextMessageEventContent { formatted: None, .. }) = content.msgtype
+        );
+        let mentions = content.mentions.unwrap();
+        assert!(mentions.user_ids.is_empty());
+        assert!(!mentions.room);
+    }
+
+    #[test]
+    fn test_extract_pills() {
+        let pills = extract_pills(
+            "<p>Hi <a href=\"https://matrix.to/#/@alice:localhost\">Alice <b>A.</b></a>, look at \
+             <a href=\"https://matrix.to/#/!room:localhost/$event\">this</a> in \
+             <a href=\"matrix:r/rust:localhost\">#rust</a>, or \
+             <a href=\"https://example.org\">here</a>.</p>",
+        );
+
+        assert_eq!(
+            pills,
+            [
+                Pill {
+                    id: MatrixId::User(owned_user_id!("@alice:localhost")),
+                    text: "Alice A.".to_owned(),
+                },
+                Pill {
+                    id: MatrixId::Event(
+                        room_id!("!room:localhost").to_owned().into(),
+                        event_id!("$event").to_owned(),
+                    ),
+                    text: "this".to_owned(),
+                },
+                Pill {
+                    id: MatrixId::RoomAlias("#rust:localhost".try_into().unwrap()),
+                    text: "#rust".to_owned(),
+                },
+            ]
+        );
+    }
+
+    #[cfg(feature = "markdown")]
+    #[test]
+    fn test_markdown_with_mentions() {
+        use super::markdown_with_mentions;
+
+        let content = markdown_with_mentions(
+            "**Hi** @alice:localhost, not `@bob:localhost`",
+            user_id!("@me:localhost"),
+        );
+
+        assert_let!(
+            MessageType::Text(TextMessageEventContent { body, formatted: Some(formatted), .. }) =
+                content.msgtype
+        );
+        assert_eq!(body, "**Hi** @alice:localhost, not `@bob:localhost`");
+        assert_eq!(
+            formatted.body,
+            "<strong>Hi</strong> <a href=\"https://matrix.to/#/@alice:localhost\">\
+             @alice:localhost</a>, not <code>@bob:localhost</code>"
+        );
+
+        let mentions = content.mentions.unwrap();
+        assert_eq!(
+            mentions.user_ids.into_iter().collect::<Vec<_>>(),
+            [owned_user_id!("@alice:localhost")]
+        );
+    }
+}
@@ -36,9 +36,14 @@ use pin_project_lite::pin_project;
 use ruma::{
     api::client::receipt::create_receipt::v3::ReceiptType,
     events::{
-        poll::unstable_start::{
-            ReplacementUnstablePollStartEventContent, UnstablePollStartContentBlock,
-            UnstablePollStartEventContent,
+        poll::{
+            start::PollKind,
+            unstable_end::UnstablePollEndEventContent,
+            unstable_response::UnstablePollResponseEventContent,
+            unstable_start::{
+                ReplacementUnstablePollStartEventContent, UnstablePollStartContentBlock,
+                UnstablePollStartEventContent,
+            },
         },
         reaction::ReactionEventContent,
         receipt::{Receipt, ReceiptThread},
@@ -52,6 +57,7 @@ use ruma::{
         },
         AnyMessageLikeEventContent, AnySyncTimelineEvent,
     },
+    matrix_uri::MatrixId,
     uint, EventId, MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedTransactionId, RoomVersionId,
     TransactionId, UserId,
 };
@@ -62,6 +68,7 @@ use self::{
     error::{RedactEventError, SendEventError},
     event_item::EventTimelineItemKind,
     futures::SendAttachment,
+    traits::RoomDataProvider,
     util::rfind_event_item,
 };
 
@@ -74,37 +81,45 @@ pub mod event_type_filter;
 pub mod futures;
 mod inner;
 mod item;
+mod mentions;
 mod pagination;
 mod polls;
 mod reactions;
 mod read_receipts;
+pub mod rich_text;
 mod sliding_sync_ext;
+mod state_groups;
 #[cfg(test)]
 mod tests;
 #[cfg(feature = "e2e-encryption")]
 mod to_device;
 mod traits;
+mod url_previews;
 mod util;
 mod virtual_item;
 
 pub use self::{
     builder::TimelineBuilder,
-    error::{Error, PaginationError, UnsupportedEditItem, UnsupportedReplyItem},
+    error::{
+        EditHistoryError, Error, PaginationError, PollError, UnsupportedEditItem,
+        UnsupportedReplyItem,
+    },
     event_item::{
-        AnyOtherFullStateEventContent, BundledReactions, EncryptedMessage, EventItemOrigin,
-        EventSendState, EventTimelineItem, InReplyToDetails, MemberProfileChange, MembershipChange,
-        Message, OtherState, Profile, ReactionGroup, RepliedToEvent, RoomMembershipChange, Sticker,
-        TimelineDetails, TimelineItemContent,
+        AnyOtherFullStateEventContent, BundledReactions, EditVersion, EncryptedMessage,
+        EventItemOrigin, EventSendState, EventTimelineItem, InReplyToDetails, MemberProfileChange,
+        MembershipChange, Message, OtherState, Profile, ReactionGroup, RepliedToEvent,
+        RoomMembershipChange, Sticker, TimelineDetails, TimelineItemContent,
     },
     event_type_filter::TimelineEventTypeFilter,
     inner::default_event_filter,
     item::{TimelineItem, TimelineItemKind},
+    mentions::{Pill, ResolvedPill},
     pagination::LiveBackPaginationStatus,
-    polls::PollResult,
+    polls::{new_poll_content, PollResult},
     reactions::ReactionSenderData,
     sliding_sync_ext::SlidingSyncRoomExt,
     traits::RoomExt,
-    virtual_item::VirtualTimelineItem,
+    virtual_item::{StateGroup, StateGroupSummary, VirtualTimelineItem},
 };
 use self::{
     inner::{ReactionAction, TimelineInner},
@@ -279,6 +294,71 @@ impl Timeline {
         (items, stream)
     }
 
+    /// Create the content of a text message, turning the user, room and room
+    /// alias IDs it contains into `matrix.to` links and filling its mentions.
+    ///
+    /// The mentioned users are the users whose ID is written in the text,
+    /// except the own user. `@room` mentions the whole room only if the own
+    /// user is allowed to trigger room notifications.
+    ///
+    /// The returned content can be sent with [`Timeline::send_reply()`], or
+    /// with [`Timeline::send()`] after calling
+    /// [`RoomMessageEventContentWithoutRelation::with_relation()`] on it.
+    pub async fn text_with_mentions(
+        &self,
+        text: &str,
+    ) -> Result<RoomMessageEventContentWithoutRelation> {
+        let content = mentions::text_with_mentions(text, self.room().own_user_id());
+        self.check_room_mention(content).await
+    }
+
+    /// Create the content of a Markdown message, turning the user, room and
+    /// room alias IDs it contains into `matrix.to` links and filling its
+    /// mentions.
+    ///
+    /// IDs in code spans and code blocks are left untouched. See
+    /// [`Timeline::text_with_mentions()`] for the details.
+    #[cfg(feature = "markdown")]
+    pub async fn markdown_with_mentions(
+        &self,
+        text: &str,
+    ) -> Result<RoomMessageEventContentWithoutRelation> {
+        let content = mentions::markdown_with_mentions(text, self.room().own_user_id());
+        self.check_room_mention(content).await
+    }
+
+    /// Remove the room mention of the given content if the own user isn't
+    /// allowed to trigger room notifications.
+    async fn check_room_mention(
+        &self,
+        mut content: RoomMessageEventContentWithoutRelation,
+    ) -> Result<RoomMessageEventContentWithoutRelation> {
+        if let Some(mentions) = content.mentions.as_mut().filter(|mentions| mentions.room) {
+            let room = self.room();
+            mentions.room = room.can_user_trigger_room_notification(room.own_user_id()).await?;
+        }
+
+        Ok(content)
+    }
+
+    /// Get the pills of the given message, along with the profile of the room
+    /// members they point to.
+    ///
+    /// See [`Message::pills()`].
+    pub async fn resolve_pills(&self, message: &Message) -> Vec<ResolvedPill> {
+        let mut pills = Vec::new();
+
+        for pill in message.pills() {
+            let profile = match &pill.id {
+                MatrixId::User(user_id) => self.room().profile_from_user_id(user_id).await,
+                _ => None,
+            };
+            pills.push(ResolvedPill { pill, profile });
+        }
+
+        pills
+    }
+
     /// Send a message to the room, and add it to the timeline as a local echo.
     ///
     /// For simplicity, this method doesn't currently allow custom message
@@ -471,6 +551,80 @@ impl Timeline {
         Ok(())
     }
 
+    /// Create a poll in the room.
+    ///
+    /// The poll has one answer for each of the given `answers`, and each
+    /// response can select at most `max_selections` of them. The poll kind
+    /// must be one of the kinds defined by MSC3381.
+    #[instrument(skip_all)]
+    pub async fn create_poll(
+        &self,
+        question: impl Into<String>,
+        answers: Vec<String>,
+        max_selections: u8,
+        kind: PollKind,
+    ) -> Result<(), PollError> {
+        let content = polls::new_poll_content(question.into(), answers, max_selections, kind)?;
+        self.send(UnstablePollStartEventContent::from(content).into()).await?;
+        Ok(())
+    }
+
+    /// Vote in the poll started by the given event.
+    ///
+    /// The answers must be IDs of the answers of the poll, and there must be
+    /// at most as many as the maximum number of selections of the poll. An
+    /// empty list of answers retracts the previous vote. The vote is added to
+    /// the results of the poll right away, and removed if it can't be sent.
+    #[instrument(skip(self, answers))]
+    pub async fn send_poll_response(
+        &self,
+        poll_start_id: &EventId,
+        answers: Vec<String>,
+    ) -> Result<(), PollError> {
+        let item =
+            self.item_by_event_id(poll_start_id).await.ok_or(PollError::PollNotInTimeline)?;
+        let TimelineItemContent::Poll(poll_state) = item.content() else {
+            return Err(PollError::PollNotInTimeline);
+        };
+        poll_state.validate_response(&answers)?;
+
+        let content = UnstablePollResponseEventContent::new(answers, poll_start_id.to_owned());
+        self.send(content.into()).await?;
+
+        Ok(())
+    }
+
+    /// End the poll started by the given event.
+    ///
+    /// Only the creator of the poll, or a user allowed to redact the events of
+    /// other users, can end it.
+    #[instrument(skip(self, text))]
+    pub async fn end_poll(
+        &self,
+        poll_start_id: &EventId,
+        text: impl Into<String>,
+    ) -> Result<(), PollError> {
+        let item =
+            self.item_by_event_id(poll_start_id).await.ok_or(PollError::PollNotInTimeline)?;
+        let TimelineItemContent::Poll(poll_state) = item.content() else {
+            return Err(PollError::PollNotInTimeline);
+        };
+
+        if poll_state.has_ended() {
+            return Err(PollError::PollEnded);
+        }
+
+        let room = self.room();
+        if !item.is_own() && !room.can_user_redact_other(room.own_user_id()).await? {
+            return Err(PollError::NotAllowedToEnd);
+        }
+
+        let content = UnstablePollEndEventContent::new(text, poll_start_id.to_owned());
+        self.send(content.into()).await?;
+
+        Ok(())
+    }
+
     /// Toggle a reaction on an event
     ///
     /// Adds or redacts a reaction based on the state of the reaction at the
@@ -807,6 +961,8 @@ struct TimelineDropHandle {
     room_update_join_handle: JoinHandle<()>,
     room_key_from_backups_join_handle: JoinHandle<()>,
     local_echo_listener_handle: Option<JoinHandle<()>>,
+    #[cfg(feature = "e2e-encryption")]
+    trust_updates_join_handle: Option<JoinHandle<()>>,
     _event_cache_drop_handle: Arc<EventCacheDropHandles>,
 }
 
@@ -818,6 +974,10 @@ impl Drop for TimelineDropHandle {
         if let Some(handle) = self.local_echo_listener_handle.take() {
             handle.abort()
         };
+        #[cfg(feature = "e2e-encryption")]
+        if let Some(handle) = self.trust_updates_join_handle.take() {
+            handle.abort()
+        };
         self.room_update_join_handle.abort();
         self.room_key_from_backups_join_handle.abort();
     }
@@ -21,13 +21,16 @@ use futures_util::{pin_mut, StreamExt as _};
 use matrix_sdk::event_cache::{
     self,
     paginator::{PaginatorError, PaginatorState},
-    BackPaginationOutcome, EventCacheError, RoomPagination,
+    BackPaginationOutcome, EventCacheError, GapFillOutcome, RoomPagination,
 };
 use tracing::{instrument, trace, warn};
 
 use super::Error;
 use crate::timeline::{event_item::RemoteEventOrigin, inner::TimelineEnd};
 
+/// The number of events requested at once when filling a gap.
+const FILL_GAP_BATCH_SIZE: u16 = 20;
+
 impl super::Timeline {
     /// Add more events to the start of the timeline.
     ///
@@ -109,6 +112,48 @@ impl super::Timeline {
         }
     }
 
+    /// Fill the gap with the given unique ID, by back-paginating from it until
+    /// it meets events that are already known, or the start of the room.
+    ///
+    /// Gaps are represented by [`VirtualTimelineItem::Gap`] items; the
+    /// missing events are inserted right after the gap item, which is removed
+    /// once the gap is closed. Events that are already in the timeline are
+    /// skipped.
+    ///
+    /// If the server stops making progress, i.e. it returns no events or the
+    /// same token, this returns early, leaving the gap in the timeline.
+    ///
+    /// [`VirtualTimelineItem::Gap`]: super::VirtualTimelineItem::Gap
+    #[instrument(skip(self), fields(room_id = ?self.room().room_id()))]
+    pub async fn fill_gap(&self, unique_id: &str) -> Result<(), Error> {
+        let pagination = self.event_cache.pagination();
+
+        loop {
+            let Some(prev_token) = self.inner.gap_token(unique_id).await else {
+                return Err(Error::GapNotInTimeline);
+            };
+
+            let GapFillOutcome { events, prev_token: new_prev_token } =
+                pagination.fill_gap(&prev_token, FILL_GAP_BATCH_SIZE).await?;
+
+            trace!("Gap filling succeeded with {} events", events.len());
+
+            let is_closed = new_prev_token.is_none();
+            let made_progress =
+                !events.is_empty() && new_prev_token.as_deref() != Some(prev_token.as_str());
+            self.inner.fill_gap(unique_id, events, new_prev_token).await?;
+
+            if is_closed {
+                return Ok(());
+            }
+
+            if !made_progress {
+                warn!("The server didn't return new events or a new token, stopping gap filling");
+                return Ok(());
+            }
+        }
+    }
+
     /// Subscribe to the back-pagination status of a live timeline.
     ///
     /// This will return `None` if the timeline is in the focused mode.
@@ -1,7 +1,9 @@
-//! This module handles rendering of MSC3381 polls in the timeline.
+//! This module handles creating, answering and rendering MSC3381 polls in the
+//! timeline.
 
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt::Write as _, sync::Arc};
 
+use matrix_sdk::Error;
 use ruma::{
     events::poll::{
         compile_unstable_poll_results,
@@ -9,12 +11,16 @@ use ruma::{
         unstable_response::UnstablePollResponseEventContent,
         unstable_start::{
             NewUnstablePollStartEventContent, NewUnstablePollStartEventContentWithoutRelation,
-            UnstablePollStartContentBlock,
+            UnstablePollAnswer, UnstablePollAnswers, UnstablePollStartContentBlock,
         },
         PollResponseData,
     },
-    EventId, MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedUserId, UserId,
+    EventId, MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedTransactionId, OwnedUserId,
+    TransactionId, UserId,
 };
+use uuid::Uuid;
+
+use super::error::PollError;
 
 /// Holds the state of a poll.
 ///
@@ -26,6 +32,9 @@ pub struct PollState {
     pub(super) start_event_content: NewUnstablePollStartEventContent,
     pub(super) response_data: Vec<ResponseData>,
     pub(super) end_event_timestamp: Option<MilliSecondsSinceUnixEpoch>,
+    /// Whether the end event is a local echo, whose timestamp will be replaced
+    /// by the one of its remote echo.
+    pub(super) end_event_is_local: bool,
     pub(super) has_been_edited: bool,
 }
 
@@ -34,6 +43,10 @@ pub(super) struct ResponseData {
     pub(super) sender: OwnedUserId,
     pub(super) timestamp: MilliSecondsSinceUnixEpoch,
     pub(super) answers: Vec<String>,
+    /// The transaction ID of the response, if it is a local echo.
+    pub(super) local_txn_id: Option<OwnedTransactionId>,
+    /// The error that happened when sending the local echo, if any.
+    pub(super) send_error: Option<Arc<Error>>,
 }
 
 impl PollState {
@@ -42,6 +55,7 @@ impl PollState {
             start_event_content: content,
             response_data: vec![],
             end_event_timestamp: None,
+            end_event_is_local: false,
             has_been_edited: false,
         }
     }
@@ -61,34 +75,107 @@ impl PollState {
         }
     }
 
+    /// Adds a response to the poll.
+    ///
+    /// `local_txn_id` must be set if the response is a local echo.
     pub(super) fn add_response(
         &self,
         sender: &UserId,
         timestamp: MilliSecondsSinceUnixEpoch,
         content: &UnstablePollResponseEventContent,
+        local_txn_id: Option<&TransactionId>,
     ) -> Self {
         let mut clone = self.clone();
         clone.response_data.push(ResponseData {
             sender: sender.to_owned(),
             timestamp,
             answers: content.poll_response.answers.clone(),
+            local_txn_id: local_txn_id.map(ToOwned::to_owned),
+            send_error: None,
         });
         clone
     }
 
+    /// Whether the poll has a local echo of a response with the given
+    /// transaction ID.
+    pub(super) fn has_local_response(&self, txn_id: &TransactionId) -> bool {
+        self.response_data.iter().any(|response| response.local_txn_id.as_deref() == Some(txn_id))
+    }
+
+    /// Removes the local echo of the response with the given transaction ID,
+    /// either because its remote echo was received or because it won't be
+    /// sent.
+    pub(super) fn remove_local_response(&mut self, txn_id: &TransactionId) {
+        self.response_data.retain(|response| response.local_txn_id.as_deref() != Some(txn_id));
+    }
+
+    /// Sets or clears the error that happened when sending the local echo of
+    /// the response with the given transaction ID.
+    pub(super) fn set_local_response_send_error(
+        &mut self,
+        txn_id: &TransactionId,
+        error: Option<Arc<Error>>,
+    ) {
+        for response in &mut self.response_data {
+            if response.local_txn_id.as_deref() == Some(txn_id) {
+                response.send_error = error.clone();
+            }
+        }
+    }
+
     /// Marks the poll as ended.
     ///
-    /// If the poll has already ended, returns `Err(())`.
-    pub(super) fn end(&self, timestamp: MilliSecondsSinceUnixEpoch) -> Result<Self, ()> {
-        if self.end_event_timestamp.is_none() {
+    /// If the poll has already ended, returns `Err(())`, unless it was ended
+    /// by a local echo and this is a remote end event, in which case the end
+    /// timestamp is updated: as per MSC3381, the responses sent after the
+    /// remote end event are ignored.
+    pub(super) fn end(
+        &self,
+        timestamp: MilliSecondsSinceUnixEpoch,
+        is_local: bool,
+    ) -> Result<Self, ()> {
+        if self.end_event_timestamp.is_none() || (self.end_event_is_local && !is_local) {
             let mut clone = self.clone();
             clone.end_event_timestamp = Some(timestamp);
+            clone.end_event_is_local = is_local;
             Ok(clone)
         } else {
             Err(())
         }
     }
 
+    /// Whether the poll has ended.
+    pub fn has_ended(&self) -> bool {
+        self.end_event_timestamp.is_some()
+    }
+
+    /// Check that the given answers make a valid response to this poll.
+    ///
+    /// A response without answers is valid: it retracts the previous vote of
+    /// the user, as per MSC3381.
+    pub(super) fn validate_response(&self, answers: &[String]) -> Result<(), PollError> {
+        if self.has_ended() {
+            return Err(PollError::PollEnded);
+        }
+
+        let poll_start = &self.start_event_content.poll_start;
+        let max_selections = u64::from(poll_start.max_selections);
+        if answers.len() as u64 > max_selections {
+            return Err(PollError::InvalidSelectionCount { count: answers.len(), max_selections });
+        }
+
+        for (idx, answer) in answers.iter().enumerate() {
+            if !poll_start.answers.iter().any(|a| a.id == *answer) {
+                return Err(PollError::UnknownAnswer(answer.clone()));
+            }
+            if answers[..idx].contains(answer) {
+                return Err(PollError::DuplicateAnswer(answer.clone()));
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn fallback_text(&self) -> Option<String> {
         self.start_event_content.text.clone()
     }
@@ -121,6 +208,12 @@ impl PollState {
                 .collect(),
             end_time: self.end_event_timestamp.map(|millis| millis.0.into()),
             has_been_edited: self.has_been_edited,
+            // Only the latest local vote counts, as it replaces the previous ones.
+            own_vote_send_error: self
+                .response_data
+                .iter()
+                .rfind(|response| response.local_txn_id.is_some())
+                .and_then(|response| response.send_error.clone()),
         }
     }
 }
@@ -159,6 +252,8 @@ impl PollPendingEvents {
             sender: sender.to_owned(),
             timestamp,
             answers: content.poll_response.answers.clone(),
+            local_txn_id: None,
+            send_error: None,
         });
     }
 
@@ -178,6 +273,59 @@ impl PollPendingEvents {
     }
 }
 
+/// The maximum number of answers of a poll, as per MSC3381.
+const MAX_POLL_ANSWERS: usize = 20;
+
+/// Build the content of a new poll, checking that it is valid.
+///
+/// The poll has one answer for each of the given `answers`, with a random ID,
+/// and the content has a plain text fallback listing the question and the
+/// answers. The poll kind must be one of the kinds defined by MSC3381.
+///
+/// This is the content sent by [`Timeline::create_poll()`], and its poll can
+/// be passed to [`Timeline::edit_poll()`].
+///
+/// [`Timeline::create_poll()`]: super::Timeline::create_poll
+/// [`Timeline::edit_poll()`]: super::Timeline::edit_poll
+pub fn new_poll_content(
+    question: String,
+    answers: Vec<String>,
+    max_selections: u8,
+    kind: PollKind,
+) -> Result<NewUnstablePollStartEventContent, PollError> {
+    if !matches!(kind, PollKind::Disclosed | PollKind::Undisclosed) {
+        return Err(PollError::UnsupportedPollKind(kind.as_ref().to_owned()));
+    }
+
+    let answer_count = answers.len();
+    if answer_count == 0 || answer_count > MAX_POLL_ANSWERS {
+        return Err(PollError::InvalidAnswerCount(answer_count));
+    }
+
+    if max_selections == 0 || usize::from(max_selections) > answer_count {
+        return Err(PollError::InvalidMaxSelections(max_selections));
+    }
+
+    let fallback_text =
+        answers.iter().enumerate().fold(question.clone(), |mut acc, (index, answer)| {
+            write!(&mut acc, "\n{}. {answer}", index + 1).unwrap();
+            acc
+        });
+
+    let answers: Vec<_> = answers
+        .into_iter()
+        .map(|answer| UnstablePollAnswer::new(Uuid::new_v4().to_string(), answer))
+        .collect();
+    let answers = UnstablePollAnswers::try_from(answers)
+        .map_err(|_| PollError::InvalidAnswerCount(answer_count))?;
+
+    let mut poll = UnstablePollStartContentBlock::new(question, answers);
+    poll.kind = kind;
+    poll.max_selections = max_selections.into();
+
+    Ok(NewUnstablePollStartEventContent::plain_text(fallback_text, poll))
+}
+
 #[derive(Debug)]
 pub struct PollResult {
     pub question: String,
@@ -187,6 +335,9 @@ pub struct PollResult {
     pub votes: HashMap<String, Vec<String>>,
     pub end_time: Option<u64>,
     pub has_been_edited: bool,
+    /// The error that happened when sending the own user's latest vote, if
+    /// it failed to be sent.
+    pub own_vote_send_error: Option<Arc<Error>>,
 }
 
 #[derive(Debug)]
@@ -0,0 +1,555 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A typed representation of the formatted body of a message.
+//!
+//! The HTML of a formatted body is sanitized following the tags and
+//! attributes allowed by the Matrix specification, its reply fallback is
+//! removed, and it is converted into a tree of [`Block`]s and [`Inline`]s, so
+//! clients don't have to parse HTML themselves.
+
+use ruma::{
+    events::room::message::{FormattedBody, MessageFormat},
+    html::{sanitize_html, Html, NodeRef, RemoveReplyFallback},
+    matrix_uri::MatrixId,
+    OwnedMxcUri, UInt,
+};
+
+use super::mentions::{collect_text, parse_matrix_id};
+use crate::DEFAULT_SANITIZER_MODE;
+
+/// The rich text of a message.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RichText {
+    /// The blocks of the message, in order.
+    pub blocks: Vec<Block>,
+}
+
+impl RichText {
+    /// Get the rich text of a message from its plain text body and its
+    /// formatted body.
+    ///
+    /// Falls back to the plain text body if there is no HTML formatted body,
+    /// or if it can't be converted.
+    pub fn new(body: &str, formatted: Option<&FormattedBody>) -> Self {
+        formatted
+            .filter(|formatted| formatted.format == MessageFormat::Html)
+            .and_then(|formatted| Self::from_html(&formatted.body))
+            .unwrap_or_else(|| Self::from_plain_text(body))
+    }
+
+    /// Convert the given HTML into rich text.
+    ///
+    /// The HTML is sanitized and its reply fallback is removed first. Returns
+    /// `None` if the HTML doesn't contain anything that can be displayed.
+    pub fn from_html(html: &str) -> Option<Self> {
+        let sanitized = sanitize_html(html, DEFAULT_SANITIZER_MODE, RemoveReplyFallback::Yes);
+        let html = Html::parse(&sanitized);
+
+        let blocks = parse_blocks(html.children());
+        (!blocks.is_empty()).then_some(Self { blocks })
+    }
+
+    /// Convert the given plain text into rich text.
+    ///
+    /// Each run of lines separated by an empty line is a paragraph.
+    pub fn from_plain_text(text: &str) -> Self {
+        let blocks = text
+            .split("\n\n")
+            .filter(|paragraph| !paragraph.trim().is_empty())
+            .map(|paragraph| {
+                let mut content = Vec::new();
+                for (i, line) in paragraph.trim_matches('\n').split('\n').enumerate() {
+                    if i > 0 {
+                        content.push(Inline::LineBreak);
+                    }
+                    content.push(Inline::Text(line.to_owned()));
+                }
+                Block::Paragraph(content)
+            })
+            .collect();
+
+        Self { blocks }
+    }
+}
+
+/// A block of rich text.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Block {
+    /// A paragraph.
+    Paragraph(Vec<Inline>),
+
+    /// A heading.
+    Heading {
+        /// The level of the heading, between 1 and 6.
+        level: u8,
+        /// The content of the heading.
+        content: Vec<Inline>,
+    },
+
+    /// A list.
+    List {
+        /// Whether the items of the list are numbered.
+        ordered: bool,
+        /// The number of the first item, if it's not 1.
+        start: Option<u64>,
+        /// The items of the list.
+        items: Vec<Vec<Block>>,
+    },
+
+    /// A block of code.
+    CodeBlock {
+        /// The language of the code, if it was specified.
+        language: Option<String>,
+        /// The code.
+        code: String,
+    },
+
+    /// A quote.
+    Quote(Vec<Block>),
+
+    /// A horizontal rule.
+    HorizontalRule,
+}
+
+/// An inline element of rich text.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Inline {
+    /// Text.
+    Text(String),
+
+    /// Content with a text style.
+    Styled {
+        /// The style of the content.
+        style: TextStyle,
+        /// The styled content.
+        content: Vec<Inline>,
+    },
+
+    /// Inline code.
+    Code(String),
+
+    /// A link to a web page.
+    Link {
+        /// The URL of the link.
+        url: String,
+        /// The content of the link.
+        content: Vec<Inline>,
+    },
+
+    /// A link to a Matrix entity, usually displayed as a pill.
+    Mention {
+        /// The user, room or event the link points to.
+        id: MatrixId,
+        /// The text of the link.
+        text: String,
+    },
+
+    /// Content that is hidden until the user chooses to reveal it.
+    Spoiler {
+        /// The reason why the content is hidden, if any.
+        reason: Option<String>,
+        /// The hidden content.
+        content: Vec<Inline>,
+    },
+
+    /// Content with a colour.
+    Colored {
+        /// The colour of the text, as a `#rrggbb` string.
+        color: Option<String>,
+        /// The colour of the background, as a `#rrggbb` string.
+        background_color: Option<String>,
+        /// The coloured content.
+        content: Vec<Inline>,
+    },
+
+    /// An image uploaded to the homeserver.
+    Image {
+        /// The URI of the image.
+        source: OwnedMxcUri,
+        /// The alternative text of the image.
+        alt: Option<String>,
+        /// The title of the image.
+        title: Option<String>,
+        /// The width of the image, in pixels.
+        width: Option<UInt>,
+        /// The height of the image, in pixels.
+        height: Option<UInt>,
+    },
+
+    /// A line break.
+    LineBreak,
+}
+
+/// The style of inline text.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextStyle {
+    /// Bold text.
+    Bold,
+    /// Italic text.
+    Italic,
+    /// Underlined text.
+    Underline,
+    /// Struck through text.
+    Strikethrough,
+    /// Superscript text.
+    Superscript,
+    /// Subscript text.
+    Subscript,
+}
+
+/// The elements that are represented as blocks.
+const BLOCK_ELEMENTS: &[&str] = &[
+    "p",
+    "div",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "ul",
+    "ol",
+    "li",
+    "pre",
+    "blockquote",
+    "hr",
+    "table",
+    "thead",
+    "tbody",
+    "tr",
+    "th",
+    "td",
+    "details",
+    "summary",
+    "caption",
+];
+
+fn parse_blocks<'a>(nodes: impl Iterator<Item = NodeRef<'a>>) -> Vec<Block> {
+    let mut blocks = Vec::new();
+    let mut inlines = Vec::new();
+
+    for node in nodes {
+        match node.as_element().map(|element| &*element.name.local) {
+            Some(name) if BLOCK_ELEMENTS.contains(&name) => {
+                flush_paragraph(&mut inlines, &mut blocks);
+                parse_block(&node, name, &mut blocks);
+            }
+            _ => parse_inline(&node, &mut inlines),
+        }
+    }
+
+    flush_paragraph(&mut inlines, &mut blocks);
+    blocks
+}
+
+/// Add the given inlines as a paragraph, unless they're only whitespace.
+fn flush_paragraph(inlines: &mut Vec<Inline>, blocks: &mut Vec<Block>) {
+    let is_blank =
+        inlines.iter().all(|inline| matches!(inline, Inline::Text(text) if text.trim().is_empty()));
+
+    if is_blank {
+        inlines.clear();
+    } else {
+        blocks.push(Block::Paragraph(std::mem::take(inlines)));
+    }
+}
+
+fn parse_block(node: &NodeRef<'_>, name: &str, blocks: &mut Vec<Block>) {
+    match name {
+        "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+            let level = name[1..].parse().unwrap_or(1);
+            blocks.push(Block::Heading { level, content: parse_inlines(node) });
+        }
+        "ul" | "ol" => {
+            let items = node
+                .children()
+                .filter(|child| child.as_element().is_some_and(|e| &*e.name.local == "li"))
+                .map(|item| parse_blocks(item.children()))
+                .collect();
+            let start = attribute(node, "start").and_then(|start| start.parse().ok());
+            blocks.push(Block::List { ordered: name == "ol", start, items });
+        }
+        "pre" => {
+            let code_element = node
+                .children()
+                .find(|child| child.as_element().is_some_and(|e| &*e.name.local == "code"));
+            let language =
+                code_element.as_ref().and_then(|code| attribute(code, "class")).and_then(|class| {
+                    class
+                        .split_whitespace()
+                        .find_map(|class| class.strip_prefix("language-"))
+                        .map(ToOwned::to_owned)
+                });
+
+            let mut code = String::new();
+            collect_text(node, &mut code);
+            blocks.push(Block::CodeBlock { language, code });
+        }
+        "blockquote" => blocks.push(Block::Quote(parse_blocks(node.children()))),
+        "hr" => blocks.push(Block::HorizontalRule),
+        // Paragraphs, and containers whose structure isn't represented.
+        _ => blocks.extend(parse_blocks(node.children())),
+    }
+}
+
+fn parse_inlines(node: &NodeRef<'_>) -> Vec<Inline> {
+    let mut inlines = Vec::new();
+    for child in node.children() {
+        parse_inline(&child, &mut inlines);
+    }
+    inlines
+}
+
+fn parse_inline(node: &NodeRef<'_>, inlines: &mut Vec<Inline>) {
+    if let Some(text) = node.as_text() {
+        push_text(inlines, text);
+        return;
+    }
+
+    let Some(element) = node.as_element() else {
+        return;
+    };
+
+    let style = match &*element.name.local {
+        "b" | "strong" => Some(TextStyle::Bold),
+        "i" | "em" => Some(TextStyle::Italic),
+        "u" => Some(TextStyle::Underline),
+        "s" | "del" | "strike" => Some(TextStyle::Strikethrough),
+        "sup" => Some(TextStyle::Superscript),
+        "sub" => Some(TextStyle::Subscript),
+        _ => None,
+    };
+    if let Some(style) = style {
+        inlines.push(Inline::Styled { style, content: parse_inlines(node) });
+        return;
+    }
+
+    match &*element.name.local {
+        "br" => inlines.push(Inline::LineBreak),
+        "code" => {
+            let mut code = String::new();
+            collect_text(node, &mut code);
+            inlines.push(Inline::Code(code));
+        }
+        "a" => {
+            let Some(href) = attribute(node, "href") else {
+                inlines.extend(parse_inlines(node));
+                return;
+            };
+
+            if let Some(id) = parse_matrix_id(&href) {
+                let mut text = String::new();
+                collect_text(node, &mut text);
+                inlines.push(Inline::Mention { id, text });
+            } else {
+                inlines.push(Inline::Link { url: href, content: parse_inlines(node) });
+            }
+        }
+        "img" => {
+            // Only images uploaded to the homeserver are allowed.
+            if let Some(source) = attribute(node, "src").filter(|src| src.starts_with("mxc://")) {
+                inlines.push(Inline::Image {
+                    source: source.into(),
+                    alt: attribute(node, "alt"),
+                    title: attribute(node, "title"),
+                    width: attribute(node, "width").and_then(|width| width.parse().ok()),
+                    height: attribute(node, "height").and_then(|height| height.parse().ok()),
+                });
+            }
+        }
+        "span" | "font" => {
+            let content = parse_inlines(node);
+
+            if let Some(reason) = attribute(node, "data-mx-spoiler") {
+                let reason = (!reason.is_empty()).then_some(reason);
+                inlines.push(Inline::Spoiler { reason, content });
+                return;
+            }
+
+            let color = attribute(node, "data-mx-color")
+                .or_else(|| attribute(node, "color"))
+                .filter(|color| is_color(color));
+            let background_color =
+                attribute(node, "data-mx-bg-color").filter(|color| is_color(color));
+
+            if color.is_some() || background_color.is_some() {
+                inlines.push(Inline::Colored { color, background_color, content });
+            } else {
+                inlines.extend(content);
+            }
+        }
+        // Block elements nested in inline elements, and elements whose
+        // semantics aren't represented.
+        _ => inlines.extend(parse_inlines(node)),
+    }
+}
+
+/// Add the given text to the inlines, merging it with the previous text if
+/// there is one.
+fn push_text(inlines: &mut Vec<Inline>, text: &str) {
+    if let Some(Inline::Text(previous)) = inlines.last_mut() {
+        previous.push_str(text);
+    } else {
+        inlines.push(Inline::Text(text.to_owned()));
+    }
+}
+
+fn attribute(node: &NodeRef<'_>, name: &str) -> Option<String> {
+    node.as_element()?
+        .attrs
+        .iter()
+        .find(|attr| &*attr.name.local == name)
+        .map(|attr| attr.value.to_string())
+}
+
+/// Whether the given string is a colour in the `#rrggbb` format required by
+/// the specification.
+fn is_color(value: &str) -> bool {
+    value.len() == 7 && value.starts_with('#') && value[1..].chars().all(|c| c.is_ascii_hexdigit())
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_matches2::assert_let;
+    use ruma::{events::room::message::FormattedBody, matrix_uri::MatrixId, uint};
+
+    use super::{Block, Inline, RichText, TextStyle};
+
+    fn text(text: &str) -> Inline {
+        Inline::Text(text.to_owned())
+    }
+
+    #[test]
+    fn test_paragraphs_and_styles() {
+        let rich_text =
+            RichText::from_html("<p>Hello <strong>world</strong></p><p><em>Bye</em></p>").unwrap();
+
+        assert_eq!(
+            rich_text.blocks,
+            [
+                Block::Paragraph(vec![
+                    text("Hello "),
+                    Inline::Styled { style: TextStyle::Bold, content: vec![text("world")] },
+                ]),
+                Block::Paragraph(vec![Inline::Styled {
+                    style: TextStyle::Italic,
+                    content: vec![text("Bye")]
+                }]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lists_code_and_quotes() {
+        let rich_text = RichText::from_html(
+            "<ol start=\"3\"><li>one</li><li>two</li></ol>\
+             <pre><code class=\"language-rust\">let a = 1;\n</code></pre>\
+             <blockquote><p>quoted</p></blockquote><hr>",
+        )
+        .unwrap();
+
+        assert_eq!(
+            rich_text.blocks,
+            [
+                Block::List {
+                    ordered: true,
+                    start: Some(3),
+                    items: vec![
+                        vec![Block::Paragraph(vec![text("one")])],
+                        vec![Block::Paragraph(vec![text("two")])],
+                    ],
+                },
+                Block::CodeBlock {
+                    language: Some("rust".to_owned()),
+                    code: "let a = 1;\n".to_owned()
+                },
+                Block::Quote(vec![Block::Paragraph(vec![text("quoted")])]),
+                Block::HorizontalRule,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_links_mentions_and_images() {
+        let rich_text = RichText::from_html(
+            "<a href=\"https://matrix.to/#/@alice:example.org\">Alice</a> \
+             <a href=\"https://example.org\">site</a> \
+             <img src=\"mxc://example.org/abc\" alt=\"cat\" width=\"32\">\
+             <img src=\"https://example.org/cat.png\">",
+        )
+        .unwrap();
+
+        assert_let!([Block::Paragraph(inlines)] = rich_text.blocks.as_slice());
+        assert_let!(Inline::Mention { id: MatrixId::User(user_id), text: mention } = &inlines[0]);
+        assert_eq!(user_id, "@alice:example.org");
+        assert_eq!(mention, "Alice");
+        assert_eq!(
+            inlines[2],
+            Inline::Link { url: "https://example.org".to_owned(), content: vec![text("site")] }
+        );
+        assert_let!(Inline::Image { source, alt, width, .. } = &inlines[4]);
+        assert_eq!(source, "mxc://example.org/abc");
+        assert_eq!(alt.as_deref(), Some("cat"));
+        assert_eq!(*width, Some(uint!(32)));
+        // Images that are not uploaded to the homeserver are dropped.
+        assert_eq!(inlines.len(), 5);
+    }
+
+    #[test]
+    fn test_spoilers_and_colors() {
+        let rich_text = RichText::from_html(
+            "<span data-mx-spoiler=\"plot\">twist</span>\
+             <font data-mx-color=\"#ff0000\" data-mx-bg-color=\"red\">red</font>",
+        )
+        .unwrap();
+
+        assert_eq!(
+            rich_text.blocks,
+            [Block::Paragraph(vec![
+                Inline::Spoiler { reason: Some("plot".to_owned()), content: vec![text("twist")] },
+                Inline::Colored {
+                    color: Some("#ff0000".to_owned()),
+                    background_color: None,
+                    content: vec![text("red")],
+                },
+            ])]
+        );
+    }
+
+    #[test]
+    fn test_reply_fallback_is_removed() {
+        let rich_text = RichText::from_html(
+            "<mx-reply><blockquote>In reply to a message</blockquote></mx-reply>The reply",
+        )
+        .unwrap();
+
+        assert_eq!(rich_text.blocks, [Block::Paragraph(vec![text("The reply")])]);
+    }
+
+    #[test]
+    fn test_fallback_to_plain_body() {
+        let formatted = FormattedBody::html("<mx-reply>Only a fallback</mx-reply>");
+        let rich_text =
+            RichText::new("First line\nSecond line\n\nOther paragraph", Some(&formatted));
+
+        assert_eq!(
+            rich_text.blocks,
+            [
+                Block::Paragraph(vec![text("First line"), Inline::LineBreak, text("Second line")]),
+                Block::Paragraph(vec![text("Other paragraph")]),
+            ]
+        );
+    }
+}
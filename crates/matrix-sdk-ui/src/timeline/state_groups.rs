@@ -0,0 +1,126 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Algorithm to adjust (insert/replace/remove) the groups of consecutive state
+//! items, after the timeline items have changed.
+
+use std::sync::Arc;
+
+use eyeball_im::ObservableVectorTransaction;
+use tracing::{instrument, trace};
+
+use super::{
+    inner::TimelineInnerMetadata, virtual_item::StateGroup, TimelineItem, TimelineItemContent,
+    TimelineItemKind, VirtualTimelineItem,
+};
+
+/// The minimum number of consecutive state items that are grouped together.
+const MIN_GROUP_SIZE: usize = 2;
+
+/// Ensures that every run of at least [`MIN_GROUP_SIZE`] consecutive state
+/// items is immediately preceded by an up-to-date [`StateGroup`], and that
+/// there is no other group in the timeline.
+///
+/// Groups that are already correct are left untouched, so that only the
+/// groups affected by the latest changes are updated in the `VectorDiff`
+/// stream. Since a group only refers to its items by their unique ID, an update
+/// of one of the items that doesn't change the summary doesn't update the
+/// group either.
+///
+/// This must be called on the transaction that changed the items, so that the
+/// groups are updated in the same batch of `VectorDiff`s.
+#[instrument(skip_all)]
+pub(super) fn adjust_state_groups(
+    items: &mut ObservableVectorTransaction<'_, Arc<TimelineItem>>,
+    meta: &mut TimelineInnerMetadata,
+) {
+    let mut i = 0;
+
+    while i < items.len() {
+        if let Some(group) = as_state_group(&items[i]) {
+            let run_len = state_run_len(items, i + 1);
+
+            if run_len < MIN_GROUP_SIZE {
+                // The items this group was about changed, or moved.
+                trace!("removing stale state group @ {i}");
+                items.remove(i);
+                continue;
+            }
+
+            let new_group = state_run(items, i + 1, run_len);
+            if *group != new_group {
+                trace!("updating state group @ {i}");
+                let item = items[i].with_kind(VirtualTimelineItem::StateGroup(new_group));
+                items.set(i, item);
+            }
+
+            i += 1 + run_len;
+            continue;
+        }
+
+        let run_len = state_run_len(items, i);
+        if run_len >= MIN_GROUP_SIZE {
+            trace!("inserting state group @ {i}");
+            let group = state_run(items, i, run_len);
+            let item = meta.new_timeline_item(VirtualTimelineItem::StateGroup(group));
+
+            // Keep push semantics, if we're inserting at the front.
+            if i == 0 {
+                items.push_front(item);
+            } else {
+                items.insert(i, item);
+            }
+
+            i += 1 + run_len;
+        } else {
+            i += run_len.max(1);
+        }
+    }
+}
+
+fn as_state_group(item: &TimelineItem) -> Option<&StateGroup> {
+    match item.kind() {
+        TimelineItemKind::Virtual(VirtualTimelineItem::StateGroup(group)) => Some(group),
+        _ => None,
+    }
+}
+
+/// Whether the given item is a state item that can be part of a group.
+fn is_groupable(item: &TimelineItem) -> bool {
+    item.as_event().is_some_and(|event| {
+        matches!(
+            event.content(),
+            TimelineItemContent::MembershipChange(_)
+                | TimelineItemContent::ProfileChange(_)
+                | TimelineItemContent::OtherState(_)
+        )
+    })
+}
+
+/// The number of consecutive groupable items starting at `start`.
+fn state_run_len(
+    items: &ObservableVectorTransaction<'_, Arc<TimelineItem>>,
+    start: usize,
+) -> usize {
+    items.iter().skip(start).take_while(|item| is_groupable(item)).count()
+}
+
+/// The group of the `len` consecutive items starting at `start`.
+fn state_run(
+    items: &ObservableVectorTransaction<'_, Arc<TimelineItem>>,
+    start: usize,
+    len: usize,
+) -> StateGroup {
+    StateGroup::new(items.iter().skip(start).take(len).map(|item| &**item))
+}
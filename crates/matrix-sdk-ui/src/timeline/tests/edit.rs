@@ -16,7 +16,7 @@ use assert_matches2::assert_let;
 use eyeball_im::VectorDiff;
 use matrix_sdk_test::{async_test, sync_timeline_event, ALICE};
 use ruma::{
-    assign,
+    assign, event_id,
     events::{
         relation::Replacement,
         room::message::{
@@ -163,3 +163,62 @@ async fn test_aggregated_sanitized() {
     let day_divider = assert_next_matches!(stream, VectorDiff::PushFront { value } => value);
     assert!(day_divider.is_day_divider());
 }
+
+#[async_test]
+async fn test_edit_redaction_restores_previous_version() {
+    let timeline = TestTimeline::new();
+    let mut stream = timeline.subscribe().await;
+
+    let original_event_id = event_id!("$original");
+    timeline
+        .handle_live_message_event_with_id(
+            &ALICE,
+            original_event_id,
+            RoomMessageEventContent::text_plain("original"),
+        )
+        .await;
+    let _item = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
+    let _day_divider = assert_next_matches!(stream, VectorDiff::PushFront { value } => value);
+
+    let make_edit = |body: &str| {
+        assign!(RoomMessageEventContent::text_plain(format!("* {body}")), {
+            relates_to: Some(message::Relation::Replacement(Replacement::new(
+                original_event_id.to_owned(),
+                MessageType::text_plain(body).into(),
+            ))),
+        })
+    };
+
+    let first_edit_id = event_id!("$edit1");
+    timeline.handle_live_message_event_with_id(&ALICE, first_edit_id, make_edit("first")).await;
+    let _item = assert_next_matches!(stream, VectorDiff::Set { index: 1, value } => value);
+
+    let second_edit_id = event_id!("$edit2");
+    timeline.handle_live_message_event_with_id(&ALICE, second_edit_id, make_edit("second")).await;
+    let item = assert_next_matches!(stream, VectorDiff::Set { index: 1, value } => value);
+
+    // All the edits are retained.
+    let event = item.as_event().unwrap();
+    let edit_ids: Vec<_> =
+        event.as_remote().unwrap().edits.iter().map(|edit| edit.event_id.clone()).collect();
+    assert_eq!(edit_ids, [first_edit_id.to_owned(), second_edit_id.to_owned()]);
+    assert_eq!(event.content().as_message().unwrap().body(), "second");
+
+    // Redacting the latest edit restores the previous one.
+    timeline.handle_live_redaction(&ALICE, second_edit_id).await;
+    let item = assert_next_matches!(stream, VectorDiff::Set { index: 1, value } => value);
+    let event = item.as_event().unwrap();
+    assert_eq!(event.as_remote().unwrap().edits.len(), 1);
+    let message = event.content().as_message().unwrap();
+    assert_eq!(message.body(), "first");
+    assert!(message.is_edited());
+
+    // Redacting the last remaining edit restores the original content.
+    timeline.handle_live_redaction(&ALICE, first_edit_id).await;
+    let item = assert_next_matches!(stream, VectorDiff::Set { index: 1, value } => value);
+    let event = item.as_event().unwrap();
+    assert!(event.as_remote().unwrap().edits.is_empty());
+    let message = event.content().as_message().unwrap();
+    assert_eq!(message.body(), "original");
+    assert!(!message.is_edited());
+}
@@ -18,17 +18,23 @@ use std::{
     io::Cursor,
     iter,
     sync::{Arc, Mutex},
+    time::Duration,
 };
 
 use assert_matches::assert_matches;
 use assert_matches2::assert_let;
 use eyeball_im::VectorDiff;
+use futures_util::{pin_mut, StreamExt};
 use matrix_sdk::{
     crypto::{decrypt_room_key_export, types::events::UtdCause, OlmMachine},
-    test_utils::test_client_builder,
+    deserialized_responses::{SyncTimelineEvent, UnableToDecryptReason, VerificationLevel},
+    encryption::TrustRequirement,
+    test_utils::{logged_in_client, test_client_builder},
 };
-use matrix_sdk_test::{async_test, BOB};
+use matrix_sdk_base::timeout::timeout;
+use matrix_sdk_test::{async_test, test_json::KEYS_QUERY, BOB};
 use ruma::{
+    api::client::keys::get_keys,
     assign,
     events::{
         room::encrypted::{
@@ -39,34 +45,38 @@ use ruma::{
     },
     room_id,
     serde::Raw,
-    user_id,
+    user_id, TransactionId,
 };
 use serde_json::{json, value::to_raw_value};
 use stream_assert::assert_next_matches;
 
-use super::TestTimeline;
+use super::{assert_no_more_updates, TestTimeline};
 use crate::{
-    timeline::{EncryptedMessage, TimelineItemContent},
+    timeline::{
+        builder::trust_updates_stream, event_item::RemoteEventOrigin, inner::TimelineEnd,
+        EncryptedMessage, TimelineItemContent,
+    },
     unable_to_decrypt_hook::{UnableToDecryptHook, UnableToDecryptInfo, UtdHookManager},
 };
 
+/// The Megolm session of [`secret_message_content()`].
+const SECRET_SESSION_ID: &str = "gM8i47Xhu0q52xLfgUXzanCMpLinoyVyH7R58cBuVBU";
+const SECRET_SESSION_KEY: &[u8] = b"\
+    -----BEGIN MEGOLM SESSION DATA-----\n\
+    ASKcWoiAVUM97482UAi83Avce62hSLce7i5JhsqoF6xeAAAACqt2Cg3nyJPRWTTMXxXH7TXnkfdlmBXbQtq5\
+    bpHo3LRijcq2Gc6TXilESCmJN14pIsfKRJrWjZ0squ/XsoTFytuVLWwkNaW3QF6obeg2IoVtJXLMPdw3b2vO\
+    vgwGY3OMP0XafH13j1vcb6YLzvgLkZQLnYvd47hv3yK/9GmKS9tokuaQ7dCVYckYcIOS09EDTs70YdxUd5WG\
+    rQynATCLFP1p/NAGv70r9MK7Cy/mNpjD0r4qC7UEDIoi1kOWzHgnLo19wtvwsb8Fg8ATxcs3Wmtj8hIUYpDx\
+    ia4sM10zbytUuaPUAfCDf42IyxdmOnGe1CueXhgI71y+RW0s0argNqUt7jB70JT0o9CyX6UBGRaqLk2MPY9T\
+    hUu5J8X3UgIa6rcbWigzohzWm9rdbEHFrSWqjpfQYMaAKQQgETrjSy4XTrp2RhC2oNqG/hylI4ab+F4X6fpH\
+    DYP1NqNMP5g36xNu7LhDnrUB5qsPjYOmWORxGLfudpF3oLYCSlr3DgHqEIB6HjQblLZ3KQuPBse3zxyROTnS\
+    AhdPH4a/z1wioFtKNVph3hecsiKEdqnz4Y2coSIdhz58mJ9JWNQoFAENE5CSsoEZAGvafYZVpW4C75YY2zq1\
+    wIeiFi1dT43/jLAUGkslsi1VvnyfUu8qO404RxYO3XHoGLMFoFLOO+lZ+VGci2Vz10AhxJhEBHxRKxw4k2uB\
+    HztoSJUr/2Y\n\
+    -----END MEGOLM SESSION DATA-----";
+
 #[async_test]
 async fn test_retry_message_decryption() {
-    const SESSION_ID: &str = "gM8i47Xhu0q52xLfgUXzanCMpLinoyVyH7R58cBuVBU";
-    const SESSION_KEY: &[u8] = b"\
-        -----BEGIN MEGOLM SESSION DATA-----\n\
-        ASKcWoiAVUM97482UAi83Avce62hSLce7i5JhsqoF6xeAAAACqt2Cg3nyJPRWTTMXxXH7TXnkfdlmBXbQtq5\
-        bpHo3LRijcq2Gc6TXilESCmJN14pIsfKRJrWjZ0squ/XsoTFytuVLWwkNaW3QF6obeg2IoVtJXLMPdw3b2vO\
-        vgwGY3OMP0XafH13j1vcb6YLzvgLkZQLnYvd47hv3yK/9GmKS9tokuaQ7dCVYckYcIOS09EDTs70YdxUd5WG\
-        rQynATCLFP1p/NAGv70r9MK7Cy/mNpjD0r4qC7UEDIoi1kOWzHgnLo19wtvwsb8Fg8ATxcs3Wmtj8hIUYpDx\
-        ia4sM10zbytUuaPUAfCDf42IyxdmOnGe1CueXhgI71y+RW0s0argNqUt7jB70JT0o9CyX6UBGRaqLk2MPY9T\
-        hUu5J8X3UgIa6rcbWigzohzWm9rdbEHFrSWqjpfQYMaAKQQgETrjSy4XTrp2RhC2oNqG/hylI4ab+F4X6fpH\
-        DYP1NqNMP5g36xNu7LhDnrUB5qsPjYOmWORxGLfudpF3oLYCSlr3DgHqEIB6HjQblLZ3KQuPBse3zxyROTnS\
-        AhdPH4a/z1wioFtKNVph3hecsiKEdqnz4Y2coSIdhz58mJ9JWNQoFAENE5CSsoEZAGvafYZVpW4C75YY2zq1\
-        wIeiFi1dT43/jLAUGkslsi1VvnyfUu8qO404RxYO3XHoGLMFoFLOO+lZ+VGci2Vz10AhxJhEBHxRKxw4k2uB\
-        HztoSJUr/2Y\n\
-        -----END MEGOLM SESSION DATA-----";
-
     #[derive(Debug, Default)]
     struct DummyUtdHook {
         utds: Mutex<Vec<UnableToDecryptInfo>>,
@@ -85,30 +95,7 @@ async fn test_retry_message_decryption() {
     let timeline = TestTimeline::with_unable_to_decrypt_hook(utd_hook.clone());
     let mut stream = timeline.subscribe().await;
 
-    timeline
-        .handle_live_message_event(
-            &BOB,
-            RoomEncryptedEventContent::new(
-                EncryptedEventScheme::MegolmV1AesSha2(
-                    MegolmV1AesSha2ContentInit {
-                        ciphertext: "\
-                            AwgAEtABPRMavuZMDJrPo6pGQP4qVmpcuapuXtzKXJyi3YpEsjSWdzuRKIgJzD4P\
-                            cSqJM1A8kzxecTQNJsC5q22+KSFEPxPnI4ltpm7GFowSoPSW9+bFdnlfUzEP1jPq\
-                            YevHAsMJp2fRKkzQQbPordrUk1gNqEpGl4BYFeRqKl9GPdKFwy45huvQCLNNueql\
-                            CFZVoYMuhxrfyMiJJAVNTofkr2um2mKjDTlajHtr39pTG8k0eOjSXkLOSdZvNOMz\
-                            hGhSaFNeERSA2G2YbeknOvU7MvjiO0AKuxaAe1CaVhAI14FCgzrJ8g0y5nly+n7x\
-                            QzL2G2Dn8EoXM5Iqj8W99iokQoVsSrUEnaQ1WnSIfewvDDt4LCaD/w7PGETMCQ"
-                            .to_owned(),
-                        sender_key: "DeHIg4gwhClxzFYcmNntPNF9YtsdZbmMy8+3kzCMXHA".to_owned(),
-                        device_id: "NLAZCWIOCO".into(),
-                        session_id: SESSION_ID.into(),
-                    }
-                    .into(),
-                ),
-                None,
-            ),
-        )
-        .await;
+    timeline.handle_live_message_event(&BOB, secret_message_content()).await;
 
     assert_eq!(timeline.inner.items().await.len(), 2);
 
@@ -120,7 +107,7 @@ async fn test_retry_message_decryption() {
             ..
         }) = event.content()
     );
-    assert_eq!(session_id, SESSION_ID);
+    assert_eq!(session_id, SECRET_SESSION_ID);
 
     assert_next_matches!(stream, VectorDiff::PushFront { value } => {
         assert!(value.is_day_divider());
@@ -133,18 +120,14 @@ async fn test_retry_message_decryption() {
         assert!(utds[0].time_to_decrypt.is_none());
     }
 
-    let own_user_id = user_id!("@example:morheus.localhost");
-    let exported_keys = decrypt_room_key_export(Cursor::new(SESSION_KEY), "1234").unwrap();
-
-    let olm_machine = OlmMachine::new(own_user_id, "SomeDeviceId".into()).await;
-    olm_machine.store().import_exported_room_keys(exported_keys, |_, _| {}).await.unwrap();
+    let olm_machine = olm_machine_with_secret_session().await;
 
     timeline
         .inner
         .retry_event_decryption_test(
             room_id!("!DovneieKSTkdHKpIXy:morpheus.localhost"),
             olm_machine,
-            Some(iter::once(SESSION_ID.to_owned()).collect()),
+            Some(iter::once(SECRET_SESSION_ID.to_owned()).collect()),
         )
         .await;
 
@@ -563,3 +546,197 @@ fn raw_event_with_unsigned(unsigned: serde_json::Value) -> Raw<AnySyncTimelineEv
         .unwrap(),
     )
 }
+
+#[async_test]
+async fn test_utd_cause_for_untrusted_sender_is_set_on_insertion() {
+    // Given a timeline,
+    let timeline = TestTimeline::new();
+    let mut stream = timeline.subscribe().await;
+
+    // When an event is added that couldn't be decrypted because its sender isn't
+    // trusted,
+    let event = timeline.event_builder.make_sync_message_event(&BOB, secret_message_content());
+    let mut event = SyncTimelineEvent::new(Raw::new(&event).unwrap().cast());
+    event.unable_to_decrypt_reason =
+        Some(UnableToDecryptReason::SenderIdentityNotTrusted(VerificationLevel::UnsignedDevice));
+
+    timeline.inner.add_events_at(vec![event], TimelineEnd::Back, RemoteEventOrigin::Sync).await;
+
+    // Then it is unable to decrypt because of its sender.
+    let item = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
+    assert_let!(
+        TimelineItemContent::UnableToDecrypt(EncryptedMessage::MegolmV1AesSha2 {
+            session_id,
+            cause,
+            ..
+        }) = item.as_event().unwrap().content()
+    );
+    assert_eq!(session_id, SECRET_SESSION_ID);
+    assert_eq!(*cause, UtdCause::UntrustedSender);
+}
+
+#[async_test]
+async fn test_retry_decryption_of_untrusted_sender_is_still_undecryptable() {
+    let room_id = room_id!("!DovneieKSTkdHKpIXy:morpheus.localhost");
+
+    // Given a timeline with an event that couldn't be decrypted,
+    let timeline = TestTimeline::new();
+    let mut stream = timeline.subscribe().await;
+
+    timeline.handle_live_message_event(&BOB, secret_message_content()).await;
+
+    let item = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
+    assert_let!(
+        TimelineItemContent::UnableToDecrypt(EncryptedMessage::MegolmV1AesSha2 { cause, .. }) =
+            item.as_event().unwrap().content()
+    );
+    assert_eq!(*cause, UtdCause::Unknown);
+    assert_next_matches!(stream, VectorDiff::PushFront { value } => {
+        assert!(value.is_day_divider());
+    });
+
+    // When its key arrives but its sender doesn't satisfy the trust requirement,
+    timeline
+        .inner
+        .retry_event_decryption_with_trust_requirement_test(
+            room_id,
+            olm_machine_with_secret_session().await,
+            TrustRequirement::CrossSigned,
+            Some(iter::once(SECRET_SESSION_ID.to_owned()).collect()),
+        )
+        .await;
+
+    // Then it stays unable to decrypt, because of its sender.
+    let item = assert_next_matches!(stream, VectorDiff::Set { index: 1, value } => value);
+    assert_let!(
+        TimelineItemContent::UnableToDecrypt(EncryptedMessage::MegolmV1AesSha2 {
+            session_id,
+            cause,
+            ..
+        }) = item.as_event().unwrap().content()
+    );
+    assert_eq!(session_id, SECRET_SESSION_ID);
+    assert_eq!(*cause, UtdCause::UntrustedSender);
+    assert_no_more_updates(&mut stream).await;
+}
+
+#[async_test]
+async fn test_reevaluate_sender_trust_hides_and_shows_events() {
+    let room_id = room_id!("!DovneieKSTkdHKpIXy:morpheus.localhost");
+
+    // Given a timeline with a decrypted event,
+    let timeline = TestTimeline::new();
+    let mut stream = timeline.subscribe().await;
+    let olm_machine = olm_machine_with_secret_session().await;
+
+    timeline.handle_live_message_event(&BOB, secret_message_content()).await;
+    let _ = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
+    let _ = assert_next_matches!(stream, VectorDiff::PushFront { value } => value);
+
+    timeline
+        .inner
+        .retry_event_decryption_with_trust_requirement_test(
+            room_id,
+            olm_machine.clone(),
+            TrustRequirement::CrossSignedOrLegacy,
+            None,
+        )
+        .await;
+
+    let item = assert_next_matches!(stream, VectorDiff::Set { index: 1, value } => value);
+    assert_let!(TimelineItemContent::Message(_) = item.as_event().unwrap().content());
+
+    // When the trust in its sender doesn't satisfy the requirement anymore,
+    timeline
+        .inner
+        .reevaluate_sender_trust_test(
+            room_id,
+            olm_machine.clone(),
+            TrustRequirement::CrossSigned,
+            iter::once(BOB.to_owned()).collect(),
+        )
+        .await;
+
+    // Then the event is hidden. The imported session isn't linked to a device, so
+    // the hidden event has no Megolm session.
+    let item = assert_next_matches!(stream, VectorDiff::Set { index: 1, value } => value);
+    let event = item.as_event().unwrap();
+    assert_let!(TimelineItemContent::UnableToDecrypt(EncryptedMessage::Unknown) = event.content());
+    assert_matches!(event.encryption_info(), Some(_));
+
+    // A retry with a satisfied trust requirement shows it again.
+    timeline
+        .inner
+        .retry_event_decryption_with_trust_requirement_test(
+            room_id,
+            olm_machine,
+            TrustRequirement::CrossSignedOrLegacy,
+            None,
+        )
+        .await;
+
+    let item = assert_next_matches!(stream, VectorDiff::Set { index: 1, value } => value);
+    assert_let!(TimelineItemContent::Message(message) = item.as_event().unwrap().content());
+    assert_eq!(message.body(), "It's a secret to everybody");
+    assert_no_more_updates(&mut stream).await;
+}
+
+#[async_test]
+async fn test_trust_updates_stream_yields_users_with_new_devices() {
+    let client = logged_in_client(None).await;
+    let alice = user_id!("@alice:example.org");
+
+    let updates = trust_updates_stream(&client).await.unwrap();
+    pin_mut!(updates);
+
+    // When new devices of Alice are received,
+    let device_keys = serde_json::from_value(KEYS_QUERY["device_keys"].clone()).unwrap();
+    let response = assign!(get_keys::v3::Response::new(), { device_keys });
+    client
+        .olm_machine_for_testing()
+        .await
+        .as_ref()
+        .unwrap()
+        .mark_request_as_sent(&TransactionId::new(), &response)
+        .await
+        .unwrap();
+
+    // Then the trust in the events of Alice needs to be checked again.
+    let user_ids = timeout(updates.next(), Duration::from_secs(1)).await.unwrap().unwrap();
+    assert_eq!(user_ids, iter::once(alice.to_owned()).collect());
+}
+
+/// An event sent by [`BOB`] in `!DovneieKSTkdHKpIXy:morpheus.localhost`, whose
+/// plain text body is "It's a secret to everybody".
+fn secret_message_content() -> RoomEncryptedEventContent {
+    RoomEncryptedEventContent::new(
+        EncryptedEventScheme::MegolmV1AesSha2(
+            MegolmV1AesSha2ContentInit {
+                ciphertext: "\
+                    AwgAEtABPRMavuZMDJrPo6pGQP4qVmpcuapuXtzKXJyi3YpEsjSWdzuRKIgJzD4P\
+                    cSqJM1A8kzxecTQNJsC5q22+KSFEPxPnI4ltpm7GFowSoPSW9+bFdnlfUzEP1jPq\
+                    YevHAsMJp2fRKkzQQbPordrUk1gNqEpGl4BYFeRqKl9GPdKFwy45huvQCLNNueql\
+                    CFZVoYMuhxrfyMiJJAVNTofkr2um2mKjDTlajHtr39pTG8k0eOjSXkLOSdZvNOMz\
+                    hGhSaFNeERSA2G2YbeknOvU7MvjiO0AKuxaAe1CaVhAI14FCgzrJ8g0y5nly+n7x\
+                    QzL2G2Dn8EoXM5Iqj8W99iokQoVsSrUEnaQ1WnSIfewvDDt4LCaD/w7PGETMCQ"
+                    .to_owned(),
+                sender_key: "DeHIg4gwhClxzFYcmNntPNF9YtsdZbmMy8+3kzCMXHA".to_owned(),
+                device_id: "NLAZCWIOCO".into(),
+                session_id: SECRET_SESSION_ID.into(),
+            }
+            .into(),
+        ),
+        None,
+    )
+}
+
+/// An `OlmMachine` that imported the key of [`secret_message_content()`].
+async fn olm_machine_with_secret_session() -> OlmMachine {
+    let own_user_id = user_id!("@example:morheus.localhost");
+    let exported_keys = decrypt_room_key_export(Cursor::new(SECRET_SESSION_KEY), "1234").unwrap();
+
+    let olm_machine = OlmMachine::new(own_user_id, "SomeDeviceId".into()).await;
+    olm_machine.store().import_exported_room_keys(exported_keys, |_, _| {}).await.unwrap();
+
+    olm_machine
+}
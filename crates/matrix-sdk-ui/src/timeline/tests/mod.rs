@@ -72,6 +72,7 @@ mod reaction_group;
 mod reactions;
 mod read_receipts;
 mod redaction;
+mod state_groups;
 mod virt;
 
 struct TestTimeline {
@@ -91,6 +92,7 @@ impl TestTimeline {
                 TimelineFocus::Live,
                 Some(prefix),
                 None,
+                false,
             ),
             event_builder: EventBuilder::new(),
         }
@@ -98,7 +100,7 @@ impl TestTimeline {
 
     fn with_room_data_provider(room_data_provider: TestRoomDataProvider) -> Self {
         Self {
-            inner: TimelineInner::new(room_data_provider, TimelineFocus::Live, None, None),
+            inner: TimelineInner::new(room_data_provider, TimelineFocus::Live, None, None, false),
             event_builder: EventBuilder::new(),
         }
     }
@@ -110,6 +112,20 @@ impl TestTimeline {
                 TimelineFocus::Live,
                 None,
                 Some(hook),
+                false,
+            ),
+            event_builder: EventBuilder::new(),
+        }
+    }
+
+    fn with_grouped_state_items() -> Self {
+        Self {
+            inner: TimelineInner::new(
+                TestRoomDataProvider::default(),
+                TimelineFocus::Live,
+                None,
+                None,
+                true,
             ),
             event_builder: EventBuilder::new(),
         }
@@ -229,7 +245,7 @@ impl TestTimeline {
     }
 
     async fn handle_live_event(&self, event: Raw<AnySyncTimelineEvent>) {
-        let event = SyncTimelineEvent { event, encryption_info: None, push_actions: vec![] };
+        let event = SyncTimelineEvent::new(event);
         self.inner.handle_live_event(event).await
     }
 
@@ -1,7 +1,11 @@
-use matrix_sdk_test::{async_test, ALICE, BOB};
+use std::sync::Arc;
+
+use assert_matches::assert_matches;
+use matrix_sdk_test::{async_test, sync_timeline_event, ALICE, BOB};
 use ruma::{
     events::{
         poll::{
+            start::PollKind,
             unstable_end::UnstablePollEndEventContent,
             unstable_response::UnstablePollResponseEventContent,
             unstable_start::{
@@ -11,11 +15,13 @@ use ruma::{
         },
         AnyMessageLikeEventContent,
     },
-    server_name, EventId, OwnedEventId, UserId,
+    server_name, uint, EventId, OwnedEventId, UserId,
 };
 
 use crate::timeline::{
-    polls::PollState, tests::TestTimeline, EventTimelineItem, TimelineItemContent,
+    polls::{new_poll_content, PollState},
+    tests::TestTimeline,
+    EventTimelineItem, PollError, TimelineItemContent,
 };
 
 #[async_test]
@@ -90,6 +96,23 @@ async fn only_the_last_vote_from_a_user_is_counted() {
     assert_eq!(results.votes["id_down"], vec![ALICE.to_string()]);
 }
 
+#[async_test]
+async fn empty_vote_retracts_the_previous_vote() {
+    let timeline = TestTimeline::new();
+    timeline.send_poll_start(&ALICE, fakes::poll_a()).await;
+    let poll_id = timeline.poll_event().await.event_id().unwrap().to_owned();
+
+    // Alice votes
+    timeline.send_poll_response(&ALICE, vec!["id_up"], &poll_id).await;
+    let results = timeline.poll_state().await.results();
+    assert_eq!(results.votes["id_up"], vec![ALICE.to_string()]);
+
+    // Alice spoils her vote
+    timeline.send_poll_response(&ALICE, vec![], &poll_id).await;
+    let results = timeline.poll_state().await.results();
+    assert!(results.votes.values().all(|voters| voters.is_empty()));
+}
+
 #[async_test]
 async fn votes_after_end_are_discarded() {
     let timeline = TestTimeline::new();
@@ -187,6 +210,173 @@ async fn events_received_before_start_are_not_lost() {
     assert_eq!(results.votes["id_down"], vec![ALICE.to_string()]);
 }
 
+#[async_test]
+async fn local_vote_is_replaced_by_its_remote_echo() {
+    let timeline = TestTimeline::new();
+    timeline.send_poll_start(&BOB, fakes::poll_a()).await;
+    let poll_id = timeline.poll_event().await.event_id().unwrap().to_owned();
+
+    // Alice votes, her vote is shown right away.
+    let txn_id = timeline
+        .handle_local_event(AnyMessageLikeEventContent::UnstablePollResponse(
+            UnstablePollResponseEventContent::new(vec!["id_up".to_owned()], poll_id.clone()),
+        ))
+        .await;
+    let poll_state = timeline.poll_state().await;
+    assert!(poll_state.has_local_response(&txn_id));
+    assert_eq!(poll_state.results().votes["id_up"], vec![ALICE.to_string()]);
+
+    // The remote echo replaces the local vote.
+    timeline
+        .handle_live_custom_event(sync_timeline_event!({
+            "content": {
+                "org.matrix.msc3381.poll.response": { "answers": ["id_up"] },
+                "m.relates_to": { "rel_type": "m.reference", "event_id": poll_id },
+            },
+            "sender": &*ALICE,
+            "event_id": "$response",
+            "origin_server_ts": 10,
+            "type": "org.matrix.msc3381.poll.response",
+            "unsigned": { "transaction_id": txn_id },
+        }))
+        .await;
+    let poll_state = timeline.poll_state().await;
+    assert!(!poll_state.has_local_response(&txn_id));
+    assert_eq!(poll_state.response_data.len(), 1);
+    assert_eq!(poll_state.results().votes["id_up"], vec![ALICE.to_string()]);
+}
+
+#[async_test]
+async fn local_vote_that_is_not_sent_is_removed() {
+    let timeline = TestTimeline::new();
+    timeline.send_poll_start(&BOB, fakes::poll_a()).await;
+    let poll_id = timeline.poll_event().await.event_id().unwrap().to_owned();
+
+    let txn_id = timeline
+        .handle_local_event(AnyMessageLikeEventContent::UnstablePollResponse(
+            UnstablePollResponseEventContent::new(vec!["id_up".to_owned()], poll_id),
+        ))
+        .await;
+    assert_eq!(timeline.poll_state().await.response_data.len(), 1);
+
+    assert!(timeline.inner.discard_local_poll_response(&txn_id).await);
+    assert!(timeline.poll_state().await.response_data.is_empty());
+
+    // There's nothing left to discard.
+    assert!(!timeline.inner.discard_local_poll_response(&txn_id).await);
+}
+
+#[async_test]
+async fn local_vote_that_fails_to_be_sent_is_kept() {
+    let timeline = TestTimeline::new();
+    timeline.send_poll_start(&BOB, fakes::poll_a()).await;
+    let poll_id = timeline.poll_event().await.event_id().unwrap().to_owned();
+
+    let txn_id = timeline
+        .handle_local_event(AnyMessageLikeEventContent::UnstablePollResponse(
+            UnstablePollResponseEventContent::new(vec!["id_up".to_owned()], poll_id),
+        ))
+        .await;
+    assert!(timeline.poll_state().await.results().own_vote_send_error.is_none());
+
+    // The vote is still counted, but marked as failed.
+    let error = Arc::new(matrix_sdk::Error::InconsistentState);
+    assert!(timeline.inner.set_local_poll_response_send_error(&txn_id, Some(error)).await);
+    let results = timeline.poll_state().await.results();
+    assert_eq!(results.votes["id_up"], vec![ALICE.to_string()]);
+    assert!(results.own_vote_send_error.is_some());
+
+    // Once it's sent, it's not marked as failed anymore.
+    assert!(timeline.inner.set_local_poll_response_send_error(&txn_id, None).await);
+    let results = timeline.poll_state().await.results();
+    assert_eq!(results.votes["id_up"], vec![ALICE.to_string()]);
+    assert!(results.own_vote_send_error.is_none());
+}
+
+#[async_test]
+async fn remote_end_replaces_the_local_end() {
+    let timeline = TestTimeline::new();
+    timeline.send_poll_start(&ALICE, fakes::poll_a()).await;
+    let poll_id = timeline.poll_event().await.event_id().unwrap().to_owned();
+
+    // Alice ends the poll.
+    timeline
+        .handle_local_event(AnyMessageLikeEventContent::UnstablePollEnd(
+            UnstablePollEndEventContent::new("ENDED", poll_id.clone()),
+        ))
+        .await;
+    let poll_state = timeline.poll_state().await;
+    assert!(poll_state.has_ended());
+    assert!(poll_state.end_event_is_local);
+    let local_end_time = poll_state.end_event_timestamp.unwrap();
+
+    // The remote echo sets the end time used to count the votes.
+    timeline.send_poll_end(&ALICE, "ENDED", &poll_id).await;
+    let poll_state = timeline.poll_state().await;
+    assert!(!poll_state.end_event_is_local);
+    assert_ne!(poll_state.end_event_timestamp.unwrap(), local_end_time);
+
+    // Other end events are still discarded.
+    let end_time = poll_state.end_event_timestamp;
+    timeline.send_poll_end(&ALICE, "ENDED", &poll_id).await;
+    assert_eq!(timeline.poll_state().await.end_event_timestamp, end_time);
+}
+
+#[async_test]
+async fn responses_are_validated() {
+    let timeline = TestTimeline::new();
+    timeline.send_poll_start(&ALICE, fakes::poll_a()).await;
+    let poll_id = timeline.poll_event().await.event_id().unwrap().to_owned();
+    let poll_state = timeline.poll_state().await;
+
+    poll_state.validate_response(&["id_up".to_owned()]).unwrap();
+    poll_state.validate_response(&[]).unwrap();
+    assert_matches!(
+        poll_state.validate_response(&["id_up".to_owned(), "id_down".to_owned()]),
+        Err(PollError::InvalidSelectionCount { count: 2, max_selections: 1 })
+    );
+    assert_matches!(
+        poll_state.validate_response(&["id_left".to_owned()]),
+        Err(PollError::UnknownAnswer(answer)) if answer == "id_left"
+    );
+
+    timeline.send_poll_end(&ALICE, "ENDED", &poll_id).await;
+    assert_matches!(
+        timeline.poll_state().await.validate_response(&["id_up".to_owned()]),
+        Err(PollError::PollEnded)
+    );
+}
+
+#[test]
+fn new_polls_are_validated() {
+    let answers = || vec!["Up".to_owned(), "Down".to_owned()];
+
+    let content =
+        new_poll_content("Up or down?".to_owned(), answers(), 2, PollKind::Undisclosed).unwrap();
+    assert_eq!(content.text.as_deref(), Some("Up or down?\n1. Up\n2. Down"));
+    assert_eq!(content.poll_start.kind, PollKind::Undisclosed);
+    assert_eq!(content.poll_start.max_selections, uint!(2));
+    assert_eq!(content.poll_start.answers.len(), 2);
+    assert_ne!(content.poll_start.answers[0].id, content.poll_start.answers[1].id);
+
+    assert_matches!(
+        new_poll_content("Nothing?".to_owned(), vec![], 1, PollKind::Disclosed),
+        Err(PollError::InvalidAnswerCount(0))
+    );
+    assert_matches!(
+        new_poll_content("Up or down?".to_owned(), answers(), 3, PollKind::Disclosed),
+        Err(PollError::InvalidMaxSelections(3))
+    );
+    assert_matches!(
+        new_poll_content("Up or down?".to_owned(), answers(), 0, PollKind::Disclosed),
+        Err(PollError::InvalidMaxSelections(0))
+    );
+    assert_matches!(
+        new_poll_content("Up or down?".to_owned(), answers(), 1, PollKind::from("custom")),
+        Err(PollError::UnsupportedPollKind(_))
+    );
+}
+
 impl TestTimeline {
     async fn event_items(&self) -> Vec<EventTimelineItem> {
         self.inner.items().await.iter().filter_map(|item| item.as_event().cloned()).collect()
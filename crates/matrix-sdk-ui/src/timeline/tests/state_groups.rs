@@ -0,0 +1,157 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use assert_matches2::assert_let;
+use matrix_sdk_test::{async_test, ALICE, BOB};
+use ruma::events::room::{
+    member::{MembershipState, RoomMemberEventContent},
+    message::RoomMessageEventContent,
+    topic::RoomTopicEventContent,
+};
+
+use super::TestTimeline;
+use crate::timeline::{MembershipChange, TimelineItemKind, VirtualTimelineItem};
+
+fn grouping_timeline() -> TestTimeline {
+    TestTimeline::with_grouped_state_items()
+}
+
+#[async_test]
+async fn test_consecutive_state_items_are_grouped() {
+    let timeline = grouping_timeline();
+
+    timeline
+        .handle_live_state_event_with_state_key(
+            &ALICE,
+            ALICE.to_owned(),
+            RoomMemberEventContent::new(MembershipState::Join),
+            None,
+        )
+        .await;
+
+    // A single state item isn't grouped.
+    let items = timeline.inner.items().await;
+    assert_eq!(items.len(), 2);
+    assert!(items[0].is_day_divider());
+    assert!(items[1].as_event().is_some());
+
+    timeline
+        .handle_live_state_event_with_state_key(
+            &BOB,
+            BOB.to_owned(),
+            RoomMemberEventContent::new(MembershipState::Join),
+            None,
+        )
+        .await;
+
+    let items = timeline.inner.items().await;
+    assert_eq!(items.len(), 4);
+    assert!(items[0].is_day_divider());
+    assert_let!(
+        TimelineItemKind::Virtual(VirtualTimelineItem::StateGroup(group)) = items[1].kind()
+    );
+    assert_eq!(group.children().len(), 2);
+    assert!(group.contains(items[2].unique_id()));
+    assert!(group.contains(items[3].unique_id()));
+
+    let summary = group.summary();
+    assert_eq!(summary.membership_changes.get(&MembershipChange::Joined), Some(&2));
+    assert_eq!(summary.users, vec![ALICE.to_owned(), BOB.to_owned()]);
+    assert_eq!(summary.other_state_changes, 0);
+
+    let group_id = items[1].unique_id().to_owned();
+
+    // Another state item extends the existing group.
+    timeline
+        .handle_live_state_event(&ALICE, RoomTopicEventContent::new("Cats".to_owned()), None)
+        .await;
+
+    let items = timeline.inner.items().await;
+    assert_eq!(items.len(), 5);
+    assert_let!(
+        TimelineItemKind::Virtual(VirtualTimelineItem::StateGroup(group)) = items[1].kind()
+    );
+    assert_eq!(items[1].unique_id(), group_id);
+    assert_eq!(group.children().len(), 3);
+    assert_eq!(group.summary().other_state_changes, 1);
+    assert_eq!(group.summary().users, vec![ALICE.to_owned(), BOB.to_owned()]);
+}
+
+#[async_test]
+async fn test_message_breaks_state_group() {
+    let timeline = grouping_timeline();
+
+    timeline
+        .handle_live_state_event_with_state_key(
+            &ALICE,
+            ALICE.to_owned(),
+            RoomMemberEventContent::new(MembershipState::Join),
+            None,
+        )
+        .await;
+    timeline
+        .handle_live_state_event_with_state_key(
+            &BOB,
+            BOB.to_owned(),
+            RoomMemberEventContent::new(MembershipState::Join),
+            None,
+        )
+        .await;
+    timeline.handle_live_message_event(&ALICE, RoomMessageEventContent::text_plain("hi")).await;
+    timeline
+        .handle_live_state_event_with_state_key(
+            &BOB,
+            BOB.to_owned(),
+            RoomMemberEventContent::new(MembershipState::Leave),
+            None,
+        )
+        .await;
+
+    // The message and the state item following it stay ungrouped.
+    let items = timeline.inner.items().await;
+    assert_eq!(items.len(), 6);
+    assert!(items[0].is_day_divider());
+    assert!(items[1].is_state_group());
+    assert!(items[2].as_event().is_some());
+    assert!(items[3].as_event().is_some());
+    assert!(items[4].as_event().is_some());
+    assert!(items[5].as_event().is_some());
+    assert_eq!(items.iter().filter(|item| item.is_state_group()).count(), 1);
+}
+
+#[async_test]
+async fn test_state_items_are_not_grouped_by_default() {
+    let timeline = TestTimeline::new();
+
+    timeline
+        .handle_live_state_event_with_state_key(
+            &ALICE,
+            ALICE.to_owned(),
+            RoomMemberEventContent::new(MembershipState::Join),
+            None,
+        )
+        .await;
+    timeline
+        .handle_live_state_event_with_state_key(
+            &BOB,
+            BOB.to_owned(),
+            RoomMemberEventContent::new(MembershipState::Join),
+            None,
+        )
+        .await;
+
+    let items = timeline.inner.items().await;
+    assert_eq!(items.len(), 3);
+    assert!(!items.iter().any(|item| item.is_state_group()));
+}
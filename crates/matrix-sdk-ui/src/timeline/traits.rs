@@ -15,7 +15,10 @@
 use async_trait::async_trait;
 use indexmap::IndexMap;
 #[cfg(feature = "e2e-encryption")]
-use matrix_sdk::{deserialized_responses::TimelineEvent, Result};
+use matrix_sdk::{
+    deserialized_responses::{EncryptionInfo, TimelineEvent},
+    Result,
+};
 use matrix_sdk::{event_cache::paginator::PaginableRoom, Room};
 use matrix_sdk_base::latest_event::LatestEvent;
 #[cfg(feature = "e2e-encryption")]
@@ -219,6 +222,8 @@ impl RoomDataProvider for Room {
 #[async_trait]
 pub(super) trait Decryptor: Clone + Send + Sync + 'static {
     async fn decrypt_event_impl(&self, raw: &Raw<AnySyncTimelineEvent>) -> Result<TimelineEvent>;
+
+    async fn check_sender_trust_impl(&self, encryption_info: &EncryptionInfo) -> Result<()>;
 }
 
 #[cfg(feature = "e2e-encryption")]
@@ -227,6 +232,10 @@ impl Decryptor for Room {
     async fn decrypt_event_impl(&self, raw: &Raw<AnySyncTimelineEvent>) -> Result<TimelineEvent> {
         self.decrypt_event(raw.cast_ref()).await
     }
+
+    async fn check_sender_trust_impl(&self, encryption_info: &EncryptionInfo) -> Result<()> {
+        self.client().encryption().check_sender_trust(encryption_info).await
+    }
 }
 
 #[cfg(all(test, feature = "e2e-encryption"))]
@@ -237,4 +246,42 @@ impl Decryptor for (matrix_sdk_base::crypto::OlmMachine, ruma::OwnedRoomId) {
         let event = olm_machine.decrypt_room_event(raw.cast_ref(), room_id).await?;
         Ok(event)
     }
+
+    async fn check_sender_trust_impl(&self, encryption_info: &EncryptionInfo) -> Result<()> {
+        let (olm_machine, _) = self;
+        olm_machine
+            .check_sender_trust(
+                encryption_info,
+                matrix_sdk::encryption::TrustRequirement::Untrusted,
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "e2e-encryption"))]
+#[async_trait]
+impl Decryptor
+    for (
+        matrix_sdk_base::crypto::OlmMachine,
+        ruma::OwnedRoomId,
+        matrix_sdk::encryption::TrustRequirement,
+    )
+{
+    async fn decrypt_event_impl(&self, raw: &Raw<AnySyncTimelineEvent>) -> Result<TimelineEvent> {
+        let (olm_machine, room_id, trust_requirement) = self;
+        let settings = matrix_sdk_base::crypto::DecryptionSettings {
+            sender_device_trust_requirement: *trust_requirement,
+        };
+        let event = olm_machine
+            .decrypt_room_event_with_settings(raw.cast_ref(), room_id, &settings)
+            .await?;
+        Ok(event)
+    }
+
+    async fn check_sender_trust_impl(&self, encryption_info: &EncryptionInfo) -> Result<()> {
+        let (olm_machine, _, trust_requirement) = self;
+        olm_machine.check_sender_trust(encryption_info, *trust_requirement).await?;
+        Ok(())
+    }
 }
@@ -0,0 +1,162 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! URL previews of the messages of the timeline.
+
+use matrix_sdk::{media::UrlPreview, Result};
+use ruma::html::{Html, NodeRef};
+use tracing::{instrument, warn};
+use url::Url;
+
+use super::EventTimelineItem;
+
+/// The characters that open a parenthesis or a quote, which are not considered
+/// part of a URL when they start it.
+const LEADING_PUNCTUATION: &[char] = &['(', '[', '{', '\'', '"'];
+
+/// The characters that end a sentence or close a parenthesis, which are not
+/// considered part of a URL when they end it.
+const TRAILING_PUNCTUATION: &[char] = &['.', ',', ';', ':', '!', '?', ')', ']', '}', '\'', '"'];
+
+impl super::Timeline {
+    /// Get the previews of the URLs of the given item, if it is a message.
+    ///
+    /// Returns an empty list if URL previews aren't allowed in the room, see
+    /// [`Room::url_previews_allowed()`]. The previews are requested for the
+    /// time the message was sent at. URLs that can't be previewed are skipped.
+    ///
+    /// The preview images can be downloaded with
+    /// [`UrlPreview::image_request()`].
+    ///
+    /// [`Room::url_previews_allowed()`]: matrix_sdk::Room::url_previews_allowed
+    #[instrument(skip_all, fields(room_id = ?self.room().room_id()))]
+    pub async fn url_previews(
+        &self,
+        item: &EventTimelineItem,
+    ) -> Result<Vec<(String, UrlPreview)>> {
+        let Some(message) = item.content().as_message() else {
+            return Ok(Vec::new());
+        };
+
+        let urls = message.urls();
+        if urls.is_empty() || !self.room().url_previews_allowed().await? {
+            return Ok(Vec::new());
+        }
+
+        let media = self.room().client().media();
+        let mut previews = Vec::with_capacity(urls.len());
+
+        for url in urls {
+            match media.get_url_preview(&url, Some(item.timestamp())).await {
+                Ok(preview) => previews.push((url, preview)),
+                Err(error) => warn!("Failed to get the preview of {url}: {error}"),
+            }
+        }
+
+        Ok(previews)
+    }
+}
+
+/// Extract the web URLs of a message, from its plain text body and the links
+/// of its HTML body, in order and without duplicates.
+///
+/// `matrix.to` links are not considered, as they point to Matrix entities.
+pub(super) fn extract_urls(body: &str, html: Option<&str>) -> Vec<String> {
+    let mut urls = Vec::new();
+
+    for word in body.split(|c: char| c.is_whitespace() || c == '<' || c == '>') {
+        let word =
+            word.trim_start_matches(LEADING_PUNCTUATION).trim_end_matches(TRAILING_PUNCTUATION);
+        push_url(&mut urls, word);
+    }
+
+    if let Some(html) = html {
+        let html = Html::parse(html);
+        for node in html.children() {
+            collect_links(&node, &mut urls);
+        }
+    }
+
+    urls
+}
+
+fn collect_links(node: &NodeRef<'_>, urls: &mut Vec<String>) {
+    if let Some(element) = node.as_element() {
+        if &*element.name.local == "a" {
+            if let Some(href) = element.attrs.iter().find(|attr| &*attr.name.local == "href") {
+                push_url(urls, &href.value);
+            }
+        }
+    }
+
+    for child in node.children() {
+        collect_links(&child, urls);
+    }
+}
+
+/// Add the given candidate to the list if it is a web URL that can be
+/// previewed and isn't there yet.
+fn push_url(urls: &mut Vec<String>, candidate: &str) {
+    if !candidate.starts_with("https://") && !candidate.starts_with("http://") {
+        return;
+    }
+
+    let Ok(url) = Url::parse(candidate) else {
+        return;
+    };
+
+    if url.host_str().map_or(true, |host| host == "matrix.to") {
+        return;
+    }
+
+    if !urls.iter().any(|known| known == candidate) {
+        urls.push(candidate.to_owned());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::extract_urls;
+
+    #[test]
+    fn test_extract_urls_from_body() {
+        let urls = extract_urls(
+            "Have a look at https://example.org/page?id=1, and (http://example.com/).",
+            None,
+        );
+        assert_eq!(urls, ["https://example.org/page?id=1", "http://example.com/"]);
+    }
+
+    #[test]
+    fn test_extract_urls_ignores_non_web_urls() {
+        let urls = extract_urls(
+            "Ping https://matrix.to/#/@alice:example.org at mailto:alice@example.org or \
+             ftp://example.org",
+            None,
+        );
+        assert!(urls.is_empty());
+    }
+
+    #[test]
+    fn test_extract_urls_from_html() {
+        let urls = extract_urls(
+            "See the docs and https://example.org",
+            Some(
+                "See <a href=\"https://docs.example.org/\">the docs</a> and \
+                 <a href=\"https://example.org\">https://example.org</a>",
+            ),
+        );
+        assert_eq!(urls, ["https://example.org", "https://docs.example.org/"]);
+    }
+}
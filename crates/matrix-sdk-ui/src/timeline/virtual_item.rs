@@ -12,7 +12,10 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use ruma::MilliSecondsSinceUnixEpoch;
+use indexmap::IndexMap;
+use ruma::{MilliSecondsSinceUnixEpoch, OwnedUserId};
+
+use super::{MembershipChange, TimelineItem, TimelineItemContent};
 
 /// A [`TimelineItem`](super::TimelineItem) that doesn't correspond to an event.
 #[derive(Clone, Debug)]
@@ -25,4 +28,117 @@ pub enum VirtualTimelineItem {
 
     /// The user's own read marker.
     ReadMarker,
+
+    /// A group of consecutive state items.
+    ///
+    /// Only created when enabled with
+    /// [`TimelineBuilder::group_state_items`](super::TimelineBuilder::group_state_items).
+    StateGroup(StateGroup),
+
+    /// A hole in the history of the room, between the items around it.
+    ///
+    /// It appears when the room had too many new events while the client was
+    /// offline, and can be filled with
+    /// [`Timeline::fill_gap`](super::Timeline::fill_gap).
+    Gap {
+        /// The token to back-paginate from, to get the missing events.
+        prev_token: String,
+    },
+}
+
+/// A group of consecutive state items, like membership or profile changes.
+///
+/// The grouped items are kept in the timeline, right after the group, so that
+/// they are updated like any other item. When the group is shown collapsed,
+/// they should be hidden; they can be recognized with [`Self::contains`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StateGroup {
+    pub(in crate::timeline) summary: StateGroupSummary,
+    pub(in crate::timeline) children: Vec<String>,
+}
+
+impl StateGroup {
+    pub(in crate::timeline) fn new<'a>(items: impl IntoIterator<Item = &'a TimelineItem>) -> Self {
+        let mut summary = StateGroupSummary::default();
+        let mut children = Vec::new();
+
+        for item in items {
+            summary.add(item);
+            children.push(item.unique_id().to_owned());
+        }
+
+        Self { summary, children }
+    }
+
+    /// A summary of the changes in this group.
+    pub fn summary(&self) -> &StateGroupSummary {
+        &self.summary
+    }
+
+    /// The unique IDs of the grouped items, in timeline order.
+    pub fn children(&self) -> &[String] {
+        &self.children
+    }
+
+    /// Whether the timeline item with the given unique ID is part of this
+    /// group.
+    pub fn contains(&self, unique_id: &str) -> bool {
+        self.children.iter().any(|id| id == unique_id)
+    }
+}
+
+/// A summary of the changes in a [`StateGroup`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StateGroupSummary {
+    /// The number of membership changes, per kind of change, in the order they
+    /// first appear.
+    ///
+    /// Membership changes that couldn't be computed are counted as
+    /// [`MembershipChange::NotImplemented`].
+    pub membership_changes: IndexMap<MembershipChange, usize>,
+
+    /// The number of display name changes.
+    pub display_name_changes: usize,
+
+    /// The number of avatar changes.
+    pub avatar_changes: usize,
+
+    /// The number of other state changes.
+    pub other_state_changes: usize,
+
+    /// The users whose membership or profile changed, or who sent other state
+    /// changes, in the order they first appear.
+    pub users: Vec<OwnedUserId>,
+}
+
+impl StateGroupSummary {
+    fn add(&mut self, item: &TimelineItem) {
+        let Some(event) = item.as_event() else { return };
+
+        let user_id = match event.content() {
+            TimelineItemContent::MembershipChange(change) => {
+                let kind = change.change().unwrap_or(MembershipChange::NotImplemented);
+                *self.membership_changes.entry(kind).or_default() += 1;
+                change.user_id()
+            }
+            TimelineItemContent::ProfileChange(change) => {
+                if change.displayname_change().is_some() {
+                    self.display_name_changes += 1;
+                }
+                if change.avatar_url_change().is_some() {
+                    self.avatar_changes += 1;
+                }
+                change.user_id()
+            }
+            TimelineItemContent::OtherState(_) => {
+                self.other_state_changes += 1;
+                event.sender()
+            }
+            _ => return,
+        };
+
+        if !self.users.iter().any(|u| **u == *user_id) {
+            self.users.push(user_id.to_owned());
+        }
+    }
 }
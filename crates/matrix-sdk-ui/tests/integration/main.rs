@@ -24,6 +24,7 @@ use wiremock::{
 };
 
 mod encryption_sync_service;
+mod media_gallery;
 mod notification_client;
 mod room_list_service;
 mod sliding_sync;
@@ -0,0 +1,184 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::time::Duration;
+
+use assert_matches2::assert_let;
+use itertools::Itertools as _;
+use matrix_sdk::{
+    config::SyncSettings,
+    media::MediaEventContent,
+    test_utils::{events::EventFactory, logged_in_client_with_server},
+};
+use matrix_sdk_test::{async_test, JoinedRoomBuilder, SyncResponseBuilder, ALICE};
+use matrix_sdk_ui::media_gallery::{
+    MediaGallery, MediaGalleryItem, MediaGalleryMonth, MediaItemContent,
+};
+use ruma::{
+    events::room::{
+        message::{ImageMessageEventContent, MessageType, RoomMessageEventContent},
+        MediaSource,
+    },
+    mxc_uri, room_id,
+};
+use serde_json::json;
+use wiremock::{
+    matchers::{header, method, path_regex, query_param_is_missing},
+    Mock, ResponseTemplate,
+};
+
+use crate::{mock_encryption_state, mock_messages, mock_sync};
+
+fn image(name: &str, url: &str) -> RoomMessageEventContent {
+    RoomMessageEventContent::new(MessageType::Image(ImageMessageEventContent::plain(
+        name.to_owned(),
+        url.into(),
+    )))
+}
+
+#[async_test]
+async fn test_media_gallery_paginates_media_by_month() {
+    let room_id = room_id!("!a98sd12bjh:example.org");
+    let (client, server) = logged_in_client_with_server().await;
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+    let mut sync_response_builder = SyncResponseBuilder::new();
+    sync_response_builder.add_joined_room(JoinedRoomBuilder::new(room_id));
+
+    mock_sync(&server, sync_response_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    mock_encryption_state(&server, false).await;
+
+    let f = EventFactory::new().room(room_id).sender(*ALICE);
+
+    // 2024-01-15.
+    f.set_next_ts(1_705_276_800_000);
+    let january_image = f.event(image("january.png", "mxc://example.org/january")).into_timeline();
+    // 2024-03-15.
+    f.set_next_ts(1_710_460_800_000);
+    let march_image = f.event(image("march.png", "mxc://example.org/march")).into_timeline();
+    let text = f.text_msg("not a media").into_timeline();
+    // 2024-03-16.
+    f.set_next_ts(1_710_547_200_000);
+    let other_march_image =
+        f.event(image("march2.png", "mxc://example.org/march2")).into_timeline();
+
+    // The first pagination starts from the end of the room.
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/messages$"))
+        .and(header("authorization", "Bearer 1234"))
+        .and(query_param_is_missing("from"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "start": "t0",
+            "end": "t1",
+            "chunk": [other_march_image, text, march_image]
+                .into_iter()
+                .map(|ev| ev.event)
+                .collect_vec(),
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let room = client.get_room(room_id).unwrap();
+    let gallery = MediaGallery::new(room);
+    let (items, _stream) = gallery.subscribe().await;
+    assert!(items.is_empty());
+
+    let hit_start = gallery.paginate_backwards(2).await.unwrap();
+    assert!(!hit_start);
+
+    let (items, _stream) = gallery.subscribe().await;
+    assert_eq!(items.len(), 3);
+    assert_let!(MediaGalleryItem::Month(month) = &items[0]);
+    assert_eq!(*month, MediaGalleryMonth { year: 2024, month: 3 });
+    assert_let!(MediaItemContent::Image(first) = items[1].as_media().unwrap().content());
+    assert_eq!(first.body, "march.png");
+    assert_let!(MediaItemContent::Image(second) = items[2].as_media().unwrap().content());
+    assert_eq!(second.body, "march2.png");
+
+    // Unencrypted images without a thumbnail are their own thumbnail source.
+    assert_eq!(
+        items[1].as_media().unwrap().thumbnail_source(),
+        Some(MediaSource::Plain(mxc_uri!("mxc://example.org/march").to_owned()))
+    );
+
+    // The next pagination continues from the previous token, and reaches the
+    // start of the room.
+    mock_messages(&server, "t1".to_owned(), None, vec![january_image], vec![]).await;
+
+    let hit_start = gallery.paginate_backwards(2).await.unwrap();
+    assert!(hit_start);
+
+    let (items, _stream) = gallery.subscribe().await;
+    assert_eq!(items.len(), 5);
+    assert_let!(MediaGalleryItem::Month(month) = &items[0]);
+    assert_eq!(*month, MediaGalleryMonth { year: 2024, month: 1 });
+    assert_eq!(
+        items[1].as_media().unwrap().source(),
+        Some(MediaSource::Plain(mxc_uri!("mxc://example.org/january").to_owned()))
+    );
+    assert_let!(MediaGalleryItem::Month(month) = &items[2]);
+    assert_eq!(*month, MediaGalleryMonth { year: 2024, month: 3 });
+
+    // Once the start is reached, there's nothing more to load.
+    assert!(gallery.paginate_backwards(2).await.unwrap());
+}
+
+#[async_test]
+async fn test_media_gallery_stops_when_token_does_not_change() {
+    let room_id = room_id!("!a98sd12bjh:example.org");
+    let (client, server) = logged_in_client_with_server().await;
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+    let mut sync_response_builder = SyncResponseBuilder::new();
+    sync_response_builder.add_joined_room(JoinedRoomBuilder::new(room_id));
+
+    mock_sync(&server, sync_response_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    mock_encryption_state(&server, false).await;
+
+    let f = EventFactory::new().room(room_id).sender(*ALICE);
+    let text = f.text_msg("not a media").into_timeline();
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/messages$"))
+        .and(header("authorization", "Bearer 1234"))
+        .and(query_param_is_missing("from"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "start": "t0",
+            "end": "t1",
+            "chunk": [text.event],
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    // The server doesn't make progress anymore.
+    mock_messages(&server, "t1".to_owned(), Some("t1".to_owned()), vec![], vec![]).await;
+
+    let room = client.get_room(room_id).unwrap();
+    let gallery = MediaGallery::new(room);
+
+    // The pagination stops instead of requesting the same page forever.
+    let hit_start = gallery.paginate_backwards(2).await.unwrap();
+    assert!(!hit_start);
+
+    let (items, _stream) = gallery.subscribe().await;
+    assert!(items.is_empty());
+}
@@ -1,13 +1,18 @@
 use std::{
+    future::ready,
     ops::Not,
+    sync::Arc,
     time::{Duration, Instant},
 };
 
 use assert_matches::assert_matches;
 use eyeball_im::VectorDiff;
 use futures_util::{pin_mut, FutureExt, StreamExt};
-use imbl::vector;
-use matrix_sdk::{test_utils::logged_in_client_with_server, Client};
+use imbl::{vector, Vector};
+use matrix_sdk::{
+    sliding_sync::Version as SlidingSyncVersion, test_utils::logged_in_client_with_server, Client,
+    SlidingSyncList, SlidingSyncMode,
+};
 use matrix_sdk_base::sync::UnreadNotificationsCount;
 use matrix_sdk_test::async_test;
 use matrix_sdk_ui::{
@@ -16,7 +21,7 @@ use matrix_sdk_ui::{
         Error, Input, InputResult, RoomListEntry, RoomListLoadingState, State, SyncIndicator,
         ALL_ROOMS_LIST_NAME as ALL_ROOMS, VISIBLE_ROOMS_LIST_NAME as VISIBLE_ROOMS,
     },
-    timeline::{TimelineItemKind, VirtualTimelineItem},
+    timeline::{TimelineItem, TimelineItemKind, VirtualTimelineItem},
     RoomListService,
 };
 use ruma::{
@@ -27,10 +32,13 @@ use ruma::{
 };
 use serde_json::json;
 use stream_assert::{assert_next_matches, assert_pending};
-use tokio::{spawn, sync::mpsc::channel, task::yield_now};
+use tokio::{spawn, sync::mpsc::channel, task::yield_now, time::timeout};
 use wiremock::MockServer;
 
-use crate::timeline::sliding_sync::{assert_timeline_stream, timeline_event};
+use crate::{
+    sliding_sync_then_assert_request_and_fake_response,
+    timeline::sliding_sync::{assert_timeline_stream, timeline_event},
+};
 
 async fn new_room_list_service() -> Result<(Client, MockServer, RoomListService), Error> {
     let (client, server) = logged_in_client_with_server().await;
@@ -474,6 +482,188 @@ async fn test_sync_all_states() -> Result<(), Error> {
     Ok(())
 }
 
+#[async_test]
+async fn test_sync_with_native_sliding_sync() -> Result<(), Error> {
+    let (client, server) = logged_in_client_with_server().await;
+    client.set_sliding_sync_version(SlidingSyncVersion::Native);
+
+    let room_list = RoomListService::new(client.clone()).await?;
+
+    let sync = room_list.sync();
+    pin_mut!(sync);
+
+    let all_rooms = room_list.all_rooms().await?;
+    let visible_rooms = || {
+        room_list.sliding_sync().on_list(VISIBLE_ROOMS, |list| {
+            ready(list.room_list::<RoomListEntry>().into_iter().collect::<imbl::Vector<_>>())
+        })
+    };
+
+    // The native server doesn't send the operations of the lists.
+    sync_then_assert_request_and_fake_response! {
+        [server, room_list, sync]
+        states = Init => SettingUp,
+        assert request >= {
+            "lists": {
+                ALL_ROOMS: {
+                    "ranges": [[0, 19]],
+                },
+            },
+        },
+        respond with = {
+            "pos": "0",
+            "lists": {
+                ALL_ROOMS: {
+                    "count": 2,
+                },
+            },
+            "rooms": {
+                "!r0:bar.org": {
+                    "name": "Room #0",
+                    "initial": true,
+                    "bump_stamp": 1,
+                },
+                "!r1:bar.org": {
+                    "name": "Room #1",
+                    "initial": true,
+                    "bump_stamp": 2,
+                },
+            },
+        },
+    };
+
+    assert_eq!(all_rooms.entries().0, entries![F("!r1:bar.org"), F("!r0:bar.org")]);
+
+    // Both lists are active now, and the response contains a new invite, that
+    // belongs to both of them.
+    sync_then_assert_request_and_fake_response! {
+        [server, room_list, sync]
+        states = SettingUp => Running,
+        assert request >= {
+            "lists": {
+                ALL_ROOMS: {
+                    "ranges": [[0, 1]],
+                },
+                VISIBLE_ROOMS: {
+                    "ranges": [[0, 19]],
+                },
+            },
+        },
+        respond with = {
+            "pos": "1",
+            "lists": {
+                ALL_ROOMS: {
+                    "count": 3,
+                },
+                VISIBLE_ROOMS: {
+                    "count": 3,
+                },
+            },
+            "rooms": {
+                "!r2:bar.org": {
+                    "name": "Room #2",
+                    "initial": true,
+                    "bump_stamp": 3,
+                    "invite_state": [
+                        {
+                            "content": { "membership": "invite" },
+                            "sender": "@alice:bar.org",
+                            "state_key": "@example:localhost",
+                            "type": "m.room.member",
+                        },
+                    ],
+                },
+            },
+        },
+    };
+
+    assert_eq!(all_rooms.entries().0, entries![F("!r2:bar.org"), F("!r1:bar.org"), E]);
+    assert_eq!(
+        visible_rooms().await.unwrap(),
+        entries![F("!r2:bar.org"), F("!r1:bar.org"), F("!r0:bar.org")]
+    );
+
+    // The growing list advances to the new number of rooms.
+    sync_then_assert_request_and_fake_response! {
+        [server, room_list, sync]
+        states = Running => Running,
+        assert request >= {
+            "lists": {
+                ALL_ROOMS: {
+                    "ranges": [[0, 2]],
+                },
+                VISIBLE_ROOMS: {
+                    "ranges": [[0, 19]],
+                },
+            },
+        },
+        respond with = {
+            "pos": "2",
+            "lists": {
+                ALL_ROOMS: {
+                    "count": 3,
+                },
+                VISIBLE_ROOMS: {
+                    "count": 3,
+                },
+            },
+            "rooms": {},
+        },
+    };
+
+    assert_eq!(
+        all_rooms.entries().0,
+        entries![F("!r2:bar.org"), F("!r1:bar.org"), F("!r0:bar.org")]
+    );
+
+    // A room that is replaced leaves both lists.
+    sync_then_assert_request_and_fake_response! {
+        [server, room_list, sync]
+        states = Running => Running,
+        assert request >= {
+            "lists": {
+                ALL_ROOMS: {
+                    "ranges": [[0, 2]],
+                },
+            },
+        },
+        respond with = {
+            "pos": "3",
+            "lists": {
+                ALL_ROOMS: {
+                    "count": 2,
+                },
+                VISIBLE_ROOMS: {
+                    "count": 2,
+                },
+            },
+            "rooms": {
+                "!r1:bar.org": {
+                    "bump_stamp": 4,
+                    "required_state": [
+                        {
+                            "content": {
+                                "body": "This room has been replaced",
+                                "replacement_room": "!r3:bar.org",
+                            },
+                            "event_id": "$tombstone",
+                            "origin_server_ts": 42,
+                            "sender": "@alice:bar.org",
+                            "state_key": "",
+                            "type": "m.room.tombstone",
+                        },
+                    ],
+                },
+            },
+        },
+    };
+
+    assert_eq!(all_rooms.entries().0, entries![F("!r2:bar.org"), F("!r0:bar.org")]);
+    assert_eq!(visible_rooms().await.unwrap(), entries![F("!r2:bar.org"), F("!r0:bar.org")]);
+
+    Ok(())
+}
+
 #[async_test]
 async fn test_sync_resumes_from_previous_state() -> Result<(), Error> {
     let (_, server, room_list) = new_room_list_service().await?;
@@ -1240,10 +1430,10 @@ async fn test_loading_states() -> Result<(), Error> {
         let sync = room_list.sync();
         pin_mut!(sync);
 
-        // The loading state is loaded! Indeed, there is data loaded from the cache.
+        // The loading state is cached! Indeed, there is data loaded from the cache.
         assert_matches!(
             all_rooms_loading_state.get(),
-            RoomListLoadingState::Loaded { maximum_number_of_rooms: Some(12) }
+            RoomListLoadingState::Cached { maximum_number_of_rooms: Some(12) }
         );
         assert_pending!(all_rooms_loading_state);
 
@@ -1281,6 +1471,176 @@ async fn test_loading_states() -> Result<(), Error> {
     Ok(())
 }
 
+#[async_test]
+async fn test_preload_rooms_from_store() -> Result<(), Error> {
+    let (client, server) = logged_in_client_with_server().await;
+
+    // Load some rooms in the state store, with another sliding sync whose list
+    // isn't cached.
+    {
+        let sliding_sync = client
+            .sliding_sync("other")
+            .map_err(Error::SlidingSync)?
+            .add_list(
+                SlidingSyncList::builder("rooms")
+                    .sync_mode(SlidingSyncMode::new_selective().add_range(0..=9)),
+            )
+            .build()
+            .await
+            .map_err(Error::SlidingSync)?;
+
+        let sync = sliding_sync.sync();
+        pin_mut!(sync);
+
+        sliding_sync_then_assert_request_and_fake_response! {
+            [server, sync]
+            assert request >= {},
+            respond with = {
+                "pos": "0",
+                "lists": {
+                    "rooms": {
+                        "count": 3,
+                        "ops": [
+                            {
+                                "op": "SYNC",
+                                "range": [0, 2],
+                                "room_ids": ["!r0:bar.org", "!r1:bar.org", "!r2:bar.org"],
+                            },
+                        ],
+                    },
+                },
+                "rooms": {
+                    "!r0:bar.org": {
+                        "initial": true,
+                        "timeline": [timeline_event!("$x0:bar.org" at 1 sec)],
+                    },
+                    "!r1:bar.org": {
+                        "initial": true,
+                        "timeline": [timeline_event!("$x1:bar.org" at 3 sec)],
+                    },
+                    "!r2:bar.org": {
+                        "initial": true,
+                        "timeline": [],
+                    },
+                },
+            },
+        };
+    }
+
+    let room_list = RoomListService::new(client).await?;
+
+    // The rooms are loaded from the store, sorted by their latest event.
+    assert!(room_list.preload_rooms_from_store().await);
+
+    // It's not possible to preload the rooms twice.
+    assert!(room_list.preload_rooms_from_store().await.not());
+
+    let all_rooms = room_list.all_rooms().await?;
+    let mut all_rooms_loading_state = all_rooms.loading_state();
+
+    assert_matches!(
+        all_rooms_loading_state.get(),
+        RoomListLoadingState::Cached { maximum_number_of_rooms: Some(3) }
+    );
+
+    let (previous_entries, entries_stream) = all_rooms.entries();
+    pin_mut!(entries_stream);
+
+    assert_eq!(previous_entries, entries![F("!r1:bar.org"), F("!r0:bar.org"), F("!r2:bar.org")]);
+
+    // The timeline of a preloaded room can be created before any sync.
+    let room = room_list.room(room_id!("!r2:bar.org")).await?;
+    room.init_timeline_with_builder(room.default_room_timeline_builder().await?).await?;
+    assert!(room.is_timeline_initialized());
+
+    // It starts with the latest event of the room.
+    let room = room_list.room(room_id!("!r1:bar.org")).await?;
+    room.init_timeline_with_builder(room.default_room_timeline_builder().await?).await?;
+    let timeline = room.timeline().unwrap();
+    let event_ids = |items: &Vector<Arc<TimelineItem>>| {
+        items
+            .iter()
+            .filter_map(|item| item.as_event()?.event_id().map(ToString::to_string))
+            .collect::<Vec<_>>()
+    };
+    let (items, timeline_stream) = timeline.subscribe().await;
+    pin_mut!(timeline_stream);
+    assert_eq!(event_ids(&items), ["$x1:bar.org"]);
+
+    let sync = room_list.sync();
+    pin_mut!(sync);
+
+    sync_then_assert_request_and_fake_response! {
+        [server, room_list, sync]
+        states = Init => SettingUp,
+        assert request >= {
+            "lists": {
+                ALL_ROOMS: {
+                    "ranges": [[0, 19]],
+                },
+            },
+        },
+        respond with = {
+            "pos": "0",
+            "lists": {
+                ALL_ROOMS: {
+                    "count": 3,
+                    "ops": [
+                        {
+                            "op": "SYNC",
+                            "range": [0, 2],
+                            "room_ids": ["!r1:bar.org", "!r2:bar.org", "!r0:bar.org"],
+                        },
+                    ],
+                },
+            },
+            "rooms": {
+                "!r1:bar.org": {
+                    "initial": true,
+                    "timeline": [
+                        timeline_event!("$x1:bar.org" at 3 sec),
+                        timeline_event!("$x3:bar.org" at 5 sec),
+                    ],
+                    "limited": true,
+                    "prev_batch": "prev_r1",
+                },
+            },
+        },
+    };
+
+    // Wait on Tokio to run all the tasks. Necessary only when testing.
+    yield_now().await;
+
+    // The data is live now.
+    assert_next_matches!(
+        all_rooms_loading_state,
+        RoomListLoadingState::Loaded { maximum_number_of_rooms: Some(3) }
+    );
+
+    // The entries are updated in place.
+    assert_entries_batch! {
+        [entries_stream]
+        set[0] [ F("!r1:bar.org") ];
+        set[1] [ F("!r2:bar.org") ];
+        set[2] [ F("!r0:bar.org") ];
+        end;
+    };
+
+    // The list isn't reset afterwards.
+    assert_pending!(entries_stream);
+
+    // The latest event in the timeline has been replaced by the synced events.
+    timeout(Duration::from_secs(1), async {
+        while event_ids(&timeline.items().await) != ["$x1:bar.org", "$x3:bar.org"] {
+            timeline_stream.next().await;
+        }
+    })
+    .await
+    .expect("the timeline should contain the synced events");
+
+    Ok(())
+}
+
 #[async_test]
 async fn test_entries_stream() -> Result<(), Error> {
     let (_, server, room_list) = new_room_list_service().await?;
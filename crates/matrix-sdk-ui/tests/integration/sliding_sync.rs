@@ -47,8 +47,11 @@ pub(crate) struct PartialSlidingSyncRequest {
 
 impl Match for SlidingSyncMatcher {
     fn matches(&self, request: &Request) -> bool {
-        request.url.path() == "/_matrix/client/unstable/org.matrix.msc3575/sync"
-            && request.method == Method::POST
+        matches!(
+            request.url.path(),
+            "/_matrix/client/unstable/org.matrix.msc3575/sync"
+                | "/_matrix/client/unstable/org.matrix.simplified_msc3575/sync"
+        ) && request.method == Method::POST
     }
 }
 
@@ -20,7 +20,8 @@ use eyeball_im::VectorDiff;
 use futures_util::StreamExt;
 use matrix_sdk::{config::SyncSettings, test_utils::logged_in_client_with_server};
 use matrix_sdk_test::{
-    async_test, EventBuilder, JoinedRoomBuilder, SyncResponseBuilder, ALICE, BOB,
+    async_test, sync_timeline_event, EventBuilder, JoinedRoomBuilder, SyncResponseBuilder, ALICE,
+    BOB,
 };
 use matrix_sdk_ui::timeline::{RoomExt, TimelineDetails, TimelineItemContent};
 use ruma::{
@@ -36,13 +37,15 @@ use ruma::{
             RoomMessageEventContentWithoutRelation, TextMessageEventContent,
         },
     },
-    room_id, user_id,
+    room_id,
+    serde::Raw,
+    user_id,
 };
 use serde_json::json;
 use stream_assert::assert_next_matches;
 use tokio::{task::yield_now, time::sleep};
 use wiremock::{
-    matchers::{method, path_regex},
+    matchers::{method, path_regex, query_param, query_param_is_missing},
     Mock, ResponseTemplate,
 };
 
@@ -410,3 +413,126 @@ async fn test_send_edit_poll() {
 
     server.verify().await;
 }
+
+#[async_test]
+async fn test_edit_history() {
+    let room_id = room_id!("!a98sd12bjh:example.org");
+    let (client, server) = logged_in_client_with_server().await;
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+    let mut sync_builder = SyncResponseBuilder::new();
+    sync_builder.add_joined_room(JoinedRoomBuilder::new(room_id));
+
+    mock_sync(&server, sync_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    let room = client.get_room(room_id).unwrap();
+    let timeline = room.timeline().await.unwrap();
+    let (_, mut timeline_stream) = timeline.subscribe().await;
+
+    let original_event_id = event_id!("$original");
+    let edit = |event_id: &str, sender: &str, body: &str, ts: u64| {
+        json!({
+            "content": {
+                "body": format!("* {body}"),
+                "msgtype": "m.text",
+                "m.new_content": { "body": body, "msgtype": "m.text" },
+                "m.relates_to": { "rel_type": "m.replace", "event_id": original_event_id },
+            },
+            "event_id": event_id,
+            "origin_server_ts": ts,
+            "sender": sender,
+            "type": "m.room.message",
+            "room_id": room_id,
+        })
+    };
+
+    sync_builder.add_joined_room(JoinedRoomBuilder::new(room_id).add_timeline_event(
+        sync_timeline_event!({
+            "content": { "body": "original", "msgtype": "m.text" },
+            "event_id": original_event_id,
+            "origin_server_ts": 1,
+            "sender": *ALICE,
+            "type": "m.room.message",
+        }),
+    ));
+    mock_sync(&server, sync_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    assert_let!(Some(VectorDiff::PushBack { .. }) = timeline_stream.next().await);
+    assert_let!(Some(VectorDiff::PushFront { .. }) = timeline_stream.next().await);
+
+    // The latest edit is received through sync.
+    let latest_edit = Raw::new(&edit("$edit3", ALICE.as_str(), "third", 4)).unwrap().cast();
+    sync_builder.add_joined_room(JoinedRoomBuilder::new(room_id).add_timeline_event(latest_edit));
+    mock_sync(&server, sync_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    assert_let!(Some(VectorDiff::Set { index: 1, value: item }) = timeline_stream.next().await);
+    let item = item.as_event().unwrap();
+    assert_eq!(item.content().as_message().unwrap().body(), "third");
+
+    // The other ones are fetched from the server.
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/v1/rooms/.*/relations/.*/m.replace$"))
+        .and(query_param_is_missing("from"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "chunk": [
+                edit("$edit3", ALICE.as_str(), "third", 4),
+                {
+                    "content": {},
+                    "event_id": "$edit2",
+                    "origin_server_ts": 3,
+                    "sender": *ALICE,
+                    "type": "m.room.message",
+                    "room_id": room_id,
+                    "unsigned": {
+                        "redacted_because": {
+                            "content": {},
+                            "redacts": "$edit2",
+                            "event_id": "$redaction",
+                            "sender": *ALICE,
+                            "origin_server_ts": 5,
+                            "type": "m.room.redaction",
+                        },
+                    },
+                },
+            ],
+            "next_batch": "next_token",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/v1/rooms/.*/relations/.*/m.replace$"))
+        .and(query_param("from", "next_token"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "chunk": [
+                edit("$edit1", ALICE.as_str(), "first", 2),
+                // Edits from other users are invalid.
+                edit("$bob_edit", BOB.as_str(), "hijacked", 6),
+            ],
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let history = item.edit_history(&room).await.unwrap();
+
+    let event_ids: Vec<_> = history.iter().map(|version| version.event_id().as_str()).collect();
+    assert_eq!(event_ids, ["$original", "$edit1", "$edit2", "$edit3"]);
+
+    let message = history[0].content().as_message().unwrap();
+    assert_eq!(message.body(), "original");
+    assert!(!message.is_edited());
+    assert_eq!(history[1].content().as_message().unwrap().body(), "first");
+    assert_matches!(history[2].content(), TimelineItemContent::RedactedMessage);
+    assert_eq!(history[3].content().as_message().unwrap().body(), "third");
+    assert!(history.iter().all(|version| version.encryption_info().is_none()));
+
+    server.verify().await;
+}
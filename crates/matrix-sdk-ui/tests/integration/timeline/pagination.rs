@@ -14,21 +14,26 @@
 
 use std::{sync::Arc, time::Duration};
 
-use assert_matches2::assert_let;
+use assert_matches2::{assert_let, assert_matches};
 use eyeball_im::VectorDiff;
 use futures_util::{
     future::{join, join3},
     FutureExt, StreamExt as _,
 };
-use matrix_sdk::{config::SyncSettings, test_utils::logged_in_client_with_server};
+use matrix_sdk::{
+    config::SyncSettings,
+    test_utils::{events::EventFactory, logged_in_client_with_server},
+};
 use matrix_sdk_test::{
     async_test, EventBuilder, JoinedRoomBuilder, StateTestEvent, SyncResponseBuilder, ALICE, BOB,
 };
 use matrix_sdk_ui::timeline::{
-    AnyOtherFullStateEventContent, LiveBackPaginationStatus, RoomExt, TimelineItemContent,
+    AnyOtherFullStateEventContent, Error, LiveBackPaginationStatus, RoomExt, TimelineItemContent,
+    VirtualTimelineItem,
 };
 use once_cell::sync::Lazy;
 use ruma::{
+    event_id,
     events::{
         room::message::{MessageType, RoomMessageEventContent},
         FullStateEventContent,
@@ -380,19 +385,6 @@ async fn test_timeline_reset_while_paginating() {
     client.sync_once(sync_settings.clone()).await.unwrap();
     server.reset().await;
 
-    // Next sync response will response with pagination_2 token and limited
-    // response, resetting the timeline
-    sync_builder.add_joined_room(
-        JoinedRoomBuilder::new(room_id)
-            .add_timeline_event(event_builder.make_sync_message_event(
-                &BOB,
-                RoomMessageEventContent::text_plain("new live event."),
-            ))
-            .set_timeline_limited()
-            .set_timeline_prev_batch("pagination_2".to_owned()),
-    );
-    mock_sync(&server, sync_builder.build_json_sync_response(), None).await;
-
     // pagination with first token
     Mock::given(method("GET"))
         .and(path_regex(r"^/_matrix/client/r0/rooms/.*/messages$"))
@@ -405,7 +397,7 @@ async fn test_timeline_reset_while_paginating() {
                     "start": "pagination_1",
                     "end": "some_other_token",
                 }))
-                // Make sure the concurrent sync request returns first
+                // Make sure the concurrent reset happens first
                 .set_delay(Duration::from_millis(200)),
         )
         .expect(1)
@@ -458,12 +450,26 @@ async fn test_timeline_reset_while_paginating() {
         assert_eq!(status, LiveBackPaginationStatus::Idle { hit_start_of_timeline: true });
     };
 
-    let sync = async {
-        client.sync_once(sync_settings.clone()).await.unwrap();
+    // Reset the room events while the first pagination is running, with the
+    // pagination_2 token. (A limited sync doesn't reset the timeline, it adds a
+    // gap instead.)
+    let reset = async {
+        sleep(Duration::from_millis(100)).await;
+
+        let f = EventFactory::new().room(room_id).sender(*BOB);
+        client
+            .event_cache()
+            .add_initial_events(
+                room_id,
+                vec![f.text_msg("new live event.").into_sync()],
+                Some("pagination_2".to_owned()),
+            )
+            .await
+            .unwrap();
     };
 
     let (hit_start, _, _) =
-        timeout(Duration::from_secs(5), join3(paginate, observe_paginating, sync)).await.unwrap();
+        timeout(Duration::from_secs(5), join3(paginate, observe_paginating, reset)).await.unwrap();
 
     // Timeline start reached because second pagination response contains no end
     // field.
@@ -806,3 +812,176 @@ async fn test_back_pagination_aborted() {
     // And there should be no other pending pagination status updates.
     assert!(back_pagination_status.next().now_or_never().is_none());
 }
+
+#[async_test]
+async fn test_fill_gap() {
+    let room_id = room_id!("!a98sd12bjh:example.org");
+    let (client, server) = logged_in_client_with_server().await;
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+    let f = EventFactory::new().room(room_id).sender(*ALICE);
+
+    let mut sync_builder = SyncResponseBuilder::new();
+    sync_builder.add_joined_room(JoinedRoomBuilder::new(room_id));
+
+    mock_sync(&server, sync_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    let room = client.get_room(room_id).unwrap();
+    let timeline = room.timeline().await.unwrap();
+    let (_, mut timeline_stream) = timeline.subscribe().await;
+
+    sync_builder.add_joined_room(
+        JoinedRoomBuilder::new(room_id)
+            .add_timeline_event(f.text_msg("before").event_id(event_id!("$before")).into_raw_sync())
+            .set_timeline_prev_batch("start".to_owned()),
+    );
+
+    mock_sync(&server, sync_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    timeout(Duration::from_secs(2), async {
+        while timeline.items().await.len() < 2 {
+            timeline_stream.next().await;
+        }
+    })
+    .await
+    .expect("the first event should be added");
+
+    // A limited sync leaves a gap between the known events and the new ones.
+    sync_builder.add_joined_room(
+        JoinedRoomBuilder::new(room_id)
+            .add_timeline_event(f.text_msg("after").event_id(event_id!("$after")).into_raw_sync())
+            .set_timeline_prev_batch("gap".to_owned())
+            .set_timeline_limited(),
+    );
+
+    mock_sync(&server, sync_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    timeout(Duration::from_secs(2), async {
+        while timeline.items().await.len() < 4 {
+            timeline_stream.next().await;
+        }
+    })
+    .await
+    .expect("the gap and the new event should be added");
+
+    let items = timeline.items().await;
+    assert!(items[0].is_day_divider());
+    assert_eq!(items[1].as_event().unwrap().content().as_message().unwrap().body(), "before");
+    assert_let!(Some(VirtualTimelineItem::Gap { prev_token }) = items[2].as_virtual());
+    assert_eq!(prev_token, "gap");
+    assert_eq!(items[3].as_event().unwrap().content().as_message().unwrap().body(), "after");
+
+    // Filling the gap stops at the first event that is already known.
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/messages$"))
+        .and(header("authorization", "Bearer 1234"))
+        .and(query_param("from", "gap"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "chunk": [
+                f.text_msg("missed").event_id(event_id!("$missed")).into_raw_timeline(),
+                f.text_msg("before").event_id(event_id!("$before")).into_raw_timeline(),
+            ],
+            "start": "gap",
+            "end": "gap2",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    timeline.fill_gap(items[2].unique_id()).await.unwrap();
+
+    let items = timeline.items().await;
+    assert_eq!(items.len(), 4);
+    assert!(items[0].is_day_divider());
+    assert_eq!(items[1].as_event().unwrap().content().as_message().unwrap().body(), "before");
+    assert_eq!(items[2].as_event().unwrap().content().as_message().unwrap().body(), "missed");
+    assert_eq!(items[3].as_event().unwrap().content().as_message().unwrap().body(), "after");
+
+    // The gap doesn't exist anymore.
+    assert_matches!(timeline.fill_gap("nope").await, Err(Error::GapNotInTimeline));
+}
+
+#[async_test]
+async fn test_fill_gap_stops_without_progress() {
+    let room_id = room_id!("!a98sd12bjh:example.org");
+    let (client, server) = logged_in_client_with_server().await;
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+    let f = EventFactory::new().room(room_id).sender(*ALICE);
+
+    let mut sync_builder = SyncResponseBuilder::new();
+    sync_builder.add_joined_room(JoinedRoomBuilder::new(room_id));
+
+    mock_sync(&server, sync_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    let room = client.get_room(room_id).unwrap();
+    let timeline = room.timeline().await.unwrap();
+    let (_, mut timeline_stream) = timeline.subscribe().await;
+
+    sync_builder.add_joined_room(
+        JoinedRoomBuilder::new(room_id)
+            .add_timeline_event(f.text_msg("before").event_id(event_id!("$before")).into_raw_sync())
+            .set_timeline_prev_batch("start".to_owned()),
+    );
+
+    mock_sync(&server, sync_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    // A limited sync leaves a gap between the known events and the new ones.
+    sync_builder.add_joined_room(
+        JoinedRoomBuilder::new(room_id)
+            .add_timeline_event(f.text_msg("after").event_id(event_id!("$after")).into_raw_sync())
+            .set_timeline_prev_batch("gap".to_owned())
+            .set_timeline_limited(),
+    );
+
+    mock_sync(&server, sync_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    timeout(Duration::from_secs(2), async {
+        while timeline.items().await.len() < 4 {
+            timeline_stream.next().await;
+        }
+    })
+    .await
+    .expect("the gap and the new event should be added");
+
+    let items = timeline.items().await;
+    assert_let!(Some(VirtualTimelineItem::Gap { .. }) = items[2].as_virtual());
+
+    // The server doesn't return any event, and the same token.
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/messages$"))
+        .and(header("authorization", "Bearer 1234"))
+        .and(query_param("from", "gap"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "chunk": [],
+            "start": "gap",
+            "end": "gap",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    // Filling the gap returns instead of spinning forever.
+    timeout(Duration::from_secs(2), timeline.fill_gap(items[2].unique_id()))
+        .await
+        .expect("filling the gap should stop")
+        .unwrap();
+
+    // The gap is still there, and can be filled later.
+    let items = timeline.items().await;
+    assert_eq!(items.len(), 4);
+    assert_let!(Some(VirtualTimelineItem::Gap { prev_token }) = items[2].as_virtual());
+    assert_eq!(prev_token, "gap");
+}
@@ -28,6 +28,7 @@ use ruma::{
         },
         config::{get_global_account_data, set_global_account_data},
         error::ErrorKind,
+        presence::set_presence,
         profile::{
             get_avatar_url, get_display_name, get_profile, set_avatar_url, set_display_name,
         },
@@ -41,6 +42,7 @@ use ruma::{
         AnyGlobalAccountDataEventContent, GlobalAccountDataEventContent,
         GlobalAccountDataEventType, StaticEventContent,
     },
+    presence::PresenceState,
     push::Ruleset,
     serde::Raw,
     thirdparty::Medium,
@@ -294,6 +296,44 @@ impl Account {
         Ok(self.client.send(request, Some(RequestConfig::short_retry().force_auth())).await?)
     }
 
+    /// Set the presence of the account.
+    ///
+    /// Note that the presence is also set by the homeserver during a sync,
+    /// with the state given to [`SyncSettings::set_presence()`].
+    ///
+    /// # Arguments
+    ///
+    /// * `state` - The new presence state.
+    ///
+    /// * `status_msg` - The status message to attach to the presence, if any.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use matrix_sdk::Client;
+    /// # use url::Url;
+    /// # async {
+    /// # let homeserver = Url::parse("http://example.com")?;
+    /// use ruma::presence::PresenceState;
+    ///
+    /// let user = "example";
+    /// let client = Client::new(homeserver).await?;
+    /// client.matrix_auth().login_username(user, "password").send().await?;
+    ///
+    /// client.account().set_presence(PresenceState::Unavailable, Some("Lunch")).await?;
+    /// # anyhow::Ok(()) };
+    /// ```
+    ///
+    /// [`SyncSettings::set_presence()`]: crate::config::SyncSettings::set_presence
+    pub async fn set_presence(&self, state: PresenceState, status_msg: Option<&str>) -> Result<()> {
+        let user_id = self.client.user_id().ok_or(Error::AuthenticationRequired)?;
+        let request = assign!(set_presence::v3::Request::new(user_id.to_owned(), state), {
+            status_msg: status_msg.map(ToOwned::to_owned),
+        });
+        self.client.send(request, None).await?;
+        Ok(())
+    }
+
     /// Change the password of the account.
     ///
     /// # Arguments
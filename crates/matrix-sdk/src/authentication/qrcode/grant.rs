@@ -0,0 +1,454 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    future::IntoFuture,
+    sync::{Arc, Mutex as StdMutex},
+};
+
+use eyeball::SharedObservable;
+use futures_core::Stream;
+use matrix_sdk_base::{boxed_into_future, crypto::types::qr_login::QrCodeData};
+use ruma::OwnedDeviceId;
+use tokio::sync::oneshot;
+use tracing::trace;
+use url::Url;
+
+use super::{
+    messages::{LoginFailureReason, LoginProtocolType, QrAuthMessage},
+    secure_channel::{EstablishedSecureChannel, SecureChannel},
+    QRCodeGrantLoginError, SecureChannelError,
+};
+#[cfg(doc)]
+use crate::oidc::Oidc;
+use crate::Client;
+
+async fn send_failure(
+    channel: &mut EstablishedSecureChannel,
+    reason: LoginFailureReason,
+) -> Result<(), SecureChannelError> {
+    channel.send_json(QrAuthMessage::LoginFailure { reason, homeserver: None }).await
+}
+
+/// A handle which is used to provide the [`CheckCode`] displayed by the new
+/// device to the QR code grant login flow.
+///
+/// [`CheckCode`]: vodozemac::ecies::CheckCode
+#[derive(Clone, Debug)]
+pub struct CheckCodeSender {
+    inner: Arc<StdMutex<Option<oneshot::Sender<u8>>>>,
+}
+
+impl CheckCodeSender {
+    fn new(sender: oneshot::Sender<u8>) -> Self {
+        Self { inner: Arc::new(StdMutex::new(Some(sender))) }
+    }
+
+    /// Send the check code, as entered by the user, to the login flow.
+    ///
+    /// Returns `false` if the check code was already sent, or if the login
+    /// flow isn't running anymore.
+    pub fn send(&self, check_code: u8) -> bool {
+        match self.inner.lock().unwrap().take() {
+            Some(sender) => sender.send(check_code).is_ok(),
+            None => false,
+        }
+    }
+}
+
+/// Type telling us about the progress of granting a login to a new device
+/// using a QR code.
+#[derive(Clone, Debug, Default)]
+pub enum GrantLoginProgress {
+    /// We're just starting up, this is the default and initial state.
+    #[default]
+    Starting,
+    /// The rendezvous session has been created, the [`QrCodeData`] needs to be
+    /// displayed as a QR code so the new device can scan it.
+    WaitingForScan {
+        /// The data which should be encoded in the QR code.
+        qr_code_data: QrCodeData,
+    },
+    /// The new device has scanned the QR code and is displaying a check code.
+    /// The user needs to enter the check code on this device to confirm that
+    /// the secure channel is indeed secure.
+    EstablishingSecureChannel {
+        /// The handle used to provide the check code the user entered.
+        check_code_sender: CheckCodeSender,
+    },
+    /// The new device has asked the OIDC provider for a device authorization
+    /// grant. The user needs to open the given URL and approve the login.
+    WaitingForAuth {
+        /// The URL the user should open to approve the login of the new
+        /// device.
+        verification_uri: Url,
+    },
+    /// The new device has logged in, we're sending it the end-to-end
+    /// encryption related secrets.
+    SyncingSecrets,
+    /// The new device has been logged in and received all the secrets, this is
+    /// the final state.
+    Done,
+}
+
+/// Named future for the [`Oidc::grant_login_with_qr_code()`] method.
+#[derive(Debug)]
+pub struct GrantLoginWithQrCode<'a> {
+    client: &'a Client,
+    state: SharedObservable<GrantLoginProgress>,
+}
+
+impl<'a> GrantLoginWithQrCode<'a> {
+    pub(crate) fn new(client: &'a Client) -> GrantLoginWithQrCode<'a> {
+        GrantLoginWithQrCode { client, state: Default::default() }
+    }
+
+    /// Subscribe to the progress of the QR code login grant.
+    ///
+    /// It's necessary to subscribe to this to display the QR code and to input
+    /// the check code the new device displays.
+    pub fn subscribe_to_progress(&self) -> impl Stream<Item = GrantLoginProgress> {
+        self.state.subscribe()
+    }
+
+    /// Check if the new device exists in our list of devices on the
+    /// homeserver.
+    async fn device_exists(
+        &self,
+        device_id: &OwnedDeviceId,
+    ) -> Result<bool, QRCodeGrantLoginError> {
+        let response = self.client.devices().await.map_err(QRCodeGrantLoginError::DeviceList)?;
+        Ok(response.devices.iter().any(|d| &d.device_id == device_id))
+    }
+
+    async fn expect_login_success(
+        &self,
+        channel: &mut EstablishedSecureChannel,
+    ) -> Result<(), QRCodeGrantLoginError> {
+        match channel.receive_json().await? {
+            QrAuthMessage::LoginSuccess => Ok(()),
+            QrAuthMessage::LoginDeclined => Err(QRCodeGrantLoginError::LoginDeclined),
+            QrAuthMessage::LoginFailure { reason, homeserver } => {
+                Err(QRCodeGrantLoginError::LoginFailure { reason, homeserver })
+            }
+            message => {
+                send_failure(channel, LoginFailureReason::UnexpectedMessageReceived).await?;
+
+                Err(QRCodeGrantLoginError::UnexpectedMessage {
+                    expected: "m.login.success",
+                    received: message,
+                })
+            }
+        }
+    }
+}
+
+impl<'a> IntoFuture for GrantLoginWithQrCode<'a> {
+    type Output = Result<(), QRCodeGrantLoginError>;
+    boxed_into_future!(extra_bounds: 'a);
+
+    fn into_future(self) -> Self::IntoFuture {
+        Box::pin(async move {
+            // Let's first check that we can share our secrets with the new device, there's
+            // no point in logging in a device which won't be verified.
+            let bundle = {
+                let olm_machine = self.client.olm_machine().await;
+                let olm_machine =
+                    olm_machine.as_ref().ok_or(QRCodeGrantLoginError::MissingOlmMachine)?;
+
+                olm_machine.store().export_secrets_bundle().await?
+            };
+
+            // Create the rendezvous session on our homeserver, and let the new device know
+            // about it using the QR code.
+            let homeserver_url = self.client.homeserver();
+            let http_client = self.client.inner.http_client.clone();
+            let channel = SecureChannel::new(http_client, &homeserver_url).await?;
+
+            trace!("Created the rendezvous session, waiting for the QR code to be scanned.");
+            self.state.set(GrantLoginProgress::WaitingForScan {
+                qr_code_data: channel.qr_code_data().clone(),
+            });
+
+            let channel = channel.connect().await?;
+
+            // The new device is now displaying the check code, the user needs to enter it
+            // here so we can be sure that we're talking to the device that scanned our QR
+            // code.
+            trace!("The QR code has been scanned, waiting for the check code.");
+            let (sender, receiver) = oneshot::channel();
+            self.state.set(GrantLoginProgress::EstablishingSecureChannel {
+                check_code_sender: CheckCodeSender::new(sender),
+            });
+
+            let check_code =
+                receiver.await.map_err(|_| QRCodeGrantLoginError::CheckCodeCancelled)?;
+            let mut channel = channel.confirm(check_code)?;
+
+            trace!("Established the secure channel.");
+
+            // Tell the new device which protocols we support and where to log in.
+            channel
+                .send_json(QrAuthMessage::LoginProtocols {
+                    protocols: vec![LoginProtocolType::DeviceAuthorizationGrant],
+                    homeserver: homeserver_url,
+                })
+                .await?;
+
+            let (device_authorization_grant, device_id) = match channel.receive_json().await? {
+                QrAuthMessage::LoginProtocol {
+                    device_authorization_grant,
+                    protocol,
+                    device_id,
+                } => {
+                    if protocol != LoginProtocolType::DeviceAuthorizationGrant {
+                        send_failure(&mut channel, LoginFailureReason::UnsupportedProtocol).await?;
+
+                        return Err(QRCodeGrantLoginError::UnsupportedProtocol(protocol));
+                    }
+
+                    (device_authorization_grant, OwnedDeviceId::from(device_id.to_base64()))
+                }
+                QrAuthMessage::LoginFailure { reason, homeserver } => {
+                    return Err(QRCodeGrantLoginError::LoginFailure { reason, homeserver });
+                }
+                message => {
+                    send_failure(&mut channel, LoginFailureReason::UnexpectedMessageReceived)
+                        .await?;
+
+                    return Err(QRCodeGrantLoginError::UnexpectedMessage {
+                        expected: "m.login.protocol",
+                        received: message,
+                    });
+                }
+            };
+
+            // The device ID the new device picked must not exist yet, otherwise we would
+            // let someone take over an existing device.
+            if self.device_exists(&device_id).await? {
+                send_failure(&mut channel, LoginFailureReason::DeviceAlreadyExists).await?;
+
+                return Err(QRCodeGrantLoginError::DeviceAlreadyExists(device_id));
+            }
+
+            channel.send_json(QrAuthMessage::LoginProtocolAccepted).await?;
+
+            // The user now needs to approve the login with the OIDC provider, prefer the
+            // URL which has the user code pre-filled.
+            let verification_uri = device_authorization_grant
+                .verification_uri_complete
+                .map(|uri| Url::parse(uri.secret()))
+                .transpose()
+                .ok()
+                .flatten()
+                .unwrap_or_else(|| device_authorization_grant.verification_uri.url().clone());
+
+            trace!("Waiting for the user to approve the login of the new device.");
+            self.state.set(GrantLoginProgress::WaitingForAuth { verification_uri });
+
+            self.expect_login_success(&mut channel).await?;
+
+            // The new device claims that it received an access token, let's double check
+            // that the homeserver knows about it before we hand over our secrets.
+            if !self.device_exists(&device_id).await? {
+                send_failure(&mut channel, LoginFailureReason::DeviceNotFound).await?;
+
+                return Err(QRCodeGrantLoginError::DeviceNotFound(device_id));
+            }
+
+            trace!("The new device has logged in, sending the secrets bundle.");
+            self.state.set(GrantLoginProgress::SyncingSecrets);
+
+            channel.send_json(QrAuthMessage::LoginSecrets(bundle)).await?;
+
+            trace!("Successfully granted the login to the new device.");
+            self.state.set(GrantLoginProgress::Done);
+
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use assert_matches2::assert_let;
+    use futures_util::StreamExt;
+    use matrix_sdk_base::crypto::{
+        store::SecretsBundleExportError,
+        types::{qr_login::QrCodeMode, SecretsBundle},
+    };
+    use matrix_sdk_test::async_test;
+    use serde_json::json;
+    use vodozemac::ecies::Ecies;
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::*;
+    use crate::{
+        authentication::qrcode::{
+            messages::AuthorizationGrant, secure_channel::test::MockedRendezvousServer,
+        },
+        test_utils::{logged_in_client, logged_in_client_with_server},
+    };
+
+    /// This is most of the code that is required to be the other side, the new
+    /// device, of the QR login dance.
+    async fn login_with_qr_code(
+        server: &MockServer,
+        qr_code_data: oneshot::Receiver<QrCodeData>,
+        check_code_sender: oneshot::Sender<u8>,
+    ) -> SecretsBundle {
+        let qr_code_data = qr_code_data.await.expect("We should receive the QR code data");
+
+        let mut bob = EstablishedSecureChannel::from_qr_code(
+            reqwest::Client::new(),
+            &qr_code_data,
+            QrCodeMode::Login,
+        )
+        .await
+        .expect("Bob should be able to establish the secure channel");
+
+        check_code_sender.send(bob.check_code().to_digit()).unwrap();
+
+        let message = bob.receive_json().await.unwrap();
+        assert_let!(QrAuthMessage::LoginProtocols { protocols, homeserver } = message);
+        assert_eq!(protocols, vec![LoginProtocolType::DeviceAuthorizationGrant]);
+        assert_eq!(homeserver.as_str(), format!("{}/", server.uri()));
+
+        let device_authorization_grant: AuthorizationGrant = serde_json::from_value(json!({
+            "verification_uri": format!("{}/link", server.uri()),
+            "verification_uri_complete": format!("{}/link?code=N32YVC", server.uri()),
+        }))
+        .unwrap();
+        let device_id = Ecies::new().public_key();
+
+        bob.send_json(QrAuthMessage::authorization_grant_login_protocol(
+            device_authorization_grant,
+            device_id,
+        ))
+        .await
+        .unwrap();
+
+        let message = bob.receive_json().await.unwrap();
+        assert_let!(QrAuthMessage::LoginProtocolAccepted = message);
+
+        // The homeserver now knows about the new device.
+        Mock::given(method("GET"))
+            .and(path("/_matrix/client/r0/devices"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "devices": [{ "device_id": device_id.to_base64() }],
+            })))
+            .mount(server)
+            .await;
+
+        bob.send_json(QrAuthMessage::LoginSuccess).await.unwrap();
+
+        let message = bob.receive_json().await.unwrap();
+        assert_let!(QrAuthMessage::LoginSecrets(bundle) = message);
+
+        bundle
+    }
+
+    #[test]
+    fn test_check_code_sender_sends_once() {
+        let (sender, mut receiver) = oneshot::channel();
+        let check_code_sender = CheckCodeSender::new(sender);
+
+        assert!(check_code_sender.clone().send(42));
+        assert!(!check_code_sender.send(7), "The check code can only be sent once");
+        assert_eq!(receiver.try_recv().unwrap(), 42);
+    }
+
+    #[async_test]
+    async fn test_grant_login_requires_cross_signing_keys() {
+        let client = logged_in_client(None).await;
+
+        let grant = client.oidc().grant_login_with_qr_code();
+        let result = grant.await;
+
+        assert_let!(
+            Err(QRCodeGrantLoginError::SecretsBundleExport(
+                SecretsBundleExportError::MissingCrossSigningKeys
+            )) = result
+        );
+    }
+
+    #[async_test]
+    async fn test_grant_login_with_qr_code() {
+        let (client, server) = logged_in_client_with_server().await;
+        let _rendezvous_server = MockedRendezvousServer::new(&server, "abcdEFG12345").await;
+
+        // Our own device has the cross-signing keys which are shared with the new
+        // device.
+        let expected_master_key = {
+            let olm_machine = client.olm_machine().await;
+            let olm_machine = olm_machine.as_ref().unwrap();
+            olm_machine.bootstrap_cross_signing(false).await.unwrap();
+            olm_machine
+                .store()
+                .export_secrets_bundle()
+                .await
+                .unwrap()
+                .cross_signing
+                .master_key
+                .clone()
+        };
+
+        // The new device doesn't exist yet when it asks to be logged in.
+        Mock::given(method("GET"))
+            .and(path("/_matrix/client/r0/devices"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "devices": [] })))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let grant = client.oidc().grant_login_with_qr_code();
+        let mut progress = grant.subscribe_to_progress();
+
+        let (qr_code_sender, qr_code_receiver) = oneshot::channel();
+        let (check_code_sender, check_code_receiver) = oneshot::channel();
+
+        let progress_task = tokio::spawn(async move {
+            let mut qr_code_sender = Some(qr_code_sender);
+            let mut check_code_receiver = Some(check_code_receiver);
+
+            while let Some(state) = progress.next().await {
+                match state {
+                    GrantLoginProgress::WaitingForScan { qr_code_data } => {
+                        qr_code_sender.take().unwrap().send(qr_code_data).unwrap();
+                    }
+                    GrantLoginProgress::EstablishingSecureChannel { check_code_sender } => {
+                        let check_code = check_code_receiver.take().unwrap().await.unwrap();
+                        assert!(check_code_sender.send(check_code));
+                    }
+                    GrantLoginProgress::Done => break,
+                    _ => {}
+                }
+            }
+        });
+
+        let (result, bundle) = tokio::join!(
+            grant.into_future(),
+            login_with_qr_code(&server, qr_code_receiver, check_code_sender)
+        );
+        result.expect("The login should have been granted");
+
+        assert_eq!(bundle.cross_signing.master_key, expected_master_key);
+
+        progress_task.await.unwrap();
+    }
+}
@@ -18,16 +18,18 @@
 //! auththentication mechanism, native Matrix authentication does not support
 //! it.
 //!
-//! This currently only implements the case where the new device is scanning the
-//! QR code. To log in using a QR code, please take a look at the
-//! [`Oidc::login_with_qr_code()`] method
+//! Both sides of the login are implemented. To log in a new device by scanning
+//! a QR code, please take a look at the [`Oidc::login_with_qr_code()`] method.
+//! To display a QR code on an existing device and grant the login to a new
+//! device, take a look at the [`Oidc::grant_login_with_qr_code()`] method.
 
 use as_variant::as_variant;
-use matrix_sdk_base::crypto::SecretImportError;
+use matrix_sdk_base::crypto::{store::SecretsBundleExportError, SecretImportError};
 pub use openidconnect::{
     core::CoreErrorResponseType, ConfigurationError, DeviceCodeErrorResponseType, DiscoveryError,
     HttpClientError, RequestTokenError, StandardErrorResponse,
 };
+use ruma::OwnedDeviceId;
 use thiserror::Error;
 use url::Url;
 pub use vodozemac::ecies::{Error as EciesError, MessageDecodeError};
@@ -36,6 +38,7 @@ pub use vodozemac::ecies::{Error as EciesError, MessageDecodeError};
 use crate::oidc::Oidc;
 use crate::{oidc::CrossProcessRefreshLockError, HttpError};
 
+mod grant;
 mod login;
 mod messages;
 mod oidc_client;
@@ -47,6 +50,7 @@ pub use matrix_sdk_base::crypto::types::qr_login::{
 };
 
 pub use self::{
+    grant::{CheckCodeSender, GrantLoginProgress, GrantLoginWithQrCode},
     login::{LoginProgress, LoginWithQrCode},
     messages::{LoginFailureReason, LoginProtocolType, QrAuthMessage},
 };
@@ -106,6 +110,68 @@ pub enum QRCodeLoginError {
     SecretImport(#[from] SecretImportError),
 }
 
+/// The error type for failures while trying to grant a login to a new device
+/// using a QR code, from an existing device.
+#[derive(Debug, Error)]
+pub enum QRCodeGrantLoginError {
+    /// The client isn't logged in, or end-to-end encryption hasn't been set up
+    /// yet.
+    #[error("The client isn't logged in or end-to-end encryption hasn't been set up yet")]
+    MissingOlmMachine,
+
+    /// We don't have the secrets that need to be shared with the new device.
+    #[error(transparent)]
+    SecretsBundleExport(#[from] SecretsBundleExportError),
+
+    /// An error happened while exchanging messages with the other device.
+    #[error(transparent)]
+    SecureChannel(#[from] SecureChannelError),
+
+    /// The check code was never provided, the [`CheckCodeSender`] has been
+    /// dropped.
+    #[error("The check code was never provided")]
+    CheckCodeCancelled,
+
+    /// The other device has signaled to us that the login has failed.
+    #[error("The login failed, reason: {reason}")]
+    LoginFailure {
+        /// The reason, as signaled by the other device, for the login failure.
+        reason: LoginFailureReason,
+        /// The homeserver that we attempted to log in to.
+        homeserver: Option<Url>,
+    },
+
+    /// The login has been declined by the user in the OIDC provider.
+    #[error("The login has been declined")]
+    LoginDeclined,
+
+    /// An unexpected message was received from the other device.
+    #[error("We have received an unexpected message, expected: {expected}, got {received:?}")]
+    UnexpectedMessage {
+        /// The message we expected.
+        expected: &'static str,
+        /// The message we received instead.
+        received: QrAuthMessage,
+    },
+
+    /// The other device picked a login protocol we don't support.
+    #[error("The other device picked an unsupported login protocol: {0}")]
+    UnsupportedProtocol(LoginProtocolType),
+
+    /// The device ID the other device wanted to use already exists.
+    #[error("The device {0} already exists")]
+    DeviceAlreadyExists(OwnedDeviceId),
+
+    /// The other device claims to have logged in, but the homeserver doesn't
+    /// know about it.
+    #[error("The device {0} was not found on the homeserver")]
+    DeviceNotFound(OwnedDeviceId),
+
+    /// The list of our devices couldn't be fetched from the homeserver.
+    #[error(transparent)]
+    DeviceList(HttpError),
+}
+
 /// Error type describing failures in the interaction between the device
 /// attempting to log in and the OIDC provider.
 #[derive(Debug, Error)]
@@ -12,16 +12,14 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-#[cfg(test)]
-use matrix_sdk_base::crypto::types::qr_login::QrCodeModeData;
-use matrix_sdk_base::crypto::types::qr_login::{QrCodeData, QrCodeMode};
+use matrix_sdk_base::crypto::types::qr_login::{QrCodeData, QrCodeMode, QrCodeModeData};
 use serde::{de::DeserializeOwned, Serialize};
 use tracing::{instrument, trace};
-#[cfg(test)]
 use url::Url;
-use vodozemac::ecies::{CheckCode, Ecies, EstablishedEcies, Message, OutboundCreationResult};
-#[cfg(test)]
-use vodozemac::ecies::{InboundCreationResult, InitialMessage};
+use vodozemac::ecies::{
+    CheckCode, Ecies, EstablishedEcies, InboundCreationResult, InitialMessage, Message,
+    OutboundCreationResult,
+};
 
 use super::{
     rendezvous_channel::{InboundChannelCreationResult, RendezvousChannel},
@@ -32,20 +30,14 @@ use crate::{config::RequestConfig, http_client::HttpClient};
 const LOGIN_INITIATE_MESSAGE: &str = "MATRIX_QR_CODE_LOGIN_INITIATE";
 const LOGIN_OK_MESSAGE: &str = "MATRIX_QR_CODE_LOGIN_OK";
 
-#[cfg(test)]
+/// The side of the secure channel which creates the rendezvous session and
+/// displays the QR code, used by the existing device to grant a login.
 pub(super) struct SecureChannel {
     channel: RendezvousChannel,
     qr_code_data: QrCodeData,
     ecies: Ecies,
 }
 
-// This is only used in tests because we're only supporting the new device part
-// of the QR login flow. It will be needed once we support reciprocating of the
-// login.
-//
-// It's still very much useful to have this, as we're testing the whole flow by
-// mocking the reciprocation.
-#[cfg(test)]
 impl SecureChannel {
     pub(super) async fn new(http_client: HttpClient, homeserver_url: &Url) -> Result<Self, Error> {
         let channel = RendezvousChannel::create_outbound(http_client, homeserver_url).await?;
@@ -96,13 +88,13 @@ impl SecureChannel {
 }
 
 /// An SecureChannel that is yet to be confirmed as with the [`CheckCode`].
-/// Same deal as for the [`SecureChannel`], not used for now.
-#[cfg(test)]
+///
+/// The check code is displayed by the device that scanned the QR code and needs
+/// to be entered on this side.
 pub(super) struct AlmostEstablishedSecureChannel {
     secure_channel: EstablishedSecureChannel,
 }
 
-#[cfg(test)]
 impl AlmostEstablishedSecureChannel {
     /// Confirm that the secure channel is indeed secure.
     ///
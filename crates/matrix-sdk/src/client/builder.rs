@@ -36,6 +36,10 @@ use crate::encryption::EncryptionSettings;
 use crate::http_client::HttpSettings;
 #[cfg(feature = "experimental-oidc")]
 use crate::oidc::OidcCtx;
+#[cfg(feature = "experimental-sliding-sync")]
+use crate::sliding_sync::{
+    self, Version as SlidingSyncVersion, VersionBuilder as SlidingSyncVersionBuilder,
+};
 use crate::{
     authentication::AuthCtx, config::RequestConfig, error::RumaApiError, http_client::HttpClient,
     send_queue::SendQueueData, HttpError, IdParseError,
@@ -85,6 +89,8 @@ pub struct ClientBuilder {
     homeserver_cfg: Option<HomeserverConfig>,
     #[cfg(feature = "experimental-sliding-sync")]
     sliding_sync_proxy: Option<String>,
+    #[cfg(feature = "experimental-sliding-sync")]
+    sliding_sync_version_builder: SlidingSyncVersionBuilder,
     http_cfg: Option<HttpConfig>,
     store_config: BuilderStoreConfig,
     request_config: RequestConfig,
@@ -94,6 +100,8 @@ pub struct ClientBuilder {
     base_client: Option<BaseClient>,
     #[cfg(feature = "e2e-encryption")]
     encryption_settings: EncryptionSettings,
+    #[cfg(feature = "sqlite")]
+    state_store_recovery: Option<StateStoreRecoveryHandler>,
 }
 
 impl ClientBuilder {
@@ -102,6 +110,8 @@ impl ClientBuilder {
             homeserver_cfg: None,
             #[cfg(feature = "experimental-sliding-sync")]
             sliding_sync_proxy: None,
+            #[cfg(feature = "experimental-sliding-sync")]
+            sliding_sync_version_builder: Default::default(),
             http_cfg: None,
             store_config: BuilderStoreConfig::Custom(StoreConfig::default()),
             request_config: Default::default(),
@@ -111,6 +121,8 @@ impl ClientBuilder {
             base_client: None,
             #[cfg(feature = "e2e-encryption")]
             encryption_settings: Default::default(),
+            #[cfg(feature = "sqlite")]
+            state_store_recovery: None,
         }
     }
 
@@ -140,6 +152,21 @@ impl ClientBuilder {
         self
     }
 
+    /// Set how to select the version of sliding sync to use.
+    ///
+    /// By default, the sliding sync proxy set with
+    /// [`Self::sliding_sync_proxy`] or discovered via the well-known lookup
+    /// is used. With [`SlidingSyncVersionBuilder::Auto`], the native sliding
+    /// sync API of the homeserver is preferred if it advertises it in
+    /// `/versions`, and the `.well-known` of the homeserver is looked up for a
+    /// sliding sync proxy otherwise, which costs additional requests when
+    /// building the client.
+    #[cfg(feature = "experimental-sliding-sync")]
+    pub fn sliding_sync_version_builder(mut self, builder: SlidingSyncVersionBuilder) -> Self {
+        self.sliding_sync_version_builder = builder;
+        self
+    }
+
     /// Set the server name to discover the homeserver from.
     ///
     /// We assume we can connect in HTTPS to that server. If that's not the
@@ -210,6 +237,55 @@ impl ClientBuilder {
         self
     }
 
+    /// Recreate the SQLite state store if it is corrupted or can't be migrated.
+    ///
+    /// By default, building the client fails if the state store set with
+    /// [`ClientBuilder::sqlite_store()`] is corrupted, or if its migration to
+    /// the current version of the SDK fails. With this option, the state store
+    /// is deleted and recreated empty instead. The crypto store is kept, so the
+    /// session and the encryption keys are not lost. Other errors, like a state
+    /// store created by a newer version of the SDK, still make building the
+    /// client fail, since the data could be used again.
+    ///
+    /// The recreated state store doesn't have a sync token, so the next sync
+    /// is an initial sync that restores the rooms from the homeserver.
+    ///
+    /// The callback is called with the outcome of every recovery, so it can be
+    /// reported to the user. If the state store can't be recreated, building
+    /// the client fails with [`ClientBuildError::StateStoreRecovery`].
+    ///
+    /// The corruption is only detected when building the client. The state
+    /// store is shared by the rooms and the caches of a running [`Client`], so
+    /// it can't be replaced without losing their consistency: if the store
+    /// becomes corrupted while the client is running, the client must be
+    /// dropped and built again with this option.
+    #[cfg(feature = "sqlite")]
+    pub fn recover_corrupted_state_store(
+        mut self,
+        callback: impl Fn(&StateStoreRecovery) + Send + Sync + 'static,
+    ) -> Self {
+        self.state_store_recovery = Some(StateStoreRecoveryHandler(Arc::new(callback)));
+        self
+    }
+
+    /// Set up the store configuration for a redb store.
+    ///
+    /// Like the SQLite store, it stores its data in files in the given
+    /// directory, encrypted with the passphrase if one is given, but it is
+    /// implemented in pure Rust.
+    #[cfg(feature = "redb")]
+    pub fn redb_store(
+        mut self,
+        path: impl AsRef<std::path::Path>,
+        passphrase: Option<&str>,
+    ) -> Self {
+        self.store_config = BuilderStoreConfig::Redb {
+            path: path.as_ref().to_owned(),
+            passphrase: passphrase.map(ToOwned::to_owned),
+        };
+        self
+    }
+
     /// Set up the store configuration for a IndexedDB store.
     #[cfg(feature = "indexeddb")]
     pub fn indexeddb_store(mut self, name: &str, passphrase: Option<&str>) -> Self {
@@ -401,12 +477,24 @@ impl ClientBuilder {
             HttpConfig::Custom(c) => c,
         };
 
+        #[cfg_attr(not(feature = "e2e-encryption"), allow(unused_mut))]
         let base_client = if let Some(base_client) = self.base_client {
             base_client
         } else {
-            BaseClient::with_store_config(build_store_config(self.store_config).await?)
+            BaseClient::with_store_config(
+                build_store_config(
+                    self.store_config,
+                    #[cfg(feature = "sqlite")]
+                    self.state_store_recovery,
+                )
+                .await?,
+            )
         };
 
+        #[cfg(feature = "e2e-encryption")]
+        let base_client = base_client
+            .with_decryption_trust_requirement(self.encryption_settings.trust_requirement);
+
         let http_client = HttpClient::new(inner_http_client.clone(), self.request_config);
 
         let (homeserver, well_known) = match homeserver_cfg {
@@ -430,6 +518,9 @@ impl ClientBuilder {
         let mut sliding_sync_proxy =
             self.sliding_sync_proxy.as_ref().map(|url| Url::parse(url)).transpose()?;
 
+        #[cfg(feature = "experimental-sliding-sync")]
+        let has_well_known = well_known.is_some();
+
         #[allow(unused_variables)]
         if let Some(well_known) = well_known {
             #[cfg(feature = "experimental-sliding-sync")]
@@ -441,6 +532,60 @@ impl ClientBuilder {
 
         let homeserver = Url::parse(&homeserver)?;
 
+        #[cfg_attr(not(feature = "experimental-sliding-sync"), allow(unused_mut))]
+        let mut server_versions = self.server_versions;
+        #[cfg_attr(not(feature = "experimental-sliding-sync"), allow(unused_mut))]
+        let mut unstable_features = None;
+        #[cfg_attr(not(feature = "experimental-sliding-sync"), allow(unused_mut))]
+        let mut authenticated_media_support = None;
+
+        #[cfg(feature = "experimental-sliding-sync")]
+        let sliding_sync_version = match self.sliding_sync_version_builder {
+            SlidingSyncVersionBuilder::Proxy => sliding_sync_proxy_version(sliding_sync_proxy),
+            SlidingSyncVersionBuilder::Native => SlidingSyncVersion::Native,
+            SlidingSyncVersionBuilder::Auto => {
+                let response = http_client
+                    .send(
+                        get_supported_versions::Request::new(),
+                        None,
+                        homeserver.to_string(),
+                        None,
+                        &[MatrixVersion::V1_0],
+                        Default::default(),
+                    )
+                    .await?;
+
+                let supports_native = response
+                    .unstable_features
+                    .get(sliding_sync::NATIVE_UNSTABLE_FEATURE)
+                    .copied()
+                    .unwrap_or(false);
+
+                // Don't waste the response, it is cached like the client does.
+                if server_versions.is_none() {
+                    let known_versions: Box<[MatrixVersion]> = response.known_versions().collect();
+                    if !known_versions.is_empty() {
+                        server_versions = Some(known_versions);
+                    }
+                }
+                authenticated_media_support = Some(super::supports_authenticated_media(&response));
+                unstable_features = Some(response.unstable_features);
+
+                if supports_native {
+                    SlidingSyncVersion::Native
+                } else {
+                    // The `.well-known` wasn't looked up if the homeserver URL was set
+                    // directly, so look it up now for a sliding sync proxy.
+                    if sliding_sync_proxy.is_none() && !has_well_known {
+                        sliding_sync_proxy =
+                            discover_sliding_sync_proxy(&homeserver, &http_client).await;
+                    }
+
+                    sliding_sync_proxy_version(sliding_sync_proxy)
+                }
+            }
+        };
+
         let auth_ctx = Arc::new(AuthCtx {
             handle_refresh_tokens: self.handle_refresh_tokens,
             refresh_token_lock: Mutex::new(Ok(())),
@@ -458,11 +603,12 @@ impl ClientBuilder {
             auth_ctx,
             homeserver,
             #[cfg(feature = "experimental-sliding-sync")]
-            sliding_sync_proxy,
+            sliding_sync_version,
             http_client,
             base_client,
-            self.server_versions,
-            None,
+            server_versions,
+            unstable_features,
+            authenticated_media_support,
             self.respect_login_well_known,
             event_cache,
             send_queue,
@@ -477,6 +623,36 @@ impl ClientBuilder {
     }
 }
 
+/// The version of sliding sync matching the given sliding sync proxy, if any.
+#[cfg(feature = "experimental-sliding-sync")]
+fn sliding_sync_proxy_version(sliding_sync_proxy: Option<Url>) -> SlidingSyncVersion {
+    match sliding_sync_proxy {
+        Some(url) => SlidingSyncVersion::Proxy { url },
+        None => SlidingSyncVersion::None,
+    }
+}
+
+/// Looks up the sliding sync proxy advertised in the `.well-known` of the
+/// server of the given homeserver URL, if any.
+#[cfg(feature = "experimental-sliding-sync")]
+async fn discover_sliding_sync_proxy(homeserver: &Url, http_client: &HttpClient) -> Option<Url> {
+    let host = homeserver.host_str()?;
+    let server_name = match homeserver.port() {
+        Some(port) => sanitize_server_name(&format!("{host}:{port}")),
+        None => sanitize_server_name(host),
+    }
+    .ok()?;
+    let protocol = if homeserver.scheme() == "http" { UrlScheme::Http } else { UrlScheme::Https };
+
+    match discover_homeserver(server_name, protocol, http_client).await {
+        Ok(well_known) => well_known.sliding_sync_proxy.and_then(|p| Url::parse(&p.url).ok()),
+        Err(error) => {
+            debug!(%error, "Well-known discovery of the sliding sync proxy failed");
+            None
+        }
+    }
+}
+
 /// Discovers a homeserver from a server name or a URL.
 ///
 /// Tries well-known discovery and checking if the URL points to a homeserver.
@@ -589,21 +765,36 @@ async fn check_is_homeserver(homeserver_url: &Url, http_client: &HttpClient) ->
     }
 }
 
-#[allow(clippy::unused_async)] // False positive when building with !sqlite & !indexeddb
+#[allow(clippy::unused_async)] // False positive when building with !sqlite & !redb & !indexeddb
 async fn build_store_config(
     builder_config: BuilderStoreConfig,
+    #[cfg(feature = "sqlite")] state_store_recovery: Option<StateStoreRecoveryHandler>,
 ) -> Result<StoreConfig, ClientBuildError> {
     #[allow(clippy::infallible_destructuring_match)]
     let store_config = match builder_config {
         #[cfg(feature = "sqlite")]
         BuilderStoreConfig::Sqlite { path, passphrase } => {
+            let state_store =
+                open_sqlite_state_store(&path, passphrase.as_deref(), state_store_recovery).await?;
+            let store_config = StoreConfig::new().state_store(state_store);
+
+            #[cfg(feature = "e2e-encryption")]
+            let store_config = store_config.crypto_store(
+                matrix_sdk_sqlite::SqliteCryptoStore::open(&path, passphrase.as_deref()).await?,
+            );
+
+            store_config
+        }
+
+        #[cfg(feature = "redb")]
+        BuilderStoreConfig::Redb { path, passphrase } => {
             let store_config = StoreConfig::new().state_store(
-                matrix_sdk_sqlite::SqliteStateStore::open(&path, passphrase.as_deref()).await?,
+                matrix_sdk_redb::RedbStateStore::open(&path, passphrase.as_deref()).await?,
             );
 
             #[cfg(feature = "e2e-encryption")]
             let store_config = store_config.crypto_store(
-                matrix_sdk_sqlite::SqliteCryptoStore::open(&path, passphrase.as_deref()).await?,
+                matrix_sdk_redb::RedbCryptoStore::open(&path, passphrase.as_deref()).await?,
             );
 
             store_config
@@ -619,6 +810,48 @@ async fn build_store_config(
     Ok(store_config)
 }
 
+/// Open the SQLite state store, recreating it if it is corrupted or can't be
+/// migrated and a recovery handler was set.
+#[cfg(feature = "sqlite")]
+async fn open_sqlite_state_store(
+    path: &std::path::Path,
+    passphrase: Option<&str>,
+    recovery_handler: Option<StateStoreRecoveryHandler>,
+) -> Result<matrix_sdk_sqlite::SqliteStateStore, ClientBuildError> {
+    use matrix_sdk_sqlite::SqliteStateStore;
+
+    let Some(handler) = recovery_handler else {
+        return Ok(SqliteStateStore::open(path, passphrase).await?);
+    };
+
+    let error = match SqliteStateStore::open(path, passphrase).await {
+        Ok(store) => return Ok(store),
+        Err(error) if error.is_corruption() || error.is_failed_migration() => error,
+        Err(error) => return Err(error.into()),
+    };
+
+    tracing::warn!(%error, "The state store can't be used, recreating it");
+
+    let reopened = match SqliteStateStore::delete_files(path).await {
+        Ok(()) => {
+            SqliteStateStore::open(path, passphrase).await.map_err(StateStoreRecoveryError::Open)
+        }
+        Err(error) => Err(StateStoreRecoveryError::Delete(error)),
+    };
+
+    match reopened {
+        Ok(store) => {
+            (handler.0)(&StateStoreRecovery { error, result: Ok(()) });
+            Ok(store)
+        }
+        Err(recovery_error) => {
+            let recovery = StateStoreRecovery { error, result: Err(recovery_error) };
+            (handler.0)(&recovery);
+            Err(recovery.result.unwrap_err().into())
+        }
+    }
+}
+
 // The indexeddb stores only implement `IntoStateStore` and `IntoCryptoStore` on
 // wasm32, so this only compiles there.
 #[cfg(all(target_arch = "wasm32", feature = "indexeddb"))]
@@ -705,6 +938,11 @@ enum BuilderStoreConfig {
         path: std::path::PathBuf,
         passphrase: Option<String>,
     },
+    #[cfg(feature = "redb")]
+    Redb {
+        path: std::path::PathBuf,
+        passphrase: Option<String>,
+    },
     #[cfg(feature = "indexeddb")]
     IndexedDb {
         name: String,
@@ -722,6 +960,10 @@ impl fmt::Debug for BuilderStoreConfig {
             Self::Sqlite { path, .. } => {
                 f.debug_struct("Sqlite").field("path", path).finish_non_exhaustive()
             }
+            #[cfg(feature = "redb")]
+            Self::Redb { path, .. } => {
+                f.debug_struct("Redb").field("path", path).finish_non_exhaustive()
+            }
             #[cfg(feature = "indexeddb")]
             Self::IndexedDb { name, .. } => {
                 f.debug_struct("IndexedDb").field("name", name).finish_non_exhaustive()
@@ -763,6 +1005,61 @@ pub enum ClientBuildError {
     #[cfg(feature = "sqlite")]
     #[error(transparent)]
     SqliteStore(#[from] matrix_sdk_sqlite::OpenStoreError),
+
+    /// Error opening the redb store.
+    #[cfg(feature = "redb")]
+    #[error(transparent)]
+    RedbStore(#[from] matrix_sdk_redb::OpenStoreError),
+
+    /// Error recreating a corrupted or unmigratable sqlite state store.
+    #[cfg(feature = "sqlite")]
+    #[error(transparent)]
+    StateStoreRecovery(#[from] StateStoreRecoveryError),
+}
+
+/// The outcome of the recovery of a SQLite state store that is corrupted or
+/// can't be migrated.
+///
+/// It is given to the callback set with
+/// [`ClientBuilder::recover_corrupted_state_store()`].
+#[cfg(feature = "sqlite")]
+#[derive(Debug)]
+pub struct StateStoreRecovery {
+    /// The error that was returned when opening the state store.
+    ///
+    /// Either [`OpenStoreError::is_corruption()`] or
+    /// [`OpenStoreError::is_failed_migration()`] is true for it.
+    ///
+    /// [`OpenStoreError::is_corruption()`]: matrix_sdk_sqlite::OpenStoreError::is_corruption
+    /// [`OpenStoreError::is_failed_migration()`]: matrix_sdk_sqlite::OpenStoreError::is_failed_migration
+    pub error: matrix_sdk_sqlite::OpenStoreError,
+
+    /// Whether the state store could be recreated.
+    pub result: Result<(), StateStoreRecoveryError>,
+}
+
+/// Errors that can happen when recreating a SQLite state store.
+#[cfg(feature = "sqlite")]
+#[derive(Debug, Error)]
+pub enum StateStoreRecoveryError {
+    /// The files of the state store could not be deleted.
+    #[error("failed to delete the state store: {0}")]
+    Delete(#[source] std::io::Error),
+
+    /// The recreated state store could not be opened.
+    #[error("failed to open the recreated state store: {0}")]
+    Open(#[source] matrix_sdk_sqlite::OpenStoreError),
+}
+
+#[cfg(feature = "sqlite")]
+#[derive(Clone)]
+struct StateStoreRecoveryHandler(Arc<dyn Fn(&StateStoreRecovery) + Send + Sync>);
+
+#[cfg(feature = "sqlite")]
+impl fmt::Debug for StateStoreRecoveryHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StateStoreRecoveryHandler").finish_non_exhaustive()
+    }
 }
 
 impl ClientBuildError {
@@ -1002,6 +1299,203 @@ pub(crate) mod tests {
         assert_eq!(client.sliding_sync_proxy(), Some("https://localhost:9012".parse().unwrap()));
     }
 
+    #[async_test]
+    #[cfg(feature = "experimental-sliding-sync")]
+    async fn test_sliding_sync_version_auto_native() {
+        // Given a homeserver that supports the native sliding sync API.
+        let homeserver = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/_matrix/client/versions"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json_internal!({
+                "versions": ["v1.0"],
+                "unstable_features": {
+                    "org.matrix.simplified_msc3575": true,
+                },
+            })))
+            .expect(1)
+            .mount(&homeserver)
+            .await;
+
+        // When building a client with the automatic selection of the sliding sync
+        // version.
+        let client = ClientBuilder::new()
+            .homeserver_url(homeserver.uri())
+            .sliding_sync_proxy("https://localhost:1234")
+            .sliding_sync_version_builder(SlidingSyncVersionBuilder::Auto)
+            .build()
+            .await
+            .unwrap();
+
+        // Then the native version is used, and the response is cached.
+        assert_eq!(client.sliding_sync_version(), SlidingSyncVersion::Native);
+        assert!(client.sliding_sync_proxy().is_none());
+        assert_eq!(client.server_versions().await.unwrap(), [MatrixVersion::V1_0]);
+    }
+
+    #[async_test]
+    #[cfg(feature = "experimental-sliding-sync")]
+    async fn test_sliding_sync_version_auto_proxy() {
+        // Given a homeserver that doesn't support the native sliding sync API.
+        let homeserver = make_mock_homeserver().await;
+
+        // When building a client with the automatic selection of the sliding sync
+        // version.
+        let client = ClientBuilder::new()
+            .homeserver_url(homeserver.uri())
+            .sliding_sync_proxy("https://localhost:1234")
+            .sliding_sync_version_builder(SlidingSyncVersionBuilder::Auto)
+            .build()
+            .await
+            .unwrap();
+
+        // Then the sliding sync proxy is used.
+        assert_eq!(
+            client.sliding_sync_version(),
+            SlidingSyncVersion::Proxy { url: "https://localhost:1234".parse().unwrap() }
+        );
+    }
+
+    #[async_test]
+    #[cfg(feature = "experimental-sliding-sync")]
+    async fn test_sliding_sync_version_auto_well_known_proxy() {
+        // Given a homeserver that doesn't support the native sliding sync API, but
+        // advertises a sliding sync proxy in its `.well-known`.
+        let homeserver = make_mock_homeserver().await;
+        Mock::given(method("GET"))
+            .and(path("/.well-known/matrix/client"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(make_well_known_json(
+                &homeserver.uri(),
+                Some("https://localhost:1234"),
+            )))
+            .expect(1)
+            .mount(&homeserver)
+            .await;
+
+        // When building a client with the homeserver URL and the automatic selection
+        // of the sliding sync version.
+        let client = ClientBuilder::new()
+            .homeserver_url(homeserver.uri())
+            .sliding_sync_version_builder(SlidingSyncVersionBuilder::Auto)
+            .build()
+            .await
+            .unwrap();
+
+        // Then the sliding sync proxy of the `.well-known` is used.
+        assert_eq!(
+            client.sliding_sync_version(),
+            SlidingSyncVersion::Proxy { url: "https://localhost:1234".parse().unwrap() }
+        );
+    }
+
+    #[async_test]
+    #[cfg(feature = "sqlite")]
+    async fn test_corrupted_state_store_without_recovery() {
+        // Given a corrupted state store.
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("matrix-sdk-state.sqlite3"), b"not a database").unwrap();
+
+        // When building a client without recovery.
+        let result = ClientBuilder::new()
+            .homeserver_url("http://localhost")
+            .server_versions([MatrixVersion::V1_0])
+            .sqlite_store(dir.path(), None)
+            .build()
+            .await;
+
+        // Then building the client fails.
+        assert_matches!(result, Err(ClientBuildError::SqliteStore(error)));
+        assert!(error.is_corruption());
+    }
+
+    #[async_test]
+    #[cfg(feature = "sqlite")]
+    async fn test_corrupted_state_store_is_recovered() {
+        use std::sync::Mutex as StdMutex;
+
+        // Given a corrupted state store.
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("matrix-sdk-state.sqlite3"), b"not a database").unwrap();
+
+        // When building a client that recovers the state store.
+        let recoveries = Arc::new(StdMutex::new(Vec::new()));
+        let client = ClientBuilder::new()
+            .homeserver_url("http://localhost")
+            .server_versions([MatrixVersion::V1_0])
+            .sqlite_store(dir.path(), None)
+            .recover_corrupted_state_store({
+                let recoveries = recoveries.clone();
+                move |recovery| recoveries.lock().unwrap().push(recovery.result.is_ok())
+            })
+            .build()
+            .await
+            .unwrap();
+
+        // Then the state store is recreated empty, and the recovery is reported.
+        assert_eq!(*recoveries.lock().unwrap(), [true]);
+        assert!(client.sync_token().await.is_none());
+        drop(client);
+
+        // And the recreated state store is opened normally afterwards.
+        ClientBuilder::new()
+            .homeserver_url("http://localhost")
+            .server_versions([MatrixVersion::V1_0])
+            .sqlite_store(dir.path(), None)
+            .recover_corrupted_state_store(|_| panic!("the state store isn't corrupted anymore"))
+            .build()
+            .await
+            .unwrap();
+    }
+
+    #[async_test]
+    #[cfg(feature = "sqlite")]
+    async fn test_state_store_with_failed_migration_is_recovered() {
+        use std::sync::Mutex as StdMutex;
+
+        use matrix_sdk_sqlite::SqliteStateStore;
+
+        // Given a state store whose last migration fails, because it was already
+        // applied but the version of the database says otherwise.
+        let dir = tempfile::tempdir().unwrap();
+        drop(SqliteStateStore::open(dir.path(), None).await.unwrap());
+
+        let conn = rusqlite::Connection::open(dir.path().join("matrix-sdk-state.sqlite3")).unwrap();
+        conn.execute("UPDATE kv SET value = X'04' WHERE key = 'version'", ()).unwrap();
+        drop(conn);
+
+        // When building a client without recovery, it fails.
+        let result = ClientBuilder::new()
+            .homeserver_url("http://localhost")
+            .server_versions([MatrixVersion::V1_0])
+            .sqlite_store(dir.path(), None)
+            .build()
+            .await;
+        assert_matches!(result, Err(ClientBuildError::SqliteStore(error)));
+        assert!(error.is_failed_migration());
+        assert!(!error.is_corruption());
+
+        // When building a client that recovers the state store.
+        let recoveries = Arc::new(StdMutex::new(Vec::new()));
+        ClientBuilder::new()
+            .homeserver_url("http://localhost")
+            .server_versions([MatrixVersion::V1_0])
+            .sqlite_store(dir.path(), None)
+            .recover_corrupted_state_store({
+                let recoveries = recoveries.clone();
+                move |recovery| {
+                    recoveries
+                        .lock()
+                        .unwrap()
+                        .push((recovery.error.is_failed_migration(), recovery.result.is_ok()))
+                }
+            })
+            .build()
+            .await
+            .unwrap();
+
+        // Then the state store is recreated, and the recovery is reported.
+        assert_eq!(*recoveries.lock().unwrap(), [(true, true)]);
+    }
+
     /* Helper functions */
 
     async fn make_mock_homeserver() -> MockServer {
@@ -27,6 +27,7 @@ use futures_core::Stream;
 #[cfg(feature = "e2e-encryption")]
 use matrix_sdk_base::crypto::store::LockableCryptoStore;
 use matrix_sdk_base::{
+    media::MediaRetentionPolicy,
     store::DynStateStore,
     sync::{Notification, RoomUpdates},
     BaseClient, RoomInfoUpdate, RoomState, RoomStateFilter, SendOutsideWasm, SessionMeta,
@@ -48,6 +49,7 @@ use ruma::{
             },
             filter::{create_filter::v3::Request as FilterUploadRequest, FilterDefinition},
             membership::{join_room_by_id, join_room_by_id_or_alias},
+            presence::get_presence,
             room::create_room,
             session::login::v3::DiscoveryInfo,
             sync::sync_events,
@@ -58,18 +60,23 @@ use ruma::{
         MatrixVersion, OutgoingRequest,
     },
     assign,
+    events::presence::PresenceEvent,
+    presence::PresenceState,
     push::Ruleset,
-    DeviceId, OwnedDeviceId, OwnedEventId, OwnedRoomId, OwnedServerName, RoomAliasId, RoomId,
-    RoomOrAliasId, ServerName, UInt, UserId,
+    serde::Raw,
+    DeviceId, OwnedDeviceId, OwnedEventId, OwnedRoomId, OwnedServerName, OwnedUserId, RoomAliasId,
+    RoomId, RoomOrAliasId, ServerName, UInt, UserId,
 };
 use serde::de::DeserializeOwned;
 use tokio::sync::{broadcast, Mutex, OnceCell, RwLock, RwLockReadGuard};
-use tracing::{debug, error, instrument, trace, Instrument, Span};
+use tracing::{debug, error, instrument, trace, warn, Instrument, Span};
 use url::Url;
 
 use self::futures::SendRequest;
 #[cfg(feature = "experimental-oidc")]
 use crate::oidc::Oidc;
+#[cfg(feature = "experimental-sliding-sync")]
+use crate::sliding_sync::Version as SlidingSyncVersion;
 use crate::{
     authentication::{AuthCtx, AuthData, ReloadSessionCallback, SaveSessionCallback},
     config::RequestConfig,
@@ -82,6 +89,7 @@ use crate::{
     http_client::HttpClient,
     matrix_auth::MatrixAuth,
     notification_settings::NotificationSettings,
+    presence::{ObservedPresence, Presence, PRESENCE_MAX_AGE},
     room_preview::RoomPreview,
     send_queue::SendQueueData,
     sync::{RoomUpdate, SyncResponse},
@@ -98,6 +106,8 @@ mod builder;
 pub(crate) mod futures;
 
 pub use self::builder::{sanitize_server_name, ClientBuildError, ClientBuilder};
+#[cfg(feature = "sqlite")]
+pub use self::builder::{StateStoreRecovery, StateStoreRecoveryError};
 
 #[cfg(not(target_arch = "wasm32"))]
 type NotificationHandlerFut = Pin<Box<dyn Future<Output = ()> + Send>>;
@@ -222,9 +232,10 @@ pub(crate) struct ClientInner {
     /// The URL of the homeserver to connect to.
     homeserver: StdRwLock<Url>,
 
-    /// The sliding sync proxy that is trusted by the homeserver.
+    /// The version of sliding sync to use, with the sliding sync proxy that is
+    /// trusted by the homeserver, if any.
     #[cfg(feature = "experimental-sliding-sync")]
-    sliding_sync_proxy: StdRwLock<Option<Url>>,
+    sliding_sync_version: StdRwLock<SlidingSyncVersion>,
 
     /// The underlying HTTP client.
     pub(crate) http_client: HttpClient,
@@ -238,6 +249,9 @@ pub(crate) struct ClientInner {
     /// The unstable features and their on/off state on the server
     unstable_features: OnceCell<BTreeMap<String, bool>>,
 
+    /// Whether the server supports authenticated media (MSC3916)
+    authenticated_media_support: OnceCell<bool>,
+
     /// Collection of locks individual client methods might want to use, either
     /// to ensure that only a single call to a method happens at once or to
     /// deduplicate multiple calls to a method.
@@ -247,6 +261,15 @@ pub(crate) struct ClientInner {
     /// keyed by room.
     pub(crate) typing_notice_times: StdRwLock<BTreeMap<OwnedRoomId, Instant>>,
 
+    /// The policy deciding which files are evicted from the media cache. See
+    /// [`Media::set_retention_policy()`].
+    ///
+    /// [`Media::set_retention_policy()`]: crate::Media::set_retention_policy
+    pub(crate) media_retention_policy: StdRwLock<MediaRetentionPolicy>,
+
+    /// When the media cache was last cleaned up automatically.
+    pub(crate) media_cache_last_clean_up: StdMutex<Option<Instant>>,
+
     /// Event handlers. See `add_event_handler`.
     pub(crate) event_handlers: EventHandlerStore,
 
@@ -288,6 +311,16 @@ pub(crate) struct ClientInner {
     ///
     /// [`SendQueue`]: crate::send_queue::SendQueue
     pub(crate) send_queue_data: Arc<SendQueueData>,
+
+    /// The presence states of the users that are observed, updated with the
+    /// presence events received in the sync. See
+    /// [`Client::subscribe_to_presence()`].
+    pub(crate) presence_states: Mutex<BTreeMap<OwnedUserId, ObservedPresence>>,
+
+    /// When the presence events of the users were last received in the sync,
+    /// for the events received less than [`PRESENCE_MAX_AGE`] ago. See
+    /// [`Client::get_presence()`].
+    pub(crate) presence_received_at: StdMutex<BTreeMap<OwnedUserId, Instant>>,
 }
 
 impl ClientInner {
@@ -300,11 +333,12 @@ impl ClientInner {
     async fn new(
         auth_ctx: Arc<AuthCtx>,
         homeserver: Url,
-        #[cfg(feature = "experimental-sliding-sync")] sliding_sync_proxy: Option<Url>,
+        #[cfg(feature = "experimental-sliding-sync")] sliding_sync_version: SlidingSyncVersion,
         http_client: HttpClient,
         base_client: BaseClient,
         server_versions: Option<Box<[MatrixVersion]>>,
         unstable_features: Option<BTreeMap<String, bool>>,
+        authenticated_media_support: Option<bool>,
         respect_login_well_known: bool,
         event_cache: OnceCell<EventCache>,
         send_queue: Arc<SendQueueData>,
@@ -314,13 +348,16 @@ impl ClientInner {
             homeserver: StdRwLock::new(homeserver),
             auth_ctx,
             #[cfg(feature = "experimental-sliding-sync")]
-            sliding_sync_proxy: StdRwLock::new(sliding_sync_proxy),
+            sliding_sync_version: StdRwLock::new(sliding_sync_version),
             http_client,
             base_client,
             locks: Default::default(),
             server_versions: OnceCell::new_with(server_versions),
             unstable_features: OnceCell::new_with(unstable_features),
+            authenticated_media_support: OnceCell::new_with(authenticated_media_support),
             typing_notice_times: Default::default(),
+            media_retention_policy: Default::default(),
+            media_cache_last_clean_up: Default::default(),
             event_handlers: Default::default(),
             notification_handlers: Default::default(),
             room_update_channels: Default::default(),
@@ -331,6 +368,8 @@ impl ClientInner {
             sync_beat: event_listener::Event::new(),
             event_cache,
             send_queue_data: send_queue,
+            presence_states: Default::default(),
+            presence_received_at: Default::default(),
             #[cfg(feature = "e2e-encryption")]
             e2ee: EncryptionData::new(encryption_settings),
             #[cfg(feature = "e2e-encryption")]
@@ -451,17 +490,40 @@ impl Client {
     }
 
     /// The sliding sync proxy that is trusted by the homeserver.
+    ///
+    /// Returns `None` if the client doesn't use a sliding sync proxy, see
+    /// [`Client::sliding_sync_version()`].
     #[cfg(feature = "experimental-sliding-sync")]
     pub fn sliding_sync_proxy(&self) -> Option<Url> {
-        let server = self.inner.sliding_sync_proxy.read().unwrap();
-        Some(server.as_ref()?.clone())
+        self.inner.sliding_sync_version.read().unwrap().proxy_url().cloned()
     }
 
     /// Force to set the sliding sync proxy URL.
+    ///
+    /// This sets the version of sliding sync to [`SlidingSyncVersion::Proxy`],
+    /// or [`SlidingSyncVersion::None`] if the URL is `None`.
     #[cfg(feature = "experimental-sliding-sync")]
     pub fn set_sliding_sync_proxy(&self, sliding_sync_proxy: Option<Url>) {
-        let mut lock = self.inner.sliding_sync_proxy.write().unwrap();
-        *lock = sliding_sync_proxy;
+        let version = match sliding_sync_proxy {
+            Some(url) => SlidingSyncVersion::Proxy { url },
+            None => SlidingSyncVersion::None,
+        };
+        self.set_sliding_sync_version(version);
+    }
+
+    /// The version of sliding sync used by this client.
+    ///
+    /// It is selected when building the client, see
+    /// [`ClientBuilder::sliding_sync_version_builder()`].
+    #[cfg(feature = "experimental-sliding-sync")]
+    pub fn sliding_sync_version(&self) -> SlidingSyncVersion {
+        self.inner.sliding_sync_version.read().unwrap().clone()
+    }
+
+    /// Force to set the version of sliding sync used by this client.
+    #[cfg(feature = "experimental-sliding-sync")]
+    pub fn set_sliding_sync_version(&self, version: SlidingSyncVersion) {
+        *self.inner.sliding_sync_version.write().unwrap() = version;
     }
 
     /// Get the Matrix user session meta information.
@@ -503,6 +565,105 @@ impl Client {
         self.send(request, None).await
     }
 
+    /// Get the presence of the given user.
+    ///
+    /// The presence received in the sync is returned if it was received less
+    /// than 5 minutes ago by this client, with its `last_active_ago` updated
+    /// with the time elapsed since then. Otherwise, like for a presence that
+    /// was stored during a previous session, it is fetched from the
+    /// homeserver.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the user to get the presence of.
+    pub async fn get_presence(&self, user_id: &UserId) -> Result<Presence> {
+        let received_at = self.inner.presence_received_at.lock().unwrap().get(user_id).copied();
+        let elapsed = received_at
+            .map(|received_at| received_at.elapsed())
+            .filter(|elapsed| *elapsed < PRESENCE_MAX_AGE);
+
+        if let Some(elapsed) = elapsed {
+            if let Some(event) = self.store().get_presence_event(user_id).await? {
+                match event.deserialize() {
+                    Ok(event) => {
+                        let mut presence = Presence::from(event.content);
+                        presence.last_active_ago = presence
+                            .last_active_ago
+                            .map(|last_active_ago| last_active_ago + elapsed);
+                        return Ok(presence);
+                    }
+                    Err(error) => warn!(%user_id, "Failed to deserialize presence event: {error}"),
+                }
+            }
+        }
+
+        let request = get_presence::v3::Request::new(user_id.to_owned());
+        Ok(self.send(request, None).await?.into())
+    }
+
+    /// Subscribe to the presence state of the given user.
+    ///
+    /// The initial value is the presence state returned by
+    /// [`Client::get_presence()`]. It is then updated with the presence events
+    /// received in the sync.
+    ///
+    /// Since sliding sync doesn't support presence, the presence state is also
+    /// loaded again every [`PRESENCE_MAX_AGE`] while it is observed. A single
+    /// task does it for each user, and it stops once all the subscribers are
+    /// dropped.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the user to observe the presence of.
+    pub async fn subscribe_to_presence(
+        &self,
+        user_id: &UserId,
+    ) -> Result<Subscriber<PresenceState>> {
+        if let Some(observed) = self.inner.presence_states.lock().await.get(user_id) {
+            return Ok(observed.state.subscribe());
+        }
+
+        let presence = self.get_presence(user_id).await?;
+
+        let mut presence_states = self.inner.presence_states.lock().await;
+        // Stop observing the users that are not observed anymore.
+        presence_states.retain(|_, observed| observed.is_observed());
+
+        Ok(presence_states
+            .entry(user_id.to_owned())
+            .or_insert_with(|| ObservedPresence::new(self, user_id.to_owned(), presence.state))
+            .state
+            .subscribe())
+    }
+
+    /// Update the observed presence states with the given presence events.
+    pub(crate) async fn update_presence_states(&self, events: &[Raw<PresenceEvent>]) {
+        if events.is_empty() {
+            return;
+        }
+
+        let now = Instant::now();
+        let mut presence_states = self.inner.presence_states.lock().await;
+        presence_states.retain(|_, observed| observed.is_observed());
+
+        let mut presence_received_at = self.inner.presence_received_at.lock().unwrap();
+        presence_received_at
+            .retain(|_, received_at| now.duration_since(*received_at) < PRESENCE_MAX_AGE);
+
+        for event in events {
+            match event.deserialize() {
+                Ok(event) => {
+                    if let Some(observed) = presence_states.get(&event.sender) {
+                        observed.state.set_if_not_eq(event.content.presence);
+                    }
+
+                    presence_received_at.insert(event.sender, now);
+                }
+                Err(error) => warn!("Failed to deserialize presence event: {error}"),
+            }
+        }
+    }
+
     /// Get the user id of the current owner of the client.
     pub fn user_id(&self) -> Option<&UserId> {
         self.session_meta().map(|s| s.user_id.as_ref())
@@ -1416,6 +1577,39 @@ impl Client {
             .await
     }
 
+    /// Send the given request and return the response as soon as its headers
+    /// are received, so its body can be streamed.
+    ///
+    /// See [`HttpClient::send_streaming()`] for the meaning of `body` and
+    /// `range_start`.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) async fn send_streaming<Request>(
+        &self,
+        request: Request,
+        config: Option<RequestConfig>,
+        body: Option<(reqwest::Body, u64)>,
+        range_start: Option<u64>,
+    ) -> HttpResult<reqwest::Response>
+    where
+        Request: OutgoingRequest + Debug,
+        HttpError: From<FromHttpResponseError<Request::EndpointError>>,
+    {
+        let access_token = self.access_token();
+
+        self.inner
+            .http_client
+            .send_streaming(
+                request,
+                config.unwrap_or(self.inner.http_client.request_config),
+                self.homeserver().to_string(),
+                access_token.as_deref(),
+                self.server_versions().await?,
+                body,
+                range_start,
+            )
+            .await
+    }
+
     fn broadcast_unknown_token(&self, soft_logout: &bool) {
         _ = self
             .inner
@@ -1424,8 +1618,12 @@ impl Client {
             .send(SessionChange::UnknownToken { soft_logout: *soft_logout });
     }
 
-    async fn request_server_versions(&self) -> HttpResult<Box<[MatrixVersion]>> {
-        let server_versions: Box<[MatrixVersion]> = self
+    /// Fetch the `/versions` response from the homeserver.
+    ///
+    /// Every response also fills the authenticated media support cache, since
+    /// it is derived from the same response.
+    async fn request_supported_versions(&self) -> HttpResult<get_supported_versions::Response> {
+        let response = self
             .inner
             .http_client
             .send(
@@ -1436,9 +1634,17 @@ impl Client {
                 &[MatrixVersion::V1_0],
                 Default::default(),
             )
-            .await?
-            .known_versions()
-            .collect();
+            .await?;
+
+        // This fails only if the cache was already filled, which is fine.
+        _ = self.inner.authenticated_media_support.set(supports_authenticated_media(&response));
+
+        Ok(response)
+    }
+
+    async fn request_server_versions(&self) -> HttpResult<Box<[MatrixVersion]>> {
+        let server_versions: Box<[MatrixVersion]> =
+            self.request_supported_versions().await?.known_versions().collect();
 
         if server_versions.is_empty() {
             Ok(vec![MatrixVersion::V1_0].into())
@@ -1459,19 +1665,8 @@ impl Client {
 
     /// Fetch unstable_features from homeserver
     async fn request_unstable_features(&self) -> HttpResult<BTreeMap<String, bool>> {
-        let unstable_features: BTreeMap<String, bool> = self
-            .inner
-            .http_client
-            .send(
-                get_supported_versions::Request::new(),
-                None,
-                self.homeserver().to_string(),
-                None,
-                &[MatrixVersion::V1_0],
-                Default::default(),
-            )
-            .await?
-            .unstable_features;
+        let unstable_features: BTreeMap<String, bool> =
+            self.request_supported_versions().await?.unstable_features;
 
         Ok(unstable_features)
     }
@@ -1518,6 +1713,41 @@ impl Client {
         Ok(self.unstable_features().await?.get("org.matrix.msc4028").copied().unwrap_or(false))
     }
 
+    /// Check whether the homeserver supports authenticated media ([MSC3916]).
+    ///
+    /// This is the case if it advertises support for Matrix 1.11 or later, or
+    /// the `org.matrix.msc3916.stable` unstable feature. The result is cached
+    /// along with the server versions.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use matrix_sdk::Client;
+    /// # use url::Url;
+    /// # async {
+    /// # let homeserver = Url::parse("http://localhost:8080")?;
+    /// # let mut client = Client::new(homeserver).await?;
+    /// if client.server_supports_authenticated_media().await? {
+    ///     println!("Media will be downloaded with authentication");
+    /// }
+    /// # anyhow::Ok(()) };
+    /// ```
+    ///
+    /// [MSC3916]: https://github.com/matrix-org/matrix-spec-proposals/pull/3916
+    pub async fn server_supports_authenticated_media(&self) -> HttpResult<bool> {
+        let supported = self
+            .inner
+            .authenticated_media_support
+            .get_or_try_init(|| async {
+                Ok::<_, HttpError>(supports_authenticated_media(
+                    &self.request_supported_versions().await?,
+                ))
+            })
+            .await?;
+
+        Ok(*supported)
+    }
+
     /// Get information of all our own devices.
     ///
     /// # Examples
@@ -2096,18 +2326,19 @@ impl Client {
     /// Create a new specialized `Client` that can process notifications.
     pub async fn notification_client(&self) -> Result<Client> {
         #[cfg(feature = "experimental-sliding-sync")]
-        let sliding_sync_proxy = self.inner.sliding_sync_proxy.read().unwrap().clone();
+        let sliding_sync_version = self.sliding_sync_version();
 
         let client = Client {
             inner: ClientInner::new(
                 self.inner.auth_ctx.clone(),
                 self.homeserver(),
                 #[cfg(feature = "experimental-sliding-sync")]
-                sliding_sync_proxy,
+                sliding_sync_version,
                 self.inner.http_client.clone(),
                 self.inner.base_client.clone_with_in_memory_state_store(),
                 self.inner.server_versions.get().cloned(),
                 self.inner.unstable_features.get().cloned(),
+                self.inner.authenticated_media_support.get().copied(),
                 self.inner.respect_login_well_known,
                 self.inner.event_cache.clone(),
                 self.inner.send_queue_data.clone(),
@@ -2144,6 +2375,23 @@ impl Client {
     }
 }
 
+/// Whether the given `/versions` response advertises support for
+/// authenticated media.
+///
+/// The version is parsed manually because Matrix 1.11 is not a known
+/// [`MatrixVersion`] yet.
+fn supports_authenticated_media(response: &get_supported_versions::Response) -> bool {
+    let stable_version = response.versions.iter().any(|version| {
+        version
+            .strip_prefix("v1.")
+            .and_then(|minor| minor.parse::<u32>().ok())
+            .is_some_and(|minor| minor >= 11)
+    });
+
+    stable_version
+        || response.unstable_features.get("org.matrix.msc3916.stable").copied().unwrap_or(false)
+}
+
 /// A weak reference to the inner client, useful when trying to get a handle
 /// on the owning client.
 #[derive(Clone)]
@@ -2182,10 +2430,11 @@ pub(crate) mod tests {
     #[cfg(target_arch = "wasm32")]
     wasm_bindgen_test::wasm_bindgen_test_configure!(run_in_browser);
 
-    use ruma::{events::ignored_user_list::IgnoredUserListEventContent, room_id, UserId};
+    use ruma::{events::ignored_user_list::IgnoredUserListEventContent, room_id, user_id, UserId};
+    use serde_json::json;
     use url::Url;
     use wiremock::{
-        matchers::{body_json, header, method, path},
+        matchers::{body_json, header, method, path, path_regex},
         Mock, MockServer, ResponseTemplate,
     };
 
@@ -2406,6 +2655,28 @@ pub(crate) mod tests {
         assert!(!response.limited);
     }
 
+    #[async_test]
+    async fn test_presence_states_without_subscribers_are_dropped() {
+        let server = MockServer::start().await;
+        let client = logged_in_client(Some(server.uri())).await;
+        let alice = user_id!("@alice:localhost");
+        let bob = user_id!("@bob:localhost");
+
+        Mock::given(method("GET"))
+            .and(path_regex(r"^/_matrix/client/.*/presence/.*/status"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "presence": "online" })))
+            .mount(&server)
+            .await;
+
+        let alice_subscriber = client.subscribe_to_presence(alice).await.unwrap();
+        drop(alice_subscriber);
+        let _bob_subscriber = client.subscribe_to_presence(bob).await.unwrap();
+
+        let presence_states = client.inner.presence_states.lock().await;
+        assert!(!presence_states.contains_key(alice));
+        assert!(presence_states.contains_key(bob));
+    }
+
     #[async_test]
     async fn test_request_unstable_features() {
         let server = MockServer::start().await;
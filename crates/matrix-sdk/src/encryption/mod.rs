@@ -17,7 +17,7 @@
 #![cfg_attr(target_arch = "wasm32", allow(unused_imports))]
 
 use std::{
-    collections::{BTreeMap, HashSet},
+    collections::{BTreeMap, BTreeSet, HashSet},
     io::{Cursor, Read, Write},
     iter,
     path::PathBuf,
@@ -30,8 +30,12 @@ use futures_util::{
     future::try_join,
     stream::{self, StreamExt},
 };
-use matrix_sdk_base::crypto::{
-    CrossSigningBootstrapRequests, OlmMachine, OutgoingRequest, RoomMessageRequest, ToDeviceRequest,
+use matrix_sdk_base::{
+    crypto::{
+        CrossSigningBootstrapRequests, OlmMachine, OutgoingRequest, RoomMessageRequest,
+        ToDeviceRequest,
+    },
+    deserialized_responses::EncryptionInfo,
 };
 use matrix_sdk_common::executor::spawn;
 use ruma::{
@@ -46,13 +50,17 @@ use ruma::{
         uiaa::AuthData,
     },
     assign,
-    events::room::{
-        message::{
-            AudioMessageEventContent, FileInfo, FileMessageEventContent, ImageMessageEventContent,
-            MessageType, VideoInfo, VideoMessageEventContent,
+    events::{
+        room::{
+            message::{
+                AudioMessageEventContent, FileInfo, FileMessageEventContent,
+                ImageMessageEventContent, MessageType, VideoInfo, VideoMessageEventContent,
+            },
+            ImageInfo, MediaSource, ThumbnailInfo,
         },
-        ImageInfo, MediaSource, ThumbnailInfo,
+        AnyToDeviceEventContent,
     },
+    serde::Raw,
     DeviceId, OwnedDeviceId, OwnedUserId, TransactionId, UserId,
 };
 use tokio::sync::RwLockReadGuard;
@@ -66,7 +74,7 @@ use self::{
     recovery::{Recovery, RecoveryState},
     secret_storage::SecretStorage,
     tasks::{BackupDownloadTask, BackupUploadingTask, ClientTasks},
-    verification::{SasVerification, Verification, VerificationRequest},
+    verification::{SasVerification, SessionVerification, Verification, VerificationRequest},
 };
 use crate::{
     attachment::{AttachmentConfig, Thumbnail},
@@ -92,7 +100,7 @@ pub use matrix_sdk_base::crypto::{
     },
     vodozemac, CrossSigningStatus, CryptoStoreError, DecryptorError, EventError, KeyExportError,
     LocalTrust, MediaEncryptionInfo, MegolmError, OlmError, RoomKeyImportResult, SecretImportError,
-    SessionCreationError, SignatureError, VERSION,
+    SessionCreationError, SignatureError, TrustRequirement, VERSION,
 };
 
 pub use crate::error::RoomKeyImportError;
@@ -156,6 +164,13 @@ pub struct EncryptionSettings {
 
     /// Automatically create a backup version if no backup exists.
     pub auto_enable_backups: bool,
+
+    /// The trust level in the sender's device that is required to decrypt room
+    /// events, by default events from all devices are decrypted.
+    ///
+    /// Events which don't satisfy the requirement fail to decrypt with a
+    /// [`MegolmError::SenderIdentityNotTrusted`] error.
+    pub trust_requirement: TrustRequirement,
 }
 
 /// Settings for end-to-end encryption features.
@@ -606,6 +621,27 @@ impl Encryption {
         self.client.inner.e2ee.encryption_settings
     }
 
+    /// Get the trust level in the sender's device that is required to decrypt
+    /// room events, as configured in the [`EncryptionSettings`].
+    pub fn trust_requirement(&self) -> TrustRequirement {
+        self.settings().trust_requirement
+    }
+
+    /// Check that the sender of an event which was already decrypted still
+    /// satisfies the configured [`TrustRequirement`].
+    ///
+    /// The trust in the sender's device can drop after the event has been
+    /// decrypted, in which case a [`MegolmError::SenderIdentityNotTrusted`]
+    /// error is returned.
+    pub async fn check_sender_trust(&self, encryption_info: &EncryptionInfo) -> Result<()> {
+        let olm = self.client.olm_machine().await;
+        let olm = olm.as_ref().ok_or(Error::NoOlmMachine)?;
+
+        olm.check_sender_trust(encryption_info, self.trust_requirement()).await?;
+
+        Ok(())
+    }
+
     /// Get the public ed25519 key of our own device. This is usually what is
     /// called the fingerprint of the device.
     pub async fn ed25519_key(&self) -> Option<String> {
@@ -976,6 +1012,114 @@ impl Encryption {
             .map(move |updates| IdentityUpdates::new(client.to_owned(), updates)))
     }
 
+    /// Encrypt and send a custom to-device event to the given devices.
+    ///
+    /// Olm sessions are first established with the devices we don't have one
+    /// with, the content is then encrypted for each device and sent out in
+    /// batches.
+    ///
+    /// The recipients receive an `m.room.encrypted` to-device event which gets
+    /// decrypted into an event of the given type. Event handlers registered
+    /// using [`Client::add_event_handler()`] can take an
+    /// `Option<EncryptionInfo>` argument to learn which device sent the event
+    /// and whether it's verified.
+    ///
+    /// # Arguments
+    ///
+    /// * `recipients` - The devices the event should be sent to.
+    ///
+    /// * `event_type` - The type of the event.
+    ///
+    /// * `content` - The content of the event.
+    ///
+    /// # Returns
+    ///
+    /// The devices we couldn't establish an Olm session with, which didn't
+    /// receive the event.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use matrix_sdk::{
+    /// #     deserialized_responses::{EncryptionInfo, VerificationState},
+    /// #     ruma::{events::macros::EventContent, serde::Raw},
+    /// #     Client,
+    /// # };
+    /// # use ruma::{device_id, user_id};
+    /// # use serde::{Deserialize, Serialize};
+    /// # let client: Client = unimplemented!();
+    /// # async {
+    /// #[derive(Clone, Debug, Deserialize, Serialize, EventContent)]
+    /// #[ruma_event(type = "org.example.call.invite", kind = ToDevice)]
+    /// struct CallInviteEventContent {
+    ///     call_id: String,
+    /// }
+    ///
+    /// let device = client
+    ///     .encryption()
+    ///     .get_device(user_id!("@alice:example.org"), device_id!("DEVICEID"))
+    ///     .await?
+    ///     .expect("We should know about the device");
+    ///
+    /// let content = CallInviteEventContent { call_id: "1234".to_owned() };
+    /// client
+    ///     .encryption()
+    ///     .send_encrypted_to_device(
+    ///         &[device],
+    ///         "org.example.call.invite",
+    ///         Raw::new(&content)?.cast(),
+    ///     )
+    ///     .await?;
+    ///
+    /// // On the receiving side.
+    /// client.add_event_handler(
+    ///     |ev: ToDeviceCallInviteEvent, info: Option<EncryptionInfo>| async move {
+    ///         // Only trust events encrypted by a verified device.
+    ///         let verified = info.map(|i| i.verification_state)
+    ///             == Some(VerificationState::Verified);
+    ///
+    ///         if verified {
+    ///             println!("Received an invite for call {}", ev.content.call_id);
+    ///         }
+    ///     },
+    /// );
+    /// # anyhow::Ok(()) };
+    /// ```
+    #[instrument(skip_all, fields(event_type, recipients = recipients.len()))]
+    pub async fn send_encrypted_to_device(
+        &self,
+        recipients: &[Device],
+        event_type: &str,
+        content: Raw<AnyToDeviceEventContent>,
+    ) -> Result<Vec<(OwnedUserId, OwnedDeviceId)>> {
+        let users: BTreeSet<_> = recipients.iter().map(|device| device.user_id()).collect();
+        self.client.claim_one_time_keys(users.into_iter()).await?;
+
+        let content = content.deserialize_as::<serde_json::Value>()?;
+        let devices = recipients.iter().map(|device| device.inner.clone()).collect();
+
+        let (requests, devices_without_session) = {
+            let olm = self.client.olm_machine().await;
+            let olm = olm.as_ref().ok_or(Error::NoOlmMachine)?;
+
+            olm.encrypt_content_for_devices(devices, event_type, &content).await?
+        };
+
+        if !devices_without_session.is_empty() {
+            warn!(
+                ?devices_without_session,
+                "Couldn't establish an Olm session with some of the recipients"
+            );
+        }
+
+        for request in requests {
+            let response = self.client.send_to_device(&request).await?;
+            self.client.mark_request_as_sent(&request.txn_id, &response).await?;
+        }
+
+        Ok(devices_without_session)
+    }
+
     /// Create and upload a new cross signing identity.
     ///
     /// # Arguments
@@ -1273,6 +1417,15 @@ impl Encryption {
         Recovery { client: self.client.to_owned() }
     }
 
+    /// Create a new controller to verify our own device, either with another
+    /// device or using the recovery key.
+    ///
+    /// Every call creates an independent controller, keep the returned value
+    /// around for the duration of the verification.
+    pub fn session_verification(&self) -> SessionVerification {
+        SessionVerification::new(self.client.to_owned())
+    }
+
     /// Get dehydrated devices manager of client.
     pub fn dehydrated_devices(&self) -> DehydratedDevices {
         DehydratedDevices {  client: self.client.to_owned() }
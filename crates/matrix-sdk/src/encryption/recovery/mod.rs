@@ -109,10 +109,10 @@ use crate::Client;
 
 pub mod futures;
 mod types;
-pub use self::types::{EnableProgress, RecoveryError, RecoveryState, Result};
+pub use self::types::{EnableProgress, RecoveryError, RecoveryHealthReport, RecoveryState, Result};
 use self::{
     futures::{Enable, RecoverAndReset, Reset},
-    types::{BackupDisabledContent, SecretStorageDisabledContent},
+    types::BackupDisabledContent,
 };
 
 /// The recovery manager for the [`Client`].
@@ -283,8 +283,7 @@ impl Recovery {
     #[instrument(skip_all)]
     pub async fn disable(&self) -> Result<()> {
         self.client.encryption().backups().disable().await?;
-        // Why oh why, can't we delete account data events?
-        self.client.account().set_account_data(SecretStorageDisabledContent {}).await?;
+        self.client.encryption().secret_storage().clear_default_key().await?;
         self.client.account().set_account_data(BackupDisabledContent { disabled: true }).await?;
         self.update_recovery_state().await?;
         // TODO: Do we want to "delete" the known secrets as well?
@@ -399,6 +398,48 @@ impl Recovery {
         Ok(devices.devices().count() == 1)
     }
 
+    /// Create a [`RecoveryHealthReport`] explaining the current
+    /// [`RecoveryState`].
+    ///
+    /// This is useful to tell the user why recovery is in the
+    /// [`RecoveryState::Incomplete`] state, for example because some secrets
+    /// are only encrypted using an older secret storage key.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use matrix_sdk::{Client, encryption::recovery::RecoveryState};
+    /// # use url::Url;
+    /// # async {
+    /// # let homeserver = Url::parse("http://example.com")?;
+    /// # let client = Client::new(homeserver).await?;
+    /// let report = client.encryption().recovery().health_report().await?;
+    ///
+    /// if report.state == RecoveryState::Incomplete {
+    ///     for (secret_name, key_ids) in &report.secret_storage.secrets_in_other_keys {
+    ///         println!("The secret {secret_name} is only encrypted using the keys {key_ids:?}");
+    ///     }
+    /// }
+    /// # anyhow::Ok(()) };
+    /// ```
+    #[instrument(skip_all)]
+    pub async fn health_report(&self) -> Result<RecoveryHealthReport> {
+        let cross_signing_complete = self
+            .client
+            .encryption()
+            .cross_signing_status()
+            .await
+            .is_some_and(|status| status.is_complete());
+
+        Ok(RecoveryHealthReport {
+            state: self.check_recovery_state().await?,
+            cross_signing_complete,
+            backups_enabled: self.client.encryption().backups().are_enabled().await,
+            backups_marked_as_disabled: self.are_backups_marked_as_disabled().await?,
+            secret_storage: self.client.encryption().secret_storage().health_report().await?,
+        })
+    }
+
     /// Did we correctly set up cross-signing and backups?
     async fn all_known_secrets_available(&self) -> Result<bool> {
         // Cross-signing state is fine if we have all the private cross-signing keys, as
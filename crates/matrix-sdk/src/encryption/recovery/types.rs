@@ -21,6 +21,7 @@ use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use zeroize::{Zeroize, ZeroizeOnDrop};
 
+use crate::encryption::secret_storage::SecretStorageHealthReport;
 #[cfg(doc)]
 use crate::encryption::{
     backups::Backups,
@@ -100,13 +101,27 @@ pub enum RecoveryState {
     Incomplete,
 }
 
-/// A hack to allow the `m.secret_storage.default_key` event to be "deleted".
+/// A report explaining the current [`RecoveryState`], especially useful to
+/// figure out why the state is [`RecoveryState::Incomplete`].
 ///
-/// This allows us to set the `m.secret_storage.default_key` event to an empty
-/// JSON object, which means that the event will be invalid.
-#[derive(Clone, Debug, Default, Deserialize, Serialize, EventContent)]
-#[ruma_event(type = "m.secret_storage.default_key", kind = GlobalAccountData)]
-pub(super) struct SecretStorageDisabledContent {}
+/// Created by the [`Recovery::health_report()`] method.
+#[derive(Clone, Debug)]
+pub struct RecoveryHealthReport {
+    /// The current recovery state.
+    pub state: RecoveryState,
+
+    /// Do we have all the private cross-signing keys locally.
+    pub cross_signing_complete: bool,
+
+    /// Are backups enabled on this device.
+    pub backups_enabled: bool,
+
+    /// Have backups been explicitly disabled at the account level.
+    pub backups_marked_as_disabled: bool,
+
+    /// The health report of the secret storage setup on the homeserver.
+    pub secret_storage: SecretStorageHealthReport,
+}
 
 /// A custom global account data event which tells us that a new backup should
 /// not be automatically created.
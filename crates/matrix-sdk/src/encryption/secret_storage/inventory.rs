@@ -0,0 +1,274 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeMap;
+
+use ruma::events::secret::request::SecretName;
+
+#[cfg(doc)]
+use super::SecretStorage;
+
+/// The secrets the SDK knows about and stores in secret storage.
+pub(super) fn well_known_secrets() -> [SecretName; 4] {
+    [
+        SecretName::CrossSigningMasterKey,
+        SecretName::CrossSigningSelfSigningKey,
+        SecretName::CrossSigningUserSigningKey,
+        SecretName::RecoveryKey,
+    ]
+}
+
+/// Info about a single secret storage key, as found in the account data of the
+/// user.
+#[derive(Clone, Debug)]
+pub struct SecretStorageKeyInfo {
+    /// The ID of the secret storage key.
+    pub key_id: String,
+
+    /// The human-readable name of the key, if one was set when the key was
+    /// created.
+    pub name: Option<String>,
+
+    /// Can the key be derived from a passphrase.
+    pub has_passphrase: bool,
+
+    /// Is this the default secret storage key, as advertised in the
+    /// `m.secret_storage.default_key` event.
+    pub is_default: bool,
+
+    /// Did we find a valid `m.secret_storage.key.*` event describing the key.
+    ///
+    /// If this is `false`, the key is only referenced by some secrets or the
+    /// default key event, but the key description itself is missing or has
+    /// been deleted, making the key unusable.
+    pub description_found: bool,
+
+    /// The secrets which are encrypted using this key.
+    pub secrets: Vec<SecretName>,
+}
+
+impl SecretStorageKeyInfo {
+    pub(super) fn new(key_id: String) -> Self {
+        Self {
+            key_id,
+            name: None,
+            has_passphrase: false,
+            is_default: false,
+            description_found: false,
+            secrets: Vec::new(),
+        }
+    }
+
+    /// Is this key orphaned, i.e. it's not the default key and it can't be used
+    /// to decrypt any secret, or its description is missing.
+    pub fn is_orphaned(&self) -> bool {
+        !self.is_default && (self.secrets.is_empty() || !self.description_found)
+    }
+}
+
+/// An inventory of all the secret storage keys and the secrets they encrypt.
+///
+/// Created by the [`SecretStorage::inventory()`] method.
+///
+/// **Note**: Account data can't be listed, so the inventory only contains the
+/// default key and the keys that encrypt at least one of the well-known
+/// secrets, or that were explicitly requested using the
+/// [`SecretStorage::inventory_with_keys()`] method.
+#[derive(Clone, Debug, Default)]
+pub struct SecretStorageInventory {
+    /// The ID of the default secret storage key, if one is set.
+    pub default_key_id: Option<String>,
+
+    /// All the secret storage keys we found, keyed by their key ID.
+    pub keys: BTreeMap<String, SecretStorageKeyInfo>,
+}
+
+impl SecretStorageInventory {
+    /// Get the info about the default secret storage key.
+    pub fn default_key(&self) -> Option<&SecretStorageKeyInfo> {
+        self.keys.get(self.default_key_id.as_deref()?)
+    }
+
+    /// Get all the keys which are orphaned and may be deleted.
+    ///
+    /// See [`SecretStorageKeyInfo::is_orphaned()`].
+    pub fn orphaned_keys(&self) -> impl Iterator<Item = &SecretStorageKeyInfo> {
+        self.keys.values().filter(|info| info.is_orphaned())
+    }
+
+    /// Get the keys which can decrypt the given secret.
+    pub fn keys_for_secret<'a>(
+        &'a self,
+        secret_name: &'a SecretName,
+    ) -> impl Iterator<Item = &'a SecretStorageKeyInfo> + 'a {
+        self.keys
+            .values()
+            .filter(move |info| info.description_found && info.secrets.contains(secret_name))
+    }
+
+    /// Create a [`SecretStorageHealthReport`] out of this inventory.
+    pub fn health_report(&self) -> SecretStorageHealthReport {
+        let default_key = self.default_key();
+
+        let default_key_missing =
+            self.default_key_id.is_some() && !default_key.is_some_and(|k| k.description_found);
+
+        let mut secrets_missing_from_default_key = Vec::new();
+        let mut secrets_in_other_keys = BTreeMap::new();
+
+        for secret_name in well_known_secrets() {
+            let in_default_key = default_key
+                .is_some_and(|k| k.description_found && k.secrets.contains(&secret_name));
+
+            if !in_default_key {
+                let other_keys: Vec<_> = self
+                    .keys_for_secret(&secret_name)
+                    .filter(|k| !k.is_default)
+                    .map(|k| k.key_id.clone())
+                    .collect();
+
+                if !other_keys.is_empty() {
+                    secrets_in_other_keys.insert(secret_name.to_string(), other_keys);
+                }
+
+                secrets_missing_from_default_key.push(secret_name);
+            }
+        }
+
+        SecretStorageHealthReport {
+            default_key_id: self.default_key_id.clone(),
+            default_key_missing,
+            secrets_missing_from_default_key,
+            secrets_in_other_keys,
+            orphaned_keys: self.orphaned_keys().map(|k| k.key_id.clone()).collect(),
+        }
+    }
+}
+
+/// A report describing problems with the secret storage setup of the user.
+///
+/// Created by the [`SecretStorage::health_report()`] method.
+#[derive(Clone, Debug, Default)]
+pub struct SecretStorageHealthReport {
+    /// The ID of the default secret storage key, if one is set.
+    pub default_key_id: Option<String>,
+
+    /// A default key is set, but its description can't be found in the account
+    /// data, the default key is unusable.
+    pub default_key_missing: bool,
+
+    /// The well-known secrets which are not encrypted using the default key.
+    pub secrets_missing_from_default_key: Vec<SecretName>,
+
+    /// The secrets, out of the ones missing from the default key, which can
+    /// still be decrypted using other keys, mapping the secret name to the
+    /// list of key IDs.
+    ///
+    /// Those secrets can be migrated to the default key using
+    /// [`SecretStore::migrate_secrets_to()`].
+    ///
+    /// [`SecretStore::migrate_secrets_to()`]: super::SecretStore::migrate_secrets_to
+    pub secrets_in_other_keys: BTreeMap<String, Vec<String>>,
+
+    /// The IDs of the keys which are orphaned and may be deleted.
+    pub orphaned_keys: Vec<String>,
+}
+
+impl SecretStorageHealthReport {
+    /// Is secret storage in a healthy state, i.e. the default key exists and
+    /// encrypts all the well-known secrets.
+    pub fn is_healthy(&self) -> bool {
+        self.default_key_id.is_some()
+            && !self.default_key_missing
+            && self.secrets_missing_from_default_key.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ruma::events::secret::request::SecretName;
+
+    use super::{SecretStorageInventory, SecretStorageKeyInfo};
+
+    fn key(key_id: &str, is_default: bool, secrets: Vec<SecretName>) -> SecretStorageKeyInfo {
+        SecretStorageKeyInfo {
+            is_default,
+            description_found: true,
+            secrets,
+            ..SecretStorageKeyInfo::new(key_id.to_owned())
+        }
+    }
+
+    #[test]
+    fn test_health_report() {
+        let mut inventory =
+            SecretStorageInventory { default_key_id: Some("new".to_owned()), ..Default::default() };
+
+        inventory.keys.insert(
+            "new".to_owned(),
+            key(
+                "new",
+                true,
+                vec![
+                    SecretName::CrossSigningMasterKey,
+                    SecretName::CrossSigningSelfSigningKey,
+                    SecretName::CrossSigningUserSigningKey,
+                ],
+            ),
+        );
+        inventory.keys.insert("old".to_owned(), key("old", false, vec![SecretName::RecoveryKey]));
+        inventory.keys.insert("unused".to_owned(), key("unused", false, vec![]));
+
+        let report = inventory.health_report();
+
+        assert!(!report.is_healthy());
+        assert!(!report.default_key_missing);
+        assert_eq!(report.secrets_missing_from_default_key, vec![SecretName::RecoveryKey]);
+        assert_eq!(
+            report.secrets_in_other_keys.get(SecretName::RecoveryKey.as_str()),
+            Some(&vec!["old".to_owned()])
+        );
+        assert_eq!(report.orphaned_keys, vec!["unused".to_owned()]);
+
+        inventory.keys.get_mut("new").unwrap().secrets.push(SecretName::RecoveryKey);
+        inventory.keys.remove("unused");
+
+        let report = inventory.health_report();
+        assert!(report.is_healthy());
+        assert!(report.orphaned_keys.is_empty());
+    }
+
+    #[test]
+    fn test_missing_default_key() {
+        let mut inventory = SecretStorageInventory {
+            default_key_id: Some("lost".to_owned()),
+            ..Default::default()
+        };
+
+        inventory.keys.insert(
+            "lost".to_owned(),
+            SecretStorageKeyInfo {
+                is_default: true,
+                ..SecretStorageKeyInfo::new("lost".to_owned())
+            },
+        );
+
+        let report = inventory.health_report();
+
+        assert!(report.default_key_missing);
+        assert!(!report.is_healthy());
+        assert_eq!(report.secrets_missing_from_default_key.len(), 4);
+        assert!(report.orphaned_keys.is_empty(), "The default key is never orphaned");
+    }
+}
@@ -66,24 +66,36 @@ use matrix_sdk_base::crypto::{
     secret_storage::{DecodeError, MacError, SecretStorageKey},
     CryptoStoreError, SecretImportError,
 };
-use ruma::events::{
-    secret_storage::{
-        default_key::SecretStorageDefaultKeyEventContent, key::SecretStorageKeyEventContent,
+use ruma::{
+    events::{
+        secret::request::SecretName,
+        secret_storage::{
+            default_key::SecretStorageDefaultKeyEventContent, key::SecretStorageKeyEventContent,
+            secret::SecretEventContent,
+        },
+        EventContentFromType, GlobalAccountDataEventType,
     },
-    EventContentFromType, GlobalAccountDataEventType,
+    exports::ruma_macros::EventContent,
+    serde::Raw,
 };
+use serde::{Deserialize, Serialize};
 use serde_json::value::to_raw_value;
 use thiserror::Error;
+use tracing::{info, instrument};
 
 use super::identities::ManualVerifyError;
 use crate::Client;
 
 mod futures;
+mod inventory;
 mod secret_store;
 
 pub use futures::CreateStore;
+pub use inventory::{SecretStorageHealthReport, SecretStorageInventory, SecretStorageKeyInfo};
 pub use secret_store::SecretStore;
 
+use self::inventory::well_known_secrets;
+
 /// Convenicence type alias for the secret-storage specific results.
 pub type Result<T, E = SecretStorageError> = std::result::Result<T, E>;
 
@@ -134,8 +146,24 @@ pub enum SecretStorageError {
     /// Error describing a decryption failure of a secret.
     #[error(transparent)]
     Decryption(#[from] DecryptionError),
+
+    /// The key can't be deleted since it's the default secret storage key,
+    /// either set a different default key or clear the default key first.
+    #[error("The secret storage key {key_id} is the default key and can't be deleted")]
+    DefaultKeyDeletion {
+        /// The key ID of the key we attempted to delete.
+        key_id: String,
+    },
 }
 
+/// A hack to allow the `m.secret_storage.default_key` event to be "deleted".
+///
+/// This allows us to set the `m.secret_storage.default_key` event to an empty
+/// JSON object, which means that the event will be invalid.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, EventContent)]
+#[ruma_event(type = "m.secret_storage.default_key", kind = GlobalAccountData)]
+pub(crate) struct SecretStorageDisabledContent {}
+
 /// Error type describing decryption failures of the secret-storage system.
 #[derive(Debug, Error)]
 pub enum DecryptionError {
@@ -196,30 +224,70 @@ impl SecretStorage {
             let default_key_id =
                 default_key_id.deserialize_as::<SecretStorageDefaultKeyEventContent>()?;
 
-            let event_type =
-                GlobalAccountDataEventType::SecretStorageKey(default_key_id.key_id.to_owned());
-            let secret_key =
-                self.client.account().fetch_account_data(event_type.to_owned()).await?;
+            self.open_secret_store_with_key_id(&default_key_id.key_id, secret_storage_key).await
+        } else {
+            Err(SecretStorageError::MissingKeyInfo { key_id: None })
+        }
+    }
 
-            if let Some(secret_key_content) = secret_key {
-                let event_type = event_type.to_string();
-                let secret_key_content = to_raw_value(&secret_key_content)?;
+    /// Open the [`SecretStore`] for the secret storage key with the given
+    /// `key_id`, which doesn't need to be the default key.
+    ///
+    /// This is useful to recover secrets that were encrypted using an older
+    /// secret storage key, for example to migrate them to the default key
+    /// using the [`SecretStore::migrate_secrets_to()`] method.
+    ///
+    /// The `secret_storage_key` can be a passphrase or a Base58 encoded secret
+    /// storage key.
+    pub async fn open_secret_store_with_key_id(
+        &self,
+        key_id: &str,
+        secret_storage_key: &str,
+    ) -> Result<SecretStore> {
+        if let Some(secret_key_content) = self.fetch_key_description(key_id).await? {
+            let key = SecretStorageKey::from_account_data(secret_storage_key, secret_key_content)?;
 
-                let secret_key_content =
-                    SecretStorageKeyEventContent::from_parts(&event_type, &secret_key_content)?;
+            Ok(SecretStore { client: self.client.to_owned(), key })
+        } else {
+            Err(SecretStorageError::MissingKeyInfo { key_id: Some(key_id.to_owned()) })
+        }
+    }
 
-                let key =
-                    SecretStorageKey::from_account_data(secret_storage_key, secret_key_content)?;
+    /// Fetch the `m.secret_storage.key.*` event describing the key with the
+    /// given key ID.
+    async fn fetch_key_description(
+        &self,
+        key_id: &str,
+    ) -> Result<Option<SecretStorageKeyEventContent>> {
+        let event_type = GlobalAccountDataEventType::SecretStorageKey(key_id.to_owned());
 
-                Ok(SecretStore { client: self.client.to_owned(), key })
-            } else {
-                Err(SecretStorageError::MissingKeyInfo { key_id: Some(default_key_id.key_id) })
-            }
+        if let Some(secret_key_content) =
+            self.client.account().fetch_account_data(event_type.to_owned()).await?
+        {
+            let event_type = event_type.to_string();
+            let secret_key_content = to_raw_value(&secret_key_content)?;
+
+            Ok(Some(SecretStorageKeyEventContent::from_parts(&event_type, &secret_key_content)?))
         } else {
-            Err(SecretStorageError::MissingKeyInfo { key_id: None })
+            Ok(None)
         }
     }
 
+    /// Fetch the key ID of the default secret storage key.
+    ///
+    /// Returns `None` if no default key is set, or if it has been cleared.
+    async fn fetch_default_key_id(&self) -> Result<Option<String>> {
+        Ok(self
+            .client
+            .account()
+            .fetch_account_data(GlobalAccountDataEventType::SecretStorageDefaultKey)
+            .await?
+            .and_then(|content| {
+                content.deserialize_as::<SecretStorageDefaultKeyEventContent>().ok()
+            })
+            .map(|content| content.key_id))
+    }
+
     /// Create a new [`SecretStore`].
     ///
     /// The [`SecretStore`] will be protected by a randomly generated key, or
@@ -285,4 +353,217 @@ impl SecretStorage {
             Ok(false)
         }
     }
+
+    /// Collect a [`SecretStorageInventory`] listing the secret storage keys of
+    /// the user and the secrets each one of them encrypts.
+    ///
+    /// **Note**: Since account data can't be enumerated, only the default key
+    /// and the keys which encrypt one of the well-known secrets will be found.
+    /// Use [`SecretStorage::inventory_with_keys()`] to include additional
+    /// keys.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use matrix_sdk::Client;
+    /// # use url::Url;
+    /// # async {
+    /// # let homeserver = Url::parse("http://example.com")?;
+    /// # let client = Client::new(homeserver).await?;
+    /// let inventory = client.encryption().secret_storage().inventory().await?;
+    ///
+    /// for (key_id, info) in &inventory.keys {
+    ///     println!("The key {key_id} encrypts the secrets {:?}", info.secrets);
+    /// }
+    /// # anyhow::Ok(()) };
+    /// ```
+    pub async fn inventory(&self) -> Result<SecretStorageInventory> {
+        self.inventory_with_keys(&[]).await
+    }
+
+    /// Collect a [`SecretStorageInventory`], making sure that the keys with
+    /// the given key IDs are part of it.
+    ///
+    /// See [`SecretStorage::inventory()`] for more info.
+    #[instrument(skip_all)]
+    pub async fn inventory_with_keys(&self, key_ids: &[&str]) -> Result<SecretStorageInventory> {
+        let default_key_id = self.fetch_default_key_id().await?;
+
+        let mut inventory =
+            SecretStorageInventory { default_key_id: default_key_id.clone(), ..Default::default() };
+
+        let requested_keys =
+            default_key_id.into_iter().chain(key_ids.iter().map(|k| k.to_string()));
+
+        for key_id in requested_keys {
+            inventory
+                .keys
+                .entry(key_id.clone())
+                .or_insert_with(|| SecretStorageKeyInfo::new(key_id));
+        }
+
+        for secret_name in well_known_secrets() {
+            let Some(content) = self.fetch_secret_content(&secret_name).await? else {
+                continue;
+            };
+
+            for key_id in content.encrypted.into_keys() {
+                inventory
+                    .keys
+                    .entry(key_id.clone())
+                    .or_insert_with(|| SecretStorageKeyInfo::new(key_id))
+                    .secrets
+                    .push(secret_name.clone());
+            }
+        }
+
+        for info in inventory.keys.values_mut() {
+            info.is_default = inventory.default_key_id.as_ref() == Some(&info.key_id);
+
+            // A deleted key has its description replaced with an empty JSON object, treat
+            // such keys, as well as any other invalid key description, as missing.
+            let description = match self.fetch_key_description(&info.key_id).await {
+                Ok(description) => description,
+                Err(SecretStorageError::Json(_)) => None,
+                Err(e) => return Err(e),
+            };
+
+            if let Some(description) = description {
+                info.description_found = true;
+                info.name = description.name;
+                info.has_passphrase = description.passphrase.is_some();
+            }
+        }
+
+        Ok(inventory)
+    }
+
+    /// Create a [`SecretStorageHealthReport`] describing problems with the
+    /// secret storage setup of the user, like a missing default key or secrets
+    /// which are only encrypted using a non-default key.
+    pub async fn health_report(&self) -> Result<SecretStorageHealthReport> {
+        Ok(self.inventory().await?.health_report())
+    }
+
+    /// Set the secret storage key with the given key ID as the default key.
+    ///
+    /// The description of the key needs to exist in the account data of the
+    /// user, otherwise a [`SecretStorageError::MissingKeyInfo`] error will be
+    /// returned.
+    #[instrument(skip(self))]
+    pub async fn set_default_key(&self, key_id: &str) -> Result<()> {
+        let _guard = self.client.locks().open_secret_store_lock.lock().await;
+
+        if self.fetch_key_description(key_id).await?.is_none() {
+            return Err(SecretStorageError::MissingKeyInfo { key_id: Some(key_id.to_owned()) });
+        }
+
+        info!("Setting the new default secret storage key");
+
+        self.client
+            .account()
+            .set_account_data(SecretStorageDefaultKeyEventContent::new(key_id.to_owned()))
+            .await?;
+
+        Ok(())
+    }
+
+    /// Clear the default secret storage key, effectively disabling secret
+    /// storage.
+    ///
+    /// The keys and the secrets they encrypt are left untouched.
+    #[instrument(skip_all)]
+    pub async fn clear_default_key(&self) -> Result<()> {
+        // Why oh why, can't we delete account data events?
+        self.client.account().set_account_data(SecretStorageDisabledContent {}).await?;
+
+        Ok(())
+    }
+
+    /// Delete the secret storage key with the given key ID.
+    ///
+    /// This removes the ciphertexts of the well-known secrets which were
+    /// encrypted using this key, and replaces the description of the key with
+    /// an empty event, since account data events can't be deleted.
+    ///
+    /// **Warning**: Secrets which are not well-known, i.e. not managed by the
+    /// SDK, and which are only encrypted using this key will become
+    /// unrecoverable.
+    ///
+    /// The default key can't be deleted, a
+    /// [`SecretStorageError::DefaultKeyDeletion`] error will be returned in
+    /// that case.
+    #[instrument(skip(self))]
+    pub async fn delete_key(&self, key_id: &str) -> Result<()> {
+        // Hold the lock while we check the default key, otherwise the key could
+        // become the default one before we delete it.
+        let _open_guard = self.client.locks().open_secret_store_lock.lock().await;
+
+        if self.fetch_default_key_id().await?.as_deref() == Some(key_id) {
+            return Err(SecretStorageError::DefaultKeyDeletion { key_id: key_id.to_owned() });
+        }
+
+        // See the documentation for the lock in the `SecretStore::put_secret()` method
+        // for more info.
+        let _guard = self.client.locks().store_secret_lock.lock().await;
+
+        for secret_name in well_known_secrets() {
+            let Some(mut content) = self.fetch_secret_content(&secret_name).await? else {
+                continue;
+            };
+
+            if content.encrypted.remove(key_id).is_some() {
+                info!(?secret_name, "Removing a secret encrypted with the deleted key");
+
+                let event_type = GlobalAccountDataEventType::from(secret_name);
+                let content = Raw::from_json(to_raw_value(&content)?);
+
+                self.client.account().set_account_data_raw(event_type, content).await?;
+            }
+        }
+
+        info!("Deleting the secret storage key description");
+
+        let event_type = GlobalAccountDataEventType::SecretStorageKey(key_id.to_owned());
+        let empty_content = Raw::from_json(to_raw_value(&serde_json::json!({}))?);
+        self.client.account().set_account_data_raw(event_type, empty_content).await?;
+
+        Ok(())
+    }
+
+    /// Delete all the orphaned secret storage keys.
+    ///
+    /// See [`SecretStorageKeyInfo::is_orphaned()`] for a definition of an
+    /// orphaned key.
+    ///
+    /// Returns the key IDs of the deleted keys.
+    pub async fn delete_orphaned_keys(&self) -> Result<Vec<String>> {
+        let inventory = self.inventory().await?;
+        let mut deleted = Vec::new();
+
+        for info in inventory.orphaned_keys() {
+            self.delete_key(&info.key_id).await?;
+            deleted.push(info.key_id.clone());
+        }
+
+        Ok(deleted)
+    }
+
+    /// Fetch the account data event containing the ciphertexts of the given
+    /// secret.
+    ///
+    /// Invalid events are treated as if they don't exist.
+    async fn fetch_secret_content(
+        &self,
+        secret_name: &SecretName,
+    ) -> Result<Option<SecretEventContent>> {
+        let event_type = GlobalAccountDataEventType::from(secret_name.to_owned());
+
+        Ok(self
+            .client
+            .account()
+            .fetch_account_data(event_type)
+            .await?
+            .and_then(|content| content.deserialize_as::<SecretEventContent>().ok()))
+    }
 }
@@ -30,7 +30,7 @@ use tracing::{
 };
 use zeroize::Zeroize;
 
-use super::{DecryptionError, Result};
+use super::{inventory::well_known_secrets, DecryptionError, Result};
 use crate::Client;
 
 #[cfg_attr(doc, aquamarine::aquamarine)]
@@ -110,6 +110,63 @@ impl SecretStore {
         self.key.to_base58()
     }
 
+    /// Get the key ID of the [`SecretStorageKey`] protecting this
+    /// [`SecretStore`].
+    pub fn key_id(&self) -> &str {
+        self.key.key_id()
+    }
+
+    /// Re-encrypt all the well-known secrets this [`SecretStore`] can decrypt
+    /// using the key of the `target` [`SecretStore`].
+    ///
+    /// This is useful to move secrets which were encrypted using an older
+    /// secret storage key to the current default key. The secrets stay
+    /// encrypted using the key of this [`SecretStore`] as well, use
+    /// [`SecretStorage::delete_key()`] to remove the old key once the
+    /// migration is done.
+    ///
+    /// Returns the names of the secrets which have been migrated.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use matrix_sdk::Client;
+    /// # use url::Url;
+    /// # async {
+    /// # let homeserver = Url::parse("http://example.com")?;
+    /// # let client = Client::new(homeserver).await?;
+    /// let secret_storage = client.encryption().secret_storage();
+    ///
+    /// let old_store = secret_storage
+    ///     .open_secret_store_with_key_id("old_key_id", "My old passphrase")
+    ///     .await?;
+    /// let new_store =
+    ///     secret_storage.open_secret_store("My new passphrase").await?;
+    ///
+    /// old_store.migrate_secrets_to(&new_store).await?;
+    /// secret_storage.delete_key(old_store.key_id()).await?;
+    /// # anyhow::Ok(()) };
+    /// ```
+    ///
+    /// [`SecretStorage::delete_key()`]: super::SecretStorage::delete_key
+    #[instrument(skip_all, fields(source_key_id = self.key_id(), target_key_id = target.key_id()))]
+    pub async fn migrate_secrets_to(&self, target: &SecretStore) -> Result<Vec<SecretName>> {
+        let mut migrated = Vec::new();
+
+        for secret_name in well_known_secrets() {
+            if let Some(mut secret) = self.get_secret(secret_name.to_owned()).await? {
+                let ret = target.put_secret(secret_name.to_owned(), &secret).await;
+                secret.zeroize();
+                ret?;
+
+                info!(?secret_name, "Migrated a secret to the new secret storage key");
+                migrated.push(secret_name);
+            }
+        }
+
+        Ok(migrated)
+    }
+
     /// Retrieve a secret from the homeserver's account data
     ///
     /// This method allows you to retrieve a secret from the account data stored
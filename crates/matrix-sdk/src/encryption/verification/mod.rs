@@ -29,11 +29,16 @@
 //!   authentication
 //! string.
 //! * [`QrVerification`] - Interactive verification using QR codes.
+//!
+//! To verify our own device, either with another device or using the recovery
+//! key, the [`SessionVerification`] controller can be used instead of driving
+//! the verification request manually.
 
 #[cfg(feature = "qrcode")]
 mod qrcode;
 mod requests;
 mod sas;
+mod session;
 
 use as_variant::as_variant;
 pub use matrix_sdk_base::crypto::{
@@ -50,6 +55,10 @@ pub use qrcode::QrVerification;
 pub use requests::{VerificationRequest, VerificationRequestState};
 use ruma::RoomId;
 pub use sas::SasVerification;
+pub use session::{
+    SessionVerification, SessionVerificationError, SessionVerificationMethods,
+    SessionVerificationState,
+};
 
 /// An enum over the different verification types the SDK supports.
 #[derive(Debug, Clone)]
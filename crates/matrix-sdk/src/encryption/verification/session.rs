@@ -0,0 +1,593 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A high-level controller to verify our own device.
+//!
+//! A freshly logged in device is not trusted by the user's other devices until
+//! it has been signed by the user's self-signing key. The
+//! [`SessionVerification`] controller unifies the two ways a device can get
+//! this signature:
+//!
+//! * Interactively verifying with another one of the user's devices, using an
+//!   emoji or QR code verification flow.
+//! * Entering the recovery key or passphrase, which imports the private
+//!   cross-signing keys from secret storage.
+
+use std::sync::{Arc, Mutex as StdMutex, Weak};
+
+use eyeball::SharedObservable;
+use futures_core::Stream;
+use futures_util::{pin_mut, StreamExt};
+use matrix_sdk_common::executor::{spawn, JoinHandle};
+use thiserror::Error;
+use tracing::{debug, error, info, instrument, warn};
+
+use super::{CancelInfo, Verification, VerificationRequest, VerificationRequestState};
+use crate::{
+    encryption::{
+        identities::{DeviceUpdates, ManualVerifyError, RequestVerificationError},
+        recovery::RecoveryError,
+    },
+    Client,
+};
+
+/// Error type for the [`SessionVerification`] controller.
+#[derive(Debug, Error)]
+pub enum SessionVerificationError {
+    /// A typical SDK error.
+    #[error(transparent)]
+    Sdk(#[from] crate::Error),
+
+    /// Our own user identity could not be found, cross-signing has not been
+    /// set up for this account.
+    #[error("Our own user identity is missing, cross-signing has not been set up")]
+    MissingIdentity,
+
+    /// The verification request could not be sent.
+    #[error(transparent)]
+    RequestVerification(#[from] RequestVerificationError),
+
+    /// Importing the secrets using the recovery key or passphrase failed.
+    #[error(transparent)]
+    Recovery(#[from] RecoveryError),
+
+    /// Our own device could not be signed with the self-signing key.
+    #[error(transparent)]
+    Signature(#[from] ManualVerifyError),
+
+    /// The self-signing key could not be imported from secret storage, so our
+    /// own device could not be signed.
+    #[error("The self-signing key is missing from secret storage")]
+    MissingSelfSigningKey,
+
+    /// Our own device was signed, but the homeserver doesn't return its
+    /// signature.
+    #[error("Our own device isn't cross-signed after being signed")]
+    DeviceNotSigned,
+}
+
+/// The states the [`SessionVerification`] controller can be in.
+#[derive(Debug, Clone, Default)]
+pub enum SessionVerificationState {
+    /// We didn't yet check if our own device is verified.
+    #[default]
+    Unknown,
+    /// Our own device is not verified, and no verification flow is ongoing.
+    Unverified,
+    /// We requested a verification from our other devices and are waiting for
+    /// one of them to accept the request.
+    WaitingForOtherDevice {
+        /// The request that was sent out to our other devices.
+        request: VerificationRequest,
+    },
+    /// The verification request has been accepted by one of our other devices.
+    ///
+    /// A concrete verification flow can now be started with the
+    /// [`VerificationRequest::start_sas()`] method, or by showing or scanning
+    /// a QR code.
+    RequestAccepted {
+        /// The request that was accepted by the other device.
+        request: VerificationRequest,
+    },
+    /// An interactive verification flow is in progress.
+    Verifying {
+        /// The concrete verification flow, use it to display the emojis or
+        /// the QR code and to confirm the verification.
+        verification: Verification,
+    },
+    /// We are importing the private cross-signing keys from secret storage.
+    Recovering,
+    /// Our own device has been verified.
+    Verified,
+    /// The interactive verification has been cancelled, either by us or by
+    /// the other device.
+    Cancelled(CancelInfo),
+    /// The verification failed, the controller may be used to try again.
+    Failed,
+}
+
+impl SessionVerificationState {
+    /// Is our own device verified.
+    pub fn is_verified(&self) -> bool {
+        matches!(self, Self::Verified)
+    }
+}
+
+/// The ways our own device can be verified.
+///
+/// Returned by [`SessionVerification::available_methods()`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SessionVerificationMethods {
+    /// At least one of our other devices is cross-signed and can verify us
+    /// interactively.
+    pub other_devices: bool,
+    /// Secret storage is set up, the recovery key or passphrase can be used to
+    /// verify us.
+    pub recovery: bool,
+}
+
+impl SessionVerificationMethods {
+    /// Is recovery the only way left to verify our own device.
+    ///
+    /// This is the case if the user logged out of all their other verified
+    /// devices, clients should then directly ask for the recovery key instead
+    /// of offering an interactive verification.
+    pub fn only_recovery(&self) -> bool {
+        self.recovery && !self.other_devices
+    }
+
+    /// Is there no way left to verify our own device.
+    ///
+    /// If this is the case, the only way forward is to reset the
+    /// cross-signing identity.
+    pub fn is_empty(&self) -> bool {
+        !self.recovery && !self.other_devices
+    }
+}
+
+/// A controller that verifies our own device, either interactively with
+/// another one of our devices or using the recovery key.
+///
+/// To get this, use [`Encryption::session_verification()`].
+///
+/// # Examples
+///
+/// ```no_run
+/// # use matrix_sdk::{Client, encryption::verification::SessionVerificationState};
+/// # use url::Url;
+/// # async {
+/// # let homeserver = Url::parse("http://example.com")?;
+/// # let client = Client::new(homeserver).await?;
+/// use futures_util::StreamExt;
+///
+/// let session_verification = client.encryption().session_verification();
+/// let methods = session_verification.available_methods().await?;
+///
+/// if methods.only_recovery() {
+///     session_verification.verify_with_recovery("my recovery key").await?;
+/// } else {
+///     session_verification.request_device_verification().await?;
+///
+///     let mut states = session_verification.state_stream();
+///
+///     while let Some(state) = states.next().await {
+///         match state {
+///             SessionVerificationState::Verifying { verification } => {
+///                 println!("Started a verification flow {verification:?}");
+///             }
+///             SessionVerificationState::Verified => break,
+///             _ => (),
+///         }
+///     }
+/// }
+/// # anyhow::Ok(()) };
+/// ```
+///
+/// [`Encryption::session_verification()`]: crate::encryption::Encryption::session_verification
+#[derive(Debug, Clone)]
+pub struct SessionVerification {
+    inner: Arc<SessionVerificationInner>,
+}
+
+#[derive(Debug)]
+struct SessionVerificationInner {
+    client: Client,
+    state: SharedObservable<SessionVerificationState>,
+    /// The task listening to the changes of the ongoing verification request.
+    request_task: StdMutex<Option<JoinHandle<()>>>,
+}
+
+impl Drop for SessionVerificationInner {
+    fn drop(&mut self) {
+        if let Some(task) = self.request_task.lock().unwrap().take() {
+            task.abort();
+        }
+    }
+}
+
+impl SessionVerification {
+    pub(crate) fn new(client: Client) -> Self {
+        Self {
+            inner: Arc::new(SessionVerificationInner {
+                client,
+                state: Default::default(),
+                request_task: Default::default(),
+            }),
+        }
+    }
+
+    /// Get the current [`SessionVerificationState`].
+    pub fn state(&self) -> SessionVerificationState {
+        self.inner.state.get()
+    }
+
+    /// Get a stream of updates to the [`SessionVerificationState`].
+    ///
+    /// This method will send out the current state as the first update.
+    pub fn state_stream(&self) -> impl Stream<Item = SessionVerificationState> {
+        self.inner.state.subscribe_reset()
+    }
+
+    /// Check if our own device is verified, and update the state accordingly.
+    ///
+    /// This doesn't interrupt an ongoing verification flow.
+    pub async fn refresh_state(&self) -> Result<(), SessionVerificationError> {
+        let is_verified = self.is_own_device_verified().await?;
+
+        self.inner.state.update(|state| {
+            if is_verified {
+                *state = SessionVerificationState::Verified;
+            } else if matches!(
+                state,
+                SessionVerificationState::Unknown | SessionVerificationState::Verified
+            ) {
+                *state = SessionVerificationState::Unverified;
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Figure out which ways are available to verify our own device.
+    ///
+    /// This will make sure that the list of our own devices is up to date and
+    /// check if secret storage has been set up.
+    pub async fn available_methods(
+        &self,
+    ) -> Result<SessionVerificationMethods, SessionVerificationError> {
+        let client = &self.inner.client;
+        let encryption = client.encryption();
+
+        encryption.ensure_initial_key_query().await?;
+
+        let own_user_id = client.user_id().ok_or(crate::Error::AuthenticationRequired)?;
+        let own_device_id = client.device_id().ok_or(crate::Error::AuthenticationRequired)?;
+
+        let other_devices = encryption
+            .get_user_devices(own_user_id)
+            .await?
+            .devices()
+            .any(|d| d.device_id() != own_device_id && d.is_cross_signed_by_owner());
+
+        let recovery = encryption.secret_storage().is_enabled().await?;
+
+        Ok(SessionVerificationMethods { other_devices, recovery })
+    }
+
+    /// Request a verification from our other devices.
+    ///
+    /// The state of the controller will follow the returned
+    /// [`VerificationRequest`]. Once the request has been accepted, a
+    /// verification flow can be started using the request. Flows started by
+    /// the other device will be picked up by the controller as well.
+    #[instrument(skip_all)]
+    pub async fn request_device_verification(
+        &self,
+    ) -> Result<VerificationRequest, SessionVerificationError> {
+        let client = &self.inner.client;
+        let own_user_id = client.user_id().ok_or(crate::Error::AuthenticationRequired)?;
+
+        let identity = client
+            .encryption()
+            .get_user_identity(own_user_id)
+            .await
+            .map_err(crate::Error::from)?
+            .ok_or(SessionVerificationError::MissingIdentity)?;
+
+        let request = identity.request_verification().await?;
+        self.follow_request(request.clone());
+
+        Ok(request)
+    }
+
+    /// Let the controller follow a verification request we received from one
+    /// of our other devices.
+    ///
+    /// The request is accepted if it wasn't already.
+    pub async fn accept_device_verification(
+        &self,
+        request: VerificationRequest,
+    ) -> Result<(), SessionVerificationError> {
+        if !request.is_self_verification() {
+            warn!("Tried to follow a verification request that isn't a self-verification");
+            return Ok(());
+        }
+
+        if !request.we_started() && !request.is_ready() {
+            request.accept().await?;
+        }
+
+        self.follow_request(request);
+
+        Ok(())
+    }
+
+    /// Verify our own device using the recovery key or passphrase.
+    ///
+    /// This imports all the secrets from secret storage, using
+    /// [`Recovery::recover()`], and signs our own device with the imported
+    /// self-signing key. It fails with
+    /// [`SessionVerificationError::MissingSelfSigningKey`] if secret storage
+    /// doesn't contain the self-signing key.
+    ///
+    /// An ongoing interactive verification is cancelled.
+    ///
+    /// [`Recovery::recover()`]: crate::encryption::recovery::Recovery::recover
+    #[instrument(skip_all)]
+    pub async fn verify_with_recovery(
+        &self,
+        recovery_key: &str,
+    ) -> Result<(), SessionVerificationError> {
+        self.cancel().await?;
+        self.inner.state.set(SessionVerificationState::Recovering);
+
+        let result = async {
+            self.inner.client.encryption().recovery().recover(recovery_key).await?;
+            self.sign_own_device().await?;
+
+            if self.refresh_own_device().await? {
+                Ok(())
+            } else {
+                Err(SessionVerificationError::DeviceNotSigned)
+            }
+        }
+        .await;
+
+        match result {
+            Ok(()) => {
+                info!("Our own device has been verified using recovery");
+                self.inner.state.set(SessionVerificationState::Verified);
+                Ok(())
+            }
+            Err(e) => {
+                self.inner.state.set(SessionVerificationState::Failed);
+                Err(e)
+            }
+        }
+    }
+
+    /// Cancel the ongoing interactive verification, if any.
+    ///
+    /// The state becomes [`SessionVerificationState::Cancelled`] if a
+    /// verification was ongoing.
+    pub async fn cancel(&self) -> Result<(), SessionVerificationError> {
+        let task = self.inner.request_task.lock().unwrap().take();
+
+        if let Some(task) = task {
+            task.abort();
+        }
+
+        let cancel_info = match self.inner.state.get() {
+            SessionVerificationState::WaitingForOtherDevice { request }
+            | SessionVerificationState::RequestAccepted { request } => {
+                request.cancel().await?;
+                request.cancel_info()
+            }
+            SessionVerificationState::Verifying { verification } => {
+                match &verification {
+                    Verification::SasV1(sas) => sas.cancel().await?,
+                    #[cfg(feature = "qrcode")]
+                    Verification::QrV1(qr) => qr.cancel().await?,
+                }
+                verification.cancel_info()
+            }
+            _ => None,
+        };
+
+        match cancel_info {
+            Some(cancel_info) => {
+                self.inner.state.set(SessionVerificationState::Cancelled(cancel_info));
+                Ok(())
+            }
+            None => self.refresh_state().await,
+        }
+    }
+
+    fn follow_request(&self, request: VerificationRequest) {
+        // Only keep a weak reference in the task, otherwise the task would keep the
+        // controller alive and would never be aborted.
+        let weak_inner = Arc::downgrade(&self.inner);
+        let mut task = self.inner.request_task.lock().unwrap();
+
+        if let Some(previous) = task.take() {
+            previous.abort();
+        }
+
+        self.inner
+            .state
+            .set(SessionVerificationState::WaitingForOtherDevice { request: request.clone() });
+
+        *task = Some(spawn(async move {
+            let mut changes = request.changes();
+
+            while let Some(state) = changes.next().await {
+                let Some(inner) = weak_inner.upgrade() else {
+                    break;
+                };
+                let this = SessionVerification { inner };
+
+                match state {
+                    VerificationRequestState::Created { .. }
+                    | VerificationRequestState::Requested { .. } => {}
+                    VerificationRequestState::Ready { .. } => {
+                        this.inner.state.set(SessionVerificationState::RequestAccepted {
+                            request: request.clone(),
+                        });
+                    }
+                    VerificationRequestState::Transitioned { verification } => {
+                        this.inner.state.set(SessionVerificationState::Verifying { verification });
+                    }
+                    VerificationRequestState::Done => {
+                        if let Some(devices) = this.on_device_verification_done().await {
+                            // Don't keep the controller alive while waiting.
+                            drop(this);
+                            Self::wait_for_own_device_signature(weak_inner, devices).await;
+                        }
+                        break;
+                    }
+                    VerificationRequestState::Cancelled(cancel_info) => {
+                        debug!(?cancel_info, "The session verification has been cancelled");
+                        this.inner.state.set(SessionVerificationState::Cancelled(cancel_info));
+                        break;
+                    }
+                }
+            }
+        }));
+    }
+
+    /// Handle the end of an interactive verification.
+    ///
+    /// Returns a stream of device updates if our own device isn't cross-signed
+    /// yet, to wait for the signature uploaded by the other device.
+    async fn on_device_verification_done(&self) -> Option<impl Stream<Item = DeviceUpdates>> {
+        // Subscribe before looking at our own device, to not miss its signature.
+        let devices = match self.inner.client.encryption().devices_stream().await {
+            Ok(devices) => devices,
+            Err(e) => {
+                error!("Couldn't listen to the updates of our own device: {e:?}");
+                self.inner.state.set(SessionVerificationState::Failed);
+                return None;
+            }
+        };
+
+        // The other device signs our device once the verification is done, but we
+        // might have received the self-signing key as well, in which case we can
+        // upload the signature ourselves without waiting for the other side.
+        match self.sign_own_device().await {
+            Ok(()) | Err(SessionVerificationError::MissingSelfSigningKey) => {}
+            Err(e) => {
+                error!("Couldn't sign our own device after the verification finished: {e:?}");
+                self.inner.state.set(SessionVerificationState::Failed);
+                return None;
+            }
+        }
+
+        match self.refresh_own_device().await {
+            Ok(true) => {
+                info!("Our own device has been verified using another device");
+                self.inner.state.set(SessionVerificationState::Verified);
+                None
+            }
+            Ok(false) => {
+                debug!("Waiting for the other device to sign our own device");
+                Some(devices)
+            }
+            Err(e) => {
+                error!("Couldn't check our own device after the verification finished: {e:?}");
+                self.inner.state.set(SessionVerificationState::Failed);
+                None
+            }
+        }
+    }
+
+    /// Wait until our own device is cross-signed, and set the state to
+    /// [`SessionVerificationState::Verified`].
+    async fn wait_for_own_device_signature(
+        weak_inner: Weak<SessionVerificationInner>,
+        devices: impl Stream<Item = DeviceUpdates>,
+    ) {
+        pin_mut!(devices);
+
+        while devices.next().await.is_some() {
+            let Some(inner) = weak_inner.upgrade() else {
+                break;
+            };
+            let this = SessionVerification { inner };
+
+            match this.is_own_device_verified().await {
+                Ok(true) => {
+                    info!("Our own device has been verified using another device");
+                    this.inner.state.set(SessionVerificationState::Verified);
+                    break;
+                }
+                Ok(false) => {}
+                Err(e) => {
+                    error!("Couldn't check our own device after the verification finished: {e:?}");
+                    this.inner.state.set(SessionVerificationState::Failed);
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Sign our own device with the self-signing key, if the device isn't
+    /// signed yet.
+    ///
+    /// Fails with [`SessionVerificationError::MissingSelfSigningKey`] if we
+    /// don't have the self-signing key.
+    async fn sign_own_device(&self) -> Result<(), SessionVerificationError> {
+        let encryption = self.inner.client.encryption();
+
+        let has_self_signing_key = encryption
+            .cross_signing_status()
+            .await
+            .map(|status| status.has_self_signing)
+            .unwrap_or_default();
+
+        if !has_self_signing_key {
+            return Err(SessionVerificationError::MissingSelfSigningKey);
+        }
+
+        if let Some(device) = encryption.get_own_device().await.map_err(crate::Error::from)? {
+            if !device.is_cross_signed_by_owner() {
+                device.verify().await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Fetch our own device keys from the homeserver, to get the latest
+    /// signatures of our own device, and check if it is verified.
+    async fn refresh_own_device(&self) -> Result<bool, SessionVerificationError> {
+        let client = &self.inner.client;
+        let own_user_id = client.user_id().ok_or(crate::Error::AuthenticationRequired)?;
+
+        client.encryption().request_user_identity(own_user_id).await?;
+
+        self.is_own_device_verified().await
+    }
+
+    async fn is_own_device_verified(&self) -> Result<bool, SessionVerificationError> {
+        Ok(self
+            .inner
+            .client
+            .encryption()
+            .get_own_device()
+            .await
+            .map_err(crate::Error::from)?
+            .map(|device| device.is_cross_signed_by_owner())
+            .unwrap_or_default())
+    }
+}
@@ -599,6 +599,72 @@ impl<const CAP: usize, Item, Gap> LinkedChunk<CAP, Item, Gap> {
         )
     }
 
+    /// Remove all the chunks before the chunk identified by
+    /// `chunk_identifier`.
+    ///
+    /// The first chunk must always be an items chunk, so it is kept, but its
+    /// items are removed.
+    ///
+    /// Because the `chunk_identifier` can be invalid, this method returns a
+    /// `Result`. It returns the number of removed items.
+    pub fn remove_chunks_before(
+        &mut self,
+        chunk_identifier: ChunkIdentifier,
+    ) -> Result<usize, Error> {
+        let chunk = self
+            .links
+            .chunk(chunk_identifier)
+            .ok_or(Error::InvalidChunkIdentifier { identifier: chunk_identifier })?;
+
+        if chunk.is_first_chunk() {
+            return Ok(0);
+        }
+
+        let mut number_of_removed_items = 0;
+
+        // Unlink and drop the chunks between the first chunk and `chunk`. `chunk` is
+        // never removed, so `self.links.last` doesn't need to be updated.
+        let mut next_ptr = self.links.first_chunk().next;
+
+        while let Some(mut chunk_ptr) = next_ptr {
+            // SAFETY: `chunk_ptr` is taken from the links of the `LinkedChunk`, so it is
+            // valid.
+            let chunk = unsafe { chunk_ptr.as_mut() };
+
+            if chunk.identifier() == chunk_identifier {
+                break;
+            }
+
+            next_ptr = chunk.next;
+            number_of_removed_items += chunk.len();
+            chunk.unlink(&mut self.updates);
+
+            // SAFETY: `chunk` is unlinked and not borrowed anymore. It is time to re-`Box`
+            // it and drop it.
+            let _chunk_boxed = unsafe { Box::from_raw(chunk_ptr.as_ptr()) };
+        }
+
+        // Empty the first chunk.
+        // SAFETY: the first chunk is always valid, and nothing else borrows it.
+        let first_chunk = unsafe { self.links.first.as_mut() };
+        let first_identifier = first_chunk.identifier();
+
+        if let ChunkContent::Items(items) = &mut first_chunk.content {
+            if !items.is_empty() {
+                number_of_removed_items += items.len();
+                items.clear();
+
+                if let Some(updates) = self.updates.as_mut() {
+                    updates.push(Update::DetachLastItems { at: Position(first_identifier, 0) });
+                }
+            }
+        }
+
+        self.length -= number_of_removed_items;
+
+        Ok(number_of_removed_items)
+    }
+
     /// Search backwards for a chunk, and return its identifier.
     pub fn chunk_identifier<'a, P>(&'a self, mut predicate: P) -> Option<ChunkIdentifier>
     where
@@ -2123,6 +2189,54 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_remove_chunks_before() -> Result<(), Error> {
+        use super::Update::*;
+
+        let mut linked_chunk = LinkedChunk::<3, char, ()>::new_with_update_history();
+        linked_chunk.push_items_back(['a', 'b', 'c', 'd']);
+        linked_chunk.push_gap_back(());
+        linked_chunk.push_items_back(['e', 'f']);
+        linked_chunk.push_gap_back(());
+        linked_chunk.push_items_back(['g']);
+        assert_items_eq!(linked_chunk, ['a', 'b', 'c'] ['d'] [-] ['e', 'f'] [-] ['g']);
+        let _ = linked_chunk.updates().unwrap().take();
+
+        // Removing the chunks before the first chunk does nothing.
+        assert_eq!(linked_chunk.remove_chunks_before(ChunkIdentifier(0))?, 0);
+        assert_items_eq!(linked_chunk, ['a', 'b', 'c'] ['d'] [-] ['e', 'f'] [-] ['g']);
+        assert!(linked_chunk.updates().unwrap().take().is_empty());
+
+        // Remove the chunks before the last gap.
+        let gap_identifier = linked_chunk.chunk_identifier(Chunk::is_gap).unwrap();
+        assert_eq!(gap_identifier, ChunkIdentifier(4));
+
+        assert_eq!(linked_chunk.remove_chunks_before(gap_identifier)?, 6);
+        assert_items_eq!(linked_chunk, [] [-] ['g']);
+        assert_eq!(linked_chunk.len(), 1);
+        assert_eq!(
+            linked_chunk.updates().unwrap().take(),
+            &[
+                RemoveChunk(ChunkIdentifier(1)),
+                RemoveChunk(ChunkIdentifier(2)),
+                RemoveChunk(ChunkIdentifier(3)),
+                DetachLastItems { at: Position(ChunkIdentifier(0), 0) },
+            ]
+        );
+
+        // New items are still pushed at the end.
+        linked_chunk.push_items_back(['h']);
+        assert_items_eq!(linked_chunk, [] [-] ['g', 'h']);
+
+        // An unknown chunk is an error.
+        assert_matches!(
+            linked_chunk.remove_chunks_before(ChunkIdentifier(42)),
+            Err(Error::InvalidChunkIdentifier { identifier: ChunkIdentifier(42) })
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn test_chunk_item_positions() {
         let mut linked_chunk = LinkedChunk::<3, char, ()>::new();
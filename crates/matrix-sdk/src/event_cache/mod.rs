@@ -33,7 +33,8 @@
 //! - [ ] provide read receipts for each message.
 //! - [x] backwards pagination
 //! - [~] forward pagination
-//! - [ ] reconcile results with cached timelines.
+//! - [~] reconcile results with cached timelines: limited syncs leave a gap
+//!   that can be filled with [`RoomPagination::fill_gap`].
 //! - [ ] retry decryption upon receiving new keys (from an encryption sync
 //!   service or from a key backup).
 //! - [ ] expose the latest event for a given room.
@@ -42,9 +43,12 @@
 #![forbid(missing_docs)]
 
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, HashSet},
     fmt::Debug,
-    sync::{Arc, OnceLock},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, OnceLock,
+    },
 };
 
 use eyeball::Subscriber;
@@ -65,6 +69,7 @@ use tokio::sync::{
 use tracing::{error, info_span, instrument, trace, warn, Instrument as _, Span};
 
 use self::{
+    linked_chunk::ChunkContent,
     pagination::RoomPaginationData,
     paginator::{Paginator, PaginatorError},
     store::{Gap, RoomEvents},
@@ -76,7 +81,11 @@ mod pagination;
 mod store;
 
 pub mod paginator;
-pub use pagination::{RoomPagination, TimelineHasBeenResetWhilePaginating};
+pub use pagination::{GapFillOutcome, RoomPagination, TimelineHasBeenResetWhilePaginating};
+
+/// The number of events of a room above which the oldest events, those
+/// before a gap, are evicted from memory.
+const MAX_IN_MEMORY_EVENTS: usize = 1024;
 
 /// An error observed in the [`EventCache`].
 #[derive(thiserror::Error, Debug)]
@@ -292,6 +301,42 @@ impl EventCache {
 
         Ok(())
     }
+
+    /// Seed the cache of a room that hasn't been synced yet with events
+    /// persisted by a previous session, like the latest event of the room.
+    ///
+    /// Nothing happens if the cache of the room already has events. When a
+    /// sync of the room contains the seeded events, they are deduplicated and
+    /// only the following events are added. Otherwise, the synced events are
+    /// added after the seeded ones, after a gap if the sync is limited. As long
+    /// as there is no back-pagination token before the seeded events, a
+    /// back-pagination replaces them.
+    #[instrument(skip(self, events))]
+    pub async fn seed_events(
+        &self,
+        room_id: &RoomId,
+        events: Vec<SyncTimelineEvent>,
+    ) -> Result<()> {
+        let Some(room_cache) = self.inner.for_room(room_id).await? else {
+            warn!("unknown room, skipping");
+            return Ok(());
+        };
+
+        let mut room_events = room_cache.inner.events.write().await;
+        if events.is_empty() || room_events.events().next().is_some() {
+            return Ok(());
+        }
+
+        room_events.push_events(events.clone());
+        room_cache.inner.seeded.store(true, Ordering::SeqCst);
+
+        let _ = room_cache
+            .inner
+            .sender
+            .send(RoomEventCacheUpdate::AddTimelineEvents { events, origin: EventsOrigin::Cache });
+
+        Ok(())
+    }
 }
 
 struct EventCacheInner {
@@ -446,6 +491,58 @@ impl RoomEventCache {
     pub fn pagination(&self) -> RoomPagination {
         RoomPagination { inner: self.inner.clone() }
     }
+
+    /// Get the gaps in the middle of the known events of this room.
+    ///
+    /// A gap is created when a sync is limited: the events received before it
+    /// are kept, and a gap separates them from the new events. Gaps are ordered
+    /// from the oldest to the most recent one.
+    pub async fn gaps(&self) -> Vec<RoomEventCacheGap> {
+        let room_events = self.inner.events.read().await;
+
+        let mut gaps = Vec::new();
+        let mut seen_events = false;
+        let mut pending_gap = None;
+
+        for chunk in room_events.chunks() {
+            match chunk.content() {
+                ChunkContent::Gap(gap) => {
+                    // A gap before any event is where back-pagination starts, not a hole in
+                    // the history.
+                    if seen_events {
+                        pending_gap = Some(gap.prev_token.clone());
+                    }
+                }
+                ChunkContent::Items(events) => {
+                    let Some(first_event) = events.first() else { continue };
+                    seen_events = true;
+
+                    if let Some(prev_token) = pending_gap.take() {
+                        gaps.push(RoomEventCacheGap {
+                            prev_token,
+                            next_event_id: first_event.event_id(),
+                        });
+                    }
+                }
+            }
+        }
+
+        if let Some(prev_token) = pending_gap {
+            gaps.push(RoomEventCacheGap { prev_token, next_event_id: None });
+        }
+
+        gaps
+    }
+}
+
+/// A hole in the known history of a room, between two sets of events.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RoomEventCacheGap {
+    /// The token to back-paginate from, to get the missing events.
+    pub prev_token: String,
+
+    /// The ID of the first event after the gap, if there's any.
+    pub next_event_id: Option<OwnedEventId>,
 }
 
 /// The (non-clonable) details of the `RoomEventCache`.
@@ -456,6 +553,9 @@ struct RoomEventCacheInner {
     /// The events of the room.
     events: RwLock<RoomEvents>,
 
+    /// The room these events belong to, used to fill gaps.
+    weak_room: WeakRoom,
+
     /// A paginator instance, that's configured to run back-pagination on our
     /// behalf.
     ///
@@ -468,6 +568,11 @@ struct RoomEventCacheInner {
     /// It's protected behind a lock to avoid multiple accesses to the paginator
     /// at the same time.
     pagination: RoomPaginationData,
+
+    /// Whether the events of the room have been seeded with
+    /// [`EventCache::seed_events`], and there is no back-pagination token
+    /// before them yet.
+    seeded: AtomicBool,
 }
 
 impl RoomEventCacheInner {
@@ -482,15 +587,18 @@ impl RoomEventCacheInner {
             events: RwLock::new(RoomEvents::default()),
             sender,
             pagination: RoomPaginationData {
-                paginator: Paginator::new(Box::new(weak_room)),
+                paginator: Paginator::new(Box::new(weak_room.clone())),
                 waited_for_initial_prev_token: Mutex::new(false),
                 token_notifier: Default::default(),
             },
+            weak_room,
+            seeded: AtomicBool::new(false),
         }
     }
 
     async fn clear(&self, room_events: &mut RwLockWriteGuard<'_, RoomEvents>) {
         room_events.reset();
+        self.seeded.store(false, Ordering::SeqCst);
 
         // Reset the back-pagination state to the initial too.
         *self.pagination.waited_for_initial_prev_token.lock().await = false;
@@ -552,33 +660,74 @@ impl RoomEventCacheInner {
         ephemeral_events: Vec<Raw<AnySyncEphemeralRoomEvent>>,
         ambiguity_changes: BTreeMap<OwnedEventId, AmbiguityChange>,
     ) -> Result<()> {
+        let mut room_events = self.events.write().await;
+
+        // If the synced events contain the seeded ones, they can take their place,
+        // along with the token to back-paginate from them. Otherwise, the seeded
+        // events are kept, and the synced ones are added after them like for any
+        // other room.
+        if self.seeded.load(Ordering::SeqCst) {
+            let known_event_ids: HashSet<_> =
+                room_events.events().filter_map(|(_, event)| event.event_id()).collect();
+            let oldest_event_id =
+                room_events.events().next().and_then(|(_, event)| event.event_id());
+
+            let contains_seeded_events = oldest_event_id.is_some_and(|oldest_event_id| {
+                timeline
+                    .events
+                    .iter()
+                    .any(|event| event.event_id().as_ref() == Some(&oldest_event_id))
+            });
+
+            if contains_seeded_events {
+                trace!("the synced events contain the seeded ones, deduplicating them");
+
+                self.seeded.store(false, Ordering::SeqCst);
+
+                room_events.reset();
+                if let Some(prev_token) = &timeline.prev_batch {
+                    room_events.push_gap(Gap { prev_token: prev_token.clone() });
+                }
+                room_events.push_events(timeline.events.clone());
+                drop(room_events);
+
+                if timeline.prev_batch.is_some() {
+                    self.pagination.token_notifier.notify_one();
+                }
+
+                // Observers already have the seeded events, only send them the events that
+                // follow. If older events come before the seeded ones, all the events are
+                // sent and observers deduplicate the seeded ones by their ID.
+                let new_events = timeline
+                    .events
+                    .into_iter()
+                    .skip_while(|event| {
+                        event.event_id().is_some_and(|event_id| known_event_ids.contains(&event_id))
+                    })
+                    .collect();
+
+                self.notify_observers(None, new_events, ephemeral_events, ambiguity_changes);
+
+                return Ok(());
+            }
+        }
+
         if timeline.limited {
-            // Ideally we'd try to reconcile existing events against those received in the
-            // timeline, but we're not there yet. In the meanwhile, clear the
-            // items from the room. TODO: implement Smart Matching™.
-            trace!("limited timeline, clearing all previous events and pushing new events");
-
-            self.replace_all_events_by(
-                timeline.events,
-                timeline.prev_batch,
-                ephemeral_events,
-                ambiguity_changes,
-            )
-            .await?;
+            // Keep the events we already know about: the new events are added after a
+            // gap, that can be filled later by back-paginating from it.
+            trace!("limited timeline, pushing a gap and new events");
         } else {
-            // Add all the events to the backend.
             trace!("adding new events");
-
-            self.append_new_events(
-                timeline.events,
-                timeline.prev_batch,
-                ephemeral_events,
-                ambiguity_changes,
-            )
-            .await?;
         }
 
-        Ok(())
+        self.append_events_locked_impl(
+            room_events,
+            timeline.events,
+            timeline.prev_batch,
+            timeline.limited,
+            ephemeral_events,
+            ambiguity_changes,
+        )
     }
 
     async fn handle_left_room_update(&self, updates: LeftRoomUpdate) -> Result<()> {
@@ -609,24 +758,7 @@ impl RoomEventCacheInner {
             room_events,
             sync_timeline_events,
             prev_batch,
-            ephemeral_events,
-            ambiguity_changes,
-        )
-    }
-
-    /// Append a set of events to the room cache and storage, notifying
-    /// observers.
-    async fn append_new_events(
-        &self,
-        sync_timeline_events: Vec<SyncTimelineEvent>,
-        prev_batch: Option<String>,
-        ephemeral_events: Vec<Raw<AnySyncEphemeralRoomEvent>>,
-        ambiguity_changes: BTreeMap<OwnedEventId, AmbiguityChange>,
-    ) -> Result<()> {
-        self.append_events_locked_impl(
-            self.events.write().await,
-            sync_timeline_events,
-            prev_batch,
+            false,
             ephemeral_events,
             ambiguity_changes,
         )
@@ -634,14 +766,18 @@ impl RoomEventCacheInner {
 
     /// Append a set of events, with an attached lock.
     ///
-    /// If the lock `room_events` is `None`, one will be created.
+    /// If the events come from a `limited` sync and the room already has
+    /// events, a gap is inserted between the known events and the new ones,
+    /// unless they overlap. The oldest events are then evicted if the room has
+    /// too many events, see [`Self::evict_events_before_gaps`].
     ///
     /// This is a private implementation. It must not be exposed publicly.
     fn append_events_locked_impl(
         &self,
         mut room_events: RwLockWriteGuard<'_, RoomEvents>,
-        sync_timeline_events: Vec<SyncTimelineEvent>,
+        mut sync_timeline_events: Vec<SyncTimelineEvent>,
         prev_batch: Option<String>,
+        limited: bool,
         ephemeral_events: Vec<Raw<AnySyncEphemeralRoomEvent>>,
         ambiguity_changes: BTreeMap<OwnedEventId, AmbiguityChange>,
     ) -> Result<()> {
@@ -653,43 +789,127 @@ impl RoomEventCacheInner {
             return Ok(());
         }
 
-        // Add the previous back-pagination token (if present), followed by the timeline
-        // events themselves.
+        let has_events = room_events.events().next().is_some();
+
+        // If a limited sync contains events we already know, there's no hole in the
+        // history: only keep the new events. The sync returns the most recent events
+        // of the room, so only the same number of our most recent events can overlap
+        // with them.
+        let mut is_gap = false;
+        if limited && has_events {
+            let known_event_ids: HashSet<_> = room_events
+                .revents()
+                .take(sync_timeline_events.len())
+                .filter_map(|(_, event)| event.event_id())
+                .collect();
+
+            let num_events = sync_timeline_events.len();
+            sync_timeline_events.retain(|event| {
+                event.event_id().map_or(true, |event_id| !known_event_ids.contains(&event_id))
+            });
+
+            is_gap = sync_timeline_events.len() == num_events;
+        }
+
+        // Add the previous back-pagination token, followed by the timeline events
+        // themselves. The token is only useful at the start of the room, or if there's
+        // a gap, otherwise the events follow the ones we already have.
+        let gap_token = prev_batch.filter(|_| !has_events || is_gap);
+
         {
-            if let Some(prev_token) = &prev_batch {
+            if let Some(prev_token) = &gap_token {
                 room_events.push_gap(Gap { prev_token: prev_token.clone() });
             }
 
             room_events.push_events(sync_timeline_events.clone());
         }
 
+        self.evict_events_before_gaps(&mut room_events);
+
         // Now that all events have been added, we can trigger the
         // `pagination_token_notifier`.
-        if prev_batch.is_some() {
+        if gap_token.is_some() && !has_events {
             self.pagination.token_notifier.notify_one();
         }
 
-        // The order of `RoomEventCacheUpdate`s is **really** important here.
-        {
-            if !sync_timeline_events.is_empty() {
-                let _ = self.sender.send(RoomEventCacheUpdate::AddTimelineEvents {
-                    events: sync_timeline_events,
-                    origin: EventsOrigin::Sync,
-                });
-            }
+        self.notify_observers(
+            gap_token.filter(|_| has_events),
+            sync_timeline_events,
+            ephemeral_events,
+            ambiguity_changes,
+        );
 
-            if !ephemeral_events.is_empty() {
-                let _ = self
-                    .sender
-                    .send(RoomEventCacheUpdate::AddEphemeralEvents { events: ephemeral_events });
-            }
+        Ok(())
+    }
+
+    /// Evict the oldest events from memory, while the room has more than
+    /// [`MAX_IN_MEMORY_EVENTS`] events.
+    ///
+    /// Only the events before a gap in the middle of the known events are
+    /// evicted, oldest first: this gap then becomes the start of the known
+    /// history, from which it is possible to back-paginate again. Nothing is
+    /// evicted while the room is observed, since a live timeline still shows
+    /// these events and back-paginates from the oldest one.
+    fn evict_events_before_gaps(&self, room_events: &mut RoomEvents) {
+        if self.sender.receiver_count() > 0 {
+            return;
+        }
+
+        while room_events.len() > MAX_IN_MEMORY_EVENTS {
+            // Find the first gap after some events.
+            let mut seen_events = false;
+            let gap_identifier = room_events.chunks().find_map(|chunk| match chunk.content() {
+                ChunkContent::Gap(..) => seen_events.then(|| chunk.identifier()),
+                ChunkContent::Items(events) => {
+                    seen_events |= !events.is_empty();
+                    None
+                }
+            });
+
+            let Some(gap_identifier) = gap_identifier else {
+                break;
+            };
 
-            if !ambiguity_changes.is_empty() {
-                let _ = self.sender.send(RoomEventCacheUpdate::UpdateMembers { ambiguity_changes });
+            match room_events.remove_chunks_before(gap_identifier) {
+                Ok(number_of_events) => trace!(number_of_events, "evicted events before a gap"),
+                Err(error) => {
+                    error!("Failed to evict events before a gap: {error}");
+                    break;
+                }
             }
         }
+    }
 
-        Ok(())
+    /// Propagate the updates of the room to observers, in order: first the gap
+    /// found after the known events, then the new events.
+    fn notify_observers(
+        &self,
+        gap_token: Option<String>,
+        sync_timeline_events: Vec<SyncTimelineEvent>,
+        ephemeral_events: Vec<Raw<AnySyncEphemeralRoomEvent>>,
+        ambiguity_changes: BTreeMap<OwnedEventId, AmbiguityChange>,
+    ) {
+        // The order of `RoomEventCacheUpdate`s is **really** important here.
+        if let Some(prev_token) = gap_token {
+            let _ = self.sender.send(RoomEventCacheUpdate::AddGap { prev_token });
+        }
+
+        if !sync_timeline_events.is_empty() {
+            let _ = self.sender.send(RoomEventCacheUpdate::AddTimelineEvents {
+                events: sync_timeline_events,
+                origin: EventsOrigin::Sync,
+            });
+        }
+
+        if !ephemeral_events.is_empty() {
+            let _ = self
+                .sender
+                .send(RoomEventCacheUpdate::AddEphemeralEvents { events: ephemeral_events });
+        }
+
+        if !ambiguity_changes.is_empty() {
+            let _ = self.sender.send(RoomEventCacheUpdate::UpdateMembers { ambiguity_changes });
+        }
     }
 }
 
@@ -731,6 +951,17 @@ pub enum RoomEventCacheUpdate {
         ambiguity_changes: BTreeMap<OwnedEventId, AmbiguityChange>,
     },
 
+    /// A gap has been found after the known events of the room, because a sync
+    /// was limited.
+    ///
+    /// The events following the gap are sent in the next
+    /// [`RoomEventCacheUpdate::AddTimelineEvents`]. The gap can be filled with
+    /// [`RoomPagination::fill_gap`].
+    AddGap {
+        /// The token to back-paginate from, to fill the gap.
+        prev_token: String,
+    },
+
     /// The room has received new timeline events.
     AddTimelineEvents {
         /// All the new events that have been added to the room's timeline.
@@ -753,19 +984,23 @@ pub enum RoomEventCacheUpdate {
 pub enum EventsOrigin {
     /// Events are coming from a sync.
     Sync,
+
+    /// Events are coming from a previous session, see
+    /// [`EventCache::seed_events`].
+    Cache,
 }
 
 #[cfg(test)]
 mod tests {
     use assert_matches2::assert_matches;
     use futures_util::FutureExt as _;
-    use matrix_sdk_base::sync::JoinedRoomUpdate;
+    use matrix_sdk_base::sync::{JoinedRoomUpdate, Timeline};
     use matrix_sdk_test::async_test;
-    use ruma::{room_id, serde::Raw};
+    use ruma::{event_id, room_id, serde::Raw, user_id, EventId};
     use serde_json::json;
 
-    use super::{EventCacheError, RoomEventCacheUpdate};
-    use crate::test_utils::logged_in_client;
+    use super::{EventCacheError, EventsOrigin, RoomEventCacheUpdate, MAX_IN_MEMORY_EVENTS};
+    use crate::test_utils::{events::EventFactory, logged_in_client};
 
     #[async_test]
     async fn test_must_explicitly_subscribe() {
@@ -828,4 +1063,179 @@ mod tests {
 
         assert!(stream.recv().now_or_never().is_none());
     }
+
+    #[async_test]
+    async fn test_seeded_events_are_deduplicated_by_sync() {
+        let client = logged_in_client(None).await;
+        let room_id = room_id!("!galette:saucisse.bzh");
+        client.base_client().get_or_create_room(room_id, matrix_sdk_base::RoomState::Joined);
+
+        let event_cache = client.event_cache();
+        event_cache.subscribe().unwrap();
+
+        let (room_event_cache, _drop_handles) = event_cache.for_room(room_id).await.unwrap();
+        let room_event_cache = room_event_cache.unwrap();
+
+        let f = EventFactory::new().room(room_id).sender(user_id!("@ben:saucisse.bzh"));
+        let latest_event = f.text_msg("latest").event_id(event_id!("$latest")).into_sync();
+
+        // The latest event of the room is seeded…
+        event_cache.seed_events(room_id, vec![latest_event.clone()]).await.unwrap();
+
+        let (events, mut stream) = room_event_cache.subscribe().await.unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].event_id().as_deref(), Some(event_id!("$latest")));
+
+        // … but not twice.
+        event_cache.seed_events(room_id, vec![latest_event.clone()]).await.unwrap();
+        assert!(stream.recv().now_or_never().is_none());
+
+        // When the room is synced with the seeded event, only the new events are
+        // added, without clearing the room first, with the token to paginate
+        // backwards from them.
+        let timeline = Timeline {
+            limited: true,
+            prev_batch: Some("prev".to_owned()),
+            events: vec![latest_event, f.text_msg("new").event_id(event_id!("$new")).into_sync()],
+        };
+        room_event_cache
+            .inner
+            .handle_joined_room_update(JoinedRoomUpdate { timeline, ..Default::default() })
+            .await
+            .unwrap();
+
+        assert_matches!(
+            stream.recv().await.unwrap(),
+            RoomEventCacheUpdate::AddTimelineEvents { events, origin: EventsOrigin::Sync }
+        );
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].event_id().as_deref(), Some(event_id!("$new")));
+        assert!(stream.recv().now_or_never().is_none());
+
+        let (events, _) = room_event_cache.subscribe().await.unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(
+            room_event_cache.pagination().get_or_wait_for_token().await.as_deref(),
+            Some("prev")
+        );
+    }
+
+    #[async_test]
+    async fn test_seeded_events_are_kept_before_a_gap_by_limited_sync() {
+        let client = logged_in_client(None).await;
+        let room_id = room_id!("!galette:saucisse.bzh");
+        client.base_client().get_or_create_room(room_id, matrix_sdk_base::RoomState::Joined);
+
+        let event_cache = client.event_cache();
+        event_cache.subscribe().unwrap();
+
+        let (room_event_cache, _drop_handles) = event_cache.for_room(room_id).await.unwrap();
+        let room_event_cache = room_event_cache.unwrap();
+
+        let f = EventFactory::new().room(room_id).sender(user_id!("@ben:saucisse.bzh"));
+        let latest_event = f.text_msg("latest").event_id(event_id!("$latest")).into_sync();
+        event_cache.seed_events(room_id, vec![latest_event]).await.unwrap();
+
+        let (_, mut stream) = room_event_cache.subscribe().await.unwrap();
+
+        // When the room is synced without the seeded event, the new events are added
+        // after a gap.
+        let timeline = Timeline {
+            limited: true,
+            prev_batch: Some("prev".to_owned()),
+            events: vec![f.text_msg("new").event_id(event_id!("$new")).into_sync()],
+        };
+        room_event_cache
+            .inner
+            .handle_joined_room_update(JoinedRoomUpdate { timeline, ..Default::default() })
+            .await
+            .unwrap();
+
+        assert_matches!(stream.recv().await.unwrap(), RoomEventCacheUpdate::AddGap { prev_token });
+        assert_eq!(prev_token, "prev");
+        assert_matches!(
+            stream.recv().await.unwrap(),
+            RoomEventCacheUpdate::AddTimelineEvents { events, origin: EventsOrigin::Sync }
+        );
+        assert_eq!(events.len(), 1);
+        assert!(stream.recv().now_or_never().is_none());
+
+        let (events, _) = room_event_cache.subscribe().await.unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].event_id().as_deref(), Some(event_id!("$latest")));
+    }
+
+    #[async_test]
+    async fn test_oldest_events_are_evicted_before_a_gap() {
+        let client = logged_in_client(None).await;
+        let room_id = room_id!("!galette:saucisse.bzh");
+        client.base_client().get_or_create_room(room_id, matrix_sdk_base::RoomState::Joined);
+
+        let event_cache = client.event_cache();
+        event_cache.subscribe().unwrap();
+
+        let (room_event_cache, _drop_handles) = event_cache.for_room(room_id).await.unwrap();
+        let room_event_cache = room_event_cache.unwrap();
+
+        let f = EventFactory::new().room(room_id).sender(user_id!("@ben:saucisse.bzh"));
+        let sync = |limited: bool, prev_batch: &str, event_ids: &[&str]| Timeline {
+            limited,
+            prev_batch: Some(prev_batch.to_owned()),
+            events: event_ids
+                .iter()
+                .map(|event_id| {
+                    f.text_msg("msg").event_id(&EventId::parse(event_id).unwrap()).into_sync()
+                })
+                .collect(),
+        };
+
+        // The room has more events than what is kept in memory, but they have no gap.
+        let event_ids: Vec<_> = (0..=MAX_IN_MEMORY_EVENTS).map(|i| format!("$old{i}")).collect();
+        let event_ids: Vec<_> = event_ids.iter().map(String::as_str).collect();
+        room_event_cache
+            .inner
+            .handle_joined_room_update(JoinedRoomUpdate {
+                timeline: sync(false, "start", &event_ids),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        let (events, stream) = room_event_cache.subscribe().await.unwrap();
+        assert_eq!(events.len(), MAX_IN_MEMORY_EVENTS + 1);
+
+        // Nothing is evicted while the room is observed.
+        room_event_cache
+            .inner
+            .handle_joined_room_update(JoinedRoomUpdate {
+                timeline: sync(true, "gap", &["$new"]),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        let (events, _) = room_event_cache.subscribe().await.unwrap();
+        assert_eq!(events.len(), MAX_IN_MEMORY_EVENTS + 2);
+
+        // Once it isn't observed anymore, the events before the oldest gap are
+        // evicted, and back-pagination starts from this gap.
+        drop(stream);
+        room_event_cache
+            .inner
+            .handle_joined_room_update(JoinedRoomUpdate {
+                timeline: sync(true, "other_gap", &["$newer"]),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        let (events, _) = room_event_cache.subscribe().await.unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].event_id().as_deref(), Some(event_id!("$new")));
+        assert_eq!(events[1].event_id().as_deref(), Some(event_id!("$newer")));
+        assert_eq!(
+            room_event_cache.pagination().get_or_wait_for_token().await.as_deref(),
+            Some("gap")
+        );
+    }
 }
@@ -14,10 +14,17 @@
 
 //! A sub-object for running pagination tasks on a given room.
 
-use std::{future::Future, ops::ControlFlow, sync::Arc, time::Duration};
+use std::{
+    collections::HashSet,
+    future::Future,
+    ops::ControlFlow,
+    sync::{atomic::Ordering, Arc},
+    time::Duration,
+};
 
 use eyeball::Subscriber;
-use matrix_sdk_base::deserialized_responses::SyncTimelineEvent;
+use matrix_sdk_base::deserialized_responses::{SyncTimelineEvent, TimelineEvent};
+use ruma::OwnedEventId;
 use tokio::{
     sync::{Mutex, Notify, RwLockReadGuard},
     time::timeout,
@@ -27,9 +34,12 @@ use tracing::{debug, instrument, trace};
 use super::{
     paginator::{PaginationResult, Paginator, PaginatorState},
     store::Gap,
-    BackPaginationOutcome, Result, RoomEventCacheInner,
+    BackPaginationOutcome, EventCacheError, Result, RoomEventCacheInner, RoomEventCacheUpdate,
+};
+use crate::event_cache::{
+    linked_chunk::{ChunkContent, ChunkIdentifier},
+    store::RoomEvents,
 };
-use crate::event_cache::{linked_chunk::ChunkContent, store::RoomEvents};
 
 #[derive(Debug)]
 pub(super) struct RoomPaginationData {
@@ -136,6 +146,14 @@ impl RoomPagination {
         // Make sure there's at most one back-pagination request.
         let prev_token = self.get_or_wait_for_token().await;
 
+        // Without a token, the pagination starts from the end of the timeline, so its
+        // result replaces the events seeded from a previous session.
+        if prev_token.is_none() && self.inner.seeded.swap(false, Ordering::SeqCst) {
+            let mut room_events = self.inner.events.write().await;
+            self.inner.clear(&mut room_events).await;
+            let _ = self.inner.sender.send(RoomEventCacheUpdate::Clear);
+        }
+
         let paginator = &self.inner.pagination.paginator;
 
         paginator.set_idle_state(prev_token.clone(), None)?;
@@ -248,6 +266,96 @@ impl RoomPagination {
         Ok(Some(BackPaginationOutcome { events, reached_start }))
     }
 
+    /// Run a single back-pagination from a gap in the middle of the known
+    /// events, and replace (part of) the gap with the returned events.
+    ///
+    /// The gap is identified by its `prev_token`, as found in
+    /// [`RoomEventCacheGap`](super::RoomEventCacheGap) or
+    /// [`RoomEventCacheUpdate::AddGap`](super::RoomEventCacheUpdate::AddGap).
+    ///
+    /// Returned events that are already known by the event cache are
+    /// discarded: meeting one of them means the gap is closed.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`EventCacheError::UnknownBackpaginationToken`] if there's no
+    /// gap with this token, for instance because it has already been filled,
+    /// or the room events have been cleared in the meanwhile.
+    #[instrument(skip(self))]
+    pub async fn fill_gap(&self, prev_token: &str, batch_size: u16) -> Result<GapFillOutcome> {
+        fn find_gap(room_events: &RoomEvents, token: &str) -> Option<ChunkIdentifier> {
+            room_events.chunk_identifier(|chunk| match chunk.content() {
+                ChunkContent::Gap(Gap { prev_token }) => prev_token == token,
+                ChunkContent::Items(..) => false,
+            })
+        }
+
+        if find_gap(&*self.inner.events.read().await, prev_token).is_none() {
+            return Err(EventCacheError::UnknownBackpaginationToken);
+        }
+
+        // Use a paginator of our own, so as not to interfere with the
+        // back-pagination at the start of the room.
+        let paginator = Paginator::new(Box::new(self.inner.weak_room.clone()));
+        paginator.set_idle_state(Some(prev_token.to_owned()), None)?;
+
+        let PaginationResult { events, hit_end_of_timeline } =
+            paginator.paginate_backward(batch_size.into()).await?;
+
+        let mut room_events = self.inner.events.write().await;
+
+        // The room events might have changed during the request.
+        let Some(gap_identifier) = find_gap(&room_events, prev_token) else {
+            return Err(EventCacheError::UnknownBackpaginationToken);
+        };
+
+        let known_event_ids: HashSet<_> =
+            room_events.events().filter_map(|(_, event)| event.event_id()).collect();
+
+        // Events are in reverse topological order: keep them until we meet one that we
+        // already know.
+        let num_events = events.len();
+        let events: Vec<_> = events
+            .into_iter()
+            .take_while(|event| {
+                event
+                    .event
+                    .get_field::<OwnedEventId>("event_id")
+                    .ok()
+                    .flatten()
+                    .map_or(true, |event_id| !known_event_ids.contains(&event_id))
+            })
+            .collect();
+        let met_known_events = events.len() < num_events;
+
+        let prev_token = if met_known_events || hit_end_of_timeline {
+            None
+        } else {
+            paginator.prev_batch_token()
+        };
+
+        let new_position = room_events
+            .replace_gap_at(
+                events.iter().rev().cloned().map(SyncTimelineEvent::from),
+                gap_identifier,
+            )
+            // SAFETY: we are sure that `gap_identifier` represents a valid `ChunkIdentifier` for
+            // a `Gap` chunk, since we hold the lock since we found it.
+            .expect("The `gap_identifier` must represent a `Gap`")
+            .first_position();
+
+        if let Some(prev_token) = &prev_token {
+            room_events
+                .insert_gap_at(Gap { prev_token: prev_token.clone() }, new_position)
+                // SAFETY: `new_position` represents the start of the new `Item` chunk.
+                .expect("The `new_position` must represent an `Item`");
+        }
+
+        trace!(num_events = events.len(), is_closed = prev_token.is_none(), "filled gap");
+
+        Ok(GapFillOutcome { events, prev_token })
+    }
+
     /// Get the latest pagination token, as stored in the room events linked
     /// list.
     #[doc(hidden)]
@@ -271,11 +379,19 @@ impl RoomPagination {
     /// token to be returned by a sync.
     async fn oldest_token(&self, max_wait: Option<Duration>) -> Option<String> {
         // Optimistically try to return the backpagination token immediately.
+        //
+        // Only a gap before all the events is a back-pagination token: gaps after them
+        // are holes in the history, that must be filled with `fill_gap`.
         fn get_oldest(room_events: RwLockReadGuard<'_, RoomEvents>) -> Option<String> {
-            room_events.chunks().find_map(|chunk| match chunk.content() {
-                ChunkContent::Gap(gap) => Some(gap.prev_token.clone()),
-                ChunkContent::Items(..) => None,
-            })
+            for chunk in room_events.chunks() {
+                match chunk.content() {
+                    ChunkContent::Gap(gap) => return Some(gap.prev_token.clone()),
+                    ChunkContent::Items(events) if !events.is_empty() => return None,
+                    ChunkContent::Items(..) => {}
+                }
+            }
+
+            None
         }
 
         if let Some(token) = get_oldest(self.inner.events.read().await) {
@@ -317,6 +433,23 @@ impl RoomPagination {
     }
 }
 
+/// The result of a single [`RoomPagination::fill_gap`] request.
+#[derive(Debug)]
+pub struct GapFillOutcome {
+    /// The events that have been inserted in place of the gap, deduplicated
+    /// against the known events.
+    ///
+    /// Events are presented in reverse order: the first element of the vec,
+    /// if present, is the most "recent" event from the chunk.
+    pub events: Vec<TimelineEvent>,
+
+    /// The token of what remains of the gap, before the returned events.
+    ///
+    /// `None` if the gap has been closed, because known events, or the start
+    /// of the room, have been reached.
+    pub prev_token: Option<String>,
+}
+
 /// A type representing whether the timeline has been reset.
 #[derive(Debug)]
 pub enum TimelineHasBeenResetWhilePaginating {
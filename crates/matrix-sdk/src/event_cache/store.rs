@@ -109,6 +109,17 @@ impl RoomEvents {
         self.chunks.replace_gap_at(events, gap_identifier)
     }
 
+    /// Remove all the events and gaps before the chunk identified by
+    /// `chunk_identifier`.
+    ///
+    /// This method returns the number of removed events.
+    pub fn remove_chunks_before(
+        &mut self,
+        chunk_identifier: ChunkIdentifier,
+    ) -> Result<usize, Error> {
+        self.chunks.remove_chunks_before(chunk_identifier)
+    }
+
     /// Search for a chunk, and return its identifier.
     pub fn chunk_identifier<'a, P>(&'a self, predicate: P) -> Option<ChunkIdentifier>
     where
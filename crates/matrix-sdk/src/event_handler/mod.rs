@@ -47,7 +47,7 @@ use std::{
 use anymap2::any::CloneAnySendSync;
 use futures_util::stream::{FuturesUnordered, StreamExt};
 use matrix_sdk_base::{
-    deserialized_responses::{EncryptionInfo, SyncTimelineEvent},
+    deserialized_responses::{EncryptionInfo, ProcessedToDeviceEvent, SyncTimelineEvent},
     SendOutsideWasm, SyncOutsideWasm,
 };
 use ruma::{events::AnySyncStateEvent, push::Action, serde::Raw, OwnedRoomId};
@@ -345,6 +345,34 @@ impl Client {
         Ok(())
     }
 
+    pub(crate) async fn handle_sync_to_device_events(
+        &self,
+        to_device_events: &[ProcessedToDeviceEvent],
+    ) -> serde_json::Result<()> {
+        #[derive(Deserialize)]
+        struct ExtractType<'a> {
+            #[serde(borrow, rename = "type")]
+            event_type: Cow<'a, str>,
+        }
+
+        for item in to_device_events {
+            let event_type = item.event.deserialize_as::<ExtractType<'_>>()?.event_type;
+            let encryption_info = item.encryption_info.as_ref();
+
+            self.call_event_handlers(
+                None,
+                item.event.json(),
+                HandlerKind::ToDevice,
+                &event_type,
+                encryption_info,
+                &[],
+            )
+            .await;
+        }
+
+        Ok(())
+    }
+
     pub(crate) async fn handle_sync_state_events(
         &self,
         room: Option<&Room>,
@@ -550,6 +578,9 @@ mod tests {
         },
     };
 
+    use matrix_sdk_base::deserialized_responses::{
+        AlgorithmInfo, EncryptionInfo, ProcessedToDeviceEvent, VerificationState,
+    };
     use matrix_sdk_test::{
         sync_timeline_event, EphemeralTestEvent, StateTestEvent, StrippedStateTestEvent,
         SyncResponseBuilder,
@@ -563,9 +594,9 @@ mod tests {
                 power_levels::OriginalSyncRoomPowerLevelsEvent,
             },
             typing::SyncTypingEvent,
-            AnySyncStateEvent, AnySyncTimelineEvent,
+            AnySyncStateEvent, AnySyncTimelineEvent, AnyToDeviceEvent,
         },
-        room_id,
+        owned_device_id, owned_user_id, room_id,
         serde::Raw,
     };
     use serde_json::json;
@@ -868,4 +899,47 @@ mod tests {
         assert_eq!(counter.load(SeqCst), 1);
         Ok(())
     }
+
+    #[async_test]
+    async fn to_device_event_handler_encryption_info() -> crate::Result<()> {
+        let client = logged_in_client(None).await;
+        let senders = Arc::new(std::sync::Mutex::new(Vec::new()));
+
+        client.add_event_handler({
+            let senders = senders.clone();
+            move |_ev: Raw<AnyToDeviceEvent>, info: Option<EncryptionInfo>| async move {
+                senders.lock().unwrap().push(info.and_then(|info| info.sender_device));
+            }
+        });
+
+        let event = Raw::new(&json!({
+            "content": { "call_id": "1234" },
+            "sender": "@alice:example.org",
+            "type": "org.example.call.invite",
+        }))?
+        .cast();
+
+        let encryption_info = EncryptionInfo {
+            sender: owned_user_id!("@alice:example.org"),
+            sender_device: Some(owned_device_id!("ALICEDEVICE")),
+            algorithm_info: AlgorithmInfo::OlmV1Curve25519AesSha2 {
+                curve25519_key: "curve25519_key".to_owned(),
+            },
+            verification_state: VerificationState::Verified,
+        };
+
+        client
+            .handle_sync_to_device_events(&[
+                ProcessedToDeviceEvent {
+                    event: event.clone(),
+                    encryption_info: Some(encryption_info),
+                },
+                ProcessedToDeviceEvent::new(event),
+            ])
+            .await?;
+
+        assert_eq!(*senders.lock().unwrap(), vec![Some(owned_device_id!("ALICEDEVICE")), None]);
+
+        Ok(())
+    }
 }
@@ -209,7 +209,7 @@ pub struct TransmissionProgress {
     pub total: usize,
 }
 
-async fn response_to_http_response(
+pub(crate) async fn response_to_http_response(
     mut response: reqwest::Response,
 ) -> Result<http::Response<Bytes>, reqwest::Error> {
     let status = response.status();
@@ -23,14 +23,17 @@ use backoff::{future::retry, Error as RetryError, ExponentialBackoff};
 use bytes::Bytes;
 use bytesize::ByteSize;
 use eyeball::SharedObservable;
-use http::header::CONTENT_LENGTH;
+use http::{
+    header::{CONTENT_LENGTH, RANGE},
+    HeaderValue,
+};
 use reqwest::Certificate;
 use ruma::api::{
     client::error::{ErrorBody as ClientApiErrorBody, ErrorKind as ClientApiErrorKind, RetryAfter},
     error::FromHttpResponseError,
-    IncomingResponse, OutgoingRequest,
+    EndpointError, IncomingResponse, MatrixVersion, OutgoingRequest,
 };
-use tracing::{info, warn};
+use tracing::{debug, info, warn};
 
 use super::{response_to_http_response, HttpClient, TransmissionProgress, DEFAULT_REQUEST_TIMEOUT};
 use crate::{config::RequestConfig, error::HttpError, RumaApiError};
@@ -127,6 +130,65 @@ impl HttpClient {
     }
 }
 
+impl HttpClient {
+    /// Send the given request and return the response as soon as its headers
+    /// are received, so its body can be streamed.
+    ///
+    /// If `body` is set, it replaces the body of the serialized request. If
+    /// `range_start` is set, only the bytes of the response body starting at
+    /// this offset are requested.
+    ///
+    /// The request is not retried, because its body can't be cloned.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) async fn send_streaming<R>(
+        &self,
+        request: R,
+        config: RequestConfig,
+        homeserver: String,
+        access_token: Option<&str>,
+        server_versions: &[MatrixVersion],
+        body: Option<(reqwest::Body, u64)>,
+        range_start: Option<u64>,
+    ) -> Result<reqwest::Response, HttpError>
+    where
+        R: OutgoingRequest + Debug,
+        HttpError: From<FromHttpResponseError<R::EndpointError>>,
+    {
+        let request =
+            self.serialize_request(request, config, homeserver, access_token, server_versions)?;
+
+        let mut request = match body {
+            Some((body, content_length)) => {
+                let mut request = reqwest::Request::try_from(request.map(|_| body))?;
+                request.headers_mut().insert(CONTENT_LENGTH, content_length.into());
+                request
+            }
+            None => reqwest::Request::try_from(request)?,
+        };
+
+        if let Some(range_start) = range_start {
+            let range = HeaderValue::from_str(&format!("bytes={range_start}-"))
+                .expect("range header is valid");
+            request.headers_mut().insert(RANGE, range);
+        }
+
+        *request.timeout_mut() = Some(config.timeout);
+
+        debug!(uri = %request.url().path(), "Sending streaming request");
+
+        let response = self.inner.execute(request).await?;
+
+        let status = response.status();
+        if status.is_client_error() || status.is_server_error() {
+            let response = response_to_http_response(response).await?;
+            let error = R::EndpointError::from_http_response(response);
+            return Err(FromHttpResponseError::Server(error).into());
+        }
+
+        Ok(response)
+    }
+}
+
 #[cfg(not(target_arch = "wasm32"))]
 #[derive(Clone, Debug)]
 pub(crate) struct HttpSettings {
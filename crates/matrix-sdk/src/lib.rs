@@ -23,9 +23,9 @@ pub use matrix_sdk_base::crypto;
 pub use matrix_sdk_base::{
     deserialized_responses,
     store::{DynStateStore, MemoryStore, StateStoreExt},
-    DisplayName, Room as BaseRoom, RoomCreateWithCreatorEventContent, RoomInfo,
-    RoomMember as BaseRoomMember, RoomMemberships, RoomState, SessionMeta, StateChanges,
-    StateStore, StoreError,
+    ComposerDraft, ComposerDraftType, DisplayName, Room as BaseRoom,
+    RoomCreateWithCreatorEventContent, RoomInfo, RoomMember as BaseRoomMember, RoomMemberships,
+    RoomState, SessionMeta, StateChanges, StateStore, StoreError,
 };
 pub use matrix_sdk_common::*;
 pub use reqwest;
@@ -47,6 +47,7 @@ pub mod media;
 pub mod notification_settings;
 #[cfg(feature = "experimental-oidc")]
 pub mod oidc;
+pub mod presence;
 pub mod pusher;
 pub mod room;
 pub mod room_directory_search;
@@ -69,6 +70,8 @@ pub use authentication::{AuthApi, AuthSession, SessionTokens};
 pub use client::{
     sanitize_server_name, Client, ClientBuildError, ClientBuilder, LoopCtrl, SessionChange,
 };
+#[cfg(feature = "sqlite")]
+pub use client::{StateStoreRecovery, StateStoreRecoveryError};
 #[cfg(feature = "image-proc")]
 pub use error::ImageError;
 pub use error::{
@@ -76,6 +79,10 @@ pub use error::{
     RumaApiError,
 };
 pub use http_client::TransmissionProgress;
+#[cfg(all(feature = "e2e-encryption", feature = "redb"))]
+pub use matrix_sdk_redb::RedbCryptoStore;
+#[cfg(feature = "redb")]
+pub use matrix_sdk_redb::RedbStateStore;
 #[cfg(all(feature = "e2e-encryption", feature = "sqlite"))]
 pub use matrix_sdk_sqlite::SqliteCryptoStore;
 #[cfg(feature = "sqlite")]
@@ -15,18 +15,52 @@
 
 //! High-level media API.
 
+mod authenticated;
+
 #[cfg(feature = "e2e-encryption")]
 use std::io::Read;
+#[cfg(all(feature = "e2e-encryption", not(target_arch = "wasm32")))]
+use std::sync::{Arc, Mutex as StdMutex};
 use std::time::Duration;
 #[cfg(not(target_arch = "wasm32"))]
-use std::{fmt, fs::File, path::Path};
+use std::{
+    fmt,
+    fs::File,
+    future::IntoFuture,
+    io::{self, SeekFrom},
+    path::{Path, PathBuf},
+    pin::Pin,
+    task::{Context, Poll},
+};
 
+#[cfg(not(target_arch = "wasm32"))]
+use bytes::Bytes;
 use eyeball::SharedObservable;
+#[cfg(not(target_arch = "wasm32"))]
+use futures_core::Stream;
 use futures_util::future::try_join;
+#[cfg(not(target_arch = "wasm32"))]
+use futures_util::{
+    stream::{self, BoxStream},
+    AsyncRead, AsyncReadExt as _, StreamExt, TryStreamExt,
+};
+#[cfg(not(target_arch = "wasm32"))]
+use http::StatusCode;
+#[cfg(all(feature = "e2e-encryption", not(target_arch = "wasm32")))]
+use matrix_sdk_base::crypto::{ChunkedAttachmentDecryptor, ChunkedAttachmentEncryptor};
 pub use matrix_sdk_base::media::*;
+#[cfg(not(target_arch = "wasm32"))]
+use matrix_sdk_common::boxed_into_future;
+use matrix_sdk_common::instant::Instant;
 use mime::Mime;
+#[cfg(not(target_arch = "wasm32"))]
+use ruma::api::IncomingResponse;
+#[cfg(all(feature = "e2e-encryption", not(target_arch = "wasm32")))]
+use ruma::events::room::{EncryptedFile, EncryptedFileInit};
 use ruma::{
-    api::client::media::{create_content, get_content, get_content_thumbnail},
+    api::client::media::{
+        create_content, get_content, get_content_thumbnail, get_media_config, get_media_preview,
+    },
     assign,
     events::room::{
         message::{
@@ -36,24 +70,92 @@ use ruma::{
         },
         ImageInfo, MediaSource, ThumbnailInfo,
     },
-    MxcUri,
+    MilliSecondsSinceUnixEpoch, MxcUri, UInt,
 };
 #[cfg(not(target_arch = "wasm32"))]
 use tempfile::{Builder as TempFileBuilder, NamedTempFile, TempDir};
+#[cfg(all(feature = "e2e-encryption", not(target_arch = "wasm32")))]
+use tokio::io::AsyncReadExt as _;
 #[cfg(not(target_arch = "wasm32"))]
-use tokio::{fs::File as TokioFile, io::AsyncWriteExt};
+use tokio::{
+    fs::{self, File as TokioFile, OpenOptions},
+    io::{AsyncSeekExt, AsyncWriteExt},
+};
+use tracing::warn;
 
 use crate::{
     attachment::{AttachmentConfig, AttachmentInfo, Thumbnail},
     futures::SendRequest,
     Client, Result, TransmissionProgress,
 };
+#[cfg(not(target_arch = "wasm32"))]
+use crate::{http_client::response_to_http_response, HttpError};
 
 /// A conservative upload speed of 1Mbps
 const DEFAULT_UPLOAD_SPEED: u64 = 125_000;
 /// 5 min minimal upload request timeout, used to clamp the request timeout.
 const MIN_UPLOAD_REQUEST_TIMEOUT: Duration = Duration::from_secs(60 * 5);
 
+/// How long a URL preview is kept in the state store.
+const URL_PREVIEW_CACHE_TTL: Duration = Duration::from_secs(60 * 60 * 24);
+
+/// The minimal interval between two automatic clean-ups of the media cache.
+const MEDIA_CACHE_CLEAN_UP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How long a streaming download can take before it is interrupted. A
+/// download to a file can then be resumed with [`Media::download_media_file()`].
+#[cfg(not(target_arch = "wasm32"))]
+const STREAMING_DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
+/// The size of the chunks that are read and written when streaming media.
+#[cfg(not(target_arch = "wasm32"))]
+const STREAMING_CHUNK_SIZE: usize = 64 * 1024;
+
+/// A request to download the file or thumbnail of a media, with the
+/// authenticated media endpoints or the legacy ones.
+enum DownloadRequest {
+    File(authenticated::get_content::Request),
+    Thumbnail(authenticated::get_content_thumbnail::Request),
+    LegacyFile(get_content::v3::Request),
+    LegacyThumbnail(get_content_thumbnail::v3::Request),
+}
+
+impl DownloadRequest {
+    fn new(uri: &MxcUri, format: &MediaFormat, authenticated: bool) -> Result<Self> {
+        Ok(match (authenticated, format) {
+            (true, MediaFormat::File) => {
+                Self::File(authenticated::get_content::Request::from_url(uri)?)
+            }
+            (true, MediaFormat::Thumbnail(size)) => {
+                Self::Thumbnail(authenticated::get_content_thumbnail::Request::from_url(
+                    uri,
+                    size.width,
+                    size.height,
+                )?)
+            }
+            (false, MediaFormat::File) => {
+                Self::LegacyFile(get_content::v3::Request::from_url(uri)?)
+            }
+            (false, MediaFormat::Thumbnail(size)) => Self::LegacyThumbnail(
+                get_content_thumbnail::v3::Request::from_url(uri, size.width, size.height)?,
+            ),
+        })
+    }
+}
+
+/// Evaluate the given expression with the request of the given
+/// [`DownloadRequest`], whatever its type.
+macro_rules! with_download_request {
+    ($download_request:expr, |$request:ident| $body:expr) => {
+        match $download_request {
+            DownloadRequest::File($request) => $body,
+            DownloadRequest::Thumbnail($request) => $body,
+            DownloadRequest::LegacyFile($request) => $body,
+            DownloadRequest::LegacyThumbnail($request) => $body,
+        }
+    };
+}
+
 /// A high-level API to interact with the media API.
 #[derive(Debug, Clone)]
 pub struct Media {
@@ -113,9 +215,241 @@ impl fmt::Display for PersistError {
     }
 }
 
+/// A stream of the content of a media file.
+///
+/// Returned by [`Media::get_media_stream()`]. If the file is encrypted and
+/// encryption is enabled, the content is decrypted on the fly, and the stream
+/// ends with an error if the content doesn't match its hash.
+///
+/// It can be turned into an `AsyncRead` with
+/// [`MediaStream::into_async_read()`].
+#[cfg(not(target_arch = "wasm32"))]
+pub struct MediaStream {
+    inner: BoxStream<'static, io::Result<Bytes>>,
+    content_length: Option<u64>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl MediaStream {
+    fn new(response: reqwest::Response) -> Self {
+        let content_length = response.content_length();
+        let inner = response
+            .bytes_stream()
+            .map_err(|error| io::Error::new(io::ErrorKind::Other, error))
+            .boxed();
+
+        Self { inner, content_length }
+    }
+
+    /// Decrypt the content of this stream with the given decryptor.
+    #[cfg(feature = "e2e-encryption")]
+    fn decrypt(self, decryptor: ChunkedAttachmentDecryptor) -> Self {
+        let inner =
+            stream::try_unfold((self.inner, decryptor), |(mut inner, mut decryptor)| async move {
+                match inner.try_next().await? {
+                    Some(chunk) => {
+                        let mut chunk = chunk.to_vec();
+                        decryptor.decrypt_chunk(&mut chunk);
+                        Ok(Some((Bytes::from(chunk), (inner, decryptor))))
+                    }
+                    None => {
+                        decryptor
+                            .verify_hash()
+                            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+                        Ok(None)
+                    }
+                }
+            })
+            .boxed();
+
+        Self { inner, content_length: self.content_length }
+    }
+
+    /// The length of the content that is left to stream, if the server sent
+    /// it.
+    pub fn content_length(&self) -> Option<u64> {
+        self.content_length
+    }
+
+    /// Turn this stream into an `AsyncRead`.
+    pub fn into_async_read(self) -> impl AsyncRead + Send + Unpin {
+        TryStreamExt::into_async_read(self)
+    }
+}
+
+#[cfg(not(any(target_arch = "wasm32", tarpaulin_include)))]
+impl fmt::Debug for MediaStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MediaStream")
+            .field("content_length", &self.content_length)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Stream for MediaStream {
+    type Item = io::Result<Bytes>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.inner.poll_next_unpin(cx)
+    }
+}
+
 /// `IntoFuture` returned by [`Media::upload`].
 pub type SendUploadRequest = SendRequest<create_content::v3::Request>;
 
+/// `IntoFuture` returned by [`Media::upload_stream()`].
+#[cfg(not(target_arch = "wasm32"))]
+#[allow(missing_debug_implementations)]
+pub struct SendUploadStream<R> {
+    client: Client,
+    content_type: Mime,
+    reader: R,
+    length: u64,
+    send_progress: SharedObservable<TransmissionProgress>,
+    /// A function applied to every chunk before it is sent.
+    transform_chunk: Option<Box<dyn FnMut(&mut [u8]) + Send + Sync>>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<R> SendUploadStream<R> {
+    /// Replace the default `SharedObservable` used for tracking upload
+    /// progress.
+    pub fn with_send_progress_observable(
+        mut self,
+        send_progress: SharedObservable<TransmissionProgress>,
+    ) -> Self {
+        self.send_progress = send_progress;
+        self
+    }
+
+    /// Get a subscriber to observe the progress of sending the request
+    /// body.
+    pub fn subscribe_to_send_progress(&self) -> eyeball::Subscriber<TransmissionProgress> {
+        self.send_progress.subscribe()
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<R> IntoFuture for SendUploadStream<R>
+where
+    R: AsyncRead + Send + Sync + Unpin + 'static,
+{
+    type Output = Result<create_content::v3::Response>;
+    boxed_into_future!();
+
+    fn into_future(self) -> Self::IntoFuture {
+        let Self { client, content_type, reader, length, send_progress, transform_chunk } = self;
+
+        Box::pin(async move {
+            send_progress.update(|p| p.total += usize::try_from(length).unwrap_or(usize::MAX));
+
+            let chunks = stream::try_unfold(
+                (reader, transform_chunk, send_progress),
+                |(mut reader, mut transform_chunk, send_progress)| async move {
+                    let mut chunk = vec![0; STREAMING_CHUNK_SIZE];
+                    let read_bytes = reader.read(&mut chunk).await?;
+                    if read_bytes == 0 {
+                        return Ok(None);
+                    }
+
+                    chunk.truncate(read_bytes);
+                    if let Some(transform_chunk) = &mut transform_chunk {
+                        transform_chunk(&mut chunk);
+                    }
+                    send_progress.update(|p| p.current += read_bytes);
+
+                    io::Result::Ok(Some((
+                        Bytes::from(chunk),
+                        (reader, transform_chunk, send_progress),
+                    )))
+                },
+            );
+
+            let timeout = std::cmp::max(
+                Duration::from_secs(length / DEFAULT_UPLOAD_SPEED),
+                MIN_UPLOAD_REQUEST_TIMEOUT,
+            );
+            let request = assign!(create_content::v3::Request::new(Vec::new()), {
+                content_type: Some(content_type.essence_str().to_owned()),
+            });
+
+            let response = client
+                .send_streaming(
+                    request,
+                    Some(client.request_config().timeout(timeout)),
+                    Some((reqwest::Body::wrap_stream(chunks), length)),
+                    None,
+                )
+                .await?;
+            let response = response_to_http_response(response).await.map_err(HttpError::from)?;
+
+            Ok(create_content::v3::Response::try_from_http_response(response)
+                .map_err(HttpError::from)?)
+        })
+    }
+}
+
+/// `IntoFuture` returned by [`Media::upload_encrypted_stream()`].
+#[cfg(all(feature = "e2e-encryption", not(target_arch = "wasm32")))]
+#[allow(missing_debug_implementations)]
+pub struct SendEncryptedUploadStream<R> {
+    upload: SendUploadStream<R>,
+    encryptor: Arc<StdMutex<Option<ChunkedAttachmentEncryptor>>>,
+}
+
+#[cfg(all(feature = "e2e-encryption", not(target_arch = "wasm32")))]
+impl<R> SendEncryptedUploadStream<R> {
+    /// Replace the default `SharedObservable` used for tracking upload
+    /// progress.
+    pub fn with_send_progress_observable(
+        mut self,
+        send_progress: SharedObservable<TransmissionProgress>,
+    ) -> Self {
+        self.upload = self.upload.with_send_progress_observable(send_progress);
+        self
+    }
+
+    /// Get a subscriber to observe the progress of sending the request
+    /// body.
+    pub fn subscribe_to_send_progress(&self) -> eyeball::Subscriber<TransmissionProgress> {
+        self.upload.subscribe_to_send_progress()
+    }
+}
+
+#[cfg(all(feature = "e2e-encryption", not(target_arch = "wasm32")))]
+impl<R> IntoFuture for SendEncryptedUploadStream<R>
+where
+    R: AsyncRead + Send + Sync + Unpin + 'static,
+{
+    type Output = Result<EncryptedFile>;
+    boxed_into_future!();
+
+    fn into_future(self) -> Self::IntoFuture {
+        let Self { upload, encryptor } = self;
+
+        Box::pin(async move {
+            let response = upload.await?;
+
+            let keys = encryptor
+                .lock()
+                .unwrap()
+                .take()
+                .expect("the encryptor is only taken once the upload is done")
+                .finish();
+
+            Ok(EncryptedFileInit {
+                url: response.content_uri,
+                key: keys.key,
+                iv: keys.iv,
+                hashes: keys.hashes,
+                v: keys.version,
+            }
+            .into())
+        })
+    }
+}
+
 impl Media {
     pub(crate) fn new(client: Client) -> Self {
         Self { client }
@@ -162,6 +496,106 @@ impl Media {
         self.client.send(request, Some(request_config))
     }
 
+    /// Upload some media to the server from an `AsyncRead`, without holding
+    /// it in memory.
+    ///
+    /// # Arguments
+    ///
+    /// * `content_type` - The type of the media, this will be used as the
+    ///   content-type header.
+    ///
+    /// * `reader` - The `AsyncRead` of the raw bytes of the media.
+    ///
+    /// * `length` - The number of bytes of the media, which must be known in
+    ///   advance.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn upload_stream<R>(
+        &self,
+        content_type: &Mime,
+        reader: R,
+        length: u64,
+    ) -> SendUploadStream<R>
+    where
+        R: AsyncRead + Send + Sync + Unpin + 'static,
+    {
+        SendUploadStream {
+            client: self.client.clone(),
+            content_type: content_type.clone(),
+            reader,
+            length,
+            send_progress: Default::default(),
+            transform_chunk: None,
+        }
+    }
+
+    /// Encrypt and upload some media to the server from an `AsyncRead`,
+    /// without holding it in memory.
+    ///
+    /// The media is encrypted incrementally, and the returned `EncryptedFile`
+    /// contains the keys to decrypt it.
+    ///
+    /// # Arguments
+    ///
+    /// * `content_type` - The type of the media, this will be used as the
+    ///   content-type header.
+    ///
+    /// * `reader` - The `AsyncRead` of the raw bytes of the media.
+    ///
+    /// * `length` - The number of bytes of the media, which must be known in
+    ///   advance.
+    #[cfg(all(feature = "e2e-encryption", not(target_arch = "wasm32")))]
+    pub fn upload_encrypted_stream<R>(
+        &self,
+        content_type: &Mime,
+        reader: R,
+        length: u64,
+    ) -> SendEncryptedUploadStream<R>
+    where
+        R: AsyncRead + Send + Sync + Unpin + 'static,
+    {
+        let encryptor = Arc::new(StdMutex::new(Some(ChunkedAttachmentEncryptor::new())));
+
+        let mut upload = self.upload_stream(content_type, reader, length);
+        upload.transform_chunk = Some(Box::new({
+            let encryptor = encryptor.clone();
+            move |chunk| {
+                if let Some(encryptor) = encryptor.lock().unwrap().as_mut() {
+                    encryptor.encrypt_chunk(chunk);
+                }
+            }
+        }));
+
+        SendEncryptedUploadStream { upload, encryptor }
+    }
+
+    /// Get the maximum size of a file that can be uploaded to the homeserver,
+    /// in bytes.
+    ///
+    /// Returns `Ok(None)` if the homeserver doesn't advertise a limit.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use matrix_sdk::Client;
+    /// # use url::Url;
+    /// # async {
+    /// # let homeserver = Url::parse("http://localhost:8080")?;
+    /// # let mut client = Client::new(homeserver).await?;
+    /// if let Some(max_size) = client.media().max_upload_size().await? {
+    ///     println!("Files up to {max_size} bytes can be uploaded");
+    /// }
+    /// # anyhow::Ok(()) };
+    /// ```
+    pub async fn max_upload_size(&self) -> Result<Option<UInt>> {
+        if self.use_authenticated_media().await {
+            let request = authenticated::get_media_config::Request::new();
+            Ok(self.client.send(request, None).await?.upload_size)
+        } else {
+            let request = get_media_config::v3::Request::new();
+            Ok(Some(self.client.send(request, None).await?.upload_size))
+        }
+    }
+
     /// Gets a media file by copying it to a temporary location on disk.
     ///
     /// The file won't be encrypted even if it is encrypted on the server.
@@ -176,7 +610,9 @@ impl Media {
     /// * `content_type` - The type of the media, this will be used to set the
     ///   temporary file's extension.
     ///
-    /// * `use_cache` - If we should use the media cache for this request.
+    /// * `use_cache` - If we should use the media cache for this request. If
+    ///   not, the content is streamed to the file without being held in
+    ///   memory.
     ///
     /// * `temp_dir` - Path to a directory where temporary directories can be
     ///   created. If not provided, a default, global temporary directory will
@@ -191,8 +627,6 @@ impl Media {
         use_cache: bool,
         temp_dir: Option<String>,
     ) -> Result<MediaFileHandle> {
-        let data = self.get_media_content(request, use_cache).await?;
-
         let inferred_extension = mime2ext::mime2ext(content_type);
 
         let body_path = body.as_ref().map(Path::new);
@@ -239,12 +673,212 @@ impl Media {
             _ => (TempFileBuilder::new().tempfile()?, None),
         };
 
-        let mut file = TokioFile::from_std(temp_file.reopen()?);
-        file.write_all(&data).await?;
+        if use_cache {
+            let data = self.get_media_content(request, use_cache).await?;
+
+            let mut file = TokioFile::from_std(temp_file.reopen()?);
+            file.write_all(&data).await?;
+            // Make sure the file metadata is flushed to disk.
+            file.sync_all().await?;
+        } else {
+            // The temporary file is deleted if the content can't be verified,
+            // so it can be written directly.
+            let mut file = TokioFile::from_std(temp_file.reopen()?);
+            let mut stream = self.get_media_stream(request).await?;
+            while let Some(chunk) = stream.try_next().await? {
+                file.write_all(&chunk).await?;
+            }
+            // Make sure the file metadata is flushed to disk.
+            file.sync_all().await?;
+        }
+
+        Ok(MediaFileHandle { file: temp_file, _directory: temp_dir })
+    }
+
+    /// Get a stream of a media file's content, without holding it in memory.
+    ///
+    /// If the content is encrypted and encryption is enabled, the content will
+    /// be decrypted on the fly.
+    ///
+    /// The media cache is not used.
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - The `MediaRequest` of the content.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn get_media_stream(&self, request: &MediaRequest) -> Result<MediaStream> {
+        #[cfg(feature = "e2e-encryption")]
+        let decryptor = media_decryptor(request)?;
+
+        let stream = MediaStream::new(self.send_media_request(request, None).await?);
+
+        #[cfg(feature = "e2e-encryption")]
+        let stream = match decryptor {
+            Some(decryptor) => stream.decrypt(decryptor),
+            None => stream,
+        };
+
+        Ok(stream)
+    }
+
+    /// Download a media file's content to the given path, without holding it
+    /// in memory.
+    ///
+    /// If the content is encrypted and encryption is enabled, the content will
+    /// be decrypted.
+    ///
+    /// The content is first written to a file with the same path and a
+    /// `.partial` extension, which is moved to `path` once the whole content
+    /// was downloaded and, if it is encrypted, matches its hash. If the hash
+    /// doesn't match, the partial file is removed.
+    ///
+    /// If the partial file already exists, it is considered to contain the
+    /// beginning of the content from an interrupted download, and only the
+    /// rest of the content is requested with an HTTP range request. If the
+    /// server doesn't support range requests, the whole content is downloaded
+    /// again.
+    ///
+    /// The media cache is not used.
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - The `MediaRequest` of the content.
+    ///
+    /// * `path` - The path of the file to write the content to.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn download_media_file(
+        &self,
+        request: &MediaRequest,
+        path: impl AsRef<Path>,
+    ) -> Result<()> {
+        let path = path.as_ref();
+        let partial_path = {
+            let mut partial_path = path.as_os_str().to_owned();
+            partial_path.push(".partial");
+            PathBuf::from(partial_path)
+        };
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(&partial_path)
+            .await?;
+        let offset = file.metadata().await?.len();
+
+        #[cfg(feature = "e2e-encryption")]
+        let mut decryptor = media_decryptor(request)?;
+
+        // The hash of an encrypted file covers all of its content, so the
+        // content that was already downloaded must go through the decryptor
+        // too.
+        #[cfg(feature = "e2e-encryption")]
+        if let Some(decryptor) = &mut decryptor {
+            let mut chunk = vec![0; STREAMING_CHUNK_SIZE];
+            loop {
+                let read_bytes = file.read(&mut chunk).await?;
+                if read_bytes == 0 {
+                    break;
+                }
+                decryptor.skip_decrypted_chunk(&chunk[..read_bytes]);
+            }
+        }
+
+        let range_start = (offset > 0).then_some(offset);
+        let response = match self.send_media_request(request, range_start).await {
+            Ok(response) => response,
+            Err(error)
+                if range_start.is_some()
+                    && error.as_client_api_error().is_some_and(|error| {
+                        error.status_code == StatusCode::RANGE_NOT_SATISFIABLE
+                    }) =>
+            {
+                // The file already contains the whole content.
+                #[cfg(feature = "e2e-encryption")]
+                if let Some(mut decryptor) = decryptor {
+                    if let Err(error) = decryptor.verify_hash() {
+                        drop(file);
+                        fs::remove_file(&partial_path).await?;
+                        return Err(error.into());
+                    }
+                }
+
+                drop(file);
+                fs::rename(&partial_path, path).await?;
+                return Ok(());
+            }
+            Err(error) => return Err(error),
+        };
+
+        if range_start.is_some() && response.status() != StatusCode::PARTIAL_CONTENT {
+            // The server sent the whole content, start over.
+            file.set_len(0).await?;
+            file.seek(SeekFrom::Start(0)).await?;
+
+            #[cfg(feature = "e2e-encryption")]
+            {
+                decryptor = media_decryptor(request)?;
+            }
+        } else {
+            file.seek(SeekFrom::End(0)).await?;
+        }
+
+        let mut stream = MediaStream::new(response);
+
+        #[cfg(feature = "e2e-encryption")]
+        if let Some(decryptor) = decryptor {
+            stream = stream.decrypt(decryptor);
+        }
+
+        loop {
+            match stream.try_next().await {
+                Ok(Some(chunk)) => file.write_all(&chunk).await?,
+                Ok(None) => break,
+                Err(error) => {
+                    // The content doesn't match its hash, so what was written
+                    // can't be trusted, nor resumed.
+                    if error.kind() == io::ErrorKind::InvalidData {
+                        drop(file);
+                        fs::remove_file(&partial_path).await?;
+                    }
+
+                    return Err(error.into());
+                }
+            }
+        }
+
         // Make sure the file metadata is flushed to disk.
         file.sync_all().await?;
+        drop(file);
 
-        Ok(MediaFileHandle { file: temp_file, _directory: temp_dir })
+        fs::rename(&partial_path, path).await?;
+
+        Ok(())
+    }
+
+    /// Send the request to download the given media, starting at the given
+    /// offset, and return the response without reading its body.
+    #[cfg(not(target_arch = "wasm32"))]
+    async fn send_media_request(
+        &self,
+        request: &MediaRequest,
+        range_start: Option<u64>,
+    ) -> Result<reqwest::Response> {
+        let config = Some(self.client.request_config().timeout(STREAMING_DOWNLOAD_TIMEOUT));
+
+        // Thumbnails of encrypted media are stored as separate encrypted files.
+        let (uri, format) = match &request.source {
+            MediaSource::Plain(uri) => (uri, &request.format),
+            MediaSource::Encrypted(file) => (&file.url, &MediaFormat::File),
+        };
+
+        let request = self.download_request(uri, format).await?;
+        let response = with_download_request!(request, |request| {
+            self.client.send_streaming(request, config, None, range_start).await?
+        });
+
+        Ok(response)
     }
 
     /// Get a media file's content.
@@ -271,8 +905,7 @@ impl Media {
 
         let content: Vec<u8> = match &request.source {
             MediaSource::Encrypted(file) => {
-                let request = get_content::v3::Request::from_url(&file.url)?;
-                let content: Vec<u8> = self.client.send(request, None).await?.file;
+                let content = self.download_content(&file.url, &MediaFormat::File).await?;
 
                 #[cfg(feature = "e2e-encryption")]
                 let content = {
@@ -294,25 +927,123 @@ impl Media {
 
                 content
             }
-            MediaSource::Plain(uri) => {
-                if let MediaFormat::Thumbnail(size) = &request.format {
-                    let request =
-                        get_content_thumbnail::v3::Request::from_url(uri, size.width, size.height)?;
-                    self.client.send(request, None).await?.file
-                } else {
-                    let request = get_content::v3::Request::from_url(uri)?;
-                    self.client.send(request, None).await?.file
-                }
-            }
+            MediaSource::Plain(uri) => self.download_content(uri, &request.format).await?,
         };
 
         if use_cache {
-            self.client.store().add_media_content(request, content.clone()).await?;
+            self.add_to_cache(request, content.clone()).await?;
         }
 
         Ok(content)
     }
 
+    /// Download the file or thumbnail of the media with the given URI, with
+    /// the authenticated media endpoints if the homeserver supports them.
+    async fn download_content(&self, uri: &MxcUri, format: &MediaFormat) -> Result<Vec<u8>> {
+        let request = self.download_request(uri, format).await?;
+        let content =
+            with_download_request!(request, |request| self.client.send(request, None).await?.file);
+
+        Ok(content)
+    }
+
+    /// Build the request to download the file or thumbnail of the media with
+    /// the given URI, with the authenticated media endpoints if the homeserver
+    /// supports them.
+    async fn download_request(
+        &self,
+        uri: &MxcUri,
+        format: &MediaFormat,
+    ) -> Result<DownloadRequest> {
+        DownloadRequest::new(uri, format, self.use_authenticated_media().await)
+    }
+
+    /// Whether the authenticated media endpoints should be used to access
+    /// media on the homeserver.
+    ///
+    /// If the support can't be detected, the legacy endpoints are used.
+    async fn use_authenticated_media(&self) -> bool {
+        match self.client.server_supports_authenticated_media().await {
+            Ok(supported) => supported,
+            Err(error) => {
+                warn!(
+                    "Couldn't check whether the homeserver supports authenticated media: {error}"
+                );
+                false
+            }
+        }
+    }
+
+    /// Add a file to the media cache, unless it is too big for the retention
+    /// policy, and clean up the cache if it wasn't cleaned up recently.
+    async fn add_to_cache(&self, request: &MediaRequest, content: Vec<u8>) -> Result<()> {
+        if self.retention_policy().exceeds_max_file_size(content.len()) {
+            return Ok(());
+        }
+
+        self.client.store().add_media_content(request, content).await?;
+
+        let should_clean_up = {
+            let mut last_clean_up = self.client.inner.media_cache_last_clean_up.lock().unwrap();
+            let should_clean_up = last_clean_up.map_or(true, |last_clean_up| {
+                last_clean_up.elapsed() >= MEDIA_CACHE_CLEAN_UP_INTERVAL
+            });
+            if should_clean_up {
+                *last_clean_up = Some(Instant::now());
+            }
+            should_clean_up
+        };
+
+        if should_clean_up {
+            if let Err(error) = self.clean_up_cache().await {
+                warn!("Failed to clean up the media cache: {error}");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Set the policy deciding which files are kept in the media cache.
+    ///
+    /// The policy is applied every time the cache is cleaned up, which happens
+    /// automatically after files are added to the cache, at most once a
+    /// minute, or when [`Media::clean_up_cache()`] is called.
+    ///
+    /// Defaults to [`MediaRetentionPolicy::default()`].
+    pub fn set_retention_policy(&self, policy: MediaRetentionPolicy) {
+        *self.client.inner.media_retention_policy.write().unwrap() = policy;
+    }
+
+    /// Get the policy deciding which files are kept in the media cache.
+    pub fn retention_policy(&self) -> MediaRetentionPolicy {
+        *self.client.inner.media_retention_policy.read().unwrap()
+    }
+
+    /// Get the total size of the files in the media cache, in bytes.
+    pub async fn cache_size(&self) -> Result<usize> {
+        Ok(self.client.store().media_cache_size().await?)
+    }
+
+    /// Evict the files of the media cache according to the retention policy.
+    ///
+    /// If the policy protects the avatars of the joined rooms or the
+    /// thumbnails, they are never evicted.
+    pub async fn clean_up_cache(&self) -> Result<()> {
+        let policy = self.retention_policy();
+
+        let protected_uris = if policy.protect_joined_room_avatars {
+            self.client.joined_rooms().iter().filter_map(|room| room.avatar_url()).collect()
+        } else {
+            Vec::new()
+        };
+
+        Ok(self
+            .client
+            .store()
+            .clean_up_media_cache(policy, &protected_uris, MilliSecondsSinceUnixEpoch::now())
+            .await?)
+    }
+
     /// Remove a media file's content from the store.
     ///
     /// # Arguments
@@ -332,6 +1063,67 @@ impl Media {
         Ok(self.client.store().remove_media_content_for_uri(uri).await?)
     }
 
+    /// Get a preview of the given URL, from its OpenGraph data.
+    ///
+    /// The previews are cached in the media store for a day, by URL and `ts`,
+    /// so they are also subject to the [`MediaRetentionPolicy`]. A stale
+    /// preview is removed from the cache when it is read.
+    ///
+    /// Note that the homeserver fetches the URL, so previewing a URL sent in
+    /// an encrypted room leaks it to the homeserver.
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - The URL to preview.
+    ///
+    /// * `ts` - The preferred point in time to return a preview for, usually
+    ///   the time the URL was sent at.
+    pub async fn get_url_preview(
+        &self,
+        url: &str,
+        ts: Option<MilliSecondsSinceUnixEpoch>,
+    ) -> Result<UrlPreview> {
+        let cache_request = UrlPreview::cache_request(url, ts);
+
+        if let Some(content) = self.client.store().get_media_content(&cache_request).await? {
+            match serde_json::from_slice::<CachedUrlPreview>(&content) {
+                Ok(cached) => {
+                    let age = MilliSecondsSinceUnixEpoch::now()
+                        .get()
+                        .saturating_sub(cached.fetched_at.get());
+                    if Duration::from_millis(age.into()) < URL_PREVIEW_CACHE_TTL {
+                        return Ok(cached.preview);
+                    }
+                }
+                Err(error) => warn!("Failed to deserialize cached URL preview: {error}"),
+            }
+
+            // Don't keep a stale entry around if fetching a new preview fails.
+            self.client.store().remove_media_content(&cache_request).await?;
+        }
+
+        let data = if self.use_authenticated_media().await {
+            let request = authenticated::get_media_preview::Request::new(url.to_owned(), ts);
+            self.client.send(request, None).await?.data
+        } else {
+            let request = assign!(get_media_preview::v3::Request::new(url.to_owned()), { ts });
+            self.client.send(request, None).await?.data
+        };
+
+        let preview = match data {
+            Some(data) => serde_json::from_str(data.get())?,
+            None => UrlPreview::default(),
+        };
+
+        let cached = CachedUrlPreview {
+            preview: preview.clone(),
+            fetched_at: MilliSecondsSinceUnixEpoch::now(),
+        };
+        self.add_to_cache(&cache_request, serde_json::to_vec(&cached)?).await?;
+
+        Ok(preview)
+    }
+
     /// Get the file of the given media event content.
     ///
     /// If the content is encrypted and encryption is enabled, the content will
@@ -546,6 +1338,17 @@ impl Media {
     }
 }
 
+/// Create the decryptor of the given media, if it is encrypted.
+#[cfg(all(feature = "e2e-encryption", not(target_arch = "wasm32")))]
+fn media_decryptor(request: &MediaRequest) -> Result<Option<ChunkedAttachmentDecryptor>> {
+    match &request.source {
+        MediaSource::Encrypted(file) => {
+            Ok(Some(ChunkedAttachmentDecryptor::new(file.as_ref().clone().into())?))
+        }
+        MediaSource::Plain(_) => Ok(None),
+    }
+}
+
 pub(crate) fn update_audio_message_event(
     mut audio_message_event_content: AudioMessageEventContent,
     content_type: &Mime,
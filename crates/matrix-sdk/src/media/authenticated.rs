@@ -0,0 +1,530 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The authenticated media endpoints, from [MSC3916] and Matrix 1.11.
+//!
+//! Ruma selects the path of an endpoint from the server versions, but it
+//! doesn't know about Matrix 1.11 yet. The stable paths are thus declared for
+//! all versions here, and [`Media`](super::Media) chooses between these
+//! endpoints and the legacy ones itself.
+//!
+//! The `OutgoingRequest` and `IncomingResponse` traits are implemented
+//! manually, since the Ruma API macros only generate them for crates with a
+//! `client` feature.
+//!
+//! [MSC3916]: https://github.com/matrix-org/matrix-spec-proposals/pull/3916
+
+use std::fmt::Display;
+
+use bytes::BufMut;
+use http::header::{HeaderName, AUTHORIZATION};
+use ruma::api::{
+    client::Error as ClientApiError, error::FromHttpResponseError, EndpointError, IntoHttpError,
+    MatrixVersion, Metadata, SendAccessToken,
+};
+
+/// Build an authenticated `GET` request to the endpoint with the given
+/// metadata.
+fn get_request<T: Default + BufMut>(
+    metadata: &Metadata,
+    base_url: &str,
+    access_token: SendAccessToken<'_>,
+    considering_versions: &[MatrixVersion],
+    path_args: &[&dyn Display],
+    query: &[(&str, String)],
+) -> Result<http::Request<T>, IntoHttpError> {
+    let query_string =
+        url::form_urlencoded::Serializer::new(String::new()).extend_pairs(query).finish();
+    let url =
+        metadata.make_endpoint_url(considering_versions, base_url, path_args, &query_string)?;
+
+    let access_token =
+        access_token.get_required_for_endpoint().ok_or(IntoHttpError::NeedsAuthentication)?;
+
+    Ok(http::Request::builder()
+        .method(metadata.method.clone())
+        .uri(url)
+        .header(AUTHORIZATION, format!("Bearer {access_token}"))
+        .body(T::default())?)
+}
+
+/// Convert an error response to the client API error it contains.
+fn check_status<T: AsRef<[u8]>>(
+    response: http::Response<T>,
+) -> Result<http::Response<T>, FromHttpResponseError<ClientApiError>> {
+    if response.status().as_u16() < 400 {
+        Ok(response)
+    } else {
+        Err(FromHttpResponseError::Server(ClientApiError::from_http_response(response)))
+    }
+}
+
+/// Get the value of the given header of the response, if it is valid UTF-8.
+fn header_value<T>(response: &http::Response<T>, name: HeaderName) -> Option<String> {
+    response.headers().get(name)?.to_str().ok().map(ToOwned::to_owned)
+}
+
+/// `GET /_matrix/client/v1/media/download/{serverName}/{mediaId}`
+pub(crate) mod get_content {
+    use std::time::Duration;
+
+    use bytes::BufMut;
+    use http::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
+    use ruma::{
+        api::{
+            client::Error as ClientApiError, error::FromHttpResponseError, AuthScheme,
+            IncomingResponse, IntoHttpError, MatrixVersion, Metadata, OutgoingRequest,
+            SendAccessToken, VersionHistory,
+        },
+        IdParseError, MxcUri, OwnedServerName,
+    };
+
+    const METADATA: Metadata = Metadata {
+        method: http::Method::GET,
+        rate_limited: true,
+        authentication: AuthScheme::AccessToken,
+        history: VersionHistory::new(
+            &[],
+            &[(MatrixVersion::V1_0, "/_matrix/client/v1/media/download/:server_name/:media_id")],
+            None,
+            None,
+        ),
+    };
+
+    /// Request type for the authenticated `get_content` endpoint.
+    #[derive(Clone, Debug)]
+    pub(crate) struct Request {
+        /// The server name from the mxc:// URI (the authority component).
+        pub server_name: OwnedServerName,
+
+        /// The media ID from the mxc:// URI (the path component).
+        pub media_id: String,
+
+        /// The maximum duration that the client is willing to wait to start
+        /// receiving data.
+        pub timeout_ms: Option<Duration>,
+    }
+
+    impl Request {
+        /// Creates a new `Request` with the given URL.
+        pub(crate) fn from_url(url: &MxcUri) -> Result<Self, IdParseError> {
+            let (server_name, media_id) = url.parts()?;
+
+            Ok(Self {
+                server_name: server_name.to_owned(),
+                media_id: media_id.to_owned(),
+                timeout_ms: None,
+            })
+        }
+    }
+
+    impl OutgoingRequest for Request {
+        type EndpointError = ClientApiError;
+        type IncomingResponse = Response;
+
+        const METADATA: Metadata = METADATA;
+
+        fn try_into_http_request<T: Default + BufMut>(
+            self,
+            base_url: &str,
+            access_token: SendAccessToken<'_>,
+            considering_versions: &[MatrixVersion],
+        ) -> Result<http::Request<T>, IntoHttpError> {
+            let query: Vec<_> = self
+                .timeout_ms
+                .map(|timeout| ("timeout_ms", timeout.as_millis().to_string()))
+                .into_iter()
+                .collect();
+
+            super::get_request(
+                &METADATA,
+                base_url,
+                access_token,
+                considering_versions,
+                &[&self.server_name, &self.media_id],
+                &query,
+            )
+        }
+    }
+
+    /// Response type for the authenticated `get_content` endpoint.
+    #[derive(Clone, Debug)]
+    pub(crate) struct Response {
+        /// The content that was previously uploaded.
+        pub file: Vec<u8>,
+
+        /// The content type of the file that was previously uploaded.
+        #[allow(dead_code)]
+        pub content_type: Option<String>,
+
+        /// The value of the `Content-Disposition` HTTP header, possibly
+        /// containing the name of the file that was previously uploaded.
+        #[allow(dead_code)]
+        pub content_disposition: Option<String>,
+    }
+
+    impl IncomingResponse for Response {
+        type EndpointError = ClientApiError;
+
+        fn try_from_http_response<T: AsRef<[u8]>>(
+            response: http::Response<T>,
+        ) -> Result<Self, FromHttpResponseError<ClientApiError>> {
+            let response = super::check_status(response)?;
+            let content_type = super::header_value(&response, CONTENT_TYPE);
+            let content_disposition = super::header_value(&response, CONTENT_DISPOSITION);
+
+            Ok(Self {
+                file: response.into_body().as_ref().to_owned(),
+                content_type,
+                content_disposition,
+            })
+        }
+    }
+}
+
+/// `GET /_matrix/client/v1/media/thumbnail/{serverName}/{mediaId}`
+pub(crate) mod get_content_thumbnail {
+    use std::time::Duration;
+
+    use bytes::BufMut;
+    use http::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
+    use ruma::{
+        api::{
+            client::{media::get_content_thumbnail::v3::Method, Error as ClientApiError},
+            error::FromHttpResponseError,
+            AuthScheme, IncomingResponse, IntoHttpError, MatrixVersion, Metadata, OutgoingRequest,
+            SendAccessToken, VersionHistory,
+        },
+        IdParseError, MxcUri, OwnedServerName, UInt,
+    };
+
+    const METADATA: Metadata = Metadata {
+        method: http::Method::GET,
+        rate_limited: true,
+        authentication: AuthScheme::AccessToken,
+        history: VersionHistory::new(
+            &[],
+            &[(MatrixVersion::V1_0, "/_matrix/client/v1/media/thumbnail/:server_name/:media_id")],
+            None,
+            None,
+        ),
+    };
+
+    /// Request type for the authenticated `get_content_thumbnail` endpoint.
+    #[derive(Clone, Debug)]
+    pub(crate) struct Request {
+        /// The server name from the mxc:// URI (the authority component).
+        pub server_name: OwnedServerName,
+
+        /// The media ID from the mxc:// URI (the path component).
+        pub media_id: String,
+
+        /// The desired resizing method.
+        pub method: Option<Method>,
+
+        /// The *desired* width of the thumbnail.
+        pub width: UInt,
+
+        /// The *desired* height of the thumbnail.
+        pub height: UInt,
+
+        /// The maximum duration that the client is willing to wait to start
+        /// receiving data.
+        pub timeout_ms: Option<Duration>,
+    }
+
+    impl Request {
+        /// Creates a new `Request` with the given URL and desired thumbnail
+        /// size.
+        pub(crate) fn from_url(
+            url: &MxcUri,
+            width: UInt,
+            height: UInt,
+        ) -> Result<Self, IdParseError> {
+            let (server_name, media_id) = url.parts()?;
+
+            Ok(Self {
+                server_name: server_name.to_owned(),
+                media_id: media_id.to_owned(),
+                method: None,
+                width,
+                height,
+                timeout_ms: None,
+            })
+        }
+    }
+
+    impl OutgoingRequest for Request {
+        type EndpointError = ClientApiError;
+        type IncomingResponse = Response;
+
+        const METADATA: Metadata = METADATA;
+
+        fn try_into_http_request<T: Default + BufMut>(
+            self,
+            base_url: &str,
+            access_token: SendAccessToken<'_>,
+            considering_versions: &[MatrixVersion],
+        ) -> Result<http::Request<T>, IntoHttpError> {
+            let mut query =
+                vec![("width", self.width.to_string()), ("height", self.height.to_string())];
+
+            if let Some(method) = &self.method {
+                query.push(("method", method.as_str().to_owned()));
+            }
+            if let Some(timeout) = self.timeout_ms {
+                query.push(("timeout_ms", timeout.as_millis().to_string()));
+            }
+
+            super::get_request(
+                &METADATA,
+                base_url,
+                access_token,
+                considering_versions,
+                &[&self.server_name, &self.media_id],
+                &query,
+            )
+        }
+    }
+
+    /// Response type for the authenticated `get_content_thumbnail` endpoint.
+    #[derive(Clone, Debug)]
+    pub(crate) struct Response {
+        /// A thumbnail of the requested content.
+        pub file: Vec<u8>,
+
+        /// The content type of the thumbnail.
+        #[allow(dead_code)]
+        pub content_type: Option<String>,
+
+        /// The value of the `Content-Disposition` HTTP header, possibly
+        /// containing the name of the thumbnail.
+        #[allow(dead_code)]
+        pub content_disposition: Option<String>,
+    }
+
+    impl IncomingResponse for Response {
+        type EndpointError = ClientApiError;
+
+        fn try_from_http_response<T: AsRef<[u8]>>(
+            response: http::Response<T>,
+        ) -> Result<Self, FromHttpResponseError<ClientApiError>> {
+            let response = super::check_status(response)?;
+            let content_type = super::header_value(&response, CONTENT_TYPE);
+            let content_disposition = super::header_value(&response, CONTENT_DISPOSITION);
+
+            Ok(Self {
+                file: response.into_body().as_ref().to_owned(),
+                content_type,
+                content_disposition,
+            })
+        }
+    }
+}
+
+/// `GET /_matrix/client/v1/media/config`
+pub(crate) mod get_media_config {
+    use bytes::BufMut;
+    use ruma::{
+        api::{
+            client::Error as ClientApiError, error::FromHttpResponseError, AuthScheme,
+            IncomingResponse, IntoHttpError, MatrixVersion, Metadata, OutgoingRequest,
+            SendAccessToken, VersionHistory,
+        },
+        UInt,
+    };
+    use serde::Deserialize;
+
+    const METADATA: Metadata = Metadata {
+        method: http::Method::GET,
+        rate_limited: true,
+        authentication: AuthScheme::AccessToken,
+        history: VersionHistory::new(
+            &[],
+            &[(MatrixVersion::V1_0, "/_matrix/client/v1/media/config")],
+            None,
+            None,
+        ),
+    };
+
+    /// Request type for the authenticated `get_media_config` endpoint.
+    #[derive(Clone, Debug, Default)]
+    pub(crate) struct Request {}
+
+    impl Request {
+        /// Creates an empty `Request`.
+        pub(crate) fn new() -> Self {
+            Self {}
+        }
+    }
+
+    impl OutgoingRequest for Request {
+        type EndpointError = ClientApiError;
+        type IncomingResponse = Response;
+
+        const METADATA: Metadata = METADATA;
+
+        fn try_into_http_request<T: Default + BufMut>(
+            self,
+            base_url: &str,
+            access_token: SendAccessToken<'_>,
+            considering_versions: &[MatrixVersion],
+        ) -> Result<http::Request<T>, IntoHttpError> {
+            super::get_request(&METADATA, base_url, access_token, considering_versions, &[], &[])
+        }
+    }
+
+    /// Response type for the authenticated `get_media_config` endpoint.
+    #[derive(Clone, Debug, Deserialize)]
+    pub(crate) struct Response {
+        /// Maximum size of upload in bytes, if the server advertises one.
+        #[serde(rename = "m.upload.size")]
+        pub upload_size: Option<UInt>,
+    }
+
+    impl IncomingResponse for Response {
+        type EndpointError = ClientApiError;
+
+        fn try_from_http_response<T: AsRef<[u8]>>(
+            response: http::Response<T>,
+        ) -> Result<Self, FromHttpResponseError<ClientApiError>> {
+            let response = super::check_status(response)?;
+            Ok(serde_json::from_slice(response.body().as_ref())?)
+        }
+    }
+}
+
+/// `GET /_matrix/client/v1/media/preview_url`
+pub(crate) mod get_media_preview {
+    use bytes::BufMut;
+    use ruma::{
+        api::{
+            client::Error as ClientApiError, error::FromHttpResponseError, AuthScheme,
+            IncomingResponse, IntoHttpError, MatrixVersion, Metadata, OutgoingRequest,
+            SendAccessToken, VersionHistory,
+        },
+        MilliSecondsSinceUnixEpoch,
+    };
+    use serde_json::value::RawValue as RawJsonValue;
+
+    const METADATA: Metadata = Metadata {
+        method: http::Method::GET,
+        rate_limited: true,
+        authentication: AuthScheme::AccessToken,
+        history: VersionHistory::new(
+            &[],
+            &[(MatrixVersion::V1_0, "/_matrix/client/v1/media/preview_url")],
+            None,
+            None,
+        ),
+    };
+
+    /// Request type for the authenticated `get_media_preview` endpoint.
+    #[derive(Clone, Debug)]
+    pub(crate) struct Request {
+        /// URL to get a preview of.
+        pub url: String,
+
+        /// Preferred point in time (in milliseconds) to return a preview for.
+        pub ts: Option<MilliSecondsSinceUnixEpoch>,
+    }
+
+    impl Request {
+        /// Creates a new `Request` with the given URL and preferred point in
+        /// time.
+        pub(crate) fn new(url: String, ts: Option<MilliSecondsSinceUnixEpoch>) -> Self {
+            Self { url, ts }
+        }
+    }
+
+    impl OutgoingRequest for Request {
+        type EndpointError = ClientApiError;
+        type IncomingResponse = Response;
+
+        const METADATA: Metadata = METADATA;
+
+        fn try_into_http_request<T: Default + BufMut>(
+            self,
+            base_url: &str,
+            access_token: SendAccessToken<'_>,
+            considering_versions: &[MatrixVersion],
+        ) -> Result<http::Request<T>, IntoHttpError> {
+            let mut query = vec![("url", self.url)];
+
+            if let Some(ts) = self.ts {
+                query.push(("ts", ts.get().to_string()));
+            }
+
+            super::get_request(&METADATA, base_url, access_token, considering_versions, &[], &query)
+        }
+    }
+
+    /// Response type for the authenticated `get_media_preview` endpoint.
+    #[derive(Clone, Debug, Default)]
+    pub(crate) struct Response {
+        /// OpenGraph-like data for the URL.
+        pub data: Option<Box<RawJsonValue>>,
+    }
+
+    impl IncomingResponse for Response {
+        type EndpointError = ClientApiError;
+
+        fn try_from_http_response<T: AsRef<[u8]>>(
+            response: http::Response<T>,
+        ) -> Result<Self, FromHttpResponseError<ClientApiError>> {
+            let response = super::check_status(response)?;
+            let body = response.body().as_ref();
+
+            let data = if body.is_empty() { None } else { Some(serde_json::from_slice(body)?) };
+
+            Ok(Self { data })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ruma::api::IncomingResponse;
+
+    use super::{get_content, get_content_thumbnail};
+
+    #[test]
+    fn test_content_response_headers() {
+        let http_response = || {
+            http::Response::builder()
+                .header("content-type", "image/png")
+                .header("content-disposition", "inline; filename=\"cat.png\"")
+                .body(b"image".to_vec())
+                .unwrap()
+        };
+
+        let response = get_content::Response::try_from_http_response(http_response()).unwrap();
+        assert_eq!(response.file, b"image");
+        assert_eq!(response.content_type.as_deref(), Some("image/png"));
+        assert_eq!(response.content_disposition.as_deref(), Some("inline; filename=\"cat.png\""));
+
+        let response =
+            get_content_thumbnail::Response::try_from_http_response(http_response()).unwrap();
+        assert_eq!(response.file, b"image");
+        assert_eq!(response.content_type.as_deref(), Some("image/png"));
+        assert_eq!(response.content_disposition.as_deref(), Some("inline; filename=\"cat.png\""));
+
+        let response = get_content::Response::try_from_http_response(
+            http::Response::builder().body(b"file".to_vec()).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(response.content_type, None);
+        assert_eq!(response.content_disposition, None);
+    }
+}
@@ -217,7 +217,10 @@ use self::{
     cross_process::{CrossProcessRefreshLockGuard, CrossProcessRefreshManager},
 };
 use crate::{
-    authentication::{qrcode::LoginWithQrCode, AuthData},
+    authentication::{
+        qrcode::{GrantLoginWithQrCode, LoginWithQrCode},
+        AuthData,
+    },
     client::SessionChange,
     Client, HttpError, RefreshTokenError, Result,
 };
@@ -434,6 +437,58 @@ impl Oidc {
         LoginWithQrCode::new(&self.client, client_metadata, data)
     }
 
+    /// Grant a login to a new device by displaying a QR code on this, existing,
+    /// device.
+    ///
+    /// This implements the existing device side of [MSC4108]. The new device
+    /// scans the QR code and uses the [`Oidc::login_with_qr_code()`] method to
+    /// log in.
+    ///
+    /// Once the new device is logged in, the private cross-signing keys and the
+    /// backup key are sent to it, so it's immediately verified and can connect
+    /// to the key backup. This requires that this device has all the private
+    /// cross-signing keys.
+    ///
+    /// [MSC4108]: https://github.com/matrix-org/matrix-spec-proposals/pull/4108
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use futures_util::StreamExt;
+    /// use matrix_sdk::{authentication::qrcode::GrantLoginProgress, Client};
+    /// # _ = async {
+    /// # let client: Client = unimplemented!();
+    /// # fn read_check_code_from_user() -> u8 { unimplemented!() }
+    /// let grant = client.oidc().grant_login_with_qr_code();
+    /// let mut progress = grant.subscribe_to_progress();
+    ///
+    /// let task = tokio::spawn(async move {
+    ///     while let Some(state) = progress.next().await {
+    ///         match state {
+    ///             GrantLoginProgress::WaitingForScan { qr_code_data } => {
+    ///                 // Render the QR code, i.e. using `qr_code_data.to_bytes()`.
+    ///             }
+    ///             GrantLoginProgress::EstablishingSecureChannel { check_code_sender } => {
+    ///                 check_code_sender.send(read_check_code_from_user());
+    ///             }
+    ///             GrantLoginProgress::WaitingForAuth { verification_uri } => {
+    ///                 println!("Please open {verification_uri} to approve the login");
+    ///             }
+    ///             GrantLoginProgress::Done => break,
+    ///             _ => (),
+    ///         }
+    ///     }
+    /// });
+    ///
+    /// grant.await?;
+    /// task.abort();
+    /// # anyhow::Ok(()) };
+    /// ```
+    #[cfg(all(feature = "e2e-encryption", not(target_arch = "wasm32")))]
+    pub fn grant_login_with_qr_code(&self) -> GrantLoginWithQrCode<'_> {
+        GrantLoginWithQrCode::new(&self.client)
+    }
+
     /// The OpenID Connect Provider used for authorization.
     ///
     /// Returns `None` if the client registration was not restored with
@@ -0,0 +1,143 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The presence of users.
+//!
+//! See [`Client::get_presence()`] and [`Client::subscribe_to_presence()`].
+
+use std::time::Duration;
+
+use eyeball::SharedObservable;
+use matrix_sdk_common::executor::{spawn, JoinHandle};
+use ruma::{
+    api::client::presence::get_presence, events::presence::PresenceEventContent,
+    presence::PresenceState, OwnedUserId,
+};
+use tracing::warn;
+
+use crate::{client::WeakClient, Client};
+
+/// How long a presence received in the sync is returned by
+/// [`Client::get_presence()`] before it is fetched again from the homeserver.
+pub const PRESENCE_MAX_AGE: Duration = Duration::from_secs(5 * 60);
+
+/// The presence of a user.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Presence {
+    /// The presence state of the user.
+    pub state: PresenceState,
+
+    /// The status message of the user, if they set one.
+    pub status_msg: Option<String>,
+
+    /// Whether the user is currently active.
+    pub currently_active: Option<bool>,
+
+    /// How long ago the user performed some action.
+    pub last_active_ago: Option<Duration>,
+}
+
+impl From<PresenceEventContent> for Presence {
+    fn from(content: PresenceEventContent) -> Self {
+        Self {
+            state: content.presence,
+            status_msg: content.status_msg,
+            currently_active: content.currently_active,
+            last_active_ago: content.last_active_ago.map(|ms| Duration::from_millis(u64::from(ms))),
+        }
+    }
+}
+
+impl From<get_presence::v3::Response> for Presence {
+    fn from(response: get_presence::v3::Response) -> Self {
+        Self {
+            state: response.presence,
+            status_msg: response.status_msg,
+            currently_active: response.currently_active,
+            last_active_ago: response.last_active_ago,
+        }
+    }
+}
+
+/// A presence state observed with [`Client::subscribe_to_presence()`].
+///
+/// Since sliding sync doesn't deliver presence, the presence state is loaded
+/// again every [`PRESENCE_MAX_AGE`] by a task, which stops once nobody is
+/// subscribed to the presence state anymore, and is aborted when this is
+/// dropped.
+#[derive(Debug)]
+pub(crate) struct ObservedPresence {
+    /// The presence state of the user.
+    pub(crate) state: SharedObservable<PresenceState>,
+
+    /// The task refreshing the presence state.
+    refresh_task: JoinHandle<()>,
+}
+
+impl ObservedPresence {
+    /// Start observing the presence state of the given user.
+    pub(crate) fn new(client: &Client, user_id: OwnedUserId, state: PresenceState) -> Self {
+        let state = SharedObservable::new(state);
+        let refresh_task =
+            spawn(Self::refresh(WeakClient::from_client(client), user_id, state.clone()));
+
+        Self { state, refresh_task }
+    }
+
+    /// Whether someone is still subscribed to the presence state.
+    pub(crate) fn is_observed(&self) -> bool {
+        self.state.subscriber_count() > 0
+    }
+
+    async fn refresh(
+        weak_client: WeakClient,
+        user_id: OwnedUserId,
+        state: SharedObservable<PresenceState>,
+    ) {
+        loop {
+            #[cfg(target_arch = "wasm32")]
+            gloo_timers::future::sleep(PRESENCE_MAX_AGE).await;
+
+            #[cfg(not(target_arch = "wasm32"))]
+            tokio::time::sleep(PRESENCE_MAX_AGE).await;
+
+            let Some(client) = weak_client.get() else {
+                break;
+            };
+
+            {
+                let mut presence_states = client.inner.presence_states.lock().await;
+
+                if state.subscriber_count() == 0 {
+                    // Dropping our own entry aborts this task, but we don't yield anymore.
+                    presence_states.remove(&user_id);
+                    break;
+                }
+            }
+
+            match client.get_presence(&user_id).await {
+                Ok(presence) => {
+                    state.set_if_not_eq(presence.state);
+                }
+                Err(error) => warn!(%user_id, "Failed to refresh the presence: {error}"),
+            }
+        }
+    }
+}
+
+impl Drop for ObservedPresence {
+    fn drop(&mut self) {
+        self.refresh_task.abort();
+    }
+}
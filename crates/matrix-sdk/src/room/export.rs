@@ -0,0 +1,555 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Export of the history of a room, for archiving.
+//!
+//! See [`Room::export()`].
+
+use std::future::IntoFuture;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::PathBuf;
+
+use eyeball::SharedObservable;
+use futures_util::io::{AsyncWrite, AsyncWriteExt};
+use matrix_sdk_common::{
+    boxed_into_future,
+    deserialized_responses::{EncryptionInfo, TimelineEvent},
+    SendOutsideWasm,
+};
+use ruma::{
+    events::{room::message::MessageType, AnyMessageLikeEvent, AnyTimelineEvent, MessageLikeEvent},
+    serde::Raw,
+    uint, MilliSecondsSinceUnixEpoch, UInt,
+};
+use serde::Serialize;
+use tracing::{instrument, warn};
+
+use super::{MessagesOptions, Room};
+use crate::Result;
+
+/// The format of an export of the history of a room.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// One JSON object per line, containing the raw event, with the
+    /// information about its decryption if it was encrypted.
+    ///
+    /// Since each event is on its own line, the output of a resumed export can
+    /// be appended to the output of the previous one.
+    Json,
+
+    /// A self-contained HTML page.
+    ///
+    /// A resumed export doesn't write the head of the page again, so its
+    /// output can be appended to the output of the interrupted export.
+    Html,
+
+    /// Plain text, one line per event.
+    PlainText,
+}
+
+/// The progress of an export of the history of a room.
+#[derive(Clone, Debug, Default)]
+pub struct ExportProgress {
+    /// The number of events that were exported.
+    pub exported_events: usize,
+
+    /// The number of attachments that were downloaded.
+    pub downloaded_attachments: usize,
+
+    /// The token to resume the export from, with [`ExportRoom::resume()`].
+    ///
+    /// All the events before this token were exported.
+    pub resume_token: Option<String>,
+}
+
+/// Future returned by [`Room::export()`].
+///
+/// The export is cancelled when this future is dropped. It can then be
+/// resumed with [`ExportRoom::resume()`], from the
+/// [`ExportProgress::resume_token`] reported by
+/// [`ExportRoom::with_progress_observable()`], but the events of the batch
+/// that was being exported when it was cancelled might be exported again.
+#[allow(missing_debug_implementations)]
+pub struct ExportRoom<'a, W> {
+    room: &'a Room,
+    writer: W,
+    format: ExportFormat,
+    from: Option<String>,
+    is_resumed: bool,
+    batch_size: UInt,
+    #[cfg(not(target_arch = "wasm32"))]
+    attachments_dir: Option<PathBuf>,
+    progress: SharedObservable<ExportProgress>,
+}
+
+impl<'a, W> ExportRoom<'a, W> {
+    pub(crate) fn new(room: &'a Room, writer: W, format: ExportFormat) -> Self {
+        Self {
+            room,
+            writer,
+            format,
+            from: None,
+            is_resumed: false,
+            batch_size: uint!(100),
+            #[cfg(not(target_arch = "wasm32"))]
+            attachments_dir: None,
+            progress: Default::default(),
+        }
+    }
+
+    /// Start the export at the given pagination token, instead of the
+    /// beginning of the room.
+    ///
+    /// To continue a previous export, use [`ExportRoom::resume()`] instead.
+    pub fn from(mut self, token: impl Into<String>) -> Self {
+        self.from = Some(token.into());
+        self.is_resumed = false;
+        self
+    }
+
+    /// Resume a previous export from its [`ExportProgress::resume_token`].
+    ///
+    /// The output continues the output of the previous export, so the head
+    /// of an HTML page is not written again.
+    pub fn resume(mut self, token: impl Into<String>) -> Self {
+        self.from = Some(token.into());
+        self.is_resumed = true;
+        self
+    }
+
+    /// Set the number of events requested at once.
+    ///
+    /// Defaults to 100.
+    pub fn batch_size(mut self, batch_size: UInt) -> Self {
+        self.batch_size = batch_size;
+        self
+    }
+
+    /// Download the attachments of the messages into the given directory,
+    /// which must exist.
+    ///
+    /// The exported events reference the path of their attachment.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn with_attachments(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.attachments_dir = Some(dir.into());
+        self
+    }
+
+    /// Replace the default `SharedObservable` used for tracking the progress
+    /// of the export.
+    ///
+    /// The progress is updated after each batch of events.
+    pub fn with_progress_observable(mut self, progress: SharedObservable<ExportProgress>) -> Self {
+        self.progress = progress;
+        self
+    }
+}
+
+impl<'a, W> IntoFuture for ExportRoom<'a, W>
+where
+    W: AsyncWrite + Unpin + SendOutsideWasm + 'a,
+{
+    type Output = Result<ExportProgress>;
+    boxed_into_future!(extra_bounds: 'a);
+
+    fn into_future(self) -> Self::IntoFuture {
+        Box::pin(self.run())
+    }
+}
+
+impl<'a, W> ExportRoom<'a, W>
+where
+    W: AsyncWrite + Unpin + SendOutsideWasm + 'a,
+{
+    #[instrument(skip_all, fields(room_id = ?self.room.room_id(), format = ?self.format))]
+    async fn run(mut self) -> Result<ExportProgress> {
+        let mut progress = ExportProgress { resume_token: self.from.clone(), ..Default::default() };
+        self.progress.set(progress.clone());
+
+        if !self.is_resumed {
+            self.write_header().await?;
+        }
+
+        loop {
+            let mut options = MessagesOptions::forward().from(progress.resume_token.as_deref());
+            options.limit = self.batch_size;
+            let messages = self.room.messages(options).await?;
+
+            for event in &messages.chunk {
+                let attachment = self.download_attachment(event, &mut progress).await;
+                self.write_event(event, attachment.as_deref()).await?;
+                progress.exported_events += 1;
+            }
+            self.writer.flush().await?;
+
+            let reached_end = messages.end.is_none() || messages.chunk.is_empty();
+            progress.resume_token = Some(messages.end.unwrap_or(messages.start));
+            self.progress.set(progress.clone());
+
+            if reached_end {
+                break;
+            }
+        }
+
+        self.write_footer().await?;
+        self.writer.flush().await?;
+
+        Ok(progress)
+    }
+
+    async fn write_header(&mut self) -> Result<()> {
+        if self.format == ExportFormat::Html {
+            let title =
+                escape_html(&self.room.name().unwrap_or_else(|| self.room.room_id().to_string()));
+            let header = format!(
+                "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+                 <title>{title}</title>\n<style>{HTML_STYLE}</style>\n</head>\n<body>\n\
+                 <h1>{title}</h1>\n"
+            );
+            self.writer.write_all(header.as_bytes()).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn write_footer(&mut self) -> Result<()> {
+        if self.format == ExportFormat::Html {
+            self.writer.write_all(b"</body>\n</html>\n").await?;
+        }
+
+        Ok(())
+    }
+
+    async fn write_event(&mut self, event: &TimelineEvent, attachment: Option<&str>) -> Result<()> {
+        let line = match self.format {
+            ExportFormat::Json => {
+                let entry = JsonEntry {
+                    event: &event.event,
+                    encryption_info: event.encryption_info.as_ref(),
+                    attachment,
+                };
+                let mut line = serde_json::to_string(&entry)?;
+                line.push('\n');
+                line
+            }
+            ExportFormat::Html => render_html(event, attachment),
+            ExportFormat::PlainText => render_text(event, attachment),
+        };
+
+        self.writer.write_all(line.as_bytes()).await?;
+        Ok(())
+    }
+
+    /// Download the attachment of the given event, if it has one and
+    /// attachments are exported.
+    ///
+    /// Returns the path of the attachment. Failures are logged, so they don't
+    /// interrupt the export.
+    #[cfg(not(target_arch = "wasm32"))]
+    async fn download_attachment(
+        &self,
+        event: &TimelineEvent,
+        progress: &mut ExportProgress,
+    ) -> Option<String> {
+        let dir = self.attachments_dir.as_ref()?;
+
+        let Ok(AnyTimelineEvent::MessageLike(AnyMessageLikeEvent::RoomMessage(
+            MessageLikeEvent::Original(event),
+        ))) = event.event.deserialize()
+        else {
+            return None;
+        };
+
+        let media = self.room.client().media();
+        let (file, filename) = match &event.content.msgtype {
+            MessageType::Image(content) => (media.get_file(content, false).await, &content.body),
+            MessageType::Video(content) => (media.get_file(content, false).await, &content.body),
+            MessageType::Audio(content) => (media.get_file(content, false).await, &content.body),
+            MessageType::File(content) => (media.get_file(content, false).await, &content.body),
+            _ => return None,
+        };
+
+        let data = match file {
+            Ok(Some(data)) => data,
+            Ok(None) => return None,
+            Err(error) => {
+                warn!(event_id = ?event.event_id, "Failed to download attachment: {error}");
+                return None;
+            }
+        };
+
+        // Prefix the file name with the event ID, to make it unique.
+        let filename = format!(
+            "{}-{}",
+            sanitize_filename(event.event_id.as_str()),
+            sanitize_filename(filename)
+        );
+        let path = dir.join(filename);
+
+        if let Err(error) = tokio::fs::write(&path, data).await {
+            warn!(event_id = ?event.event_id, "Failed to write attachment: {error}");
+            return None;
+        }
+
+        progress.downloaded_attachments += 1;
+        Some(path.to_string_lossy().into_owned())
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    async fn download_attachment(
+        &self,
+        _event: &TimelineEvent,
+        _progress: &mut ExportProgress,
+    ) -> Option<String> {
+        None
+    }
+}
+
+/// An event of a JSON export.
+#[derive(Serialize)]
+struct JsonEntry<'a> {
+    event: &'a Raw<AnyTimelineEvent>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    encryption_info: Option<&'a EncryptionInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    attachment: Option<&'a str>,
+}
+
+const HTML_STYLE: &str = "body { font-family: sans-serif; max-width: 50em; margin: auto; } \
+    .event { margin: 0.5em 0; } .time { color: #777; } .sender { font-weight: bold; } \
+    .body { white-space: pre-wrap; } .notice { color: #555; } .state { color: #777; }";
+
+/// The parts of an event that are displayed in HTML and plain text exports.
+struct DisplayedEvent {
+    timestamp: MilliSecondsSinceUnixEpoch,
+    sender: String,
+    kind: DisplayedEventKind,
+}
+
+enum DisplayedEventKind {
+    /// A message, with its body.
+    Message(String),
+    /// An emote, with its body.
+    Emote(String),
+    /// A notice, with its body.
+    Notice(String),
+    /// Any other event, with a description.
+    Other(String),
+}
+
+impl DisplayedEvent {
+    fn new(event: &TimelineEvent) -> Option<Self> {
+        let event = match event.event.deserialize() {
+            Ok(event) => event,
+            Err(error) => {
+                warn!("Failed to deserialize event to export: {error}");
+                return None;
+            }
+        };
+
+        let kind = match &event {
+            AnyTimelineEvent::MessageLike(AnyMessageLikeEvent::RoomMessage(
+                MessageLikeEvent::Original(message),
+            )) => match &message.content.msgtype {
+                MessageType::Emote(content) => DisplayedEventKind::Emote(content.body.clone()),
+                MessageType::Notice(content) => DisplayedEventKind::Notice(content.body.clone()),
+                MessageType::Text(content) => DisplayedEventKind::Message(content.body.clone()),
+                msgtype @ (MessageType::Image(_)
+                | MessageType::Video(_)
+                | MessageType::Audio(_)
+                | MessageType::File(_)) => DisplayedEventKind::Message(format!(
+                    "[{}: {}]",
+                    msgtype.msgtype().trim_start_matches("m."),
+                    msgtype.body()
+                )),
+                msgtype => DisplayedEventKind::Message(msgtype.body().to_owned()),
+            },
+            AnyTimelineEvent::MessageLike(AnyMessageLikeEvent::RoomEncrypted(_)) => {
+                DisplayedEventKind::Other("Unable to decrypt this message".to_owned())
+            }
+            AnyTimelineEvent::MessageLike(event) if event.original_content().is_none() => {
+                DisplayedEventKind::Other("This message was deleted".to_owned())
+            }
+            AnyTimelineEvent::MessageLike(event) => {
+                DisplayedEventKind::Other(format!("Sent a {} event", event.event_type()))
+            }
+            AnyTimelineEvent::State(event) => {
+                DisplayedEventKind::Other(format!("Changed the {} state", event.event_type()))
+            }
+        };
+
+        Some(Self { timestamp: event.origin_server_ts(), sender: event.sender().to_string(), kind })
+    }
+}
+
+fn render_text(event: &TimelineEvent, attachment: Option<&str>) -> String {
+    let Some(DisplayedEvent { timestamp, sender, kind }) = DisplayedEvent::new(event) else {
+        return String::new();
+    };
+
+    let time = format_timestamp(timestamp);
+    let mut line = match kind {
+        DisplayedEventKind::Message(body) | DisplayedEventKind::Notice(body) => {
+            format!("[{time}] {sender}: {body}")
+        }
+        DisplayedEventKind::Emote(body) => format!("[{time}] * {sender} {body}"),
+        DisplayedEventKind::Other(description) => format!("[{time}] {sender}: <{description}>"),
+    };
+
+    if let Some(attachment) = attachment {
+        line.push_str(&format!(" (attachment: {attachment})"));
+    }
+
+    line.push('\n');
+    line
+}
+
+fn render_html(event: &TimelineEvent, attachment: Option<&str>) -> String {
+    let Some(DisplayedEvent { timestamp, sender, kind }) = DisplayedEvent::new(event) else {
+        return String::new();
+    };
+
+    let (class, body) = match kind {
+        DisplayedEventKind::Message(body) => ("body", escape_html(&body)),
+        DisplayedEventKind::Emote(body) => ("body", format!("* {}", escape_html(&body))),
+        DisplayedEventKind::Notice(body) => ("body notice", escape_html(&body)),
+        DisplayedEventKind::Other(description) => ("state", escape_html(&description)),
+    };
+
+    let attachment = attachment
+        .map(|path| {
+            let path = escape_html(path);
+            format!("<div class=\"attachment\"><a href=\"{path}\">{path}</a></div>")
+        })
+        .unwrap_or_default();
+
+    format!(
+        "<div class=\"event\"><span class=\"time\">{}</span> \
+         <span class=\"sender\">{}</span><div class=\"{class}\">{body}</div>{attachment}</div>\n",
+        format_timestamp(timestamp),
+        escape_html(&sender),
+    )
+}
+
+/// Escape the characters of the given text that have a meaning in HTML.
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// Replace the characters that can't be used in a file name.
+#[cfg(not(target_arch = "wasm32"))]
+fn sanitize_filename(name: &str) -> String {
+    name.trim_start_matches('.')
+        .chars()
+        .map(|c| if c.is_alphanumeric() || matches!(c, '.' | '-' | '_') { c } else { '_' })
+        .collect()
+}
+
+/// Format the given timestamp as a UTC date and time.
+fn format_timestamp(timestamp: MilliSecondsSinceUnixEpoch) -> String {
+    let secs = u64::from(timestamp.as_secs());
+    let (days, secs_of_day) = (secs / 86_400, secs % 86_400);
+
+    // Convert the number of days since the epoch to a civil date, see
+    // <https://howardhinnant.github.io/date_algorithms.html#civil_from_days>.
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02}",
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use matrix_sdk_common::deserialized_responses::TimelineEvent;
+    use ruma::{serde::Raw, MilliSecondsSinceUnixEpoch, UInt};
+    use serde_json::json;
+
+    use super::{escape_html, format_timestamp, render_html, render_text};
+
+    fn message(msgtype: &str, body: &str) -> TimelineEvent {
+        TimelineEvent::new(
+            Raw::new(&json!({
+                "type": "m.room.message",
+                "event_id": "$message",
+                "room_id": "!room:example.org",
+                "sender": "@alice:example.org",
+                "origin_server_ts": 1_700_000_000_000_u64,
+                "content": { "msgtype": msgtype, "body": body, "url": "mxc://example.org/media" },
+            }))
+            .unwrap()
+            .cast(),
+        )
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        let timestamp = |secs: u64| MilliSecondsSinceUnixEpoch(UInt::new(secs * 1000).unwrap());
+
+        assert_eq!(format_timestamp(timestamp(0)), "1970-01-01 00:00:00");
+        assert_eq!(format_timestamp(timestamp(951_827_696)), "2000-02-29 12:34:56");
+        assert_eq!(format_timestamp(timestamp(1_700_000_000)), "2023-11-14 22:13:20");
+    }
+
+    #[test]
+    fn test_render_text() {
+        assert_eq!(
+            render_text(&message("m.text", "Hello"), None),
+            "[2023-11-14 22:13:20] @alice:example.org: Hello\n"
+        );
+        assert_eq!(
+            render_text(&message("m.emote", "waves"), None),
+            "[2023-11-14 22:13:20] * @alice:example.org waves\n"
+        );
+        assert_eq!(
+            render_text(&message("m.file", "report.pdf"), Some("export/report.pdf")),
+            "[2023-11-14 22:13:20] @alice:example.org: [file: report.pdf] \
+             (attachment: export/report.pdf)\n"
+        );
+    }
+
+    #[test]
+    fn test_render_html_escapes_content() {
+        let html = render_html(&message("m.text", "<script>alert(1)</script>"), None);
+
+        assert!(html.contains("&lt;script&gt;alert(1)&lt;/script&gt;"));
+        assert!(!html.contains("<script>"));
+        assert_eq!(escape_html("\"a\" & 'b'"), "&quot;a&quot; &amp; &#39;b&#39;");
+    }
+}
@@ -0,0 +1,158 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A paginated and searchable list of the members of a room.
+//!
+//! See [`Room::member_list()`].
+
+use matrix_sdk_base::RoomMemberships;
+use ruma::{events::room::member::SyncRoomMemberEvent, OwnedUserId};
+use tokio::sync::broadcast;
+use tracing::warn;
+
+use super::{Room, RoomMember};
+use crate::{event_handler::EventHandlerDropGuard, Result};
+
+/// The default number of members in a page of a [`RoomMemberList`].
+const DEFAULT_PAGE_SIZE: usize = 50;
+
+/// A paginated and searchable list of the members of a room.
+///
+/// The members are sorted by power level, in descending order, then by name,
+/// case-insensitively. The store keeps the name used for sorting next to the
+/// membership of each member, so only the profiles of the members of the
+/// requested page are loaded.
+///
+/// An unencrypted SQLite store sorts, filters and pages the members in its
+/// query. Other stores, and SQLite stores with a passphrase whose names are
+/// encrypted, read the names of all the members of the room and sort them in
+/// memory for each page. The encrypted SQLite store keeps the sorted members
+/// until the members of the room or their profiles change.
+#[derive(Debug, Clone)]
+pub struct RoomMemberList {
+    room: Room,
+    memberships: RoomMemberships,
+    search: Option<String>,
+    page_size: usize,
+}
+
+impl RoomMemberList {
+    pub(super) fn new(room: Room, memberships: RoomMemberships) -> Self {
+        Self { room, memberships, search: None, page_size: DEFAULT_PAGE_SIZE }
+    }
+
+    /// Only list the members whose display name or user ID contains the given
+    /// string, case-insensitively.
+    pub fn search(mut self, search: impl Into<String>) -> Self {
+        self.search = Some(search.into());
+        self
+    }
+
+    /// Set the number of members in a page.
+    ///
+    /// Defaults to 50.
+    pub fn page_size(mut self, page_size: usize) -> Self {
+        self.page_size = page_size;
+        self
+    }
+
+    /// Get the page of the list at the given index, starting at 0.
+    ///
+    /// If the members of the room were not fetched from the homeserver yet,
+    /// they are fetched first, see [`Room::sync_members()`].
+    pub async fn page(&self, index: usize) -> Result<RoomMemberListPage> {
+        self.room.sync_members().await?;
+
+        let (members, total) = self
+            .room
+            .inner
+            .members_page(
+                self.memberships,
+                self.search.as_deref(),
+                index.saturating_mul(self.page_size),
+                self.page_size,
+            )
+            .await?;
+        let members = members
+            .into_iter()
+            .map(|member| RoomMember::new(self.room.client.clone(), member))
+            .collect();
+
+        Ok(RoomMemberListPage { members, total })
+    }
+
+    /// Whether the given member belongs to this list.
+    pub fn contains(&self, member: &RoomMember) -> bool {
+        self.memberships.matches(member.membership())
+            && self.search.as_deref().map_or(true, |search| member.matches_search(search))
+    }
+
+    /// Subscribe to the changes of the members of this list.
+    ///
+    /// The returned receiver will receive an update for each membership event
+    /// of the room received in a sync response, once it has been saved to the
+    /// store. The pages that were already loaded can be updated accordingly.
+    pub fn subscribe(&self) -> (EventHandlerDropGuard, broadcast::Receiver<RoomMemberListUpdate>) {
+        let (sender, receiver) = broadcast::channel(128);
+        let list = self.clone();
+
+        let handle = self.room.add_event_handler(move |event: SyncRoomMemberEvent| {
+            let list = list.clone();
+            let sender = sender.clone();
+
+            async move {
+                let user_id = event.state_key();
+
+                let update = match list.room.get_member_no_sync(user_id).await {
+                    Ok(Some(member)) if list.contains(&member) => {
+                        RoomMemberListUpdate::Updated(member)
+                    }
+                    Ok(_) => RoomMemberListUpdate::Removed(user_id.to_owned()),
+                    Err(error) => {
+                        warn!(%user_id, "Failed to load the updated member: {error}");
+                        return;
+                    }
+                };
+
+                // Ignore the result. It can only fail if there are no listeners.
+                let _ = sender.send(update);
+            }
+        });
+
+        (self.room.client.event_handler_drop_guard(handle), receiver)
+    }
+}
+
+/// A page of a [`RoomMemberList`].
+#[derive(Debug, Clone)]
+pub struct RoomMemberListPage {
+    /// The members in this page, in order.
+    pub members: Vec<RoomMember>,
+
+    /// The total number of members in the list.
+    pub total: usize,
+}
+
+/// A change of the members of a [`RoomMemberList`].
+#[derive(Debug, Clone)]
+pub enum RoomMemberListUpdate {
+    /// A member of the list was added or changed.
+    Updated(RoomMember),
+
+    /// A member is not in the list anymore, because their membership changed
+    /// or they don't match the search anymore.
+    ///
+    /// The member might not have been in the list before.
+    Removed(OwnedUserId),
+}
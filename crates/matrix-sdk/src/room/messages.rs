@@ -17,13 +17,17 @@ use std::fmt;
 use matrix_sdk_common::{debug::DebugStructExt as _, deserialized_responses::TimelineEvent};
 use ruma::{
     api::{
-        client::{filter::RoomEventFilter, message::get_message_events},
+        client::{
+            filter::RoomEventFilter,
+            message::get_message_events,
+            relations::{get_relating_events, get_relating_events_with_rel_type},
+        },
         Direction,
     },
     assign,
-    events::AnyStateEvent,
+    events::{relation::RelationType, AnyStateEvent},
     serde::Raw,
-    uint, RoomId, UInt,
+    uint, EventId, RoomId, UInt,
 };
 
 /// Options for [`messages`][super::Room::messages].
@@ -140,6 +144,100 @@ pub struct Messages {
     pub state: Vec<Raw<AnyStateEvent>>,
 }
 
+/// Options for [`relations`][super::Room::relations].
+///
+/// See that method and
+/// <https://spec.matrix.org/v1.10/client-server-api/#get_matrixclientv1roomsroomidrelationseventid>
+/// for details.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct RelationsOptions {
+    /// The token to start returning events from.
+    ///
+    /// This token can be obtained from a `next_batch_token` or
+    /// `prev_batch_token` returned by a previous `relations` call.
+    pub from: Option<String>,
+
+    /// The direction to return events in.
+    pub dir: Direction,
+
+    /// The maximum number of events to return.
+    ///
+    /// If not set, the homeserver chooses a default.
+    pub limit: Option<UInt>,
+
+    /// Only return events with this relation type.
+    ///
+    /// If not set, events with any relation type are returned.
+    pub rel_type: Option<RelationType>,
+}
+
+impl RelationsOptions {
+    /// Creates `RelationsOptions` returning related events of any type, most
+    /// recent first.
+    pub fn new() -> Self {
+        Self { from: None, dir: Direction::Backward, limit: None, rel_type: None }
+    }
+
+    /// Creates `RelationsOptions` returning only the events that relate to
+    /// the target event with the given relation type, most recent first.
+    pub fn with_rel_type(rel_type: RelationType) -> Self {
+        Self { rel_type: Some(rel_type), ..Self::new() }
+    }
+
+    /// Creates a new `RelationsOptions` from `self` with the `from` field set
+    /// to the given value.
+    pub fn from<'a>(self, from: impl Into<Option<&'a str>>) -> Self {
+        Self { from: from.into().map(ToOwned::to_owned), ..self }
+    }
+
+    pub(super) fn into_request(self, room_id: &RoomId, event_id: &EventId) -> RelationsRequest {
+        match self.rel_type {
+            Some(rel_type) => RelationsRequest::WithRelType(assign!(
+                get_relating_events_with_rel_type::v1::Request::new(
+                    room_id.to_owned(),
+                    event_id.to_owned(),
+                    rel_type,
+                ),
+                { from: self.from, dir: self.dir, limit: self.limit }
+            )),
+            None => RelationsRequest::Any(assign!(
+                get_relating_events::v1::Request::new(room_id.to_owned(), event_id.to_owned()),
+                { from: self.from, dir: self.dir, limit: self.limit }
+            )),
+        }
+    }
+}
+
+impl Default for RelationsOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The request to send for a [`RelationsOptions`], depending on whether a
+/// relation type filter is set.
+pub(super) enum RelationsRequest {
+    Any(get_relating_events::v1::Request),
+    WithRelType(get_relating_events_with_rel_type::v1::Request),
+}
+
+/// The result of a [`super::Room::relations`] call.
+///
+/// This is a wrapper around the response of a `/relations` API call, with
+/// events decrypted if needs be.
+#[derive(Debug, Default)]
+pub struct Relations {
+    /// The events relating to the target event.
+    pub chunk: Vec<TimelineEvent>,
+
+    /// Token to fetch the next batch of related events, if any.
+    pub next_batch_token: Option<String>,
+
+    /// Token to fetch the previous batch of related events, if any.
+    pub prev_batch_token: Option<String>,
+}
+
 /// The result of a [`super::Room::event_with_context`] query.
 ///
 /// This is a wrapper around
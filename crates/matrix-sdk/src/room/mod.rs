@@ -20,7 +20,7 @@ use matrix_sdk_base::{
     },
     instant::Instant,
     store::StateStoreExt,
-    RoomMemberships, StateChanges,
+    ComposerDraft, RoomMemberships, StateChanges, StateStoreDataKey, StateStoreDataValue,
 };
 use matrix_sdk_common::timeout::timeout;
 use mime::Mime;
@@ -85,10 +85,15 @@ use thiserror::Error;
 use tokio::sync::broadcast;
 use tracing::{debug, info, instrument, warn};
 
-use self::futures::{SendAttachment, SendMessageLikeEvent, SendRawMessageLikeEvent};
+use self::{
+    export::{ExportFormat, ExportRoom},
+    futures::{SendAttachment, SendMessageLikeEvent, SendRawMessageLikeEvent},
+    member_list::RoomMemberList,
+    messages::RelationsRequest,
+};
 pub use self::{
     member::{RoomMember, RoomMemberRole},
-    messages::{EventWithContextResponse, Messages, MessagesOptions},
+    messages::{EventWithContextResponse, Messages, MessagesOptions, Relations, RelationsOptions},
 };
 #[cfg(doc)]
 use crate::event_cache::EventCache;
@@ -107,8 +112,10 @@ use crate::{
     BaseRoom, Client, Error, HttpError, HttpResult, Result, RoomState, TransmissionProgress,
 };
 
+pub mod export;
 pub mod futures;
 mod member;
+pub mod member_list;
 mod messages;
 pub mod power_levels;
 
@@ -309,6 +316,38 @@ impl Room {
         Ok(response)
     }
 
+    /// Export the history of this room to the given writer, in the given
+    /// format.
+    ///
+    /// The history is paginated forwards from the beginning of the room, or
+    /// from [`ExportRoom::from()`], with [`Room::messages()`], so encrypted
+    /// events are decrypted if the keys are available. The attachments of the
+    /// messages can be downloaded alongside the export.
+    ///
+    /// Returns the progress of the export once it reached the end of the room,
+    /// with a token to export the events that will be received later.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use eyeball::SharedObservable;
+    /// # use matrix_sdk::room::{export::ExportFormat, Room};
+    /// # async fn example(room: Room) -> matrix_sdk::Result<()> {
+    /// let mut transcript = Vec::new();
+    /// let progress = SharedObservable::new(Default::default());
+    ///
+    /// let outcome = room
+    ///     .export(&mut transcript, ExportFormat::PlainText)
+    ///     .with_progress_observable(progress.clone())
+    ///     .await?;
+    /// println!("Exported {} events", outcome.exported_events);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn export<W>(&self, writer: W, format: ExportFormat) -> ExportRoom<'_, W> {
+        ExportRoom::new(self, writer, format)
+    }
+
     /// Register a handler for events of a specific type, within this room.
     ///
     /// This method works the same way as [`Client::add_event_handler`], except
@@ -435,6 +474,62 @@ impl Room {
         })
     }
 
+    /// Fetch the events relating to the event with the given `EventId` in this
+    /// room, using the `/relations` endpoint.
+    ///
+    /// Encrypted related events are decrypted if possible. Use
+    /// [`Relations::next_batch_token`] with [`RelationsOptions::from`] to
+    /// fetch the following batch.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use matrix_sdk::{room::RelationsOptions, Client};
+    /// # use matrix_sdk::ruma::{event_id, events::relation::RelationType, room_id};
+    /// # use url::Url;
+    ///
+    /// # let homeserver = Url::parse("http://example.com").unwrap();
+    /// # async {
+    /// let client = Client::new(homeserver).await.unwrap();
+    /// let room = client.get_room(room_id!("!roomid:example.com")).unwrap();
+    /// let event_id = event_id!("$edited:example.com");
+    ///
+    /// let mut options = RelationsOptions::with_rel_type(RelationType::Replacement);
+    /// loop {
+    ///     let relations = room.relations(event_id, options.clone()).await.unwrap();
+    ///     println!("Got {} edits", relations.chunk.len());
+    ///
+    ///     let Some(token) = relations.next_batch_token else { break };
+    ///     options = options.from(token.as_str());
+    /// }
+    /// # };
+    /// ```
+    #[instrument(skip_all, fields(room_id = ?self.inner.room_id(), ?event_id, ?options))]
+    pub async fn relations(
+        &self,
+        event_id: &EventId,
+        options: RelationsOptions,
+    ) -> Result<Relations> {
+        let (chunk, next_batch_token, prev_batch_token) =
+            match options.into_request(self.room_id(), event_id) {
+                RelationsRequest::Any(request) => {
+                    let response = self.client.send(request, None).await?;
+                    (response.chunk, response.next_batch, response.prev_batch)
+                }
+                RelationsRequest::WithRelType(request) => {
+                    let response = self.client.send(request, None).await?;
+                    (response.chunk, response.next_batch, response.prev_batch)
+                }
+            };
+
+        // Note: as in `event_with_context`, `try_decrypt_event` doesn't hard-fail
+        // on decryption errors.
+        let chunk =
+            try_join_all(chunk.into_iter().map(|ev| self.try_decrypt_event(ev.cast()))).await?;
+
+        Ok(Relations { chunk, next_batch_token, prev_batch_token })
+    }
+
     pub(crate) async fn request_members(&self) -> Result<()> {
         self.client
             .locks()
@@ -665,6 +760,18 @@ impl Room {
             .collect())
     }
 
+    /// Get a paginated and searchable list of the members of this room, with
+    /// the given memberships.
+    ///
+    /// Unlike [`Room::members()`], only the profiles of the members of the
+    /// requested pages are loaded from the store, so it should be preferred
+    /// for rooms with a lot of members. Depending on the store, the names of
+    /// all the members might still be read and sorted in memory to select a
+    /// page, see [`RoomMemberList`].
+    pub fn member_list(&self, memberships: RoomMemberships) -> RoomMemberList {
+        RoomMemberList::new(self.clone(), memberships)
+    }
+
     /// Get all state events of a given type in this room.
     pub async fn get_state_events(
         &self,
@@ -1121,26 +1228,44 @@ impl Room {
         &self,
         event: &Raw<OriginalSyncRoomEncryptedEvent>,
     ) -> Result<TimelineEvent> {
+        use matrix_sdk_base::crypto::{DecryptionSettings, MegolmError};
         use ruma::events::room::encrypted::EncryptedEventScheme;
 
         let machine = self.client.olm_machine().await;
         let machine = machine.as_ref().ok_or(Error::NoOlmMachine)?;
 
-        let mut event =
-            match machine.decrypt_room_event(event.cast_ref(), self.inner.room_id()).await {
-                Ok(event) => event,
-                Err(e) => {
-                    let event = event.deserialize()?;
-                    if let EncryptedEventScheme::MegolmV1AesSha2(c) = event.content.scheme {
-                        self.client
-                            .encryption()
-                            .backups()
-                            .maybe_download_room_key(self.room_id().to_owned(), c.session_id);
-                    }
+        let decryption_settings = DecryptionSettings {
+            sender_device_trust_requirement: self
+                .client
+                .inner
+                .e2ee
+                .encryption_settings
+                .trust_requirement,
+        };
 
-                    return Err(e.into());
+        let mut event = match machine
+            .decrypt_room_event_with_settings(
+                event.cast_ref(),
+                self.inner.room_id(),
+                &decryption_settings,
+            )
+            .await
+        {
+            Ok(event) => event,
+            // We have the room key, there's no point in trying to download it from the backup.
+            Err(e @ MegolmError::SenderIdentityNotTrusted(_)) => return Err(e.into()),
+            Err(e) => {
+                let event = event.deserialize()?;
+                if let EncryptedEventScheme::MegolmV1AesSha2(c) = event.content.scheme {
+                    self.client
+                        .encryption()
+                        .backups()
+                        .maybe_download_room_key(self.room_id().to_owned(), c.session_id);
                 }
-            };
+
+                return Err(e.into());
+            }
+        };
 
         event.push_actions = self.event_push_actions(&event.event).await?;
 
@@ -2682,6 +2807,72 @@ impl Room {
         self.send(call_notify_event_content).await?;
         Ok(())
     }
+
+    /// Store the given `ComposerDraft` in the state store using the current
+    /// room id, as identifier.
+    pub async fn save_composer_draft(&self, draft: ComposerDraft) -> Result<()> {
+        self.client
+            .store()
+            .set_kv_data(
+                StateStoreDataKey::ComposerDraft(self.room_id()),
+                StateStoreDataValue::ComposerDraft(draft),
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Retrieve the `ComposerDraft` stored in the state store for this room.
+    pub async fn load_composer_draft(&self) -> Result<Option<ComposerDraft>> {
+        let data = self
+            .client
+            .store()
+            .get_kv_data(StateStoreDataKey::ComposerDraft(self.room_id()))
+            .await?;
+        Ok(data.and_then(|d| d.into_composer_draft()))
+    }
+
+    /// Remove the `ComposerDraft` stored in the state store for this room.
+    pub async fn clear_composer_draft(&self) -> Result<()> {
+        self.client
+            .store()
+            .remove_kv_data(StateStoreDataKey::ComposerDraft(self.room_id()))
+            .await?;
+        Ok(())
+    }
+
+    /// Set whether URL previews should be shown in this room, if it is
+    /// encrypted.
+    ///
+    /// Previewing a URL sends it to the homeserver, so URL previews are
+    /// disabled in encrypted rooms by default.
+    pub async fn set_url_previews_allowed_if_encrypted(&self, allowed: bool) -> Result<()> {
+        self.client
+            .store()
+            .set_kv_data(
+                StateStoreDataKey::UrlPreviewsInEncryptedRoom(self.room_id()),
+                StateStoreDataValue::UrlPreviewsInEncryptedRoom(allowed),
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Whether URL previews should be shown in this room.
+    ///
+    /// They are always allowed in unencrypted rooms. In encrypted rooms, they
+    /// are only allowed if [`Room::set_url_previews_allowed_if_encrypted()`]
+    /// was called with `true`.
+    pub async fn url_previews_allowed(&self) -> Result<bool> {
+        if !self.is_encrypted().await? {
+            return Ok(true);
+        }
+
+        let data = self
+            .client
+            .store()
+            .get_kv_data(StateStoreDataKey::UrlPreviewsInEncryptedRoom(self.room_id()))
+            .await?;
+        Ok(data.and_then(|d| d.into_url_previews_in_encrypted_room()).unwrap_or(false))
+    }
 }
 
 /// A wrapper for a weak client and a room id that allows to lazily retrieve a
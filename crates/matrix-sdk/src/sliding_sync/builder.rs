@@ -21,7 +21,7 @@ use super::{
     cache::{format_storage_key_prefix, restore_sliding_sync_state},
     sticky_parameters::SlidingSyncStickyManager,
     Error, SlidingSync, SlidingSyncInner, SlidingSyncListBuilder, SlidingSyncPositionMarkers,
-    SlidingSyncRoom,
+    SlidingSyncRoom, Version,
 };
 use crate::{sliding_sync::SlidingSyncStickyParameters, Client, Result};
 
@@ -33,7 +33,7 @@ use crate::{sliding_sync::SlidingSyncStickyParameters, Client, Result};
 pub struct SlidingSyncBuilder {
     id: String,
     storage_key: String,
-    sliding_sync_proxy: Option<Url>,
+    version: Option<Version>,
     client: Client,
     lists: Vec<SlidingSyncListBuilder>,
     extensions: Option<ExtensionsConfig>,
@@ -55,7 +55,7 @@ impl SlidingSyncBuilder {
             Ok(Self {
                 id,
                 storage_key,
-                sliding_sync_proxy: None,
+                version: None,
                 client,
                 lists: Vec::new(),
                 extensions: None,
@@ -76,7 +76,16 @@ impl SlidingSyncBuilder {
     /// URL. This method should only be called if the proxy is at a
     /// different URL than the one publicized in the `.well-known` endpoint.
     pub fn sliding_sync_proxy(mut self, value: Url) -> Self {
-        self.sliding_sync_proxy = Some(value);
+        self.version = Some(Version::Proxy { url: value });
+        self
+    }
+
+    /// Set the version of sliding sync to use.
+    ///
+    /// By default, the version selected by the client is used, see
+    /// [`Client::sliding_sync_version`].
+    pub fn version(mut self, version: Version) -> Self {
+        self.version = Some(version);
         self
     }
 
@@ -279,13 +288,13 @@ impl SlidingSyncBuilder {
         let rooms = AsyncRwLock::new(self.rooms);
         let lists = AsyncRwLock::new(lists);
 
-        // Use the configured sliding sync proxy, or if not set, try to use the one
-        // auto-discovered by the client, if any.
-        let sliding_sync_proxy = self.sliding_sync_proxy.or_else(|| client.sliding_sync_proxy());
+        // Use the configured version of sliding sync, or if not set, the one selected
+        // by the client.
+        let version = self.version.unwrap_or_else(|| client.sliding_sync_version());
 
         Ok(SlidingSync::new(SlidingSyncInner {
             id: self.id,
-            sliding_sync_proxy,
+            version,
 
             client,
             storage_key: self.storage_key,
@@ -305,6 +314,7 @@ impl SlidingSyncBuilder {
                 ),
             )),
             room_unsubscriptions: Default::default(),
+            bump_stamps: Default::default(),
 
             internal_channel: internal_channel_sender,
 
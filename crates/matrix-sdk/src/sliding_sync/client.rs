@@ -1,12 +1,77 @@
 use std::collections::BTreeMap;
 
 use imbl::Vector;
-use matrix_sdk_base::{sync::SyncResponse, PreviousEventsProvider};
-use ruma::{api::client::sync::sync_events::v4, events::AnyToDeviceEvent, serde::Raw, OwnedRoomId};
+use matrix_sdk_base::{
+    deserialized_responses::ProcessedToDeviceEvent, sync::SyncResponse, PreviousEventsProvider,
+};
+use ruma::{api::client::sync::sync_events::v4, OwnedRoomId};
+use url::Url;
 
 use super::{SlidingSync, SlidingSyncBuilder};
 use crate::{Client, Result, SlidingSyncRoom};
 
+/// The version of sliding sync to use.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum Version {
+    /// No sliding sync server was configured or discovered.
+    ///
+    /// Requests are sent to the sliding sync proxy endpoint of the homeserver.
+    #[default]
+    None,
+
+    /// Use the sliding sync proxy ([MSC3575]) at the given URL.
+    ///
+    /// [MSC3575]: https://github.com/matrix-org/matrix-spec-proposals/pull/3575
+    Proxy {
+        /// The URL of the proxy.
+        url: Url,
+    },
+
+    /// Use the simplified sliding sync API ([MSC4186]) implemented natively by
+    /// the homeserver.
+    ///
+    /// [MSC4186]: https://github.com/matrix-org/matrix-spec-proposals/pull/4186
+    Native,
+}
+
+impl Version {
+    /// Whether this is the native version of sliding sync.
+    pub fn is_native(&self) -> bool {
+        matches!(self, Self::Native)
+    }
+
+    /// The URL of the sliding sync proxy, if this version uses one.
+    pub fn proxy_url(&self) -> Option<&Url> {
+        match self {
+            Self::Proxy { url } => Some(url),
+            Self::None | Self::Native => None,
+        }
+    }
+}
+
+/// How to select the [`Version`] of sliding sync when building a [`Client`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum VersionBuilder {
+    /// Use the sliding sync proxy set with
+    /// [`ClientBuilder::sliding_sync_proxy`], or else the one advertised in the
+    /// `.well-known` of the server, if any.
+    ///
+    /// [`ClientBuilder::sliding_sync_proxy`]: crate::ClientBuilder::sliding_sync_proxy
+    #[default]
+    Proxy,
+
+    /// Use the native sliding sync API of the homeserver, without checking
+    /// that it supports it.
+    Native,
+
+    /// Use the native sliding sync API if the homeserver advertises it in
+    /// `/versions`, and behave like [`VersionBuilder::Proxy`] otherwise.
+    ///
+    /// If the homeserver URL was set directly, its `.well-known` is looked up
+    /// for a sliding sync proxy too.
+    Auto,
+}
+
 impl Client {
     /// Create a [`SlidingSyncBuilder`] tied to this client, with the given
     /// identifier.
@@ -55,7 +120,7 @@ impl<'a> PreviousEventsProvider for SlidingSyncPreviousEventsProvider<'a> {
 #[must_use]
 pub(crate) struct SlidingSyncResponseProcessor<'a> {
     client: Client,
-    to_device_events: Vec<Raw<AnyToDeviceEvent>>,
+    to_device_events: Vec<ProcessedToDeviceEvent>,
     response: Option<SyncResponse>,
     rooms: &'a BTreeMap<OwnedRoomId, SlidingSyncRoom>,
 }
@@ -239,6 +239,36 @@ impl SlidingSyncList {
         Ok(new_changes)
     }
 
+    /// Fill the room list with the given rooms, and mark this list as
+    /// [`SlidingSyncListLoadingState::Preloaded`], if it has neither been
+    /// loaded from the server nor restored from the cache yet.
+    ///
+    /// Returns `false` if the list has not been modified.
+    pub(super) fn preload(&self, room_ids: Vec<OwnedRoomId>) -> bool {
+        let mut state = self.inner.state.write().unwrap();
+
+        if **state != SlidingSyncListLoadingState::NotLoaded || room_ids.is_empty() {
+            return false;
+        }
+
+        let mut room_list = self.inner.room_list.write().unwrap();
+
+        if !room_list.is_empty() {
+            return false;
+        }
+
+        let maximum_number_of_rooms = room_ids.len().try_into().ok();
+
+        room_list.append(room_ids.into_iter().map(RoomListEntry::Filled).collect());
+        Observable::set(
+            &mut self.inner.maximum_number_of_rooms.write().unwrap(),
+            maximum_number_of_rooms,
+        );
+        Observable::set(&mut state, SlidingSyncListLoadingState::Preloaded);
+
+        true
+    }
+
     /// Commit the set of sticky parameters for this list.
     pub fn maybe_commit_sticky(&mut self, txn_id: &TransactionId) {
         self.inner.sticky.write().unwrap().maybe_commit(txn_id);
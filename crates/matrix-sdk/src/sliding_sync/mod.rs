@@ -20,6 +20,7 @@ mod cache;
 mod client;
 mod error;
 mod list;
+mod native;
 mod room;
 mod sticky_parameters;
 mod utils;
@@ -40,19 +41,26 @@ use ruma::{
         error::ErrorKind,
         sync::sync_events::v4::{self, ExtensionsConfig},
     },
-    assign, OwnedEventId, OwnedRoomId, RoomId,
+    assign, OwnedEventId, OwnedRoomId, RoomId, UInt,
 };
 use serde::{Deserialize, Serialize};
 use tokio::{
     select, spawn,
     sync::{broadcast::Sender, Mutex as AsyncMutex, OwnedMutexGuard, RwLock as AsyncRwLock},
 };
-use tracing::{debug, error, info, instrument, trace, warn, Instrument, Span};
+use tracing::{debug, error, info, instrument, trace, Instrument, Span};
 use url::Url;
 
+pub(crate) use self::native::UNSTABLE_FEATURE as NATIVE_UNSTABLE_FEATURE;
 #[cfg(feature = "e2e-encryption")]
 use self::utils::JoinHandleExt as _;
-pub use self::{builder::*, error::*, list::*, room::*};
+pub use self::{
+    builder::*,
+    client::{Version, VersionBuilder},
+    error::*,
+    list::*,
+    room::*,
+};
 use self::{
     cache::restore_sliding_sync_state,
     client::SlidingSyncResponseProcessor,
@@ -76,8 +84,15 @@ pub(super) struct SlidingSyncInner {
     /// Used to distinguish different connections to the sliding sync proxy.
     id: String,
 
-    /// Customize the sliding sync proxy URL.
-    sliding_sync_proxy: Option<Url>,
+    /// The version of sliding sync to use, with the sliding sync proxy URL if
+    /// any.
+    version: Version,
+
+    /// The bump stamps of the rooms, with the native version of sliding sync.
+    ///
+    /// They are used to sort the lists when the server doesn't send their
+    /// operations.
+    bump_stamps: StdRwLock<BTreeMap<OwnedRoomId, u64>>,
 
     /// The HTTP Matrix client.
     client: Client,
@@ -148,6 +163,11 @@ impl SlidingSync {
         SlidingSyncBuilder::new(id, client)
     }
 
+    /// Get the version of sliding sync used by this instance.
+    pub fn version(&self) -> &Version {
+        &self.inner.version
+    }
+
     /// Subscribe to a given room.
     ///
     /// If the associated `Room` exists, it will be marked as
@@ -266,6 +286,38 @@ impl SlidingSync {
         self.add_list(list_builder).await
     }
 
+    /// Preload a list with the given rooms, if the list has neither been
+    /// loaded from the server nor restored from the cache yet.
+    ///
+    /// It allows to show the rooms known by the client, e.g. the ones loaded
+    /// from the state store, before the first response from the server is
+    /// received. The list is then marked as
+    /// [`SlidingSyncListLoadingState::Preloaded`], and its entries are
+    /// updated in place by the next responses.
+    ///
+    /// Returns `true` if the list has been preloaded.
+    pub async fn preload_list(&self, list_name: &str, room_ids: Vec<OwnedRoomId>) -> bool {
+        let lists = self.inner.lists.read().await;
+
+        let Some(list) = lists.get(list_name) else {
+            return false;
+        };
+
+        if !list.preload(room_ids.clone()) {
+            return false;
+        }
+
+        let mut rooms = self.inner.rooms.write().await;
+
+        for room_id in room_ids {
+            rooms.entry(room_id.clone()).or_insert_with(|| {
+                SlidingSyncRoom::new(self.inner.client.clone(), room_id, None, Vec::new())
+            });
+        }
+
+        true
+    }
+
     /// Lookup a set of rooms
     pub async fn get_rooms<I: Iterator<Item = OwnedRoomId>>(
         &self,
@@ -589,6 +641,108 @@ impl SlidingSync {
             || !self.inner.lists.read().await.is_empty()
     }
 
+    /// Compute the operations of the lists of a response of the native version
+    /// of sliding sync, when the server didn't send them.
+    ///
+    /// The server doesn't say which lists the rooms of the response belong to,
+    /// so the membership of every list is worked out from its filters: a room
+    /// of the response, or of another list, joins the lists whose filters it
+    /// matches, and a room of the response leaves the lists whose filters it
+    /// doesn't match anymore. Rooms that are only part of a room subscription
+    /// aren't added to any list. The lists are then sorted by bump stamp, and
+    /// their requested ranges are synced.
+    async fn complete_native_response(
+        &self,
+        response: &mut v4::Response,
+        bump_stamps: BTreeMap<OwnedRoomId, u64>,
+        requested_lists: &BTreeMap<String, RequestedList>,
+    ) {
+        let bump_stamps = {
+            let mut known_bump_stamps = self.inner.bump_stamps.write().unwrap();
+            known_bump_stamps.extend(bump_stamps);
+            known_bump_stamps.clone()
+        };
+
+        if response.lists.values().all(|list_response| !list_response.ops.is_empty()) {
+            return;
+        }
+
+        let room_subscriptions =
+            self.inner.sticky.read().unwrap().data().room_subscriptions.clone();
+        let lists = self.inner.lists.read().await;
+
+        let rooms_of_lists = lists
+            .iter()
+            .map(|(name, list)| {
+                let room_ids = list
+                    .room_list::<RoomListEntry>()
+                    .iter()
+                    .filter_map(|entry| entry.as_room_id().map(ToOwned::to_owned))
+                    .collect::<Vec<_>>();
+
+                (name.clone(), room_ids)
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        // The facts about the rooms of the response, and about the rooms of the other
+        // lists, that the server doesn't send again when they didn't change.
+        let mut room_facts = BTreeMap::new();
+        let unchanged_room = v4::SlidingSyncRoom::default();
+
+        for (room_id, room) in response
+            .rooms
+            .iter()
+            .chain(rooms_of_lists.values().flatten().map(|room_id| (room_id, &unchanged_room)))
+        {
+            if !room_facts.contains_key(room_id) {
+                let known_room = self.inner.client.get_room(room_id);
+                let facts = native::RoomFacts::new(room, known_room.as_ref()).await;
+                room_facts.insert(room_id.clone(), facts);
+            }
+        }
+
+        for (name, list_response) in &mut response.lists {
+            if !list_response.ops.is_empty() {
+                continue;
+            }
+
+            let Some(current_rooms) = rooms_of_lists.get(name) else {
+                continue;
+            };
+
+            let (ranges, filters) = requested_lists
+                .get(name)
+                .map(|list| (list.ranges.as_slice(), list.filters.as_ref()))
+                .unwrap_or_default();
+
+            // Drop the rooms whose new state doesn't match the filters of the list anymore.
+            let current_rooms = current_rooms
+                .iter()
+                .filter(|room_id| {
+                    !response.rooms.contains_key(*room_id) || room_facts[*room_id].matches(filters)
+                })
+                .cloned()
+                .collect();
+
+            let new_rooms = room_facts
+                .iter()
+                .filter(|(room_id, facts)| {
+                    facts.matches(filters)
+                        && (!room_subscriptions.contains_key(*room_id)
+                            || rooms_of_lists.values().any(|room_ids| room_ids.contains(room_id)))
+                })
+                .map(|(room_id, _)| room_id.clone());
+
+            list_response.ops.extend(native::sync_operations(
+                current_rooms,
+                new_rooms,
+                &bump_stamps,
+                ranges,
+                list_response.count.try_into().unwrap_or(usize::MAX),
+            ));
+        }
+    }
+
     #[instrument(skip_all, fields(pos))]
     async fn sync_once(&self) -> Result<UpdateSummary> {
         let (request, request_config, requested_room_unsubscriptions, mut position_guard) =
@@ -596,11 +750,40 @@ impl SlidingSync {
 
         debug!("Sending request");
 
+        // Remember the ranges and filters of the lists, to complete the responses of
+        // the native version of sliding sync.
+        let requested_lists = request
+            .lists
+            .iter()
+            .map(|(name, list)| {
+                let requested_list =
+                    RequestedList { ranges: list.ranges.clone(), filters: list.filters.clone() };
+
+                (name.clone(), requested_list)
+            })
+            .collect::<BTreeMap<_, _>>();
+
         // Prepare the request.
-        let request =
-            self.inner.client.send(request, Some(request_config)).with_homeserver_override(
-                self.inner.sliding_sync_proxy.as_ref().map(ToString::to_string),
-            );
+        let request = {
+            let client = self.inner.client.clone();
+            let version = self.inner.version.clone();
+
+            async move {
+                if version.is_native() {
+                    let native::Response { response, bump_stamps } =
+                        client.send(native::Request::from(request), Some(request_config)).await?;
+
+                    Ok::<_, crate::HttpError>((response, Some(bump_stamps)))
+                } else {
+                    let response = client
+                        .send(request, Some(request_config))
+                        .with_homeserver_override(version.proxy_url().map(ToString::to_string))
+                        .await?;
+
+                    Ok((response, None))
+                }
+            }
+        };
 
         // Send the request and get a response with end-to-end encryption support.
         //
@@ -659,6 +842,8 @@ impl SlidingSync {
         #[cfg(not(feature = "e2e-encryption"))]
         let response = request.await?;
 
+        let (mut response, bump_stamps) = response;
+
         debug!("Received response");
 
         // At this point, the request has been sent, and a response has been received.
@@ -692,6 +877,11 @@ impl SlidingSync {
                     .retain(|room_id| !requested_room_unsubscriptions.contains(room_id));
             }
 
+            // The native version of sliding sync may not send the operations of the lists.
+            if let Some(bump_stamps) = bump_stamps {
+                this.complete_native_response(&mut response, bump_stamps, &requested_lists).await;
+            }
+
             // Handle the response.
             let updates = this.handle_response(response, &mut position_guard).await?;
 
@@ -869,7 +1059,7 @@ impl SlidingSync {
 
     /// Get the URL to Sliding Sync.
     pub fn sliding_sync_proxy(&self) -> Option<Url> {
-        self.inner.sliding_sync_proxy.clone()
+        self.inner.version.proxy_url().cloned()
     }
 
     /// Read the static extension configuration for this Sliding Sync.
@@ -883,6 +1073,17 @@ impl SlidingSync {
     }
 }
 
+/// The parameters of a list of a request that are needed to complete the
+/// response of the native version of sliding sync.
+#[derive(Debug, Default)]
+struct RequestedList {
+    /// The requested ranges of the list.
+    ranges: Vec<(UInt, UInt)>,
+
+    /// The filters of the list.
+    filters: Option<v4::SyncRequestListFilters>,
+}
+
 #[derive(Clone, Debug)]
 pub(super) struct SlidingSyncPositionMarkers {
     /// An ephemeral position in the current stream, as received from the
@@ -1074,13 +1275,18 @@ mod tests {
     use serde_json::json;
     use stream_assert::assert_pending;
     use url::Url;
-    use wiremock::{http::Method, Match, Mock, MockServer, Request, ResponseTemplate};
+    use wiremock::{
+        http::Method,
+        matchers::{method, path},
+        Match, Mock, MockServer, Request, ResponseTemplate,
+    };
 
     use super::{
         compute_limited,
         sticky_parameters::{LazyTransactionId, SlidingSyncStickyManager},
-        FrozenSlidingSync, SlidingSync, SlidingSyncList, SlidingSyncListBuilder, SlidingSyncMode,
-        SlidingSyncRoom, SlidingSyncStickyParameters,
+        FrozenSlidingSync, SlidingSync, SlidingSyncList, SlidingSyncListBuilder,
+        SlidingSyncListLoadingState, SlidingSyncMode, SlidingSyncRoom, SlidingSyncStickyParameters,
+        Version,
     };
     use crate::{
         sliding_sync::cache::restore_sliding_sync_state, test_utils::logged_in_client, Result,
@@ -1265,6 +1471,47 @@ mod tests {
         Ok(())
     }
 
+    #[async_test]
+    async fn test_preload_list() -> Result<()> {
+        let (_server, sliding_sync) = new_sliding_sync(vec![SlidingSyncList::builder("foo")
+            .sync_mode(SlidingSyncMode::new_selective().add_range(0..=10))])
+        .await?;
+
+        let room_id_0 = room_id!("!r0:bar.org");
+        let room_id_1 = room_id!("!r1:bar.org");
+
+        // Unknown lists can't be preloaded.
+        assert!(!sliding_sync.preload_list("bar", vec![room_id_0.to_owned()]).await);
+
+        assert!(
+            sliding_sync
+                .preload_list("foo", vec![room_id_0.to_owned(), room_id_1.to_owned()])
+                .await
+        );
+
+        let list = sliding_sync.on_list("foo", |list| ready(list.clone())).await.unwrap();
+
+        assert_eq!(list.state(), SlidingSyncListLoadingState::Preloaded);
+        assert_eq!(list.maximum_number_of_rooms(), Some(2));
+        assert_eq!(
+            list.room_list::<RoomListEntry>(),
+            vec![
+                RoomListEntry::Filled(room_id_0.to_owned()),
+                RoomListEntry::Filled(room_id_1.to_owned())
+            ]
+        );
+
+        // The rooms exist.
+        assert!(sliding_sync.get_room(room_id_0).await.is_some());
+        assert!(sliding_sync.get_room(room_id_1).await.is_some());
+
+        // A list can only be preloaded once.
+        assert!(!sliding_sync.preload_list("foo", vec![room_id_1.to_owned()]).await);
+        assert_eq!(list.room_list::<RoomListEntry>().len(), 2);
+
+        Ok(())
+    }
+
     #[test]
     fn test_sticky_parameters_api_invalidated_flow() {
         let r0 = room_id!("!room:example.org");
@@ -1929,6 +2176,211 @@ mod tests {
         Ok(())
     }
 
+    #[async_test]
+    async fn test_native_sliding_sync() -> Result<()> {
+        let server = MockServer::start().await;
+        let client = logged_in_client(Some(server.uri())).await;
+
+        let sliding_sync = client
+            .sliding_sync("native")?
+            .version(Version::Native)
+            .add_list(
+                SlidingSyncList::builder("all")
+                    .sync_mode(SlidingSyncMode::new_selective().add_range(0..=9)),
+            )
+            .build()
+            .await?;
+
+        assert!(sliding_sync.version().is_native());
+        assert!(sliding_sync.sliding_sync_proxy().is_none());
+
+        let room_id_0 = room_id!("!r0:bar.org");
+        let room_id_1 = room_id!("!r1:bar.org");
+        let room_id_2 = room_id!("!r2:bar.org");
+
+        // The server doesn't send the operations of the list.
+        let _mock_guard = Mock::given(method("POST"))
+            .and(path("/_matrix/client/unstable/org.matrix.simplified_msc3575/sync"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "pos": "0",
+                "lists": {
+                    "all": {
+                        "count": 3,
+                    },
+                },
+                "rooms": {
+                    room_id_0: {
+                        "name": "Room #0",
+                        "initial": true,
+                        "bump_stamp": 1,
+                    },
+                    room_id_1: {
+                        "name": "Room #1",
+                        "initial": true,
+                        "bump_stamp": 3,
+                    },
+                    room_id_2: {
+                        "name": "Room #2",
+                        "initial": true,
+                        "bump_stamp": 2,
+                    },
+                },
+            })))
+            .expect(1)
+            .mount_as_scoped(&server)
+            .await;
+
+        let stream = sliding_sync.sync();
+        pin_mut!(stream);
+
+        let update_summary = stream.next().await.unwrap()?;
+        assert_eq!(update_summary.lists, ["all"]);
+
+        // The parameters that the native endpoint doesn't support are not sent.
+        let requests = server.received_requests().await.unwrap();
+        let body: serde_json::Value = requests[0].body_json().unwrap();
+        assert!(body.get("txn_id").is_none());
+        assert!(body["lists"]["all"].get("sort").is_none());
+        assert_eq!(body["lists"]["all"]["ranges"], json!([[0, 9]]));
+
+        // The list is sorted by bump stamp.
+        let list = sliding_sync.on_list("all", |list| ready(list.clone())).await.unwrap();
+        assert_eq!(
+            list.room_list::<RoomListEntry>(),
+            [
+                RoomListEntry::Filled(room_id_1.to_owned()),
+                RoomListEntry::Filled(room_id_2.to_owned()),
+                RoomListEntry::Filled(room_id_0.to_owned()),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[async_test]
+    async fn test_native_sliding_sync_tracks_the_rooms_of_the_lists() -> Result<()> {
+        let server = MockServer::start().await;
+        let client = logged_in_client(Some(server.uri())).await;
+
+        let sliding_sync = client
+            .sliding_sync("native")?
+            .version(Version::Native)
+            .add_list(
+                SlidingSyncList::builder("all")
+                    .sync_mode(SlidingSyncMode::new_selective().add_range(0..=9))
+                    .filters(Some(assign!(v4::SyncRequestListFilters::default(), {
+                        is_invite: Some(false),
+                    }))),
+            )
+            .add_list(
+                SlidingSyncList::builder("invites")
+                    .sync_mode(SlidingSyncMode::new_selective().add_range(0..=9))
+                    .filters(Some(assign!(v4::SyncRequestListFilters::default(), {
+                        is_invite: Some(true),
+                    }))),
+            )
+            .build()
+            .await?;
+
+        let room_id_0 = room_id!("!r0:bar.org");
+        let room_id_1 = room_id!("!r1:bar.org");
+        let room_id_2 = room_id!("!r2:bar.org");
+        let room_id_3 = room_id!("!r3:bar.org");
+
+        let invite_state = json!([{
+            "content": { "membership": "invite" },
+            "sender": "@alice:bar.org",
+            "state_key": client.user_id().unwrap(),
+            "type": "m.room.member",
+        }]);
+
+        let stream = sliding_sync.sync();
+        pin_mut!(stream);
+
+        // The first response contains the operations of the lists.
+        {
+            let _mock_guard = Mock::given(method("POST"))
+                .and(path("/_matrix/client/unstable/org.matrix.simplified_msc3575/sync"))
+                .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                    "pos": "0",
+                    "lists": {
+                        "all": {
+                            "count": 2,
+                            "ops": [{
+                                "op": "SYNC",
+                                "range": [0, 1],
+                                "room_ids": [room_id_0, room_id_1],
+                            }],
+                        },
+                        "invites": {
+                            "count": 1,
+                            "ops": [{
+                                "op": "SYNC",
+                                "range": [0, 0],
+                                "room_ids": [room_id_2],
+                            }],
+                        },
+                    },
+                    "rooms": {
+                        room_id_0: { "initial": true, "bump_stamp": 2 },
+                        room_id_1: { "initial": true, "bump_stamp": 1 },
+                        room_id_2: { "initial": true, "bump_stamp": 3, "invite_state": invite_state },
+                    },
+                })))
+                .expect(1)
+                .mount_as_scoped(&server)
+                .await;
+
+            stream.next().await.unwrap()?;
+        }
+
+        // The second one doesn't, and contains a new invite that isn't part of any list
+        // yet.
+        {
+            let _mock_guard = Mock::given(method("POST"))
+                .and(path("/_matrix/client/unstable/org.matrix.simplified_msc3575/sync"))
+                .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                    "pos": "1",
+                    "lists": {
+                        "all": { "count": 2 },
+                        "invites": { "count": 2 },
+                    },
+                    "rooms": {
+                        room_id_1: { "bump_stamp": 4 },
+                        room_id_2: { "bump_stamp": 5 },
+                        room_id_3: { "initial": true, "bump_stamp": 6, "invite_state": invite_state },
+                    },
+                })))
+                .expect(1)
+                .mount_as_scoped(&server)
+                .await;
+
+            stream.next().await.unwrap()?;
+        }
+
+        // The rooms stay in their lists, which are sorted by bump stamp. The new invite
+        // only joins the list whose filters it matches.
+        let all = sliding_sync.on_list("all", |list| ready(list.clone())).await.unwrap();
+        assert_eq!(
+            all.room_list::<RoomListEntry>(),
+            [
+                RoomListEntry::Filled(room_id_1.to_owned()),
+                RoomListEntry::Filled(room_id_0.to_owned()),
+            ]
+        );
+
+        let invites = sliding_sync.on_list("invites", |list| ready(list.clone())).await.unwrap();
+        assert_eq!(
+            invites.room_list::<RoomListEntry>(),
+            [
+                RoomListEntry::Filled(room_id_3.to_owned()),
+                RoomListEntry::Filled(room_id_2.to_owned()),
+            ]
+        );
+
+        Ok(())
+    }
+
     #[async_test]
     async fn test_limited_flag_computation() {
         let server = MockServer::start().await;
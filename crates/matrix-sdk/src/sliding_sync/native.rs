@@ -0,0 +1,557 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The simplified sliding sync endpoint ([MSC4186]), implemented natively by
+//! homeservers.
+//!
+//! Sliding sync is built around the requests and responses of the sliding
+//! sync proxy ([MSC3575]). This module translates them for the native
+//! endpoint, which differs in a few ways:
+//!
+//! - there are no sticky parameters, so the transaction ID isn't sent and the
+//!   sticky parameters are never committed, i.e. they are sent with every
+//!   request,
+//! - room unsubscriptions don't exist, since the room subscriptions are sent
+//!   with every request,
+//! - the lists are always sorted by recency, and servers may not send the
+//!   `ops` of the lists, in which case [`sync_operations`] computes them
+//!   from the `bump_stamp` of the rooms, and [`RoomFacts`] finds out which
+//!   lists the new rooms belong to.
+//!
+//! [MSC3575]: https://github.com/matrix-org/matrix-spec-proposals/pull/3575
+//! [MSC4186]: https://github.com/matrix-org/matrix-spec-proposals/pull/4186
+
+use std::{cmp::Reverse, collections::BTreeMap, time::Duration};
+
+use bytes::BufMut;
+use http::header::{AUTHORIZATION, CONTENT_TYPE};
+use matrix_sdk_base::RoomState;
+use ruma::{
+    api::{
+        client::{sync::sync_events::v4, Error as ClientApiError},
+        error::FromHttpResponseError,
+        AuthScheme, EndpointError, IncomingResponse, IntoHttpError, MatrixVersion, Metadata,
+        OutgoingRequest, SendAccessToken, VersionHistory,
+    },
+    assign, OwnedRoomId, UInt,
+};
+use serde::{Deserialize, Serialize};
+
+/// The unstable feature advertised in `/versions` by homeservers that
+/// implement the simplified sliding sync API.
+pub(crate) const UNSTABLE_FEATURE: &str = "org.matrix.simplified_msc3575";
+
+const METADATA: Metadata = Metadata {
+    method: http::Method::POST,
+    rate_limited: false,
+    authentication: AuthScheme::AccessToken,
+    history: VersionHistory::new(
+        &["/_matrix/client/unstable/org.matrix.simplified_msc3575/sync"],
+        &[],
+        None,
+        None,
+    ),
+};
+
+/// A sliding sync request for the native endpoint.
+#[derive(Clone, Debug)]
+pub(super) struct Request {
+    /// The position in the stream of the previous response.
+    pos: Option<String>,
+
+    /// The maximum time to poll before responding to this request.
+    timeout: Option<Duration>,
+
+    /// The JSON body of the request.
+    body: RequestBody,
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct RequestBody {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    conn_id: Option<String>,
+
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    lists: BTreeMap<String, RequestList>,
+
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    room_subscriptions: BTreeMap<OwnedRoomId, v4::RoomSubscription>,
+
+    extensions: v4::ExtensionsConfig,
+}
+
+/// A list of a request, without the parameters that the native endpoint
+/// doesn't support, like `sort` or `bump_event_types`.
+#[derive(Clone, Debug, Serialize)]
+struct RequestList {
+    ranges: Vec<(UInt, UInt)>,
+
+    #[serde(flatten)]
+    room_details: v4::RoomDetailsConfig,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    include_heroes: Option<bool>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    filters: Option<v4::SyncRequestListFilters>,
+}
+
+impl From<v4::Request> for Request {
+    fn from(request: v4::Request) -> Self {
+        let lists = request
+            .lists
+            .into_iter()
+            .map(|(name, list)| {
+                let list = RequestList {
+                    ranges: list.ranges,
+                    room_details: list.room_details,
+                    include_heroes: list.include_heroes,
+                    filters: list.filters,
+                };
+
+                (name, list)
+            })
+            .collect();
+
+        Self {
+            pos: request.pos,
+            timeout: request.timeout,
+            body: RequestBody {
+                conn_id: request.conn_id,
+                lists,
+                room_subscriptions: request.room_subscriptions,
+                extensions: request.extensions,
+            },
+        }
+    }
+}
+
+impl OutgoingRequest for Request {
+    type EndpointError = ClientApiError;
+    type IncomingResponse = Response;
+
+    const METADATA: Metadata = METADATA;
+
+    fn try_into_http_request<T: Default + BufMut>(
+        self,
+        base_url: &str,
+        access_token: SendAccessToken<'_>,
+        considering_versions: &[MatrixVersion],
+    ) -> Result<http::Request<T>, IntoHttpError> {
+        let mut query = url::form_urlencoded::Serializer::new(String::new());
+        if let Some(pos) = &self.pos {
+            query.append_pair("pos", pos);
+        }
+        if let Some(timeout) = self.timeout {
+            query.append_pair("timeout", &timeout.as_millis().to_string());
+        }
+
+        let url =
+            METADATA.make_endpoint_url(considering_versions, base_url, &[], &query.finish())?;
+
+        let access_token =
+            access_token.get_required_for_endpoint().ok_or(IntoHttpError::NeedsAuthentication)?;
+
+        let mut body = T::default();
+        serde_json::to_writer((&mut body).writer(), &self.body)?;
+
+        Ok(http::Request::builder()
+            .method(METADATA.method)
+            .uri(url)
+            .header(CONTENT_TYPE, "application/json")
+            .header(AUTHORIZATION, format!("Bearer {access_token}"))
+            .body(body)?)
+    }
+}
+
+/// A sliding sync response of the native endpoint.
+#[derive(Debug)]
+pub(super) struct Response {
+    /// The response, translated to the sliding sync proxy format.
+    pub response: v4::Response,
+
+    /// The bump stamps of the rooms of the response that have one.
+    ///
+    /// The higher the bump stamp, the more recent the activity in the room.
+    pub bump_stamps: BTreeMap<OwnedRoomId, u64>,
+}
+
+#[derive(Deserialize)]
+struct ResponseBody {
+    pos: String,
+
+    #[serde(default)]
+    lists: BTreeMap<String, v4::SyncList>,
+
+    #[serde(default)]
+    rooms: BTreeMap<OwnedRoomId, ResponseRoom>,
+
+    #[serde(default)]
+    extensions: v4::Extensions,
+}
+
+#[derive(Deserialize)]
+struct ResponseRoom {
+    #[serde(flatten)]
+    room: v4::SlidingSyncRoom,
+
+    bump_stamp: Option<UInt>,
+}
+
+impl IncomingResponse for Response {
+    type EndpointError = ClientApiError;
+
+    fn try_from_http_response<T: AsRef<[u8]>>(
+        response: http::Response<T>,
+    ) -> Result<Self, FromHttpResponseError<ClientApiError>> {
+        if response.status().as_u16() >= 400 {
+            return Err(FromHttpResponseError::Server(ClientApiError::from_http_response(
+                response,
+            )));
+        }
+
+        let body: ResponseBody = serde_json::from_slice(response.body().as_ref())?;
+
+        let mut rooms = BTreeMap::new();
+        let mut bump_stamps = BTreeMap::new();
+
+        for (room_id, room) in body.rooms {
+            if let Some(bump_stamp) = room.bump_stamp {
+                bump_stamps.insert(room_id.clone(), bump_stamp.into());
+            }
+
+            rooms.insert(room_id, room.room);
+        }
+
+        let response = assign!(v4::Response::new(body.pos), {
+            lists: body.lists,
+            rooms,
+            extensions: body.extensions,
+        });
+
+        Ok(Self { response, bump_stamps })
+    }
+}
+
+/// What the client knows about a room of a response, to find out which lists
+/// the room belongs to when the server doesn't send the operations of the
+/// lists.
+///
+/// The facts come from the state events of the response, completed by the
+/// room known by the client, if any. A fact that can't be known is `None`.
+#[derive(Debug, Default)]
+pub(super) struct RoomFacts {
+    /// Whether the user is invited to the room.
+    pub is_invite: bool,
+
+    /// Whether the room was replaced by another room.
+    pub is_tombstoned: bool,
+
+    /// Whether the room is a direct message room.
+    pub is_dm: Option<bool>,
+
+    /// Whether the room is encrypted.
+    pub is_encrypted: Option<bool>,
+
+    /// The type of the room, from its creation event.
+    pub room_type: Option<String>,
+}
+
+/// The minimal fields of a state event that [`RoomFacts`] looks at.
+#[derive(Deserialize)]
+struct MinimalStateEvent {
+    #[serde(rename = "type")]
+    event_type: String,
+
+    #[serde(default)]
+    content: serde_json::Value,
+}
+
+impl RoomFacts {
+    /// Collect the facts about `room`, a room of a response, and `known_room`,
+    /// the same room as known by the client before the response is handled.
+    pub(super) async fn new(room: &v4::SlidingSyncRoom, known_room: Option<&crate::Room>) -> Self {
+        let events = room
+            .required_state
+            .iter()
+            .filter_map(|event| event.deserialize_as::<MinimalStateEvent>().ok())
+            .chain(
+                room.invite_state
+                    .iter()
+                    .flatten()
+                    .filter_map(|event| event.deserialize_as::<MinimalStateEvent>().ok()),
+            )
+            .collect::<Vec<_>>();
+        let has_event = |event_type: &str| events.iter().any(|e| e.event_type == event_type);
+
+        // A room with state or timeline events in the response isn't an invite
+        // anymore, even if the client didn't handle the new membership yet.
+        let is_invite = room.invite_state.is_some()
+            || (room.required_state.is_empty()
+                && room.timeline.is_empty()
+                && known_room.is_some_and(|known_room| known_room.state() == RoomState::Invited));
+
+        let room_type = events
+            .iter()
+            .find(|event| event.event_type == "m.room.create")
+            .map(|event| event.content.get("type").and_then(|t| t.as_str()).map(ToOwned::to_owned))
+            .unwrap_or_else(|| {
+                known_room.and_then(|known_room| known_room.room_type()).map(|t| t.to_string())
+            });
+
+        // The client may not know the encryption state of a room yet, so a room
+        // is only known to be encrypted, never to be unencrypted.
+        let is_encrypted = (has_event("m.room.encryption")
+            || known_room.is_some_and(|known_room| known_room.encryption_settings().is_some()))
+        .then_some(true);
+
+        let is_dm = match known_room {
+            Some(known_room) => known_room.is_direct().await.ok(),
+            None => None,
+        };
+
+        Self {
+            is_invite,
+            is_tombstoned: has_event("m.room.tombstone")
+                || known_room.is_some_and(|known_room| known_room.is_tombstoned()),
+            is_dm,
+            is_encrypted,
+            room_type,
+        }
+    }
+
+    /// Whether the room matches the `filters` of a list.
+    ///
+    /// Filters about facts that can't be known, like the spaces or the tags of
+    /// a new room, are considered to match: the server only sends rooms that
+    /// match the filters of a list or a room subscription.
+    pub(super) fn matches(&self, filters: Option<&v4::SyncRequestListFilters>) -> bool {
+        fn matches_flag(filter: Option<bool>, value: Option<bool>) -> bool {
+            match (filter, value) {
+                (Some(filter), Some(value)) => filter == value,
+                _ => true,
+            }
+        }
+
+        let Some(filters) = filters else {
+            return true;
+        };
+
+        matches_flag(filters.is_invite, Some(self.is_invite))
+            && matches_flag(filters.is_tombstoned, Some(self.is_tombstoned))
+            && matches_flag(filters.is_dm, self.is_dm)
+            && matches_flag(filters.is_encrypted, self.is_encrypted)
+            && !filters
+                .not_room_types
+                .iter()
+                .any(|room_type| Some(room_type.as_str()) == self.room_type.as_deref())
+    }
+}
+
+/// Compute the `SYNC` operations that set the requested ranges of a list, for
+/// a response that doesn't contain the operations of the list.
+///
+/// The rooms that were already in the list and the `new_rooms` are sorted by
+/// decreasing bump stamp, like the server sorts the list. Rooms with an equal
+/// or unknown bump stamp keep their relative order. Only the first `count`
+/// rooms are kept, and an operation is computed for every range that contains
+/// at least one of them.
+pub(super) fn sync_operations(
+    current_rooms: Vec<OwnedRoomId>,
+    new_rooms: impl IntoIterator<Item = OwnedRoomId>,
+    bump_stamps: &BTreeMap<OwnedRoomId, u64>,
+    ranges: &[(UInt, UInt)],
+    count: usize,
+) -> Vec<v4::SyncOp> {
+    let mut room_ids = current_rooms;
+
+    for room_id in new_rooms {
+        if !room_ids.contains(&room_id) {
+            room_ids.push(room_id);
+        }
+    }
+
+    room_ids.sort_by_key(|room_id| Reverse(bump_stamps.get(room_id).copied().unwrap_or_default()));
+    room_ids.truncate(count);
+
+    ranges
+        .iter()
+        .filter_map(|(start, end)| {
+            let start = usize::try_from(*start).ok()?;
+            let end =
+                usize::try_from(*end).unwrap_or(usize::MAX).min(room_ids.len().checked_sub(1)?);
+            let room_ids = room_ids.get(start..=end)?;
+
+            // The fields of `SyncOp` can't be set directly.
+            serde_json::from_value(serde_json::json!({
+                "op": v4::SlidingOp::Sync,
+                "range": [start, end],
+                "room_ids": room_ids,
+            }))
+            .ok()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use assert_matches::assert_matches;
+    use matrix_sdk_test::async_test;
+    use ruma::{api::client::sync::sync_events::v4, assign, owned_room_id, uint};
+    use serde_json::json;
+
+    use super::{sync_operations, RoomFacts};
+
+    #[test]
+    fn test_sync_operations_sorts_by_bump_stamp() {
+        let bump_stamps = BTreeMap::from([
+            (owned_room_id!("!a:b.c"), 10),
+            (owned_room_id!("!d:e.f"), 30),
+            (owned_room_id!("!g:h.i"), 20),
+        ]);
+
+        let operations = sync_operations(
+            vec![owned_room_id!("!a:b.c"), owned_room_id!("!j:k.l")],
+            [owned_room_id!("!d:e.f"), owned_room_id!("!g:h.i"), owned_room_id!("!a:b.c")],
+            &bump_stamps,
+            &[(uint!(0), uint!(9))],
+            3,
+        );
+
+        assert_eq!(operations.len(), 1);
+        assert_matches!(operations[0].op, v4::SlidingOp::Sync);
+        assert_eq!(operations[0].range, Some((uint!(0), uint!(2))));
+        assert_eq!(
+            operations[0].room_ids,
+            [owned_room_id!("!d:e.f"), owned_room_id!("!g:h.i"), owned_room_id!("!a:b.c")]
+        );
+    }
+
+    #[test]
+    fn test_sync_operations_respect_ranges() {
+        let bump_stamps = BTreeMap::from([
+            (owned_room_id!("!a:b.c"), 40),
+            (owned_room_id!("!d:e.f"), 30),
+            (owned_room_id!("!g:h.i"), 20),
+            (owned_room_id!("!j:k.l"), 10),
+        ]);
+
+        let operations = sync_operations(
+            vec![owned_room_id!("!j:k.l"), owned_room_id!("!g:h.i")],
+            [owned_room_id!("!d:e.f"), owned_room_id!("!a:b.c")],
+            &bump_stamps,
+            &[(uint!(0), uint!(0)), (uint!(2), uint!(5)), (uint!(6), uint!(9))],
+            4,
+        );
+
+        // The last range doesn't contain any room.
+        assert_eq!(operations.len(), 2);
+        assert_eq!(operations[0].range, Some((uint!(0), uint!(0))));
+        assert_eq!(operations[0].room_ids, [owned_room_id!("!a:b.c")]);
+        assert_eq!(operations[1].range, Some((uint!(2), uint!(3))));
+        assert_eq!(operations[1].room_ids, [owned_room_id!("!g:h.i"), owned_room_id!("!j:k.l")]);
+    }
+
+    #[test]
+    fn test_sync_operations_empty_list() {
+        assert!(sync_operations(Vec::new(), [], &BTreeMap::new(), &[(uint!(0), uint!(9))], 10)
+            .is_empty());
+    }
+
+    #[async_test]
+    async fn test_room_facts_from_response() {
+        let room: v4::SlidingSyncRoom = serde_json::from_value(json!({
+            "required_state": [
+                {
+                    "content": { "type": "m.space" },
+                    "event_id": "$create",
+                    "origin_server_ts": 1,
+                    "sender": "@alice:b.c",
+                    "state_key": "",
+                    "type": "m.room.create",
+                },
+                {
+                    "content": { "body": "Moved", "replacement_room": "!d:e.f" },
+                    "event_id": "$tombstone",
+                    "origin_server_ts": 2,
+                    "sender": "@alice:b.c",
+                    "state_key": "",
+                    "type": "m.room.tombstone",
+                },
+            ],
+        }))
+        .unwrap();
+
+        let facts = RoomFacts::new(&room, None).await;
+
+        assert!(!facts.is_invite);
+        assert!(facts.is_tombstoned);
+        assert_eq!(facts.is_dm, None);
+        assert_eq!(facts.is_encrypted, None);
+        assert_eq!(facts.room_type.as_deref(), Some("m.space"));
+
+        let room: v4::SlidingSyncRoom = serde_json::from_value(json!({
+            "invite_state": [
+                {
+                    "content": { "membership": "invite" },
+                    "sender": "@alice:b.c",
+                    "state_key": "@bob:b.c",
+                    "type": "m.room.member",
+                },
+            ],
+        }))
+        .unwrap();
+
+        let facts = RoomFacts::new(&room, None).await;
+
+        assert!(facts.is_invite);
+        assert!(!facts.is_tombstoned);
+        assert_eq!(facts.room_type, None);
+    }
+
+    #[test]
+    fn test_room_facts_match_filters() {
+        let joined_room = RoomFacts::default();
+        let invite = RoomFacts { is_invite: true, ..Default::default() };
+        let space = RoomFacts { room_type: Some("m.space".to_owned()), ..Default::default() };
+        let tombstoned_room = RoomFacts { is_tombstoned: true, ..Default::default() };
+
+        // Without filters, all the rooms match.
+        assert!(joined_room.matches(None));
+        assert!(space.matches(None));
+
+        let all_rooms = assign!(v4::SyncRequestListFilters::default(), {
+            is_tombstoned: Some(false),
+            not_room_types: vec!["m.space".to_owned()],
+        });
+
+        assert!(joined_room.matches(Some(&all_rooms)));
+        assert!(invite.matches(Some(&all_rooms)));
+        assert!(!space.matches(Some(&all_rooms)));
+        assert!(!tombstoned_room.matches(Some(&all_rooms)));
+
+        let invites = assign!(v4::SyncRequestListFilters::default(), { is_invite: Some(true) });
+
+        assert!(!joined_room.matches(Some(&invites)));
+        assert!(invite.matches(Some(&invites)));
+
+        // Facts that can't be known match.
+        let dms = assign!(v4::SyncRequestListFilters::default(), { is_dm: Some(true) });
+
+        assert!(joined_room.matches(Some(&dms)));
+        assert!(!RoomFacts { is_dm: Some(false), ..Default::default() }.matches(Some(&dms)));
+    }
+}
@@ -1,21 +1,24 @@
 use std::{collections::BTreeMap, time::Duration};
 
 use assert_matches2::assert_let;
-use futures_util::FutureExt;
+use futures_util::{AsyncReadExt, FutureExt};
 use matrix_sdk::{
     config::SyncSettings,
-    media::{MediaFormat, MediaRequest, MediaThumbnailSize},
+    media::{
+        CachedUrlPreview, MediaFormat, MediaRequest, MediaRetentionPolicy, MediaThumbnailSize,
+        UrlPreview,
+    },
     sync::RoomUpdate,
     test_utils::no_retry_test_client_with_server,
 };
-use matrix_sdk_base::{sync::RoomUpdates, RoomState};
+use matrix_sdk_base::{sync::RoomUpdates, RoomState, StateChanges, StateStore as _};
 use matrix_sdk_test::{
     async_test, sync_state_event,
     test_json::{
         self,
         sync::{MIXED_INVITED_ROOM_ID, MIXED_JOINED_ROOM_ID, MIXED_LEFT_ROOM_ID, MIXED_SYNC},
     },
-    JoinedRoomBuilder, SyncResponseBuilder, DEFAULT_TEST_ROOM_ID,
+    JoinedRoomBuilder, PresenceTestEvent, SyncResponseBuilder, DEFAULT_TEST_ROOM_ID,
 };
 use ruma::{
     api::client::{
@@ -34,15 +37,17 @@ use ruma::{
         room::{message::ImageMessageEventContent, ImageInfo, MediaSource},
         AnyInitialStateEvent,
     },
-    mxc_uri, room_id,
+    mxc_uri,
+    presence::PresenceState,
+    room_id,
     serde::Raw,
-    uint, user_id, OwnedUserId,
+    uint, user_id, MilliSecondsSinceUnixEpoch, OwnedUserId,
 };
 use serde_json::{json, Value as JsonValue};
 use stream_assert::{assert_next_matches, assert_pending};
 use tokio_stream::wrappers::BroadcastStream;
 use wiremock::{
-    matchers::{header, method, path, path_regex},
+    matchers::{body_partial_json, header, method, path, path_regex, query_param},
     Mock, Request, ResponseTemplate,
 };
 
@@ -357,6 +362,283 @@ async fn test_get_media_content() {
     }
 }
 
+#[async_test]
+async fn test_get_media_stream() {
+    let (client, server) = logged_in_client_with_server().await;
+
+    let request = MediaRequest {
+        source: MediaSource::Plain(mxc_uri!("mxc://localhost/textfile").to_owned()),
+        format: MediaFormat::File,
+    };
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/media/r0/download/localhost/textfile"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("Hello, World!"))
+        .mount(&server)
+        .await;
+
+    let stream = client.media().get_media_stream(&request).await.unwrap();
+    assert_eq!(stream.content_length(), Some(13));
+
+    let mut content = String::new();
+    stream.into_async_read().read_to_string(&mut content).await.unwrap();
+    assert_eq!(content, "Hello, World!");
+}
+
+#[async_test]
+async fn test_download_media_file_resumes() {
+    let (client, server) = logged_in_client_with_server().await;
+
+    let request = MediaRequest {
+        source: MediaSource::Plain(mxc_uri!("mxc://localhost/textfile").to_owned()),
+        format: MediaFormat::File,
+    };
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/media/r0/download/localhost/textfile"))
+        .and(header("range", "bytes=7-"))
+        .respond_with(ResponseTemplate::new(206).set_body_string("World!"))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let file_path = dir.path().join("textfile");
+    let partial_path = dir.path().join("textfile.partial");
+    std::fs::write(&partial_path, "Hello, ").unwrap();
+
+    client.media().download_media_file(&request, &file_path).await.unwrap();
+    assert_eq!(std::fs::read_to_string(&file_path).unwrap(), "Hello, World!");
+    assert!(!partial_path.exists());
+}
+
+#[async_test]
+async fn test_download_media_file_restarts_without_range_support() {
+    let (client, server) = logged_in_client_with_server().await;
+
+    let request = MediaRequest {
+        source: MediaSource::Plain(mxc_uri!("mxc://localhost/textfile").to_owned()),
+        format: MediaFormat::File,
+    };
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/media/r0/download/localhost/textfile"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("Hello, World!"))
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let file_path = dir.path().join("textfile");
+    let partial_path = dir.path().join("textfile.partial");
+    std::fs::write(&partial_path, "Hello, ").unwrap();
+
+    client.media().download_media_file(&request, &file_path).await.unwrap();
+    assert_eq!(std::fs::read_to_string(&file_path).unwrap(), "Hello, World!");
+    assert!(!partial_path.exists());
+}
+
+#[cfg(feature = "e2e-encryption")]
+#[async_test]
+async fn test_download_encrypted_media_file() {
+    use std::io::Read as _;
+
+    use matrix_sdk_base::crypto::AttachmentEncryptor;
+    use ruma::events::room::{EncryptedFile, EncryptedFileInit};
+
+    let (client, server) = logged_in_client_with_server().await;
+
+    let content = b"Hello, encrypted World!";
+    let mut encryptor = AttachmentEncryptor::new(&content[..]);
+    let mut encrypted = Vec::new();
+    encryptor.read_to_end(&mut encrypted).unwrap();
+    let keys = encryptor.finish();
+
+    let request = |uri: &str| {
+        let file: EncryptedFile = EncryptedFileInit {
+            url: uri.into(),
+            key: keys.key.clone(),
+            iv: keys.iv.clone(),
+            hashes: keys.hashes.clone(),
+            v: keys.version.clone(),
+        }
+        .into();
+        MediaRequest { source: MediaSource::Encrypted(Box::new(file)), format: MediaFormat::File }
+    };
+
+    // The rest of the content is decrypted after the part that was already
+    // downloaded.
+    Mock::given(method("GET"))
+        .and(path("/_matrix/media/r0/download/localhost/encrypted"))
+        .and(header("range", "bytes=7-"))
+        .respond_with(ResponseTemplate::new(206).set_body_bytes(encrypted[7..].to_vec()))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let file_path = dir.path().join("encrypted");
+    let partial_path = dir.path().join("encrypted.partial");
+    std::fs::write(&partial_path, &content[..7]).unwrap();
+
+    client
+        .media()
+        .download_media_file(&request("mxc://localhost/encrypted"), &file_path)
+        .await
+        .unwrap();
+    assert_eq!(std::fs::read(&file_path).unwrap(), content);
+    assert!(!partial_path.exists());
+
+    // Content that doesn't match its hash is never moved to the file.
+    let mut tampered = encrypted.clone();
+    *tampered.last_mut().unwrap() ^= 1;
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/media/r0/download/localhost/tampered"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(tampered))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let file_path = dir.path().join("tampered");
+    client
+        .media()
+        .download_media_file(&request("mxc://localhost/tampered"), &file_path)
+        .await
+        .unwrap_err();
+    assert!(!file_path.exists());
+    assert!(!dir.path().join("tampered.partial").exists());
+}
+
+#[async_test]
+async fn test_upload_stream() {
+    let (client, server) = logged_in_client_with_server().await;
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/media/r0/upload"))
+        .and(header("content-type", "text/plain"))
+        .and(header("content-length", "13"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "content_uri": "mxc://localhost/textfile"
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let reader = futures_util::io::Cursor::new(b"Hello, World!".to_vec());
+    let upload = client.media().upload_stream(&mime::TEXT_PLAIN, reader, 13);
+    let progress = upload.subscribe_to_send_progress();
+
+    let response = upload.await.unwrap();
+    assert_eq!(response.content_uri, "mxc://localhost/textfile");
+
+    let progress = progress.get();
+    assert_eq!(progress.current, 13);
+    assert_eq!(progress.total, 13);
+}
+
+#[async_test]
+async fn test_get_media_content_authenticated() {
+    let (client, server) = logged_in_client_with_server().await;
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/versions"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(json!({ "versions": ["v1.10", "v1.11"] })),
+        )
+        .mount(&server)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/v1/media/download/localhost/textfile"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("Hello, World!"))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/v1/media/thumbnail/localhost/textfile"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("Hello"))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    assert!(client.server_supports_authenticated_media().await.unwrap());
+
+    let request = MediaRequest {
+        source: MediaSource::Plain(mxc_uri!("mxc://localhost/textfile").to_owned()),
+        format: MediaFormat::File,
+    };
+    let content = client.media().get_media_content(&request, false).await.unwrap();
+    assert_eq!(content, b"Hello, World!");
+
+    let request = MediaRequest {
+        format: MediaFormat::Thumbnail(MediaThumbnailSize {
+            method: Method::Scale,
+            width: uint!(100),
+            height: uint!(100),
+        }),
+        ..request
+    };
+    let content = client.media().get_media_content(&request, false).await.unwrap();
+    assert_eq!(content, b"Hello");
+}
+
+#[async_test]
+async fn test_get_media_content_falls_back_to_legacy_endpoints() {
+    let (client, server) = logged_in_client_with_server().await;
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/versions"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "versions": ["v1.9", "v1.10"],
+            "unstable_features": { "org.matrix.msc3916.stable": false },
+        })))
+        .mount(&server)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/media/r0/download/localhost/textfile"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("Hello, World!"))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    assert!(!client.server_supports_authenticated_media().await.unwrap());
+
+    let request = MediaRequest {
+        source: MediaSource::Plain(mxc_uri!("mxc://localhost/textfile").to_owned()),
+        format: MediaFormat::File,
+    };
+    let content = client.media().get_media_content(&request, false).await.unwrap();
+    assert_eq!(content, b"Hello, World!");
+}
+
+#[async_test]
+async fn test_max_upload_size_authenticated() {
+    let (client, server) = logged_in_client_with_server().await;
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/versions"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "versions": ["v1.10"],
+            "unstable_features": { "org.matrix.msc3916.stable": true },
+        })))
+        .mount(&server)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/v1/media/config"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "m.upload.size": 1024 })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    assert_eq!(client.media().max_upload_size().await.unwrap(), Some(uint!(1024)));
+}
+
 #[async_test]
 async fn test_get_media_file() {
     let (client, server) = logged_in_client_with_server().await;
@@ -1260,3 +1542,206 @@ async fn test_test_ambiguity_changes() {
 
     assert_pending!(updates);
 }
+
+#[async_test]
+async fn test_presence() {
+    let (client, server) = logged_in_client_with_server().await;
+    let user_id = user_id!("@example:localhost");
+
+    // The presence is fetched from the homeserver when it's not in the store.
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/.*/presence/.*/status"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "presence": "unavailable",
+            "last_active_ago": 420845,
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let presence = client.get_presence(user_id).await.unwrap();
+    assert_eq!(presence.state, PresenceState::Unavailable);
+    assert_eq!(presence.last_active_ago, Some(Duration::from_millis(420845)));
+    assert_eq!(presence.status_msg, None);
+
+    let mut subscriber = client.subscribe_to_presence(user_id).await.unwrap();
+    assert_eq!(subscriber.get(), PresenceState::Unavailable);
+
+    // The presence events received in the sync update the subscriber.
+    let mut sync_builder = SyncResponseBuilder::new();
+    sync_builder.add_presence_event(PresenceTestEvent::Presence);
+    mock_sync(&server, sync_builder.build_json_sync_response(), None).await;
+    client.sync_once(SyncSettings::default()).await.unwrap();
+    server.reset().await;
+
+    assert_eq!(subscriber.next().await, Some(PresenceState::Online));
+
+    // The presence received in the sync is now returned without a request.
+    let presence = client.get_presence(user_id).await.unwrap();
+    assert_eq!(presence.state, PresenceState::Online);
+    assert_eq!(presence.status_msg.as_deref(), Some("Making cupcakes"));
+    assert_eq!(presence.currently_active, Some(false));
+
+    // Set the presence of the current user.
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/.*/presence/.*/status"))
+        .and(body_partial_json(json!({
+            "presence": "online",
+            "status_msg": "Busy",
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    client.account().set_presence(PresenceState::Online, Some("Busy")).await.unwrap();
+}
+
+#[async_test]
+async fn test_presence_stored_in_previous_session_is_fetched_again() {
+    let (client, server) = logged_in_client_with_server().await;
+    let user_id = user_id!("@example:localhost");
+
+    // A presence that was received during a previous session.
+    let mut changes = StateChanges::default();
+    changes.presence.insert(
+        user_id.to_owned(),
+        Raw::new(&json!({
+            "type": "m.presence",
+            "sender": user_id,
+            "content": {
+                "presence": "online",
+                "last_active_ago": 10,
+            },
+        }))
+        .unwrap()
+        .cast(),
+    );
+    client.store().save_changes(&changes).await.unwrap();
+
+    // It may be outdated, so the presence is fetched from the homeserver.
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/.*/presence/.*/status"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "presence": "offline",
+            "last_active_ago": 420845,
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let presence = client.get_presence(user_id).await.unwrap();
+    assert_eq!(presence.state, PresenceState::Offline);
+    assert_eq!(presence.last_active_ago, Some(Duration::from_millis(420845)));
+}
+
+#[async_test]
+async fn test_get_url_preview() {
+    let (client, server) = logged_in_client_with_server().await;
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/versions"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "versions": ["v1.9", "v1.10"],
+            "unstable_features": { "org.matrix.msc3916.stable": false },
+        })))
+        .mount(&server)
+        .await;
+
+    // Some homeservers send the integers as strings.
+    Mock::given(method("GET"))
+        .and(path_regex(r"/preview_url$"))
+        .and(header("authorization", "Bearer 1234"))
+        .and(query_param("url", "https://example.org/"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "og:title": "Example",
+            "og:image": "mxc://localhost/image",
+            "og:image:width": "640",
+            "og:image:height": 480,
+            "matrix:image:size": "not a number",
+        })))
+        .expect(2)
+        .mount(&server)
+        .await;
+
+    let preview = client.media().get_url_preview("https://example.org/", None).await.unwrap();
+    assert_eq!(preview.title.as_deref(), Some("Example"));
+    assert_eq!(preview.image.as_deref(), Some(mxc_uri!("mxc://localhost/image")));
+    assert_eq!(preview.image_width, Some(uint!(640)));
+    assert_eq!(preview.image_height, Some(uint!(480)));
+    assert_eq!(preview.image_size, None);
+
+    // The second time, the preview comes from the cache.
+    let preview = client.media().get_url_preview("https://example.org/", None).await.unwrap();
+    assert_eq!(preview.title.as_deref(), Some("Example"));
+
+    // The preview for another point in time is cached separately.
+    let preview = client
+        .media()
+        .get_url_preview("https://example.org/", Some(MilliSecondsSinceUnixEpoch(uint!(1))))
+        .await
+        .unwrap();
+    assert_eq!(preview.title.as_deref(), Some("Example"));
+
+    // The previews are part of the media cache.
+    assert!(client.media().cache_size().await.unwrap() > 0);
+
+    // An expired preview is fetched again.
+    let stale_request = UrlPreview::cache_request("https://example.org/stale", None);
+    let stale = CachedUrlPreview {
+        preview: UrlPreview { title: Some("Stale".to_owned()), ..Default::default() },
+        fetched_at: MilliSecondsSinceUnixEpoch(uint!(0)),
+    };
+    client
+        .store()
+        .add_media_content(&stale_request, serde_json::to_vec(&stale).unwrap())
+        .await
+        .unwrap();
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"/preview_url$"))
+        .and(header("authorization", "Bearer 1234"))
+        .and(query_param("url", "https://example.org/stale"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "og:title": "Fresh" })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let preview = client.media().get_url_preview("https://example.org/stale", None).await.unwrap();
+    assert_eq!(preview.title.as_deref(), Some("Fresh"));
+
+    let content = client.store().get_media_content(&stale_request).await.unwrap().unwrap();
+    let cached: CachedUrlPreview = serde_json::from_slice(&content).unwrap();
+    assert_eq!(cached.preview.title.as_deref(), Some("Fresh"));
+
+    // An expired preview that can't be fetched again is removed from the cache.
+    let gone_request = UrlPreview::cache_request("https://example.org/gone", None);
+    let stale = CachedUrlPreview {
+        preview: UrlPreview { title: Some("Gone".to_owned()), ..Default::default() },
+        fetched_at: MilliSecondsSinceUnixEpoch(uint!(0)),
+    };
+    client
+        .store()
+        .add_media_content(&gone_request, serde_json::to_vec(&stale).unwrap())
+        .await
+        .unwrap();
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"/preview_url$"))
+        .and(query_param("url", "https://example.org/gone"))
+        .respond_with(ResponseTemplate::new(404).set_body_json(json!({
+            "errcode": "M_NOT_FOUND",
+            "error": "Not found",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    client.media().get_url_preview("https://example.org/gone", None).await.unwrap_err();
+    assert!(client.store().get_media_content(&gone_request).await.unwrap().is_none());
+
+    // The previews are evicted with the other media files.
+    client.media().set_retention_policy(MediaRetentionPolicy::empty().with_max_cache_size(Some(0)));
+    client.media().clean_up_cache().await.unwrap();
+    assert_eq!(client.media().cache_size().await.unwrap(), 0);
+}
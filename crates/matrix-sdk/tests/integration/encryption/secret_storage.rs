@@ -663,3 +663,183 @@ async fn is_secret_storage_enabled() {
         );
     }
 }
+
+async fn mock_missing_account_data(server: &MockServer, user_id: &UserId, event_type: &str) {
+    Mock::given(method("GET"))
+        .and(path(format!("_matrix/client/r0/user/{user_id}/account_data/{event_type}")))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(404).set_body_json(json!({
+            "errcode": "M_NOT_FOUND",
+            "error": "Account data not found."
+        })))
+        .named(format!("{event_type} account data GET"))
+        .mount(server)
+        .await;
+}
+
+#[async_test]
+async fn test_secret_storage_inventory() {
+    let (client, server) = logged_in_client_with_server().await;
+    let user_id = client.user_id().unwrap();
+
+    mock_secret_store_key(
+        &server,
+        user_id,
+        "bmur2d9ypPUH1msSwCxQOJkuKRmJI55e",
+        "xv5b6/p3ExEw++wTyfSHEg==",
+        "ujBBbXahnTAMkmPUX2/0+VTfUh63pGyVRuBcDMgmJC8=",
+    )
+    .await;
+
+    // An older key, created by another client, which still encrypts the master key.
+    Mock::given(method("GET"))
+        .and(path(format!(
+            "_matrix/client/r0/user/{user_id}/account_data/m.secret_storage.key.old_key"
+        )))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "algorithm": "m.secret_storage.v1.aes-hmac-sha2",
+            "name": "Old key",
+            "iv": "xv5b6/p3ExEw++wTyfSHEg==",
+            "mac": "ujBBbXahnTAMkmPUX2/0+VTfUh63pGyVRuBcDMgmJC8=",
+        })))
+        .expect(1..)
+        .named("old key account data GET")
+        .mount(&server)
+        .await;
+
+    // A key which has been deleted, but is still referenced by the user-signing
+    // key.
+    Mock::given(method("GET"))
+        .and(path(format!(
+            "_matrix/client/r0/user/{user_id}/account_data/m.secret_storage.key.deleted_key"
+        )))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+        .expect(1..)
+        .named("deleted key account data GET")
+        .mount(&server)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path(format!("_matrix/client/r0/user/{user_id}/account_data/m.cross_signing.master")))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "encrypted": {
+                "bmur2d9ypPUH1msSwCxQOJkuKRmJI55e": {
+                    "ciphertext": "lCRSSA1lChONEXj/8RyogsgAa8ouQwYDnLr4XBCheRikrZykLRzPCx3doCE=",
+                    "iv": "bdfCwu+ECYgZ/jWTkGrQ/A==",
+                    "mac": "NXeV1dZaOe2JLvQ6Hh6tFto7AgFFdaQnY0l9pruwdtE="
+                },
+                "old_key": {
+                    "ciphertext": "lCRSSA1lChONEXj/8RyogsgAa8ouQwYDnLr4XBCheRikrZykLRzPCx3doCE=",
+                    "iv": "bdfCwu+ECYgZ/jWTkGrQ/A==",
+                    "mac": "NXeV1dZaOe2JLvQ6Hh6tFto7AgFFdaQnY0l9pruwdtE="
+                }
+            }
+        })))
+        .expect(1..)
+        .named("m.cross_signing.master account data GET")
+        .mount(&server)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path(format!(
+            "_matrix/client/r0/user/{user_id}/account_data/m.cross_signing.user_signing"
+        )))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "encrypted": {
+                "deleted_key": {
+                    "ciphertext": "lCRSSA1lChONEXj/8RyogsgAa8ouQwYDnLr4XBCheRikrZykLRzPCx3doCE=",
+                    "iv": "bdfCwu+ECYgZ/jWTkGrQ/A==",
+                    "mac": "NXeV1dZaOe2JLvQ6Hh6tFto7AgFFdaQnY0l9pruwdtE="
+                }
+            }
+        })))
+        .expect(1..)
+        .named("m.cross_signing.user_signing account data GET")
+        .mount(&server)
+        .await;
+
+    mock_missing_account_data(&server, user_id, "m.cross_signing.self_signing").await;
+    mock_missing_account_data(&server, user_id, "m.megolm_backup.v1").await;
+
+    let secret_storage = client.encryption().secret_storage();
+    let inventory =
+        secret_storage.inventory().await.expect("We should be able to collect the inventory");
+
+    assert_eq!(inventory.default_key_id.as_deref(), Some("bmur2d9ypPUH1msSwCxQOJkuKRmJI55e"));
+    assert_eq!(inventory.keys.len(), 3);
+
+    let default_key = inventory.default_key().expect("The default key should be in the inventory");
+    assert!(default_key.is_default);
+    assert!(default_key.description_found);
+    assert_eq!(default_key.secrets, vec![SecretName::CrossSigningMasterKey]);
+
+    let old_key = &inventory.keys["old_key"];
+    assert!(!old_key.is_default);
+    assert!(old_key.description_found);
+    assert_eq!(old_key.name.as_deref(), Some("Old key"));
+    assert!(!old_key.is_orphaned());
+
+    let deleted_key = &inventory.keys["deleted_key"];
+    assert!(!deleted_key.description_found);
+    assert!(deleted_key.is_orphaned());
+
+    let report = inventory.health_report();
+    assert!(!report.is_healthy());
+    assert!(!report.default_key_missing);
+    assert_eq!(
+        report.secrets_missing_from_default_key,
+        vec![
+            SecretName::CrossSigningSelfSigningKey,
+            SecretName::CrossSigningUserSigningKey,
+            SecretName::RecoveryKey
+        ]
+    );
+    assert!(
+        report.secrets_in_other_keys.is_empty(),
+        "The user-signing key is only encrypted by a deleted key, it can't be recovered"
+    );
+    assert_eq!(report.orphaned_keys, vec!["deleted_key".to_owned()]);
+
+    server.verify().await;
+}
+
+#[async_test]
+async fn test_secret_storage_key_management_errors() {
+    let (client, server) = logged_in_client_with_server().await;
+    let user_id = client.user_id().unwrap();
+
+    mock_secret_store_key(
+        &server,
+        user_id,
+        "bmur2d9ypPUH1msSwCxQOJkuKRmJI55e",
+        "xv5b6/p3ExEw++wTyfSHEg==",
+        "ujBBbXahnTAMkmPUX2/0+VTfUh63pGyVRuBcDMgmJC8=",
+    )
+    .await;
+    mock_missing_account_data(&server, user_id, "m.secret_storage.key.unknown_key").await;
+
+    let secret_storage = client.encryption().secret_storage();
+
+    assert_matches!(
+        secret_storage.delete_key("bmur2d9ypPUH1msSwCxQOJkuKRmJI55e").await,
+        Err(SecretStorageError::DefaultKeyDeletion { key_id })
+            if key_id == "bmur2d9ypPUH1msSwCxQOJkuKRmJI55e",
+        "The default key should not be deletable"
+    );
+
+    assert_matches!(
+        secret_storage.set_default_key("unknown_key").await,
+        Err(SecretStorageError::MissingKeyInfo { key_id: Some(key_id) }) if key_id == "unknown_key",
+        "A key without a description can't become the default key"
+    );
+
+    let secret_store = secret_storage
+        .open_secret_store_with_key_id("bmur2d9ypPUH1msSwCxQOJkuKRmJI55e", SECRET_STORE_KEY)
+        .await
+        .expect("We should be able to open the secret store using an explicit key ID");
+    assert_eq!(secret_store.key_id(), "bmur2d9ypPUH1msSwCxQOJkuKRmJI55e");
+}
@@ -1,14 +1,18 @@
 use std::{
     collections::BTreeMap,
     sync::{Arc, Mutex},
+    time::Duration,
 };
 
 use assert_matches2::assert_matches;
-use futures_util::FutureExt;
+use futures_util::{FutureExt, StreamExt};
 use imbl::HashSet;
 use matrix_sdk::{
     config::RequestConfig,
-    encryption::VerificationState,
+    encryption::{
+        verification::{SessionVerification, SessionVerificationState},
+        VerificationState,
+    },
     matrix_auth::{MatrixSession, MatrixSessionTokens},
     test_utils::logged_in_client_with_server,
     Client,
@@ -22,9 +26,10 @@ use ruma::{
     serde::Raw,
     user_id, DeviceId, DeviceKeyId, OwnedDeviceId, OwnedUserId,
 };
-use serde_json::json;
+use serde_json::{json, Value as JsonValue};
+use tokio::time::timeout;
 use wiremock::{
-    matchers::{body_json, method, path},
+    matchers::{body_json, method, path, path_regex},
     Mock, MockServer, Request, ResponseTemplate,
 };
 
@@ -496,6 +501,102 @@ async fn test_reset_cross_signing_resets_verification() {
     );
 }
 
+#[async_test]
+async fn test_session_verification_methods() {
+    let mut server = MockedServer::new().await;
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/user/.*/account_data/m.secret_storage.default_key"))
+        .respond_with(ResponseTemplate::new(404).set_body_json(json!({
+            "errcode": "M_NOT_FOUND",
+            "error": "Account data not found"
+        })))
+        .mount(&server.server)
+        .await;
+
+    let user_id = owned_user_id!("@alice:example.org");
+    let device_id = owned_device_id!("4L1C3");
+    let alice = Client::builder()
+        .homeserver_url(server.server.uri())
+        .server_versions([MatrixVersion::V1_0])
+        .request_config(RequestConfig::new().disable_retry())
+        .build()
+        .await
+        .unwrap();
+    alice
+        .restore_session(MatrixSession {
+            meta: SessionMeta { user_id: user_id.clone(), device_id: device_id.clone() },
+            tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+        })
+        .await
+        .unwrap();
+
+    server.add_known_device(&device_id);
+
+    // Have Alice bootstrap cross-signing, this signs her first device.
+    bootstrap_cross_signing(&alice).await;
+
+    {
+        let mut sync_response_builder = SyncResponseBuilder::new();
+        sync_response_builder.add_change_device(&user_id);
+
+        let _scope = mock_sync_scoped(
+            &server.server,
+            sync_response_builder.build_json_sync_response(),
+            None,
+        )
+        .await;
+        alice.sync_once(Default::default()).await.unwrap();
+    }
+
+    let session_verification = alice.encryption().session_verification();
+    assert_matches!(session_verification.state(), SessionVerificationState::Unknown);
+    session_verification.refresh_state().await.unwrap();
+    assert_matches!(session_verification.state(), SessionVerificationState::Verified);
+
+    // Alice logs in on a second device.
+    let device_id = owned_device_id!("AliceDevice2");
+    let alice2 = Client::builder()
+        .homeserver_url(server.server.uri())
+        .server_versions([MatrixVersion::V1_0])
+        .request_config(RequestConfig::new().disable_retry())
+        .build()
+        .await
+        .unwrap();
+    alice2
+        .restore_session(MatrixSession {
+            meta: SessionMeta { user_id: user_id.clone(), device_id: device_id.clone() },
+            tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+        })
+        .await
+        .unwrap();
+
+    server.add_known_device(&device_id);
+
+    {
+        let sync_response_builder = SyncResponseBuilder::new();
+        let _scope = mock_sync_scoped(
+            &server.server,
+            sync_response_builder.build_json_sync_response(),
+            None,
+        )
+        .await;
+        alice2.sync_once(Default::default()).await.unwrap();
+    }
+
+    let session_verification = alice2.encryption().session_verification();
+    session_verification.refresh_state().await.unwrap();
+    assert_matches!(session_verification.state(), SessionVerificationState::Unverified);
+
+    // The first device is cross-signed and can verify the second one, there's no
+    // secret storage so recovery isn't an option.
+    let methods = session_verification.available_methods().await.unwrap();
+    assert!(methods.other_devices);
+    assert!(!methods.recovery);
+    assert!(!methods.only_recovery());
+    assert!(!methods.is_empty());
+}
+
 #[async_test]
 async fn test_unchecked_mutual_verification() {
     let mut server = MockedServer::new().await;
@@ -741,3 +842,280 @@ async fn test_request_user_identity() {
     assert_matches!(encryption.request_user_identity(bob_id).await, Ok(Some(_)));
     assert_matches!(encryption.get_user_identity(bob_id).await, Ok(Some(_)));
 }
+
+/// Captures the to-device messages sent by the clients, so they can be
+/// delivered to their recipients with a sync.
+///
+/// The clients must use their device ID as their access token, so the sender
+/// of a message can be identified.
+#[derive(Clone, Default)]
+struct ToDeviceRelay {
+    /// The pending messages, as the device ID of the sender, the device ID of
+    /// the recipient or `*`, and the event.
+    messages: Arc<Mutex<Vec<(String, String, JsonValue)>>>,
+}
+
+impl ToDeviceRelay {
+    async fn mock_endpoint(&self, server: &MockServer) {
+        let messages = self.messages.clone();
+
+        Mock::given(method("PUT"))
+            .and(path_regex(r"^/_matrix/client/r0/sendToDevice/.*"))
+            .respond_with(move |req: &Request| {
+                let event_type = req.url.path_segments().unwrap().nth_back(1).unwrap().to_owned();
+                let sender_device = req
+                    .headers
+                    .get("authorization")
+                    .unwrap()
+                    .to_str()
+                    .unwrap()
+                    .trim_start_matches("Bearer ")
+                    .to_owned();
+                let body: JsonValue = req.body_json().unwrap();
+
+                let mut messages = messages.lock().unwrap();
+
+                // All the clients belong to the same user, so the recipient is the sender.
+                for (user_id, devices) in body["messages"].as_object().unwrap() {
+                    for (device_id, content) in devices.as_object().unwrap() {
+                        messages.push((
+                            sender_device.clone(),
+                            device_id.clone(),
+                            json!({
+                                "type": event_type,
+                                "sender": user_id,
+                                "content": content,
+                            }),
+                        ));
+                    }
+                }
+
+                ResponseTemplate::new(200).set_body_json(json!({}))
+            })
+            .mount(server)
+            .await;
+    }
+
+    /// Deliver the pending messages to the clients with a sync, until the
+    /// clients don't send any more messages.
+    async fn deliver_all(&self, server: &MockServer, clients: &[&Client]) {
+        loop {
+            let messages = std::mem::take(&mut *self.messages.lock().unwrap());
+
+            if messages.is_empty() {
+                break;
+            }
+
+            for client in clients {
+                let device_id = client.device_id().unwrap().as_str();
+                let events: Vec<_> = messages
+                    .iter()
+                    .filter(|(sender, recipient, _)| {
+                        recipient == device_id || (recipient == "*" && sender != device_id)
+                    })
+                    .map(|(_, _, event)| event.clone())
+                    .collect();
+
+                let mut response = SyncResponseBuilder::new().build_json_sync_response();
+                response["to_device"] = json!({ "events": events });
+
+                let _scope = mock_sync_scoped(server, response, None).await;
+                client.sync_once(Default::default()).await.unwrap();
+            }
+        }
+    }
+}
+
+/// Wait for the session verification to reach a state matching the given
+/// predicate.
+async fn wait_for_session_verification_state(
+    session_verification: &SessionVerification,
+    predicate: impl Fn(&SessionVerificationState) -> bool,
+) -> SessionVerificationState {
+    let mut states = session_verification.state_stream();
+
+    timeout(Duration::from_secs(5), async {
+        while let Some(state) = states.next().await {
+            if predicate(&state) {
+                return state;
+            }
+        }
+
+        panic!("the session verification state stream ended");
+    })
+    .await
+    .expect("the session verification didn't reach the expected state")
+}
+
+/// Set up a cross-signed device for Alice, and a second unverified one, both
+/// aware of each other.
+async fn set_up_unverified_second_device() -> (MockedServer, ToDeviceRelay, Client, Client) {
+    let mut server = MockedServer::new().await;
+    let relay = ToDeviceRelay::default();
+    relay.mock_endpoint(&server.server).await;
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/user/.*/account_data/m.secret_storage.default_key"))
+        .respond_with(ResponseTemplate::new(404).set_body_json(json!({
+            "errcode": "M_NOT_FOUND",
+            "error": "Account data not found"
+        })))
+        .mount(&server.server)
+        .await;
+
+    let user_id = owned_user_id!("@alice:example.org");
+    let mut clients = Vec::new();
+
+    for device_id in [owned_device_id!("4L1C3"), owned_device_id!("AliceDevice2")] {
+        let client = Client::builder()
+            .homeserver_url(server.server.uri())
+            .server_versions([MatrixVersion::V1_0])
+            .request_config(RequestConfig::new().disable_retry())
+            .build()
+            .await
+            .unwrap();
+        client
+            .restore_session(MatrixSession {
+                meta: SessionMeta { user_id: user_id.clone(), device_id: device_id.clone() },
+                tokens: MatrixSessionTokens {
+                    access_token: device_id.to_string(),
+                    refresh_token: None,
+                },
+            })
+            .await
+            .unwrap();
+
+        server.add_known_device(&device_id);
+        clients.push(client);
+    }
+
+    let alice2 = clients.pop().unwrap();
+    let alice = clients.pop().unwrap();
+
+    // Have Alice bootstrap cross-signing on her first device.
+    bootstrap_cross_signing(&alice).await;
+
+    // Have both devices upload their keys.
+    {
+        let mut sync_response_builder = SyncResponseBuilder::new();
+        let _scope = mock_sync_scoped(
+            &server.server,
+            sync_response_builder.build_json_sync_response(),
+            None,
+        )
+        .await;
+        alice.sync_once(Default::default()).await.unwrap();
+        alice2.sync_once(Default::default()).await.unwrap();
+    }
+
+    // Notify both devices that the devices of Alice changed, so they fetch the
+    // keys of each other.
+    {
+        let mut sync_response_builder = SyncResponseBuilder::new();
+        sync_response_builder.add_change_device(&user_id);
+
+        let _scope = mock_sync_scoped(
+            &server.server,
+            sync_response_builder.build_json_sync_response(),
+            None,
+        )
+        .await;
+        alice.sync_once(Default::default()).await.unwrap();
+        alice2.sync_once(Default::default()).await.unwrap();
+    }
+
+    let session_verification = alice2.encryption().session_verification();
+    session_verification.refresh_state().await.unwrap();
+    assert_matches!(session_verification.state(), SessionVerificationState::Unverified);
+    assert!(session_verification.available_methods().await.unwrap().other_devices);
+
+    (server, relay, alice, alice2)
+}
+
+#[async_test]
+async fn test_session_verification_cancel() {
+    let (server, relay, alice, alice2) = set_up_unverified_second_device().await;
+
+    let session_verification = alice2.encryption().session_verification();
+    let request = session_verification.request_device_verification().await.unwrap();
+    assert_matches!(
+        session_verification.state(),
+        SessionVerificationState::WaitingForOtherDevice { .. }
+    );
+
+    // The request reaches the other device.
+    relay.deliver_all(&server.server, &[&alice, &alice2]).await;
+    let alice_request = alice
+        .encryption()
+        .get_verification_request(alice.user_id().unwrap(), request.flow_id())
+        .await
+        .expect("the first device should have received the request");
+
+    // Cancelling the request publishes the cancelled state right away.
+    session_verification.cancel().await.unwrap();
+    assert_matches!(session_verification.state(), SessionVerificationState::Cancelled(info));
+    assert!(info.cancelled_by_us());
+
+    // The other device is notified of the cancellation.
+    relay.deliver_all(&server.server, &[&alice, &alice2]).await;
+    assert!(alice_request.is_cancelled());
+
+    // Cancelling again doesn't do anything.
+    session_verification.cancel().await.unwrap();
+    assert_matches!(session_verification.state(), SessionVerificationState::Cancelled(_));
+}
+
+#[async_test]
+async fn test_session_verification_with_other_device() {
+    let (server, relay, alice, alice2) = set_up_unverified_second_device().await;
+    let clients = [&alice, &alice2];
+    let user_id = alice.user_id().unwrap();
+
+    let session_verification = alice2.encryption().session_verification();
+    let request = session_verification.request_device_verification().await.unwrap();
+    let flow_id = request.flow_id().to_owned();
+    relay.deliver_all(&server.server, &clients).await;
+
+    // The first device accepts the request.
+    let alice_request =
+        alice.encryption().get_verification_request(user_id, &flow_id).await.unwrap();
+    alice_request.accept().await.unwrap();
+    relay.deliver_all(&server.server, &clients).await;
+
+    wait_for_session_verification_state(&session_verification, |state| {
+        matches!(state, SessionVerificationState::RequestAccepted { .. })
+    })
+    .await;
+
+    // The second device starts a SAS verification, which the first device
+    // accepts.
+    request.start_sas().await.unwrap().unwrap();
+    relay.deliver_all(&server.server, &clients).await;
+
+    let state = wait_for_session_verification_state(&session_verification, |state| {
+        matches!(state, SessionVerificationState::Verifying { .. })
+    })
+    .await;
+    assert_matches!(state, SessionVerificationState::Verifying { verification });
+    let sas = verification.sas().unwrap();
+
+    let alice_sas = alice.encryption().get_verification(user_id, &flow_id).await.unwrap();
+    let alice_sas = alice_sas.sas().unwrap();
+    alice_sas.accept().await.unwrap();
+    relay.deliver_all(&server.server, &clients).await;
+
+    // Both sides see the same short authentication string and confirm it.
+    assert!(sas.can_be_presented());
+    assert_eq!(sas.decimals().unwrap(), alice_sas.decimals().unwrap());
+
+    alice_sas.confirm().await.unwrap();
+    sas.confirm().await.unwrap();
+    relay.deliver_all(&server.server, &clients).await;
+
+    wait_for_session_verification_state(&session_verification, |state| {
+        matches!(state, SessionVerificationState::Verified)
+    })
+    .await;
+    assert!(sas.is_done());
+    assert!(alice_sas.is_done());
+}
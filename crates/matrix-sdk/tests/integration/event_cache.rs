@@ -3,7 +3,7 @@ use std::{future::ready, ops::ControlFlow, time::Duration};
 use assert_matches2::{assert_let, assert_matches};
 use matrix_sdk::{
     event_cache::{
-        BackPaginationOutcome, EventCacheError, RoomEventCacheUpdate,
+        BackPaginationOutcome, EventCacheError, RoomEventCacheGap, RoomEventCacheUpdate,
         TimelineHasBeenResetWhilePaginating,
     },
     test_utils::{assert_event_matches_msg, events::EventFactory, logged_in_client_with_server},
@@ -19,7 +19,10 @@ use ruma::{
     user_id,
 };
 use serde_json::json;
-use tokio::{spawn, time::timeout};
+use tokio::{
+    spawn,
+    time::{sleep, timeout},
+};
 use wiremock::{
     matchers::{header, method, path_regex, query_param},
     Mock, MockServer, ResponseTemplate,
@@ -668,27 +671,17 @@ async fn test_reset_while_backpaginating() {
     }
 
     // We're going to cause a small race:
-    // - a background request to sync will be sent,
+    // - the room events will be reset,
     // - a backpagination will be sent concurrently.
     //
     // So events have to happen in this order:
     // - the backpagination request is sent, with a prev-batch A
-    // - the sync endpoint returns *after* the backpagination started, before the
+    // - the room events are reset *after* the backpagination started, before the
     // backpagination ends
     // - the backpagination ends, with a prev-batch token that's now stale.
     //
     // The backpagination should result in an unknown-token-error.
 
-    sync_builder.add_joined_room(
-        JoinedRoomBuilder::new(room_id)
-            // Note to self: a timeline must have at least single event to be properly
-            // serialized.
-            .add_timeline_event(ev_factory.text_msg("heyo").into_raw_sync())
-            .set_timeline_prev_batch("second_backpagination".to_owned())
-            .set_timeline_limited(),
-    );
-    let sync_response_body = sync_builder.build_json_sync_response();
-
     // Mock the first back-pagination request:
     let chunk = vec![ev_factory.text_msg("lalala").into_raw_timeline()];
     let response_json = json!({
@@ -741,9 +734,18 @@ async fn test_reset_while_backpaginating() {
         }
     });
 
-    // Receive the sync response (which clears the timeline).
-    mock_sync(&server, sync_response_body, None).await;
-    client.sync_once(Default::default()).await.unwrap();
+    // Reset the room events while the back-pagination is running. (A limited sync
+    // doesn't clear the events anymore, it inserts a gap instead.)
+    sleep(Duration::from_millis(100)).await;
+    client
+        .event_cache()
+        .add_initial_events(
+            room_id,
+            vec![ev_factory.text_msg("heyo").into_sync()],
+            Some("second_backpagination".to_owned()),
+        )
+        .await
+        .unwrap();
 
     let outcome = backpagination.await.expect("join failed").unwrap();
 
@@ -818,3 +820,155 @@ async fn test_backpaginating_without_token() {
 
     assert!(room_stream.is_empty());
 }
+
+#[async_test]
+async fn test_limited_sync_creates_a_gap_that_can_be_filled() {
+    let (client, server) = logged_in_client_with_server().await;
+
+    let event_cache = client.event_cache();
+
+    // Immediately subscribe the event cache to sync updates.
+    event_cache.subscribe().unwrap();
+
+    let room_id = room_id!("!omelette:fromage.fr");
+
+    let ev_factory = EventFactory::new().room(room_id).sender(user_id!("@a:b.c"));
+    let mut sync_builder = SyncResponseBuilder::new();
+
+    // If I sync and get a first event, with a previous batch token,
+    {
+        sync_builder.add_joined_room(
+            JoinedRoomBuilder::new(room_id)
+                .add_timeline_event(
+                    ev_factory.text_msg("before").event_id(event_id!("$before")).into_raw_sync(),
+                )
+                .set_timeline_prev_batch("start".to_owned()),
+        );
+        let response_body = sync_builder.build_json_sync_response();
+
+        mock_sync(&server, response_body, None).await;
+        client.sync_once(Default::default()).await.unwrap();
+        server.reset().await;
+    }
+
+    let (room_event_cache, _drop_handles) =
+        client.get_room(room_id).unwrap().event_cache().await.unwrap();
+
+    let (events, mut room_stream) = room_event_cache.subscribe().await.unwrap();
+
+    // This is racy: either the initial message has been processed by the event
+    // cache (and no room updates will happen in this case), or it hasn't, and
+    // the stream will return the next message soon.
+    if events.is_empty() {
+        let _ = room_stream.recv().await.expect("read error");
+    } else {
+        assert_eq!(events.len(), 1);
+    }
+
+    // And then a limited sync,
+    {
+        sync_builder.add_joined_room(
+            JoinedRoomBuilder::new(room_id)
+                .add_timeline_event(
+                    ev_factory.text_msg("after").event_id(event_id!("$after")).into_raw_sync(),
+                )
+                .set_timeline_prev_batch("gap".to_owned())
+                .set_timeline_limited(),
+        );
+        let response_body = sync_builder.build_json_sync_response();
+
+        mock_sync(&server, response_body, None).await;
+        client.sync_once(Default::default()).await.unwrap();
+        server.reset().await;
+    }
+
+    // Then I'm notified about a gap, followed by the new events.
+    let update = timeout(Duration::from_secs(2), room_stream.recv())
+        .await
+        .expect("timeout after receiving a sync update")
+        .expect("should've received a room event cache update");
+    assert_let!(RoomEventCacheUpdate::AddGap { prev_token } = update);
+    assert_eq!(prev_token, "gap");
+
+    let update = timeout(Duration::from_secs(2), room_stream.recv())
+        .await
+        .expect("timeout after receiving a sync update")
+        .expect("should've received a room event cache update");
+    assert_let!(RoomEventCacheUpdate::AddTimelineEvents { events, .. } = update);
+    assert_eq!(events.len(), 1);
+    assert_event_matches_msg(&events[0], "after");
+
+    // The previous events are kept, and the gap is known.
+    let (events, _) = room_event_cache.subscribe().await.unwrap();
+    assert_eq!(events.len(), 2);
+    assert_eq!(
+        room_event_cache.gaps().await,
+        vec![RoomEventCacheGap {
+            prev_token: "gap".to_owned(),
+            next_event_id: Some(event_id!("$after").to_owned()),
+        }]
+    );
+
+    // The start of the room can still be back-paginated.
+    let pagination = room_event_cache.pagination();
+    assert_eq!(pagination.get_or_wait_for_token().await.as_deref(), Some("start"));
+
+    // When I fill the gap a first time,
+    mock_messages(
+        &server,
+        "gap",
+        Some("gap2"),
+        vec![
+            ev_factory.text_msg("missed 2").event_id(event_id!("$m2")).into_raw_timeline(),
+            ev_factory.text_msg("missed 1").event_id(event_id!("$m1")).into_raw_timeline(),
+        ],
+    )
+    .await;
+
+    let outcome = pagination.fill_gap("gap", 10).await.unwrap();
+    assert_eq!(outcome.events.len(), 2);
+    assert_eq!(outcome.prev_token.as_deref(), Some("gap2"));
+
+    // Then the gap shrinks.
+    assert_eq!(
+        room_event_cache.gaps().await,
+        vec![RoomEventCacheGap {
+            prev_token: "gap2".to_owned(),
+            next_event_id: Some(event_id!("$m1").to_owned()),
+        }]
+    );
+
+    // And when I fill it again, meeting an event I already know,
+    mock_messages(
+        &server,
+        "gap2",
+        Some("gap3"),
+        vec![
+            ev_factory.text_msg("missed 0").event_id(event_id!("$m0")).into_raw_timeline(),
+            ev_factory.text_msg("before").event_id(event_id!("$before")).into_raw_timeline(),
+        ],
+    )
+    .await;
+
+    let outcome = pagination.fill_gap("gap2", 10).await.unwrap();
+    assert_eq!(outcome.events.len(), 1);
+    assert_event_matches_msg(&outcome.events[0], "missed 0");
+    assert!(outcome.prev_token.is_none());
+
+    // Then the gap is closed, and the events are in the right order.
+    assert!(room_event_cache.gaps().await.is_empty());
+
+    let (events, _) = room_event_cache.subscribe().await.unwrap();
+    assert_eq!(events.len(), 5);
+    assert_event_matches_msg(&events[0], "before");
+    assert_event_matches_msg(&events[1], "missed 0");
+    assert_event_matches_msg(&events[2], "missed 1");
+    assert_event_matches_msg(&events[3], "missed 2");
+    assert_event_matches_msg(&events[4], "after");
+
+    // Filling a gap that doesn't exist anymore fails.
+    assert_matches!(
+        pagination.fill_gap("gap", 10).await,
+        Err(EventCacheError::UnknownBackpaginationToken)
+    );
+}
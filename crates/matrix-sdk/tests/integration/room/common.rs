@@ -1,7 +1,11 @@
 use std::time::Duration;
 
 use assert_matches2::assert_let;
-use matrix_sdk::{config::SyncSettings, room::RoomMember, DisplayName, RoomMemberships};
+use matrix_sdk::{
+    config::SyncSettings,
+    room::{member_list::RoomMemberListUpdate, RelationsOptions, RoomMember},
+    DisplayName, RoomMemberships,
+};
 use matrix_sdk_test::{
     async_test, bulk_room_members, sync_timeline_event, test_json, JoinedRoomBuilder,
     StateTestEvent, SyncResponseBuilder, DEFAULT_TEST_ROOM_ID,
@@ -9,14 +13,14 @@ use matrix_sdk_test::{
 use ruma::{
     event_id,
     events::{
-        room::member::MembershipState, AnyStateEvent, AnySyncStateEvent, AnyTimelineEvent,
-        StateEventType,
+        relation::RelationType, room::member::MembershipState, AnyStateEvent, AnySyncStateEvent,
+        AnyTimelineEvent, StateEventType,
     },
-    room_id,
+    room_id, user_id,
 };
 use serde_json::json;
 use wiremock::{
-    matchers::{header, method, path_regex},
+    matchers::{header, method, path_regex, query_param, query_param_is_missing},
     Mock, ResponseTemplate,
 };
 
@@ -559,3 +563,163 @@ async fn test_event() {
     assert!(push_actions.iter().any(|a| a.is_highlight()));
     assert!(push_actions.iter().any(|a| a.should_notify()));
 }
+
+#[async_test]
+async fn test_relations() {
+    let event_id = event_id!("$original");
+
+    let (client, server) = logged_in_client_with_server().await;
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+    let mut sync_builder = SyncResponseBuilder::new();
+    sync_builder.add_joined_room(JoinedRoomBuilder::new(&DEFAULT_TEST_ROOM_ID));
+
+    mock_sync(&server, sync_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings).await.unwrap();
+    server.reset().await;
+
+    let room = client.get_room(&DEFAULT_TEST_ROOM_ID).unwrap();
+
+    let edit = |id: &str, body: &str, ts: u64| {
+        json!({
+            "content": {
+                "body": format!("* {body}"),
+                "msgtype": "m.text",
+                "m.new_content": { "body": body, "msgtype": "m.text" },
+                "m.relates_to": { "rel_type": "m.replace", "event_id": event_id },
+            },
+            "event_id": id,
+            "origin_server_ts": ts,
+            "sender": "@alice:localhost",
+            "type": "m.room.message",
+            "room_id": *DEFAULT_TEST_ROOM_ID,
+        })
+    };
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/v1/rooms/.*/relations/.*/m.replace$"))
+        .and(query_param_is_missing("from"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "chunk": [edit("$edit2", "second", 2)],
+            "next_batch": "next_token",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/v1/rooms/.*/relations/.*/m.replace$"))
+        .and(query_param("from", "next_token"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "chunk": [edit("$edit1", "first", 1)],
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let options = RelationsOptions::with_rel_type(RelationType::Replacement);
+    let relations = room.relations(event_id, options.clone()).await.unwrap();
+    assert_eq!(relations.chunk.len(), 1);
+    assert_eq!(
+        relations.chunk[0].event.get_field::<String>("event_id").unwrap().as_deref(),
+        Some("$edit2")
+    );
+    assert_eq!(relations.next_batch_token.as_deref(), Some("next_token"));
+
+    let relations = room.relations(event_id, options.from("next_token")).await.unwrap();
+    assert_eq!(relations.chunk.len(), 1);
+    assert_eq!(
+        relations.chunk[0].event.get_field::<String>("event_id").unwrap().as_deref(),
+        Some("$edit1")
+    );
+    assert!(relations.next_batch_token.is_none());
+}
+
+#[async_test]
+async fn test_member_list() {
+    let (client, server) = logged_in_client_with_server().await;
+    let room_id = room_id!("!member_list:localhost");
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/members"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "chunk": [] })))
+        .mount(&server)
+        .await;
+
+    let mut sync_builder = SyncResponseBuilder::new();
+    sync_builder.add_joined_room(
+        JoinedRoomBuilder::new(room_id)
+            .add_timeline_state_bulk(bulk_room_members(
+                0,
+                0..5,
+                "localhost",
+                &MembershipState::Join,
+            ))
+            .add_timeline_event(sync_timeline_event!({
+                "content": {
+                    "users": {
+                        "@user_3:localhost": 50,
+                    },
+                },
+                "event_id": "$power_levels",
+                "origin_server_ts": 151800200,
+                "sender": "@user_0:localhost",
+                "state_key": "",
+                "type": "m.room.power_levels",
+            })),
+    );
+    mock_sync(&server, sync_builder.build_json_sync_response(), None).await;
+    let sync_token = client.sync_once(SyncSettings::new()).await.unwrap().next_batch;
+    let room = client.get_room(room_id).unwrap();
+
+    // The members are sorted by power level, then by name.
+    let list = room.member_list(RoomMemberships::JOIN).page_size(2);
+    let page = list.page(0).await.unwrap();
+    assert_eq!(page.total, 5);
+    let user_ids = page.members.iter().map(|m| m.user_id()).collect::<Vec<_>>();
+    assert_eq!(user_ids, [user_id!("@user_3:localhost"), user_id!("@user_0:localhost")]);
+
+    let page = list.page(2).await.unwrap();
+    assert_eq!(page.total, 5);
+    let user_ids = page.members.iter().map(|m| m.user_id()).collect::<Vec<_>>();
+    assert_eq!(user_ids, [user_id!("@user_4:localhost")]);
+
+    let page = list.page(3).await.unwrap();
+    assert!(page.members.is_empty());
+
+    // Search.
+    let search_list = room.member_list(RoomMemberships::JOIN).search("USER_1");
+    let page = search_list.page(0).await.unwrap();
+    assert_eq!(page.total, 1);
+    assert_eq!(page.members[0].user_id(), "@user_1:localhost");
+
+    // A member leaves and another one joins.
+    let (_guard, mut updates) = list.subscribe();
+
+    sync_builder.add_joined_room(
+        JoinedRoomBuilder::new(room_id)
+            .add_timeline_state_bulk(bulk_room_members(
+                1,
+                1..2,
+                "localhost",
+                &MembershipState::Leave,
+            ))
+            .add_timeline_state_bulk(bulk_room_members(
+                1,
+                5..6,
+                "localhost",
+                &MembershipState::Join,
+            )),
+    );
+    mock_sync(&server, sync_builder.build_json_sync_response(), Some(sync_token.clone())).await;
+    client.sync_once(SyncSettings::new().token(sync_token)).await.unwrap();
+
+    assert_let!(Ok(RoomMemberListUpdate::Removed(user_id)) = updates.try_recv());
+    assert_eq!(user_id, "@user_1:localhost");
+    assert_let!(Ok(RoomMemberListUpdate::Updated(member)) = updates.try_recv());
+    assert_eq!(member.user_id(), "@user_5:localhost");
+    assert!(updates.try_recv().is_err());
+
+    let page = list.page(0).await.unwrap();
+    assert_eq!(page.total, 5);
+}
@@ -0,0 +1,216 @@
+use std::{future::IntoFuture, pin::pin, time::Duration};
+
+use assert_matches2::assert_matches;
+use eyeball::SharedObservable;
+use futures_util::future::{select, Either};
+use matrix_sdk::{
+    room::export::{ExportFormat, ExportProgress},
+    test_utils::{events::EventFactory, logged_in_client_with_server},
+};
+use matrix_sdk_test::{async_test, JoinedRoomBuilder, ALICE};
+use ruma::{
+    event_id,
+    events::room::message::{FileMessageEventContent, MessageType, RoomMessageEventContent},
+    room_id, uint,
+};
+use serde_json::json;
+use wiremock::{
+    matchers::{header, method, path, path_regex, query_param, query_param_is_missing},
+    Mock, ResponseTemplate,
+};
+
+use crate::{mock_encryption_state, mock_sync_with_new_room};
+
+#[async_test]
+async fn test_export_is_cancelled_and_resumed() {
+    let (client, server) = logged_in_client_with_server().await;
+    let room_id = room_id!("!a98sd12bjh:example.org");
+
+    let room = mock_sync_with_new_room(
+        |builder| {
+            builder.add_joined_room(JoinedRoomBuilder::new(room_id));
+        },
+        &client,
+        &server,
+        room_id,
+    )
+    .await;
+    mock_encryption_state(&server, false).await;
+
+    let f = EventFactory::new().room(room_id).sender(*ALICE);
+    let file = f
+        .event(RoomMessageEventContent::new(MessageType::File(FileMessageEventContent::plain(
+            "report.pdf".to_owned(),
+            "mxc://localhost/report".into(),
+        ))))
+        .event_id(event_id!("$file"))
+        .into_timeline();
+    let first_text = f.text_msg("First").into_timeline();
+    let second_text = f.text_msg("Second").into_timeline();
+
+    // The export starts from the beginning of the room.
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/messages$"))
+        .and(header("authorization", "Bearer 1234"))
+        .and(query_param("dir", "f"))
+        .and(query_param("limit", "2"))
+        .and(query_param_is_missing("from"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "start": "t0",
+            "end": "t1",
+            "chunk": [file.event, first_text.event],
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/media/r0/download/localhost/report"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("report"))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    // The second batch never arrives, so the export is cancelled while waiting
+    // for it.
+    let stalled_batch = Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/messages$"))
+        .and(query_param("from", "t1"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(json!({ "start": "t1", "chunk": [] }))
+                .set_delay(Duration::from_secs(60)),
+        )
+        .mount_as_scoped(&server)
+        .await;
+
+    let attachments_dir = tempfile::tempdir().unwrap();
+    let progress = SharedObservable::new(ExportProgress::default());
+    let mut progress_subscriber = progress.subscribe();
+    let mut output = Vec::new();
+
+    let export = room
+        .export(&mut output, ExportFormat::Html)
+        .batch_size(uint!(2))
+        .with_attachments(attachments_dir.path())
+        .with_progress_observable(progress.clone())
+        .into_future();
+    let first_batch_exported =
+        pin!(async { while progress_subscriber.next().await.unwrap().exported_events < 2 {} });
+    assert_matches!(select(export, first_batch_exported).await, Either::Right(_));
+    drop(stalled_batch);
+
+    let cancelled = progress.get();
+    assert_eq!(cancelled.exported_events, 2);
+    assert_eq!(cancelled.downloaded_attachments, 1);
+    assert_eq!(cancelled.resume_token.as_deref(), Some("t1"));
+
+    let attachment = attachments_dir.path().join("_file-report.pdf");
+    assert_eq!(std::fs::read_to_string(&attachment).unwrap(), "report");
+
+    let html = String::from_utf8(output.clone()).unwrap();
+    assert_eq!(html.matches("<!DOCTYPE html>").count(), 1);
+    assert_eq!(html.matches("<div class=\"event\">").count(), 2);
+    assert!(html.contains(&*attachment.to_string_lossy()));
+    assert!(!html.contains("</html>"));
+
+    // The export is resumed from the token of the cancelled one, until the end
+    // of the room.
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/messages$"))
+        .and(query_param("from", "t1"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "start": "t1",
+            "end": "t2",
+            "chunk": [second_text.event],
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/messages$"))
+        .and(query_param("from", "t2"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "start": "t2",
+            "chunk": [],
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let resumed = room
+        .export(&mut output, ExportFormat::Html)
+        .batch_size(uint!(2))
+        .resume(cancelled.resume_token.unwrap())
+        .await
+        .unwrap();
+    assert_eq!(resumed.exported_events, 1);
+    assert_eq!(resumed.resume_token.as_deref(), Some("t2"));
+
+    // The resumed export continues the same page.
+    let html = String::from_utf8(output).unwrap();
+    assert_eq!(html.matches("<!DOCTYPE html>").count(), 1);
+    assert_eq!(html.matches("<div class=\"event\">").count(), 3);
+    assert!(html.contains("First"));
+    assert!(html.contains("Second"));
+    assert!(html.trim_end().ends_with("</html>"));
+}
+
+#[async_test]
+async fn test_export_json_lines() {
+    let (client, server) = logged_in_client_with_server().await;
+    let room_id = room_id!("!a98sd12bjh:example.org");
+
+    let room = mock_sync_with_new_room(
+        |builder| {
+            builder.add_joined_room(JoinedRoomBuilder::new(room_id));
+        },
+        &client,
+        &server,
+        room_id,
+    )
+    .await;
+    mock_encryption_state(&server, false).await;
+
+    let f = EventFactory::new().room(room_id).sender(*ALICE);
+    let hello = f.text_msg("Hello").event_id(event_id!("$hello")).into_timeline();
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/messages$"))
+        .and(query_param("from", "t0"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "start": "t0",
+            "end": "t1",
+            "chunk": [hello.event],
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/messages$"))
+        .and(query_param("from", "t1"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "start": "t1",
+            "chunk": [],
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let mut output = Vec::new();
+    let progress = room.export(&mut output, ExportFormat::Json).from("t0").await.unwrap();
+    assert_eq!(progress.exported_events, 1);
+    assert_eq!(progress.downloaded_attachments, 0);
+    assert_eq!(progress.resume_token.as_deref(), Some("t1"));
+
+    let output = String::from_utf8(output).unwrap();
+    let lines = output.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 1);
+
+    let entry: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+    assert_eq!(entry["event"]["event_id"], "$hello");
+    assert_eq!(entry["event"]["content"]["body"], "Hello");
+    assert!(entry.get("attachment").is_none());
+}
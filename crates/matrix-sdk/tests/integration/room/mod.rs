@@ -1,5 +1,6 @@
 mod attachment;
 mod common;
+mod export;
 mod joined;
 mod left;
 mod notification_mode;
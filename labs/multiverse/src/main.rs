@@ -769,6 +769,12 @@ impl App {
                     VirtualTimelineItem::ReadMarker => {
                         content.push("Read marker".to_owned());
                     }
+                    VirtualTimelineItem::StateGroup(group) => {
+                        content.push(format!("{} state changes", group.children().len()));
+                    }
+                    VirtualTimelineItem::Gap { .. } => {
+                        content.push("Missing messages".to_owned());
+                    }
                 },
             }
         }
@@ -65,6 +65,7 @@ enum FeatureSet {
     NoSqlite,
     NoEncryptionAndSqlite,
     SqliteCryptostore,
+    RedbCryptostore,
     RustlsTls,
     Markdown,
     Socks,
@@ -222,6 +223,10 @@ fn run_feature_tests(cmd: Option<FeatureSet>) -> Result<()> {
             FeatureSet::SqliteCryptostore,
             "--no-default-features --features e2e-encryption,sqlite,native-tls,testing",
         ),
+        (
+            FeatureSet::RedbCryptostore,
+            "--no-default-features --features e2e-encryption,redb,native-tls,testing",
+        ),
         (FeatureSet::RustlsTls, "--no-default-features --features rustls-tls,testing"),
         (FeatureSet::Markdown, "--features markdown,testing"),
         (FeatureSet::Socks, "--features socks,testing"),
@@ -276,6 +281,9 @@ fn run_crypto_tests() -> Result<()> {
     )
     .run()?;
 
+    cmd!("rustup run stable cargo nextest run -p matrix-sdk-redb --features crypto-store,testing")
+        .run()?;
+
     Ok(())
 }
 